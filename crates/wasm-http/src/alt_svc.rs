@@ -0,0 +1,427 @@
+//! HTTP Alternative Services (RFC 7838): the `Alt-Svc` header, and a per-origin
+//! record of the alternatives advertised, e.g. HTTP/3 on another port.
+//!
+//! Sans-IO like the cookie jar and `HstsStore`: the caller passes the response URL
+//! and the current time with each header, and asks what is known for an origin
+//! when deciding how to connect.
+
+use wasm_bindgen::prelude::*;
+
+use crate::error::Error;
+use crate::headers::Headers;
+use crate::url::{percent_decode, HttpUrl};
+
+/// Freshness of an alternative without `ma` (§3.1): 24 hours.
+const DEFAULT_MAX_AGE: i64 = 86400;
+
+/// One alternative as written in the header, before it is tied to an origin.
+#[derive(Debug, PartialEq, Eq)]
+struct Alternative {
+    protocol: String,
+    /// None when the authority leaves the host out: the origin's host
+    host: Option<String>,
+    port: u16,
+    max_age: i64,
+    persist: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum AltSvcValue {
+    Clear,
+    Alternatives(Vec<Alternative>),
+}
+
+/// Parse an Alt-Svc value (§3). Alternatives that do not parse are skipped;
+/// None when nothing is left.
+fn parse_alt_svc(value: &str) -> Option<AltSvcValue> {
+    if value.trim().eq_ignore_ascii_case("clear") {
+        return Some(AltSvcValue::Clear);
+    }
+    let alternatives: Vec<Alternative> = split_unquoted(value, ',')
+        .into_iter()
+        .filter_map(parse_alternative)
+        .collect();
+    (!alternatives.is_empty()).then_some(AltSvcValue::Alternatives(alternatives))
+}
+
+/// `protocol-id="[host]:port"; ma=3600; persist=1`
+fn parse_alternative(value: &str) -> Option<Alternative> {
+    let mut parts = split_unquoted(value, ';').into_iter();
+    let (protocol, authority) = parts.next()?.split_once('=')?;
+    let protocol = protocol_id(protocol.trim())?;
+    if protocol.is_empty() || !protocol.bytes().all(|b| b.is_ascii_graphic()) {
+        return None;
+    }
+    let authority = unquote(authority.trim())?;
+    let (host, port) = authority.rsplit_once(':')?;
+    if port.is_empty() || !port.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let port = port.parse().ok().filter(|&p| p != 0)?;
+    let valid_host = host
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || b"-._~[]:".contains(&b));
+    if !valid_host {
+        return None;
+    }
+    let host = (!host.is_empty()).then(|| host.to_ascii_lowercase());
+
+    let (mut max_age, mut persist) = (DEFAULT_MAX_AGE, false);
+    for parameter in parts {
+        let Some((name, value)) = parameter.split_once('=') else {
+            continue;
+        };
+        let Some(value) = unquote(value.trim()) else {
+            continue;
+        };
+        match name.trim().to_ascii_lowercase().as_str() {
+            "ma" if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) => {
+                max_age = value.parse().unwrap_or(i64::MAX);
+            }
+            // Only "1" is defined; anything else is as if the parameter were absent
+            "persist" => persist = value == "1",
+            _ => {}
+        }
+    }
+    Some(Alternative {
+        protocol,
+        host,
+        port,
+        max_age,
+        persist,
+    })
+}
+
+/// Split on `separator` outside double-quoted strings.
+fn split_unquoted(value: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut start, mut quoted, mut escaped) = (0, false, false);
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            c if c == separator && !quoted => {
+                parts.push(&value[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&value[start..]);
+    parts
+}
+
+/// A token or quoted-string's content, with backslash escapes removed.
+fn unquote(value: &str) -> Option<String> {
+    let Some(inner) = value.strip_prefix('"') else {
+        return Some(value.to_string());
+    };
+    let inner = inner.strip_suffix('"')?;
+    let mut out = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        out.push(if c == '\\' { chars.next()? } else { c });
+    }
+    Some(out)
+}
+
+/// protocol-ids are percent-encoded ALPN identifiers (§3); a '%' without two hex
+/// digits after it makes the value invalid.
+fn protocol_id(value: &str) -> Option<String> {
+    let bytes = value.as_bytes();
+    let escapes_valid = (0..bytes.len()).filter(|&i| bytes[i] == b'%').all(|i| {
+        bytes
+            .get(i + 1..i + 3)
+            .is_some_and(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+    });
+    if !escapes_valid {
+        return None;
+    }
+    String::from_utf8(percent_decode(value)).ok()
+}
+
+/// An alternative endpoint for an origin, as returned by `AltSvcStore::alternatives`.
+#[wasm_bindgen]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AltService {
+    protocol: String,
+    host: String,
+    port: u16,
+    /// Unix seconds
+    expires: i64,
+    persist: bool,
+}
+
+#[wasm_bindgen]
+impl AltService {
+    /// ALPN protocol ID, e.g. "h3" or "h2".
+    pub fn protocol(&self) -> String {
+        self.protocol.clone()
+    }
+
+    /// Host to connect to: the origin's own when the header left it out. IPv6
+    /// literals keep their brackets.
+    pub fn host(&self) -> String {
+        self.host.clone()
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// When the alternative stops being fresh, in unix seconds.
+    pub fn expires(&self) -> f64 {
+        self.expires as f64
+    }
+
+    /// Whether it survives `network_changed` (`persist=1`).
+    pub fn persist(&self) -> bool {
+        self.persist
+    }
+}
+
+/// Alternative services by origin, most preferred first, with their expiry.
+#[wasm_bindgen]
+#[derive(Clone, Debug, Default)]
+pub struct AltSvcStore {
+    /// Serialized origin and its alternatives
+    origins: Vec<(String, Vec<AltService>)>,
+}
+
+#[wasm_bindgen]
+impl AltSvcStore {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> AltSvcStore {
+        AltSvcStore::default()
+    }
+
+    /// Process an Alt-Svc value received in the response to `url`. It replaces
+    /// whatever was known for the origin; "clear" forgets it. Returns false when
+    /// it is ignored: received over plain http, as browsers do, or unparseable.
+    pub fn store_header(&mut self, url: &str, header: &str, now: f64) -> Result<bool, Error> {
+        let url = HttpUrl::parse(url)?;
+        Ok(self.store(&url, header, now as i64))
+    }
+
+    /// Process the Alt-Svc fields among `names`/`values`, e.g. the
+    /// `header_names()`/`header_values()` of a response event. Several fields
+    /// form one list.
+    pub fn store_response(
+        &mut self,
+        url: &str,
+        names: Vec<String>,
+        values: Vec<String>,
+        now: f64,
+    ) -> Result<bool, Error> {
+        if names.len() != values.len() {
+            return Err(Error::HeaderCountMismatch);
+        }
+        let mut headers = Headers::new();
+        for (name, value) in names.into_iter().zip(values) {
+            headers.push(name, value);
+        }
+        self.store_headers(url, &headers, now)
+    }
+
+    /// Fresh alternatives for the origin of `url`, most preferred first.
+    pub fn alternatives(&self, url: &str, now: f64) -> Result<Vec<AltService>, Error> {
+        let origin = HttpUrl::parse(url)?.origin();
+        let now = now as i64;
+        Ok(self
+            .origins
+            .iter()
+            .find(|(o, _)| *o == origin)
+            .map(|(_, services)| {
+                services
+                    .iter()
+                    .filter(|s| s.expires > now)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    /// Forget every alternative without `persist=1`, as §3.1 asks when the
+    /// client's network changes. Returns how many were removed.
+    pub fn network_changed(&mut self) -> usize {
+        self.retain(|s| s.persist)
+    }
+
+    /// Drop expired alternatives; returns how many were removed. Lookups skip them anyway.
+    pub fn evict_expired(&mut self, now: f64) -> usize {
+        self.retain(|s| s.expires > now as i64)
+    }
+
+    pub fn clear(&mut self) {
+        self.origins.clear();
+    }
+
+    /// Number of alternatives stored, over all origins.
+    pub fn len(&self) -> usize {
+        self.origins.iter().map(|(_, s)| s.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.origins.is_empty()
+    }
+}
+
+impl AltSvcStore {
+    /// Rust-side `store_response` for a parsed head.
+    pub fn store_headers(&mut self, url: &str, headers: &Headers, now: f64) -> Result<bool, Error> {
+        let url = HttpUrl::parse(url)?;
+        let value = headers.get_all("alt-svc").collect::<Vec<_>>().join(",");
+        Ok(!value.is_empty() && self.store(&url, &value, now as i64))
+    }
+
+    fn store(&mut self, url: &HttpUrl, header: &str, now: i64) -> bool {
+        if !url.secure {
+            return false;
+        }
+        let Some(value) = parse_alt_svc(header) else {
+            return false;
+        };
+        let origin = url.origin();
+        self.origins.retain(|(o, _)| *o != origin);
+        if let AltSvcValue::Alternatives(alternatives) = value {
+            let services = alternatives
+                .into_iter()
+                .map(|a| AltService {
+                    protocol: a.protocol,
+                    host: a.host.unwrap_or_else(|| url.host.clone()),
+                    port: a.port,
+                    expires: now.saturating_add(a.max_age),
+                    persist: a.persist,
+                })
+                .collect();
+            self.origins.push((origin, services));
+        }
+        true
+    }
+
+    fn retain(&mut self, keep: impl Fn(&AltService) -> bool) -> usize {
+        let before = self.len();
+        for (_, services) in &mut self.origins {
+            services.retain(&keep);
+        }
+        self.origins.retain(|(_, s)| !s.is_empty());
+        before - self.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: f64 = 1_700_000_000.0;
+
+    #[test]
+    fn header_parsing() {
+        let value = r#"h3=":443"; ma=3600, h3%2D29="alt.example.com:8443";persist=1, bogus, h2="[::1]:0", h2="A\"B:1""#;
+        let Some(AltSvcValue::Alternatives(alternatives)) = parse_alt_svc(value) else {
+            panic!("no alternatives");
+        };
+        assert_eq!(
+            alternatives,
+            [
+                Alternative {
+                    protocol: "h3".to_string(),
+                    host: None,
+                    port: 443,
+                    max_age: 3600,
+                    persist: false,
+                },
+                Alternative {
+                    protocol: "h3-29".to_string(),
+                    host: Some("alt.example.com".to_string()),
+                    port: 8443,
+                    max_age: DEFAULT_MAX_AGE,
+                    persist: true,
+                },
+            ]
+        );
+        assert_eq!(parse_alt_svc(" Clear "), Some(AltSvcValue::Clear));
+        for invalid in ["", "h3", "h3=443", "h3=\":\"", "h3=\":443", "h3%2=\":443\""] {
+            assert_eq!(parse_alt_svc(invalid), None, "{}", invalid);
+        }
+        assert_eq!(
+            split_unquoted(r#"a="x,\",y",b"#, ','),
+            [r#"a="x,\",y""#, "b"]
+        );
+    }
+
+    #[test]
+    fn per_origin_alternatives_with_expiry() {
+        let mut store = AltSvcStore::new();
+        let url = "https://Example.com/page";
+        let header = "h3=\":443\"; ma=60, h2=\"cdn.example.net:443\"; persist=1";
+        assert!(store.store_header(url, header, NOW).unwrap());
+        assert!(!store
+            .store_header("http://example.com/", header, NOW)
+            .unwrap());
+        assert!(!store.store_header(url, "nonsense", NOW).unwrap());
+
+        let found = store
+            .alternatives("https://example.com:443/other", NOW)
+            .unwrap();
+        let endpoints: Vec<_> = found
+            .iter()
+            .map(|s| (s.protocol(), s.host(), s.port()))
+            .collect();
+        assert_eq!(
+            endpoints,
+            [
+                ("h3".to_string(), "example.com".to_string(), 443),
+                ("h2".to_string(), "cdn.example.net".to_string(), 443),
+            ]
+        );
+        assert_eq!(found[0].expires(), NOW + 60.0);
+        assert!(store
+            .alternatives("https://example.com:8443/", NOW)
+            .unwrap()
+            .is_empty());
+        assert!(store
+            .alternatives("http://example.com/", NOW)
+            .unwrap()
+            .is_empty());
+        assert_eq!(store.alternatives(url, NOW + 60.0).unwrap().len(), 1);
+        assert_eq!(store.evict_expired(NOW + 60.0), 1);
+
+        // A new header replaces the origin's alternatives
+        store.store_header(url, "h3=\":8443\"", NOW).unwrap();
+        assert_eq!(store.alternatives(url, NOW).unwrap()[0].port(), 8443);
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn clear_and_network_change() {
+        let mut store = AltSvcStore::new();
+        let names = vec!["Alt-Svc".to_string(), "alt-svc".to_string()];
+        let values = vec![
+            "h3=\":443\"".to_string(),
+            "h2=\":443\"; persist=1".to_string(),
+        ];
+        let url = "https://a.example/";
+        assert!(store.store_response(url, names, values, NOW).unwrap());
+        store
+            .store_header("https://b.example/", "h3=\":443\"", NOW)
+            .unwrap();
+        assert_eq!(store.len(), 3);
+
+        assert_eq!(store.network_changed(), 2);
+        let found = store.alternatives(url, NOW).unwrap();
+        assert_eq!((found.len(), found[0].protocol()), (1, "h2".to_string()));
+        assert!(store
+            .alternatives("https://b.example/", NOW)
+            .unwrap()
+            .is_empty());
+
+        assert!(store.store_header(url, "clear", NOW).unwrap());
+        assert!(store.is_empty());
+        assert_eq!(
+            store.store_response(url, vec!["Alt-Svc".into()], vec![], NOW),
+            Err(Error::HeaderCountMismatch)
+        );
+    }
+}
@@ -0,0 +1,229 @@
+//! WWW-Authenticate / Proxy-Authenticate parsing (RFC 9110 §11.6.1).
+//!
+//! A field holds a comma-separated list of challenges, each a scheme followed by
+//! a token68 or a comma-separated list of parameters, so a comma may end either
+//! a parameter or a challenge: a new challenge starts at a token that is not
+//! followed by `=`.
+
+/// One challenge of an authenticate field.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Challenge {
+    /// Lowercased
+    pub(crate) scheme: String,
+    pub(crate) token68: Option<String>,
+    /// Names lowercased, values unquoted
+    pub(crate) params: Vec<(String, String)>,
+}
+
+impl Challenge {
+    /// Value of the first parameter named `lower_name`.
+    pub(crate) fn param(&self, lower_name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(n, _)| n == lower_name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Every challenge in a field value, in order. Malformed parts are skipped.
+pub(crate) fn parse_challenges(value: &str) -> Vec<Challenge> {
+    let mut cursor = Cursor {
+        bytes: value.as_bytes(),
+        pos: 0,
+    };
+    let mut challenges = Vec::new();
+    loop {
+        cursor.skip(|b| b == b',' || is_space(b));
+        if cursor.at_end() {
+            return challenges;
+        }
+        let Some(scheme) = cursor.token() else {
+            cursor.pos += 1;
+            cursor.skip(|b| b != b',');
+            continue;
+        };
+        let mut challenge = Challenge {
+            scheme: scheme.to_ascii_lowercase(),
+            token68: None,
+            params: Vec::new(),
+        };
+        cursor.skip(is_space);
+        if let Some(token68) = cursor.token68() {
+            challenge.token68 = Some(token68);
+        } else {
+            loop {
+                let Some(param) = cursor.param() else {
+                    // After a comma this is the next challenge; here it is garbage
+                    if challenge.params.is_empty() {
+                        cursor.skip(|b| b != b',');
+                    }
+                    break;
+                };
+                challenge.params.push(param);
+                cursor.skip(is_space);
+                match cursor.peek() {
+                    Some(b',') => cursor.skip(|b| b == b',' || is_space(b)),
+                    None => break,
+                    Some(_) => {
+                        cursor.skip(|b| b != b',');
+                        break;
+                    }
+                }
+            }
+        }
+        challenges.push(challenge);
+    }
+}
+
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Cursor<'_> {
+    fn at_end(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn skip(&mut self, mut while_: impl FnMut(u8) -> bool) {
+        while self.peek().is_some_and(&mut while_) {
+            self.pos += 1;
+        }
+    }
+
+    fn token(&mut self) -> Option<String> {
+        let start = self.pos;
+        self.skip(is_tchar);
+        (self.pos > start).then(|| text(&self.bytes[start..self.pos]))
+    }
+
+    /// A token68 standing alone, i.e. ending the field or followed by a comma.
+    fn token68(&mut self) -> Option<String> {
+        let start = self.pos;
+        self.skip(|b| b.is_ascii_alphanumeric() || b"-._~+/".contains(&b));
+        if self.pos == start {
+            return None;
+        }
+        self.skip(|b| b == b'=');
+        let end = self.pos;
+        self.skip(is_space);
+        if matches!(self.peek(), None | Some(b',')) {
+            return Some(text(&self.bytes[start..end]));
+        }
+        self.pos = start;
+        None
+    }
+
+    /// `name = value`; leaves the cursor where it was when there is none.
+    fn param(&mut self) -> Option<(String, String)> {
+        let start = self.pos;
+        let param = self.name_value();
+        if param.is_none() {
+            self.pos = start;
+        }
+        param
+    }
+
+    fn name_value(&mut self) -> Option<(String, String)> {
+        let name = self.token()?;
+        self.skip(is_space);
+        if self.peek() != Some(b'=') {
+            return None;
+        }
+        self.pos += 1;
+        self.skip(is_space);
+        let value = match self.peek() {
+            Some(b'"') => self.quoted()?,
+            _ => self.token()?,
+        };
+        Some((name.to_ascii_lowercase(), value))
+    }
+
+    fn quoted(&mut self) -> Option<String> {
+        let mut value = Vec::new();
+        self.pos += 1;
+        loop {
+            let b = self.peek()?;
+            self.pos += 1;
+            match b {
+                b'"' => return Some(text(&value)),
+                b'\\' => {
+                    value.push(self.peek()?);
+                    self.pos += 1;
+                }
+                _ => value.push(b),
+            }
+        }
+    }
+}
+
+fn text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+fn is_space(b: u8) -> bool {
+    b == b' ' || b == b'\t'
+}
+
+fn is_tchar(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(challenge: &Challenge) -> Vec<(&str, &str)> {
+        challenge
+            .params
+            .iter()
+            .map(|(n, v)| (n.as_str(), v.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn challenges_and_params_share_commas() {
+        let parsed = parse_challenges(
+            r#"Newauth realm="apps", type=1, title="Login to \"apps\"", Basic realm="simple""#,
+        );
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].scheme, "newauth");
+        assert_eq!(
+            params(&parsed[0]),
+            [
+                ("realm", "apps"),
+                ("type", "1"),
+                ("title", r#"Login to "apps""#)
+            ]
+        );
+        assert_eq!(parsed[1].scheme, "basic");
+        assert_eq!(parsed[1].param("realm"), Some("simple"));
+    }
+
+    #[test]
+    fn token68_and_bare_schemes() {
+        let parsed = parse_challenges("Negotiate abc+/d==, Bearer ,, NTLM");
+        let schemes: Vec<&str> = parsed.iter().map(|c| c.scheme.as_str()).collect();
+        assert_eq!(schemes, ["negotiate", "bearer", "ntlm"]);
+        assert_eq!(parsed[0].token68.as_deref(), Some("abc+/d=="));
+        assert!(parsed[1].params.is_empty() && parsed[1].token68.is_none());
+    }
+
+    #[test]
+    fn loose_spacing_and_garbage() {
+        let parsed = parse_challenges(
+            "Digest  realm = \"r\" ,qop=\"auth,auth-int\",  nonce=n1 , @@, Basic realm=\"unterminated",
+        );
+        assert_eq!(
+            params(&parsed[0]),
+            [("realm", "r"), ("qop", "auth,auth-int"), ("nonce", "n1")]
+        );
+        // The unterminated quoted string leaves Basic without parameters
+        assert_eq!(parsed.last().unwrap().scheme, "basic");
+        assert!(parsed.last().unwrap().params.is_empty());
+    }
+}
@@ -0,0 +1,274 @@
+//! Digest access authentication (RFC 7616), qop=auth or none (RFC 2069 style).
+
+use sha2::{Digest as _, Sha256, Sha512_256};
+
+use super::challenge::Challenge;
+use crate::md5;
+
+/// Hash function of a Digest challenge, weakest first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Algorithm {
+    Md5,
+    Sha256,
+    Sha512_256,
+}
+
+impl Algorithm {
+    /// `MD5`, `SHA-256` or `SHA-512-256`, each optionally `-sess`; returns the
+    /// algorithm and whether it is a session variant.
+    fn parse(name: &str) -> Option<(Algorithm, bool)> {
+        let lower = name.to_ascii_lowercase();
+        let (base, sess) = match lower.strip_suffix("-sess") {
+            Some(base) => (base, true),
+            None => (lower.as_str(), false),
+        };
+        let algorithm = match base {
+            "md5" => Algorithm::Md5,
+            "sha-256" => Algorithm::Sha256,
+            "sha-512-256" => Algorithm::Sha512_256,
+            _ => return None,
+        };
+        Some((algorithm, sess))
+    }
+
+    fn hash(self, data: &str) -> String {
+        match self {
+            Algorithm::Md5 => hex(&md5::digest(data.as_bytes())),
+            Algorithm::Sha256 => hex(&Sha256::digest(data)),
+            Algorithm::Sha512_256 => hex(&Sha512_256::digest(data)),
+        }
+    }
+}
+
+/// A Digest challenge and the requests answered under its nonce.
+#[derive(Clone, Debug)]
+pub(crate) struct Digest {
+    pub(crate) realm: String,
+    nonce: String,
+    opaque: Option<String>,
+    pub(crate) algorithm: Algorithm,
+    sess: bool,
+    /// The algorithm parameter as the challenge spelled it, echoed back; the
+    /// response has none when the challenge had none
+    algorithm_name: Option<String>,
+    /// qop=auth was offered; otherwise the response is computed without cnonce and nc
+    qop: bool,
+    userhash: bool,
+    /// The challenge says the nonce, not the credentials, was rejected
+    pub(crate) stale: bool,
+    /// URIs of the protection space, unresolved
+    pub(crate) domain: Vec<String>,
+    /// Responses computed with `nonce` so far
+    nc: u32,
+}
+
+impl Digest {
+    /// The challenge if it is a Digest one this client can answer: a nonce, a known
+    /// algorithm, and qop=auth among the qops offered, if any.
+    pub(crate) fn from_challenge(challenge: &Challenge) -> Option<Digest> {
+        if challenge.scheme != "digest" {
+            return None;
+        }
+        let nonce = challenge.param("nonce")?.to_string();
+        let algorithm_name = challenge.param("algorithm").map(str::to_string);
+        let (algorithm, sess) = match &algorithm_name {
+            Some(name) => Algorithm::parse(name)?,
+            None => (Algorithm::Md5, false),
+        };
+        let qop = match challenge.param("qop") {
+            Some(qops) => {
+                if !qops
+                    .split(',')
+                    .any(|q| q.trim().eq_ignore_ascii_case("auth"))
+                {
+                    return None;
+                }
+                true
+            }
+            // Session variants need the cnonce that only comes with qop
+            None if sess => return None,
+            None => false,
+        };
+        let flag = |name| {
+            challenge
+                .param(name)
+                .is_some_and(|v| v.eq_ignore_ascii_case("true"))
+        };
+        Some(Digest {
+            realm: challenge.param("realm").unwrap_or_default().to_string(),
+            nonce,
+            opaque: challenge.param("opaque").map(str::to_string),
+            algorithm,
+            sess,
+            algorithm_name,
+            qop,
+            userhash: flag("userhash"),
+            stale: flag("stale"),
+            domain: challenge
+                .param("domain")
+                .map(|d| d.split_whitespace().map(str::to_string).collect())
+                .unwrap_or_default(),
+            nc: 0,
+        })
+    }
+
+    /// Authorization value for a request of `method` to `uri` (its request-target),
+    /// counting it against the nonce.
+    pub(crate) fn authorization(
+        &mut self,
+        username: &str,
+        password: &str,
+        method: &str,
+        uri: &str,
+        cnonce: &str,
+    ) -> String {
+        self.nc += 1;
+        let h = |data: String| self.algorithm.hash(&data);
+        let mut ha1 = h(format!("{}:{}:{}", username, self.realm, password));
+        if self.sess {
+            ha1 = h(format!("{}:{}:{}", ha1, self.nonce, cnonce));
+        }
+        let ha2 = h(format!("{}:{}", method, uri));
+        let nc = format!("{:08x}", self.nc);
+        let response = if self.qop {
+            h(format!(
+                "{}:{}:{}:{}:auth:{}",
+                ha1, self.nonce, nc, cnonce, ha2
+            ))
+        } else {
+            h(format!("{}:{}:{}", ha1, self.nonce, ha2))
+        };
+        let username = if self.userhash {
+            h(format!("{}:{}", username, self.realm))
+        } else {
+            username.to_string()
+        };
+
+        // Chrome's parameter order
+        let mut out = format!(
+            "Digest username={}, realm={}, nonce={}, uri={}",
+            quote(&username),
+            quote(&self.realm),
+            quote(&self.nonce),
+            quote(uri)
+        );
+        if let Some(name) = &self.algorithm_name {
+            out.push_str(&format!(", algorithm={}", name));
+        }
+        out.push_str(&format!(", response={}", quote(&response)));
+        if let Some(opaque) = &self.opaque {
+            out.push_str(&format!(", opaque={}", quote(opaque)));
+        }
+        if self.qop {
+            out.push_str(&format!(", qop=auth, nc={}, cnonce={}", nc, quote(cnonce)));
+        }
+        if self.userhash {
+            out.push_str(", userhash=true");
+        }
+        out
+    }
+}
+
+fn quote(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        if c == '"' || c == '\\' {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out.push('"');
+    out
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::challenge::parse_challenges;
+    use super::*;
+
+    fn digest(field: &str) -> Option<Digest> {
+        Digest::from_challenge(&parse_challenges(field)[0])
+    }
+
+    #[test]
+    fn rfc7616_examples() {
+        let challenge = |algorithm: &str| {
+            format!(
+                "Digest realm=\"http-auth@example.org\", qop=\"auth, auth-int\", algorithm={}, \
+                 nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", \
+                 opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\"",
+                algorithm
+            )
+        };
+        let cnonce = "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ";
+        let answer = |algorithm: &str| {
+            digest(&challenge(algorithm)).unwrap().authorization(
+                "Mufasa",
+                "Circle of Life",
+                "GET",
+                "/dir/index.html",
+                cnonce,
+            )
+        };
+        assert_eq!(
+            answer("MD5"),
+            "Digest username=\"Mufasa\", realm=\"http-auth@example.org\", \
+             nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", uri=\"/dir/index.html\", \
+             algorithm=MD5, response=\"8ca523f5e9506fed4657c9700eebdbec\", \
+             opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\", qop=auth, nc=00000001, \
+             cnonce=\"f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ\""
+        );
+        assert!(answer("SHA-256").contains(
+            "response=\"753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1\""
+        ));
+    }
+
+    #[test]
+    fn rfc2617_example_and_nonce_counting() {
+        let mut d = digest(
+            "Digest realm=\"testrealm@host.com\", qop=\"auth,auth-int\", \
+             nonce=\"dcd98b7102dd2f0e8b11d0f600bfb0c093\", \
+             opaque=\"5ccc069c403ebaf9f0171e9517f40e41\"",
+        )
+        .unwrap();
+        let first = d.authorization(
+            "Mufasa",
+            "Circle Of Life",
+            "GET",
+            "/dir/index.html",
+            "0a4f113b",
+        );
+        assert!(first.contains("response=\"6629fae49393a05397450978507c4ef1\""));
+        assert!(!first.contains("algorithm"));
+        let second = d.authorization(
+            "Mufasa",
+            "Circle Of Life",
+            "GET",
+            "/dir/index.html",
+            "0a4f113b",
+        );
+        assert!(second.contains("nc=00000002"));
+    }
+
+    #[test]
+    fn unsupported_challenges() {
+        assert!(digest(r#"Digest realm="r", nonce="n", qop="auth-int""#).is_none());
+        assert!(digest(r#"Digest realm="r", nonce="n", algorithm=SHA-1"#).is_none());
+        assert!(digest(r#"Digest realm="r", nonce="n", algorithm=MD5-sess"#).is_none());
+        assert!(digest(r#"Digest realm="r""#).is_none());
+        assert!(digest(r#"Basic realm="r", nonce="n""#).is_none());
+
+        // No qop: the RFC 2069 response, without cnonce or nc
+        let mut legacy =
+            digest(r#"Digest realm="r", nonce="n", stale=TRUE, userhash=true"#).unwrap();
+        assert!(legacy.stale);
+        let value = legacy.authorization("u\"1", "p", "GET", "/", "c");
+        assert!(!value.contains("qop") && value.ends_with(", userhash=true"));
+        assert!(!value.contains("u\\\"1"));
+    }
+}
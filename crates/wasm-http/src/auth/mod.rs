@@ -0,0 +1,609 @@
+//! HTTP authentication: Basic (RFC 7617) and Digest (RFC 7616) challenges.
+//!
+//! `AuthStore` keeps the credentials of every protection space (origin or proxy,
+//! realm and scheme) a session has logged into. Feed it each 401 or 407 with
+//! `on_response`: it picks the strongest challenge it can answer, asks the
+//! caller's callback for credentials, and returns the Authorization (or
+//! Proxy-Authorization) value to retry the request with, once. Later requests
+//! into the same space get their value up front from `authorization`.
+//!
+//! Passwords never leave the store except inside those header values; `Debug`
+//! leaves them out, and `HarRecorder` always redacts both headers.
+
+mod challenge;
+mod digest;
+
+use std::fmt;
+
+use wasm_bindgen::prelude::*;
+
+use crate::base64;
+use crate::error::Error;
+use crate::headers::Headers;
+use crate::url::HttpUrl;
+use challenge::parse_challenges;
+use digest::{hex, Digest};
+
+/// The challenge `AuthStore::on_response` asks credentials for.
+#[wasm_bindgen]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuthChallenge {
+    scheme: &'static str,
+    realm: String,
+    url: String,
+    proxy: Option<String>,
+    algorithm: Option<String>,
+}
+
+#[wasm_bindgen]
+impl AuthChallenge {
+    /// "Basic" or "Digest".
+    pub fn scheme(&self) -> String {
+        self.scheme.to_string()
+    }
+
+    pub fn realm(&self) -> String {
+        self.realm.clone()
+    }
+
+    /// URL of the request that was challenged.
+    pub fn url(&self) -> String {
+        self.url.clone()
+    }
+
+    /// The proxy that asked (407), or null for the origin server.
+    pub fn proxy(&self) -> Option<String> {
+        self.proxy.clone()
+    }
+
+    /// Digest hash: "MD5", "SHA-256" or "SHA-512-256"; null for Basic.
+    pub fn algorithm(&self) -> Option<String> {
+        self.algorithm.clone()
+    }
+}
+
+/// Credentials accepted for one protection space.
+#[derive(Clone)]
+struct Space {
+    proxy: bool,
+    /// Origin of the server, or the proxy's authority
+    authority: String,
+    realm: String,
+    /// Origins and path prefixes credentials are sent to without being asked;
+    /// a proxy's cover every request through it
+    scope: Vec<(String, String)>,
+    username: String,
+    password: String,
+    /// None for Basic
+    digest: Option<Digest>,
+}
+
+impl Space {
+    /// Length of the longest scope prefix covering `url`.
+    fn coverage(&self, url: &HttpUrl) -> Option<usize> {
+        let origin = url.origin();
+        self.scope
+            .iter()
+            .filter(|(o, path)| *o == origin && url.path.starts_with(path.as_str()))
+            .map(|(_, path)| path.len())
+            .max()
+    }
+
+    fn covers(&self, url: &HttpUrl) -> bool {
+        self.proxy || self.coverage(url).is_some()
+    }
+
+    fn header(&mut self, method: &str, uri: &str, cnonce: &str) -> String {
+        match &mut self.digest {
+            Some(digest) => {
+                digest.authorization(&self.username, &self.password, method, uri, cnonce)
+            }
+            None => basic(&self.username, &self.password),
+        }
+    }
+}
+
+/// Basic credentials (RFC 7617) as an Authorization or Proxy-Authorization value.
+pub(crate) fn basic_credentials(username: &str, password: &str) -> Result<String, Error> {
+    if username
+        .chars()
+        .chain(password.chars())
+        .any(char::is_control)
+    {
+        return Err(Error::Auth("control characters in credentials"));
+    }
+    if username.contains(':') {
+        return Err(Error::Auth("a Basic user-id cannot contain a colon"));
+    }
+    Ok(basic(username, password))
+}
+
+fn basic(username: &str, password: &str) -> String {
+    format!(
+        "Basic {}",
+        base64::encode(format!("{}:{}", username, password).as_bytes())
+    )
+}
+
+impl fmt::Debug for Space {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Space")
+            .field("proxy", &self.proxy)
+            .field("authority", &self.authority)
+            .field("realm", &self.realm)
+            .field("username", &self.username)
+            .field("digest", &self.digest.is_some())
+            .finish()
+    }
+}
+
+/// A challenge this client can answer.
+enum Offer {
+    Basic { realm: String },
+    Digest(Digest),
+}
+
+impl Offer {
+    fn realm(&self) -> &str {
+        match self {
+            Offer::Basic { realm } => realm,
+            Offer::Digest(digest) => &digest.realm,
+        }
+    }
+
+    /// Basic below every Digest, Digest by hash strength.
+    fn rank(&self) -> u8 {
+        match self {
+            Offer::Basic { .. } => 0,
+            Offer::Digest(digest) => 1 + digest.algorithm as u8,
+        }
+    }
+}
+
+/// Credentials by protection space, for a session. See the module docs.
+#[wasm_bindgen]
+#[derive(Clone, Debug, Default)]
+pub struct AuthStore {
+    /// Oldest first
+    spaces: Vec<Space>,
+    /// Client nonce to use instead of a random one
+    cnonce: Option<String>,
+}
+
+#[wasm_bindgen]
+impl AuthStore {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> AuthStore {
+        AuthStore::default()
+    }
+
+    /// Authorization value to send with a request to `url` before being asked:
+    /// the credentials of the space covering it, if any. Basic covers the
+    /// directory of the URL that was challenged and everything below it; Digest
+    /// the URIs of its domain parameter, or the whole origin.
+    pub fn authorization(&mut self, method: &str, url: &str) -> Result<Option<String>, Error> {
+        let url = HttpUrl::parse(url)?;
+        let best = self
+            .spaces
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| !s.proxy)
+            .filter_map(|(i, s)| Some((s.coverage(&url)?, i)))
+            .max();
+        match best {
+            Some((_, i)) => self.header_at(i, method, &url).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Proxy-Authorization value for a request to `url` through the proxy at
+    /// `proxy` (its "host:port"), if the proxy has asked for credentials before.
+    pub fn proxy_authorization(
+        &mut self,
+        method: &str,
+        url: &str,
+        proxy: &str,
+    ) -> Result<Option<String>, Error> {
+        let url = HttpUrl::parse(url)?;
+        let proxy = proxy.to_ascii_lowercase();
+        match self
+            .spaces
+            .iter()
+            .rposition(|s| s.proxy && s.authority == proxy)
+        {
+            Some(i) => self.header_at(i, method, &url).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Answer a 401, or a 407 from the proxy at `proxy`; returns the value of
+    /// Authorization (or Proxy-Authorization) to send the request again with, or
+    /// null when it cannot be answered and the response is final. Other statuses
+    /// return null.
+    ///
+    /// Credentials the store already holds for the realm are used when they were
+    /// not sent with the request, or a Digest nonce expired (`stale=true`).
+    /// Otherwise they are forgotten and `credentials` is called with the
+    /// `AuthChallenge`; it returns `{ username, password }`, or null to give up.
+    /// `retried` says the request was already the retry: it is not answered
+    /// again, so each request is retried at most once.
+    #[allow(clippy::too_many_arguments)]
+    pub fn on_response(
+        &mut self,
+        method: &str,
+        url: &str,
+        proxy: Option<String>,
+        status: u16,
+        names: Vec<String>,
+        values: Vec<String>,
+        retried: bool,
+        credentials: &js_sys::Function,
+    ) -> Result<Option<String>, Error> {
+        if names.len() != values.len() {
+            return Err(Error::HeaderCountMismatch);
+        }
+        let url = HttpUrl::parse(url)?;
+        let mut headers = Headers::new();
+        for (name, value) in names.into_iter().zip(values) {
+            headers.push(name, value);
+        }
+        let ask = |challenge: &AuthChallenge| {
+            let answer = credentials
+                .call1(&JsValue::NULL, &JsValue::from(challenge.clone()))
+                .map_err(|_| Error::Auth("the credentials callback threw"))?;
+            if answer.is_null() || answer.is_undefined() {
+                return Ok(None);
+            }
+            let field = |name: &str| {
+                js_sys::Reflect::get(&answer, &JsValue::from_str(name))
+                    .ok()
+                    .and_then(|v| v.as_string())
+            };
+            match (field("username"), field("password")) {
+                (Some(username), Some(password)) => Ok(Some((username, password))),
+                _ => Err(Error::Auth("credentials need a username and a password")),
+            }
+        };
+        self.answer(
+            method,
+            &url,
+            proxy.as_deref(),
+            status,
+            &headers,
+            retried,
+            ask,
+        )
+    }
+
+    /// Forget every credential.
+    pub fn clear(&mut self) {
+        self.spaces.clear();
+    }
+
+    /// Protection spaces with credentials.
+    pub fn len(&self) -> usize {
+        self.spaces.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spaces.is_empty()
+    }
+}
+
+impl AuthStore {
+    /// `on_response` with the response headers already collected; `credentials`
+    /// returns the username and password.
+    #[allow(clippy::too_many_arguments)]
+    pub fn answer(
+        &mut self,
+        method: &str,
+        url: &HttpUrl,
+        proxy: Option<&str>,
+        status: u16,
+        headers: &Headers,
+        retried: bool,
+        credentials: impl FnOnce(&AuthChallenge) -> Result<Option<(String, String)>, Error>,
+    ) -> Result<Option<String>, Error> {
+        let (field, authority) = match (status, proxy) {
+            (401, _) => ("www-authenticate", url.origin()),
+            (407, Some(proxy)) => ("proxy-authenticate", proxy.to_ascii_lowercase()),
+            _ => return Ok(None),
+        };
+        let is_proxy = status == 407;
+        let Some(offer) = best_offer(headers.get_all(field)) else {
+            return Ok(None);
+        };
+
+        let known = self.spaces.iter().position(|s| {
+            s.proxy == is_proxy
+                && s.authority == authority
+                && s.realm == offer.realm()
+                && s.digest.is_some() == matches!(offer, Offer::Digest(_))
+        });
+        if let Some(i) = known {
+            let stale = matches!(&offer, Offer::Digest(d) if d.stale);
+            let space = &mut self.spaces[i];
+            if !retried && (stale || !space.covers(url)) {
+                if let Offer::Digest(digest) = offer {
+                    space.scope.extend(digest_scope(&digest, url));
+                    space.digest = Some(digest);
+                } else {
+                    space.scope.push((url.origin(), directory(&url.path)));
+                }
+                return self.header_at(i, method, url).map(Some);
+            }
+            if !stale {
+                // Sent and rejected
+                self.spaces.remove(i);
+            }
+        }
+        if retried {
+            return Ok(None);
+        }
+
+        let challenge = AuthChallenge {
+            scheme: match offer {
+                Offer::Basic { .. } => "Basic",
+                Offer::Digest(_) => "Digest",
+            },
+            realm: offer.realm().to_string(),
+            url: url.to_string(),
+            proxy: is_proxy.then(|| authority.clone()),
+            algorithm: match &offer {
+                Offer::Basic { .. } => None,
+                Offer::Digest(d) => Some(algorithm_name(d).to_string()),
+            },
+        };
+        let Some((username, password)) = credentials(&challenge)? else {
+            return Ok(None);
+        };
+        if username
+            .chars()
+            .chain(password.chars())
+            .any(char::is_control)
+        {
+            return Err(Error::Auth("control characters in credentials"));
+        }
+        let (scope, digest) = match offer {
+            Offer::Basic { .. } if username.contains(':') => {
+                return Err(Error::Auth("a Basic user-id cannot contain a colon"));
+            }
+            Offer::Basic { .. } => (vec![(url.origin(), directory(&url.path))], None),
+            Offer::Digest(digest) => (digest_scope(&digest, url), Some(digest)),
+        };
+        self.spaces.push(Space {
+            proxy: is_proxy,
+            authority,
+            realm: challenge.realm,
+            scope,
+            username,
+            password,
+            digest,
+        });
+        self.header_at(self.spaces.len() - 1, method, url).map(Some)
+    }
+
+    fn header_at(&mut self, i: usize, method: &str, url: &HttpUrl) -> Result<String, Error> {
+        let cnonce = match (&self.spaces[i].digest, &self.cnonce) {
+            (None, _) => String::new(),
+            (Some(_), Some(fixed)) => fixed.clone(),
+            (Some(_), None) => {
+                let mut random = [0u8; 8];
+                getrandom::fill(&mut random).map_err(|_| Error::Auth("no random source"))?;
+                hex(&random)
+            }
+        };
+        let space = &mut self.spaces[i];
+        let uri = request_uri(method, url, space.proxy);
+        Ok(space.header(method, &uri, &cnonce))
+    }
+}
+
+/// The strongest challenge of the fields that this client supports; the first
+/// of equals.
+fn best_offer<'a>(fields: impl Iterator<Item = &'a str>) -> Option<Offer> {
+    let offers: Vec<Offer> = fields
+        .flat_map(parse_challenges)
+        .filter_map(|c| match c.scheme.as_str() {
+            "basic" => Some(Offer::Basic {
+                realm: c.param("realm").unwrap_or_default().to_string(),
+            }),
+            _ => Digest::from_challenge(&c).map(Offer::Digest),
+        })
+        .collect();
+    offers.into_iter().rev().max_by_key(Offer::rank)
+}
+
+fn algorithm_name(digest: &Digest) -> &'static str {
+    match digest.algorithm {
+        digest::Algorithm::Md5 => "MD5",
+        digest::Algorithm::Sha256 => "SHA-256",
+        digest::Algorithm::Sha512_256 => "SHA-512-256",
+    }
+}
+
+/// Where Digest credentials apply: the domain URIs, resolved against `url`, or
+/// its whole origin.
+fn digest_scope(digest: &Digest, url: &HttpUrl) -> Vec<(String, String)> {
+    let scope: Vec<(String, String)> = digest
+        .domain
+        .iter()
+        .filter_map(|uri| url.join(uri).ok())
+        .map(|u| (u.origin(), u.path))
+        .collect();
+    if scope.is_empty() {
+        vec![(url.origin(), "/".to_string())]
+    } else {
+        scope
+    }
+}
+
+/// The path up to its last '/' (RFC 7617 §2.2).
+fn directory(path: &str) -> String {
+    path[..=path.rfind('/').unwrap_or(0)].to_string()
+}
+
+/// The request-target Digest covers: origin-form, absolute-form to a proxy, or
+/// authority-form for CONNECT.
+fn request_uri(method: &str, url: &HttpUrl, proxy: bool) -> String {
+    match (proxy, method) {
+        (false, _) => url.target(),
+        (true, "CONNECT") => format!("{}:{}", url.host, url.port),
+        (true, _) => url.without_fragment(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(s: &str) -> HttpUrl {
+        HttpUrl::parse(s).unwrap()
+    }
+
+    fn challenge(field: &str, value: &str) -> Headers {
+        let mut headers = Headers::new();
+        headers.push(field, value);
+        headers
+    }
+
+    fn mufasa() -> Result<Option<(String, String)>, Error> {
+        Ok(Some(("Mufasa".to_string(), "Circle of Life".to_string())))
+    }
+
+    #[test]
+    fn basic_retry_once_then_preemptive() {
+        let mut store = AuthStore::new();
+        let page = url("https://example.com/docs/a.html");
+        let basic = challenge("WWW-Authenticate", r#"Basic realm="docs", charset="UTF-8""#);
+        let mut asked = None;
+        let value = store
+            .answer("GET", &page, None, 401, &basic, false, |c| {
+                asked = Some(c.clone());
+                mufasa()
+            })
+            .unwrap();
+        assert_eq!(value.as_deref(), Some("Basic TXVmYXNhOkNpcmNsZSBvZiBMaWZl"));
+        let asked = asked.unwrap();
+        assert_eq!(
+            (asked.scheme(), asked.realm()),
+            ("Basic".into(), "docs".into())
+        );
+        assert_eq!((asked.proxy(), asked.algorithm()), (None, None));
+
+        // The directory and below get the credentials up front
+        let sent = |store: &mut AuthStore, u: &str| store.authorization("GET", u).unwrap();
+        assert_eq!(sent(&mut store, "https://example.com/docs/b/c"), value);
+        assert_eq!(sent(&mut store, "https://example.com/other"), None);
+        assert_eq!(sent(&mut store, "http://example.com/docs/"), None);
+
+        // Another directory of the realm reuses them without asking
+        let other = url("https://example.com/other");
+        let reused = store
+            .answer("GET", &other, None, 401, &basic, false, |_| panic!("asked"))
+            .unwrap();
+        assert_eq!(reused, value);
+        assert_eq!(sent(&mut store, "https://example.com/other/x"), value);
+
+        // The retry is rejected as well: final, and the credentials are dropped
+        let rejected = store.answer("GET", &page, None, 401, &basic, true, |_| panic!("asked"));
+        assert_eq!(rejected, Ok(None));
+        assert!(store.is_empty());
+        let declined = store.answer("GET", &page, None, 401, &basic, false, |_| Ok(None));
+        assert_eq!(declined, Ok(None));
+
+        let colon = store.answer("GET", &page, None, 401, &basic, false, |_| {
+            Ok(Some(("a:b".to_string(), "p".to_string())))
+        });
+        assert!(matches!(colon, Err(Error::Auth(_))));
+        assert!(format!("{:?}", store).find("Circle").is_none());
+    }
+
+    #[test]
+    fn digest_preferred_counted_and_renewed() {
+        let mut store = AuthStore::new();
+        store.cnonce = Some("0a4f113b".to_string());
+        let mut headers = challenge("WWW-Authenticate", r#"Basic realm="testrealm@host.com""#);
+        headers.push(
+            "WWW-Authenticate",
+            r#"Digest realm="testrealm@host.com", qop="auth,auth-int", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", opaque="5ccc069c403ebaf9f0171e9517f40e41""#,
+        );
+        let page = url("http://www.nowhere.org/dir/index.html");
+        let value = store
+            .answer("GET", &page, None, 401, &headers, false, |c| {
+                assert_eq!(c.algorithm().as_deref(), Some("MD5"));
+                Ok(Some(("Mufasa".to_string(), "Circle Of Life".to_string())))
+            })
+            .unwrap()
+            .unwrap();
+        assert!(value.starts_with("Digest username=\"Mufasa\""));
+        assert!(value.contains("response=\"6629fae49393a05397450978507c4ef1\""));
+        assert!(value.contains("opaque=\"5ccc069c403ebaf9f0171e9517f40e41\""));
+
+        // The whole origin, counting requests under the nonce
+        let next = store
+            .authorization("GET", "http://www.nowhere.org/elsewhere")
+            .unwrap()
+            .unwrap();
+        assert!(next.contains("uri=\"/elsewhere\"") && next.contains("nc=00000002"));
+
+        // A stale nonce is renewed without asking again
+        let stale = challenge(
+            "WWW-Authenticate",
+            r#"Digest realm="testrealm@host.com", qop="auth", nonce="fresh", stale=true"#,
+        );
+        let renewed = store
+            .answer("GET", &page, None, 401, &stale, false, |_| panic!("asked"))
+            .unwrap()
+            .unwrap();
+        assert!(renewed.contains("nonce=\"fresh\"") && renewed.contains("nc=00000001"));
+        assert!(!renewed.contains("opaque"));
+    }
+
+    #[test]
+    fn proxy_challenges() {
+        let mut store = AuthStore::new();
+        store.cnonce = Some("c".to_string());
+        let headers = challenge(
+            "Proxy-Authenticate",
+            r#"Digest realm="proxy", nonce="n", algorithm=SHA-256, qop="auth""#,
+        );
+        let target = url("https://example.com/");
+        assert_eq!(
+            store.answer("CONNECT", &target, None, 407, &headers, false, |_| mufasa()),
+            Ok(None)
+        );
+        let value = store
+            .answer(
+                "CONNECT",
+                &target,
+                Some("Proxy:3128"),
+                407,
+                &headers,
+                false,
+                |c| {
+                    assert_eq!(c.proxy().as_deref(), Some("proxy:3128"));
+                    mufasa()
+                },
+            )
+            .unwrap()
+            .unwrap();
+        assert!(value.contains("uri=\"example.com:443\"") && value.contains("algorithm=SHA-256"));
+        assert_eq!(
+            store.authorization("GET", "https://example.com/").unwrap(),
+            None
+        );
+        let next = store
+            .proxy_authorization("GET", "http://other.example/a#f", "proxy:3128")
+            .unwrap()
+            .unwrap();
+        assert!(next.contains("uri=\"http://other.example/a\""));
+    }
+
+    #[test]
+    fn directories() {
+        assert_eq!(directory("/docs/a.html"), "/docs/");
+        assert_eq!(directory("/"), "/");
+        assert_eq!(directory("/a/b/"), "/a/b/");
+    }
+}
@@ -0,0 +1,70 @@
+//! Standard base64 (RFC 4648 §4), for handshake keys, digests and wasm-tls's
+//! `TlsConnection::from_json`, and the URL-safe variant (§5) for DNS-over-HTTPS
+//! GET requests.
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Base64 with padding.
+pub fn encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Unpadded base64url: '-' and '_' in place of '+' and '/'.
+pub fn encode_url(data: &[u8]) -> String {
+    encode(data)
+        .trim_end_matches('=')
+        .replace('+', "-")
+        .replace('/', "_")
+}
+
+/// Decode base64, padded or not; None for any other character or a truncated group.
+pub fn decode(text: &str) -> Option<Vec<u8>> {
+    let text = text
+        .strip_suffix("==")
+        .or(text.strip_suffix('='))
+        .unwrap_or(text);
+    let mut out = Vec::with_capacity(text.len() * 3 / 4);
+    let (mut acc, mut bits) = (0u32, 0);
+    for b in text.bytes() {
+        let digit = ALPHABET.iter().position(|&a| a == b)? as u32;
+        acc = (acc << 6 | digit) & 0xffffff;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    // A lone trailing digit cannot encode a byte
+    (bits < 6).then_some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for (input, encoded) in [("", ""), ("f", "Zg=="), ("fo", "Zm8="), ("foo", "Zm9v")] {
+            assert_eq!(encode(input.as_bytes()), encoded);
+            assert_eq!(decode(encoded).unwrap(), input.as_bytes());
+        }
+        assert_eq!(decode("Zm8").unwrap(), b"fo");
+        assert_eq!(decode("Z"), None);
+        assert_eq!(decode("Zm-v"), None);
+        assert_eq!(encode_url(&[0xfb, 0xff]), "-_8");
+    }
+}
@@ -0,0 +1,689 @@
+//! HTTP caching (RFC 9111) without the cache: Cache-Control parsing, the
+//! freshness of a stored response, and revalidating it with a conditional request.
+//!
+//! Storage is the caller's (IndexedDB, a Map): a `CachedResponse` is what a
+//! private cache keeps next to the body, and round-trips through `export_json`.
+//! Like the cookie jar, nothing reads a clock; times are unix seconds passed in.
+//! Which request a stored response may answer (method, URL, Vary) is also left
+//! to the caller.
+
+use wasm_bindgen::prelude::*;
+
+use crate::cookie::parse_cookie_date;
+use crate::error::Error;
+use crate::headers::Headers;
+use crate::http1::RequestBuilder;
+use crate::json::{self, Value};
+
+/// Statuses that may be given a heuristic freshness lifetime (RFC 9110 §15.1).
+const HEURISTICALLY_CACHEABLE: [u16; 12] =
+    [200, 203, 204, 206, 300, 301, 308, 404, 405, 410, 414, 501];
+
+/// Share of the time since Last-Modified used as heuristic lifetime (§4.2.2).
+const HEURISTIC_FRACTION: i64 = 10;
+
+/// delta-seconds beyond this are taken as this (§1.2.2).
+const MAX_DELTA_SECONDS: u32 = 2_147_483_648;
+
+/// Fields a 304 does not update in the stored response (§3.2): hop-by-hop
+/// ones, and those describing the stored body rather than the representation.
+const NOT_UPDATED: [&str; 10] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "content-length",
+    "content-encoding",
+    "content-range",
+];
+
+/// The Cache-Control directives that decide whether and how long a response
+/// may be reused (RFC 9111 §5.2.2). Directives without a meaning here are ignored.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheControl {
+    max_age: Option<u32>,
+    s_maxage: Option<u32>,
+    no_store: bool,
+    no_cache: bool,
+    must_revalidate: bool,
+    stale_while_revalidate: Option<u32>,
+}
+
+#[wasm_bindgen]
+impl CacheControl {
+    /// Parse a Cache-Control value, with several header lines joined by commas.
+    /// Parsing never fails: a directive repeated keeps its first value, and a
+    /// max-age that is not a number makes the response stale (§4.2.1).
+    pub fn parse(value: &str) -> CacheControl {
+        let mut cc = CacheControl::default();
+        let mut seen = Vec::new();
+        for directive in split_directives(value) {
+            let (name, argument) = match directive.split_once('=') {
+                Some((name, argument)) => (name.trim(), Some(unquote(argument.trim()))),
+                None => (directive, None),
+            };
+            let name = name.to_ascii_lowercase();
+            if seen.contains(&name) {
+                continue;
+            }
+            let seconds = || Some(argument.as_deref().map_or(0, delta_seconds));
+            match name.as_str() {
+                "max-age" => cc.max_age = seconds(),
+                "s-maxage" => cc.s_maxage = seconds(),
+                "stale-while-revalidate" => cc.stale_while_revalidate = seconds(),
+                // With field names (`no-cache="Set-Cookie"`) it is taken as plain
+                // no-cache, which is allowed and safe
+                "no-cache" => cc.no_cache = true,
+                "no-store" => cc.no_store = true,
+                "must-revalidate" | "proxy-revalidate" => cc.must_revalidate = true,
+                _ => {}
+            }
+            seen.push(name);
+        }
+        cc
+    }
+
+    /// `max-age` in seconds, or null.
+    pub fn max_age(&self) -> Option<u32> {
+        self.max_age
+    }
+
+    /// `s-maxage`, for shared caches only; `CachedResponse` does not use it.
+    pub fn s_maxage(&self) -> Option<u32> {
+        self.s_maxage
+    }
+
+    pub fn no_store(&self) -> bool {
+        self.no_store
+    }
+
+    /// The response must be revalidated before each reuse, fresh or not.
+    pub fn no_cache(&self) -> bool {
+        self.no_cache
+    }
+
+    /// Once stale the response must not be reused without revalidating, which
+    /// also rules out stale-while-revalidate.
+    pub fn must_revalidate(&self) -> bool {
+        self.must_revalidate
+    }
+
+    /// `stale-while-revalidate` (RFC 5861) in seconds, or null.
+    pub fn stale_while_revalidate(&self) -> Option<u32> {
+        self.stale_while_revalidate
+    }
+}
+
+impl CacheControl {
+    /// Every Cache-Control line of `headers` together.
+    pub fn from_headers(headers: &Headers) -> CacheControl {
+        let lines: Vec<&str> = headers.get_all("cache-control").collect();
+        CacheControl::parse(&lines.join(","))
+    }
+}
+
+/// A stored response without its body: status, headers and when it was
+/// received, which is all freshness and revalidation need.
+#[wasm_bindgen]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CachedResponse {
+    status: u16,
+    headers: Headers,
+    /// When the request that produced it was sent
+    request_time: i64,
+    /// When the response arrived
+    response_time: i64,
+}
+
+#[wasm_bindgen]
+impl CachedResponse {
+    /// A response received at `response_time` to a request sent at
+    /// `request_time`, e.g. the `header_names()`/`header_values()` of a response
+    /// event. Check `is_storable` before keeping it.
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        status: u16,
+        names: Vec<String>,
+        values: Vec<String>,
+        request_time: f64,
+        response_time: f64,
+    ) -> Result<CachedResponse, Error> {
+        let headers = collect(names, values)?;
+        Ok(CachedResponse::from_headers(
+            status,
+            headers,
+            request_time,
+            response_time,
+        ))
+    }
+
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// Stored header names, updated by each 304 passed to `on_revalidated`.
+    pub fn header_names(&self) -> Vec<String> {
+        self.headers.names()
+    }
+
+    /// Values matching `header_names`.
+    pub fn header_values(&self) -> Vec<String> {
+        self.headers.values()
+    }
+
+    pub fn etag(&self) -> Option<String> {
+        self.headers.get("etag").map(|v| v.trim().to_string())
+    }
+
+    pub fn last_modified(&self) -> Option<String> {
+        self.headers
+            .get("last-modified")
+            .map(|v| v.trim().to_string())
+    }
+
+    pub fn cache_control(&self) -> CacheControl {
+        CacheControl::from_headers(&self.headers)
+    }
+
+    /// Whether a private cache may store the response to a GET (§3): a final
+    /// status other than 206 (partial content needs a range-aware cache), no
+    /// no-store, and either explicit freshness or a status cacheable by default.
+    pub fn is_storable(&self) -> bool {
+        let cc = self.cache_control();
+        self.status >= 200
+            && self.status != 206
+            && !cc.no_store
+            && (cc.max_age.is_some()
+                || self.headers.contains("expires")
+                || HEURISTICALLY_CACHEABLE.contains(&self.status)
+                || has_directive(&self.headers, "public"))
+    }
+
+    /// How long the response is fresh, in seconds after it was generated
+    /// (§4.2.1): max-age, else Expires minus Date, else a tenth of the time
+    /// since Last-Modified for statuses that allow a heuristic, else 0.
+    pub fn freshness_lifetime(&self) -> f64 {
+        let cc = self.cache_control();
+        if let Some(max_age) = cc.max_age {
+            return max_age as f64;
+        }
+        let date = self.date();
+        if let Some(expires) = self.headers.get("expires") {
+            // An invalid date, e.g. "0", means already expired
+            return parse_cookie_date(expires).map_or(0, |e| (e - date).max(0)) as f64;
+        }
+        let last_modified = self
+            .headers
+            .get("last-modified")
+            .and_then(parse_cookie_date);
+        match last_modified {
+            Some(lm) if HEURISTICALLY_CACHEABLE.contains(&self.status) => {
+                ((date - lm).max(0) / HEURISTIC_FRACTION) as f64
+            }
+            _ => 0.0,
+        }
+    }
+
+    /// Current age in seconds (§4.2.3), counting the Age header, the time the
+    /// response was in transit, and the time it has been stored.
+    pub fn age(&self, now: f64) -> f64 {
+        let age_value = self
+            .headers
+            .get("age")
+            .map_or(0, |v| delta_seconds(v.trim())) as i64;
+        let apparent_age = (self.response_time - self.date()).max(0);
+        let response_delay = (self.response_time - self.request_time).max(0);
+        let corrected_initial_age = apparent_age.max(age_value + response_delay);
+        (corrected_initial_age + (now as i64 - self.response_time)) as f64
+    }
+
+    /// Whether the response is still fresh at `now`.
+    pub fn is_fresh(&self, now: f64) -> bool {
+        self.age(now) < self.freshness_lifetime()
+    }
+
+    /// What to do with the stored response for a request at `now`:
+    /// - "fresh": use it without contacting the server
+    /// - "stale-while-revalidate": use it, and revalidate in the background
+    /// - "revalidate": send the conditional request (see `apply`) first
+    pub fn usability(&self, now: f64) -> String {
+        let cc = self.cache_control();
+        let age = self.age(now);
+        let lifetime = self.freshness_lifetime();
+        let outcome = if cc.no_cache {
+            "revalidate"
+        } else if age < lifetime {
+            "fresh"
+        } else if !cc.must_revalidate
+            && cc
+                .stale_while_revalidate
+                .is_some_and(|swr| age < lifetime + swr as f64)
+        {
+            "stale-while-revalidate"
+        } else {
+            "revalidate"
+        };
+        outcome.to_string()
+    }
+
+    /// Whether there is a validator to revalidate with; without one the
+    /// response can only be fetched again in full.
+    pub fn can_revalidate(&self) -> bool {
+        self.conditional_headers().next().is_some()
+    }
+
+    /// Add the conditional headers to `request`: If-None-Match with the ETag and
+    /// If-Modified-Since with Last-Modified (§4.3.1), replacing ones added earlier.
+    pub fn apply(&self, request: &mut RequestBuilder) -> Result<(), Error> {
+        for (name, value) in self.conditional_headers() {
+            if !request.replace_header(&name.to_ascii_lowercase(), &value) {
+                request.header(name, &value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Names of the conditional headers, for `Http2Connection::open_stream`.
+    pub fn conditional_names(&self) -> Vec<String> {
+        self.conditional_headers()
+            .map(|(n, _)| n.to_string())
+            .collect()
+    }
+
+    /// Values matching `conditional_names`.
+    pub fn conditional_values(&self) -> Vec<String> {
+        self.conditional_headers().map(|(_, v)| v).collect()
+    }
+
+    /// Handle the response to a conditional request:
+    /// - "not-modified": a 304; the stored headers and times are updated (§4.3.4)
+    ///   and the stored body is still the one to use
+    /// - "modified": anything else; this body replaces the stored response
+    ///
+    /// A 304 whose ETag differs from the stored one fails with `NotModifiedMismatch`:
+    /// the stored body is not the one the server validated.
+    pub fn on_revalidated(
+        &mut self,
+        status: u16,
+        names: Vec<String>,
+        values: Vec<String>,
+        request_time: f64,
+        response_time: f64,
+    ) -> Result<String, Error> {
+        let headers = collect(names, values)?;
+        self.revalidated(status, &headers, request_time, response_time)
+            .map(str::to_string)
+    }
+
+    /// The stored response as JSON, to persist until `import_json`.
+    pub fn export_json(&self) -> String {
+        let mut out = format!(
+            "{{\"status\":{},\"requestTime\":{},\"responseTime\":{},\"headers\":[",
+            self.status, self.request_time, self.response_time
+        );
+        for (i, (name, value)) in self.headers.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            out.push('[');
+            json::quote(name, &mut out);
+            out.push(',');
+            json::quote(value, &mut out);
+            out.push(']');
+        }
+        out.push_str("]}");
+        out
+    }
+
+    /// A stored response from `export_json` output.
+    pub fn import_json(json: &str) -> Result<CachedResponse, Error> {
+        let Some(entry) = json::parse(json) else {
+            return Err(Error::InvalidCachedResponse("expected a JSON object"));
+        };
+        let status = match entry.get("status") {
+            Some(Value::Number(n)) if n.fract() == 0.0 && (100.0..1000.0).contains(n) => *n as u16,
+            _ => return Err(Error::InvalidCachedResponse("status")),
+        };
+        let time = |key: &'static str| match entry.get(key) {
+            Some(Value::Number(n)) if n.fract() == 0.0 && n.abs() < 9e15 => Ok(*n as i64),
+            _ => Err(Error::InvalidCachedResponse(key)),
+        };
+        let (request_time, response_time) = (time("requestTime")?, time("responseTime")?);
+        let Some(Value::Array(fields)) = entry.get("headers") else {
+            return Err(Error::InvalidCachedResponse("headers"));
+        };
+        let mut headers = Headers::new();
+        for field in fields {
+            match field {
+                Value::Array(pair) => match pair.as_slice() {
+                    [Value::String(name), Value::String(value)] => headers.push(name, value),
+                    _ => return Err(Error::InvalidCachedResponse("headers")),
+                },
+                _ => return Err(Error::InvalidCachedResponse("headers")),
+            }
+        }
+        Ok(CachedResponse {
+            status,
+            headers,
+            request_time,
+            response_time,
+        })
+    }
+}
+
+impl CachedResponse {
+    /// `new` with the headers already collected.
+    pub fn from_headers(
+        status: u16,
+        headers: Headers,
+        request_time: f64,
+        response_time: f64,
+    ) -> CachedResponse {
+        CachedResponse {
+            status,
+            headers,
+            request_time: request_time as i64,
+            response_time: response_time as i64,
+        }
+    }
+
+    /// `on_revalidated` with the headers already collected.
+    pub fn revalidated(
+        &mut self,
+        status: u16,
+        headers: &Headers,
+        request_time: f64,
+        response_time: f64,
+    ) -> Result<&'static str, Error> {
+        if status != 304 {
+            return Ok("modified");
+        }
+        if let (Some(stored), Some(validated)) = (self.etag(), headers.get("etag")) {
+            if weak_tag(&stored) != weak_tag(validated.trim()) {
+                return Err(Error::NotModifiedMismatch);
+            }
+        }
+        let mut updated = Headers::new();
+        for (name, value) in self.headers.iter() {
+            if !headers.contains(name) || NOT_UPDATED.contains(&name.to_ascii_lowercase().as_str())
+            {
+                updated.push(name, value);
+            }
+        }
+        for (name, value) in headers.iter() {
+            if !NOT_UPDATED.contains(&name.to_ascii_lowercase().as_str()) {
+                updated.push(name, value);
+            }
+        }
+        self.headers = updated;
+        self.request_time = request_time as i64;
+        self.response_time = response_time as i64;
+        Ok("not-modified")
+    }
+
+    /// The Date header, or the time the response arrived without one (§4.2.3).
+    fn date(&self) -> i64 {
+        self.headers
+            .get("date")
+            .and_then(parse_cookie_date)
+            .unwrap_or(self.response_time)
+    }
+
+    fn conditional_headers(&self) -> impl Iterator<Item = (&'static str, String)> + '_ {
+        let etag = self.etag().map(|v| ("If-None-Match", v));
+        let last_modified = self.last_modified().map(|v| ("If-Modified-Since", v));
+        etag.into_iter().chain(last_modified)
+    }
+}
+
+fn collect(names: Vec<String>, values: Vec<String>) -> Result<Headers, Error> {
+    if names.len() != values.len() {
+        return Err(Error::HeaderCountMismatch);
+    }
+    let mut headers = Headers::new();
+    for (name, value) in names.into_iter().zip(values) {
+        headers.push(name, value);
+    }
+    Ok(headers)
+}
+
+fn has_directive(headers: &Headers, directive: &str) -> bool {
+    headers.get_all("cache-control").any(|line| {
+        split_directives(line).any(|d| {
+            d.split('=')
+                .next()
+                .is_some_and(|name| name.trim().eq_ignore_ascii_case(directive))
+        })
+    })
+}
+
+/// Directives split on commas outside quoted strings, trimmed, empty ones dropped.
+fn split_directives(value: &str) -> impl Iterator<Item = &str> {
+    let mut parts = Vec::new();
+    let (mut start, mut quoted) = (0, false);
+    for (i, c) in value.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                parts.push(&value[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&value[start..]);
+    parts.into_iter().map(str::trim).filter(|d| !d.is_empty())
+}
+
+fn unquote(value: &str) -> String {
+    value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(value)
+        .to_string()
+}
+
+/// §1.2.2: digits only, capped; anything else counts as 0 (stale).
+fn delta_seconds(value: &str) -> u32 {
+    if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return 0;
+    }
+    value.parse().unwrap_or(MAX_DELTA_SECONDS)
+}
+
+/// The opaque-tag for weak comparison (RFC 9110 §8.8.3.2).
+fn weak_tag(etag: &str) -> &str {
+    etag.strip_prefix("W/").unwrap_or(etag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: f64 = 1_700_000_000.0;
+    /// `NOW` as an IMF-fixdate
+    const NOW_DATE: &str = "Tue, 14 Nov 2023 22:13:20 GMT";
+
+    fn response(status: u16, fields: &[(&str, &str)]) -> CachedResponse {
+        let mut headers = Headers::new();
+        for (name, value) in fields {
+            headers.push(*name, *value);
+        }
+        CachedResponse::from_headers(status, headers, NOW - 1.0, NOW)
+    }
+
+    #[test]
+    fn cache_control_directives() {
+        let cc = CacheControl::parse(
+            "public, Max-Age=\"600\", max-age=5, s-maxage=60, no-cache=\"Set-Cookie, X\", \
+             stale-while-revalidate=30, must-revalidate, unknown=1",
+        );
+        assert_eq!(cc.max_age(), Some(600));
+        assert_eq!(cc.s_maxage(), Some(60));
+        assert_eq!(cc.stale_while_revalidate(), Some(30));
+        assert!(cc.no_cache() && cc.must_revalidate() && !cc.no_store());
+        assert_eq!(CacheControl::parse("max-age=abc").max_age(), Some(0));
+        assert_eq!(
+            CacheControl::parse("max-age=99999999999").max_age(),
+            Some(MAX_DELTA_SECONDS)
+        );
+        assert!(CacheControl::parse(" , no-store,,").no_store());
+        assert_eq!(CacheControl::parse(""), CacheControl::default());
+    }
+
+    #[test]
+    fn freshness_and_age() {
+        // max-age wins over Expires; Age and the request's round trip count
+        let cached = response(
+            200,
+            &[
+                ("Date", NOW_DATE),
+                ("Cache-Control", "max-age=100"),
+                ("Expires", "Thu, 01 Jan 1970 00:00:00 GMT"),
+                ("Age", "10"),
+            ],
+        );
+        assert_eq!(cached.freshness_lifetime(), 100.0);
+        assert_eq!(cached.age(NOW), 11.0);
+        assert!(cached.is_fresh(NOW + 88.0));
+        assert!(!cached.is_fresh(NOW + 89.0));
+
+        let expires = response(
+            200,
+            &[
+                ("Date", NOW_DATE),
+                ("Expires", "Tue, 14 Nov 2023 23:13:20 GMT"),
+            ],
+        );
+        assert_eq!(expires.freshness_lifetime(), 3600.0);
+        assert_eq!(response(200, &[("Expires", "0")]).freshness_lifetime(), 0.0);
+
+        // A tenth of the 10 days since Last-Modified, only for cacheable statuses
+        let modified = [
+            ("Date", NOW_DATE),
+            ("Last-Modified", "Sat, 04 Nov 2023 22:13:20 GMT"),
+        ];
+        assert_eq!(response(200, &modified).freshness_lifetime(), 86400.0);
+        assert_eq!(response(302, &modified).freshness_lifetime(), 0.0);
+        assert_eq!(response(200, &[]).freshness_lifetime(), 0.0);
+    }
+
+    #[test]
+    fn storable_and_usability() {
+        assert!(response(200, &[]).is_storable());
+        assert!(!response(200, &[("Cache-Control", "max-age=60, no-store")]).is_storable());
+        assert!(!response(206, &[("Cache-Control", "max-age=60")]).is_storable());
+        assert!(!response(302, &[]).is_storable());
+        assert!(response(302, &[("Cache-Control", "max-age=60")]).is_storable());
+        assert!(response(302, &[("Cache-Control", "public")]).is_storable());
+
+        let swr = response(
+            200,
+            &[("Cache-Control", "max-age=60, stale-while-revalidate=30")],
+        );
+        assert_eq!(swr.usability(NOW + 30.0), "fresh");
+        assert_eq!(swr.usability(NOW + 70.0), "stale-while-revalidate");
+        assert_eq!(swr.usability(NOW + 100.0), "revalidate");
+        let strict = response(
+            200,
+            &[(
+                "Cache-Control",
+                "max-age=60, stale-while-revalidate=30, must-revalidate",
+            )],
+        );
+        assert_eq!(strict.usability(NOW + 70.0), "revalidate");
+        let no_cache = response(200, &[("Cache-Control", "no-cache, max-age=60")]);
+        assert_eq!(no_cache.usability(NOW), "revalidate");
+    }
+
+    #[test]
+    fn conditional_request_and_not_modified() {
+        let mut cached = response(
+            200,
+            &[
+                ("ETag", "\"v1\""),
+                ("Last-Modified", "Sat, 04 Nov 2023 22:13:20 GMT"),
+                ("Cache-Control", "max-age=60"),
+                ("Content-Length", "5"),
+                ("X-Kept", "a"),
+            ],
+        );
+        assert!(cached.can_revalidate());
+        assert_eq!(
+            cached.conditional_names(),
+            ["If-None-Match", "If-Modified-Since"]
+        );
+        let mut request = RequestBuilder::new("GET", "/").unwrap();
+        request.set_host("example.com").unwrap();
+        request.header("If-None-Match", "\"old\"").unwrap();
+        cached.apply(&mut request).unwrap();
+        let bytes = String::from_utf8(request.to_bytes().unwrap()).unwrap();
+        assert!(bytes.contains(
+            "\r\nIf-None-Match: \"v1\"\r\nIf-Modified-Since: Sat, 04 Nov 2023 22:13:20 GMT\r\n"
+        ));
+
+        let not_modified = |fields: &[(&str, &str)]| {
+            let names = fields.iter().map(|(n, _)| n.to_string()).collect();
+            let values = fields.iter().map(|(_, v)| v.to_string()).collect();
+            (names, values)
+        };
+        let (names, values) = not_modified(&[
+            ("ETag", "W/\"v1\""),
+            ("Cache-Control", "max-age=120"),
+            ("Content-Length", "0"),
+        ]);
+        let outcome = cached
+            .on_revalidated(304, names, values, NOW + 99.0, NOW + 100.0)
+            .unwrap();
+        assert_eq!(outcome, "not-modified");
+        assert_eq!(
+            cached.header_names(),
+            [
+                "Last-Modified",
+                "Content-Length",
+                "X-Kept",
+                "ETag",
+                "Cache-Control"
+            ]
+        );
+        assert_eq!(cached.header_values()[1], "5");
+        assert!(cached.is_fresh(NOW + 200.0));
+
+        let (names, values) = not_modified(&[("ETag", "\"v2\"")]);
+        assert_eq!(
+            cached.on_revalidated(304, names, values, NOW, NOW),
+            Err(Error::NotModifiedMismatch)
+        );
+        assert_eq!(
+            cached
+                .on_revalidated(200, vec![], vec![], NOW, NOW)
+                .unwrap(),
+            "modified"
+        );
+        assert!(!response(200, &[]).can_revalidate());
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let cached = response(200, &[("ETag", "\"a\\b\""), ("Cache-Control", "max-age=1")]);
+        let json = cached.export_json();
+        assert_eq!(CachedResponse::import_json(&json).unwrap(), cached);
+        for (bad, field) in [
+            ("[]", "status"),
+            ("{\"status\":200}", "requestTime"),
+            (
+                "{\"status\":200,\"requestTime\":1,\"responseTime\":2,\"headers\":[[\"a\"]]}",
+                "headers",
+            ),
+        ] {
+            assert_eq!(
+                CachedResponse::import_json(bad),
+                Err(Error::InvalidCachedResponse(field))
+            );
+        }
+    }
+}
@@ -0,0 +1,461 @@
+//! Response body charsets: transcoding a body to UTF-8 as the WHATWG Encoding
+//! Standard decodes it, with the encoding taken from a BOM, the Content-Type
+//! header or an HTML `<meta>` tag.
+
+use std::mem;
+
+use encoding_rs::{CoderResult, Decoder, Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
+use wasm_bindgen::prelude::*;
+
+use crate::error::Error;
+use crate::headers::Headers;
+
+/// Bytes the HTML standard's prescan looks at for a `<meta>` charset.
+pub const META_PRESCAN_LENGTH: usize = 1024;
+
+/// The byte order marks `Encoding::for_bom` recognizes.
+const BOMS: [&[u8]; 3] = [b"\xef\xbb\xbf", b"\xfe\xff", b"\xff\xfe"];
+
+/// Where the encoding in use came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Source {
+    Bom,
+    Label,
+    Header,
+    Meta,
+    Default,
+}
+
+impl Source {
+    fn as_str(self) -> &'static str {
+        match self {
+            Source::Bom => "bom",
+            Source::Label => "label",
+            Source::Header => "header",
+            Source::Meta => "meta",
+            Source::Default => "default",
+        }
+    }
+}
+
+/// Streaming transcoder from a response body's charset to UTF-8, for bodies
+/// that would otherwise each need a JS `TextDecoder` round trip. Every encoding
+/// of the Encoding Standard is supported, decoded by `encoding_rs`.
+///
+/// The encoding is chosen as browsers choose it for a document: a byte order
+/// mark wins, then the Content-Type charset, then (if enabled) a `<meta>` tag in
+/// the first 1024 bytes, else UTF-8. Feed the decoded body (after Content-Encoding)
+/// in chunks split anywhere; invalid sequences become U+FFFD, never an error.
+#[wasm_bindgen]
+#[derive(Debug)]
+pub struct CharsetDecoder {
+    /// None until a BOM, the header or the prescan has decided it
+    encoding: Option<&'static Encoding>,
+    source: Source,
+    sniff_meta: bool,
+    bom_checked: bool,
+    /// A charset the header named that is not an encoding label
+    unsupported: Option<String>,
+    /// The prefix held for sniffing
+    pending: Vec<u8>,
+    /// Set up once the encoding is decided and its BOM, if any, is dropped
+    decoder: Option<Decoder>,
+}
+
+#[wasm_bindgen]
+impl CharsetDecoder {
+    /// Decoder for a body with this Content-Type header value ("" if there is
+    /// none). With `sniff_meta`, a body whose header names no charset is held back
+    /// until 1024 bytes (or the end) have arrived and searched for a `<meta>` charset.
+    #[wasm_bindgen(constructor)]
+    pub fn new(content_type: &str, sniff_meta: bool) -> CharsetDecoder {
+        let mut decoder = CharsetDecoder {
+            encoding: None,
+            source: Source::Default,
+            sniff_meta,
+            bom_checked: false,
+            unsupported: None,
+            pending: Vec::new(),
+            decoder: None,
+        };
+        if let Some(label) = content_type_charset(content_type) {
+            match Encoding::for_label(label.as_bytes()) {
+                Some(encoding) => {
+                    decoder.encoding = Some(encoding);
+                    decoder.source = Source::Header;
+                }
+                None => decoder.unsupported = Some(label.to_string()),
+            }
+        }
+        decoder
+    }
+
+    /// Decoder for a charset label such as "windows-1251" or "latin1". A BOM in
+    /// the body still takes precedence, as it does for `TextDecoder`, which also
+    /// refuses the labels of the replacement encoding (e.g. "iso-2022-kr").
+    pub fn for_label(label: &str) -> Result<CharsetDecoder, Error> {
+        let encoding = Encoding::for_label_no_replacement(label.as_bytes())
+            .ok_or_else(|| Error::UnsupportedCharset(label.to_string()))?;
+        let mut decoder = CharsetDecoder::new("", false);
+        decoder.encoding = Some(encoding);
+        decoder.source = Source::Label;
+        Ok(decoder)
+    }
+
+    /// Transcode the next body chunk. The result may lag the input by a sequence
+    /// cut off at the chunk boundary, or by the prefix held for sniffing.
+    pub fn decode(&mut self, chunk: &[u8]) -> String {
+        let mut out = String::new();
+        self.decode_into(chunk, &mut out);
+        out
+    }
+
+    /// Signal the end of the body and return the rest of it; a trailing
+    /// incomplete sequence becomes U+FFFD.
+    pub fn finish(&mut self) -> String {
+        let mut out = String::new();
+        self.finish_into(&mut out);
+        out
+    }
+
+    /// Name of the encoding in use (e.g. "windows-1251", "Shift_JIS"), or null
+    /// while it is still being sniffed.
+    pub fn encoding(&self) -> Option<String> {
+        self.encoding.map(|e| e.name().to_string())
+    }
+
+    /// Where the encoding came from: "bom", "label", "header", "meta" or
+    /// "default" (UTF-8, nothing named one); null while still sniffing.
+    pub fn encoding_source(&self) -> Option<String> {
+        self.encoding.map(|_| self.source.as_str().to_string())
+    }
+
+    /// Charset the Content-Type named that is not a label of the Encoding
+    /// Standard, and which was therefore ignored.
+    pub fn unsupported_charset(&self) -> Option<String> {
+        self.unsupported.clone()
+    }
+}
+
+impl CharsetDecoder {
+    /// `new` for a response's headers.
+    pub fn for_headers(headers: &Headers, sniff_meta: bool) -> CharsetDecoder {
+        CharsetDecoder::new(headers.get("content-type").unwrap_or_default(), sniff_meta)
+    }
+
+    /// Rust-side `decode`, appending to `out`.
+    pub fn decode_into(&mut self, chunk: &[u8], out: &mut String) {
+        if let Some(decoder) = &mut self.decoder {
+            return decode(decoder, chunk, out, false);
+        }
+        self.pending.extend_from_slice(chunk);
+        self.decode_pending(out, false);
+    }
+
+    /// Rust-side `finish`, appending to `out`. Bytes decoded after it start
+    /// afresh in the same encoding.
+    pub fn finish_into(&mut self, out: &mut String) {
+        match self.decoder.take() {
+            Some(mut decoder) => decode(&mut decoder, &[], out, true),
+            None => self.decode_pending(out, true),
+        }
+    }
+
+    /// Decode what was held back, once the encoding can be chosen.
+    fn decode_pending(&mut self, out: &mut String, last: bool) {
+        let Some(encoding) = self.resolve(last) else {
+            return;
+        };
+        let mut decoder = encoding.new_decoder_without_bom_handling();
+        decode(&mut decoder, &mem::take(&mut self.pending), out, last);
+        if !last {
+            self.decoder = Some(decoder);
+        }
+    }
+
+    /// The encoding to decode `pending` with, once enough of the body is in to
+    /// choose it. A BOM found here is removed from `pending`.
+    fn resolve(&mut self, at_end: bool) -> Option<&'static Encoding> {
+        if !self.bom_checked {
+            let prefix = &self.pending[..];
+            if !at_end
+                && BOMS
+                    .iter()
+                    .any(|bom| bom.len() > prefix.len() && bom.starts_with(prefix))
+            {
+                return None;
+            }
+            self.bom_checked = true;
+            if let Some((encoding, length)) = Encoding::for_bom(prefix) {
+                self.pending.drain(..length);
+                self.encoding = Some(encoding);
+                self.source = Source::Bom;
+            }
+        }
+        if self.encoding.is_none() {
+            let prescanned = if self.sniff_meta {
+                if !at_end && self.pending.len() < META_PRESCAN_LENGTH {
+                    return None;
+                }
+                let end = self.pending.len().min(META_PRESCAN_LENGTH);
+                prescan(&self.pending[..end])
+            } else {
+                None
+            };
+            (self.encoding, self.source) = match prescanned {
+                Some(encoding) => (Some(encoding), Source::Meta),
+                None => (Some(UTF_8), Source::Default),
+            };
+        }
+        self.encoding
+    }
+}
+
+/// The charset parameter of a Content-Type value, unquoted.
+fn content_type_charset(value: &str) -> Option<&str> {
+    value.split(';').skip(1).find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("charset")
+            .then(|| value.trim().trim_matches('"'))
+            .filter(|v| !v.is_empty())
+    })
+}
+
+/// Decode `input` with `decoder` onto `out`; invalid sequences become U+FFFD.
+fn decode(decoder: &mut Decoder, mut input: &[u8], out: &mut String, last: bool) {
+    loop {
+        let room = decoder.max_utf8_buffer_length(input.len());
+        out.reserve(room.unwrap_or(input.len()));
+        let (result, read, _) = decoder.decode_to_string(input, out, last);
+        input = &input[read..];
+        if result == CoderResult::InputEmpty {
+            return;
+        }
+    }
+}
+
+/// The HTML standard's prescan (§13.2.3.2), reduced to `<meta>` tags and comments.
+fn prescan(bytes: &[u8]) -> Option<&'static Encoding> {
+    let mut i = 0;
+    while i < bytes.len() {
+        let rest = &bytes[i..];
+        if rest.starts_with(b"<!--") {
+            // "<!-->" is a complete comment: the search starts at its second dash
+            i += 2 + find(&rest[2..], b"-->").map_or(rest.len(), |end| end + 3);
+        } else if rest.len() > 5
+            && rest[..5].eq_ignore_ascii_case(b"<meta")
+            && matches!(rest[5], b'\t' | b'\n' | b'\x0c' | b'\r' | b' ' | b'/')
+        {
+            i += 5;
+            if let Some(encoding) = meta_charset(bytes, &mut i) {
+                return Some(encoding);
+            }
+        } else {
+            i += 1;
+        }
+    }
+    None
+}
+
+/// Charset declared by the attributes of a `<meta>` tag starting at `*i`.
+fn meta_charset(bytes: &[u8], i: &mut usize) -> Option<&'static Encoding> {
+    let (mut charset, mut content, mut pragma) = (None, None, false);
+    while let Some((name, value)) = attribute(bytes, i) {
+        match name.as_str() {
+            "http-equiv" => pragma |= value.eq_ignore_ascii_case("content-type"),
+            "charset" if charset.is_none() => charset = Some(value),
+            "content" if content.is_none() => content = Some(value),
+            _ => {}
+        }
+    }
+    let label = match (charset, content) {
+        (Some(charset), _) => charset,
+        (None, Some(content)) if pragma => content_charset(&content)?.to_string(),
+        _ => return None,
+    };
+    // A document cannot declare UTF-16 in itself: it would not be ASCII to read
+    match Encoding::for_label(label.as_bytes())? {
+        encoding if encoding == UTF_16LE || encoding == UTF_16BE => Some(UTF_8),
+        encoding if encoding == encoding_rs::X_USER_DEFINED => Some(WINDOWS_1252),
+        encoding => Some(encoding),
+    }
+}
+
+/// One attribute of a tag, with a lowercased name; None at the end of the tag.
+fn attribute(bytes: &[u8], i: &mut usize) -> Option<(String, String)> {
+    let space = |b: u8| matches!(b, b'\t' | b'\n' | b'\x0c' | b'\r' | b' ');
+    let skip = |i: &mut usize, f: &dyn Fn(u8) -> bool| {
+        while bytes.get(*i).is_some_and(|&b| f(b)) {
+            *i += 1;
+        }
+    };
+    skip(i, &|b| space(b) || b == b'/');
+    if bytes.get(*i).is_none_or(|&b| b == b'>') {
+        return None;
+    }
+    let start = *i;
+    *i += 1;
+    skip(i, &|b| !space(b) && !matches!(b, b'=' | b'/' | b'>'));
+    let name = String::from_utf8_lossy(&bytes[start..*i]).to_ascii_lowercase();
+    skip(i, &space);
+    if bytes.get(*i) != Some(&b'=') {
+        return Some((name, String::new()));
+    }
+    *i += 1;
+    skip(i, &space);
+    let value = match bytes.get(*i) {
+        Some(&quote @ (b'"' | b'\'')) => {
+            *i += 1;
+            let start = *i;
+            skip(i, &|b| b != quote);
+            let value = &bytes[start..*i];
+            *i += 1;
+            value
+        }
+        _ => {
+            let start = *i;
+            skip(i, &|b| !space(b) && b != b'>');
+            &bytes[start..*i]
+        }
+    };
+    Some((name, String::from_utf8_lossy(value).into_owned()))
+}
+
+/// The charset in a `<meta http-equiv content>` value, e.g. "text/html; charset=koi8-r".
+fn content_charset(content: &str) -> Option<&str> {
+    let lower = content.to_ascii_lowercase();
+    let at = lower.find("charset")?;
+    let rest = content[at + "charset".len()..].trim_start();
+    let rest = rest.strip_prefix('=')?.trim_start();
+    let value = match rest.strip_prefix(['"', '\'']) {
+        Some(quoted) => quoted.split(['"', '\'']).next()?,
+        None => rest.split([';', ' ', '\t', '\n', '\r', '\x0c']).next()?,
+    };
+    (!value.is_empty()).then_some(value)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decode `body` fed one byte at a time, so every sequence is cut.
+    fn bytewise(decoder: &mut CharsetDecoder, body: &[u8]) -> String {
+        let mut out = String::new();
+        for b in body {
+            decoder.decode_into(std::slice::from_ref(b), &mut out);
+        }
+        decoder.finish_into(&mut out);
+        out
+    }
+
+    #[test]
+    fn header_charsets_across_chunk_boundaries() {
+        let mut decoder = CharsetDecoder::new("text/html; Charset=\"windows-1251\"", false);
+        assert_eq!(decoder.encoding().as_deref(), Some("windows-1251"));
+        assert_eq!(decoder.decode(b"\xcf\xf0\xe8\xe2\xe5\xf2!"), "Привет!");
+
+        let mut decoder = CharsetDecoder::new("text/plain", false);
+        let body = "añ€😀".as_bytes();
+        assert_eq!(bytewise(&mut decoder, body), "añ€😀");
+        assert_eq!(decoder.encoding_source().as_deref(), Some("default"));
+        // Invalid sequences are replaced; a cut-off one only at the end
+        let mut decoder = CharsetDecoder::new("", false);
+        assert_eq!(decoder.decode(b"a\xff\xe2\x82"), "a\u{fffd}");
+        assert_eq!(decoder.finish(), "\u{fffd}");
+
+        let mut decoder = CharsetDecoder::for_label(" latin1 ").unwrap();
+        assert_eq!(decoder.decode(b"\xe9\x80\x81"), "é€\u{81}");
+        assert_eq!(
+            CharsetDecoder::for_label("x-unknown").unwrap_err(),
+            Error::UnsupportedCharset("x-unknown".to_string())
+        );
+        let decoder = CharsetDecoder::new("text/html; charset=x-unknown", false);
+        assert_eq!(decoder.unsupported_charset().as_deref(), Some("x-unknown"));
+    }
+
+    #[test]
+    fn byte_order_marks_win() {
+        let mut decoder = CharsetDecoder::new("text/plain; charset=koi8-r", false);
+        let mut body = b"\xff\xfe".to_vec();
+        body.extend_from_slice(b"a\x00\xf1\x00\x3d\xd8\x00\xde\x3d\xd8");
+        assert_eq!(bytewise(&mut decoder, &body), "añ😀\u{fffd}");
+        assert_eq!(
+            (
+                decoder.encoding().as_deref(),
+                decoder.encoding_source().as_deref()
+            ),
+            (Some("UTF-16LE"), Some("bom"))
+        );
+        let mut decoder = CharsetDecoder::new("text/plain; charset=koi8-r", false);
+        assert_eq!(decoder.decode(b"\xef\xbb"), "");
+        assert_eq!(decoder.decode(b"\xbf\xd0\x96"), "Ж");
+        let mut decoder = CharsetDecoder::new("text/plain; charset=koi8-r", false);
+        assert_eq!(decoder.decode(b"\xf6"), "Ж");
+    }
+
+    #[test]
+    fn meta_prescan() {
+        let page = b"<!DOCTYPE html><!-- <meta charset=koi8-r> --><html><head>\
+            <meta name=viewport content=\"width=device-width\">\
+            <META http-equiv='Content-Type' content='text/html; charset=\"windows-1251\"'>\
+            </head><body>\xcf\xf0\xe8\xe2\xe5\xf2</body></html>";
+        let mut decoder = CharsetDecoder::new("text/html", true);
+        // Nothing comes out until the prescan has the whole prefix (or the end)
+        assert_eq!(decoder.decode(page), "");
+        assert_eq!(decoder.encoding(), None);
+        assert!(decoder.finish().ends_with("<body>Привет</body></html>"));
+        assert_eq!(decoder.encoding_source().as_deref(), Some("meta"));
+
+        // A charset attribute needs no http-equiv, and UTF-16 means UTF-8
+        assert_eq!(prescan(b"<meta/charset='utf-16le'>"), Some(UTF_8));
+        assert_eq!(prescan(b"<meta content='text/html; charset=koi8-r'>"), None);
+        let mut long = vec![b' '; META_PRESCAN_LENGTH];
+        long.extend_from_slice(b"<meta charset=koi8-r>\xf6");
+        let mut decoder = CharsetDecoder::new("text/html", true);
+        assert!(decoder
+            .decode(&long)
+            .ends_with("<meta charset=koi8-r>\u{fffd}"));
+        assert_eq!(decoder.encoding_source().as_deref(), Some("default"));
+    }
+
+    #[test]
+    fn shift_jis() {
+        let mut decoder = CharsetDecoder::new("text/html; charset=Shift_JIS", false);
+        assert_eq!(
+            bytewise(&mut decoder, b"\x93\xfa\x96\x7b\x8c\xea\xb1"),
+            "日本語ｱ"
+        );
+        // A bad pair with an ASCII trail keeps the trail; a cut-off lead is replaced
+        assert_eq!(decoder.decode(b"\x85\x41\xf0\x40"), "\u{fffd}A\u{e000}");
+        assert_eq!(decoder.decode(b"\x93"), "");
+        assert_eq!(decoder.finish(), "\u{fffd}");
+    }
+
+    #[test]
+    fn multibyte_encodings() {
+        let cases: [(&str, &[u8], &str); 5] = [
+            ("gbk", b"\xd6\xd0\xce\xc4", "中文"),
+            ("big5", b"\xa4\xa4\xa4\xe5", "中文"),
+            ("euc-kr", b"\xc7\xd1\xb1\xb9", "한국"),
+            ("euc-jp", b"\xc6\xfc\xcb\xdc", "日本"),
+            ("iso-2022-jp", b"\x1b$BF|K\\\x1b(B", "日本"),
+        ];
+        for (label, body, text) in cases {
+            let content_type = format!("text/html; charset={}", label);
+            let mut decoder = CharsetDecoder::new(&content_type, false);
+            assert_eq!(bytewise(&mut decoder, body), text, "{}", label);
+            assert_eq!(decoder.unsupported_charset(), None);
+        }
+        let mut decoder = CharsetDecoder::new("text/html", true);
+        decoder.decode(b"<meta charset=gb2312>\xd6\xd0");
+        assert_eq!(decoder.finish(), "<meta charset=gb2312>中");
+        assert_eq!(decoder.encoding().as_deref(), Some("GBK"));
+        // TextDecoder refuses the replacement encoding's labels
+        assert!(CharsetDecoder::for_label("iso-2022-kr").is_err());
+    }
+}
@@ -0,0 +1,193 @@
+use std::fmt;
+
+use brotli_decompressor::{BrotliDecompressStream, BrotliResult, BrotliState, StandardAlloc};
+
+use crate::error::Error;
+
+const CODING: &str = "br";
+
+/// Decoder output is staged through a buffer of this size.
+const OUT_CHUNK: usize = 16 * 1024;
+
+/// Streaming brotli (RFC 7932) decoder.
+pub struct BrotliDecoder {
+    state: Box<BrotliState<StandardAlloc, StandardAlloc, StandardAlloc>>,
+    done: bool,
+    total_in: u64,
+    total_out: u64,
+    max_output: u64,
+}
+
+impl BrotliDecoder {
+    /// `max_output` bounds the total decoded size.
+    pub fn new(max_output: u64) -> Self {
+        BrotliDecoder {
+            state: Box::new(BrotliState::new(
+                StandardAlloc::default(),
+                StandardAlloc::default(),
+                StandardAlloc::default(),
+            )),
+            done: false,
+            total_in: 0,
+            total_out: 0,
+            max_output,
+        }
+    }
+
+    /// Encoded bytes consumed so far.
+    pub fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    /// Decoded bytes produced so far.
+    pub fn total_out(&self) -> u64 {
+        self.total_out
+    }
+
+    /// Decode `input`, appending the decompressed bytes to `out`.
+    pub fn decode(&mut self, input: &[u8], out: &mut Vec<u8>) -> Result<(), Error> {
+        if input.is_empty() {
+            return Ok(());
+        }
+        if self.done {
+            // Nothing may follow the final meta-block
+            return Err(invalid());
+        }
+        self.total_in += input.len() as u64;
+        let mut chunk = [0u8; OUT_CHUNK];
+        let mut available_in = input.len();
+        let mut input_offset = 0;
+        let mut written = 0;
+        loop {
+            let mut available_out = chunk.len();
+            let mut output_offset = 0;
+            let result = BrotliDecompressStream(
+                &mut available_in,
+                &mut input_offset,
+                input,
+                &mut available_out,
+                &mut output_offset,
+                &mut chunk,
+                &mut written,
+                &mut self.state,
+            );
+            self.emit(&chunk[..output_offset], out)?;
+            match result {
+                BrotliResult::NeedsMoreOutput => {}
+                BrotliResult::NeedsMoreInput => return Ok(()),
+                BrotliResult::ResultSuccess => {
+                    self.done = true;
+                    if available_in > 0 {
+                        return Err(invalid());
+                    }
+                    return Ok(());
+                }
+                BrotliResult::ResultFailure => return Err(invalid()),
+            }
+        }
+    }
+
+    /// Signal the end of the body. Errors if the stream did not reach its final meta-block.
+    pub fn finish(&mut self) -> Result<(), Error> {
+        // An empty body has nothing to decode (e.g. Content-Length: 0)
+        if self.done || self.total_in == 0 {
+            Ok(())
+        } else {
+            Err(Error::TruncatedEncoding { coding: CODING })
+        }
+    }
+
+    fn emit(&mut self, data: &[u8], out: &mut Vec<u8>) -> Result<(), Error> {
+        self.total_out += data.len() as u64;
+        if self.total_out > self.max_output {
+            return Err(Error::DecodedTooLarge {
+                limit: self.max_output,
+            });
+        }
+        out.extend_from_slice(data);
+        Ok(())
+    }
+}
+
+impl fmt::Debug for BrotliDecoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BrotliDecoder")
+            .field("done", &self.done)
+            .field("total_in", &self.total_in)
+            .field("total_out", &self.total_out)
+            .finish()
+    }
+}
+
+fn invalid() -> Error {
+    Error::InvalidEncoding { coding: CODING }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn brotli(data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let params = brotli::enc::BrotliEncoderParams::default();
+        brotli::BrotliCompress(&mut &data[..], &mut out, &params).unwrap();
+        out
+    }
+
+    fn decode_split(input: &[u8], step: usize, limit: u64) -> Result<Vec<u8>, Error> {
+        let mut dec = BrotliDecoder::new(limit);
+        let mut out = Vec::new();
+        for piece in input.chunks(step) {
+            dec.decode(piece, &mut out)?;
+        }
+        dec.finish()?;
+        assert_eq!(dec.total_in(), input.len() as u64);
+        assert_eq!(dec.total_out(), out.len() as u64);
+        Ok(out)
+    }
+
+    #[test]
+    fn incremental_feeding() {
+        let text = b"brotli brotli brotli, the quick brown fox ".repeat(40);
+        let encoded = brotli(&text);
+        for step in [1, 2, 7, encoded.len()] {
+            assert_eq!(decode_split(&encoded, step, u64::MAX).unwrap(), text);
+        }
+    }
+
+    #[test]
+    fn truncation_is_an_error() {
+        let encoded = brotli(&b"truncated stream ".repeat(30));
+        for cut in [1, encoded.len() / 2, encoded.len() - 1] {
+            assert_eq!(
+                decode_split(&encoded[..cut], 3, u64::MAX),
+                Err(Error::TruncatedEncoding { coding: "br" }),
+                "cut at {}",
+                cut
+            );
+        }
+        assert_eq!(decode_split(b"", 1, u64::MAX), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn bomb_limit() {
+        let zeros = vec![0u8; 1 << 20];
+        let encoded = brotli(&zeros);
+        assert!(encoded.len() < 1024);
+        assert_eq!(
+            decode_split(&encoded, encoded.len(), 4096),
+            Err(Error::DecodedTooLarge { limit: 4096 })
+        );
+    }
+
+    #[test]
+    fn corrupt_and_trailing_data() {
+        let trailing = [brotli(b"ok"), b"x".to_vec()].concat();
+        for input in [&b"\xff\xff\xff\xff"[..], &trailing] {
+            assert_eq!(
+                decode_split(input, input.len(), u64::MAX),
+                Err(Error::InvalidEncoding { coding: "br" })
+            );
+        }
+    }
+}
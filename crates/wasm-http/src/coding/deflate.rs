@@ -0,0 +1,194 @@
+use std::fmt;
+
+use miniz_oxide::inflate::stream::{inflate, InflateState};
+use miniz_oxide::{DataFormat, MZError, MZFlush, MZStatus};
+
+use crate::error::Error;
+
+const CODING: &str = "deflate";
+
+/// Inflate output is staged through a buffer of this size.
+const OUT_CHUNK: usize = 16 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    /// Waiting for the first two bytes to tell zlib from raw deflate
+    Detect,
+    Stream,
+    Done,
+}
+
+/// Streaming `deflate` content-coding decoder. RFC 9110 means zlib (RFC 1950), but
+/// some servers send raw deflate (RFC 1951); like browsers, both are accepted.
+pub struct DeflateDecoder {
+    state: State,
+    /// First bytes held back until the format is known
+    pending: Vec<u8>,
+    inflate: Box<InflateState>,
+    total_in: u64,
+    total_out: u64,
+    max_output: u64,
+}
+
+impl DeflateDecoder {
+    /// `max_output` bounds the total decoded size.
+    pub fn new(max_output: u64) -> Self {
+        DeflateDecoder {
+            state: State::Detect,
+            pending: Vec::new(),
+            inflate: InflateState::new_boxed(DataFormat::Zlib),
+            total_in: 0,
+            total_out: 0,
+            max_output,
+        }
+    }
+
+    /// Encoded bytes consumed so far.
+    pub fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    /// Decoded bytes produced so far.
+    pub fn total_out(&self) -> u64 {
+        self.total_out
+    }
+
+    /// Decode `input`, appending the decompressed bytes to `out`.
+    pub fn decode(&mut self, input: &[u8], out: &mut Vec<u8>) -> Result<(), Error> {
+        if input.is_empty() {
+            return Ok(());
+        }
+        self.total_in += input.len() as u64;
+        match self.state {
+            State::Detect => {
+                self.pending.extend_from_slice(input);
+                let &[cmf, flg, ..] = self.pending.as_slice() else {
+                    return Ok(());
+                };
+                let zlib = cmf & 0x0f == 8 && (u16::from(cmf) << 8 | u16::from(flg)) % 31 == 0;
+                if !zlib {
+                    self.inflate.reset(DataFormat::Raw);
+                }
+                self.state = State::Stream;
+                let pending = std::mem::take(&mut self.pending);
+                self.inflate_all(&pending, out)
+            }
+            State::Stream => self.inflate_all(input, out),
+            // Nothing may follow the end of the stream
+            State::Done => Err(invalid()),
+        }
+    }
+
+    /// Signal the end of the body. Errors if the stream did not reach its end.
+    pub fn finish(&mut self) -> Result<(), Error> {
+        // An empty body has nothing to decode (e.g. Content-Length: 0)
+        if self.state == State::Done || self.total_in == 0 {
+            Ok(())
+        } else {
+            Err(Error::TruncatedEncoding { coding: CODING })
+        }
+    }
+
+    fn inflate_all(&mut self, input: &[u8], out: &mut Vec<u8>) -> Result<(), Error> {
+        let mut chunk = [0u8; OUT_CHUNK];
+        let mut consumed = 0;
+        loop {
+            let res = inflate(
+                &mut self.inflate,
+                &input[consumed..],
+                &mut chunk,
+                MZFlush::None,
+            );
+            consumed += res.bytes_consumed;
+            self.emit(&chunk[..res.bytes_written], out)?;
+            match res.status {
+                Ok(MZStatus::StreamEnd) => {
+                    self.state = State::Done;
+                    if consumed < input.len() {
+                        return Err(invalid());
+                    }
+                    return Ok(());
+                }
+                Ok(_) if consumed == input.len() && res.bytes_written < chunk.len() => {
+                    return Ok(());
+                }
+                Ok(_) => {}
+                // No progress possible without more input
+                Err(MZError::Buf) => return Ok(()),
+                Err(_) => return Err(invalid()),
+            }
+        }
+    }
+
+    fn emit(&mut self, data: &[u8], out: &mut Vec<u8>) -> Result<(), Error> {
+        self.total_out += data.len() as u64;
+        if self.total_out > self.max_output {
+            return Err(Error::DecodedTooLarge {
+                limit: self.max_output,
+            });
+        }
+        out.extend_from_slice(data);
+        Ok(())
+    }
+}
+
+impl fmt::Debug for DeflateDecoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeflateDecoder")
+            .field("state", &self.state)
+            .field("total_in", &self.total_in)
+            .field("total_out", &self.total_out)
+            .finish()
+    }
+}
+
+fn invalid() -> Error {
+    Error::InvalidEncoding { coding: CODING }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use miniz_oxide::deflate::{compress_to_vec, compress_to_vec_zlib};
+
+    fn decode_split(input: &[u8], step: usize) -> Result<Vec<u8>, Error> {
+        let mut dec = DeflateDecoder::new(u64::MAX);
+        let mut out = Vec::new();
+        for piece in input.chunks(step) {
+            dec.decode(piece, &mut out)?;
+        }
+        dec.finish()?;
+        assert_eq!(dec.total_in(), input.len() as u64);
+        Ok(out)
+    }
+
+    #[test]
+    fn zlib_and_raw_deflate() {
+        let text = b"deflate deflate deflate ".repeat(30);
+        for encoded in [compress_to_vec_zlib(&text, 6), compress_to_vec(&text, 6)] {
+            for step in [1, 2, 10, encoded.len()] {
+                assert_eq!(decode_split(&encoded, step).unwrap(), text);
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_corrupt_and_trailing() {
+        let mut encoded = compress_to_vec_zlib(&b"checked by adler32 ".repeat(10), 6);
+        assert_eq!(
+            decode_split(&encoded[..encoded.len() - 1], 4),
+            Err(Error::TruncatedEncoding { coding: "deflate" })
+        );
+        let trailing = [&encoded[..], b"x"].concat();
+        assert_eq!(
+            decode_split(&trailing, trailing.len()),
+            Err(Error::InvalidEncoding { coding: "deflate" })
+        );
+        let last = encoded.len() - 1;
+        encoded[last] ^= 0xff;
+        assert_eq!(
+            decode_split(&encoded, 3),
+            Err(Error::InvalidEncoding { coding: "deflate" })
+        );
+    }
+}
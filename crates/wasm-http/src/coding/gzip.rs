@@ -0,0 +1,488 @@
+use std::fmt;
+
+use miniz_oxide::deflate::core::{create_comp_flags_from_zip_params, CompressorOxide};
+use miniz_oxide::deflate::stream::deflate;
+use miniz_oxide::inflate::stream::{inflate, InflateState};
+use miniz_oxide::{DataFormat, MZError, MZFlush, MZStatus};
+
+use crate::error::Error;
+
+const CODING: &str = "gzip";
+
+const FHCRC: u8 = 0x02;
+const FEXTRA: u8 = 0x04;
+const FNAME: u8 = 0x08;
+const FCOMMENT: u8 = 0x10;
+const FRESERVED: u8 = 0xe0;
+
+/// Longest member header we buffer (FEXTRA plus file name and comment).
+const MAX_HEADER: usize = 64 * 1024;
+const TRAILER_LEN: usize = 8;
+
+/// Inflate output is staged through a buffer of this size.
+const OUT_CHUNK: usize = 16 * 1024;
+
+/// Member header written by `GzipEncoder`: no flags, no modification time, OS unknown.
+const ENCODER_HEADER: [u8; 10] = [0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+const ENCODER_LEVEL: i32 = 6;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    /// Collecting a member header into `pending` (empty `pending` = between members)
+    Header,
+    /// Inside the raw deflate stream of a member
+    Deflate,
+    /// Collecting the CRC32 / ISIZE trailer into `pending`
+    Trailer,
+}
+
+/// Streaming gzip (RFC 1952) decoder. Multi-member streams decode to the
+/// concatenation of their members; each member's CRC32 and length are checked.
+pub struct GzipDecoder {
+    state: State,
+    pending: Vec<u8>,
+    inflate: Box<InflateState>,
+    /// CRC32 and length (mod 2^32) of the current member's output
+    crc: u32,
+    member_len: u32,
+    total_in: u64,
+    total_out: u64,
+    max_output: u64,
+}
+
+impl GzipDecoder {
+    /// `max_output` bounds the total decoded size.
+    pub fn new(max_output: u64) -> Self {
+        GzipDecoder {
+            state: State::Header,
+            pending: Vec::new(),
+            inflate: InflateState::new_boxed(DataFormat::Raw),
+            crc: 0,
+            member_len: 0,
+            total_in: 0,
+            total_out: 0,
+            max_output,
+        }
+    }
+
+    /// Encoded bytes consumed so far.
+    pub fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    /// Decoded bytes produced so far.
+    pub fn total_out(&self) -> u64 {
+        self.total_out
+    }
+
+    /// Decode `input`, appending the decompressed bytes to `out`.
+    pub fn decode(&mut self, mut input: &[u8], out: &mut Vec<u8>) -> Result<(), Error> {
+        self.total_in += input.len() as u64;
+        while !input.is_empty() {
+            match self.state {
+                State::Header => {
+                    let before = self.pending.len();
+                    self.pending.extend_from_slice(input);
+                    match header_len(&self.pending)? {
+                        Some(len) => {
+                            input = &input[len - before..];
+                            self.pending.clear();
+                            self.state = State::Deflate;
+                        }
+                        None if self.pending.len() > MAX_HEADER => return Err(invalid()),
+                        None => input = &[],
+                    }
+                }
+                State::Deflate => {
+                    let consumed = self.inflate_some(input, out)?;
+                    input = &input[consumed..];
+                }
+                State::Trailer => {
+                    let n = (TRAILER_LEN - self.pending.len()).min(input.len());
+                    self.pending.extend_from_slice(&input[..n]);
+                    input = &input[n..];
+                    if self.pending.len() == TRAILER_LEN {
+                        self.check_trailer()?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Signal the end of the body. Errors if a member is incomplete.
+    pub fn finish(&mut self) -> Result<(), Error> {
+        if self.state == State::Header && self.pending.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::TruncatedEncoding { coding: CODING })
+        }
+    }
+
+    /// Run the inflater over `input`; returns how many bytes it consumed.
+    fn inflate_some(&mut self, input: &[u8], out: &mut Vec<u8>) -> Result<usize, Error> {
+        let mut chunk = [0u8; OUT_CHUNK];
+        let mut consumed = 0;
+        loop {
+            let res = inflate(
+                &mut self.inflate,
+                &input[consumed..],
+                &mut chunk,
+                MZFlush::None,
+            );
+            consumed += res.bytes_consumed;
+            self.emit(&chunk[..res.bytes_written], out)?;
+            match res.status {
+                Ok(MZStatus::StreamEnd) => {
+                    self.state = State::Trailer;
+                    return Ok(consumed);
+                }
+                Ok(_) if consumed == input.len() && res.bytes_written < chunk.len() => {
+                    return Ok(consumed);
+                }
+                Ok(_) => {}
+                // No progress possible without more input
+                Err(MZError::Buf) => return Ok(consumed),
+                Err(_) => return Err(invalid()),
+            }
+        }
+    }
+
+    fn emit(&mut self, data: &[u8], out: &mut Vec<u8>) -> Result<(), Error> {
+        self.total_out += data.len() as u64;
+        if self.total_out > self.max_output {
+            return Err(Error::DecodedTooLarge {
+                limit: self.max_output,
+            });
+        }
+        self.crc = crc32(self.crc, data);
+        self.member_len = self.member_len.wrapping_add(data.len() as u32);
+        out.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn check_trailer(&mut self) -> Result<(), Error> {
+        let t = &self.pending;
+        let crc = u32::from_le_bytes([t[0], t[1], t[2], t[3]]);
+        let len = u32::from_le_bytes([t[4], t[5], t[6], t[7]]);
+        if crc != self.crc || len != self.member_len {
+            return Err(invalid());
+        }
+        self.pending.clear();
+        self.inflate.reset(DataFormat::Raw);
+        self.crc = 0;
+        self.member_len = 0;
+        self.state = State::Header;
+        Ok(())
+    }
+}
+
+impl fmt::Debug for GzipDecoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GzipDecoder")
+            .field("state", &self.state)
+            .field("total_in", &self.total_in)
+            .field("total_out", &self.total_out)
+            .finish()
+    }
+}
+
+/// Streaming gzip encoder: one member whose header carries no name or time, so
+/// the same input always encodes to the same bytes.
+pub struct GzipEncoder {
+    compressor: Box<CompressorOxide>,
+    header_written: bool,
+    finished: bool,
+    crc: u32,
+    len: u32,
+    total_in: u64,
+    total_out: u64,
+}
+
+impl GzipEncoder {
+    pub fn new() -> Self {
+        GzipEncoder {
+            compressor: Box::new(CompressorOxide::new(create_comp_flags_from_zip_params(
+                ENCODER_LEVEL,
+                0,
+                0,
+            ))),
+            header_written: false,
+            finished: false,
+            crc: 0,
+            len: 0,
+            total_in: 0,
+            total_out: 0,
+        }
+    }
+
+    /// Uncompressed bytes taken so far.
+    pub fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    /// Encoded bytes produced so far.
+    pub fn total_out(&self) -> u64 {
+        self.total_out
+    }
+
+    /// Compress `input`; returns the encoded bytes ready so far, which may be
+    /// none while the compressor is still collecting a block.
+    pub fn encode(&mut self, input: &[u8]) -> Vec<u8> {
+        let mut out = self.start();
+        if self.finished || input.is_empty() {
+            return out;
+        }
+        self.crc = crc32(self.crc, input);
+        self.len = self.len.wrapping_add(input.len() as u32);
+        self.total_in += input.len() as u64;
+        self.run(input, MZFlush::None, &mut out);
+        self.total_out += out.len() as u64;
+        out
+    }
+
+    /// End the member: the rest of the deflate stream and the CRC32 / ISIZE
+    /// trailer. Later calls return nothing.
+    pub fn finish(&mut self) -> Vec<u8> {
+        let mut out = self.start();
+        if self.finished {
+            return out;
+        }
+        self.finished = true;
+        self.run(&[], MZFlush::Finish, &mut out);
+        out.extend_from_slice(&self.crc.to_le_bytes());
+        out.extend_from_slice(&self.len.to_le_bytes());
+        self.total_out += out.len() as u64;
+        out
+    }
+
+    /// The member header, the first time it is asked for.
+    fn start(&mut self) -> Vec<u8> {
+        if self.header_written {
+            return Vec::new();
+        }
+        self.header_written = true;
+        ENCODER_HEADER.to_vec()
+    }
+
+    fn run(&mut self, mut input: &[u8], flush: MZFlush, out: &mut Vec<u8>) {
+        let mut buf = vec![0u8; OUT_CHUNK];
+        loop {
+            let result = deflate(&mut self.compressor, input, &mut buf, flush);
+            input = &input[result.bytes_consumed..];
+            out.extend_from_slice(&buf[..result.bytes_written]);
+            match result.status {
+                Ok(MZStatus::StreamEnd) => return,
+                // A full buffer may hide more output
+                Ok(_) if result.bytes_written == buf.len() => {}
+                Ok(_) if flush == MZFlush::None && input.is_empty() => return,
+                Ok(_) => {}
+                // Nothing left to take or give: only when the input ran out
+                Err(_) => return,
+            }
+        }
+    }
+}
+
+impl Default for GzipEncoder {
+    fn default() -> Self {
+        GzipEncoder::new()
+    }
+}
+
+impl fmt::Debug for GzipEncoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GzipEncoder")
+            .field("finished", &self.finished)
+            .field("total_in", &self.total_in)
+            .field("total_out", &self.total_out)
+            .finish()
+    }
+}
+
+fn invalid() -> Error {
+    Error::InvalidEncoding { coding: CODING }
+}
+
+/// Length of the member header at the start of `buf`, or None if it is not complete yet.
+fn header_len(buf: &[u8]) -> Result<Option<usize>, Error> {
+    match buf {
+        [] => return Ok(None),
+        [0x1f] | [0x1f, 0x8b] | [0x1f, 0x8b, 8, ..] => {}
+        _ => return Err(invalid()),
+    }
+    if buf.len() < 10 {
+        return Ok(None);
+    }
+    let flags = buf[3];
+    if flags & FRESERVED != 0 {
+        return Err(invalid());
+    }
+    let mut pos = 10;
+    if flags & FEXTRA != 0 {
+        let Some(xlen) = buf.get(pos..pos + 2) else {
+            return Ok(None);
+        };
+        pos += 2 + u16::from_le_bytes([xlen[0], xlen[1]]) as usize;
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            let Some(nul) = buf.get(pos..).and_then(|b| b.iter().position(|&b| b == 0)) else {
+                return Ok(None);
+            };
+            pos += nul + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        pos += 2;
+    }
+    Ok((pos <= buf.len()).then_some(pos))
+}
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xedb8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+};
+
+/// CRC-32 (IEEE) as used by the gzip trailer.
+fn crc32(crc: u32, data: &[u8]) -> u32 {
+    !data.iter().fold(!crc, |c, &b| {
+        CRC_TABLE[((c ^ b as u32) & 0xff) as usize] ^ (c >> 8)
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use miniz_oxide::deflate::compress_to_vec;
+
+    /// One gzip member with an FNAME field.
+    pub(crate) fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut out = vec![0x1f, 0x8b, 8, FNAME, 0, 0, 0, 0, 0, 3];
+        out.extend_from_slice(b"body.txt\0");
+        out.extend(compress_to_vec(data, 6));
+        out.extend_from_slice(&crc32(0, data).to_le_bytes());
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        out
+    }
+
+    fn decode_split(input: &[u8], step: usize, limit: u64) -> Result<Vec<u8>, Error> {
+        let mut dec = GzipDecoder::new(limit);
+        let mut out = Vec::new();
+        for piece in input.chunks(step) {
+            dec.decode(piece, &mut out)?;
+        }
+        dec.finish()?;
+        assert_eq!(dec.total_in(), input.len() as u64);
+        assert_eq!(dec.total_out(), out.len() as u64);
+        Ok(out)
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(0, b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn single_member_at_any_split() {
+        let text = b"hello hello hello gzip world ".repeat(50);
+        let member = gzip(&text);
+        for step in [1, 2, 5, 13, member.len()] {
+            assert_eq!(decode_split(&member, step, u64::MAX).unwrap(), text);
+        }
+    }
+
+    #[test]
+    fn multi_member_concatenates() {
+        let input = [gzip(b"first,"), gzip(b""), gzip(b"second")].concat();
+        for step in [1, 3, input.len()] {
+            assert_eq!(
+                decode_split(&input, step, u64::MAX).unwrap(),
+                b"first,second"
+            );
+        }
+    }
+
+    #[test]
+    fn truncation_is_an_error() {
+        let member = gzip(b"some body that gets cut off");
+        for cut in [1, 5, 15, member.len() - 4, member.len() - 1] {
+            assert_eq!(
+                decode_split(&member[..cut], 4, u64::MAX),
+                Err(Error::TruncatedEncoding { coding: "gzip" }),
+                "cut at {}",
+                cut
+            );
+        }
+        assert_eq!(decode_split(b"", 1, u64::MAX), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn bomb_limit() {
+        let zeros = vec![0u8; 1 << 20];
+        let member = gzip(&zeros);
+        assert!(member.len() < 4096);
+        assert_eq!(
+            decode_split(&member, member.len(), 1000),
+            Err(Error::DecodedTooLarge { limit: 1000 })
+        );
+        assert_eq!(decode_split(&member, 512, 1 << 20).unwrap().len(), 1 << 20);
+    }
+
+    #[test]
+    fn corrupt_input() {
+        let mut member = gzip(b"checksummed");
+        let crc_at = member.len() - 8;
+        member[crc_at] ^= 1;
+        let garbage = b"not gzip at all";
+        let trailing = [gzip(b"ok"), b"junk".to_vec()].concat();
+        for input in [&member[..], garbage, &trailing] {
+            assert_eq!(
+                decode_split(input, 3, u64::MAX),
+                Err(Error::InvalidEncoding { coding: "gzip" })
+            );
+        }
+    }
+
+    #[test]
+    fn encoder_round_trips() {
+        let text: Vec<u8> = (0..200_000u32)
+            .flat_map(|i| (i % 251).to_le_bytes())
+            .collect();
+        for step in [1000, 70_000, text.len()] {
+            let mut enc = GzipEncoder::new();
+            let mut member = Vec::new();
+            for piece in text.chunks(step) {
+                member.extend(enc.encode(piece));
+            }
+            member.extend(enc.finish());
+            assert!(enc.finish().is_empty());
+            assert_eq!(
+                (enc.total_in(), enc.total_out()),
+                (text.len() as u64, member.len() as u64)
+            );
+            assert!(member.len() < text.len() / 4);
+            assert_eq!(decode_split(&member, 4096, u64::MAX).unwrap(), text);
+        }
+
+        let mut enc = GzipEncoder::new();
+        let empty = enc.finish();
+        assert_eq!(&empty[..10], &ENCODER_HEADER);
+        assert!(decode_split(&empty, 1, u64::MAX).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,228 @@
+//! Content-Encoding decoders (RFC 9110 §8.4), and the gzip encoder used for
+//! request bodies.
+//!
+//! Decoders are push-style like the rest of the crate: feed encoded bytes as they
+//! arrive, collect decoded bytes, and call `finish` once the body has ended so a
+//! truncated stream is reported instead of silently accepted.
+
+#[cfg(feature = "brotli")]
+pub(crate) mod brotli;
+mod deflate;
+pub(crate) mod gzip;
+#[cfg(feature = "zstd")]
+pub(crate) mod zstd;
+
+#[cfg(feature = "brotli")]
+pub use brotli::BrotliDecoder;
+pub use deflate::DeflateDecoder;
+pub use gzip::{GzipDecoder, GzipEncoder};
+#[cfg(feature = "zstd")]
+pub use zstd::{ZstdDecoder, DEFAULT_MAX_ZSTD_WINDOW};
+
+use crate::error::Error;
+use crate::headers::Headers;
+
+/// Default cap on the decoded size of one response body.
+pub const DEFAULT_MAX_DECODED_SIZE: u64 = 64 * 1024 * 1024;
+
+/// `Accept-Encoding` value listing every compiled-in coding, in Chrome's order.
+pub fn accept_encoding() -> String {
+    let mut codings = vec!["gzip", "deflate"];
+    if cfg!(feature = "brotli") {
+        codings.push("br");
+    }
+    if cfg!(feature = "zstd") {
+        codings.push("zstd");
+    }
+    codings.join(", ")
+}
+
+/// One of the compiled-in decoders.
+#[derive(Debug)]
+pub(crate) enum ContentDecoder {
+    Gzip(GzipDecoder),
+    Deflate(DeflateDecoder),
+    #[cfg(feature = "brotli")]
+    Brotli(BrotliDecoder),
+    #[cfg(feature = "zstd")]
+    Zstd(ZstdDecoder),
+}
+
+impl ContentDecoder {
+    /// Decoder for a single content-coding name, if it is compiled in.
+    fn for_coding(coding: &str, max_output: u64) -> Option<ContentDecoder> {
+        let is = |name: &str| coding.eq_ignore_ascii_case(name);
+        if is("gzip") || is("x-gzip") {
+            return Some(ContentDecoder::Gzip(GzipDecoder::new(max_output)));
+        }
+        if is("deflate") {
+            return Some(ContentDecoder::Deflate(DeflateDecoder::new(max_output)));
+        }
+        #[cfg(feature = "brotli")]
+        if is("br") {
+            return Some(ContentDecoder::Brotli(BrotliDecoder::new(max_output)));
+        }
+        #[cfg(feature = "zstd")]
+        if is("zstd") {
+            return Some(ContentDecoder::Zstd(ZstdDecoder::new(max_output)));
+        }
+        None
+    }
+
+    fn decode(&mut self, input: &[u8], out: &mut Vec<u8>) -> Result<(), Error> {
+        match self {
+            ContentDecoder::Gzip(d) => d.decode(input, out),
+            ContentDecoder::Deflate(d) => d.decode(input, out),
+            #[cfg(feature = "brotli")]
+            ContentDecoder::Brotli(d) => d.decode(input, out),
+            #[cfg(feature = "zstd")]
+            ContentDecoder::Zstd(d) => d.decode(input, out),
+        }
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        match self {
+            ContentDecoder::Gzip(d) => d.finish(),
+            ContentDecoder::Deflate(d) => d.finish(),
+            #[cfg(feature = "brotli")]
+            ContentDecoder::Brotli(d) => d.finish(),
+            #[cfg(feature = "zstd")]
+            ContentDecoder::Zstd(d) => d.finish(),
+        }
+    }
+}
+
+/// How a response body has to be decoded, from its Content-Encoding header.
+#[derive(Debug)]
+pub(crate) enum DecodePlan {
+    /// No content coding (or only `identity`)
+    Identity,
+    /// Decoders in the order to apply them (the reverse of the header order)
+    Chain(DecoderChain),
+    /// At least one coding is not compiled in; carries the coding list
+    Unsupported(String),
+}
+
+impl DecodePlan {
+    pub(crate) fn for_headers(headers: &Headers, max_output: u64) -> DecodePlan {
+        let codings: Vec<&str> = headers
+            .get_all("content-encoding")
+            .flat_map(|v| v.split(','))
+            .map(str::trim)
+            .filter(|c| !c.is_empty() && !c.eq_ignore_ascii_case("identity"))
+            .collect();
+        if codings.is_empty() {
+            return DecodePlan::Identity;
+        }
+        let stages = codings
+            .iter()
+            .rev()
+            .map(|c| ContentDecoder::for_coding(c, max_output))
+            .collect::<Option<Vec<_>>>();
+        match stages {
+            Some(stages) => DecodePlan::Chain(DecoderChain { stages }),
+            None => DecodePlan::Unsupported(codings.join(", ")),
+        }
+    }
+}
+
+/// Decoders for stacked content codings, each feeding the next.
+#[derive(Debug)]
+pub(crate) struct DecoderChain {
+    stages: Vec<ContentDecoder>,
+}
+
+impl DecoderChain {
+    pub(crate) fn decode(&mut self, input: &[u8], out: &mut Vec<u8>) -> Result<(), Error> {
+        let mut data = input.to_vec();
+        for stage in &mut self.stages {
+            let mut next = Vec::new();
+            stage.decode(&data, &mut next)?;
+            data = next;
+        }
+        out.extend(data);
+        Ok(())
+    }
+
+    pub(crate) fn finish(&mut self) -> Result<(), Error> {
+        self.stages.iter_mut().try_for_each(ContentDecoder::finish)
+    }
+}
+
+/// Content decoding of a body received outside `ResponseParser`, e.g. on an
+/// HTTP/2 stream. Like the parser with decompression on, a body using a coding
+/// that is not compiled in is passed through untouched.
+#[derive(Debug)]
+pub struct BodyDecoder {
+    chain: Option<DecoderChain>,
+    undecoded: Option<String>,
+}
+
+impl BodyDecoder {
+    /// Decoder for the Content-Encoding of the response head `headers`, limited
+    /// to `max_output` decoded bytes (see `DEFAULT_MAX_DECODED_SIZE`).
+    pub fn for_headers(headers: &Headers, max_output: u64) -> BodyDecoder {
+        let (chain, undecoded) = match DecodePlan::for_headers(headers, max_output) {
+            DecodePlan::Identity => (None, None),
+            DecodePlan::Chain(chain) => (Some(chain), None),
+            DecodePlan::Unsupported(codings) => (None, Some(codings)),
+        };
+        BodyDecoder { chain, undecoded }
+    }
+
+    /// Decoded bytes of the next piece of the body.
+    pub fn decode(&mut self, input: &[u8]) -> Result<Vec<u8>, Error> {
+        match &mut self.chain {
+            Some(chain) => {
+                let mut out = Vec::new();
+                chain.decode(input, &mut out)?;
+                Ok(out)
+            }
+            None => Ok(input.to_vec()),
+        }
+    }
+
+    /// Call once the body has ended: fails if a coded stream was cut short.
+    pub fn finish(&mut self) -> Result<(), Error> {
+        match &mut self.chain {
+            Some(chain) => chain.finish(),
+            None => Ok(()),
+        }
+    }
+
+    /// Content codings left on the body, or nothing if it is fully decoded.
+    pub fn undecoded_encoding(&self) -> Option<&str> {
+        self.undecoded.as_deref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(encoding: &str) -> Headers {
+        let mut headers = Headers::new();
+        headers.push("content-encoding", encoding);
+        headers
+    }
+
+    #[test]
+    fn body_decoder() {
+        let encoded = gzip::tests::gzip(b"decoded body");
+        let mut decoder = BodyDecoder::for_headers(&headers("gzip"), DEFAULT_MAX_DECODED_SIZE);
+        let mut out = decoder.decode(&encoded[..10]).unwrap();
+        out.extend(decoder.decode(&encoded[10..]).unwrap());
+        decoder.finish().unwrap();
+        assert_eq!(out, b"decoded body");
+        assert_eq!(decoder.undecoded_encoding(), None);
+
+        let mut truncated = BodyDecoder::for_headers(&headers("gzip"), DEFAULT_MAX_DECODED_SIZE);
+        truncated.decode(&encoded[..encoded.len() - 4]).unwrap();
+        assert!(truncated.finish().is_err());
+
+        let mut passthrough =
+            BodyDecoder::for_headers(&headers("compress"), DEFAULT_MAX_DECODED_SIZE);
+        assert_eq!(passthrough.decode(b"raw").unwrap(), b"raw");
+        assert_eq!(passthrough.undecoded_encoding(), Some("compress"));
+    }
+}
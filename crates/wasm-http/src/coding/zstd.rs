@@ -0,0 +1,327 @@
+use std::fmt;
+
+use ruzstd::decoding::errors::FrameDecoderError;
+use ruzstd::decoding::FrameDecoder;
+
+use crate::error::Error;
+
+const CODING: &str = "zstd";
+
+const FRAME_MAGIC: u32 = 0xfd2f_b528;
+/// Skippable frames use magics 0x184D2A50..=0x184D2A5F
+const SKIPPABLE_MAGIC: u32 = 0x184d_2a50;
+
+/// Largest window we accept. RFC 9659 caps zstd windows at 8 MiB for HTTP,
+/// which is also what browsers enforce.
+pub const DEFAULT_MAX_ZSTD_WINDOW: u64 = 8 * 1024 * 1024;
+
+/// Decoder output is staged through a buffer of this size.
+const OUT_CHUNK: usize = 16 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    /// Expecting the magic number of the next frame (or the end of the body)
+    Boundary,
+    /// Inside a skippable frame with this many bytes left
+    Skippable(u64),
+    /// Inside a zstd frame
+    Frame,
+}
+
+/// Streaming Zstandard (RFC 8878) decoder. Multiple frames decode to the concatenation
+/// of their contents; skippable frames are ignored. Dictionaries are not supported.
+pub struct ZstdDecoder {
+    state: State,
+    /// Input not yet consumed (ruzstd decodes whole blocks at a time)
+    pending: Vec<u8>,
+    frame: Box<FrameDecoder>,
+    total_in: u64,
+    total_out: u64,
+    max_output: u64,
+}
+
+impl ZstdDecoder {
+    /// `max_output` bounds the total decoded size.
+    pub fn new(max_output: u64) -> Self {
+        let mut frame = Box::new(FrameDecoder::new());
+        frame.set_max_window_size(DEFAULT_MAX_ZSTD_WINDOW);
+        ZstdDecoder {
+            state: State::Boundary,
+            pending: Vec::new(),
+            frame,
+            total_in: 0,
+            total_out: 0,
+            max_output,
+        }
+    }
+
+    /// Reject frames whose window (the decoder's history buffer) exceeds `limit` bytes.
+    pub fn set_max_window_size(&mut self, limit: u64) {
+        self.frame.set_max_window_size(limit);
+    }
+
+    /// Encoded bytes consumed so far.
+    pub fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    /// Decoded bytes produced so far.
+    pub fn total_out(&self) -> u64 {
+        self.total_out
+    }
+
+    /// Decode `input`, appending the decompressed bytes to `out`.
+    pub fn decode(&mut self, input: &[u8], out: &mut Vec<u8>) -> Result<(), Error> {
+        self.total_in += input.len() as u64;
+        self.pending.extend_from_slice(input);
+        while !self.pending.is_empty() {
+            let progressed = match self.state {
+                State::Boundary => self.start_frame()?,
+                State::Skippable(remaining) => {
+                    let n = remaining.min(self.pending.len() as u64);
+                    self.pending.drain(..n as usize);
+                    self.state = if n == remaining {
+                        State::Boundary
+                    } else {
+                        State::Skippable(remaining - n)
+                    };
+                    true
+                }
+                State::Frame => self.decode_frame(out)?,
+            };
+            if !progressed {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Signal the end of the body. Errors if it stopped inside a frame.
+    pub fn finish(&mut self) -> Result<(), Error> {
+        if self.state == State::Boundary && self.pending.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::TruncatedEncoding { coding: CODING })
+        }
+    }
+
+    /// Read the next frame's header from `pending`. Returns false if more input is needed.
+    fn start_frame(&mut self) -> Result<bool, Error> {
+        let Some(magic) = self.pending.get(..4) else {
+            return Ok(false);
+        };
+        let magic = u32::from_le_bytes([magic[0], magic[1], magic[2], magic[3]]);
+        if magic & !0xf == SKIPPABLE_MAGIC {
+            let Some(len) = self.pending.get(4..8) else {
+                return Ok(false);
+            };
+            let len = u32::from_le_bytes([len[0], len[1], len[2], len[3]]);
+            self.pending.drain(..8);
+            self.state = State::Skippable(len as u64);
+            return Ok(true);
+        }
+        if magic != FRAME_MAGIC {
+            return Err(invalid());
+        }
+        let Some(header_len) = frame_header_len(&self.pending)? else {
+            return Ok(false);
+        };
+        self.frame
+            .reset(&self.pending[..header_len])
+            .map_err(map_error)?;
+        self.pending.drain(..header_len);
+        self.state = State::Frame;
+        Ok(true)
+    }
+
+    /// Decode complete blocks from `pending`. Returns false if more input is needed.
+    fn decode_frame(&mut self, out: &mut Vec<u8>) -> Result<bool, Error> {
+        let mut chunk = [0u8; OUT_CHUNK];
+        let mut progressed = false;
+        loop {
+            let (read, written) = self
+                .frame
+                .decode_from_to(&self.pending, &mut chunk)
+                .map_err(map_error)?;
+            if read > self.pending.len() {
+                // ruzstd reports the 4 checksum bytes as read even when they are not there yet
+                return Ok(progressed);
+            }
+            self.pending.drain(..read);
+            self.emit(&chunk[..written], out)?;
+            if self.frame.is_finished() && self.frame.can_collect() == 0 {
+                self.check_checksum()?;
+                self.state = State::Boundary;
+                return Ok(true);
+            }
+            if read == 0 && written == 0 {
+                return Ok(progressed);
+            }
+            progressed = true;
+        }
+    }
+
+    fn check_checksum(&self) -> Result<(), Error> {
+        match (
+            self.frame.get_checksum_from_data(),
+            self.frame.get_calculated_checksum(),
+        ) {
+            (Some(expected), Some(actual)) if expected != actual => Err(invalid()),
+            _ => Ok(()),
+        }
+    }
+
+    fn emit(&mut self, data: &[u8], out: &mut Vec<u8>) -> Result<(), Error> {
+        self.total_out += data.len() as u64;
+        if self.total_out > self.max_output {
+            return Err(Error::DecodedTooLarge {
+                limit: self.max_output,
+            });
+        }
+        out.extend_from_slice(data);
+        Ok(())
+    }
+}
+
+impl fmt::Debug for ZstdDecoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ZstdDecoder")
+            .field("state", &self.state)
+            .field("total_in", &self.total_in)
+            .field("total_out", &self.total_out)
+            .finish()
+    }
+}
+
+fn invalid() -> Error {
+    Error::InvalidEncoding { coding: CODING }
+}
+
+fn map_error(e: FrameDecoderError) -> Error {
+    match e {
+        FrameDecoderError::WindowSizeTooBig { requested, max } => Error::WindowTooLarge {
+            coding: CODING,
+            requested,
+            limit: max,
+        },
+        _ => invalid(),
+    }
+}
+
+/// Length of the frame header (magic included) at the start of `buf`,
+/// or None if it is not complete yet.
+fn frame_header_len(buf: &[u8]) -> Result<Option<usize>, Error> {
+    let Some(&descriptor) = buf.get(4) else {
+        return Ok(None);
+    };
+    if descriptor & 0x08 != 0 {
+        // Reserved bit
+        return Err(invalid());
+    }
+    let single_segment = descriptor & 0x20 != 0;
+    let window_len = if single_segment { 0 } else { 1 };
+    let dict_len = [0, 1, 2, 4][(descriptor & 0x03) as usize];
+    let fcs_len = match descriptor >> 6 {
+        0 if single_segment => 1,
+        0 => 0,
+        1 => 2,
+        2 => 4,
+        _ => 8,
+    };
+    let len = 5 + window_len + dict_len + fcs_len;
+    Ok((len <= buf.len()).then_some(len))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use ruzstd::encoding::{compress_to_vec, CompressionLevel};
+
+    pub(crate) fn zstd(data: &[u8]) -> Vec<u8> {
+        compress_to_vec(data, CompressionLevel::Fastest)
+    }
+
+    fn decode_split(input: &[u8], step: usize, limit: u64) -> Result<Vec<u8>, Error> {
+        let mut dec = ZstdDecoder::new(limit);
+        let mut out = Vec::new();
+        for piece in input.chunks(step) {
+            dec.decode(piece, &mut out)?;
+        }
+        dec.finish()?;
+        assert_eq!(dec.total_in(), input.len() as u64);
+        assert_eq!(dec.total_out(), out.len() as u64);
+        Ok(out)
+    }
+
+    #[test]
+    fn incremental_feeding() {
+        let text = b"zstandard zstandard zstandard frames ".repeat(100);
+        let encoded = zstd(&text);
+        for step in [1, 2, 9, encoded.len()] {
+            assert_eq!(decode_split(&encoded, step, u64::MAX).unwrap(), text);
+        }
+    }
+
+    #[test]
+    fn multiple_and_skippable_frames() {
+        let skippable = [
+            &0x184d_2a53u32.to_le_bytes()[..],
+            &3u32.to_le_bytes(),
+            b"abc",
+        ]
+        .concat();
+        let input = [zstd(b"one,"), skippable, zstd(b"two")].concat();
+        for step in [1, 5, input.len()] {
+            assert_eq!(decode_split(&input, step, u64::MAX).unwrap(), b"one,two");
+        }
+    }
+
+    #[test]
+    fn truncation_is_an_error() {
+        let encoded = zstd(&b"a frame that gets cut ".repeat(20));
+        for cut in [2, 6, encoded.len() / 2, encoded.len() - 1] {
+            assert_eq!(
+                decode_split(&encoded[..cut], 3, u64::MAX),
+                Err(Error::TruncatedEncoding { coding: "zstd" }),
+                "cut at {}",
+                cut
+            );
+        }
+        assert_eq!(decode_split(b"", 1, u64::MAX), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn limits() {
+        let zeros = vec![0u8; 1 << 20];
+        let encoded = zstd(&zeros);
+        assert_eq!(
+            decode_split(&encoded, encoded.len(), 10_000),
+            Err(Error::DecodedTooLarge { limit: 10_000 })
+        );
+
+        // Frame header declaring a 2 GiB window (exponent 21 + 10)
+        let huge_window = [&FRAME_MAGIC.to_le_bytes()[..], &[0x00, 21 << 3]].concat();
+        assert_eq!(
+            decode_split(&huge_window, huge_window.len(), u64::MAX),
+            Err(Error::WindowTooLarge {
+                coding: "zstd",
+                requested: 1 << 31,
+                limit: DEFAULT_MAX_ZSTD_WINDOW
+            })
+        );
+    }
+
+    #[test]
+    fn unknown_frame_types() {
+        for input in [
+            &b"\x28\xb5\x2f\xfe rest"[..],
+            b"PK\x03\x04",
+            b"\x28\xb5\x2f\xfd\x08",
+        ] {
+            assert_eq!(
+                decode_split(input, input.len(), u64::MAX),
+                Err(Error::InvalidEncoding { coding: "zstd" })
+            );
+        }
+    }
+}
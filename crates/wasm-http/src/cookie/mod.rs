@@ -0,0 +1,687 @@
+//! Cookie storage with RFC 6265 semantics (§5.3 storage, §5.4 retrieval).
+//!
+//! The jar is sans-IO like the rest of the crate: the caller passes the request URL
+//! and the current time, hands over the response's Set-Cookie headers, and asks for
+//! the Cookie header of the next request, whether it goes out over HTTP/1.1 or HTTP/2.
+//! Domain attributes naming a public suffix ("co.uk", "github.io") are rejected when
+//! the jar has a `PublicSuffixList`, which it does by default with the `psl` feature.
+//! Without one, only a Domain without an inner dot (e.g. "com") is.
+
+mod parse;
+
+use std::net::Ipv4Addr;
+
+use wasm_bindgen::prelude::*;
+
+use crate::error::Error;
+use crate::headers::Headers;
+use crate::json::{self, Value};
+use crate::psl::PublicSuffixList;
+pub(crate) use parse::parse_cookie_date;
+use parse::{parse_set_cookie, SameSite};
+
+/// Expiry dates further out than this are capped, as browsers do (RFC 6265bis §5.5).
+const MAX_LIFETIME: i64 = 400 * 86_400;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Cookie {
+    name: String,
+    value: String,
+    /// Host the cookie was set by, or its Domain attribute
+    domain: String,
+    path: String,
+    /// No Domain attribute: only sent to exactly `domain`
+    host_only: bool,
+    secure: bool,
+    http_only: bool,
+    same_site: SameSite,
+    /// Unix seconds; None for a session cookie
+    expires: Option<i64>,
+    /// Unix seconds when the cookie was first stored
+    created: i64,
+    /// Storage order, breaking ties between cookies created in the same second
+    seq: u64,
+}
+
+impl Cookie {
+    fn is_expired(&self, now: i64) -> bool {
+        self.expires.is_some_and(|t| t <= now)
+    }
+
+    fn same_slot(&self, other: &Cookie) -> bool {
+        self.name == other.name && self.domain == other.domain && self.path == other.path
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        let host_ok = if self.host_only {
+            url.host == self.domain
+        } else {
+            domain_match(&url.host, &self.domain)
+        };
+        host_ok && path_match(&url.path, &self.path) && (url.secure || !self.secure)
+    }
+}
+
+/// The parts of a request URL that cookies depend on.
+#[derive(Debug, PartialEq, Eq)]
+struct Url {
+    secure: bool,
+    /// Lowercased, without port or userinfo
+    host: String,
+    path: String,
+}
+
+impl Url {
+    /// Parse an absolute http(s) or ws(s) URL.
+    fn parse(url: &str) -> Result<Url, Error> {
+        let (scheme, rest) = url.split_once("://").ok_or(Error::InvalidUrl)?;
+        let secure = match scheme.to_ascii_lowercase().as_str() {
+            "https" | "wss" => true,
+            "http" | "ws" => false,
+            _ => return Err(Error::InvalidUrl),
+        };
+        let authority_end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+        let (authority, rest) = rest.split_at(authority_end);
+        let host_port = authority.rsplit_once('@').map_or(authority, |(_, h)| h);
+        let host = if host_port.starts_with('[') {
+            host_port.find(']').map(|end| &host_port[..=end])
+        } else {
+            host_port.split(':').next()
+        }
+        .filter(|h| !h.is_empty())
+        .ok_or(Error::InvalidUrl)?;
+        let path_end = rest.find(['?', '#']).unwrap_or(rest.len());
+        let path = match &rest[..path_end] {
+            "" => "/",
+            path => path,
+        };
+        Ok(Url {
+            secure,
+            host: host.to_ascii_lowercase(),
+            path: path.to_string(),
+        })
+    }
+
+    fn is_ip(&self) -> bool {
+        self.host.starts_with('[') || self.host.parse::<Ipv4Addr>().is_ok()
+    }
+}
+
+/// RFC 6265 §5.1.3.
+fn domain_match(host: &str, domain: &str) -> bool {
+    if host == domain {
+        return true;
+    }
+    let is_ip = host.starts_with('[') || host.parse::<Ipv4Addr>().is_ok();
+    !is_ip
+        && host
+            .strip_suffix(domain)
+            .is_some_and(|prefix| prefix.ends_with('.'))
+}
+
+/// RFC 6265 §5.1.4.
+fn path_match(request_path: &str, cookie_path: &str) -> bool {
+    match request_path.strip_prefix(cookie_path) {
+        Some(rest) => rest.is_empty() || cookie_path.ends_with('/') || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+/// Path a cookie without a Path attribute gets: the request path up to its last '/'.
+fn default_path(request_path: &str) -> &str {
+    match request_path.rfind('/') {
+        Some(0) | None => "/",
+        Some(i) => &request_path[..i],
+    }
+}
+
+/// Cookies from responses, replayed on later requests the way a browser does.
+/// Times are unix seconds as `f64` (`Date.now() / 1000` in JS).
+///
+/// Every matching cookie is sent: SameSite needs a notion of the requesting site
+/// that a fetch without a page does not have, and HttpOnly only hides cookies from
+/// page scripts. Both are stored and exported.
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct CookieJar {
+    cookies: Vec<Cookie>,
+    next_seq: u64,
+    public_suffixes: Option<PublicSuffixList>,
+}
+
+impl Default for CookieJar {
+    fn default() -> Self {
+        CookieJar {
+            cookies: Vec::new(),
+            next_seq: 0,
+            public_suffixes: bundled_public_suffixes(),
+        }
+    }
+}
+
+fn bundled_public_suffixes() -> Option<PublicSuffixList> {
+    #[cfg(feature = "psl")]
+    return Some(PublicSuffixList::bundled());
+    #[cfg(not(feature = "psl"))]
+    None
+}
+
+#[wasm_bindgen]
+impl CookieJar {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> CookieJar {
+        CookieJar::default()
+    }
+
+    /// Check Domain attributes against `list` from now on, in place of the bundled
+    /// list or the inner-dot rule. Cookies already stored are kept.
+    pub fn set_public_suffix_list(&mut self, list: &PublicSuffixList) {
+        self.public_suffixes = Some(list.clone());
+    }
+
+    /// Store one Set-Cookie header value received in the response to `url`.
+    /// Returns false when the cookie is ignored: unparsable, a Domain the host is
+    /// not in, Secure (or a `__Secure-`/`__Host-` name, or SameSite=None) from a
+    /// plain-http URL. A cookie that is already expired deletes its stored copy.
+    pub fn set_cookie(&mut self, url: &str, header: &str, now: f64) -> Result<bool, Error> {
+        let url = Url::parse(url)?;
+        Ok(self.store(&url, header, now as i64))
+    }
+
+    /// Store every Set-Cookie header among `names`/`values`, e.g. the
+    /// `header_names()`/`header_values()` of a response event. Returns how many were kept.
+    pub fn store_response(
+        &mut self,
+        url: &str,
+        names: Vec<String>,
+        values: Vec<String>,
+        now: f64,
+    ) -> Result<usize, Error> {
+        if names.len() != values.len() {
+            return Err(Error::HeaderCountMismatch);
+        }
+        let url = Url::parse(url)?;
+        let stored = names
+            .iter()
+            .zip(&values)
+            .filter(|(n, _)| n.eq_ignore_ascii_case("set-cookie"))
+            .filter(|(_, v)| self.store(&url, v, now as i64))
+            .count();
+        Ok(stored)
+    }
+
+    /// Cookie header value for a request to `url`, or null when no cookie applies.
+    /// Cookies with longer paths come first, then older ones, as in browsers.
+    pub fn cookie_header(&self, url: &str, now: f64) -> Result<Option<String>, Error> {
+        let url = Url::parse(url)?;
+        let now = now as i64;
+        let mut matching: Vec<&Cookie> = self
+            .cookies
+            .iter()
+            .filter(|c| !c.is_expired(now) && c.matches(&url))
+            .collect();
+        if matching.is_empty() {
+            return Ok(None);
+        }
+        matching.sort_by(|a, b| {
+            b.path
+                .len()
+                .cmp(&a.path.len())
+                .then(a.created.cmp(&b.created))
+                .then(a.seq.cmp(&b.seq))
+        });
+        let pairs: Vec<String> = matching
+            .iter()
+            .map(|c| format!("{}={}", c.name, c.value))
+            .collect();
+        Ok(Some(pairs.join("; ")))
+    }
+
+    /// Drop expired cookies; returns how many were removed. Lookups skip them anyway.
+    pub fn evict_expired(&mut self, now: f64) -> usize {
+        let before = self.cookies.len();
+        self.cookies.retain(|c| !c.is_expired(now as i64));
+        before - self.cookies.len()
+    }
+
+    /// Drop session cookies (those without Expires or Max-Age), as when a browser closes.
+    pub fn clear_session_cookies(&mut self) {
+        self.cookies.retain(|c| c.expires.is_some());
+    }
+
+    pub fn clear(&mut self) {
+        self.cookies.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.cookies.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cookies.is_empty()
+    }
+
+    /// Every stored cookie as a JSON array of objects with `name`, `value`, `domain`,
+    /// `path`, `hostOnly`, `secure`, `httpOnly`, `sameSite` ("strict", "lax", "none" or
+    /// ""), `expires` (unix seconds, null for session cookies) and `created`.
+    pub fn export_json(&self) -> String {
+        let mut out = String::from("[");
+        for (i, c) in self.cookies.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            let strings = [
+                ("name", c.name.as_str()),
+                ("value", &c.value),
+                ("domain", &c.domain),
+                ("path", &c.path),
+                ("sameSite", c.same_site.as_str()),
+            ];
+            out.push('{');
+            for (key, value) in strings {
+                json::quote(key, &mut out);
+                out.push(':');
+                json::quote(value, &mut out);
+                out.push(',');
+            }
+            let expires = c.expires.map_or("null".to_string(), |t| t.to_string());
+            out.push_str(&format!(
+                "\"hostOnly\":{},\"secure\":{},\"httpOnly\":{},\"expires\":{},\"created\":{}}}",
+                c.host_only, c.secure, c.http_only, expires, c.created
+            ));
+        }
+        out.push(']');
+        out
+    }
+
+    /// Add the cookies from `export_json` output, replacing stored cookies with the
+    /// same name, domain and path. Nothing is imported if any entry is malformed.
+    pub fn import_json(&mut self, json: &str) -> Result<(), Error> {
+        let Some(Value::Array(entries)) = json::parse(json) else {
+            return Err(Error::InvalidCookieJar("expected a JSON array"));
+        };
+        let cookies = entries
+            .iter()
+            .map(cookie_from_json)
+            .collect::<Result<Vec<_>, _>>()?;
+        for mut cookie in cookies {
+            cookie.seq = self.take_seq();
+            self.cookies.retain(|c| !c.same_slot(&cookie));
+            self.cookies.push(cookie);
+        }
+        Ok(())
+    }
+}
+
+impl CookieJar {
+    /// Rust-side `store_response` for a parsed head or trailers.
+    pub fn store_headers(
+        &mut self,
+        url: &str,
+        headers: &Headers,
+        now: f64,
+    ) -> Result<usize, Error> {
+        let url = Url::parse(url)?;
+        let stored = headers
+            .get_all("set-cookie")
+            .filter(|v| self.store(&url, v, now as i64))
+            .count();
+        Ok(stored)
+    }
+
+    fn take_seq(&mut self) -> u64 {
+        self.next_seq += 1;
+        self.next_seq
+    }
+
+    fn store(&mut self, url: &Url, header: &str, now: i64) -> bool {
+        let Some(parsed) = parse_set_cookie(header) else {
+            return false;
+        };
+        let expires = match (parsed.max_age, parsed.expires) {
+            (Some(delta), _) if delta <= 0 => Some(i64::MIN),
+            (Some(delta), _) => Some(now.saturating_add(delta.min(MAX_LIFETIME))),
+            (None, Some(time)) => Some(time.min(now + MAX_LIFETIME)),
+            (None, None) => None,
+        };
+        let public_suffix = |domain: &str| match &self.public_suffixes {
+            Some(list) => list.is_public_suffix(domain),
+            None => !domain.contains('.'),
+        };
+        let (domain, host_only) = match parsed.domain {
+            // RFC 6265 §5.3 step 5: a public suffix may only name the host itself
+            Some(domain) if domain == url.host && public_suffix(&domain) => (domain, true),
+            Some(domain) if domain == url.host => (domain, false),
+            Some(domain) => {
+                if url.is_ip() || public_suffix(&domain) || !domain_match(&url.host, &domain) {
+                    return false;
+                }
+                (domain, false)
+            }
+            None => (url.host.clone(), true),
+        };
+        let path = parsed
+            .path
+            .unwrap_or_else(|| default_path(&url.path))
+            .to_string();
+
+        let secure_only = parsed.secure
+            || parsed.name.starts_with("__Secure-")
+            || parsed.name.starts_with("__Host-")
+            || parsed.same_site == SameSite::None;
+        if secure_only && !(url.secure && parsed.secure) {
+            return false;
+        }
+        if parsed.name.starts_with("__Host-") && (!host_only || path != "/") {
+            return false;
+        }
+
+        let mut cookie = Cookie {
+            name: parsed.name.to_string(),
+            value: parsed.value.to_string(),
+            domain,
+            path,
+            host_only,
+            secure: parsed.secure,
+            http_only: parsed.http_only,
+            same_site: parsed.same_site,
+            expires,
+            created: now,
+            seq: 0,
+        };
+        let old = self.cookies.iter().position(|c| c.same_slot(&cookie));
+        if let Some(i) = old {
+            let old = self.cookies.remove(i);
+            cookie.created = old.created;
+            cookie.seq = old.seq;
+        } else {
+            cookie.seq = self.take_seq();
+        }
+        if !cookie.is_expired(now) {
+            self.cookies.push(cookie);
+        }
+        true
+    }
+}
+
+fn cookie_from_json(entry: &Value) -> Result<Cookie, Error> {
+    let string = |key: &'static str| {
+        entry
+            .get(key)
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .ok_or(Error::InvalidCookieJar(key))
+    };
+    let flag = |key: &'static str| {
+        entry
+            .get(key)
+            .and_then(Value::as_bool)
+            .ok_or(Error::InvalidCookieJar(key))
+    };
+    let time = |key: &'static str| match entry.get(key) {
+        Some(Value::Null) => Ok(None),
+        Some(Value::Number(n)) if n.fract() == 0.0 && n.abs() < 9e15 => Ok(Some(*n as i64)),
+        _ => Err(Error::InvalidCookieJar(key)),
+    };
+    let cookie = Cookie {
+        name: string("name")?,
+        value: string("value")?,
+        domain: string("domain")?.to_ascii_lowercase(),
+        path: string("path")?,
+        host_only: flag("hostOnly")?,
+        secure: flag("secure")?,
+        http_only: flag("httpOnly")?,
+        same_site: SameSite::parse(&string("sameSite")?),
+        expires: time("expires")?,
+        created: time("created")?.ok_or(Error::InvalidCookieJar("created"))?,
+        seq: 0,
+    };
+    let has_control = |s: &str| s.bytes().any(|b| b.is_ascii_control());
+    if cookie.name.is_empty()
+        || [&cookie.name, &cookie.value, &cookie.domain, &cookie.path]
+            .iter()
+            .any(|s| has_control(s) || s.contains(';'))
+        || !cookie.path.starts_with('/')
+    {
+        return Err(Error::InvalidCookieJar("name, value, domain or path"));
+    }
+    Ok(cookie)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: f64 = 1_700_000_000.0;
+
+    fn header(jar: &CookieJar, url: &str) -> Option<String> {
+        jar.cookie_header(url, NOW).unwrap()
+    }
+
+    #[test]
+    fn urls_and_matching_rules() {
+        assert_eq!(
+            Url::parse("HTTPS://user:pw@WWW.Example.com:8443/a/b?q=/x#f").unwrap(),
+            Url {
+                secure: true,
+                host: "www.example.com".to_string(),
+                path: "/a/b".to_string(),
+            }
+        );
+        assert_eq!(Url::parse("http://[::1]:80").unwrap().host, "[::1]");
+        assert_eq!(Url::parse("http://h?x").unwrap().path, "/");
+        for bad in [
+            "example.com/a",
+            "ftp://h/",
+            "https:///path",
+            "https://:443/",
+        ] {
+            assert_eq!(Url::parse(bad), Err(Error::InvalidUrl), "{:?}", bad);
+        }
+
+        assert!(domain_match("a.example.com", "example.com"));
+        assert!(!domain_match("badexample.com", "example.com"));
+        assert!(!domain_match("1.2.3.4", "2.3.4"));
+        assert!(path_match("/docs/web", "/docs"));
+        assert!(path_match("/docs/", "/docs/"));
+        assert!(!path_match("/docsweb", "/docs"));
+        assert_eq!(default_path("/a/b/c"), "/a/b");
+        assert_eq!(default_path("/a"), "/");
+    }
+
+    #[test]
+    fn domain_path_and_secure_scoping() {
+        let mut jar = CookieJar::new();
+        let url = "https://www.example.com/app/page";
+        assert!(jar.set_cookie(url, "host=1", NOW).unwrap());
+        assert!(jar
+            .set_cookie(url, "wide=2; Domain=.example.com; Path=/", NOW)
+            .unwrap());
+        assert!(jar.set_cookie(url, "sec=3; Secure", NOW).unwrap());
+        assert!(!jar
+            .set_cookie(url, "other=4; Domain=other.com", NOW)
+            .unwrap());
+        assert!(!jar.set_cookie(url, "tld=5; Domain=com", NOW).unwrap());
+        assert!(!jar
+            .set_cookie("http://www.example.com/", "s=1; Secure", NOW)
+            .unwrap());
+        assert!(!jar
+            .set_cookie(url, "__Host-x=1; Secure; Path=/app", NOW)
+            .unwrap());
+        assert!(!jar.set_cookie(url, "none=1; SameSite=None", NOW).unwrap());
+        assert_eq!(jar.len(), 3);
+
+        assert_eq!(
+            header(&jar, "https://www.example.com/app/x").as_deref(),
+            Some("host=1; sec=3; wide=2")
+        );
+        // host-only and default-path cookies stay on their host and directory
+        assert_eq!(
+            header(&jar, "https://api.example.com/app/x").as_deref(),
+            Some("wide=2")
+        );
+        assert_eq!(
+            header(&jar, "https://www.example.com/").as_deref(),
+            Some("wide=2")
+        );
+        assert_eq!(
+            header(&jar, "http://www.example.com/app/").as_deref(),
+            Some("host=1; wide=2")
+        );
+        assert_eq!(header(&jar, "https://example.org/"), None);
+    }
+
+    #[test]
+    fn order_replacement_and_expiry() {
+        let mut jar = CookieJar::new();
+        let url = "https://example.com/";
+        jar.set_cookie(url, "b=1; Path=/", NOW).unwrap();
+        jar.set_cookie(url, "a=1; Path=/", NOW).unwrap();
+        jar.set_cookie(url, "deep=1; Path=/x/y", NOW + 5.0).unwrap();
+        jar.set_cookie(url, "mid=1; Path=/x; Max-Age=10", NOW)
+            .unwrap();
+        assert_eq!(
+            header(&jar, "https://example.com/x/y/z").as_deref(),
+            Some("deep=1; mid=1; b=1; a=1")
+        );
+
+        // Replacing keeps the creation time, so the order holds
+        jar.set_cookie(url, "b=2; Path=/", NOW + 9.0).unwrap();
+        assert_eq!(header(&jar, url).as_deref(), Some("b=2; a=1"));
+
+        assert_eq!(
+            jar.cookie_header("https://example.com/x/", NOW + 10.0)
+                .unwrap()
+                .as_deref(),
+            Some("b=2; a=1")
+        );
+        assert_eq!(jar.evict_expired(NOW + 10.0), 1);
+
+        assert!(jar.set_cookie(url, "a=; Max-Age=0", NOW).unwrap());
+        assert!(jar
+            .set_cookie(url, "b=; Expires=Thu, 01 Jan 1970 00:00:00 GMT", NOW)
+            .unwrap());
+        assert_eq!(header(&jar, url), None);
+
+        // Far-future dates are capped at 400 days
+        jar.set_cookie(url, "long=1; Expires=Fri, 31 Dec 9999 23:59:59 GMT", NOW)
+            .unwrap();
+        let later = NOW + MAX_LIFETIME as f64;
+        assert_eq!(jar.cookie_header(url, later).unwrap(), None);
+    }
+
+    #[test]
+    fn public_suffix_domains() {
+        let mut jar = CookieJar::new();
+        jar.set_public_suffix_list(&PublicSuffixList::new("io\ngithub.io\nuk\nco.uk\n"));
+        let url = "https://evil.github.io/";
+        assert!(!jar.set_cookie(url, "a=1; Domain=github.io", NOW).unwrap());
+        assert!(!jar
+            .set_cookie("https://shop.example.co.uk/", "b=1; Domain=co.uk", NOW)
+            .unwrap());
+        assert!(jar
+            .set_cookie(
+                "https://shop.example.co.uk/",
+                "c=1; Domain=example.co.uk",
+                NOW
+            )
+            .unwrap());
+        // A Domain equal to a suffix host only ever reaches that host
+        assert!(jar
+            .set_cookie("https://github.io/", "d=1; Domain=github.io", NOW)
+            .unwrap());
+        assert_eq!(header(&jar, url), None);
+        assert_eq!(header(&jar, "https://github.io/").as_deref(), Some("d=1"));
+        assert_eq!(
+            header(&jar, "https://www.example.co.uk/").as_deref(),
+            Some("c=1")
+        );
+    }
+
+    #[test]
+    fn stores_set_cookie_headers_of_a_response() {
+        let mut jar = CookieJar::new();
+        let names = ["Content-Type", "Set-Cookie", "set-cookie", "SET-COOKIE"].map(String::from);
+        let values = ["text/html", "a=1", "garbage", "b=2; HttpOnly"].map(String::from);
+        let url = "https://example.com/";
+        assert_eq!(
+            jar.store_response(url, names.to_vec(), values.to_vec(), NOW),
+            Ok(2)
+        );
+        assert_eq!(
+            jar.store_response(url, names.to_vec(), Vec::new(), NOW),
+            Err(Error::HeaderCountMismatch)
+        );
+        let mut headers = Headers::new();
+        headers.push("Set-Cookie", "c=3");
+        assert_eq!(jar.store_headers(url, &headers, NOW), Ok(1));
+        assert_eq!(header(&jar, url).as_deref(), Some("a=1; b=2; c=3"));
+        jar.clear_session_cookies();
+        assert!(jar.is_empty());
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let mut jar = CookieJar::new();
+        let url = "https://shop.example.com/cart/view";
+        jar.set_cookie(
+            url,
+            "sid=\"q\\uote\"; Secure; HttpOnly; SameSite=Strict",
+            NOW,
+        )
+        .unwrap();
+        jar.set_cookie(
+            url,
+            "pref=dark; Domain=example.com; Path=/; Max-Age=3600",
+            NOW + 1.0,
+        )
+        .unwrap();
+        let json = jar.export_json();
+        assert!(
+            json.starts_with(r#"[{"name":"sid","value":"\"q\\uote\"","#),
+            "{}",
+            json
+        );
+        assert!(
+            json.contains(r#""expires":1700003601,"created":1700000001}"#),
+            "{}",
+            json
+        );
+
+        let mut restored = CookieJar::new();
+        restored.import_json(&json).unwrap();
+        assert_eq!(restored.cookies.len(), 2);
+        for (a, b) in restored.cookies.iter().zip(&jar.cookies) {
+            assert_eq!(
+                Cookie {
+                    seq: 0,
+                    ..a.clone()
+                },
+                Cookie {
+                    seq: 0,
+                    ..b.clone()
+                }
+            );
+        }
+        assert_eq!(restored.export_json(), json);
+        // Importing again replaces rather than duplicates
+        restored.import_json(&json).unwrap();
+        assert_eq!(restored.len(), 2);
+        assert_eq!(
+            restored
+                .cookie_header("https://shop.example.com/cart/", NOW)
+                .unwrap(),
+            header(&jar, "https://shop.example.com/cart/")
+        );
+
+        for bad in [
+            "{}",
+            r#"[{"name":"a"}]"#,
+            r#"[{"name":"a","value":"1;x","domain":"h","path":"/","hostOnly":true,"secure":false,"httpOnly":false,"sameSite":"","expires":null,"created":0}]"#,
+            r#"[{"name":"a","value":"1","domain":"h","path":"/","hostOnly":true,"secure":false,"httpOnly":false,"sameSite":"","expires":1.5,"created":0}]"#,
+        ] {
+            assert!(restored.import_json(bad).is_err(), "{}", bad);
+        }
+        assert_eq!(restored.len(), 2);
+    }
+}
@@ -0,0 +1,290 @@
+//! Set-Cookie parsing (RFC 6265 §5.2) and the cookie-date algorithm (§5.1.1).
+
+/// SameSite attribute as received; an unrecognized value counts as unspecified.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum SameSite {
+    Unspecified,
+    Strict,
+    Lax,
+    None,
+}
+
+impl SameSite {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            SameSite::Unspecified => "",
+            SameSite::Strict => "strict",
+            SameSite::Lax => "lax",
+            SameSite::None => "none",
+        }
+    }
+
+    pub(crate) fn parse(value: &str) -> SameSite {
+        let is = |name: &str| value.eq_ignore_ascii_case(name);
+        if is("strict") {
+            SameSite::Strict
+        } else if is("lax") {
+            SameSite::Lax
+        } else if is("none") {
+            SameSite::None
+        } else {
+            SameSite::Unspecified
+        }
+    }
+}
+
+/// One parsed Set-Cookie header, before the storage rules are applied.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct SetCookie<'a> {
+    pub(crate) name: &'a str,
+    pub(crate) value: &'a str,
+    /// Expires attribute, as unix seconds
+    pub(crate) expires: Option<i64>,
+    /// Max-Age attribute in seconds; takes precedence over Expires
+    pub(crate) max_age: Option<i64>,
+    /// Domain attribute, lowercased and without a leading dot
+    pub(crate) domain: Option<String>,
+    /// Path attribute, only kept when it starts with '/'
+    pub(crate) path: Option<&'a str>,
+    pub(crate) secure: bool,
+    pub(crate) http_only: bool,
+    pub(crate) same_site: SameSite,
+}
+
+/// Parse a Set-Cookie value. None means the header is ignored: no '=' in the
+/// name-value pair, an empty name, or control characters.
+pub(crate) fn parse_set_cookie(header: &str) -> Option<SetCookie<'_>> {
+    if header.bytes().any(|b| b.is_ascii_control() && b != b'\t') {
+        return None;
+    }
+    let mut parts = header.split(';');
+    let (name, value) = parts.next()?.split_once('=')?;
+    let name = name.trim();
+    if name.is_empty() {
+        return None;
+    }
+    let mut cookie = SetCookie {
+        name,
+        value: value.trim(),
+        expires: None,
+        max_age: None,
+        domain: None,
+        path: None,
+        secure: false,
+        http_only: false,
+        same_site: SameSite::Unspecified,
+    };
+    for attribute in parts {
+        let (key, value) = attribute.split_once('=').unwrap_or((attribute, ""));
+        let (key, value) = (key.trim(), value.trim());
+        let is = |name: &str| key.eq_ignore_ascii_case(name);
+        if is("expires") {
+            if let Some(time) = parse_cookie_date(value) {
+                cookie.expires = Some(time);
+            }
+        } else if is("max-age") {
+            if let Some(delta) = parse_max_age(value) {
+                cookie.max_age = Some(delta);
+            }
+        } else if is("domain") {
+            let domain = value.strip_prefix('.').unwrap_or(value);
+            if !domain.is_empty() {
+                cookie.domain = Some(domain.to_ascii_lowercase());
+            }
+        } else if is("path") {
+            cookie.path = value.starts_with('/').then_some(value);
+        } else if is("secure") {
+            cookie.secure = true;
+        } else if is("httponly") {
+            cookie.http_only = true;
+        } else if is("samesite") {
+            cookie.same_site = SameSite::parse(value);
+        }
+    }
+    Some(cookie)
+}
+
+/// Max-Age value: an optional '-' and digits, saturating on overflow.
+fn parse_max_age(value: &str) -> Option<i64> {
+    let digits = value.strip_prefix('-').unwrap_or(value);
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let negative = digits.len() < value.len();
+    Some(
+        value
+            .parse()
+            .unwrap_or(if negative { i64::MIN } else { i64::MAX }),
+    )
+}
+
+fn is_delimiter(b: u8) -> bool {
+    matches!(b, 0x09 | 0x20..=0x2f | 0x3b..=0x40 | 0x5b..=0x60 | 0x7b..=0x7e)
+}
+
+/// Leading digits of `token` as a number, if there are `min..=max` of them and no
+/// digit follows.
+fn leading_number(token: &str, min: usize, max: usize) -> Option<(u32, &str)> {
+    let count = token.bytes().take_while(u8::is_ascii_digit).count();
+    if !(min..=max).contains(&count) {
+        return None;
+    }
+    let (digits, rest) = token.split_at(count);
+    Some((digits.parse().ok()?, rest))
+}
+
+/// Parse the date of an Expires attribute into unix seconds, accepting the many
+/// formats servers send the way browsers do.
+pub(crate) fn parse_cookie_date(date: &str) -> Option<i64> {
+    const MONTHS: [&str; 12] = [
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ];
+    let (mut time, mut day, mut month, mut year) = (None, None, None, None);
+    for token in date.split(|c: char| c.is_ascii() && is_delimiter(c as u8)) {
+        if token.is_empty() {
+            continue;
+        }
+        if time.is_none() {
+            if let Some(t) = parse_time(token) {
+                time = Some(t);
+                continue;
+            }
+        }
+        if day.is_none() {
+            if let Some((d, _)) = leading_number(token, 1, 2) {
+                day = Some(d);
+                continue;
+            }
+        }
+        if month.is_none() {
+            let prefix = token.get(..3).map(str::to_ascii_lowercase);
+            if let Some(m) = MONTHS.iter().position(|m| prefix.as_deref() == Some(*m)) {
+                month = Some(m as u32 + 1);
+                continue;
+            }
+        }
+        if year.is_none() {
+            if let Some((y, _)) = leading_number(token, 2, 4) {
+                year = Some(y);
+            }
+        }
+    }
+    let ((hour, minute, second), day, month, mut year) = (time?, day?, month?, year?);
+    match year {
+        70..=99 => year += 1900,
+        0..=69 => year += 2000,
+        _ => {}
+    }
+    if year < 1601 || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+    if day == 0 || day > days_in_month(year, month) {
+        return None;
+    }
+    let days = days_from_civil(year as i64, month, day);
+    Some(days * 86_400 + (hour * 3600 + minute * 60 + second) as i64)
+}
+
+/// `hh:mm:ss` with one or two digits each; anything not a digit may follow.
+fn parse_time(token: &str) -> Option<(u32, u32, u32)> {
+    let mut fields = token.splitn(3, ':');
+    let (hour, rest) = leading_number(fields.next()?, 1, 2)?;
+    let (minute, rest2) = leading_number(fields.next()?, 1, 2)?;
+    let (second, _) = leading_number(fields.next()?, 1, 2)?;
+    (rest.is_empty() && rest2.is_empty()).then_some((hour, minute, second))
+}
+
+fn days_in_month(year: u32, month: u32) -> u32 {
+    match month {
+        2 if year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400)) => {
+            29
+        }
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cookie_dates_in_server_formats() {
+        // 2015-10-21T07:28:00Z
+        let expected = Some(1_445_412_480);
+        for date in [
+            "Wed, 21 Oct 2015 07:28:00 GMT",
+            "Wednesday, 21-Oct-15 07:28:00 GMT",
+            "Wed Oct 21 07:28:00 2015",
+            "21 October 2015 07:28:00",
+            "Wed, 21-oct-2015 7:28:0 UTC",
+        ] {
+            assert_eq!(parse_cookie_date(date), expected, "{:?}", date);
+        }
+        assert_eq!(parse_cookie_date("Thu, 01 Jan 1970 00:00:00 GMT"), Some(0));
+        assert_eq!(
+            parse_cookie_date("Tue, 29 Feb 2000 12:00:00 GMT"),
+            Some(951_825_600)
+        );
+        for bad in [
+            "Wed, 30 Feb 2015 07:28:00 GMT",
+            "21 Oct 2015",
+            "Wed, 21 Oct 2015 24:00:00 GMT",
+            "Wed, 21 Foo 2015 07:28:00 GMT",
+            "01 Jan 1600 00:00:00",
+        ] {
+            assert_eq!(parse_cookie_date(bad), None, "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn set_cookie_attributes() {
+        let cookie = parse_set_cookie(
+            " sid = a=b ; Path=/app; DOMAIN=.Example.COM; Max-Age=60; secure; HttpOnly; \
+             SameSite=LAX; Expires=Wed, 21 Oct 2015 07:28:00 GMT; Unknown=1",
+        )
+        .unwrap();
+        assert_eq!(
+            cookie,
+            SetCookie {
+                name: "sid",
+                value: "a=b",
+                expires: Some(1_445_412_480),
+                max_age: Some(60),
+                domain: Some("example.com".to_string()),
+                path: Some("/app"),
+                secure: true,
+                http_only: true,
+                same_site: SameSite::Lax,
+            }
+        );
+        let plain =
+            parse_set_cookie("a=; Path=relative; Domain=; Max-Age=1x; SameSite=odd").unwrap();
+        assert_eq!((plain.name, plain.value), ("a", ""));
+        assert_eq!(
+            (plain.path, &plain.domain, plain.max_age),
+            (None, &None, None)
+        );
+        assert_eq!(plain.same_site, SameSite::Unspecified);
+        assert_eq!(
+            parse_set_cookie("a=1; Max-Age=-5").unwrap().max_age,
+            Some(-5)
+        );
+
+        for ignored in ["novalue", "=v", " =v; Path=/", "a=1\r\nX: y", ""] {
+            assert_eq!(parse_set_cookie(ignored), None, "{:?}", ignored);
+        }
+    }
+}
@@ -0,0 +1,266 @@
+//! DNS over HTTPS (RFC 8484): the request that carries a query to a resolver,
+//! and the checks on what comes back.
+
+use wasm_bindgen::prelude::*;
+
+use super::message::DnsMessage;
+use super::record::record_type;
+use crate::base64;
+use crate::error::Error;
+use crate::url::HttpUrl;
+
+/// Media type of DNS messages over HTTP.
+pub const DNS_MESSAGE: &str = "application/dns-message";
+
+/// Block size DoH queries are padded to by default (RFC 8467 §4.1).
+pub const QUERY_PADDING_BLOCK: usize = 128;
+
+/// Largest DNS message: its length travels in 16 bits elsewhere, and resolvers
+/// answer nothing longer over HTTP either.
+pub const MAX_MESSAGE_SIZE: usize = 65535;
+
+/// The HTTP request for one DNS query to a DoH resolver, and the reader of the
+/// response to it. Whatever sends the request (`StealthFetch`, or the host's own
+/// fetch) only has to move bytes; `read_response` turns the body back into a
+/// `DnsMessage`.
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct DohQuery {
+    resolver: HttpUrl,
+    message: DnsMessage,
+    get: bool,
+    padding: usize,
+}
+
+#[wasm_bindgen]
+impl DohQuery {
+    /// A query for `record_type` ("A", "AAAA", "CNAME", "TXT", "SVCB" or "HTTPS")
+    /// records of `name`, to the resolver at `resolver_url`, e.g.
+    /// "https://1.1.1.1/dns-query". It goes out with POST, padded to 128-byte
+    /// blocks, advertising a 1232-byte UDP size.
+    #[wasm_bindgen(constructor)]
+    pub fn new(resolver_url: &str, name: &str, record_type_name: &str) -> Result<DohQuery, Error> {
+        let resolver = HttpUrl::parse(resolver_url)?;
+        if resolver.scheme() != "https" {
+            return Err(Error::InvalidOption {
+                option: "resolver_url",
+                value: resolver_url.to_string(),
+            });
+        }
+        let record_type = record_type(record_type_name).ok_or_else(|| Error::InvalidOption {
+            option: "record_type",
+            value: record_type_name.to_string(),
+        })?;
+        Ok(DohQuery {
+            resolver,
+            message: DnsMessage::query(name, record_type)?,
+            get: false,
+            padding: QUERY_PADDING_BLOCK,
+        })
+    }
+
+    /// "POST" (the default), or "GET", which carries the query base64url-encoded
+    /// in the `dns` parameter so HTTP caches can answer it.
+    pub fn set_method(&mut self, method: &str) -> Result<(), Error> {
+        self.get = match method {
+            "GET" => true,
+            "POST" => false,
+            _ => {
+                return Err(Error::InvalidOption {
+                    option: "method",
+                    value: method.to_string(),
+                })
+            }
+        };
+        Ok(())
+    }
+
+    /// UDP payload size advertised in EDNS (default 1232), which a resolver may
+    /// pass on upstream. Sizes below 512 count as 512 (RFC 6891 §6.2.3).
+    pub fn set_udp_size(&mut self, size: u16) {
+        if let Some(edns) = &mut self.message.edns {
+            edns.udp_size = size.max(512);
+        }
+    }
+
+    /// Pad the query to a multiple of `block` bytes (default 128), so its length
+    /// says less about the name; 0 sends it unpadded.
+    pub fn set_padding(&mut self, block: usize) {
+        self.padding = block;
+    }
+
+    pub fn method(&self) -> String {
+        if self.get { "GET" } else { "POST" }.to_string()
+    }
+
+    /// URL to request: the resolver's, with the `dns` parameter for GET.
+    pub fn url(&self) -> String {
+        let url = self.resolver.without_fragment();
+        if !self.get {
+            return url;
+        }
+        let separator = if self.resolver.query().is_some() {
+            '&'
+        } else {
+            '?'
+        };
+        format!(
+            "{}{}dns={}",
+            url,
+            separator,
+            base64::encode_url(&self.encoded())
+        )
+    }
+
+    /// Names of the request headers: Accept, and Content-Type for POST.
+    pub fn header_names(&self) -> Vec<String> {
+        let names: &[&str] = if self.get {
+            &["accept"]
+        } else {
+            &["accept", "content-type"]
+        };
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    /// Values of the request headers, in the order of `header_names`.
+    pub fn header_values(&self) -> Vec<String> {
+        let count = if self.get { 1 } else { 2 };
+        vec![DNS_MESSAGE.to_string(); count]
+    }
+
+    /// Request body: the DNS message for POST, nothing for GET.
+    pub fn body(&self) -> Vec<u8> {
+        if self.get {
+            Vec::new()
+        } else {
+            self.encoded()
+        }
+    }
+}
+
+impl DohQuery {
+    /// The query as it is sent, padding aside.
+    pub fn message(&self) -> &DnsMessage {
+        &self.message
+    }
+
+    /// The DNS message in the resolver's response: fails unless the response is
+    /// a 2xx with a DNS message body that answers this query (same ID and
+    /// question). A response code other than NOERROR is left to the caller.
+    pub fn read_response(
+        &self,
+        status: u16,
+        content_type: Option<&str>,
+        body: &[u8],
+    ) -> Result<DnsMessage, Error> {
+        if !(200..300).contains(&status) {
+            return Err(Error::Dns("the resolver did not answer with a 2xx status"));
+        }
+        let media_type = content_type.and_then(|value| value.split(';').next());
+        if !media_type.is_some_and(|media_type| media_type.trim().eq_ignore_ascii_case(DNS_MESSAGE))
+        {
+            return Err(Error::Dns("the resolver's response is not a DNS message"));
+        }
+        if body.len() > MAX_MESSAGE_SIZE {
+            return Err(Error::Dns("message longer than 65535 bytes"));
+        }
+        let response = DnsMessage::decode(body)?;
+        let question = |m: &DnsMessage| {
+            m.questions
+                .first()
+                .map(|q| (q.name.to_ascii_lowercase(), q.record_type, q.class))
+        };
+        if !response.is_response()
+            || response.id != self.message.id
+            || question(&response) != question(&self.message)
+        {
+            return Err(Error::Dns(
+                "the resolver's response does not answer the query",
+            ));
+        }
+        Ok(response)
+    }
+
+    fn encoded(&self) -> Vec<u8> {
+        // The only name was checked by `DnsMessage::query`, so this cannot fail
+        self.message.encode_padded(self.padding).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use super::*;
+    use crate::dns::{Record, RecordData, A};
+
+    #[test]
+    fn post_and_get_requests() {
+        let mut query =
+            DohQuery::new("https://dns.example/dns-query#x", "example.com", "a").unwrap();
+        assert_eq!(query.method(), "POST");
+        assert_eq!(query.url(), "https://dns.example/dns-query");
+        assert_eq!(query.header_names(), ["accept", "content-type"]);
+        assert_eq!(query.header_values(), [DNS_MESSAGE, DNS_MESSAGE]);
+        let body = query.body();
+        assert_eq!(body.len(), 128);
+        assert_eq!(
+            DnsMessage::decode(&body).unwrap().questions,
+            query.message().questions
+        );
+
+        query.set_method("GET").unwrap();
+        query.set_padding(0);
+        query.set_udp_size(100);
+        assert!(query.body().is_empty());
+        assert_eq!(query.header_names(), ["accept"]);
+        let encoded = query.message().encode().unwrap();
+        // UDP size 512, in place of the OPT record's class
+        assert_eq!(encoded[encoded.len() - 8..encoded.len() - 6], [2, 0]);
+        assert_eq!(
+            query.url(),
+            format!(
+                "https://dns.example/dns-query?dns={}",
+                base64::encode_url(&encoded)
+            )
+        );
+        let mut query = DohQuery::new("https://dns.example/q?ct", "example.com", "HTTPS").unwrap();
+        query.set_method("GET").unwrap();
+        assert!(query.url().starts_with("https://dns.example/q?ct&dns="));
+
+        assert!(DohQuery::new("http://dns.example/dns-query", "example.com", "A").is_err());
+        assert!(DohQuery::new("https://dns.example/dns-query", "example.com", "MX").is_err());
+        assert!(query.set_method("PUT").is_err());
+    }
+
+    #[test]
+    fn responses_must_answer_the_query() {
+        let query = DohQuery::new("https://dns.example/dns-query", "Example.com", "A").unwrap();
+        let mut answer = query.message().clone();
+        answer.flags |= 0x8000;
+        answer.questions[0].name = "EXAMPLE.com".into();
+        let address = RecordData::A(Ipv4Addr::new(192, 0, 2, 1));
+        answer.answers.push(Record::new("example.com", 30, address));
+        let body = answer.encode().unwrap();
+
+        let response = query
+            .read_response(200, Some("Application/DNS-Message; charset=x"), &body)
+            .unwrap();
+        assert_eq!(
+            response.addresses(),
+            [(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)), 30)]
+        );
+
+        assert!(query.read_response(500, Some(DNS_MESSAGE), &body).is_err());
+        assert!(query.read_response(200, Some("text/html"), &body).is_err());
+        assert!(query.read_response(200, None, &body).is_err());
+        // Not a response, or to another question
+        let request = query.message().encode().unwrap();
+        assert!(query
+            .read_response(200, Some(DNS_MESSAGE), &request)
+            .is_err());
+        answer.questions[0].record_type = A + 1;
+        let other = answer.encode().unwrap();
+        assert!(query.read_response(200, Some(DNS_MESSAGE), &other).is_err());
+    }
+}
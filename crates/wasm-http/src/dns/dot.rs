@@ -0,0 +1,393 @@
+//! DNS over TLS (RFC 7858): messages framed as over TCP, each behind a 2-byte
+//! length (RFC 1035 §4.2.2), pipelined on one connection to the resolver.
+
+use std::mem;
+
+use wasm_bindgen::prelude::*;
+
+use super::doh::{MAX_MESSAGE_SIZE, QUERY_PADDING_BLOCK};
+use super::message::{DnsMessage, Edns};
+use super::record::record_type;
+use crate::error::Error;
+
+/// ALPN protocol ID of DNS over TLS, as resolvers that also serve other
+/// protocols on the port expect it (ADoT, RFC 9539).
+pub const DOT_ALPN: &str = "dot";
+
+/// Port DNS over TLS resolvers listen on.
+pub const DOT_PORT: u16 = 853;
+
+/// Idle timeout `DotClient::idle_timeout` reports until the resolver announces
+/// its own: resolvers commonly close idle connections after about 10 seconds.
+pub const DEFAULT_IDLE_TIMEOUT_MS: u32 = 10_000;
+
+/// EDNS TCP keepalive option (RFC 7828): empty in queries, the idle timeout in
+/// units of 100 ms in responses
+const TCP_KEEPALIVE: u16 = 11;
+
+/// Queries waiting for a response at most, well below the 65536 IDs to pick from
+const MAX_OUTSTANDING: usize = 1024;
+
+/// The client side of a DNS over TLS connection. Nothing here touches the
+/// network: open a `TlsConnection` to the resolver's port 853 with ALPN
+/// `DOT_ALPN`, pass `take_outgoing` to its `write_plaintext` and what
+/// `take_plaintext` returns to `feed`.
+///
+/// Any number of queries may be in flight at once; each gets a random unused ID,
+/// and responses, which may come in any order, are matched to their query by it.
+/// The connection is meant to be kept for later queries: `idle_timeout` says how
+/// long the resolver will keep it open once nothing is outstanding.
+#[wasm_bindgen]
+#[derive(Debug)]
+pub struct DotClient {
+    padding: usize,
+    outgoing: Vec<u8>,
+    /// Received bytes of a message not complete yet
+    received: Vec<u8>,
+    /// Queries without a response yet, in the order sent
+    outstanding: Vec<(u16, DnsMessage)>,
+    /// Responses (or why there is none) not taken yet
+    answered: Vec<(u16, Result<DnsMessage, Error>)>,
+    /// From the resolver's last TCP keepalive option
+    idle_timeout: Option<u32>,
+    unmatched: u32,
+}
+
+impl Default for DotClient {
+    fn default() -> Self {
+        DotClient {
+            padding: QUERY_PADDING_BLOCK,
+            outgoing: Vec::new(),
+            received: Vec::new(),
+            outstanding: Vec::new(),
+            answered: Vec::new(),
+            idle_timeout: None,
+            unmatched: 0,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl DotClient {
+    /// A client that pads queries to 128-byte blocks.
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> DotClient {
+        DotClient::default()
+    }
+
+    /// Pad queries to a multiple of `block` bytes (default 128), as for
+    /// `DohQuery::set_padding`; 0 sends them unpadded.
+    pub fn set_padding(&mut self, block: usize) {
+        self.padding = block;
+    }
+
+    /// Queue a query for `record_type` ("A", "AAAA", "CNAME", "TXT", "SVCB" or
+    /// "HTTPS") records of `name`. Returns its ID, which `feed` reports once the
+    /// response is in.
+    pub fn query(&mut self, name: &str, record_type_name: &str) -> Result<u16, Error> {
+        let record_type = record_type(record_type_name).ok_or_else(|| Error::InvalidOption {
+            option: "record_type",
+            value: record_type_name.to_string(),
+        })?;
+        self.send(DnsMessage::query(name, record_type)?)
+    }
+
+    /// Bytes for `TlsConnection::write_plaintext` (moved out; empty afterwards).
+    pub fn take_outgoing(&mut self) -> Vec<u8> {
+        mem::take(&mut self.outgoing)
+    }
+
+    /// Feed plaintext from the resolver; a message may arrive split across
+    /// records and feeds, or several in one. Returns the IDs of the queries it
+    /// completed, for `take_response`. A response with the TC bit, which has
+    /// nowhere to retry over TCP, completes its query with an error; one whose ID
+    /// is not outstanding is dropped (see `unmatched_responses`).
+    ///
+    /// Fails on an empty message: the stream cannot be framed any more, so every
+    /// outstanding query fails with it and the connection should be closed.
+    pub fn feed(&mut self, data: &[u8]) -> Result<Vec<u16>, Error> {
+        self.received.extend_from_slice(data);
+        let mut completed = Vec::new();
+        while let [high, low, ..] = self.received[..] {
+            let len = u16::from_be_bytes([high, low]) as usize;
+            if len == 0 {
+                self.received.clear();
+                let error = Error::Dns("empty message on a DNS over TLS connection");
+                self.fail_outstanding(&error);
+                return Err(error);
+            }
+            if self.received.len() < 2 + len {
+                break;
+            }
+            let message: Vec<u8> = self.received.drain(..2 + len).skip(2).collect();
+            completed.extend(self.read_message(&message));
+        }
+        Ok(completed)
+    }
+
+    /// Tell the client the connection closed, e.g. after `idle_timeout`. Queries
+    /// still outstanding fail, their IDs are returned, and they can be sent again
+    /// on a new connection; a message cut off by the close is dropped. Queries
+    /// made afterwards go out on the new connection.
+    pub fn transport_closed(&mut self) -> Vec<u16> {
+        self.received.clear();
+        self.outgoing.clear();
+        self.fail_outstanding(&Error::Dns("connection closed before the response"))
+    }
+
+    /// Queries sent and not answered yet.
+    pub fn outstanding(&self) -> usize {
+        self.outstanding.len()
+    }
+
+    /// Milliseconds the resolver keeps an idle connection open: what it announced
+    /// in its last TCP keepalive option, or `DEFAULT_IDLE_TIMEOUT_MS`. Reuse the
+    /// connection within that time for the next query; past it, close it. 0 asks
+    /// for the connection to be closed once nothing is outstanding.
+    pub fn idle_timeout(&self) -> u32 {
+        self.idle_timeout.unwrap_or(DEFAULT_IDLE_TIMEOUT_MS)
+    }
+
+    /// Responses dropped for an ID no outstanding query has.
+    pub fn unmatched_responses(&self) -> u32 {
+        self.unmatched
+    }
+}
+
+impl DotClient {
+    /// Queue `message` under a fresh ID, asking for the resolver's idle timeout.
+    /// Returns the ID.
+    pub fn send(&mut self, mut message: DnsMessage) -> Result<u16, Error> {
+        if self.outstanding.len() >= MAX_OUTSTANDING {
+            return Err(Error::Dns("too many outstanding queries"));
+        }
+        let id = loop {
+            let mut random = [0; 2];
+            getrandom::fill(&mut random).map_err(|_| Error::Dns("no random source"))?;
+            let id = u16::from_be_bytes(random);
+            if !self.outstanding.iter().any(|(other, _)| *other == id) {
+                break id;
+            }
+        };
+        message.id = id;
+        let edns = message.edns.get_or_insert_with(Edns::default);
+        if !edns.options.iter().any(|(code, _)| *code == TCP_KEEPALIVE) {
+            edns.options.push((TCP_KEEPALIVE, Vec::new()));
+        }
+        let encoded = message.encode_padded(self.padding)?;
+        if encoded.len() > MAX_MESSAGE_SIZE {
+            return Err(Error::Dns("message longer than 65535 bytes"));
+        }
+        self.outgoing
+            .extend_from_slice(&(encoded.len() as u16).to_be_bytes());
+        self.outgoing.extend_from_slice(&encoded);
+        self.outstanding.push((id, message));
+        Ok(id)
+    }
+
+    /// The response to query `id` once `feed` reported it, or the error that
+    /// ended the query. Each is taken once.
+    pub fn take_response(&mut self, id: u16) -> Option<Result<DnsMessage, Error>> {
+        let index = self.answered.iter().position(|(other, _)| *other == id)?;
+        Some(self.answered.remove(index).1)
+    }
+
+    /// Match one received message to its query. Returns the query's ID.
+    fn read_message(&mut self, data: &[u8]) -> Option<u16> {
+        let id = match data {
+            [high, low, ..] => u16::from_be_bytes([*high, *low]),
+            _ => 0,
+        };
+        let Some(index) = self.outstanding.iter().position(|(other, _)| *other == id) else {
+            self.unmatched += 1;
+            return None;
+        };
+        let (id, query) = self.outstanding.remove(index);
+        let result = DnsMessage::decode(data).and_then(|response| {
+            let question = |m: &DnsMessage| {
+                m.questions
+                    .first()
+                    .map(|q| (q.name.to_ascii_lowercase(), q.record_type, q.class))
+            };
+            if !response.is_response() || question(&response) != question(&query) {
+                return Err(Error::Dns(
+                    "the resolver's response does not answer the query",
+                ));
+            }
+            if let Some(timeout) = keepalive(&response) {
+                self.idle_timeout = Some(timeout);
+            }
+            if response.truncated() {
+                return Err(Error::Dns("the resolver truncated its response"));
+            }
+            Ok(response)
+        });
+        self.answered.push((id, result));
+        Some(id)
+    }
+
+    fn fail_outstanding(&mut self, error: &Error) -> Vec<u16> {
+        let ids: Vec<u16> = self.outstanding.drain(..).map(|(id, _)| id).collect();
+        for &id in &ids {
+            self.answered.push((id, Err(error.clone())));
+        }
+        ids
+    }
+}
+
+/// Idle timeout in a response's TCP keepalive option, in milliseconds.
+fn keepalive(response: &DnsMessage) -> Option<u32> {
+    let edns = response.edns.as_ref()?;
+    edns.options
+        .iter()
+        .find_map(|(code, value)| match value[..] {
+            [high, low] if *code == TCP_KEEPALIVE => {
+                Some(u16::from_be_bytes([high, low]) as u32 * 100)
+            }
+            _ => None,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use super::*;
+    use crate::dns::{Record, RecordData};
+
+    /// The messages in `data`, length prefixes stripped.
+    fn messages(mut data: &[u8]) -> Vec<DnsMessage> {
+        let mut messages = Vec::new();
+        while let [high, low, rest @ ..] = data {
+            let len = u16::from_be_bytes([*high, *low]) as usize;
+            messages.push(DnsMessage::decode(&rest[..len]).unwrap());
+            data = &rest[len..];
+        }
+        messages
+    }
+
+    /// `query` answered with one A record, framed for the connection.
+    fn answer(query: &DnsMessage, last_octet: u8) -> Vec<u8> {
+        let mut response = query.clone();
+        response.flags |= 0x8000;
+        response.edns = Some(Edns::default());
+        let address = RecordData::A(Ipv4Addr::new(192, 0, 2, last_octet));
+        let name = &query.questions[0].name;
+        response.answers.push(Record::new(name, 60, address));
+        frame(&response)
+    }
+
+    fn frame(message: &DnsMessage) -> Vec<u8> {
+        let encoded = message.encode().unwrap();
+        let mut framed = (encoded.len() as u16).to_be_bytes().to_vec();
+        framed.extend(encoded);
+        framed
+    }
+
+    fn address(client: &mut DotClient, id: u16) -> IpAddr {
+        let response = client.take_response(id).unwrap().unwrap();
+        response.addresses()[0].0
+    }
+
+    #[test]
+    fn pipelined_queries_split_responses() {
+        let mut client = DotClient::new();
+        let first = client.query("one.example", "A").unwrap();
+        let second = client.query("two.example", "a").unwrap();
+        assert_ne!(first, second);
+        assert_eq!(client.outstanding(), 2);
+        let queries = messages(&client.take_outgoing());
+        assert_eq!(queries.len(), 2);
+        assert_eq!(queries[0].id, first);
+        // Padded, and asking for the idle timeout
+        assert_eq!(frame(&queries[0]).len(), 2 + 128);
+        let edns = queries[1].edns.as_ref().unwrap();
+        assert!(edns.options.contains(&(TCP_KEEPALIVE, Vec::new())));
+
+        // Answered in the other order, the first response cut across feeds
+        let mut stream = answer(&queries[1], 2);
+        stream.extend(answer(&queries[0], 1));
+        let (head, tail) = stream.split_at(stream.len() - 5);
+        assert_eq!(client.feed(&head[..1]).unwrap(), []);
+        assert_eq!(client.feed(&head[1..]).unwrap(), [second]);
+        assert_eq!(client.feed(tail).unwrap(), [first]);
+        assert_eq!(
+            address(&mut client, first),
+            IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))
+        );
+        assert_eq!(
+            address(&mut client, second),
+            IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2))
+        );
+        assert!(client.take_response(first).is_none());
+        assert_eq!(client.outstanding(), 0);
+        assert_eq!(client.idle_timeout(), DEFAULT_IDLE_TIMEOUT_MS);
+
+        // The same connection carries the next query
+        let third = client.query("three.example", "AAAA").unwrap();
+        let query = messages(&client.take_outgoing()).remove(0);
+        let mut response = DnsMessage::decode(&answer(&query, 3)[2..]).unwrap();
+        let keepalive = (TCP_KEEPALIVE, 25u16.to_be_bytes().to_vec());
+        response.edns.as_mut().unwrap().options.push(keepalive);
+        assert_eq!(client.feed(&frame(&response)).unwrap(), [third]);
+        assert_eq!(client.idle_timeout(), 2500);
+    }
+
+    #[test]
+    fn mismatched_and_truncated_responses() {
+        let mut client = DotClient::new();
+        client.set_padding(0);
+        let id = client.query("example.com", "A").unwrap();
+        let query = messages(&client.take_outgoing()).remove(0);
+
+        // An ID nothing waits for is dropped; the query stays outstanding
+        let mut stray = DnsMessage::decode(&answer(&query, 1)[2..]).unwrap();
+        stray.id = id.wrapping_add(1);
+        assert_eq!(client.feed(&frame(&stray)).unwrap(), []);
+        assert_eq!(client.unmatched_responses(), 1);
+        assert_eq!(client.outstanding(), 1);
+
+        let mut truncated = DnsMessage::decode(&answer(&query, 1)[2..]).unwrap();
+        truncated.flags |= 0x0200;
+        assert_eq!(client.feed(&frame(&truncated)).unwrap(), [id]);
+        assert_eq!(
+            client.take_response(id),
+            Some(Err(Error::Dns("the resolver truncated its response")))
+        );
+
+        // Right ID, wrong question
+        let id = client.query("example.com", "A").unwrap();
+        let mut query = messages(&client.take_outgoing()).remove(0);
+        query.questions[0].name = "example.net".into();
+        client.feed(&answer(&query, 1)).unwrap();
+        assert!(client.take_response(id).unwrap().is_err());
+    }
+
+    #[test]
+    fn closed_and_broken_connections() {
+        let mut client = DotClient::new();
+        let first = client.query("one.example", "A").unwrap();
+        let second = client.query("two.example", "A").unwrap();
+        let queries = messages(&client.take_outgoing());
+        // The resolver answers one and closes mid-way through the other
+        let mut stream = answer(&queries[0], 1);
+        let cut = answer(&queries[1], 2);
+        stream.extend(&cut[..cut.len() / 2]);
+        assert_eq!(client.feed(&stream).unwrap(), [first]);
+        assert_eq!(client.transport_closed(), [second]);
+        assert!(client.take_response(first).unwrap().is_ok());
+        assert!(client.take_response(second).unwrap().is_err());
+
+        // Reconnected: the leftover half is gone
+        let third = client.query("two.example", "A").unwrap();
+        let query = messages(&client.take_outgoing()).remove(0);
+        assert_eq!(client.feed(&answer(&query, 2)).unwrap(), [third]);
+        assert!(client.take_response(third).unwrap().is_ok());
+
+        let id = client.query("one.example", "A").unwrap();
+        assert!(client.feed(&[0, 0]).is_err());
+        assert_eq!(client.outstanding(), 0);
+        assert!(client.take_response(id).unwrap().is_err());
+        assert!(client.query("one.example", "MX").is_err());
+    }
+}
@@ -0,0 +1,677 @@
+//! DNS messages in wire format (RFC 1035 §4.1), with EDNS(0) (RFC 6891).
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use super::record::{
+    Record, RecordData, ServiceBinding, A, AAAA, CNAME, HTTPS, IN, OPT, SVCB, TXT,
+};
+use crate::error::Error;
+use crate::url::dns_name;
+
+/// QR: the message is a response
+const QR: u16 = 0x8000;
+/// TC: the response was truncated
+const TC: u16 = 0x0200;
+/// RD: recursion desired
+const RD: u16 = 0x0100;
+/// EDNS option carrying padding (RFC 7830)
+const PADDING: u16 = 12;
+/// Bytes of a name on the wire at most (RFC 1035 §3.1)
+const MAX_NAME: usize = 255;
+/// CNAMEs followed from the question's name to its addresses
+const MAX_CNAME_CHAIN: usize = 16;
+
+/// UDP payload size a query advertises by default: what fits an unfragmented
+/// packet on most paths (DNS Flag Day 2020).
+pub const DEFAULT_UDP_SIZE: u16 = 1232;
+
+const TRUNCATED: Error = Error::Dns("message truncated");
+const MALFORMED: Error = Error::Dns("RDATA does not match its length or type");
+
+/// An entry of the question section.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Question {
+    /// Name asked about, without the trailing dot
+    pub name: String,
+    pub record_type: u16,
+    pub class: u16,
+}
+
+/// The EDNS(0) OPT pseudo-record of a message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Edns {
+    /// Largest UDP payload the sender reassembles
+    pub udp_size: u16,
+    /// Upper 8 bits of the 12-bit response code
+    pub extended_rcode: u8,
+    pub version: u8,
+    /// DO: DNSSEC records wanted
+    pub dnssec_ok: bool,
+    /// Options as (code, data), padding included
+    pub options: Vec<(u16, Vec<u8>)>,
+}
+
+impl Default for Edns {
+    fn default() -> Self {
+        Edns {
+            udp_size: DEFAULT_UDP_SIZE,
+            extended_rcode: 0,
+            version: 0,
+            dnssec_ok: false,
+            options: Vec::new(),
+        }
+    }
+}
+
+/// A DNS query or response. `encode` writes names uncompressed; `decode` follows
+/// compression pointers, and takes the OPT record out into `edns`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DnsMessage {
+    /// 0 in DoH queries (RFC 8484 §4.1), whose responses are matched by the HTTP
+    /// exchange instead
+    pub id: u16,
+    /// QR, Opcode, AA, TC, RD, RA, Z, AD, CD and the low 4 bits of RCODE
+    pub flags: u16,
+    pub questions: Vec<Question>,
+    pub answers: Vec<Record>,
+    pub authorities: Vec<Record>,
+    /// Additional records, the OPT record aside
+    pub additionals: Vec<Record>,
+    pub edns: Option<Edns>,
+}
+
+impl DnsMessage {
+    /// A recursive query for `record_type` records of `name`, which is sent in the
+    /// ASCII form of `domain_to_ascii` and must fit DNS: labels of 1 to 63 bytes,
+    /// 253 in all. ID 0, with EDNS(0) advertising `DEFAULT_UDP_SIZE`.
+    pub fn query(name: &str, record_type: u16) -> Result<DnsMessage, Error> {
+        let name = dns_name(name.strip_suffix('.').unwrap_or(name)).ok_or_else(|| {
+            Error::InvalidOption {
+                option: "name",
+                value: name.to_string(),
+            }
+        })?;
+        Ok(DnsMessage {
+            flags: RD,
+            questions: vec![Question {
+                name,
+                record_type,
+                class: IN,
+            }],
+            edns: Some(Edns::default()),
+            ..DnsMessage::default()
+        })
+    }
+
+    pub fn is_response(&self) -> bool {
+        self.flags & QR != 0
+    }
+
+    /// Whether the responder left records out for lack of room (TC).
+    pub fn truncated(&self) -> bool {
+        self.flags & TC != 0
+    }
+
+    /// The 12-bit response code: its low 4 bits from the header, the rest from
+    /// EDNS. 0 is NOERROR, 3 NXDOMAIN; see `rcode_name`.
+    pub fn rcode(&self) -> u16 {
+        let extended = self.edns.as_ref().map_or(0, |edns| edns.extended_rcode);
+        (extended as u16) << 4 | (self.flags & 0xf)
+    }
+
+    /// Addresses of the first question's name among the answers, following its
+    /// CNAME chain: A and AAAA records in the order they came, each with the
+    /// smallest TTL along the way.
+    pub fn addresses(&self) -> Vec<(IpAddr, u32)> {
+        let Some(question) = self.questions.first() else {
+            return Vec::new();
+        };
+        let mut name = question.name.as_str();
+        let mut ttl = u32::MAX;
+        for _ in 0..=MAX_CNAME_CHAIN {
+            let mut addresses = Vec::new();
+            let mut alias = None;
+            for record in &self.answers {
+                if !record.name.eq_ignore_ascii_case(name) {
+                    continue;
+                }
+                match &record.data {
+                    RecordData::A(ip) => addresses.push((IpAddr::V4(*ip), record.ttl.min(ttl))),
+                    RecordData::Aaaa(ip) => addresses.push((IpAddr::V6(*ip), record.ttl.min(ttl))),
+                    RecordData::Cname(target) => alias = alias.or(Some((target, record.ttl))),
+                    _ => {}
+                }
+            }
+            if !addresses.is_empty() {
+                return addresses;
+            }
+            let Some((target, alias_ttl)) = alias else {
+                break;
+            };
+            name = target;
+            ttl = ttl.min(alias_ttl);
+        }
+        Vec::new()
+    }
+
+    /// The message in wire format. Fails on a name that does not fit DNS or a
+    /// TXT string over 255 bytes.
+    pub fn encode(&self) -> Result<Vec<u8>, Error> {
+        let mut out = Vec::with_capacity(512);
+        out.extend_from_slice(&self.id.to_be_bytes());
+        out.extend_from_slice(&self.flags.to_be_bytes());
+        let additionals = self.additionals.len() + self.edns.is_some() as usize;
+        for count in [
+            self.questions.len(),
+            self.answers.len(),
+            self.authorities.len(),
+            additionals,
+        ] {
+            let count = u16::try_from(count).map_err(|_| Error::Dns("too many records"))?;
+            out.extend_from_slice(&count.to_be_bytes());
+        }
+        for question in &self.questions {
+            write_name(&mut out, &question.name)?;
+            out.extend_from_slice(&question.record_type.to_be_bytes());
+            out.extend_from_slice(&question.class.to_be_bytes());
+        }
+        for record in self
+            .answers
+            .iter()
+            .chain(&self.authorities)
+            .chain(&self.additionals)
+        {
+            write_record(&mut out, record)?;
+        }
+        if let Some(edns) = &self.edns {
+            write_opt(&mut out, edns)?;
+        }
+        Ok(out)
+    }
+
+    /// `encode`, with an EDNS padding option that brings the length to a multiple
+    /// of `block` bytes (RFC 7830; RFC 8467 recommends 128 for queries), so the
+    /// length tells an observer less about the name. Adds EDNS if the message has
+    /// none, and replaces any padding it has.
+    pub fn encode_padded(&self, block: usize) -> Result<Vec<u8>, Error> {
+        let mut message = self.clone();
+        let edns = message.edns.get_or_insert_with(Edns::default);
+        edns.options.retain(|&(code, _)| code != PADDING);
+        if block == 0 {
+            return message.encode();
+        }
+        // The option's own code and length count too
+        let unpadded = message.encode()?.len() + 4;
+        let padding = (block - unpadded % block) % block;
+        if let Some(edns) = &mut message.edns {
+            edns.options.push((PADDING, vec![0; padding]));
+        }
+        message.encode()
+    }
+
+    /// Parse a message in wire format, e.g. the body of a DoH response.
+    pub fn decode(data: &[u8]) -> Result<DnsMessage, Error> {
+        let mut reader = Reader { data, pos: 0 };
+        let id = reader.u16()?;
+        let flags = reader.u16()?;
+        let counts = [reader.u16()?, reader.u16()?, reader.u16()?, reader.u16()?];
+        let mut message = DnsMessage {
+            id,
+            flags,
+            ..DnsMessage::default()
+        };
+        for _ in 0..counts[0] {
+            let name = reader.name()?;
+            message.questions.push(Question {
+                name,
+                record_type: reader.u16()?,
+                class: reader.u16()?,
+            });
+        }
+        for (section, &count) in counts.iter().enumerate().skip(1) {
+            for _ in 0..count {
+                let name = reader.name()?;
+                let record_type = reader.u16()?;
+                let class = reader.u16()?;
+                let ttl = reader.u32()?;
+                let len = reader.u16()? as usize;
+                let end = reader.pos + len;
+                if end > data.len() {
+                    return Err(TRUNCATED);
+                }
+                if record_type == OPT {
+                    if section != 3 || message.edns.is_some() {
+                        return Err(Error::Dns("misplaced or repeated OPT record"));
+                    }
+                    message.edns = Some(read_opt(class, ttl, &data[reader.pos..end])?);
+                    reader.pos = end;
+                    continue;
+                }
+                let data = reader.record_data(record_type, end)?;
+                let record = Record {
+                    name,
+                    class,
+                    ttl,
+                    data,
+                };
+                match section {
+                    1 => message.answers.push(record),
+                    2 => message.authorities.push(record),
+                    _ => message.additionals.push(record),
+                }
+            }
+        }
+        Ok(message)
+    }
+}
+
+/// Mnemonic of a response code (RFC 6895 §2.3), e.g. "NXDOMAIN" for 3.
+pub fn rcode_name(rcode: u16) -> &'static str {
+    match rcode {
+        0 => "NOERROR",
+        1 => "FORMERR",
+        2 => "SERVFAIL",
+        3 => "NXDOMAIN",
+        4 => "NOTIMP",
+        5 => "REFUSED",
+        6 => "YXDOMAIN",
+        7 => "YXRRSET",
+        8 => "NXRRSET",
+        9 => "NOTAUTH",
+        10 => "NOTZONE",
+        16 => "BADVERS",
+        _ => "unassigned",
+    }
+}
+
+/// Write `name` as labels. "\DDD" and "\X" escapes, as `decode` produces them for
+/// bytes that are not printable or are '.', stand for one byte of a label.
+fn write_name(out: &mut Vec<u8>, name: &str) -> Result<(), Error> {
+    const INVALID: Error = Error::Dns("name does not fit DNS");
+    let start = out.len();
+    let name = match name.strip_suffix('.') {
+        Some(stripped) if !stripped.ends_with('\\') => stripped,
+        _ => name,
+    };
+    let mut bytes = name.bytes();
+    let mut label = Vec::new();
+    while !name.is_empty() {
+        let next = bytes.next();
+        match next {
+            Some(b'\\') => {
+                let escaped = bytes.next().ok_or(INVALID)?;
+                if !escaped.is_ascii_digit() {
+                    label.push(escaped);
+                    continue;
+                }
+                let digits = [
+                    escaped,
+                    bytes.next().ok_or(INVALID)?,
+                    bytes.next().ok_or(INVALID)?,
+                ];
+                let value = std::str::from_utf8(&digits)
+                    .ok()
+                    .and_then(|d| d.parse().ok());
+                label.push(value.ok_or(INVALID)?);
+            }
+            Some(b'.') | None => {
+                if label.is_empty() || label.len() > 63 {
+                    return Err(INVALID);
+                }
+                out.push(label.len() as u8);
+                out.append(&mut label);
+                if next.is_none() {
+                    break;
+                }
+            }
+            Some(b) => label.push(b),
+        }
+    }
+    out.push(0);
+    if out.len() - start > MAX_NAME {
+        return Err(INVALID);
+    }
+    Ok(())
+}
+
+fn write_record(out: &mut Vec<u8>, record: &Record) -> Result<(), Error> {
+    write_name(out, &record.name)?;
+    out.extend_from_slice(&record.record_type().to_be_bytes());
+    out.extend_from_slice(&record.class.to_be_bytes());
+    out.extend_from_slice(&record.ttl.to_be_bytes());
+    let len_at = out.len();
+    out.extend_from_slice(&[0, 0]);
+    match &record.data {
+        RecordData::A(ip) => out.extend_from_slice(&ip.octets()),
+        RecordData::Aaaa(ip) => out.extend_from_slice(&ip.octets()),
+        RecordData::Cname(target) => write_name(out, target)?,
+        RecordData::Txt(strings) => {
+            for string in strings {
+                let len = u8::try_from(string.len())
+                    .map_err(|_| Error::Dns("TXT string longer than 255 bytes"))?;
+                out.push(len);
+                out.extend_from_slice(string);
+            }
+        }
+        RecordData::Svcb(binding) | RecordData::Https(binding) => {
+            out.extend_from_slice(&binding.priority.to_be_bytes());
+            write_name(out, &binding.target)?;
+            for (key, value) in &binding.params {
+                out.extend_from_slice(&key.to_be_bytes());
+                write_length(out, value.len())?;
+                out.extend_from_slice(value);
+            }
+        }
+        RecordData::Other { data, .. } => out.extend_from_slice(data),
+    }
+    let len = u16::try_from(out.len() - len_at - 2).map_err(|_| Error::Dns("RDATA too long"))?;
+    out[len_at..len_at + 2].copy_from_slice(&len.to_be_bytes());
+    Ok(())
+}
+
+fn write_opt(out: &mut Vec<u8>, edns: &Edns) -> Result<(), Error> {
+    // Root name, type, then the UDP size in place of the class
+    out.extend_from_slice(&[0, 0, OPT as u8]);
+    out.extend_from_slice(&edns.udp_size.to_be_bytes());
+    let ttl = (edns.extended_rcode as u32) << 24
+        | (edns.version as u32) << 16
+        | (edns.dnssec_ok as u32) << 15;
+    out.extend_from_slice(&ttl.to_be_bytes());
+    let options: usize = edns.options.iter().map(|(_, data)| 4 + data.len()).sum();
+    write_length(out, options)?;
+    for (code, data) in &edns.options {
+        out.extend_from_slice(&code.to_be_bytes());
+        write_length(out, data.len())?;
+        out.extend_from_slice(data);
+    }
+    Ok(())
+}
+
+fn write_length(out: &mut Vec<u8>, len: usize) -> Result<(), Error> {
+    let len = u16::try_from(len).map_err(|_| Error::Dns("RDATA too long"))?;
+    out.extend_from_slice(&len.to_be_bytes());
+    Ok(())
+}
+
+fn read_opt(class: u16, ttl: u32, mut data: &[u8]) -> Result<Edns, Error> {
+    let mut options = Vec::new();
+    while !data.is_empty() {
+        let [c0, c1, l0, l1, rest @ ..] = data else {
+            return Err(Error::Dns("malformed EDNS option"));
+        };
+        let len = u16::from_be_bytes([*l0, *l1]) as usize;
+        let value = rest.get(..len).ok_or(Error::Dns("malformed EDNS option"))?;
+        options.push((u16::from_be_bytes([*c0, *c1]), value.to_vec()));
+        data = &rest[len..];
+    }
+    Ok(Edns {
+        udp_size: class,
+        extended_rcode: (ttl >> 24) as u8,
+        version: (ttl >> 16) as u8,
+        dnssec_ok: ttl & 0x8000 != 0,
+        options,
+    })
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        let bytes = self.data.get(self.pos..self.pos + len).ok_or(TRUNCATED)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// A name, following compression pointers. Each pointer must go to before the
+    /// labels that led to it, so a chain of them cannot loop.
+    fn name(&mut self) -> Result<String, Error> {
+        let mut name = String::new();
+        let mut pos = self.pos;
+        let mut segment_start = pos;
+        let mut wire_len = 1;
+        let mut after = None;
+        loop {
+            let len = *self.data.get(pos).ok_or(TRUNCATED)? as usize;
+            match len & 0xc0 {
+                0x00 if len == 0 => {
+                    pos += 1;
+                    break;
+                }
+                0x00 => {
+                    let label = self.data.get(pos + 1..pos + 1 + len).ok_or(TRUNCATED)?;
+                    wire_len += 1 + len;
+                    if wire_len > MAX_NAME {
+                        return Err(Error::Dns("name longer than 255 bytes"));
+                    }
+                    if !name.is_empty() {
+                        name.push('.');
+                    }
+                    for &b in label {
+                        match b {
+                            b'.' | b'\\' => {
+                                name.push('\\');
+                                name.push(b as char);
+                            }
+                            b if b.is_ascii_graphic() => name.push(b as char),
+                            b => name.push_str(&format!("\\{:03}", b)),
+                        }
+                    }
+                    pos += 1 + len;
+                }
+                0xc0 => {
+                    let low = *self.data.get(pos + 1).ok_or(TRUNCATED)?;
+                    let target = ((len & 0x3f) << 8) | low as usize;
+                    if target >= segment_start {
+                        return Err(Error::Dns("compression pointer does not point backward"));
+                    }
+                    after.get_or_insert(pos + 2);
+                    pos = target;
+                    segment_start = target;
+                }
+                _ => return Err(Error::Dns("unsupported label type")),
+            }
+        }
+        self.pos = after.unwrap_or(pos);
+        if name.is_empty() {
+            name.push('.');
+        }
+        Ok(name)
+    }
+
+    /// RDATA of `record_type` ending at `end`.
+    fn record_data(&mut self, record_type: u16, end: usize) -> Result<RecordData, Error> {
+        let start = self.pos;
+        let data = match record_type {
+            A => {
+                let octets: [u8; 4] = self.bytes(end - start)?.try_into().map_err(|_| MALFORMED)?;
+                RecordData::A(Ipv4Addr::from(octets))
+            }
+            AAAA => {
+                let octets: [u8; 16] =
+                    self.bytes(end - start)?.try_into().map_err(|_| MALFORMED)?;
+                RecordData::Aaaa(Ipv6Addr::from(octets))
+            }
+            CNAME => RecordData::Cname(self.name()?),
+            TXT => {
+                let mut strings = Vec::new();
+                while self.pos < end {
+                    let len = self.bytes(1)?[0] as usize;
+                    strings.push(self.bytes(len)?.to_vec());
+                }
+                RecordData::Txt(strings)
+            }
+            SVCB | HTTPS => {
+                let priority = self.u16()?;
+                let target = self.name()?;
+                let mut params = Vec::new();
+                while self.pos < end {
+                    let key = self.u16()?;
+                    let len = self.u16()? as usize;
+                    params.push((key, self.bytes(len)?.to_vec()));
+                }
+                let binding = ServiceBinding {
+                    priority,
+                    target,
+                    params,
+                };
+                match record_type {
+                    SVCB => RecordData::Svcb(binding),
+                    _ => RecordData::Https(binding),
+                }
+            }
+            _ => RecordData::Other {
+                record_type,
+                data: self.bytes(end - start)?.to_vec(),
+            },
+        };
+        if self.pos != end {
+            return Err(MALFORMED);
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_with_edns_and_padding() {
+        let query = DnsMessage::query("Example.COM.", A).unwrap();
+        let encoded = query.encode().unwrap();
+        let mut expected = vec![0, 0, 1, 0, 0, 1, 0, 0, 0, 0, 0, 1];
+        expected.extend(b"\x07example\x03com\x00\x00\x01\x00\x01");
+        // OPT: root, type 41, UDP size 1232, no flags or options
+        expected.extend([0, 0, 41, 0x04, 0xd0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(encoded, expected);
+        assert_eq!(DnsMessage::decode(&encoded).unwrap(), query);
+
+        let padded = query.encode_padded(128).unwrap();
+        assert_eq!(padded.len(), 128);
+        let decoded = DnsMessage::decode(&padded).unwrap();
+        let edns = decoded.edns.unwrap();
+        assert_eq!(edns.options, [(PADDING, vec![0; 128 - expected.len() - 4])]);
+        assert_eq!(query.encode_padded(0).unwrap(), expected);
+
+        assert!(DnsMessage::query("a..example", A).is_err());
+        assert!(DnsMessage::query(&format!("{}.example", "a".repeat(64)), A).is_err());
+    }
+
+    #[test]
+    fn compressed_response_with_a_cname_chain() {
+        let mut response = vec![0, 0, 0x81, 0x80, 0, 1, 0, 3, 0, 0, 0, 0];
+        // Question at 12: www.example.com A IN
+        response.extend(b"\x03www\x07example\x03com\x00\x00\x01\x00\x01");
+        // www -> cdn.example.com (pointer to "example.com" at 16), TTL 300
+        response.extend([0xc0, 12, 0, 5, 0, 1, 0, 0, 0x01, 0x2c, 0, 6]);
+        response.extend(b"\x03cdn\xc0\x10");
+        // cdn.example.com (pointer into the CNAME's RDATA at 45) A, TTL 60
+        response.extend([0xc0, 45, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 192, 0, 2, 7]);
+        // An unrelated name's address is not one of ours
+        response.extend([0xc0, 16, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 192, 0, 2, 9]);
+        let message = DnsMessage::decode(&response).unwrap();
+        assert!(message.is_response() && !message.truncated());
+        assert_eq!(message.rcode(), 0);
+        assert_eq!(
+            message.answers[0].data,
+            RecordData::Cname("cdn.example.com".into())
+        );
+        assert_eq!(message.answers[1].name, "cdn.example.com");
+        assert_eq!(
+            message.addresses(),
+            [(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 7)), 60)]
+        );
+
+        // A pointer to itself, or past its own name, is a loop
+        let mut looping = response[..12].to_vec();
+        looping[5] = 1;
+        looping[7] = 0;
+        looping.extend([0xc0, 12, 0, 1, 0, 1]);
+        assert!(DnsMessage::decode(&looping).is_err());
+        assert_eq!(DnsMessage::decode(&response[..40]), Err(TRUNCATED));
+    }
+
+    #[test]
+    fn record_types_round_trip() {
+        let binding = ServiceBinding {
+            priority: 1,
+            target: ".".into(),
+            params: vec![
+                (1, b"\x02h3\x02h2".to_vec()),
+                (3, vec![0x20, 0xfb]),
+                (4, vec![192, 0, 2, 1, 192, 0, 2, 2]),
+                (5, b"ech".to_vec()),
+                (
+                    6,
+                    [0x20, 0x01, 0x0d, 0xb8]
+                        .into_iter()
+                        .chain([0; 11])
+                        .chain([1])
+                        .collect(),
+                ),
+            ],
+        };
+        let mut message = DnsMessage::query("example.com", HTTPS).unwrap();
+        message.flags |= QR | 3;
+        message.edns.as_mut().unwrap().extended_rcode = 1;
+        message.answers = vec![
+            Record::new("example.com", 3600, RecordData::Https(binding.clone())),
+            Record::new(
+                "example.com",
+                60,
+                RecordData::Txt(vec![b"v=spf1 -all".to_vec(), b"".to_vec()]),
+            ),
+            Record::new(
+                "example.com",
+                60,
+                RecordData::Aaaa("2001:db8::1".parse().unwrap()),
+            ),
+            Record::new(
+                "a\\.b\\000.example",
+                60,
+                RecordData::Other {
+                    record_type: 99,
+                    data: vec![1, 2],
+                },
+            ),
+        ];
+        let decoded = DnsMessage::decode(&message.encode().unwrap()).unwrap();
+        assert_eq!(decoded, message);
+        // The upper bits of the code come from EDNS
+        assert_eq!(decoded.rcode(), 19);
+        assert_eq!(rcode_name(3), "NXDOMAIN");
+
+        assert_eq!(binding.alpn(), ["h3", "h2"]);
+        assert_eq!(binding.port(), Some(8443));
+        assert_eq!(
+            binding.ipv4_hints(),
+            [Ipv4Addr::new(192, 0, 2, 1), Ipv4Addr::new(192, 0, 2, 2)]
+        );
+        assert_eq!(
+            binding.ipv6_hints(),
+            ["2001:db8::1".parse::<Ipv6Addr>().unwrap()]
+        );
+        assert_eq!(binding.ech_config_list(), Some(&b"ech"[..]));
+        assert!(!binding.is_alias());
+
+        message.answers = vec![Record::new(
+            "example.com",
+            60,
+            RecordData::Txt(vec![vec![b'x'; 256]]),
+        )];
+        assert!(message.encode().is_err());
+    }
+}
@@ -0,0 +1,22 @@
+//! DNS messages in wire format (RFC 1035), DNS over HTTPS (RFC 8484) and DNS over
+//! TLS (RFC 7858), so names can be resolved over the same connections as
+//! everything else rather than by the platform's resolver.
+//!
+//! `DnsMessage` encodes queries and decodes responses: A, AAAA, CNAME, TXT, SVCB
+//! and HTTPS records, and EDNS(0) with its UDP size and padding. `DohQuery` wraps
+//! a query in the HTTP request a DoH resolver expects and checks its response;
+//! `StealthFetch::set_doh_resolver` does both for every connection it opens.
+//! `DotClient` frames queries for a `TlsConnection` to a resolver's port 853,
+//! where DoH endpoints are blocked.
+
+mod doh;
+mod dot;
+mod message;
+mod record;
+
+pub use doh::{DohQuery, DNS_MESSAGE, MAX_MESSAGE_SIZE, QUERY_PADDING_BLOCK};
+pub use dot::{DotClient, DEFAULT_IDLE_TIMEOUT_MS, DOT_ALPN, DOT_PORT};
+pub use message::{rcode_name, DnsMessage, Edns, Question, DEFAULT_UDP_SIZE};
+pub use record::{
+    record_type, Record, RecordData, ServiceBinding, A, AAAA, CNAME, HTTPS, IN, OPT, SVCB, TXT,
+};
@@ -0,0 +1,160 @@
+//! Resource records, with the RDATA of the types `DnsMessage` understands.
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+pub const A: u16 = 1;
+pub const CNAME: u16 = 5;
+pub const TXT: u16 = 16;
+pub const AAAA: u16 = 28;
+/// EDNS(0) pseudo-record; `DnsMessage::edns`, never a `Record`
+pub const OPT: u16 = 41;
+pub const SVCB: u16 = 64;
+pub const HTTPS: u16 = 65;
+/// Class IN, the only one in use
+pub const IN: u16 = 1;
+
+/// SvcParamKeys (RFC 9460 §14.3.2)
+const KEY_ALPN: u16 = 1;
+const KEY_PORT: u16 = 3;
+const KEY_IPV4HINT: u16 = 4;
+const KEY_ECH: u16 = 5;
+const KEY_IPV6HINT: u16 = 6;
+
+/// Record type named `name` ("A", "aaaa", …), for the types above but OPT.
+pub fn record_type(name: &str) -> Option<u16> {
+    let record_type = match name.to_ascii_uppercase().as_str() {
+        "A" => A,
+        "CNAME" => CNAME,
+        "TXT" => TXT,
+        "AAAA" => AAAA,
+        "SVCB" => SVCB,
+        "HTTPS" => HTTPS,
+        _ => return None,
+    };
+    Some(record_type)
+}
+
+/// A resource record of a message's answer, authority or additional section.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    /// Owner name, without the trailing dot; "." for the root
+    pub name: String,
+    pub class: u16,
+    /// Seconds it may be cached for
+    pub ttl: u32,
+    pub data: RecordData,
+}
+
+impl Record {
+    /// A record of class IN.
+    pub fn new(name: &str, ttl: u32, data: RecordData) -> Record {
+        Record {
+            name: name.to_string(),
+            class: IN,
+            ttl,
+            data,
+        }
+    }
+
+    pub fn record_type(&self) -> u16 {
+        match &self.data {
+            RecordData::A(_) => A,
+            RecordData::Aaaa(_) => AAAA,
+            RecordData::Cname(_) => CNAME,
+            RecordData::Txt(_) => TXT,
+            RecordData::Svcb(_) => SVCB,
+            RecordData::Https(_) => HTTPS,
+            RecordData::Other { record_type, .. } => *record_type,
+        }
+    }
+}
+
+/// RDATA by record type.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RecordData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    /// Canonical name the owner is an alias of
+    Cname(String),
+    /// Character-strings of up to 255 bytes each
+    Txt(Vec<Vec<u8>>),
+    Svcb(ServiceBinding),
+    Https(ServiceBinding),
+    /// A type not decoded, with its RDATA as it came
+    Other {
+        record_type: u16,
+        data: Vec<u8>,
+    },
+}
+
+/// RDATA of SVCB and HTTPS records (RFC 9460): where and how to reach a service.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServiceBinding {
+    /// 0 for AliasMode, otherwise the preference of this endpoint (lower first)
+    pub priority: u16,
+    /// Target name; "." stands for the owner itself
+    pub target: String,
+    /// SvcParams as (key, value), in ascending key order on the wire
+    pub params: Vec<(u16, Vec<u8>)>,
+}
+
+impl ServiceBinding {
+    /// Whether it only points to another name (`target`) without parameters.
+    pub fn is_alias(&self) -> bool {
+        self.priority == 0
+    }
+
+    /// Value of SvcParam `key`.
+    pub fn param(&self, key: u16) -> Option<&[u8]> {
+        self.params
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, value)| &value[..])
+    }
+
+    /// ALPN protocol IDs the endpoint supports, e.g. ["h3", "h2"].
+    pub fn alpn(&self) -> Vec<String> {
+        let mut alpn = Vec::new();
+        let mut rest = self.param(KEY_ALPN).unwrap_or_default();
+        while let Some((&len, tail)) = rest.split_first() {
+            let Some(id) = tail.get(..len as usize) else {
+                break;
+            };
+            alpn.push(String::from_utf8_lossy(id).into_owned());
+            rest = &tail[len as usize..];
+        }
+        alpn
+    }
+
+    /// Port to connect to instead of the scheme's default.
+    pub fn port(&self) -> Option<u16> {
+        match self.param(KEY_PORT)? {
+            &[high, low] => Some(u16::from_be_bytes([high, low])),
+            _ => None,
+        }
+    }
+
+    /// IPv4 addresses of the target, usable before its A records arrive.
+    pub fn ipv4_hints(&self) -> Vec<Ipv4Addr> {
+        let hints = self.param(KEY_IPV4HINT).unwrap_or_default();
+        hints
+            .chunks_exact(4)
+            .map(|octets| Ipv4Addr::new(octets[0], octets[1], octets[2], octets[3]))
+            .collect()
+    }
+
+    /// IPv6 addresses of the target, usable before its AAAA records arrive.
+    pub fn ipv6_hints(&self) -> Vec<Ipv6Addr> {
+        let hints = self.param(KEY_IPV6HINT).unwrap_or_default();
+        hints
+            .chunks_exact(16)
+            .filter_map(|octets| <[u8; 16]>::try_from(octets).ok())
+            .map(Ipv6Addr::from)
+            .collect()
+    }
+
+    /// ECHConfigList of the endpoint, for `TlsOptions::set_ech_config_list`.
+    pub fn ech_config_list(&self) -> Option<&[u8]> {
+        self.param(KEY_ECH)
+    }
+}
@@ -0,0 +1,334 @@
+use std::fmt;
+
+use wasm_bindgen::JsValue;
+
+/// Errors produced by the HTTP layer.
+/// Messages never include header values, which may carry credentials.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Request method is empty or contains non-token characters
+    InvalidMethod(String),
+    /// Request target is empty or contains whitespace/control characters
+    InvalidTarget,
+    /// Unsupported HTTP version string
+    InvalidVersion(String),
+    /// Header name is empty or contains non-token characters
+    InvalidHeaderName(String),
+    /// Header value contains CR, LF or NUL (carries the header name)
+    InvalidHeaderValue(String),
+    /// Header value uses obsolete line folding (carries the header name)
+    ObsFold(String),
+    /// HTTP/1.1 request without a Host header
+    MissingHost,
+    /// Content-Length header disagrees with the body length
+    ContentLengthMismatch { declared: String, actual: usize },
+    /// Response status line is malformed or not HTTP/1.x
+    InvalidStatusLine,
+    /// An interim (1xx) response declares a body
+    InvalidInformational,
+    /// Response header line has no colon or contains stray control bytes
+    InvalidHeaderLine,
+    /// Status line plus headers exceeded the configured maximum
+    HeadersTooLarge { limit: usize },
+    /// Content-Length is not a number, or repeated with different values
+    InvalidContentLength,
+    /// Connection closed before the response head was complete
+    IncompleteHead,
+    /// Connection closed before the Content-Length body was complete
+    IncompleteBody { remaining: u64 },
+    /// Chunk-size line is not hex, overflows, or has garbage before its extensions
+    InvalidChunkSize,
+    /// Chunk-size line (with extensions) exceeded the configured maximum
+    ChunkLineTooLong { limit: usize },
+    /// Chunk payload not followed by CRLF
+    InvalidChunkTerminator,
+    /// Connection closed before the terminating chunk and trailers
+    IncompleteChunkedBody,
+    /// Content-coded body is corrupt (bad header, data or checksum)
+    InvalidEncoding { coding: &'static str },
+    /// Content-coded body ended in the middle of the encoded stream
+    TruncatedEncoding { coding: &'static str },
+    /// Content-Encoding lists a coding that is not compiled in (carries the coding list)
+    UnsupportedEncoding(String),
+    /// Charset label that is not compiled in
+    UnsupportedCharset(String),
+    /// Decoded body exceeded the configured maximum
+    DecodedTooLarge { limit: u64 },
+    /// Encoded stream asks for a larger decoding window than allowed
+    WindowTooLarge {
+        coding: &'static str,
+        requested: u64,
+        limit: u64,
+    },
+    /// Header names and values passed separately have different lengths
+    HeaderCountMismatch,
+    /// HTTP/2 pseudo-header that is unknown, repeated, placed after a regular
+    /// field, or required but missing (carries the name)
+    InvalidPseudoHeader(String),
+    /// Connection-specific header, which HTTP/2 forbids (carries the name)
+    ConnectionHeader(String),
+    /// HPACK header block could not be decoded
+    Compression(&'static str),
+    /// HTTP/2 connection error; a GOAWAY carrying `code` has been queued
+    Http2 { code: u32, reason: &'static str },
+    /// No such HTTP/2 stream, or it has already finished
+    UnknownStream(u32),
+    /// `HarRecorder` call with an id `start_request` did not return
+    UnknownHarEntry(u32),
+    /// The request side of the HTTP/2 stream has already ended
+    StreamClosed(u32),
+    /// More body bytes released than were received and not yet released
+    ReleaseTooLarge { stream_id: u32, unreleased: u32 },
+    /// The HTTP/2 connection is shutting down; open a new one
+    GoingAway,
+    /// Unrecognized value for a string-valued option
+    InvalidOption { option: &'static str, value: String },
+    /// Not an absolute http(s) or ws(s) URL with a host
+    InvalidUrl,
+    /// `CookieJar::import_json` input is malformed (carries the offending field)
+    InvalidCookieJar(&'static str),
+    /// `HstsStore::import_json` input is malformed (carries the offending field)
+    InvalidHstsStore(&'static str),
+    /// `CachedResponse::import_json` input is malformed (carries the offending field)
+    InvalidCachedResponse(&'static str),
+    /// A 304 validated an ETag other than the stored response's
+    NotModifiedMismatch,
+    /// `Http1Connection::send` before the previous response was fully read
+    RequestInFlight,
+    /// `Http1Connection::send` on a connection that was closed, failed or not kept alive
+    ConnectionNotReusable,
+    /// More redirects than `RedirectPolicy` allows
+    TooManyRedirects { limit: usize },
+    /// A redirect leads back to a request already made
+    RedirectLoop,
+    /// Redirect from https to http with downgrades disallowed
+    InsecureRedirect,
+    /// WebSocket upgrade could not be sent, or its 101 response is invalid
+    WebSocketHandshake(&'static str),
+    /// The server answered the WebSocket upgrade with another status
+    WebSocketRejected { status: u16 },
+    /// WebSocket connection failed; a close frame carrying `code` has been queued
+    WebSocket { code: u16, reason: &'static str },
+    /// The WebSocket connection is not open (yet, or any more)
+    WebSocketClosed,
+    /// A CONNECT tunnel was used out of order (see `ProxyTunnel`)
+    Tunnel(&'static str),
+    /// A SOCKS5 handshake failed or was used out of order (see `Socks5Client`)
+    Socks5(&'static str),
+    /// A SOCKS4 handshake failed or was used out of order (see `Socks4Client`)
+    Socks4(&'static str),
+    /// A DNS message is malformed, or a DNS-over-HTTPS response is not one (see `dns`)
+    Dns(&'static str),
+    /// A server-sent event block exceeded the configured maximum
+    SseEventTooLarge { limit: usize },
+    /// A multipart body was built or pulled out of order
+    Multipart(&'static str),
+    /// A streamed multipart part got more or less data than its declared size
+    MultipartSizeMismatch { declared: u64, actual: u64 },
+    /// A streamed request body was written out of order or against its framing
+    Upload(&'static str),
+    /// The server responded before the streamed request body was finished
+    UploadAborted { status: u16 },
+    /// A body set to be compressed already has this Content-Encoding
+    AlreadyEncoded(String),
+    /// Credentials that cannot be sent, or a credentials callback that failed
+    Auth(&'static str),
+    /// A field that may not appear in trailers was sent or declared as one
+    ForbiddenTrailer(String),
+    /// A response to a resumed download does not continue it (see `ResumableDownload`)
+    Range(&'static str),
+    /// A response size limit tripped: `limit` names it ("header block", "body" or
+    /// "decoded body"), `consumed` is the input bytes read for that part so far
+    LimitExceeded {
+        limit: &'static str,
+        max: u64,
+        consumed: u64,
+    },
+    /// A covered component of an HTTP message signature that is malformed,
+    /// unsupported or repeated (carries the identifier)
+    InvalidComponent(String),
+    /// A covered component the message does not have (carries the identifier)
+    MissingComponent(String),
+    /// A signature label or parameter that cannot be serialized (carries its name)
+    InvalidSignatureParam(&'static str),
+    /// Integrity metadata could not be parsed
+    InvalidIntegrity,
+    /// A response body does not match its digest: `check` is "integrity" (the
+    /// expected metadata), "Content-Digest" or "Repr-Digest"
+    Integrity {
+        check: &'static str,
+        expected: String,
+        computed: String,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidMethod(m) => write!(f, "Invalid method: {:?}", m),
+            Error::InvalidTarget => write!(f, "Invalid request target"),
+            Error::InvalidVersion(v) => write!(f, "Unsupported HTTP version: {:?}", v),
+            Error::InvalidHeaderName(n) => write!(f, "Invalid header name: {:?}", n),
+            Error::InvalidHeaderValue(n) => write!(f, "Invalid value for header {:?}", n),
+            Error::ObsFold(n) => write!(f, "Obsolete line folding in header {:?}", n),
+            Error::MissingHost => write!(f, "HTTP/1.1 request requires a Host header"),
+            Error::ContentLengthMismatch { declared, actual } => write!(
+                f,
+                "Content-Length {:?} does not match body length {}",
+                declared, actual
+            ),
+            Error::InvalidStatusLine => write!(f, "Invalid status line"),
+            Error::InvalidInformational => write!(f, "Informational response with a body"),
+            Error::InvalidHeaderLine => write!(f, "Invalid header line"),
+            Error::HeadersTooLarge { limit } => {
+                write!(f, "Response headers exceed {} bytes", limit)
+            }
+            Error::InvalidContentLength => write!(f, "Invalid Content-Length"),
+            Error::IncompleteHead => write!(f, "Connection closed before response headers ended"),
+            Error::IncompleteBody { remaining } => write!(
+                f,
+                "Connection closed with {} body bytes still expected",
+                remaining
+            ),
+            Error::InvalidChunkSize => write!(f, "Invalid chunk size"),
+            Error::ChunkLineTooLong { limit } => {
+                write!(f, "Chunk size line exceeds {} bytes", limit)
+            }
+            Error::InvalidChunkTerminator => write!(f, "Chunk data not terminated by CRLF"),
+            Error::IncompleteChunkedBody => {
+                write!(f, "Connection closed before the last chunk")
+            }
+            Error::InvalidEncoding { coding } => write!(f, "Corrupt {} body", coding),
+            Error::TruncatedEncoding { coding } => write!(f, "Truncated {} body", coding),
+            Error::UnsupportedEncoding(c) => write!(f, "Unsupported content coding: {:?}", c),
+            Error::UnsupportedCharset(c) => write!(f, "Unsupported charset: {:?}", c),
+            Error::DecodedTooLarge { limit } => {
+                write!(f, "Decoded body exceeds {} bytes", limit)
+            }
+            Error::WindowTooLarge {
+                coding,
+                requested,
+                limit,
+            } => write!(
+                f,
+                "{} window of {} bytes exceeds the {} byte limit",
+                coding, requested, limit
+            ),
+            Error::HeaderCountMismatch => write!(f, "Header names and values differ in length"),
+            Error::InvalidPseudoHeader(n) => write!(f, "Invalid or missing pseudo-header {:?}", n),
+            Error::ConnectionHeader(n) => {
+                write!(
+                    f,
+                    "Connection-specific header {:?} is not allowed in HTTP/2",
+                    n
+                )
+            }
+            Error::Compression(reason) => write!(f, "HPACK decoding error: {}", reason),
+            Error::Http2 { code, reason } => write!(
+                f,
+                "HTTP/2 connection error {}: {}",
+                crate::http2::error_code_name(*code),
+                reason
+            ),
+            Error::UnknownStream(id) => write!(f, "No open HTTP/2 stream {}", id),
+            Error::UnknownHarEntry(id) => write!(f, "No HAR entry {}", id),
+            Error::StreamClosed(id) => write!(f, "HTTP/2 stream {} has already ended", id),
+            Error::ReleaseTooLarge {
+                stream_id,
+                unreleased,
+            } => write!(
+                f,
+                "HTTP/2 stream {} has only {} unreleased bytes",
+                stream_id, unreleased
+            ),
+            Error::GoingAway => write!(f, "HTTP/2 connection is shutting down"),
+            Error::InvalidOption { option, value } => write!(f, "Invalid {}: {:?}", option, value),
+            Error::InvalidUrl => write!(f, "Invalid URL"),
+            Error::InvalidCookieJar(field) => write!(f, "Invalid cookie jar JSON: {}", field),
+            Error::InvalidHstsStore(field) => write!(f, "Invalid HSTS store JSON: {}", field),
+            Error::InvalidCachedResponse(field) => {
+                write!(f, "Invalid cached response JSON: {}", field)
+            }
+            Error::NotModifiedMismatch => write!(
+                f,
+                "304 Not Modified does not match the stored response's ETag"
+            ),
+            Error::RequestInFlight => {
+                write!(f, "Previous response on this connection not fully read")
+            }
+            Error::ConnectionNotReusable => {
+                write!(f, "Connection cannot carry another request")
+            }
+            Error::TooManyRedirects { limit } => write!(f, "More than {} redirects", limit),
+            Error::RedirectLoop => write!(f, "Redirect loop"),
+            Error::InsecureRedirect => write!(f, "Redirect from https to http refused"),
+            Error::WebSocketHandshake(reason) => {
+                write!(f, "WebSocket handshake failed: {}", reason)
+            }
+            Error::WebSocketRejected { status } => {
+                write!(f, "WebSocket upgrade refused with status {}", status)
+            }
+            Error::WebSocket { code, reason } => {
+                write!(f, "WebSocket connection error {}: {}", code, reason)
+            }
+            Error::WebSocketClosed => write!(f, "WebSocket is not open"),
+            Error::Tunnel(reason) => write!(f, "Proxy tunnel: {}", reason),
+            Error::Socks5(reason) => write!(f, "SOCKS5 proxy: {}", reason),
+            Error::Socks4(reason) => write!(f, "SOCKS4 proxy: {}", reason),
+            Error::Dns(reason) => write!(f, "DNS: {}", reason),
+            Error::SseEventTooLarge { limit } => {
+                write!(f, "Server-sent event exceeds {} bytes", limit)
+            }
+            Error::Multipart(reason) => write!(f, "Multipart body error: {}", reason),
+            Error::MultipartSizeMismatch { declared, actual } => write!(
+                f,
+                "Multipart part declared {} bytes but got {}",
+                declared, actual
+            ),
+            Error::Upload(reason) => write!(f, "Request body upload error: {}", reason),
+            Error::UploadAborted { status } => write!(
+                f,
+                "Upload aborted: the server responded with status {}",
+                status
+            ),
+            Error::AlreadyEncoded(coding) => write!(
+                f,
+                "Request body is already encoded as {:?}; not compressing it again",
+                coding
+            ),
+            Error::Auth(reason) => write!(f, "Authentication error: {}", reason),
+            Error::ForbiddenTrailer(n) => write!(f, "Field {:?} is not allowed in trailers", n),
+            Error::Range(reason) => write!(f, "Range response error: {}", reason),
+            Error::LimitExceeded {
+                limit,
+                max,
+                consumed,
+            } => write!(
+                f,
+                "Response {} exceeds {} bytes ({} bytes consumed)",
+                limit, max, consumed
+            ),
+            Error::InvalidComponent(c) => write!(f, "Invalid signature component {}", c),
+            Error::MissingComponent(c) => write!(f, "Signature component {} is missing", c),
+            Error::InvalidSignatureParam(p) => write!(f, "Invalid signature parameter: {}", p),
+            Error::InvalidIntegrity => write!(f, "Invalid integrity metadata"),
+            Error::Integrity {
+                check,
+                expected,
+                computed,
+            } => write!(
+                f,
+                "Body does not match {}: expected {}, computed {}",
+                check, expected, computed
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for JsValue {
+    fn from(e: Error) -> JsValue {
+        wasm_bindgen::JsError::new(&e.to_string()).into()
+    }
+}
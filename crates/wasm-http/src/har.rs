@@ -0,0 +1,772 @@
+//! Recording request/response transactions and exporting them as HAR 1.2
+//! (<http://www.softwareishard.com/blog/har-12-spec/>), for debugging sessions.
+//!
+//! Recording is opt-in: create a `HarRecorder` for a connection, or share one
+//! across every request of a session, and report each transaction's progress
+//! with the caller's clock (milliseconds since the epoch, e.g. `Date.now()`).
+
+use wasm_bindgen::prelude::*;
+
+use crate::base64;
+use crate::error::Error;
+use crate::headers::Headers;
+use crate::http1::{ParserEvent, ResponseEvent};
+use crate::json;
+use crate::url::HttpUrl;
+
+/// Headers whose values are recorded as "[redacted]" unless configured otherwise.
+const DEFAULT_REDACTED: [&str; 4] = [
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+];
+
+/// Credentials, redacted whatever the configured list says.
+const CREDENTIALS: [&str; 2] = ["authorization", "proxy-authorization"];
+
+const REDACTED: &str = "[redacted]";
+
+/// A body's length, and its first bytes when bodies are captured.
+#[derive(Clone, Debug, Default)]
+struct Body {
+    size: u64,
+    captured: Vec<u8>,
+}
+
+impl Body {
+    fn push(&mut self, data: &[u8], limit: usize) {
+        self.size += data.len() as u64;
+        let room = limit.saturating_sub(self.captured.len());
+        self.captured
+            .extend_from_slice(&data[..room.min(data.len())]);
+    }
+
+    /// HAR `text` (plus `encoding` for binary bodies) and a `comment` when cut short.
+    fn write_text(&self, out: &mut String) {
+        match std::str::from_utf8(&self.captured) {
+            Ok(text) => {
+                out.push_str(",\"text\":");
+                json::quote(text, out);
+            }
+            Err(_) => {
+                out.push_str(",\"text\":");
+                json::quote(&base64::encode(&self.captured), out);
+                out.push_str(",\"encoding\":\"base64\"");
+            }
+        }
+        if (self.captured.len() as u64) < self.size {
+            out.push_str(&format!(
+                ",\"comment\":\"truncated to {} of {} bytes\"",
+                self.captured.len(),
+                self.size
+            ));
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Response {
+    status: u16,
+    status_text: String,
+    version: String,
+    headers: Vec<(String, String)>,
+    /// Length of the head on the wire, when it is HTTP/1.x
+    headers_size: Option<u64>,
+    /// Decoded content
+    content: Body,
+    /// Bytes as received, when the caller reported them
+    body_size: Option<u64>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Tls {
+    version: String,
+    cipher_suite: String,
+    alpn: String,
+}
+
+#[derive(Clone, Debug)]
+struct Entry {
+    started: f64,
+    method: String,
+    url: HttpUrl,
+    version: String,
+    headers: Vec<(String, String)>,
+    headers_size: Option<u64>,
+    request_body: Body,
+    response: Option<Response>,
+    connection: String,
+    server_ip: Option<String>,
+    tls: Option<Tls>,
+    /// DNS, connect and TLS handshake durations, -1 when not applicable
+    setup: [f64; 3],
+    sent: Option<f64>,
+    responded: Option<f64>,
+    finished: Option<f64>,
+}
+
+/// Transactions recorded for a HAR export.
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct HarRecorder {
+    entries: Vec<Entry>,
+    /// Lowercased names of headers whose values are not recorded
+    redacted: Vec<String>,
+    max_body_capture: usize,
+}
+
+impl Default for HarRecorder {
+    fn default() -> Self {
+        HarRecorder {
+            entries: Vec::new(),
+            redacted: DEFAULT_REDACTED.iter().map(|n| n.to_string()).collect(),
+            max_body_capture: 0,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl HarRecorder {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> HarRecorder {
+        HarRecorder::default()
+    }
+
+    /// Headers whose values are recorded as "[redacted]" (default Authorization,
+    /// Proxy-Authorization, Cookie and Set-Cookie), replacing the list; the two
+    /// credential headers are redacted regardless. Applies to transactions
+    /// recorded afterwards: redacted values are never stored.
+    pub fn set_redacted_headers(&mut self, names: Vec<String>) {
+        self.redacted = names.iter().map(|n| n.to_ascii_lowercase()).collect();
+    }
+
+    /// Capture up to `limit` bytes of each request and response body (default
+    /// 0: sizes only). Text bodies are exported as is, others in base64.
+    pub fn set_max_body_capture(&mut self, limit: usize) {
+        self.max_body_capture = limit;
+    }
+
+    /// Record a request about to be sent on `connection` (any id the caller
+    /// uses for its connections). Returns the id of the new transaction.
+    #[allow(clippy::too_many_arguments)]
+    pub fn start_request(
+        &mut self,
+        method: &str,
+        url: &str,
+        http_version: &str,
+        names: Vec<String>,
+        values: Vec<String>,
+        connection: &str,
+        started: f64,
+    ) -> Result<u32, Error> {
+        if names.len() != values.len() {
+            return Err(Error::HeaderCountMismatch);
+        }
+        let url = HttpUrl::parse(url)?;
+        let headers: Vec<(String, String)> = names.into_iter().zip(values).collect();
+        let headers_size = http1_head_size(
+            &format!("{} {} {}", method, url.target(), http_version),
+            http_version,
+            &headers,
+        );
+        self.entries.push(Entry {
+            started,
+            method: method.to_string(),
+            url,
+            version: http_version.to_string(),
+            headers: self.redact(headers),
+            headers_size,
+            request_body: Body::default(),
+            response: None,
+            connection: connection.to_string(),
+            server_ip: None,
+            tls: None,
+            setup: [-1.0; 3],
+            sent: None,
+            responded: None,
+            finished: None,
+        });
+        Ok(self.entries.len() as u32 - 1)
+    }
+
+    /// Record request body bytes as they are written.
+    pub fn add_request_body(&mut self, id: u32, data: &[u8]) -> Result<(), Error> {
+        let limit = self.max_body_capture;
+        self.entry(id)?.request_body.push(data, limit);
+        Ok(())
+    }
+
+    /// The request, body included, has been written.
+    pub fn mark_sent(&mut self, id: u32, at: f64) -> Result<(), Error> {
+        self.entry(id)?.sent = Some(at);
+        Ok(())
+    }
+
+    /// Durations in milliseconds of the DNS lookup, TCP connect and TLS handshake
+    /// that preceded the request; -1 for those that did not happen (e.g. on a
+    /// reused connection).
+    pub fn set_connection_timings(
+        &mut self,
+        id: u32,
+        dns: f64,
+        connect: f64,
+        ssl: f64,
+    ) -> Result<(), Error> {
+        self.entry(id)?.setup = [dns, connect, ssl];
+        Ok(())
+    }
+
+    /// Address the connection went to, exported as `serverIPAddress`.
+    pub fn set_server_ip(&mut self, id: u32, address: &str) -> Result<(), Error> {
+        self.entry(id)?.server_ip = Some(address.to_string());
+        Ok(())
+    }
+
+    /// TLS details of the connection, exported as `_tls`: protocol version (e.g.
+    /// "TLSv1.3"), cipher suite name and negotiated ALPN protocol, any of which
+    /// may be empty.
+    pub fn set_tls(
+        &mut self,
+        id: u32,
+        version: &str,
+        cipher_suite: &str,
+        alpn: &str,
+    ) -> Result<(), Error> {
+        self.entry(id)?.tls = Some(Tls {
+            version: version.to_string(),
+            cipher_suite: cipher_suite.to_string(),
+            alpn: alpn.to_string(),
+        });
+        Ok(())
+    }
+
+    /// Record the final response head.
+    #[allow(clippy::too_many_arguments)]
+    pub fn record_response(
+        &mut self,
+        id: u32,
+        status: u16,
+        status_text: &str,
+        http_version: &str,
+        names: Vec<String>,
+        values: Vec<String>,
+        at: f64,
+    ) -> Result<(), Error> {
+        if names.len() != values.len() {
+            return Err(Error::HeaderCountMismatch);
+        }
+        let headers: Vec<(String, String)> = names.into_iter().zip(values).collect();
+        let status_line = format!("{} {} {}", http_version, status, status_text);
+        let headers_size = http1_head_size(&status_line, http_version, &headers);
+        let headers = self.redact(headers);
+        let entry = self.entry(id)?;
+        entry.response = Some(Response {
+            status,
+            status_text: status_text.to_string(),
+            version: http_version.to_string(),
+            headers,
+            headers_size,
+            content: Body::default(),
+            body_size: None,
+        });
+        entry.responded = Some(at);
+        Ok(())
+    }
+
+    /// Record decoded response body bytes as they are delivered.
+    pub fn add_response_body(&mut self, id: u32, data: &[u8]) -> Result<(), Error> {
+        let limit = self.max_body_capture;
+        if let Some(response) = &mut self.entry(id)?.response {
+            response.content.push(data, limit);
+        }
+        Ok(())
+    }
+
+    /// The response is complete. `body_size` is the number of body bytes
+    /// received before content decoding (e.g. `encoded_body_bytes()`), or a
+    /// negative number if unknown.
+    pub fn finish(&mut self, id: u32, body_size: f64, at: f64) -> Result<(), Error> {
+        let entry = self.entry(id)?;
+        if let Some(response) = &mut entry.response {
+            response.body_size = (body_size >= 0.0).then_some(body_size as u64);
+        }
+        entry.finished = Some(at);
+        Ok(())
+    }
+
+    /// Feed an HTTP/1.1 parser event of transaction `id`, before taking its
+    /// data: heads, body bytes and completion are recorded as by
+    /// `record_response`, `add_response_body` and `finish`. The received body
+    /// size is only known when the response has no Content-Encoding.
+    pub fn record_event(&mut self, id: u32, event: &ParserEvent, at: f64) -> Result<(), Error> {
+        self.record_response_event(id, event.inner(), at)
+    }
+
+    /// Transactions recorded so far.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Every transaction as a HAR 1.2 document. Transactions without a response
+    /// are exported with status 0, as browsers do for failed requests.
+    pub fn export_json(&self) -> String {
+        let mut out = String::from(
+            "{\"log\":{\"version\":\"1.2\",\"creator\":{\"name\":\"stealth-fetch\",\"version\":",
+        );
+        json::quote(env!("CARGO_PKG_VERSION"), &mut out);
+        out.push_str("},\"entries\":[");
+        for (i, entry) in self.entries.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            write_entry(entry, &mut out);
+        }
+        out.push_str("]}}");
+        out
+    }
+}
+
+impl HarRecorder {
+    /// Rust-side `record_event`.
+    pub fn record_response_event(
+        &mut self,
+        id: u32,
+        event: &ResponseEvent,
+        at: f64,
+    ) -> Result<(), Error> {
+        match event {
+            ResponseEvent::Head(head) => self.record_response(
+                id,
+                head.status,
+                &head.reason,
+                head.version.as_str(),
+                head.headers.names(),
+                head.headers.values(),
+                at,
+            ),
+            ResponseEvent::Body(data) => self.add_response_body(id, data),
+            ResponseEvent::Complete => {
+                // Without a content coding, what was delivered is what was received
+                let size = self.entry(id)?.response.as_ref().and_then(|r| {
+                    let coded = !header(&r.headers, "content-encoding").is_empty();
+                    (!coded).then_some(r.content.size)
+                });
+                self.finish(id, size.map_or(-1.0, |s| s as f64), at)
+            }
+            ResponseEvent::Informational(_) | ResponseEvent::Trailers(_) => {
+                self.entry(id).map(drop)
+            }
+        }
+    }
+
+    /// Rust-side `start_request` for collected headers.
+    pub fn start_request_headers(
+        &mut self,
+        method: &str,
+        url: &str,
+        http_version: &str,
+        headers: &Headers,
+        connection: &str,
+        started: f64,
+    ) -> Result<u32, Error> {
+        self.start_request(
+            method,
+            url,
+            http_version,
+            headers.names(),
+            headers.values(),
+            connection,
+            started,
+        )
+    }
+
+    fn entry(&mut self, id: u32) -> Result<&mut Entry, Error> {
+        self.entries
+            .get_mut(id as usize)
+            .ok_or(Error::UnknownHarEntry(id))
+    }
+
+    fn redact(&self, mut headers: Vec<(String, String)>) -> Vec<(String, String)> {
+        for (name, value) in &mut headers {
+            if self
+                .redacted
+                .iter()
+                .map(String::as_str)
+                .chain(CREDENTIALS)
+                .any(|r| name.eq_ignore_ascii_case(r))
+            {
+                *value = REDACTED.to_string();
+            }
+        }
+        headers
+    }
+}
+
+/// Bytes of an HTTP/1.x head: start line, fields and the blank line. None for
+/// other versions, whose compressed heads have no such size.
+fn http1_head_size(start_line: &str, version: &str, headers: &[(String, String)]) -> Option<u64> {
+    if !version.starts_with("HTTP/1") {
+        return None;
+    }
+    let fields: usize = headers.iter().map(|(n, v)| n.len() + v.len() + 4).sum();
+    Some((start_line.len() + 2 + fields + 2) as u64)
+}
+
+fn write_entry(entry: &Entry, out: &mut String) {
+    let responded = entry.responded;
+    let sent = entry.sent.unwrap_or(entry.started);
+    let send = entry.sent.map_or(0.0, |s| s - entry.started);
+    let wait = responded.map_or(0.0, |r| r - sent);
+    let receive = match (responded, entry.finished) {
+        (Some(r), Some(f)) => f - r,
+        _ => 0.0,
+    };
+    let [dns, connect, ssl] = entry.setup;
+    // ssl is part of connect, so it is not added twice
+    let time = [dns, connect, send, wait, receive]
+        .iter()
+        .filter(|t| **t >= 0.0)
+        .sum::<f64>();
+
+    out.push_str("{\"startedDateTime\":");
+    json::quote(&iso_8601(entry.started), out);
+    out.push_str(&format!(",\"time\":{}", number(time)));
+
+    out.push_str(",\"request\":{\"method\":");
+    json::quote(&entry.method, out);
+    out.push_str(",\"url\":");
+    json::quote(&entry.url.to_string(), out);
+    out.push_str(",\"httpVersion\":");
+    json::quote(&entry.version, out);
+    out.push_str(",\"cookies\":");
+    write_cookies(&entry.headers, "cookie", out);
+    out.push_str(",\"headers\":");
+    write_headers(&entry.headers, out);
+    out.push_str(",\"queryString\":[");
+    let query = entry.url.query.as_deref().unwrap_or_default();
+    for (i, pair) in query.split('&').filter(|p| !p.is_empty()).enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+        write_pair(name, value, out);
+    }
+    out.push(']');
+    let body = &entry.request_body;
+    if body.size > 0 {
+        out.push_str(",\"postData\":{\"mimeType\":");
+        json::quote(header(&entry.headers, "content-type"), out);
+        out.push_str(",\"params\":[]");
+        body.write_text(out);
+        out.push('}');
+    }
+    out.push_str(&format!(
+        ",\"headersSize\":{},\"bodySize\":{}}}",
+        size(entry.headers_size),
+        body.size
+    ));
+
+    out.push_str(",\"response\":");
+    match &entry.response {
+        Some(response) => {
+            out.push_str(&format!("{{\"status\":{},\"statusText\":", response.status));
+            json::quote(&response.status_text, out);
+            out.push_str(",\"httpVersion\":");
+            json::quote(&response.version, out);
+            out.push_str(",\"cookies\":");
+            write_cookies(&response.headers, "set-cookie", out);
+            out.push_str(",\"headers\":");
+            write_headers(&response.headers, out);
+            out.push_str(&format!(
+                ",\"content\":{{\"size\":{},\"mimeType\":",
+                response.content.size
+            ));
+            json::quote(header(&response.headers, "content-type"), out);
+            if !response.content.captured.is_empty() {
+                response.content.write_text(out);
+            }
+            out.push_str("},\"redirectURL\":");
+            json::quote(header(&response.headers, "location"), out);
+            out.push_str(&format!(
+                ",\"headersSize\":{},\"bodySize\":{}}}",
+                size(response.headers_size),
+                size(response.body_size)
+            ));
+        }
+        None => out.push_str(
+            "{\"status\":0,\"statusText\":\"\",\"httpVersion\":\"\",\"cookies\":[],\"headers\":[],\
+             \"content\":{\"size\":0,\"mimeType\":\"\"},\"redirectURL\":\"\",\"headersSize\":-1,\"bodySize\":-1}",
+        ),
+    }
+
+    out.push_str(&format!(
+        ",\"cache\":{{}},\"timings\":{{\"blocked\":-1,\"dns\":{},\"connect\":{},\"send\":{},\"wait\":{},\"receive\":{},\"ssl\":{}}}",
+        number(dns),
+        number(connect),
+        number(send),
+        number(wait),
+        number(receive),
+        number(ssl)
+    ));
+    if let Some(address) = &entry.server_ip {
+        out.push_str(",\"serverIPAddress\":");
+        json::quote(address, out);
+    }
+    out.push_str(",\"connection\":");
+    json::quote(&entry.connection, out);
+    if let Some(tls) = &entry.tls {
+        out.push_str(",\"_tls\":{\"version\":");
+        json::quote(&tls.version, out);
+        out.push_str(",\"cipherSuite\":");
+        json::quote(&tls.cipher_suite, out);
+        out.push_str(",\"alpn\":");
+        json::quote(&tls.alpn, out);
+        out.push('}');
+    }
+    out.push('}');
+}
+
+fn write_pair(name: &str, value: &str, out: &mut String) {
+    out.push_str("{\"name\":");
+    json::quote(name, out);
+    out.push_str(",\"value\":");
+    json::quote(value, out);
+    out.push('}');
+}
+
+fn write_headers(headers: &[(String, String)], out: &mut String) {
+    out.push('[');
+    for (i, (name, value)) in headers.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        write_pair(name, value, out);
+    }
+    out.push(']');
+}
+
+/// Name/value pairs of Cookie (or Set-Cookie) fields; none when they were redacted.
+fn write_cookies(headers: &[(String, String)], field: &str, out: &mut String) {
+    out.push('[');
+    let values = headers
+        .iter()
+        .filter(|(n, v)| n.eq_ignore_ascii_case(field) && v != REDACTED)
+        .map(|(_, v)| v.as_str());
+    let pairs: Vec<&str> = if field == "cookie" {
+        values.flat_map(|v| v.split(';')).collect()
+    } else {
+        values.filter_map(|v| v.split(';').next()).collect()
+    };
+    let mut first = true;
+    for pair in pairs {
+        let Some((name, value)) = pair.split_once('=') else {
+            continue;
+        };
+        if !first {
+            out.push(',');
+        }
+        first = false;
+        write_pair(name.trim(), value.trim(), out);
+    }
+    out.push(']');
+}
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> &'a str {
+    headers
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map_or("", |(_, v)| v.as_str())
+}
+
+fn size(size: Option<u64>) -> String {
+    size.map_or_else(|| "-1".to_string(), |s| s.to_string())
+}
+
+/// A duration in milliseconds, to three decimals at most.
+fn number(ms: f64) -> String {
+    let rounded = (ms * 1000.0).round() / 1000.0;
+    if rounded.is_finite() {
+        format!("{}", rounded)
+    } else {
+        "-1".to_string()
+    }
+}
+
+/// `2024-01-02T03:04:05.678Z` for milliseconds since the epoch.
+fn iso_8601(ms: f64) -> String {
+    let ms = ms as i64;
+    let (days, ms_of_day) = (ms.div_euclid(86_400_000), ms.rem_euclid(86_400_000));
+    // civil_from_days (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        ms_of_day / 3_600_000,
+        ms_of_day / 60_000 % 60,
+        ms_of_day / 1000 % 60,
+        ms_of_day % 1000
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json::Value;
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(iso_8601(0.0), "1970-01-01T00:00:00.000Z");
+        assert_eq!(iso_8601(1_709_251_199_999.0), "2024-02-29T23:59:59.999Z");
+        assert_eq!(iso_8601(951_782_400_000.0), "2000-02-29T00:00:00.000Z");
+    }
+
+    #[test]
+    fn exports_a_transaction() {
+        let mut har = HarRecorder::new();
+        har.set_max_body_capture(4);
+        let t0 = 1_700_000_000_000.0;
+        let id = har
+            .start_request(
+                "POST",
+                "https://example.com/api?q=1&x",
+                "HTTP/1.1",
+                strings(&["Host", "Authorization", "Cookie", "Content-Type"]),
+                strings(&["example.com", "Bearer t", "a=1; b=2", "text/plain"]),
+                "conn-1",
+                t0,
+            )
+            .unwrap();
+        har.add_request_body(id, b"hello").unwrap();
+        har.mark_sent(id, t0 + 1.5).unwrap();
+        har.set_connection_timings(id, 2.0, 10.0, 6.0).unwrap();
+        har.set_tls(id, "TLSv1.3", "TLS13_AES_128_GCM_SHA256", "http/1.1")
+            .unwrap();
+
+        let mut parser = crate::http1::ResponseParser::new();
+        parser
+            .feed(b"HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\nSet-Cookie: s=1; Path=/\r\nContent-Length: 3\r\n\r\n\xff\x00\x01")
+            .unwrap();
+        let mut at = t0 + 20.0;
+        while let Some(event) = parser.next_event() {
+            har.record_event(id, &event, at).unwrap();
+            at += 5.0;
+        }
+        assert_eq!(har.finish(7, 0.0, at), Err(Error::UnknownHarEntry(7)));
+        har.start_request("GET", "http://a.test/", "h2", vec![], vec![], "conn-2", t0)
+            .unwrap();
+
+        let exported = har.export_json();
+        assert!(!exported.contains("Bearer"));
+        let json = json::parse(&exported).unwrap();
+        let log = json.get("log").unwrap();
+        assert_eq!(log.get("version").and_then(Value::as_str), Some("1.2"));
+        let Some(Value::Array(entries)) = log.get("entries") else {
+            panic!("no entries");
+        };
+        assert_eq!(entries.len(), 2);
+        let entry = &entries[0];
+        let field = |path: &[&str]| {
+            path.iter()
+                .try_fold(entry, |v, key| v.get(key))
+                .cloned()
+                .unwrap_or(Value::Null)
+        };
+        assert_eq!(
+            field(&["startedDateTime"]),
+            Value::String("2023-11-14T22:13:20.000Z".into())
+        );
+        assert_eq!(field(&["request", "cookies"]), Value::Array(vec![]));
+        assert_eq!(
+            field(&["request", "postData", "text"]),
+            Value::String("hell".into())
+        );
+        assert_eq!(field(&["request", "bodySize"]), Value::Number(5.0));
+        assert_eq!(field(&["response", "status"]), Value::Number(200.0));
+        assert_eq!(
+            field(&["response", "content", "encoding"]),
+            Value::String("base64".into())
+        );
+        assert_eq!(field(&["response", "bodySize"]), Value::Number(3.0));
+        assert_eq!(field(&["timings", "wait"]), Value::Number(18.5));
+        assert_eq!(field(&["timings", "receive"]), Value::Number(10.0));
+        assert_eq!(field(&["time"]), Value::Number(42.0));
+        assert_eq!(field(&["_tls", "alpn"]), Value::String("http/1.1".into()));
+        let Value::Array(query) = field(&["request", "queryString"]) else {
+            panic!("no query string");
+        };
+        assert_eq!(query.len(), 2);
+        assert_eq!(
+            entries[1].get("response").and_then(|r| r.get("status")),
+            Some(&Value::Number(0.0))
+        );
+    }
+
+    #[test]
+    fn redaction_is_configurable() {
+        let mut har = HarRecorder::new();
+        har.set_redacted_headers(strings(&["X-Api-Key"]));
+        let id = har
+            .start_request(
+                "GET",
+                "https://example.com/",
+                "HTTP/1.1",
+                strings(&["x-api-key", "Cookie"]),
+                strings(&["secret", "a=1; b=2"]),
+                "c",
+                0.0,
+            )
+            .unwrap();
+        let json = har.export_json();
+        assert!(!json.contains("secret"));
+        assert!(json.contains(r#""cookies":[{"name":"a","value":"1"},{"name":"b","value":"2"}]"#));
+        har.set_redacted_headers(Vec::new());
+        har.start_request(
+            "GET",
+            "https://example.com/",
+            "HTTP/1.1",
+            strings(&["Authorization", "proxy-authorization"]),
+            strings(&["Basic c2VjcmV0", "Basic c2VjcmV0"]),
+            "c",
+            0.0,
+        )
+        .unwrap();
+        assert!(!har.export_json().contains("c2VjcmV0"));
+        assert_eq!(har.entries[id as usize].headers_size, Some(55));
+        assert!(matches!(
+            har.start_request(
+                "GET",
+                "https://e/",
+                "HTTP/1.1",
+                strings(&["a"]),
+                vec![],
+                "c",
+                0.0
+            ),
+            Err(Error::HeaderCountMismatch)
+        ));
+    }
+}
@@ -0,0 +1,397 @@
+//! HTTP Strict Transport Security (RFC 6797): remembering the hosts that asked to
+//! be reached over https only, and upgrading http URLs to them.
+//!
+//! Like the cookie jar, the store is sans-IO: the caller passes the response URL
+//! and the current time with each Strict-Transport-Security header, and asks before
+//! each request (and `RedirectController` before each hop) whether to upgrade it.
+
+use std::net::Ipv4Addr;
+
+use wasm_bindgen::prelude::*;
+
+use crate::error::Error;
+use crate::headers::Headers;
+use crate::json::{self, Value};
+use crate::url::HttpUrl;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct KnownHost {
+    /// Lowercased, without a trailing dot
+    host: String,
+    include_subdomains: bool,
+    /// Unix seconds
+    expires: i64,
+}
+
+/// Directives of a valid Strict-Transport-Security value (§6.1).
+#[derive(Debug, PartialEq, Eq)]
+struct Policy {
+    max_age: i64,
+    include_subdomains: bool,
+}
+
+/// Parse one Strict-Transport-Security value. None when it is invalid: no
+/// max-age, a max-age that is not a number, or a directive given twice.
+fn parse_policy(value: &str) -> Option<Policy> {
+    let (mut max_age, mut include_subdomains) = (None, false);
+    let mut seen = Vec::new();
+    for directive in value.split(';') {
+        let directive = directive.trim();
+        if directive.is_empty() {
+            continue;
+        }
+        let (name, value) = match directive.split_once('=') {
+            Some((name, value)) => (name.trim(), Some(value.trim())),
+            None => (directive, None),
+        };
+        let name = name.to_ascii_lowercase();
+        if seen.contains(&name) {
+            return None;
+        }
+        match name.as_str() {
+            "max-age" => {
+                let value = value?;
+                let digits = value
+                    .strip_prefix('"')
+                    .and_then(|v| v.strip_suffix('"'))
+                    .unwrap_or(value);
+                if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+                    return None;
+                }
+                // Too large to represent is as good as forever
+                max_age = Some(digits.parse().unwrap_or(i64::MAX));
+            }
+            "includesubdomains" => include_subdomains = true,
+            // Unknown directives (e.g. preload) are ignored
+            _ => {}
+        }
+        seen.push(name);
+    }
+    Some(Policy {
+        max_age: max_age?,
+        include_subdomains,
+    })
+}
+
+/// Known HSTS hosts (§8.1), with expiry times and includeSubDomains.
+#[wasm_bindgen]
+#[derive(Clone, Debug, Default)]
+pub struct HstsStore {
+    hosts: Vec<KnownHost>,
+}
+
+#[wasm_bindgen]
+impl HstsStore {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> HstsStore {
+        HstsStore::default()
+    }
+
+    /// Process a Strict-Transport-Security value received in the response to
+    /// `url`. Returns false when it is ignored: sent over plain http, to an IP
+    /// address, or invalid. `max-age=0` forgets the host.
+    pub fn store_header(&mut self, url: &str, header: &str, now: f64) -> Result<bool, Error> {
+        let url = HttpUrl::parse(url)?;
+        Ok(self.store(&url, header, now as i64))
+    }
+
+    /// Process the Strict-Transport-Security header among `names`/`values`, e.g.
+    /// the `header_names()`/`header_values()` of a response event. Only the first
+    /// one counts, as §8.1 requires.
+    pub fn store_response(
+        &mut self,
+        url: &str,
+        names: Vec<String>,
+        values: Vec<String>,
+        now: f64,
+    ) -> Result<bool, Error> {
+        if names.len() != values.len() {
+            return Err(Error::HeaderCountMismatch);
+        }
+        let url = HttpUrl::parse(url)?;
+        let header = names
+            .iter()
+            .position(|n| n.eq_ignore_ascii_case("strict-transport-security"));
+        Ok(header.is_some_and(|i| self.store(&url, &values[i], now as i64)))
+    }
+
+    /// Whether requests to `host` must go over https: it is a known HSTS host, or
+    /// a subdomain of one that set includeSubDomains (§8.2).
+    pub fn should_upgrade(&self, host: &str, now: f64) -> bool {
+        let host = normalize(host);
+        let now = now as i64;
+        self.hosts.iter().any(|known| {
+            known.expires > now
+                && (known.host == host
+                    || known.include_subdomains
+                        && host
+                            .strip_suffix(known.host.as_str())
+                            .is_some_and(|prefix| prefix.ends_with('.')))
+        })
+    }
+
+    /// `url` with http changed to https (and port 80 to 443) if its host should
+    /// be upgraded; otherwise `url` unchanged.
+    pub fn upgrade_url(&self, url: &str, now: f64) -> Result<String, Error> {
+        let mut parsed = HttpUrl::parse(url)?;
+        if parsed.secure || !self.should_upgrade(&parsed.host, now) {
+            return Ok(url.to_string());
+        }
+        upgrade(&mut parsed);
+        Ok(parsed.to_string())
+    }
+
+    /// Drop expired entries; returns how many were removed. Lookups skip them anyway.
+    pub fn evict_expired(&mut self, now: f64) -> usize {
+        let before = self.hosts.len();
+        self.hosts.retain(|h| h.expires > now as i64);
+        before - self.hosts.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.hosts.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.hosts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty()
+    }
+
+    /// Every known host as a JSON array of objects with `host`,
+    /// `includeSubDomains` and `expires` (unix seconds).
+    pub fn export_json(&self) -> String {
+        let mut out = String::from("[");
+        for (i, known) in self.hosts.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            out.push_str("{\"host\":");
+            json::quote(&known.host, &mut out);
+            out.push_str(&format!(
+                ",\"includeSubDomains\":{},\"expires\":{}}}",
+                known.include_subdomains, known.expires
+            ));
+        }
+        out.push(']');
+        out
+    }
+
+    /// Add the hosts from `export_json` output, replacing stored entries for the
+    /// same host. Nothing is imported if any entry is malformed.
+    pub fn import_json(&mut self, json: &str) -> Result<(), Error> {
+        let Some(Value::Array(entries)) = json::parse(json) else {
+            return Err(Error::InvalidHstsStore("expected a JSON array"));
+        };
+        let hosts = entries
+            .iter()
+            .map(known_host_from_json)
+            .collect::<Result<Vec<_>, _>>()?;
+        for known in hosts {
+            self.hosts.retain(|h| h.host != known.host);
+            self.hosts.push(known);
+        }
+        Ok(())
+    }
+}
+
+impl HstsStore {
+    /// Rust-side `store_response` for a parsed head.
+    pub fn store_headers(&mut self, url: &str, headers: &Headers, now: f64) -> Result<bool, Error> {
+        let url = HttpUrl::parse(url)?;
+        Ok(self.store_for(&url, headers, now as i64))
+    }
+
+    pub(crate) fn store_for(&mut self, url: &HttpUrl, headers: &Headers, now: i64) -> bool {
+        headers
+            .get("strict-transport-security")
+            .is_some_and(|header| self.store(url, header, now))
+    }
+
+    /// Rewrite `url` to https if its host should be upgraded; returns whether it was.
+    pub(crate) fn upgrade_in_place(&self, url: &mut HttpUrl, now: i64) -> bool {
+        if url.secure || !self.should_upgrade(&url.host, now as f64) {
+            return false;
+        }
+        upgrade(url);
+        true
+    }
+
+    fn store(&mut self, url: &HttpUrl, header: &str, now: i64) -> bool {
+        // §8.1: only over a secure transport, and never for IP literals
+        let host = normalize(&url.host);
+        if !url.secure || host.starts_with('[') || host.parse::<Ipv4Addr>().is_ok() {
+            return false;
+        }
+        let Some(policy) = parse_policy(header) else {
+            return false;
+        };
+        self.hosts.retain(|h| h.host != host);
+        if policy.max_age > 0 {
+            self.hosts.push(KnownHost {
+                host,
+                include_subdomains: policy.include_subdomains,
+                expires: now.saturating_add(policy.max_age),
+            });
+        }
+        true
+    }
+}
+
+/// §8.3 step 5: the scheme becomes https, and port 80 its default.
+fn upgrade(url: &mut HttpUrl) {
+    url.secure = true;
+    if url.port == 80 {
+        url.port = 443;
+    }
+}
+
+fn normalize(host: &str) -> String {
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+fn known_host_from_json(entry: &Value) -> Result<KnownHost, Error> {
+    let host = entry
+        .get("host")
+        .and_then(Value::as_str)
+        .filter(|h| !h.is_empty() && h.bytes().all(|b| b.is_ascii_graphic()))
+        .ok_or(Error::InvalidHstsStore("host"))?;
+    let include_subdomains = entry
+        .get("includeSubDomains")
+        .and_then(Value::as_bool)
+        .ok_or(Error::InvalidHstsStore("includeSubDomains"))?;
+    let expires = match entry.get("expires") {
+        Some(Value::Number(n)) if n.fract() == 0.0 && n.abs() < 9e15 => *n as i64,
+        _ => return Err(Error::InvalidHstsStore("expires")),
+    };
+    Ok(KnownHost {
+        host: normalize(host),
+        include_subdomains,
+        expires,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: f64 = 1_700_000_000.0;
+
+    #[test]
+    fn header_parsing() {
+        let policy = |v| parse_policy(v).map(|p| (p.max_age, p.include_subdomains));
+        assert_eq!(policy("max-age=31536000"), Some((31536000, false)));
+        assert_eq!(
+            policy(" Max-Age=\"600\" ; includeSubDomains; preload"),
+            Some((600, true))
+        );
+        assert_eq!(
+            policy("max-age=99999999999999999999"),
+            Some((i64::MAX, false))
+        );
+        for invalid in [
+            "",
+            "includeSubDomains",
+            "max-age=",
+            "max-age=-1",
+            "max-age=1.5",
+            "max-age=1; max-age=2",
+            "max-age=1; includeSubDomains; includesubdomains",
+        ] {
+            assert_eq!(policy(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn superdomain_matching_and_expiry() {
+        let mut store = HstsStore::new();
+        let sts = "max-age=3600; includeSubDomains";
+        assert!(store
+            .store_header("https://example.com/", sts, NOW)
+            .unwrap());
+        assert!(store
+            .store_header("https://only.test/", "max-age=3600", NOW)
+            .unwrap());
+        // Not over plain http, and not for addresses
+        assert!(!store.store_header("http://plain.test/", sts, NOW).unwrap());
+        assert!(!store.store_header("https://10.0.0.1/", sts, NOW).unwrap());
+
+        assert!(store.should_upgrade("example.com", NOW));
+        assert!(store.should_upgrade("API.Example.com.", NOW));
+        assert!(!store.should_upgrade("badexample.com", NOW));
+        assert!(store.should_upgrade("only.test", NOW));
+        assert!(!store.should_upgrade("www.only.test", NOW));
+        assert!(!store.should_upgrade("plain.test", NOW));
+        assert!(!store.should_upgrade("example.com", NOW + 3600.0));
+
+        assert_eq!(
+            store.upgrade_url("http://a.example.com/x?y", NOW).unwrap(),
+            "https://a.example.com/x?y"
+        );
+        assert_eq!(
+            store.upgrade_url("http://example.com:8080/", NOW).unwrap(),
+            "https://example.com:8080/"
+        );
+        assert_eq!(
+            store.upgrade_url("http://plain.test/", NOW).unwrap(),
+            "http://plain.test/"
+        );
+        assert_eq!(store.evict_expired(NOW + 3600.0), 2);
+    }
+
+    #[test]
+    fn max_age_zero_forgets_the_host() {
+        let mut store = HstsStore::new();
+        let url = "https://example.com/";
+        store
+            .store_header(url, "max-age=3600; includeSubDomains", NOW)
+            .unwrap();
+        // A subdomain's own policy does not lift the superdomain's
+        store
+            .store_header("https://www.example.com/", "max-age=0", NOW)
+            .unwrap();
+        assert!(store.should_upgrade("www.example.com", NOW));
+        assert!(store.store_header(url, "max-age=0", NOW).unwrap());
+        assert!(!store.should_upgrade("example.com", NOW));
+        assert!(!store.should_upgrade("www.example.com", NOW));
+        assert!(store.is_empty());
+        // An invalid header leaves the entry alone
+        store.store_header(url, "max-age=60", NOW).unwrap();
+        assert!(!store
+            .store_header(url, "max-age=0; max-age=0", NOW)
+            .unwrap());
+        assert!(store.should_upgrade("example.com", NOW));
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let mut store = HstsStore::new();
+        let names = vec!["Strict-Transport-Security".to_string(); 2];
+        let values = vec![
+            "max-age=60; includeSubDomains".to_string(),
+            "max-age=0".to_string(),
+        ];
+        let url = "https://example.com/";
+        assert!(store.store_response(url, names, values, NOW).unwrap());
+        let json = store.export_json();
+        assert_eq!(
+            json,
+            r#"[{"host":"example.com","includeSubDomains":true,"expires":1700000060}]"#
+        );
+        let mut restored = HstsStore::new();
+        restored.import_json(&json).unwrap();
+        restored.import_json(&json).unwrap();
+        assert_eq!(restored.hosts, store.hosts);
+        for bad in [
+            "{}",
+            r#"[{"host":"a"}]"#,
+            r#"[{"host":"a b","includeSubDomains":true,"expires":1}]"#,
+            r#"[{"host":"a","includeSubDomains":true,"expires":1.5}]"#,
+        ] {
+            assert!(restored.import_json(bad).is_err(), "{}", bad);
+        }
+        assert_eq!(restored.len(), 1);
+    }
+}
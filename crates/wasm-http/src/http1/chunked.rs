@@ -0,0 +1,289 @@
+use super::response::{block_lines, find_head_end, parse_fields};
+use crate::error::Error;
+use crate::headers::Headers;
+
+/// The zero-size chunk and empty trailer section that end a chunked body.
+pub(crate) const LAST_CHUNK: &[u8] = b"0\r\n\r\n";
+
+/// Append `data` as one chunk; it must not be empty, or it would end the body.
+pub(crate) fn write_chunk(out: &mut Vec<u8>, data: &[u8]) {
+    out.extend_from_slice(format!("{:x}\r\n", data.len()).as_bytes());
+    out.extend_from_slice(data);
+    out.extend_from_slice(b"\r\n");
+}
+
+/// Longest accepted chunk-size line, extensions included.
+pub const DEFAULT_MAX_CHUNK_LINE: usize = 4096;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    /// Reading a chunk-size line into `line`
+    Size,
+    /// Chunk payload with this many bytes left
+    Data(u64),
+    /// Expecting the CRLF that ends a chunk's payload
+    DataEnd,
+    /// Reading the trailer section into `line`
+    Trailers,
+    Done,
+}
+
+/// Outcome of one [`ChunkedDecoder::decode`] call.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Decoded {
+    /// Input bytes that belonged to the chunked body; the rest is leftover
+    pub consumed: usize,
+    /// Trailer fields, present once the terminating chunk's trailer section is read
+    pub trailers: Option<Headers>,
+    /// Whether the terminating chunk and trailer section have been read
+    pub done: bool,
+}
+
+/// Incremental `Transfer-Encoding: chunked` decoder (RFC 9112 §7.1).
+/// Chunk extensions are skipped; trailers are parsed and returned.
+#[derive(Debug)]
+pub struct ChunkedDecoder {
+    state: State,
+    line: Vec<u8>,
+    max_line: usize,
+    max_trailers: usize,
+}
+
+impl ChunkedDecoder {
+    /// `max_trailers` bounds the trailer section (typically the header size limit).
+    pub fn new(max_trailers: usize) -> Self {
+        ChunkedDecoder {
+            state: State::Size,
+            line: Vec::new(),
+            max_line: DEFAULT_MAX_CHUNK_LINE,
+            max_trailers,
+        }
+    }
+
+    pub fn set_max_line(&mut self, limit: usize) {
+        self.max_line = limit;
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.state == State::Done
+    }
+
+    /// Decode as much of `input` as possible, appending payload bytes to `body`.
+    pub fn decode(&mut self, input: &[u8], body: &mut Vec<u8>) -> Result<Decoded, Error> {
+        let mut pos = 0;
+        let mut trailers = None;
+        while pos < input.len() {
+            let rest = &input[pos..];
+            match self.state {
+                State::Size => match rest.iter().position(|&b| b == b'\n') {
+                    Some(i) => {
+                        self.push_line(&rest[..i])?;
+                        pos += i + 1;
+                        let size = parse_size_line(&self.line)?;
+                        self.line.clear();
+                        self.state = if size == 0 {
+                            State::Trailers
+                        } else {
+                            State::Data(size)
+                        };
+                    }
+                    None => {
+                        self.push_line(rest)?;
+                        pos = input.len();
+                    }
+                },
+                State::Data(remaining) => {
+                    let n = remaining.min(rest.len() as u64) as usize;
+                    body.extend_from_slice(&rest[..n]);
+                    pos += n;
+                    self.state = if n as u64 == remaining {
+                        State::DataEnd
+                    } else {
+                        State::Data(remaining - n as u64)
+                    };
+                }
+                State::DataEnd => {
+                    match (self.line.as_slice(), rest[0]) {
+                        ([], b'\r') => self.line.push(b'\r'),
+                        ([] | [b'\r'], b'\n') => {
+                            self.line.clear();
+                            self.state = State::Size;
+                        }
+                        _ => return Err(Error::InvalidChunkTerminator),
+                    }
+                    pos += 1;
+                }
+                State::Trailers => {
+                    let before = self.line.len();
+                    self.line.extend_from_slice(rest);
+                    match trailer_end(&self.line, before) {
+                        Some(end) => {
+                            pos += end - before;
+                            self.line.truncate(end);
+                            let fields = parse_fields(block_lines(&self.line))?;
+                            self.line.clear();
+                            trailers = Some(fields);
+                            self.state = State::Done;
+                        }
+                        None => {
+                            if self.line.len() > self.max_trailers {
+                                return Err(Error::HeadersTooLarge {
+                                    limit: self.max_trailers,
+                                });
+                            }
+                            pos = input.len();
+                        }
+                    }
+                }
+                State::Done => break,
+            }
+        }
+        Ok(Decoded {
+            consumed: pos,
+            trailers,
+            done: self.state == State::Done,
+        })
+    }
+
+    fn push_line(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        if self.line.len() + bytes.len() > self.max_line {
+            return Err(Error::ChunkLineTooLong {
+                limit: self.max_line,
+            });
+        }
+        self.line.extend_from_slice(bytes);
+        Ok(())
+    }
+}
+
+/// End offset of the trailer section in `buf` (which starts right after the last-chunk line).
+fn trailer_end(buf: &[u8], scanned: usize) -> Option<usize> {
+    match buf {
+        [b'\n', ..] => Some(1),
+        [b'\r', b'\n', ..] => Some(2),
+        [b'\r'] => None,
+        _ => find_head_end(buf, scanned.saturating_sub(2)),
+    }
+}
+
+/// Parse `chunk-size [ chunk-ext ]`, ignoring the extensions.
+fn parse_size_line(line: &[u8]) -> Result<u64, Error> {
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    let digits = line.iter().take_while(|b| b.is_ascii_hexdigit()).count();
+    if digits == 0 || digits > 16 {
+        return Err(Error::InvalidChunkSize);
+    }
+    let ext = &line[digits..];
+    let ext_start = ext.iter().position(|&b| b != b' ' && b != b'\t');
+    if let Some(i) = ext_start {
+        if ext[i] != b';' {
+            return Err(Error::InvalidChunkSize);
+        }
+    }
+    if line.contains(&b'\r') || line.contains(&0) {
+        return Err(Error::InvalidChunkSize);
+    }
+    let hex = std::str::from_utf8(&line[..digits]).map_err(|_| Error::InvalidChunkSize)?;
+    u64::from_str_radix(hex, 16).map_err(|_| Error::InvalidChunkSize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WITH_TRAILERS: &[u8] = b"4;name=value\r\nWiki\r\n5 ; a ; b=\"c\"\r\npedia\r\n\
+          E\r\n in\r\n\r\nchunks.\r\n0;final\r\nExpires: never\r\nX-Checksum: abc\r\n\r\nNEXT";
+
+    fn decode_all(input: &[u8], step: usize) -> (Vec<u8>, Option<Headers>, usize) {
+        let mut dec = ChunkedDecoder::new(1024);
+        let mut body = Vec::new();
+        let mut trailers = None;
+        let mut consumed = 0;
+        for piece in input.chunks(step) {
+            if dec.is_done() {
+                break;
+            }
+            let d = dec.decode(piece, &mut body).unwrap();
+            consumed += d.consumed;
+            if d.trailers.is_some() {
+                trailers = d.trailers;
+            }
+            if !d.done {
+                assert_eq!(d.consumed, piece.len());
+            }
+        }
+        assert!(dec.is_done());
+        (body, trailers, consumed)
+    }
+
+    #[test]
+    fn one_byte_feeds_with_extensions_and_trailers() {
+        for step in [1, 2, 3, 7, WITH_TRAILERS.len()] {
+            let (body, trailers, consumed) = decode_all(WITH_TRAILERS, step);
+            assert_eq!(body, b"Wikipedia in\r\n\r\nchunks.");
+            let trailers = trailers.unwrap();
+            assert_eq!(trailers.get("expires"), Some("never"));
+            assert_eq!(trailers.get("X-CHECKSUM"), Some("abc"));
+            assert_eq!(consumed, WITH_TRAILERS.len() - 4, "step {}", step);
+        }
+    }
+
+    #[test]
+    fn empty_trailer_section_and_bare_lf() {
+        let input = b"3\nabc\n0\n\n";
+        for step in 1..=input.len() {
+            let (body, trailers, consumed) = decode_all(input, step);
+            assert_eq!(body, b"abc");
+            assert!(trailers.unwrap().is_empty());
+            assert_eq!(consumed, input.len());
+        }
+    }
+
+    #[test]
+    fn malformed_sizes() {
+        for input in [
+            &b"zz\r\n"[..],
+            b"\r\n",
+            b"5 x\r\n",
+            b"11111111111111111\r\n",
+        ] {
+            let mut dec = ChunkedDecoder::new(1024);
+            assert_eq!(
+                dec.decode(input, &mut Vec::new()),
+                Err(Error::InvalidChunkSize),
+                "{:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn oversized_size_line() {
+        let mut dec = ChunkedDecoder::new(1024);
+        dec.set_max_line(16);
+        let mut body = Vec::new();
+        dec.decode(b"1;ext=aaaa", &mut body).unwrap();
+        assert_eq!(
+            dec.decode(b"aaaaaaaaaaaa", &mut body),
+            Err(Error::ChunkLineTooLong { limit: 16 })
+        );
+    }
+
+    #[test]
+    fn missing_crlf_after_data() {
+        let mut dec = ChunkedDecoder::new(1024);
+        assert_eq!(
+            dec.decode(b"2\r\nabX", &mut Vec::new()),
+            Err(Error::InvalidChunkTerminator)
+        );
+    }
+
+    #[test]
+    fn oversized_trailers() {
+        let mut dec = ChunkedDecoder::new(8);
+        assert_eq!(
+            dec.decode(b"0\r\nX-Long: 0123456789", &mut Vec::new()),
+            Err(Error::HeadersTooLarge { limit: 8 })
+        );
+    }
+}
@@ -0,0 +1,931 @@
+use std::mem;
+
+use wasm_bindgen::prelude::*;
+
+use super::chunked::{write_chunk, LAST_CHUNK};
+use super::response::{ParserEvent, ResponseEvent, ResponseParser};
+use super::{RequestBuilder, Version};
+use crate::coding::GzipEncoder;
+use crate::error::Error;
+use crate::headers::validate_trailer;
+use crate::timing::Timings;
+
+/// Sequential HTTP/1.1 exchanges over one connection (keep-alive, no pipelining).
+///
+/// `send` serializes a request for `write_plaintext`; what `take_plaintext` returns
+/// goes to `feed`, and the response comes out of `next_event`. The next request can
+/// only be sent once the "complete" event of the previous one has been taken, and
+/// only while `is_reusable` says the connection may carry it.
+#[wasm_bindgen]
+#[derive(Debug, Default)]
+pub struct Http1Connection {
+    /// Parser of the exchange in flight
+    parser: Option<ResponseParser>,
+    /// The request in flight allows the connection to persist
+    request_persistent: bool,
+    /// The response in flight allows the connection to persist
+    response_persistent: bool,
+    /// Status of the response in flight, once its head has arrived
+    response_status: u16,
+    /// Bytes received after the last complete response
+    leftover: Vec<u8>,
+    /// The connection was closed, failed, or a response ruled out reuse
+    done: bool,
+    exchanges: u32,
+    /// Request body still being streamed: bytes left under a Content-Length, or
+    /// `None` for a chunked body
+    upload: Option<Option<u64>>,
+    /// Status of the response that cut the streamed body short
+    upload_aborted: Option<u16>,
+    /// Progress of an `Expect: 100-continue` upload
+    expect: Option<Expect>,
+    /// Gzip state of a streamed body sent with `RequestBuilder::set_compress_body`
+    compression: Option<UploadCompression>,
+    /// Time last given to `set_now`
+    now: Option<f64>,
+    /// TLS handshake of the connection, from `set_tls_timings`
+    setup: Timings,
+    /// Of the exchange in flight, or the last one
+    timings: Timings,
+}
+
+/// Where a streamed upload sent with `Expect: 100-continue` stands.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Expect {
+    /// Body held back until the server answers or the caller gives up waiting
+    Waiting,
+    /// `100 Continue` arrived: send the body
+    Continued,
+    /// A final response arrived first: the body is skipped
+    FinalResponse,
+    /// The caller's timer fired first: send the body anyway
+    TimedOut,
+}
+
+/// A streamed body gzipped on its way out.
+#[derive(Debug)]
+struct UploadCompression {
+    encoder: GzipEncoder,
+    /// The request, without framing, and the compressed bytes, held back while the
+    /// body may still end within `limit` uncompressed bytes
+    held: Option<(RequestBuilder, Vec<u8>)>,
+    limit: u64,
+}
+
+impl UploadCompression {
+    /// Compress the next piece; returns the bytes to write.
+    fn write(&mut self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let encoded = self.encoder.encode(data);
+        if let Some((_, held)) = &mut self.held {
+            held.extend(encoded);
+            if self.encoder.total_in() <= self.limit {
+                return Ok(Vec::new());
+            }
+            return self.release_chunked();
+        }
+        let mut out = Vec::new();
+        if !encoded.is_empty() {
+            write_chunk(&mut out, &encoded);
+        }
+        Ok(out)
+    }
+
+    /// Compress what is left; returns the bytes to write and whether the body is
+    /// chunked, i.e. still needs its last chunk. A body held back whole is sent
+    /// with a Content-Length, unless trailers follow.
+    fn finish(&mut self, trailers: bool) -> Result<(Vec<u8>, bool), Error> {
+        let mut rest = self.encoder.finish();
+        if let Some((request, held)) = &mut self.held {
+            held.append(&mut rest);
+            if !trailers {
+                request.header("Content-Length", &held.len().to_string())?;
+                let mut out = request.to_bytes()?;
+                out.extend_from_slice(held);
+                self.held = None;
+                return Ok((out, false));
+            }
+        }
+        let mut out = self.release_chunked()?;
+        if !rest.is_empty() {
+            write_chunk(&mut out, &rest);
+        }
+        Ok((out, true))
+    }
+
+    /// The held-back head, now chunked, and what was compressed so far.
+    fn release_chunked(&mut self) -> Result<Vec<u8>, Error> {
+        let Some((mut request, held)) = self.held.take() else {
+            return Ok(Vec::new());
+        };
+        if request.version() == Version::Http10 {
+            return Err(Error::Upload("HTTP/1.0 has no chunked encoding"));
+        }
+        request.header("Transfer-Encoding", "chunked")?;
+        let mut out = request.to_bytes()?;
+        if !held.is_empty() {
+            write_chunk(&mut out, &held);
+        }
+        Ok(out)
+    }
+}
+
+impl Expect {
+    fn as_str(self) -> &'static str {
+        match self {
+            Expect::Waiting => "waiting",
+            Expect::Continued => "continue",
+            Expect::FinalResponse => "final-response",
+            Expect::TimedOut => "timeout",
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl Http1Connection {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Http1Connection {
+        Http1Connection::default()
+    }
+
+    /// Start an exchange: returns the bytes of `request` to write. Fails if the
+    /// previous response has not been fully read, or the connection cannot be reused.
+    pub fn send(&mut self, request: &RequestBuilder) -> Result<Vec<u8>, Error> {
+        if self.parser.is_some() {
+            return Err(Error::RequestInFlight);
+        }
+        if self.done {
+            return Err(Error::ConnectionNotReusable);
+        }
+        let bytes = request.to_bytes()?;
+        let mut parser = ResponseParser::for_request(request);
+        let leftover = mem::take(&mut self.leftover);
+        if let Err(e) = parser.feed(&leftover) {
+            self.done = true;
+            return Err(e);
+        }
+        self.request_persistent = persistent(request.version(), request.header_all("connection"));
+        self.response_persistent = false;
+        self.response_status = 0;
+        self.parser = Some(parser);
+        self.upload = None;
+        self.upload_aborted = None;
+        self.expect = None;
+        self.compression = None;
+        // Only the first request waited for the handshake
+        let setup = if self.exchanges == 0 {
+            self.setup
+        } else {
+            Timings::default()
+        };
+        self.timings = Timings {
+            queued: self.now,
+            request_sent: self.now,
+            ..setup
+        };
+        Ok(bytes)
+    }
+
+    /// `send` for a request whose body is streamed afterwards with
+    /// `write_body_chunk` and `finish_body`: with a Content-Length of
+    /// `content_length` bytes if given, chunked otherwise (HTTP/1.1 only). The
+    /// framing header is appended; `request` must not have one.
+    ///
+    /// Should the server answer with a final status of 300 or more, or complete
+    /// its response, before the body is finished, the upload is aborted: the
+    /// body calls fail with `UploadAborted` and the connection cannot be reused.
+    ///
+    /// A request with `Expect: 100-continue` (see `RequestBuilder::set_expect_continue`)
+    /// holds the body back until `100 Continue` arrives. If a final response arrives
+    /// instead, e.g. 417 or 403, it is delivered and the body is never sent. Call
+    /// `expect_timeout` when the caller's own timer fires to send the body anyway.
+    /// `expect_outcome` tells which of these happened.
+    ///
+    /// A body to be gzipped (see `RequestBuilder::set_compress_body`) has no
+    /// length up front, so `content_length` must be `None`. Its head is held
+    /// back, and nothing returned here, until the body outgrows the buffer limit
+    /// (`RequestBuilder::set_compress_buffer_limit`) and goes out chunked, or ends
+    /// within it and goes out whole with a Content-Length; an HTTP/1.0 body must
+    /// end within it. With `Expect: 100-continue` the head is sent at once and
+    /// the body chunked.
+    pub fn send_streaming(
+        &mut self,
+        request: &RequestBuilder,
+        content_length: Option<u64>,
+    ) -> Result<Vec<u8>, Error> {
+        if !request.header_all("content-length").is_empty()
+            || !request.header_all("transfer-encoding").is_empty()
+        {
+            return Err(Error::Upload("request already has a framing header"));
+        }
+        let compress = request.compress_body();
+        if compress {
+            if let Some(coding) = request.header_all("content-encoding").first() {
+                return Err(Error::AlreadyEncoded(coding.to_string()));
+            }
+            if content_length.is_some() {
+                return Err(Error::Upload(
+                    "a compressed body has no Content-Length up front",
+                ));
+            }
+        }
+        let hold = compress && !request.expects_continue() && request.compress_buffer_limit() > 0;
+        let mut request = request.clone();
+        match content_length {
+            Some(len) => request.header("Content-Length", &len.to_string())?,
+            // Framed once the body ends or outgrows the buffer limit
+            None if hold => {}
+            None if request.version() == Version::Http10 => {
+                return Err(Error::Upload("HTTP/1.0 has no chunked encoding"));
+            }
+            None => request.header("Transfer-Encoding", "chunked")?,
+        }
+        request.set_streamed_body();
+        let mut bytes = self.send(&request)?;
+        self.timings.request_sent = None;
+        self.upload = Some(content_length);
+        self.expect = request.expects_continue().then_some(Expect::Waiting);
+        self.update_expect();
+        if compress {
+            if hold {
+                bytes.clear();
+            }
+            self.compression = Some(UploadCompression {
+                encoder: GzipEncoder::new(),
+                limit: request.compress_buffer_limit() as u64,
+                held: hold.then_some((request, Vec::new())),
+            });
+        }
+        Ok(bytes)
+    }
+
+    /// Frame the next piece of a streamed body; returns the bytes to write.
+    pub fn write_body_chunk(&mut self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let remaining = self.upload_state()?;
+        let mut out = Vec::new();
+        match remaining {
+            Some(left) => {
+                if data.len() as u64 > *left {
+                    return Err(Error::Upload("body longer than its Content-Length"));
+                }
+                *left -= data.len() as u64;
+                out.extend_from_slice(data);
+            }
+            // An empty chunk would end the body
+            None if data.is_empty() => {}
+            None => match &mut self.compression {
+                Some(compression) => out = compression.write(data)?,
+                None => write_chunk(&mut out, data),
+            },
+        }
+        Ok(out)
+    }
+
+    /// End a streamed body; returns the bytes to write (the last chunk, if chunked).
+    pub fn finish_body(&mut self) -> Result<Vec<u8>, Error> {
+        self.finish_body_with_trailers(Vec::new(), Vec::new())
+    }
+
+    /// `finish_body` with a trailer section after the last chunk, e.g. for fields the
+    /// request announced in its `Trailer` header. Trailers need a chunked body, and
+    /// fields such as Content-Length or Host are not allowed in them.
+    pub fn finish_body_with_trailers(
+        &mut self,
+        names: Vec<String>,
+        values: Vec<String>,
+    ) -> Result<Vec<u8>, Error> {
+        if names.len() != values.len() {
+            return Err(Error::HeaderCountMismatch);
+        }
+        for (name, value) in names.iter().zip(&values) {
+            validate_trailer(name, value)?;
+        }
+        let mut out = Vec::new();
+        let chunked = match self.upload_state()? {
+            Some(_) if !names.is_empty() => {
+                return Err(Error::Upload("trailers need a chunked body"));
+            }
+            Some(0) => false,
+            Some(_) => return Err(Error::Upload("body shorter than its Content-Length")),
+            None => match &mut self.compression {
+                Some(compression) => {
+                    let chunked;
+                    (out, chunked) = compression.finish(!names.is_empty())?;
+                    chunked
+                }
+                None => true,
+            },
+        };
+        if chunked && names.is_empty() {
+            out.extend_from_slice(LAST_CHUNK);
+        } else if chunked {
+            out.extend_from_slice(b"0\r\n");
+            for (name, value) in names.iter().zip(&values) {
+                out.extend_from_slice(format!("{}: {}\r\n", name, value).as_bytes());
+            }
+            out.extend_from_slice(b"\r\n");
+        }
+        self.upload = None;
+        self.compression = None;
+        self.timings.request_sent = self.now;
+        Ok(out)
+    }
+
+    /// Stop waiting for `100 Continue` and let the body be sent, once the caller's
+    /// timeout has passed. Does nothing unless the upload is still waiting.
+    pub fn expect_timeout(&mut self) {
+        if self.expect == Some(Expect::Waiting) {
+            self.expect = Some(Expect::TimedOut);
+        }
+    }
+
+    /// For an upload with `Expect: 100-continue`: "waiting" (the body must not be
+    /// sent yet), "continue" (100 received), "final-response" (answered without
+    /// 100, body skipped) or "timeout" (sent after `expect_timeout`). Nothing for
+    /// other requests.
+    pub fn expect_outcome(&self) -> Option<String> {
+        self.expect.map(|e| e.as_str().to_string())
+    }
+
+    /// Whether a body started with `send_streaming` has not been finished or aborted.
+    pub fn is_uploading(&self) -> bool {
+        self.upload.is_some()
+    }
+
+    /// Feed received plaintext. Bytes arriving while no request is in flight are kept
+    /// for the next response.
+    pub fn feed(&mut self, data: &[u8]) -> Result<(), Error> {
+        let Some(parser) = &mut self.parser else {
+            self.leftover.extend_from_slice(data);
+            return Ok(());
+        };
+        let result = parser.feed(data);
+        if result.is_err() {
+            self.done = true;
+        }
+        if !data.is_empty() && self.timings.first_byte.is_none() {
+            self.timings.first_byte = self.now;
+        }
+        if parser.is_complete() && self.timings.last_byte.is_none() {
+            self.timings.last_byte = self.now;
+        }
+        self.update_expect();
+        result
+    }
+
+    /// Signal that the peer closed the connection. Completes a close-delimited body;
+    /// an unfinished response is an error. The connection is not reusable afterwards.
+    pub fn finish(&mut self) -> Result<(), Error> {
+        self.done = true;
+        match &mut self.parser {
+            Some(parser) => {
+                let result = parser.finish();
+                if parser.is_complete() && self.timings.last_byte.is_none() {
+                    self.timings.last_byte = self.now;
+                }
+                result
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Current time in milliseconds on the caller's clock, stamped on the phases
+    /// the calls that follow reach (see `timings`). Without it they stay null.
+    pub fn set_now(&mut self, now_ms: f64) {
+        self.now = Some(now_ms);
+    }
+
+    /// TLS handshake of this connection, e.g. `TlsConnection::timings()`,
+    /// reported in the timings of its first request only.
+    pub fn set_tls_timings(&mut self, timings: &Timings) {
+        self.setup = Timings::handshake(timings.tls_start, timings.tls_end);
+        if self.exchanges == 0 && self.parser.is_some() {
+            self.timings.tls_start = timings.tls_start;
+            self.timings.tls_end = timings.tls_end;
+        }
+    }
+
+    /// Phases of the exchange in flight, or of the last one: queued and
+    /// request_sent at `send` (`finish_body` for a streamed body), first_byte at
+    /// the first `feed`, last_byte once the response is complete.
+    pub fn timings(&self) -> Timings {
+        self.timings
+    }
+
+    /// Next event of the response in flight, or nothing if more input is needed.
+    pub fn next_event(&mut self) -> Option<ParserEvent> {
+        self.poll_event().map(ParserEvent::from)
+    }
+
+    /// Whether no exchange is in flight, i.e. `send` may be called.
+    pub fn is_idle(&self) -> bool {
+        self.parser.is_none()
+    }
+
+    /// Whether another request may be sent on this connection once it is idle: no
+    /// Connection: close on either side, HTTP/1.0 only with keep-alive, no close or
+    /// error so far, and no protocol switch.
+    pub fn is_reusable(&self) -> bool {
+        !self.done
+    }
+
+    /// Exchanges completed on this connection.
+    pub fn exchanges(&self) -> u32 {
+        self.exchanges
+    }
+}
+
+impl Http1Connection {
+    /// Rust-side `next_event`.
+    pub fn poll_event(&mut self) -> Option<ResponseEvent> {
+        let parser = self.parser.as_mut()?;
+        let event = parser.poll_event()?;
+        match &event {
+            ResponseEvent::Head(head) => {
+                self.response_persistent = head.status != 101
+                    && persistent(head.version, head.headers.get_all("connection").collect());
+                self.response_status = head.status;
+                if head.status >= 300 {
+                    self.abort_upload(head.status);
+                }
+            }
+            ResponseEvent::Complete => {
+                self.leftover = parser.take_leftover();
+                self.parser = None;
+                self.exchanges += 1;
+                self.abort_upload(self.response_status);
+                if !(self.request_persistent && self.response_persistent) {
+                    self.done = true;
+                }
+            }
+            ResponseEvent::Informational(_)
+            | ResponseEvent::Body(_)
+            | ResponseEvent::Trailers(_) => {}
+        }
+        Some(event)
+    }
+
+    fn upload_state(&mut self) -> Result<&mut Option<u64>, Error> {
+        if let Some(status) = self.upload_aborted {
+            return Err(Error::UploadAborted { status });
+        }
+        if self.expect == Some(Expect::Waiting) {
+            return Err(Error::Upload("waiting for 100 Continue"));
+        }
+        self.upload
+            .as_mut()
+            .ok_or(Error::Upload("no streamed body in progress"))
+    }
+
+    /// Leave the waiting state once the parser has seen `100 Continue` or a final head.
+    fn update_expect(&mut self) {
+        if self.expect != Some(Expect::Waiting) {
+            return;
+        }
+        let Some(parser) = &self.parser else {
+            return;
+        };
+        if parser.continue_received() {
+            self.expect = Some(Expect::Continued);
+        } else if let Some(status) = parser.final_status() {
+            self.expect = Some(Expect::FinalResponse);
+            self.abort_upload(status);
+        }
+    }
+
+    /// The server answered before the streamed body was finished: stop sending
+    /// it. The connection is left mid-body, so it cannot carry another request.
+    fn abort_upload(&mut self, status: u16) {
+        if self.upload.take().is_some() {
+            self.compression = None;
+            self.upload_aborted = Some(status);
+            self.done = true;
+        }
+    }
+}
+
+/// RFC 9112 §9.3: whether a message with these Connection values lets the
+/// connection persist.
+pub(crate) fn persistent(version: Version, connection: Vec<&str>) -> bool {
+    let has = |token: &str| {
+        connection
+            .iter()
+            .flat_map(|v| v.split(','))
+            .any(|t| t.trim().eq_ignore_ascii_case(token))
+    };
+    match version {
+        _ if has("close") => false,
+        Version::Http11 => true,
+        Version::Http10 => has("keep-alive"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http1::ChunkedDecoder;
+
+    fn get(path: &str) -> RequestBuilder {
+        let mut request = RequestBuilder::new("GET", path).unwrap();
+        request.set_host("example.com").unwrap();
+        request
+    }
+
+    fn body_of(conn: &mut Http1Connection) -> (u16, Vec<u8>, bool) {
+        let (mut status, mut body, mut complete) = (0, Vec::new(), false);
+        while let Some(event) = conn.poll_event() {
+            match event {
+                ResponseEvent::Head(head) => status = head.status,
+                ResponseEvent::Body(data) => body.extend(data),
+                ResponseEvent::Informational(_) | ResponseEvent::Trailers(_) => {}
+                ResponseEvent::Complete => complete = true,
+            }
+        }
+        (status, body, complete)
+    }
+
+    #[test]
+    fn sequential_requests_share_the_connection() {
+        let mut conn = Http1Connection::new();
+        assert!(conn.is_idle() && conn.is_reusable());
+        let bytes = conn.send(&get("/a")).unwrap();
+        assert!(bytes.starts_with(b"GET /a HTTP/1.1\r\nHost: example.com\r\n"));
+        assert_eq!(conn.send(&get("/b")), Err(Error::RequestInFlight));
+
+        // Both responses in one read: the second is kept for the next exchange
+        conn.feed(b"HTTP/1.1 200 OK\r\nContent-Length: 1\r\n\r\na")
+            .unwrap();
+        conn.feed(
+            b"HTTP/1.1 404 Not Found\r\nTransfer-Encoding: chunked\r\n\r\n1\r\nb\r\n0\r\n\r\n",
+        )
+        .unwrap();
+        // Complete but not drained yet
+        assert_eq!(conn.send(&get("/b")), Err(Error::RequestInFlight));
+        assert_eq!(body_of(&mut conn), (200, b"a".to_vec(), true));
+        assert!(conn.is_idle() && conn.is_reusable());
+
+        conn.send(&get("/b")).unwrap();
+        assert_eq!(body_of(&mut conn), (404, b"b".to_vec(), true));
+
+        let mut head = RequestBuilder::new("HEAD", "/c").unwrap();
+        head.set_host("example.com").unwrap();
+        conn.send(&head).unwrap();
+        conn.feed(b"HTTP/1.1 200 OK\r\nContent-Length: 99\r\n\r\n")
+            .unwrap();
+        assert_eq!(body_of(&mut conn), (200, Vec::new(), true));
+        assert_eq!(conn.exchanges(), 3);
+        assert!(conn.is_reusable());
+    }
+
+    #[test]
+    fn exchanges_are_timed_on_the_caller_clock() {
+        let mut conn = Http1Connection::new();
+        conn.set_tls_timings(&Timings::handshake(Some(1.0), Some(4.0)));
+        conn.set_now(10.0);
+        conn.send(&get("/a")).unwrap();
+        conn.set_now(30.0);
+        conn.feed(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\na")
+            .unwrap();
+        assert_eq!(conn.timings().last_byte(), None);
+        conn.set_now(35.0);
+        conn.feed(b"b").unwrap();
+        assert_eq!(
+            conn.timings().to_json(),
+            r#"{"queued":10,"tlsStart":1,"tlsEnd":4,"requestSent":10,"firstByte":30,"lastByte":35}"#
+        );
+        assert_eq!(conn.timings().ttfb(), Some(20.0));
+        assert!(body_of(&mut conn).2);
+
+        // The second request on the connection did not wait for the handshake
+        conn.set_now(50.0);
+        conn.send(&get("/b")).unwrap();
+        let timed = conn.timings();
+        assert_eq!((timed.queued(), timed.tls_start()), (Some(50.0), None));
+        assert_eq!(timed.first_byte(), None);
+    }
+
+    #[test]
+    fn close_and_http10_end_reuse() {
+        let respond = |request: RequestBuilder, response: &[u8]| {
+            let mut conn = Http1Connection::new();
+            conn.send(&request).unwrap();
+            conn.feed(response).unwrap();
+            assert!(body_of(&mut conn).2);
+            assert!(conn.is_idle());
+            conn
+        };
+        let ok = b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n";
+        assert!(respond(get("/"), ok).is_reusable());
+        let conn = respond(
+            get("/"),
+            b"HTTP/1.1 200 OK\r\nConnection: Close\r\nContent-Length: 0\r\n\r\n",
+        );
+        assert!(!conn.is_reusable());
+        assert!(!respond(get("/"), b"HTTP/1.0 200 OK\r\nContent-Length: 0\r\n\r\n").is_reusable());
+        assert!(respond(
+            get("/"),
+            b"HTTP/1.0 200 OK\r\nConnection: keep-alive\r\nContent-Length: 0\r\n\r\n"
+        )
+        .is_reusable());
+        assert!(!respond(
+            get("/"),
+            b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\r\n"
+        )
+        .is_reusable());
+
+        let mut request = get("/");
+        request.header("Connection", "keep-alive, close").unwrap();
+        let mut conn = respond(request, ok);
+        assert!(!conn.is_reusable());
+        assert_eq!(conn.send(&get("/")), Err(Error::ConnectionNotReusable));
+
+        let mut request = get("/");
+        request.set_version("HTTP/1.0").unwrap();
+        assert!(!respond(request.clone(), ok).is_reusable());
+        request.header("Connection", "Keep-Alive").unwrap();
+        assert!(respond(request, ok).is_reusable());
+    }
+
+    fn post(path: &str) -> RequestBuilder {
+        let mut request = RequestBuilder::new("POST", path).unwrap();
+        request.set_host("example.com").unwrap();
+        request
+    }
+
+    #[test]
+    fn streamed_uploads() {
+        // 50 MB in 16 KiB pieces, decoded back chunk by chunk
+        let mut conn = Http1Connection::new();
+        let head = conn.send_streaming(&post("/upload"), None).unwrap();
+        assert!(head.ends_with(b"\r\nTransfer-Encoding: chunked\r\n\r\n"));
+        let piece: Vec<u8> = (0..16 * 1024).map(|i| i as u8).collect();
+        let mut decoder = ChunkedDecoder::new(1024);
+        let mut decoded = 0;
+        for _ in 0..50 * 64 {
+            let framed = conn.write_body_chunk(&piece).unwrap();
+            let mut out = Vec::new();
+            assert_eq!(
+                decoder.decode(&framed, &mut out).unwrap().consumed,
+                framed.len()
+            );
+            assert_eq!(out, piece);
+            decoded += out.len();
+        }
+        assert!(conn.write_body_chunk(&[]).unwrap().is_empty());
+        let last = conn.finish_body().unwrap();
+        assert!(decoder.decode(&last, &mut Vec::new()).unwrap().done);
+        assert_eq!(decoded, 50 << 20);
+        assert!(!conn.is_uploading());
+        conn.feed(b"HTTP/1.1 201 Created\r\nContent-Length: 0\r\n\r\n")
+            .unwrap();
+        assert_eq!(body_of(&mut conn), (201, Vec::new(), true));
+        assert!(conn.is_reusable());
+
+        // A Content-Length body must have exactly that many bytes
+        let head = conn.send_streaming(&post("/upload"), Some(5)).unwrap();
+        assert!(head.ends_with(b"\r\nContent-Length: 5\r\n\r\n"));
+        assert_eq!(conn.write_body_chunk(b"abc").unwrap(), b"abc");
+        assert!(matches!(conn.finish_body(), Err(Error::Upload(_))));
+        assert!(matches!(
+            conn.write_body_chunk(b"defg"),
+            Err(Error::Upload(_))
+        ));
+        assert_eq!(conn.write_body_chunk(b"de").unwrap(), b"de");
+        assert!(conn.finish_body().unwrap().is_empty());
+        assert!(matches!(conn.write_body_chunk(b"f"), Err(Error::Upload(_))));
+
+        let mut conn = Http1Connection::new();
+        let mut framed = post("/");
+        framed.header("Content-Length", "3").unwrap();
+        assert!(conn.send_streaming(&framed, Some(3)).is_err());
+        let mut old = post("/");
+        old.set_version("HTTP/1.0").unwrap();
+        assert!(conn.send_streaming(&old, None).is_err());
+        assert!(conn.is_idle());
+    }
+
+    #[test]
+    fn upload_trailers() {
+        let mut request = post("/rpc");
+        assert_eq!(
+            request.header("Trailer", "grpc-status, Content-Length"),
+            Err(Error::ForbiddenTrailer("Content-Length".to_string()))
+        );
+        request.header("Trailer", "grpc-status").unwrap();
+        let mut conn = Http1Connection::new();
+        conn.send_streaming(&request, None).unwrap();
+        let mut body = conn.write_body_chunk(b"msg").unwrap();
+        let trailers = |names: &[&str]| -> (Vec<String>, Vec<String>) {
+            let names = names.iter().map(|n| n.to_string()).collect();
+            (names, vec!["0".to_string()])
+        };
+        let (names, values) = trailers(&["host"]);
+        assert_eq!(
+            conn.finish_body_with_trailers(names, values),
+            Err(Error::ForbiddenTrailer("host".to_string()))
+        );
+        let (names, values) = trailers(&["grpc-status"]);
+        let end = conn.finish_body_with_trailers(names, values).unwrap();
+        assert_eq!(end, b"0\r\ngrpc-status: 0\r\n\r\n");
+        body.extend(end);
+        let mut decoder = ChunkedDecoder::new(1024);
+        let decoded = decoder.decode(&body, &mut Vec::new()).unwrap();
+        assert_eq!(decoded.trailers.unwrap().get("grpc-status"), Some("0"));
+
+        let mut conn = Http1Connection::new();
+        conn.send_streaming(&post("/"), Some(0)).unwrap();
+        let (names, values) = trailers(&["grpc-status"]);
+        assert!(matches!(
+            conn.finish_body_with_trailers(names, values),
+            Err(Error::Upload(_))
+        ));
+    }
+
+    #[test]
+    fn early_response_aborts_the_upload() {
+        let mut conn = Http1Connection::new();
+        conn.send_streaming(&post("/upload"), Some(1000)).unwrap();
+        conn.write_body_chunk(&[0; 10]).unwrap();
+        conn.feed(b"HTTP/1.1 413 Payload Too Large\r\nContent-Length: 0\r\n\r\n")
+            .unwrap();
+        assert_eq!(body_of(&mut conn), (413, Vec::new(), true));
+        let aborted = Err(Error::UploadAborted { status: 413 });
+        assert_eq!(conn.write_body_chunk(&[0; 10]), aborted);
+        assert_eq!(conn.finish_body(), aborted);
+        assert!(!conn.is_uploading() && !conn.is_reusable());
+
+        // A successful response may stream while the body is still being sent
+        let mut conn = Http1Connection::new();
+        conn.send_streaming(&post("/echo"), None).unwrap();
+        conn.feed(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n1\r\na\r\n")
+            .unwrap();
+        assert_eq!(body_of(&mut conn), (200, b"a".to_vec(), false));
+        assert_eq!(conn.write_body_chunk(b"b").unwrap(), b"1\r\nb\r\n");
+        // ...but not once it is complete
+        conn.feed(b"0\r\n\r\n").unwrap();
+        assert_eq!(body_of(&mut conn), (0, Vec::new(), true));
+        assert_eq!(
+            conn.finish_body(),
+            Err(Error::UploadAborted { status: 200 })
+        );
+        assert!(!conn.is_reusable());
+    }
+
+    #[test]
+    fn expect_continue_outcomes() {
+        let mut request = post("/upload");
+        request.set_expect_continue(true);
+        let mut conn = Http1Connection::new();
+        let head = conn.send_streaming(&request, Some(3)).unwrap();
+        assert!(head.ends_with(b"\r\nContent-Length: 3\r\nExpect: 100-continue\r\n\r\n"));
+        assert_eq!(conn.expect_outcome().as_deref(), Some("waiting"));
+        assert_eq!(
+            conn.write_body_chunk(b"abc"),
+            Err(Error::Upload("waiting for 100 Continue"))
+        );
+        conn.feed(b"HTTP/1.1 100 Continue\r\n\r\n").unwrap();
+        assert_eq!(conn.expect_outcome().as_deref(), Some("continue"));
+        assert_eq!(conn.write_body_chunk(b"abc").unwrap(), b"abc");
+        assert!(conn.finish_body().unwrap().is_empty());
+        conn.feed(b"HTTP/1.1 201 Created\r\nContent-Length: 0\r\n\r\n")
+            .unwrap();
+        assert_eq!(body_of(&mut conn), (201, Vec::new(), true));
+        assert!(conn.is_reusable());
+
+        // Refused outright: the response is delivered and the body never sent
+        conn.send_streaming(&request, None).unwrap();
+        conn.feed(b"HTTP/1.1 417 Expectation Failed\r\nContent-Length: 0\r\n\r\n")
+            .unwrap();
+        assert_eq!(conn.expect_outcome().as_deref(), Some("final-response"));
+        assert_eq!(
+            conn.write_body_chunk(b"abc"),
+            Err(Error::UploadAborted { status: 417 })
+        );
+        assert_eq!(body_of(&mut conn), (417, Vec::new(), true));
+        assert!(!conn.is_reusable());
+
+        // No answer in time: the body goes out anyway
+        let mut conn = Http1Connection::new();
+        conn.send_streaming(&request, None).unwrap();
+        conn.expect_timeout();
+        assert_eq!(conn.expect_outcome().as_deref(), Some("timeout"));
+        assert_eq!(conn.write_body_chunk(b"a").unwrap(), b"1\r\na\r\n");
+        // A late 100 does not change the recorded path
+        conn.feed(b"HTTP/1.1 100 Continue\r\n\r\n").unwrap();
+        assert_eq!(conn.expect_outcome().as_deref(), Some("timeout"));
+
+        let mut conn = Http1Connection::new();
+        conn.send_streaming(&post("/"), None).unwrap();
+        assert_eq!(conn.expect_outcome(), None);
+    }
+
+    fn gunzip(body: &[u8]) -> Vec<u8> {
+        let mut decoder = crate::coding::GzipDecoder::new(u64::MAX);
+        let mut out = Vec::new();
+        decoder.decode(body, &mut out).unwrap();
+        decoder.finish().unwrap();
+        out
+    }
+
+    fn dechunk(body: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        assert!(
+            ChunkedDecoder::new(1024)
+                .decode(body, &mut out)
+                .unwrap()
+                .done
+        );
+        out
+    }
+
+    #[test]
+    fn compressed_uploads() {
+        let mut request = post("/upload");
+        request.set_compress_body("gzip").unwrap();
+        request.set_compress_buffer_limit(1000);
+        let piece = b"0123456789".repeat(60);
+
+        // Ends within the buffer limit: sent whole with a Content-Length
+        let mut conn = Http1Connection::new();
+        assert!(conn.send_streaming(&request, None).unwrap().is_empty());
+        assert!(conn.write_body_chunk(&piece).unwrap().is_empty());
+        let sent = conn.finish_body().unwrap();
+        let split = sent.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        let head = format!(
+            "POST /upload HTTP/1.1\r\nHost: example.com\r\nContent-Length: {}\r\nContent-Encoding: gzip\r\n\r\n",
+            sent.len() - split
+        );
+        assert_eq!(String::from_utf8_lossy(&sent[..split]), head);
+        assert_eq!(gunzip(&sent[split..]), piece);
+        conn.feed(b"HTTP/1.1 201 Created\r\nContent-Length: 0\r\n\r\n")
+            .unwrap();
+        assert_eq!(body_of(&mut conn), (201, Vec::new(), true));
+
+        // Outgrows it: the head goes out chunked with what was held back
+        assert!(conn.send_streaming(&request, None).unwrap().is_empty());
+        assert!(conn.write_body_chunk(&piece).unwrap().is_empty());
+        let mut sent = conn.write_body_chunk(&piece).unwrap();
+        assert!(sent.starts_with(
+            b"POST /upload HTTP/1.1\r\nHost: example.com\r\n\
+              Transfer-Encoding: chunked\r\nContent-Encoding: gzip\r\n\r\n"
+        ));
+        sent.extend(conn.write_body_chunk(&piece).unwrap());
+        sent.extend(conn.finish_body().unwrap());
+        let split = sent.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        assert_eq!(gunzip(&dechunk(&sent[split..])), piece.repeat(3));
+
+        // With 100-continue the head is sent at once, and the body chunked
+        let mut waiting = request.clone();
+        waiting.set_expect_continue(true);
+        let mut conn = Http1Connection::new();
+        let head = conn.send_streaming(&waiting, None).unwrap();
+        assert!(head.ends_with(
+            b"Transfer-Encoding: chunked\r\nExpect: 100-continue\r\nContent-Encoding: gzip\r\n\r\n"
+        ));
+        assert_eq!(
+            conn.write_body_chunk(&piece),
+            Err(Error::Upload("waiting for 100 Continue"))
+        );
+        conn.feed(b"HTTP/1.1 100 Continue\r\n\r\n").unwrap();
+        let mut body = conn.write_body_chunk(&piece).unwrap();
+        body.extend(conn.finish_body().unwrap());
+        assert_eq!(gunzip(&dechunk(&body)), piece);
+
+        // No length up front, and no compressing twice
+        let mut conn = Http1Connection::new();
+        assert!(matches!(
+            conn.send_streaming(&request, Some(600)),
+            Err(Error::Upload(_))
+        ));
+        let mut encoded = request.clone();
+        encoded.header("Content-Encoding", "zstd").unwrap();
+        assert_eq!(
+            conn.send_streaming(&encoded, None),
+            Err(Error::AlreadyEncoded("zstd".into()))
+        );
+        let mut old = request.clone();
+        old.set_version("HTTP/1.0").unwrap();
+        conn.send_streaming(&old, None).unwrap();
+        conn.write_body_chunk(&piece).unwrap();
+        assert!(matches!(
+            conn.write_body_chunk(&piece),
+            Err(Error::Upload(_))
+        ));
+    }
+
+    #[test]
+    fn close_delimited_bodies_and_errors() {
+        let mut conn = Http1Connection::new();
+        conn.send(&get("/")).unwrap();
+        conn.feed(b"HTTP/1.1 200 OK\r\n\r\nuntil close").unwrap();
+        assert_eq!(conn.poll_event().map(|_| ()), Some(()));
+        conn.finish().unwrap();
+        assert_eq!(body_of(&mut conn), (0, b"until close".to_vec(), true));
+        assert!(conn.is_idle() && !conn.is_reusable());
+
+        let mut conn = Http1Connection::new();
+        conn.send(&get("/")).unwrap();
+        assert_eq!(conn.feed(b"garbage\r\n\r\n"), Err(Error::InvalidStatusLine));
+        assert!(!conn.is_reusable());
+        assert_eq!(conn.finish(), Err(Error::InvalidStatusLine));
+    }
+}
@@ -0,0 +1,27 @@
+//! HTTP/1.1 request serialization and response parsing.
+
+mod chunked;
+mod connection;
+mod request;
+mod response;
+mod tunnel;
+
+pub(crate) use chunked::{write_chunk, LAST_CHUNK};
+pub use chunked::{ChunkedDecoder, Decoded, DEFAULT_MAX_CHUNK_LINE};
+pub(crate) use connection::persistent;
+pub use connection::Http1Connection;
+pub use request::{HostPlacement, RequestBuilder, Version, DEFAULT_COMPRESS_BUFFER_LIMIT};
+pub use response::{
+    ParserEvent, ResponseEvent, ResponseHead, ResponseParser, DEFAULT_MAX_HEADER_SIZE,
+};
+pub use tunnel::ProxyTunnel;
+
+/// RFC 9110 `tchar`.
+pub(crate) fn is_tchar(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+/// Non-empty RFC 9110 `token`.
+pub(crate) fn is_token(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(is_tchar)
+}
@@ -0,0 +1,791 @@
+use wasm_bindgen::prelude::*;
+
+use super::is_token;
+use crate::coding::{accept_encoding, GzipEncoder};
+use crate::cookie::CookieJar;
+use crate::error::Error;
+use crate::headers::{validate_trailer, Headers};
+use crate::sse::SseParser;
+
+/// HTTP version written on the request line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+impl Version {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Version::Http10 => "HTTP/1.0",
+            Version::Http11 => "HTTP/1.1",
+        }
+    }
+}
+
+/// Where the Host header is emitted when it was set via `set_host` rather than `header`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HostPlacement {
+    /// Before every other header (what browsers do)
+    First,
+    /// Before the header currently at this index; past the end means last
+    At(usize),
+    /// After every other header
+    Last,
+    /// Never emitted, even for HTTP/1.1
+    Omit,
+}
+
+/// Response size limits set on a request; unset ones keep the parser's defaults.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct ResponseLimits {
+    pub(crate) header_size: Option<usize>,
+    pub(crate) body_size: Option<u64>,
+    pub(crate) decoded_size: Option<u64>,
+}
+
+/// Default for `RequestBuilder::set_compress_buffer_limit`.
+pub const DEFAULT_COMPRESS_BUFFER_LIMIT: usize = 64 * 1024;
+
+/// Byte-exact HTTP/1.1 request serializer.
+/// Headers are written in insertion order with the caller's casing; duplicates are kept.
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct RequestBuilder {
+    method: String,
+    target: String,
+    version: Version,
+    headers: Vec<(String, String)>,
+    host: Option<String>,
+    host_placement: HostPlacement,
+    lowercase_names: bool,
+    auto_content_length: bool,
+    auto_decompress: bool,
+    expect_continue: bool,
+    response_limits: ResponseLimits,
+    /// The body is written separately after the head (see `MultipartBuilder`)
+    streamed_body: bool,
+    body: Vec<u8>,
+    /// Gzip the body (see `set_compress_body`)
+    compress_body: bool,
+    compress_buffer_limit: usize,
+    /// `body` gzipped, while compression is on and the body is not empty
+    encoded_body: Option<Vec<u8>>,
+}
+
+#[wasm_bindgen]
+impl RequestBuilder {
+    /// Start a request. `target` is written verbatim (origin-form, absolute-form, etc.).
+    #[wasm_bindgen(constructor)]
+    pub fn new(method: &str, target: &str) -> Result<RequestBuilder, Error> {
+        if !is_token(method) {
+            return Err(Error::InvalidMethod(method.to_string()));
+        }
+        if target.is_empty() || target.bytes().any(|b| b <= b' ' || b == 0x7f) {
+            return Err(Error::InvalidTarget);
+        }
+        Ok(RequestBuilder {
+            method: method.to_string(),
+            target: target.to_string(),
+            version: Version::Http11,
+            headers: Vec::new(),
+            host: None,
+            host_placement: HostPlacement::First,
+            lowercase_names: false,
+            auto_content_length: true,
+            auto_decompress: false,
+            expect_continue: false,
+            response_limits: ResponseLimits::default(),
+            streamed_body: false,
+            body: Vec::new(),
+            compress_body: false,
+            compress_buffer_limit: DEFAULT_COMPRESS_BUFFER_LIMIT,
+            encoded_body: None,
+        })
+    }
+
+    /// Set the request-line version: "HTTP/1.1" (default) or "HTTP/1.0".
+    pub fn set_version(&mut self, version: &str) -> Result<(), Error> {
+        self.version = match version {
+            "HTTP/1.1" => Version::Http11,
+            "HTTP/1.0" => Version::Http10,
+            _ => return Err(Error::InvalidVersion(version.to_string())),
+        };
+        Ok(())
+    }
+
+    /// Append a header. Order, casing and duplicates are preserved exactly.
+    /// The fields a `Trailer` header announces must be allowed in trailers.
+    pub fn header(&mut self, name: &str, value: &str) -> Result<(), Error> {
+        if !is_token(name) {
+            return Err(Error::InvalidHeaderName(name.to_string()));
+        }
+        validate_value(name, value)?;
+        if name.eq_ignore_ascii_case("trailer") {
+            for field in value.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+                validate_trailer(field, "")?;
+            }
+        }
+        self.headers.push((name.to_string(), value.to_string()));
+        Ok(())
+    }
+
+    /// Set the Host value to be placed according to the host placement.
+    /// Ignored if a Host header was also added through `header`.
+    pub fn set_host(&mut self, host: &str) -> Result<(), Error> {
+        validate_value("Host", host)?;
+        self.host = Some(host.to_string());
+        Ok(())
+    }
+
+    /// Host placement by name: "first" (default), "last" or "omit".
+    pub fn set_host_placement(&mut self, placement: &str) -> Result<(), Error> {
+        self.host_placement = match placement {
+            "first" => HostPlacement::First,
+            "last" => HostPlacement::Last,
+            "omit" => HostPlacement::Omit,
+            _ => return Err(Error::InvalidHeaderValue("host placement".to_string())),
+        };
+        Ok(())
+    }
+
+    /// Place Host before the header at `index` (0 is the same as "first").
+    pub fn set_host_index(&mut self, index: usize) {
+        self.host_placement = HostPlacement::At(index);
+    }
+
+    /// Lowercase every header name on output (off by default).
+    pub fn set_lowercase_names(&mut self, enabled: bool) {
+        self.lowercase_names = enabled;
+    }
+
+    /// Append `Content-Length` when the body is non-empty and no framing header was given
+    /// (on by default). Disable to send the headers exactly as added.
+    pub fn set_auto_content_length(&mut self, enabled: bool) {
+        self.auto_content_length = enabled;
+    }
+
+    /// Advertise the compiled-in content codings and have `ResponseParser::for_request`
+    /// decode the response (off by default). Appends `Accept-Encoding` after the other
+    /// headers unless one was added explicitly, e.g. to match a browser profile.
+    pub fn set_auto_decompress(&mut self, enabled: bool) {
+        self.auto_decompress = enabled;
+    }
+
+    /// Ask the server to confirm with `100 Continue` before the body is sent
+    /// (off by default): appends `Expect: 100-continue` after the other headers
+    /// unless an Expect header was added explicitly. HTTP/1.1 only. See
+    /// `Http1Connection::send_streaming` for how the body is held back.
+    pub fn set_expect_continue(&mut self, enabled: bool) {
+        self.expect_continue = enabled;
+    }
+
+    /// Limit the response head (status line plus headers) to `limit` bytes for
+    /// the parser `ResponseParser::for_request` creates (default 64 KiB).
+    pub fn set_max_response_header_size(&mut self, limit: usize) {
+        self.response_limits.header_size = Some(limit);
+    }
+
+    /// Limit the response body, as received, to `limit` bytes (default unlimited).
+    pub fn set_max_response_body_size(&mut self, limit: u64) {
+        self.response_limits.body_size = Some(limit);
+    }
+
+    /// Limit the response body after decompression to `limit` bytes (default 64 MiB).
+    pub fn set_max_decoded_size(&mut self, limit: u64) {
+        self.response_limits.decoded_size = Some(limit);
+    }
+
+    /// Append a Cookie header with the cookies `jar` holds for `url` (unix seconds
+    /// `now`), if there are any. Call it where the header belongs in the order.
+    pub fn add_cookies(&mut self, jar: &CookieJar, url: &str, now: f64) -> Result<(), Error> {
+        match jar.cookie_header(url, now)? {
+            Some(cookies) => self.header("Cookie", &cookies),
+            None => Ok(()),
+        }
+    }
+
+    /// Set Last-Event-ID to the last event ID `parser` saw, to resume an event stream
+    /// where it broke off. Replaces the value of a Last-Event-ID header added
+    /// earlier, keeping its position; does nothing while the ID is empty.
+    pub fn add_last_event_id(&mut self, parser: &SseParser) -> Result<(), Error> {
+        let id = parser.last_event_id();
+        if id.is_empty() {
+            return Ok(());
+        }
+        validate_value("Last-Event-ID", &id)?;
+        if !self.replace_header("last-event-id", &id) {
+            self.header("Last-Event-ID", &id)?;
+        }
+        Ok(())
+    }
+
+    /// Set the request body, written verbatim after the header block (gzipped
+    /// with `set_compress_body`).
+    pub fn set_body(&mut self, body: Vec<u8>) {
+        self.body = body;
+        self.encode_body();
+    }
+
+    /// Content-code the body: "gzip", or "identity" (default) to send it as is.
+    /// Appends `Content-Encoding: gzip` after the other headers and frames the
+    /// compressed body; an empty body is left alone. `to_bytes` refuses with
+    /// `AlreadyEncoded` if a Content-Encoding header was added, as the body is
+    /// encoded already. A body streamed with `Http1Connection::send_streaming`
+    /// is compressed as it is written.
+    pub fn set_compress_body(&mut self, coding: &str) -> Result<(), Error> {
+        self.compress_body = match coding {
+            "gzip" => true,
+            "identity" => false,
+            _ => {
+                return Err(Error::InvalidOption {
+                    option: "compress_body",
+                    value: coding.to_string(),
+                })
+            }
+        };
+        self.encode_body();
+        Ok(())
+    }
+
+    /// For a compressed body streamed with `Http1Connection::send_streaming`: the
+    /// head is held back until `limit` uncompressed bytes have been written, so a
+    /// body that ends within them is sent whole with a Content-Length; a longer
+    /// one, or any with `Expect: 100-continue`, is chunked (default 64 KiB, 0 to
+    /// always chunk).
+    pub fn set_compress_buffer_limit(&mut self, limit: usize) {
+        self.compress_buffer_limit = limit;
+    }
+
+    /// Serialize the full request (head + body).
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let explicit_host = self.has_header("host");
+        let host = if explicit_host || self.host_placement == HostPlacement::Omit {
+            None
+        } else {
+            self.host.as_deref()
+        };
+        if self.version == Version::Http11
+            && !explicit_host
+            && host.is_none()
+            && self.host_placement != HostPlacement::Omit
+        {
+            return Err(Error::MissingHost);
+        }
+
+        let coded = self.content_coded();
+        if let Some(encoding) = self.header_value("content-encoding").filter(|_| coded) {
+            return Err(Error::AlreadyEncoded(encoding.to_string()));
+        }
+        let body = self.body();
+        let framed = self.has_header("content-length") || self.has_header("transfer-encoding");
+        if let Some(declared) = self
+            .header_value("content-length")
+            .filter(|_| !self.streamed_body)
+        {
+            if declared.trim().parse::<usize>().ok() != Some(body.len()) {
+                return Err(Error::ContentLengthMismatch {
+                    declared: declared.to_string(),
+                    actual: body.len(),
+                });
+            }
+        }
+        let content_length = (self.auto_content_length && !framed && !body.is_empty())
+            .then(|| body.len().to_string());
+
+        let host_index = match self.host_placement {
+            HostPlacement::First => 0,
+            HostPlacement::At(i) => i.min(self.headers.len()),
+            HostPlacement::Last | HostPlacement::Omit => self.headers.len(),
+        };
+
+        let mut out = Vec::with_capacity(self.head_len_hint() + body.len());
+        out.extend_from_slice(self.method.as_bytes());
+        out.push(b' ');
+        out.extend_from_slice(self.target.as_bytes());
+        out.push(b' ');
+        out.extend_from_slice(self.version.as_str().as_bytes());
+        out.extend_from_slice(b"\r\n");
+
+        for (i, (name, value)) in self.headers.iter().enumerate() {
+            if i == host_index {
+                if let Some(h) = host {
+                    self.write_header(&mut out, "Host", h);
+                }
+            }
+            self.write_header(&mut out, name, value);
+        }
+        if host_index == self.headers.len() {
+            if let Some(h) = host {
+                self.write_header(&mut out, "Host", h);
+            }
+        }
+        if self.auto_decompress && !self.has_header("accept-encoding") {
+            self.write_header(&mut out, "Accept-Encoding", &accept_encoding());
+        }
+        if self.expect_continue && self.version == Version::Http11 && !self.has_header("expect") {
+            self.write_header(&mut out, "Expect", "100-continue");
+        }
+        if coded {
+            self.write_header(&mut out, "Content-Encoding", "gzip");
+        }
+        if let Some(len) = &content_length {
+            self.write_header(&mut out, "Content-Length", len);
+        }
+
+        out.extend_from_slice(b"\r\n");
+        out.extend_from_slice(body);
+        Ok(out)
+    }
+}
+
+impl RequestBuilder {
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    pub fn auto_decompress(&self) -> bool {
+        self.auto_decompress
+    }
+
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    pub(crate) fn response_limits(&self) -> ResponseLimits {
+        self.response_limits
+    }
+
+    /// Whether the request carries `Expect: 100-continue`, from `set_expect_continue`
+    /// or added explicitly.
+    pub fn expects_continue(&self) -> bool {
+        self.version == Version::Http11
+            && (self.expect_continue
+                || self
+                    .header_all("expect")
+                    .iter()
+                    .any(|v| v.trim().eq_ignore_ascii_case("100-continue")))
+    }
+
+    /// Every value of a header added with `header`, in order.
+    pub(crate) fn header_all(&self, lower_name: &str) -> Vec<&str> {
+        self.headers
+            .iter()
+            .filter(|(n, _)| n.eq_ignore_ascii_case(lower_name))
+            .map(|(_, v)| v.as_str())
+            .collect()
+    }
+
+    /// Replace the value of the first header named `lower_name` in place, keeping its
+    /// position and casing. Returns false if there is no such header.
+    pub(crate) fn replace_header(&mut self, lower_name: &str, value: &str) -> bool {
+        match self
+            .headers
+            .iter_mut()
+            .find(|(n, _)| n.eq_ignore_ascii_case(lower_name))
+        {
+            Some((_, v)) => {
+                *v = value.to_string();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The header fields `to_bytes` writes, including the ones it adds (Host,
+    /// Accept-Encoding, Expect, Content-Encoding, Content-Length), in no particular order.
+    pub(crate) fn fields(&self) -> Headers {
+        let mut fields = Headers::new();
+        for (name, value) in &self.headers {
+            fields.push(name.as_str(), value.as_str());
+        }
+        if let Some(host) = self.host.as_deref() {
+            if !self.has_header("host") && self.host_placement != HostPlacement::Omit {
+                fields.push("Host", host);
+            }
+        }
+        if self.auto_decompress && !self.has_header("accept-encoding") {
+            fields.push("Accept-Encoding", accept_encoding());
+        }
+        if self.expect_continue && self.version == Version::Http11 && !self.has_header("expect") {
+            fields.push("Expect", "100-continue");
+        }
+        if self.content_coded() {
+            fields.push("Content-Encoding", "gzip");
+        }
+        let framed = self.has_header("content-length") || self.has_header("transfer-encoding");
+        if self.auto_content_length && !framed && !self.body().is_empty() {
+            fields.push("Content-Length", self.body().len().to_string());
+        }
+        fields
+    }
+
+    /// The body as sent, i.e. compressed with `set_compress_body`.
+    pub(crate) fn body(&self) -> &[u8] {
+        self.encoded_body.as_deref().unwrap_or(&self.body)
+    }
+
+    /// Whether `set_compress_body` asked for gzip.
+    pub(crate) fn compress_body(&self) -> bool {
+        self.compress_body
+    }
+
+    pub(crate) fn compress_buffer_limit(&self) -> usize {
+        self.compress_buffer_limit
+    }
+
+    /// The body follows the head separately, so a declared Content-Length is not
+    /// checked against `set_body`.
+    pub(crate) fn set_streamed_body(&mut self) {
+        self.streamed_body = true;
+    }
+
+    /// Rust-side equivalent of `set_host_placement` / `set_host_index`.
+    pub fn host_placement(&mut self, placement: HostPlacement) {
+        self.host_placement = placement;
+    }
+
+    /// Whether the body is gzipped: compression is on and there is a body, or one
+    /// is streamed after the head.
+    fn content_coded(&self) -> bool {
+        self.compress_body && (self.streamed_body || !self.body.is_empty())
+    }
+
+    fn encode_body(&mut self) {
+        self.encoded_body = (self.compress_body && !self.body.is_empty()).then(|| {
+            let mut encoder = GzipEncoder::new();
+            let mut out = encoder.encode(&self.body);
+            out.extend(encoder.finish());
+            out
+        });
+    }
+
+    fn has_header(&self, lower_name: &str) -> bool {
+        self.header_value(lower_name).is_some()
+    }
+
+    fn header_value(&self, lower_name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(lower_name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn head_len_hint(&self) -> usize {
+        let headers: usize = self
+            .headers
+            .iter()
+            .map(|(n, v)| n.len() + v.len() + 4)
+            .sum();
+        self.method.len() + self.target.len() + 64 + headers
+    }
+
+    fn write_header(&self, out: &mut Vec<u8>, name: &str, value: &str) {
+        if self.lowercase_names {
+            out.extend(name.bytes().map(|b| b.to_ascii_lowercase()));
+        } else {
+            out.extend_from_slice(name.as_bytes());
+        }
+        out.extend_from_slice(b": ");
+        out.extend_from_slice(value.as_bytes());
+        out.extend_from_slice(b"\r\n");
+    }
+}
+
+fn validate_value(name: &str, value: &str) -> Result<(), Error> {
+    let bytes = value.as_bytes();
+    for (i, &b) in bytes.iter().enumerate() {
+        match b {
+            b'\r'
+                if bytes.get(i + 1) == Some(&b'\n')
+                    && matches!(bytes.get(i + 2), Some(b' ' | b'\t')) =>
+            {
+                return Err(Error::ObsFold(name.to_string()));
+            }
+            b'\r' | b'\n' | 0 => return Err(Error::InvalidHeaderValue(name.to_string())),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(req: &RequestBuilder) -> String {
+        String::from_utf8(req.to_bytes().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn headers_keep_insertion_order_casing_and_duplicates() {
+        let mut req = RequestBuilder::new("GET", "/a?b=1").unwrap();
+        req.set_host("example.com").unwrap();
+        req.header("user-agent", "x").unwrap();
+        req.header("Accept", "*/*").unwrap();
+        req.header("X-Dup", "1").unwrap();
+        req.header("x-dup", "2").unwrap();
+        assert_eq!(
+            text(&req),
+            "GET /a?b=1 HTTP/1.1\r\nHost: example.com\r\nuser-agent: x\r\nAccept: */*\r\n\
+             X-Dup: 1\r\nx-dup: 2\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn odd_casings_are_written_verbatim_and_matched_case_insensitively() {
+        let mut req = RequestBuilder::new("POST", "/").unwrap();
+        req.header("hOsT", "example.com").unwrap();
+        req.header("CoNtEnT-lEnGtH", "2").unwrap();
+        req.header("ACCEPT-encoding", "gzip").unwrap();
+        req.set_host("ignored.example").unwrap();
+        req.set_auto_decompress(true);
+        req.set_body(b"hi".to_vec());
+        // No second Host, Content-Length or Accept-Encoding is added
+        assert_eq!(
+            text(&req),
+            "POST / HTTP/1.1\r\nhOsT: example.com\r\nCoNtEnT-lEnGtH: 2\r\n\
+             ACCEPT-encoding: gzip\r\n\r\nhi"
+        );
+        req.set_body(b"longer".to_vec());
+        assert!(matches!(
+            req.to_bytes(),
+            Err(Error::ContentLengthMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn cookies_from_a_jar() {
+        let mut jar = CookieJar::new();
+        jar.set_cookie("https://example.com/", "a=1", 0.0).unwrap();
+        let mut req = RequestBuilder::new("GET", "/").unwrap();
+        req.set_host("example.com").unwrap();
+        req.header("Accept", "*/*").unwrap();
+        req.add_cookies(&jar, "https://example.com/x", 0.0).unwrap();
+        req.add_cookies(&jar, "https://other.example/", 0.0)
+            .unwrap();
+        assert_eq!(
+            text(&req),
+            "GET / HTTP/1.1\r\nHost: example.com\r\nAccept: */*\r\nCookie: a=1\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn last_event_id_from_a_parser() {
+        let mut parser = SseParser::new();
+        let mut req = RequestBuilder::new("GET", "/events").unwrap();
+        req.set_host("example.com").unwrap();
+        req.add_last_event_id(&parser).unwrap();
+        req.header("Accept", "text/event-stream").unwrap();
+        assert_eq!(
+            text(&req),
+            "GET /events HTTP/1.1\r\nHost: example.com\r\nAccept: text/event-stream\r\n\r\n"
+        );
+        parser.feed(b"id: 7\ndata: x\n\n").unwrap();
+        req.add_last_event_id(&parser).unwrap();
+        parser.feed(b"id: 8\n\n").unwrap();
+        req.add_last_event_id(&parser).unwrap();
+        assert_eq!(
+            text(&req),
+            "GET /events HTTP/1.1\r\nHost: example.com\r\nAccept: text/event-stream\r\n\
+             Last-Event-ID: 8\r\n\r\n"
+        );
+        parser.set_last_event_id("a\nb");
+        assert!(req.add_last_event_id(&parser).is_err());
+    }
+
+    #[test]
+    fn host_placement_variants() {
+        let mut req = RequestBuilder::new("GET", "/").unwrap();
+        req.set_host("h").unwrap();
+        req.header("A", "1").unwrap();
+        req.header("B", "2").unwrap();
+
+        req.set_host_index(1);
+        assert_eq!(
+            text(&req),
+            "GET / HTTP/1.1\r\nA: 1\r\nHost: h\r\nB: 2\r\n\r\n"
+        );
+
+        req.set_host_placement("last").unwrap();
+        assert_eq!(
+            text(&req),
+            "GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nHost: h\r\n\r\n"
+        );
+
+        req.host_placement(HostPlacement::At(99));
+        assert_eq!(
+            text(&req),
+            "GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nHost: h\r\n\r\n"
+        );
+
+        req.set_host_placement("omit").unwrap();
+        assert_eq!(text(&req), "GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\n\r\n");
+    }
+
+    #[test]
+    fn explicit_host_header_wins() {
+        let mut req = RequestBuilder::new("GET", "/").unwrap();
+        req.set_host("ignored").unwrap();
+        req.header("A", "1").unwrap();
+        req.header("HOST", "real").unwrap();
+        assert_eq!(text(&req), "GET / HTTP/1.1\r\nA: 1\r\nHOST: real\r\n\r\n");
+    }
+
+    #[test]
+    fn missing_host_is_rejected_for_http11_only() {
+        let req = RequestBuilder::new("GET", "/").unwrap();
+        assert_eq!(req.to_bytes(), Err(Error::MissingHost));
+
+        let mut req = RequestBuilder::new("GET", "/").unwrap();
+        req.set_version("HTTP/1.0").unwrap();
+        assert_eq!(text(&req), "GET / HTTP/1.0\r\n\r\n");
+    }
+
+    #[test]
+    fn rejects_obs_fold_and_bare_line_breaks() {
+        let mut req = RequestBuilder::new("GET", "/").unwrap();
+        assert_eq!(
+            req.header("X-Fold", "a\r\n b"),
+            Err(Error::ObsFold("X-Fold".into()))
+        );
+        assert_eq!(
+            req.header("X-Split", "a\r\nInjected: 1"),
+            Err(Error::InvalidHeaderValue("X-Split".into()))
+        );
+        assert_eq!(
+            req.header("X-Lf", "a\nb"),
+            Err(Error::InvalidHeaderValue("X-Lf".into()))
+        );
+        assert_eq!(
+            req.header("Bad Name", "v"),
+            Err(Error::InvalidHeaderName("Bad Name".into()))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_request_line() {
+        assert!(matches!(
+            RequestBuilder::new("G ET", "/"),
+            Err(Error::InvalidMethod(_))
+        ));
+        assert!(matches!(
+            RequestBuilder::new("GET", "/a b"),
+            Err(Error::InvalidTarget)
+        ));
+        let mut req = RequestBuilder::new("GET", "/").unwrap();
+        assert!(matches!(
+            req.set_version("HTTP/2"),
+            Err(Error::InvalidVersion(_))
+        ));
+    }
+
+    #[test]
+    fn lowercase_names_on_request() {
+        let mut req = RequestBuilder::new("GET", "/").unwrap();
+        req.set_host("h").unwrap();
+        req.header("X-Mixed", "V").unwrap();
+        req.set_lowercase_names(true);
+        assert_eq!(
+            text(&req),
+            "GET / HTTP/1.1\r\nhost: h\r\nx-mixed: V\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn auto_decompress_advertises_compiled_in_codings() {
+        let mut req = RequestBuilder::new("GET", "/").unwrap();
+        req.set_host("h").unwrap();
+        req.header("Accept", "*/*").unwrap();
+        req.set_auto_decompress(true);
+        assert_eq!(
+            text(&req),
+            format!(
+                "GET / HTTP/1.1\r\nHost: h\r\nAccept: */*\r\nAccept-Encoding: {}\r\n\r\n",
+                accept_encoding()
+            )
+        );
+        #[cfg(all(feature = "brotli", feature = "zstd"))]
+        assert_eq!(accept_encoding(), "gzip, deflate, br, zstd");
+
+        // A profile's own Accept-Encoding is kept as-is
+        req.header("accept-encoding", "gzip").unwrap();
+        assert_eq!(
+            text(&req).to_lowercase().matches("accept-encoding").count(),
+            1
+        );
+    }
+
+    #[test]
+    fn body_framing() {
+        let mut req = RequestBuilder::new("POST", "/").unwrap();
+        req.set_host("h").unwrap();
+        req.set_body(b"hello".to_vec());
+        assert_eq!(
+            text(&req),
+            "POST / HTTP/1.1\r\nHost: h\r\nContent-Length: 5\r\n\r\nhello"
+        );
+
+        req.set_auto_content_length(false);
+        assert_eq!(text(&req), "POST / HTTP/1.1\r\nHost: h\r\n\r\nhello");
+
+        req.header("content-length", "4").unwrap();
+        assert_eq!(
+            req.to_bytes(),
+            Err(Error::ContentLengthMismatch {
+                declared: "4".into(),
+                actual: 5
+            })
+        );
+    }
+
+    #[test]
+    fn transfer_encoding_body_is_left_alone() {
+        let mut req = RequestBuilder::new("POST", "/").unwrap();
+        req.set_host("h").unwrap();
+        req.header("Transfer-Encoding", "chunked").unwrap();
+        req.set_body(b"5\r\nhello\r\n0\r\n\r\n".to_vec());
+        assert_eq!(
+            text(&req),
+            "POST / HTTP/1.1\r\nHost: h\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn compressed_body() {
+        let plain = b"compress me, compress me, compress me".repeat(20);
+        let mut req = RequestBuilder::new("POST", "/").unwrap();
+        req.set_host("h").unwrap();
+        req.set_compress_body("gzip").unwrap();
+        assert_eq!(text(&req), "POST / HTTP/1.1\r\nHost: h\r\n\r\n");
+        req.set_body(plain.clone());
+        let bytes = req.to_bytes().unwrap();
+        let split = bytes.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        let body = &bytes[split..];
+        assert_eq!(
+            String::from_utf8_lossy(&bytes[..split]),
+            format!(
+                "POST / HTTP/1.1\r\nHost: h\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\n\r\n",
+                body.len()
+            )
+        );
+        assert_eq!(req.body(), body);
+        assert_eq!(req.fields().get("content-encoding"), Some("gzip"));
+        let mut decoded = Vec::new();
+        let mut decoder = crate::coding::GzipDecoder::new(u64::MAX);
+        decoder.decode(body, &mut decoded).unwrap();
+        decoder.finish().unwrap();
+        assert_eq!(decoded, plain);
+
+        assert!(matches!(
+            req.set_compress_body("br"),
+            Err(Error::InvalidOption { .. })
+        ));
+        req.header("Content-Encoding", "br").unwrap();
+        assert_eq!(req.to_bytes(), Err(Error::AlreadyEncoded("br".into())));
+        req.set_compress_body("identity").unwrap();
+        // Sent as is again, with the caller's own Content-Encoding
+        assert_eq!(req.body(), &plain[..]);
+        assert!(req.to_bytes().unwrap().ends_with(&plain));
+    }
+}
@@ -0,0 +1,1356 @@
+use std::collections::VecDeque;
+use std::mem;
+
+use wasm_bindgen::prelude::*;
+
+use super::chunked::ChunkedDecoder;
+use super::RequestBuilder;
+use super::{is_token, Version};
+use crate::coding::{DecodePlan, DecoderChain, DEFAULT_MAX_DECODED_SIZE};
+use crate::error::Error;
+use crate::headers::{latin1, Headers};
+use crate::integrity::{BodyIntegrity, Expected};
+
+/// Default cap on the size of the status line plus header block.
+pub const DEFAULT_MAX_HEADER_SIZE: usize = 64 * 1024;
+
+/// Status line and headers of a response.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResponseHead {
+    pub version: Version,
+    pub status: u16,
+    pub reason: String,
+    pub headers: Headers,
+}
+
+/// Output of [`ResponseParser`], in wire order.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ResponseEvent {
+    /// 1xx interim response other than 101 (e.g. 103 Early Hints); any number of
+    /// them may come before the final head
+    Informational(ResponseHead),
+    Head(ResponseHead),
+    Body(Vec<u8>),
+    /// Trailer fields of a chunked body (only emitted when non-empty)
+    Trailers(Headers),
+    Complete,
+}
+
+#[derive(Debug)]
+enum State {
+    Head,
+    /// Content-Length framed body with this many bytes left
+    Length(u64),
+    /// Chunked body
+    Chunked(ChunkedDecoder),
+    /// Body runs until the connection closes
+    UntilClose,
+    Done,
+}
+
+/// Incremental sans-IO HTTP/1.1 response parser.
+/// Feed it whatever `take_plaintext` returned (any split points) and drain events.
+#[wasm_bindgen]
+#[derive(Debug)]
+pub struct ResponseParser {
+    state: State,
+    /// Unparsed bytes (head in progress, or leftovers after completion)
+    buf: Vec<u8>,
+    /// How far `buf` has already been searched for the end of the head
+    head_scanned: usize,
+    max_header_size: usize,
+    head_request: bool,
+    /// A 2xx to CONNECT has no body: what follows belongs to the tunnel
+    connect_request: bool,
+    decompress: bool,
+    max_decoded_size: u64,
+    max_body_size: u64,
+    reject_unsupported_encoding: bool,
+    /// Content-Encoding decoders for the current body, when decompression applies
+    decoder: Option<DecoderChain>,
+    /// Codings that decompression was asked for but could not undo
+    undecoded_encoding: Option<String>,
+    /// Body bytes after transfer decoding, before content decoding
+    encoded_bytes: u64,
+    /// Body bytes handed out in body events
+    decoded_bytes: u64,
+    events: VecDeque<ResponseEvent>,
+    failed: Option<Error>,
+    /// A `100 Continue` interim response was skipped
+    continue_received: bool,
+    /// Status of the final response, once its head is parsed
+    final_status: Option<u16>,
+    /// From `set_expected_integrity`
+    expected_integrity: Option<Expected>,
+    verify_digest_fields: bool,
+    compute_digest: bool,
+    /// Hashes of the body in progress
+    integrity: Option<BodyIntegrity>,
+    /// Digest of the completed body, when one was computed
+    body_digest: Option<String>,
+}
+
+impl Default for ResponseParser {
+    fn default() -> Self {
+        ResponseParser {
+            state: State::Head,
+            buf: Vec::new(),
+            head_scanned: 0,
+            max_header_size: DEFAULT_MAX_HEADER_SIZE,
+            head_request: false,
+            connect_request: false,
+            decompress: false,
+            max_decoded_size: DEFAULT_MAX_DECODED_SIZE,
+            max_body_size: u64::MAX,
+            reject_unsupported_encoding: false,
+            decoder: None,
+            undecoded_encoding: None,
+            encoded_bytes: 0,
+            decoded_bytes: 0,
+            events: VecDeque::new(),
+            failed: None,
+            continue_received: false,
+            final_status: None,
+            expected_integrity: None,
+            verify_digest_fields: false,
+            compute_digest: false,
+            integrity: None,
+            body_digest: None,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl ResponseParser {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> ResponseParser {
+        ResponseParser::default()
+    }
+
+    /// Parser for the response to `request`: takes its method and response size
+    /// limits, and enables decompression if the request was built with
+    /// `set_auto_decompress`.
+    pub fn for_request(request: &RequestBuilder) -> ResponseParser {
+        let mut parser = ResponseParser::new();
+        parser.set_request_method(request.method());
+        parser.set_decompress(request.auto_decompress());
+        let limits = request.response_limits();
+        if let Some(limit) = limits.header_size {
+            parser.set_max_header_size(limit);
+        }
+        if let Some(limit) = limits.body_size {
+            parser.set_max_body_size(limit);
+        }
+        if let Some(limit) = limits.decoded_size {
+            parser.set_max_decoded_size(limit);
+        }
+        parser
+    }
+
+    /// Maximum bytes for the status line plus headers (default 64 KiB). Exceeding
+    /// it fails with `LimitExceeded` for the "header block".
+    pub fn set_max_header_size(&mut self, limit: usize) {
+        self.max_header_size = limit;
+    }
+
+    /// Method of the request this response answers; HEAD responses carry no body,
+    /// and neither do 2xx responses to CONNECT (the tunnel's bytes follow them).
+    pub fn set_request_method(&mut self, method: &str) {
+        self.head_request = method.eq_ignore_ascii_case("HEAD");
+        self.connect_request = method.eq_ignore_ascii_case("CONNECT");
+    }
+
+    /// Undo the response's Content-Encoding, so body events carry the decoded bytes.
+    /// Stacked codings (`gzip, br`) are decoded last-applied first. A body using a
+    /// coding that is not compiled in is passed through untouched and reported by
+    /// `undecoded_encoding`.
+    pub fn set_decompress(&mut self, enabled: bool) {
+        self.decompress = enabled;
+    }
+
+    /// Fail with an error instead of passing through bodies whose Content-Encoding
+    /// cannot be decoded (off by default).
+    pub fn set_reject_unsupported_encoding(&mut self, enabled: bool) {
+        self.reject_unsupported_encoding = enabled;
+    }
+
+    /// Content codings left on the body although decompression was enabled
+    /// (e.g. "compress"), or nothing if the body events are fully decoded.
+    pub fn undecoded_encoding(&self) -> Option<String> {
+        self.undecoded_encoding.clone()
+    }
+
+    /// Maximum decoded body size when decompressing (default 64 MiB). Exceeding it
+    /// fails with `LimitExceeded` for the "decoded body".
+    pub fn set_max_decoded_size(&mut self, limit: u64) {
+        self.max_decoded_size = limit;
+    }
+
+    /// Maximum body size as received, excluding chunked framing (default unlimited).
+    /// Exceeding it fails with `LimitExceeded` for the "body"; a larger Content-Length
+    /// fails as soon as the head is parsed.
+    pub fn set_max_body_size(&mut self, limit: u64) {
+        self.max_body_size = limit;
+    }
+
+    /// Check the body as body events deliver it (so after decompression, if
+    /// enabled) against subresource-integrity metadata: `sha256-<base64>`, or
+    /// sha384 / sha512, space-separated alternatives allowed. A mismatch fails the
+    /// feed that completes the body with `Integrity`, in place of the complete event.
+    pub fn set_expected_integrity(&mut self, metadata: &str) -> Result<(), Error> {
+        self.expected_integrity = Some(Expected::parse(metadata)?);
+        Ok(())
+    }
+
+    /// Check the body as received (still content-coded) against the response's
+    /// Content-Digest, and on a 200 its Repr-Digest, when present with sha-256 or
+    /// sha-512 (off by default). A mismatch fails like `set_expected_integrity`.
+    pub fn set_verify_digest_fields(&mut self, enabled: bool) {
+        self.verify_digest_fields = enabled;
+    }
+
+    /// Compute the body's SHA-256 for `body_digest` even when nothing is expected.
+    pub fn set_compute_digest(&mut self, enabled: bool) {
+        self.compute_digest = enabled;
+    }
+
+    /// Digest of the body as integrity metadata (`sha256-<base64>`, or the expected
+    /// metadata's algorithm), once the body is complete; also set on a mismatch.
+    pub fn body_digest(&self) -> Option<String> {
+        self.body_digest.clone()
+    }
+
+    /// Body bytes received so far, excluding chunked framing (still compressed, if any).
+    pub fn encoded_body_bytes(&self) -> u64 {
+        self.encoded_bytes
+    }
+
+    /// Body bytes delivered through body events so far (after decompression).
+    pub fn decoded_body_bytes(&self) -> u64 {
+        self.decoded_bytes
+    }
+
+    /// Feed received plaintext. Events become available through `next_event`.
+    pub fn feed(&mut self, data: &[u8]) -> Result<(), Error> {
+        if let Some(e) = &self.failed {
+            return Err(e.clone());
+        }
+        let result = self.process(data);
+        if let Err(e) = &result {
+            self.failed = Some(e.clone());
+        }
+        result
+    }
+
+    /// Signal that the connection was closed by the peer.
+    /// Completes a close-delimited body; any other unfinished message is an error.
+    pub fn finish(&mut self) -> Result<(), Error> {
+        if let Some(e) = &self.failed {
+            return Err(e.clone());
+        }
+        let result = match &self.state {
+            State::Done => Ok(()),
+            State::UntilClose => self.complete(),
+            State::Head => Err(Error::IncompleteHead),
+            &State::Length(remaining) => Err(Error::IncompleteBody { remaining }),
+            State::Chunked(_) => Err(Error::IncompleteChunkedBody),
+        };
+        if let Err(e) = &result {
+            self.failed = Some(e.clone());
+        }
+        result
+    }
+
+    /// Next parsed event, or nothing if more input is needed.
+    pub fn next_event(&mut self) -> Option<ParserEvent> {
+        self.poll_event().map(ParserEvent)
+    }
+
+    /// Whether the full response has been parsed.
+    pub fn is_complete(&self) -> bool {
+        matches!(self.state, State::Done)
+    }
+
+    /// Bytes received after the end of this response (next keep-alive response).
+    /// Only meaningful once `is_complete()` is true.
+    pub fn take_leftover(&mut self) -> Vec<u8> {
+        if self.is_complete() {
+            mem::take(&mut self.buf)
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+impl ResponseParser {
+    /// Rust-side `next_event`.
+    pub fn poll_event(&mut self) -> Option<ResponseEvent> {
+        self.events.pop_front()
+    }
+
+    /// Whether a `100 Continue` arrived ahead of the final response.
+    pub(crate) fn continue_received(&self) -> bool {
+        self.continue_received
+    }
+
+    /// Status of the final response, once its head has been parsed.
+    pub(crate) fn final_status(&self) -> Option<u16> {
+        self.final_status
+    }
+
+    fn process(&mut self, mut data: &[u8]) -> Result<(), Error> {
+        loop {
+            match &mut self.state {
+                State::Head => {
+                    self.buf.extend_from_slice(data);
+                    data = &[];
+                    if !self.parse_head()? {
+                        return Ok(());
+                    }
+                    // The rest of `buf` is body (or leftover) — re-process it
+                    if !self.is_complete() {
+                        let rest = mem::take(&mut self.buf);
+                        return self.process(&rest);
+                    }
+                }
+                State::Length(remaining) => {
+                    let remaining = *remaining;
+                    if data.is_empty() {
+                        return Ok(());
+                    }
+                    let n = remaining.min(data.len() as u64) as usize;
+                    self.push_body(&data[..n])?;
+                    data = &data[n..];
+                    if n as u64 == remaining {
+                        self.complete()?;
+                    } else {
+                        self.state = State::Length(remaining - n as u64);
+                    }
+                }
+                State::Chunked(decoder) => {
+                    if data.is_empty() {
+                        return Ok(());
+                    }
+                    let mut body = Vec::new();
+                    let decoded = decoder.decode(data, &mut body)?;
+                    self.push_body(&body)?;
+                    if let Some(trailers) = decoded.trailers.filter(|t| !t.is_empty()) {
+                        self.events.push_back(ResponseEvent::Trailers(trailers));
+                    }
+                    data = &data[decoded.consumed..];
+                    if decoded.done {
+                        self.complete()?;
+                    }
+                }
+                State::UntilClose => return self.push_body(data),
+                State::Done => {
+                    self.buf.extend_from_slice(data);
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Queue a body event for `data`, decoding it first if a content decoder is active.
+    fn push_body(&mut self, data: &[u8]) -> Result<(), Error> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        self.encoded_bytes += data.len() as u64;
+        if self.encoded_bytes > self.max_body_size {
+            return Err(Error::LimitExceeded {
+                limit: "body",
+                max: self.max_body_size,
+                consumed: self.encoded_bytes,
+            });
+        }
+        if let Some(integrity) = &mut self.integrity {
+            integrity.update_encoded(data);
+        }
+        let consumed = self.encoded_bytes;
+        let body = match &mut self.decoder {
+            Some(decoder) => {
+                let mut out = Vec::new();
+                decoder
+                    .decode(data, &mut out)
+                    .map_err(|e| decoded_limit(e, consumed))?;
+                out
+            }
+            None => data.to_vec(),
+        };
+        if !body.is_empty() {
+            if let Some(integrity) = &mut self.integrity {
+                integrity.update_decoded(&body);
+            }
+            self.decoded_bytes += body.len() as u64;
+            self.events.push_back(ResponseEvent::Body(body));
+        }
+        Ok(())
+    }
+
+    fn complete(&mut self) -> Result<(), Error> {
+        if let Some(decoder) = &mut self.decoder {
+            let consumed = self.encoded_bytes;
+            decoder.finish().map_err(|e| decoded_limit(e, consumed))?;
+        }
+        if let Some(integrity) = self.integrity.take() {
+            let (digest, verdict) = integrity.finish();
+            self.body_digest = digest;
+            verdict?;
+        }
+        self.state = State::Done;
+        self.events.push_back(ResponseEvent::Complete);
+        Ok(())
+    }
+
+    /// Try to parse a complete head from `buf`. Returns false if more bytes are needed.
+    fn parse_head(&mut self) -> Result<bool, Error> {
+        // Tolerate stray CRLFs between keep-alive responses
+        let skip = self
+            .buf
+            .iter()
+            .take_while(|&&b| b == b'\r' || b == b'\n')
+            .count();
+        if skip > 0 {
+            self.buf.drain(..skip);
+            self.head_scanned = 0;
+        }
+
+        let Some(end) = find_head_end(&self.buf, self.head_scanned) else {
+            if self.buf.len() > self.max_header_size {
+                return Err(self.header_limit(self.buf.len()));
+            }
+            self.head_scanned = self.buf.len().saturating_sub(3);
+            return Ok(false);
+        };
+        if end > self.max_header_size {
+            return Err(self.header_limit(end));
+        }
+
+        let head = parse_head_block(&self.buf[..end])?;
+        self.buf.drain(..end);
+        self.head_scanned = 0;
+
+        if (100..200).contains(&head.status) && head.status != 101 {
+            // Interim response: the final response follows
+            if head.headers.contains("transfer-encoding")
+                || content_length(&head.headers)?.is_some_and(|len| len > 0)
+            {
+                return Err(Error::InvalidInformational);
+            }
+            self.continue_received |= head.status == 100;
+            self.events.push_back(ResponseEvent::Informational(head));
+            return self.parse_head();
+        }
+
+        let framing = self.body_framing(&head)?;
+        if self.decompress {
+            match DecodePlan::for_headers(&head.headers, self.max_decoded_size) {
+                DecodePlan::Identity => {}
+                DecodePlan::Chain(chain) => self.decoder = Some(chain),
+                DecodePlan::Unsupported(codings) if self.reject_unsupported_encoding => {
+                    return Err(Error::UnsupportedEncoding(codings));
+                }
+                DecodePlan::Unsupported(codings) => self.undecoded_encoding = Some(codings),
+            }
+        }
+        if !matches!(framing, State::Done) {
+            self.integrity = BodyIntegrity::new(
+                self.expected_integrity.as_ref(),
+                self.compute_digest,
+                self.verify_digest_fields,
+                head.status,
+                &head.headers,
+            );
+        }
+        self.final_status = Some(head.status);
+        self.events.push_back(ResponseEvent::Head(head));
+        match framing {
+            State::Done | State::Length(0) => self.complete()?,
+            state => self.state = state,
+        }
+        Ok(true)
+    }
+
+    fn body_framing(&self, head: &ResponseHead) -> Result<State, Error> {
+        if self.head_request || head.status == 101 || head.status == 204 || head.status == 304 {
+            return Ok(State::Done);
+        }
+        if self.connect_request && (200..300).contains(&head.status) {
+            return Ok(State::Done);
+        }
+        if let Some(te) = last_transfer_coding(&head.headers) {
+            if te.eq_ignore_ascii_case("chunked") {
+                return Ok(State::Chunked(ChunkedDecoder::new(self.max_header_size)));
+            }
+            // Non-chunked final coding: the body is delimited by connection close
+            return Ok(State::UntilClose);
+        }
+        match content_length(&head.headers)? {
+            Some(len) if len > self.max_body_size => Err(Error::LimitExceeded {
+                limit: "body",
+                max: self.max_body_size,
+                consumed: 0,
+            }),
+            Some(len) => Ok(State::Length(len)),
+            None => Ok(State::UntilClose),
+        }
+    }
+
+    fn header_limit(&self, consumed: usize) -> Error {
+        Error::LimitExceeded {
+            limit: "header block",
+            max: self.max_header_size as u64,
+            consumed: consumed as u64,
+        }
+    }
+}
+
+/// The decoders report their output limit as `DecodedTooLarge`.
+fn decoded_limit(e: Error, consumed: u64) -> Error {
+    match e {
+        Error::DecodedTooLarge { limit } => Error::LimitExceeded {
+            limit: "decoded body",
+            max: limit,
+            consumed,
+        },
+        e => e,
+    }
+}
+
+/// Offset just past the blank line ending the head, searching from `from`.
+pub(super) fn find_head_end(buf: &[u8], from: usize) -> Option<usize> {
+    let mut i = from;
+    while i < buf.len() {
+        if buf[i] == b'\n' {
+            match buf.get(i + 1) {
+                Some(b'\n') => return Some(i + 2),
+                Some(b'\r') if buf.get(i + 2) == Some(&b'\n') => return Some(i + 3),
+                _ => {}
+            }
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Split a head/trailer block into lines, dropping the CR of each CRLF.
+pub(super) fn block_lines(block: &[u8]) -> impl Iterator<Item = &[u8]> {
+    block
+        .split(|&b| b == b'\n')
+        .map(|l| l.strip_suffix(b"\r").unwrap_or(l))
+}
+
+fn parse_head_block(block: &[u8]) -> Result<ResponseHead, Error> {
+    let mut lines = block_lines(block);
+    let status_line = lines.next().ok_or(Error::InvalidStatusLine)?;
+    let (version, status, reason) = parse_status_line(status_line)?;
+    let headers = parse_fields(lines)?;
+    Ok(ResponseHead {
+        version,
+        status,
+        reason,
+        headers,
+    })
+}
+
+/// Parse field lines up to the first empty line (header block or trailer section).
+pub(super) fn parse_fields<'a>(lines: impl Iterator<Item = &'a [u8]>) -> Result<Headers, Error> {
+    let mut headers = Headers::new();
+    for line in lines {
+        if line.is_empty() {
+            break;
+        }
+        if line.contains(&b'\r') || line.contains(&0) {
+            return Err(Error::InvalidHeaderLine);
+        }
+        if line[0] == b' ' || line[0] == b'\t' {
+            // obs-fold: RFC 9112 §5.2 allows replacing it with a single SP
+            let (_, value) = headers.last_mut().ok_or(Error::InvalidHeaderLine)?;
+            value.push(' ');
+            value.push_str(&latin1(trim_ows(line)));
+            continue;
+        }
+        let colon = line
+            .iter()
+            .position(|&b| b == b':')
+            .ok_or(Error::InvalidHeaderLine)?;
+        let name = latin1(&line[..colon]);
+        if !is_token(&name) {
+            return Err(Error::InvalidHeaderName(name));
+        }
+        headers.push(name, latin1(trim_ows(&line[colon + 1..])));
+    }
+    Ok(headers)
+}
+
+fn parse_status_line(line: &[u8]) -> Result<(Version, u16, String), Error> {
+    let version = match line.get(..9) {
+        Some(b"HTTP/1.1 ") => Version::Http11,
+        Some(b"HTTP/1.0 ") => Version::Http10,
+        _ => return Err(Error::InvalidStatusLine),
+    };
+    let code = line.get(9..12).ok_or(Error::InvalidStatusLine)?;
+    if !code.iter().all(u8::is_ascii_digit) {
+        return Err(Error::InvalidStatusLine);
+    }
+    let status = code
+        .iter()
+        .fold(0u16, |acc, &d| acc * 10 + (d - b'0') as u16);
+    let reason = match line.get(12..) {
+        None | Some([]) => String::new(),
+        Some([b' ', reason @ ..]) => latin1(reason),
+        Some(_) => return Err(Error::InvalidStatusLine),
+    };
+    Ok((version, status, reason))
+}
+
+fn trim_ows(mut s: &[u8]) -> &[u8] {
+    while let [b' ' | b'\t', rest @ ..] = s {
+        s = rest;
+    }
+    while let [rest @ .., b' ' | b'\t'] = s {
+        s = rest;
+    }
+    s
+}
+
+/// Final coding of the Transfer-Encoding list, if the header is present.
+fn last_transfer_coding(headers: &Headers) -> Option<String> {
+    headers
+        .get_all("transfer-encoding")
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .filter(|c| !c.is_empty())
+        .last()
+        .map(str::to_string)
+}
+
+/// Content-Length, accepting repeated identical values (RFC 9110 §8.6).
+fn content_length(headers: &Headers) -> Result<Option<u64>, Error> {
+    let mut length = None;
+    for v in headers.get_all("content-length").flat_map(|v| v.split(',')) {
+        let v = v.trim();
+        if v.is_empty() || !v.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(Error::InvalidContentLength);
+        }
+        let n: u64 = v.parse().map_err(|_| Error::InvalidContentLength)?;
+        if length.is_some_and(|l| l != n) {
+            return Err(Error::InvalidContentLength);
+        }
+        length = Some(n);
+    }
+    Ok(length)
+}
+
+/// One parser event, as exposed to JS.
+#[wasm_bindgen]
+#[derive(Debug)]
+pub struct ParserEvent(ResponseEvent);
+
+#[wasm_bindgen]
+impl ParserEvent {
+    /// "informational", "head", "body", "trailers" or "complete".
+    pub fn kind(&self) -> String {
+        match self.0 {
+            ResponseEvent::Informational(_) => "informational",
+            ResponseEvent::Head(_) => "head",
+            ResponseEvent::Body(_) => "body",
+            ResponseEvent::Trailers(_) => "trailers",
+            ResponseEvent::Complete => "complete",
+        }
+        .to_string()
+    }
+
+    /// Status code of a head or informational event (0 otherwise).
+    pub fn status(&self) -> u16 {
+        self.head().map_or(0, |h| h.status)
+    }
+
+    pub fn reason(&self) -> Option<String> {
+        self.head().map(|h| h.reason.clone())
+    }
+
+    /// "HTTP/1.1" or "HTTP/1.0" for a head event.
+    pub fn version(&self) -> Option<String> {
+        self.head().map(|h| h.version.as_str().to_string())
+    }
+
+    /// Header (or trailer) names in received order and casing; empty for other events.
+    pub fn header_names(&self) -> Vec<String> {
+        self.fields().map(Headers::names).unwrap_or_default()
+    }
+
+    /// Header (or trailer) values, index-aligned with `header_names`.
+    pub fn header_values(&self) -> Vec<String> {
+        self.fields().map(Headers::values).unwrap_or_default()
+    }
+
+    /// First value of a header (or trailer), looked up case-insensitively.
+    pub fn header(&self, name: &str) -> Option<String> {
+        self.fields()?.get(name).map(str::to_string)
+    }
+
+    /// Every value of a header (or trailer) in received order, looked up case-insensitively.
+    pub fn header_all(&self, name: &str) -> Vec<String> {
+        self.fields()
+            .map(|h| h.get_all(name).map(str::to_string).collect())
+            .unwrap_or_default()
+    }
+
+    /// Take the bytes of a body event (empty for other events).
+    pub fn take_data(&mut self) -> Vec<u8> {
+        match &mut self.0 {
+            ResponseEvent::Body(data) => mem::take(data),
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl From<ResponseEvent> for ParserEvent {
+    fn from(event: ResponseEvent) -> Self {
+        ParserEvent(event)
+    }
+}
+
+impl ParserEvent {
+    pub fn into_inner(self) -> ResponseEvent {
+        self.0
+    }
+
+    pub(crate) fn inner(&self) -> &ResponseEvent {
+        &self.0
+    }
+
+    fn head(&self) -> Option<&ResponseHead> {
+        match &self.0 {
+            ResponseEvent::Informational(h) | ResponseEvent::Head(h) => Some(h),
+            _ => None,
+        }
+    }
+
+    fn fields(&self) -> Option<&Headers> {
+        match &self.0 {
+            ResponseEvent::Informational(h) | ResponseEvent::Head(h) => Some(&h.headers),
+            ResponseEvent::Trailers(t) => Some(t),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coding::gzip::tests::gzip;
+
+    const NGINX_CL: &[u8] = include_bytes!("../../tests/fixtures/nginx-content-length.http");
+    const CLOUDFLARE_CL: &[u8] =
+        include_bytes!("../../tests/fixtures/cloudflare-content-length.http");
+    const APACHE_CLOSE: &[u8] = include_bytes!("../../tests/fixtures/apache-close-delimited.http");
+
+    fn drain(parser: &mut ResponseParser) -> Vec<ResponseEvent> {
+        std::iter::from_fn(|| parser.poll_event()).collect()
+    }
+
+    /// Fold events into (head, concatenated body, whether Complete was seen),
+    /// checking they arrive in wire order.
+    fn summarize(events: Vec<ResponseEvent>) -> (Option<ResponseHead>, Vec<u8>, bool) {
+        let (mut head, mut body, mut complete) = (None, Vec::new(), false);
+        for ev in events {
+            match ev {
+                ResponseEvent::Informational(_) => assert!(head.is_none()),
+                ResponseEvent::Head(h) => {
+                    assert!(head.is_none() && !complete);
+                    head = Some(h);
+                }
+                ResponseEvent::Body(b) => {
+                    assert!(head.is_some() && !complete);
+                    body.extend(b);
+                }
+                ResponseEvent::Trailers(_) => assert!(head.is_some() && !complete),
+                ResponseEvent::Complete => complete = true,
+            }
+        }
+        (head, body, complete)
+    }
+
+    fn collect(parser: &mut ResponseParser) -> (Option<ResponseHead>, Vec<u8>, bool) {
+        summarize(drain(parser))
+    }
+
+    fn parse_split(input: &[u8], chunk: usize) -> (Option<ResponseHead>, Vec<u8>, bool) {
+        let mut parser = ResponseParser::new();
+        let mut events = Vec::new();
+        for piece in input.chunks(chunk) {
+            parser.feed(piece).unwrap();
+            events.extend(drain(&mut parser));
+        }
+        summarize(events)
+    }
+
+    #[test]
+    fn nginx_fixture_at_every_split_size() {
+        let body_start = NGINX_CL.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        for chunk in 1..=NGINX_CL.len() {
+            let (head, body, complete) = parse_split(NGINX_CL, chunk);
+            let head = head.unwrap();
+            assert_eq!(head.status, 200);
+            assert_eq!(head.reason, "OK");
+            assert_eq!(head.headers.get("content-type"), Some("text/html"));
+            assert_eq!(body, &NGINX_CL[body_start..]);
+            assert!(complete, "chunk size {}", chunk);
+        }
+    }
+
+    #[test]
+    fn cloudflare_fixture_preserves_order_and_casing() {
+        let (head, body, complete) = parse_split(CLOUDFLARE_CL, 7);
+        let head = head.unwrap();
+        let names: Vec<_> = head.headers.iter().map(|(n, _)| n).collect();
+        assert_eq!(
+            names,
+            [
+                "Date",
+                "Content-Type",
+                "Content-Length",
+                "Connection",
+                "cf-cache-status",
+                "Set-Cookie",
+                "Set-Cookie",
+                "Server",
+                "CF-RAY",
+                "alt-svc"
+            ]
+        );
+        assert_eq!(head.headers.get_all("set-cookie").count(), 2);
+        assert_eq!(head.headers.get("CF-Ray"), Some("8a1b2c3d4e5f6a7b-NRT"));
+        assert_eq!(body, b"{\"ok\":true}");
+        assert!(complete);
+    }
+
+    #[test]
+    fn odd_casings_round_trip_and_still_frame() {
+        let mut parser = ResponseParser::new();
+        parser
+            .feed(
+                b"HTTP/1.1 200 OK\r\ncOnTeNt-TyPe: text/plain\r\nTRANSFER-ENCODING: Chunked\r\n\
+                  x-ODD-name: 1\r\n\r\n3\r\nabc\r\n0\r\n\r\n",
+            )
+            .unwrap();
+        let (head, body, complete) = collect(&mut parser);
+        let head = head.unwrap();
+        assert_eq!(
+            head.headers.names(),
+            ["cOnTeNt-TyPe", "TRANSFER-ENCODING", "x-ODD-name"]
+        );
+        assert_eq!(head.headers.get("Content-Type"), Some("text/plain"));
+        assert_eq!(head.headers.get("X-Odd-Name"), Some("1"));
+        assert_eq!(body, b"abc");
+        assert!(complete);
+
+        let mut parser = ResponseParser::new();
+        parser
+            .feed(b"HTTP/1.1 200 OK\r\ncontent-LENGTH: 2\r\n\r\nok")
+            .unwrap();
+        let (head, body, complete) = collect(&mut parser);
+        assert_eq!(head.unwrap().headers.names(), ["content-LENGTH"]);
+        assert_eq!(body, b"ok");
+        assert!(complete);
+    }
+
+    #[test]
+    fn close_delimited_body_completes_on_finish() {
+        let mut parser = ResponseParser::new();
+        parser.feed(APACHE_CLOSE).unwrap();
+        let (head, body, complete) = collect(&mut parser);
+        assert_eq!(head.unwrap().version, Version::Http10);
+        assert!(!complete);
+        parser.finish().unwrap();
+        let (_, more, complete) = collect(&mut parser);
+        assert!(complete);
+        let body_start = APACHE_CLOSE
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .unwrap()
+            + 4;
+        assert_eq!([body, more].concat(), &APACHE_CLOSE[body_start..]);
+    }
+
+    #[test]
+    fn leftover_bytes_belong_to_next_response() {
+        let mut input = NGINX_CL.to_vec();
+        input.extend_from_slice(b"HTTP/1.1 204 No Content\r\n\r\n");
+        let mut parser = ResponseParser::new();
+        parser.feed(&input).unwrap();
+        let (_, _, complete) = collect(&mut parser);
+        assert!(complete);
+        let leftover = parser.take_leftover();
+        assert_eq!(leftover, b"HTTP/1.1 204 No Content\r\n\r\n");
+
+        let mut next = ResponseParser::new();
+        next.feed(&leftover).unwrap();
+        let (head, body, complete) = collect(&mut next);
+        assert_eq!(head.unwrap().status, 204);
+        assert!(body.is_empty() && complete);
+    }
+
+    #[test]
+    fn bodyless_responses() {
+        for (input, method) in [
+            (
+                &b"HTTP/1.1 304 Not Modified\r\nContent-Length: 10\r\n\r\n"[..],
+                "GET",
+            ),
+            (b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n", "HEAD"),
+            (b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n", "GET"),
+            (b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n", "CONNECT"),
+        ] {
+            let mut parser = ResponseParser::new();
+            parser.set_request_method(method);
+            parser.feed(input).unwrap();
+            let (head, body, complete) = collect(&mut parser);
+            assert!(head.is_some() && body.is_empty() && complete);
+        }
+    }
+
+    #[test]
+    fn interim_responses_come_first() {
+        let mut parser = ResponseParser::new();
+        parser
+            .feed(b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nhi")
+            .unwrap();
+        let (head, body, complete) = collect(&mut parser);
+        assert_eq!(head.unwrap().status, 200);
+        assert_eq!(body, b"hi");
+        assert!(complete);
+
+        let mut parser = ResponseParser::new();
+        parser
+            .feed(
+                b"HTTP/1.1 103 Early Hints\r\nLink: </a.css>; rel=preload\r\n\r\n\
+                  HTTP/1.1 103 Early Hints\r\nLink: </b.js>; rel=preload\r\n\r\n\
+                  HTTP/1.1 204 No Content\r\n\r\n",
+            )
+            .unwrap();
+        let mut hints = Vec::new();
+        while let Some(event) = parser.next_event() {
+            if event.kind() == "informational" {
+                assert_eq!(event.status(), 103);
+                hints.extend(event.header("link"));
+            } else {
+                assert_eq!((event.kind().as_str(), event.status()), ("head", 204));
+                break;
+            }
+        }
+        assert_eq!(hints, ["</a.css>; rel=preload", "</b.js>; rel=preload"]);
+
+        let mut parser = ResponseParser::new();
+        assert_eq!(
+            parser.feed(b"HTTP/1.1 103 Early Hints\r\nContent-Length: 3\r\n\r\nabc"),
+            Err(Error::InvalidInformational)
+        );
+    }
+
+    #[test]
+    fn bare_lf_and_obs_fold_are_tolerated() {
+        let mut parser = ResponseParser::new();
+        parser
+            .feed(b"HTTP/1.1 200\nX-Long: a\n  b\nContent-Length: 1\n\nz")
+            .unwrap();
+        let (head, body, complete) = collect(&mut parser);
+        let head = head.unwrap();
+        assert_eq!(head.reason, "");
+        assert_eq!(head.headers.get("x-long"), Some("a b"));
+        assert_eq!(body, b"z");
+        assert!(complete);
+    }
+
+    #[test]
+    fn header_size_limit() {
+        let mut parser = ResponseParser::new();
+        parser.set_max_header_size(32);
+        assert_eq!(
+            parser.feed(b"HTTP/1.1 200 OK\r\nX-Padding: aaaaaaaaaaaaaaaaaaaa"),
+            Err(Error::LimitExceeded {
+                limit: "header block",
+                max: 32,
+                consumed: 48
+            })
+        );
+        // The failure is sticky
+        assert!(parser.feed(b"\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn framing_errors() {
+        for input in [
+            &b"HTTP/1.1 200 OK\r\nContent-Length: 1, 2\r\n\r\n"[..],
+            b"HTTP/1.1 200 OK\r\nContent-Length: -1\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nContent-Length: 1\r\nContent-Length: 3\r\n\r\n",
+        ] {
+            let mut parser = ResponseParser::new();
+            assert_eq!(parser.feed(input), Err(Error::InvalidContentLength));
+        }
+        let mut parser = ResponseParser::new();
+        assert_eq!(
+            parser.feed(b"HTTP/2 200 OK\r\n\r\n"),
+            Err(Error::InvalidStatusLine)
+        );
+        let mut parser = ResponseParser::new();
+        assert_eq!(
+            parser.feed(b"HTTP/1.1 200 OK\r\nBad Name: x\r\n\r\n"),
+            Err(Error::InvalidHeaderName("Bad Name".into()))
+        );
+        let mut parser = ResponseParser::new();
+        assert_eq!(
+            parser.feed(b"HTTP/1.1 200 OK\r\nno-colon\r\n\r\n"),
+            Err(Error::InvalidHeaderLine)
+        );
+    }
+
+    #[test]
+    fn premature_close() {
+        let mut parser = ResponseParser::new();
+        parser.feed(b"HTTP/1.1 200 OK\r\nContent-Le").unwrap();
+        assert_eq!(parser.finish(), Err(Error::IncompleteHead));
+
+        let mut parser = ResponseParser::new();
+        parser
+            .feed(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nabc")
+            .unwrap();
+        assert_eq!(parser.finish(), Err(Error::IncompleteBody { remaining: 7 }));
+    }
+
+    #[test]
+    fn body_size_limit() {
+        let mut parser = ResponseParser::new();
+        parser.set_max_body_size(4);
+        assert_eq!(
+            parser.feed(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n"),
+            Err(Error::LimitExceeded {
+                limit: "body",
+                max: 4,
+                consumed: 0
+            })
+        );
+
+        let mut request = RequestBuilder::new("GET", "/").unwrap();
+        request.set_max_response_body_size(4);
+        let mut parser = ResponseParser::for_request(&request);
+        parser
+            .feed(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n")
+            .unwrap();
+        assert_eq!(
+            parser.feed(b"2\r\nde\r\n"),
+            Err(Error::LimitExceeded {
+                limit: "body",
+                max: 4,
+                consumed: 5
+            })
+        );
+
+        // Exactly at the limit is fine, and bytes past Content-Length stay for the
+        // next response
+        let mut parser = ResponseParser::for_request(&request);
+        parser
+            .feed(b"HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\nabcdHTTP/1.1")
+            .unwrap();
+        let (_, body, complete) = collect(&mut parser);
+        assert_eq!((body, complete), (b"abcd".to_vec(), true));
+        assert_eq!(parser.take_leftover(), b"HTTP/1.1");
+    }
+
+    #[test]
+    fn chunked_body_with_trailers_and_leftover() {
+        let input = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: gzip, chunked\r\n\r\n\
+                      3;x=y\r\nabc\r\n0\r\nGrpc-Status: 0\r\n\r\nHTTP/1.1";
+        for step in 1..=input.len() {
+            let mut parser = ResponseParser::new();
+            let mut events = Vec::new();
+            for piece in input.chunks(step) {
+                parser.feed(piece).unwrap();
+                events.extend(drain(&mut parser));
+            }
+            let trailers = events.iter().find_map(|e| match e {
+                ResponseEvent::Trailers(t) => Some(t.clone()),
+                _ => None,
+            });
+            assert_eq!(trailers.unwrap().get("grpc-status"), Some("0"));
+            let (_, body, complete) = summarize(events);
+            assert_eq!(body, b"abc");
+            assert!(complete);
+            assert_eq!(parser.take_leftover(), b"HTTP/1.1");
+        }
+    }
+
+    #[test]
+    fn chunked_premature_close() {
+        let mut parser = ResponseParser::new();
+        parser
+            .feed(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nab")
+            .unwrap();
+        assert_eq!(parser.finish(), Err(Error::IncompleteChunkedBody));
+    }
+
+    #[test]
+    fn transfer_encoding_overrides_content_length() {
+        let mut parser = ResponseParser::new();
+        parser
+            .feed(b"HTTP/1.1 200 OK\r\nContent-Length: 100\r\nTransfer-Encoding: chunked\r\n\r\n1\r\nx\r\n0\r\n\r\n")
+            .unwrap();
+        let (_, body, complete) = collect(&mut parser);
+        assert_eq!(body, b"x");
+        assert!(complete);
+    }
+
+    #[test]
+    fn gzip_body_is_decompressed() {
+        let text = b"compressible compressible compressible".repeat(20);
+        let member = gzip(&text);
+        let mut chunked = Vec::new();
+        for piece in member.chunks(100) {
+            chunked.extend(format!("{:x}\r\n", piece.len()).bytes());
+            chunked.extend_from_slice(piece);
+            chunked.extend_from_slice(b"\r\n");
+        }
+        chunked.extend_from_slice(b"0\r\n\r\n");
+        let heads = [
+            format!("Content-Length: {}\r\n", member.len()),
+            "Transfer-Encoding: chunked\r\n".to_string(),
+        ];
+        for (head, body) in heads.iter().zip([&member, &chunked]) {
+            let mut input =
+                format!("HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\n{}\r\n", head).into_bytes();
+            input.extend_from_slice(body);
+            for step in [1, 64, input.len()] {
+                let mut parser = ResponseParser::new();
+                parser.set_decompress(true);
+                let mut events = Vec::new();
+                for piece in input.chunks(step) {
+                    parser.feed(piece).unwrap();
+                    events.extend(drain(&mut parser));
+                }
+                let (head, decoded, complete) = summarize(events);
+                assert_eq!(head.unwrap().headers.get("content-encoding"), Some("gzip"));
+                assert_eq!(decoded, text);
+                assert!(complete);
+                assert_eq!(parser.encoded_body_bytes(), member.len() as u64);
+                assert_eq!(parser.decoded_body_bytes(), text.len() as u64);
+            }
+        }
+    }
+
+    #[test]
+    fn gzip_passthrough_and_errors() {
+        let member = gzip(b"raw");
+        let mut input = format!(
+            "HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\n\r\n",
+            member.len()
+        )
+        .into_bytes();
+        input.extend_from_slice(&member);
+
+        // Decompression is opt-in
+        let mut parser = ResponseParser::new();
+        parser.feed(&input).unwrap();
+        assert_eq!(collect(&mut parser).1, member);
+
+        // A close-delimited body cut inside the gzip stream is not a clean end
+        let mut parser = ResponseParser::new();
+        parser.set_decompress(true);
+        let head_len = input.len() - member.len();
+        let close_delimited = [
+            &b"HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\n\r\n"[..],
+            &member[..member.len() - 3],
+        ]
+        .concat();
+        parser.feed(&close_delimited).unwrap();
+        assert_eq!(
+            parser.finish(),
+            Err(Error::TruncatedEncoding { coding: "gzip" })
+        );
+
+        let mut parser = ResponseParser::new();
+        parser.set_decompress(true);
+        parser.set_max_decoded_size(2);
+        assert_eq!(
+            parser.feed(&input[..head_len + member.len()]),
+            Err(Error::LimitExceeded {
+                limit: "decoded body",
+                max: 2,
+                consumed: member.len() as u64
+            })
+        );
+    }
+
+    #[test]
+    fn body_integrity() {
+        use sha2::{Digest, Sha256};
+        let sha256 = |data: &[u8]| crate::base64::encode(&Sha256::digest(data));
+        let text = b"mirrored artifact ".repeat(40);
+        let member = gzip(&text);
+        let response = |digest: &str| {
+            let mut input = format!(
+                "HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\nContent-Digest: {}\r\n\
+                 Content-Length: {}\r\n\r\n",
+                digest,
+                member.len()
+            )
+            .into_bytes();
+            input.extend_from_slice(&member);
+            input
+        };
+        // Content-Digest covers the gzip bytes, the integrity metadata the decoded text
+        let input = response(&format!("sha-256=:{}:", sha256(&member)));
+        let mut parser = ResponseParser::new();
+        parser.set_decompress(true);
+        parser.set_verify_digest_fields(true);
+        let integrity = format!("sha256-{}", sha256(&text));
+        parser.set_expected_integrity(&integrity).unwrap();
+        for piece in input.chunks(7) {
+            parser.feed(piece).unwrap();
+        }
+        assert!(collect(&mut parser).2);
+        assert_eq!(parser.body_digest(), Some(integrity.clone()));
+
+        let input = response("sha-256=:AAAA:");
+        let mut parser = ResponseParser::new();
+        parser.set_verify_digest_fields(true);
+        parser.set_compute_digest(true);
+        let err = parser.feed(&input).unwrap_err();
+        assert_eq!(
+            err,
+            Error::Integrity {
+                check: "Content-Digest",
+                expected: "sha-256=:AAAA:".into(),
+                computed: format!("sha-256=:{}:", sha256(&member)),
+            }
+        );
+        let (_, body, complete) = collect(&mut parser);
+        assert_eq!((body, complete), (member.clone(), false));
+        assert_eq!(
+            parser.body_digest(),
+            Some(format!("sha256-{}", sha256(&member)))
+        );
+        assert_eq!(
+            parser.set_expected_integrity("sha256"),
+            Err(Error::InvalidIntegrity)
+        );
+    }
+
+    #[cfg(feature = "brotli")]
+    #[test]
+    fn brotli_body_is_decompressed() {
+        let text = b"{\"items\":[1,2,3,4,5,6,7,8,9]}".repeat(30);
+        let encoded = crate::coding::brotli::tests::brotli(&text);
+        let mut input = format!(
+            "HTTP/1.1 200 OK\r\nContent-Encoding: br\r\nContent-Length: {}\r\n\r\n",
+            encoded.len()
+        )
+        .into_bytes();
+        input.extend_from_slice(&encoded);
+        let mut parser = ResponseParser::new();
+        parser.set_decompress(true);
+        for piece in input.chunks(11) {
+            parser.feed(piece).unwrap();
+        }
+        let (_, body, complete) = collect(&mut parser);
+        assert_eq!(body, text);
+        assert!(complete);
+        assert_eq!(parser.encoded_body_bytes(), encoded.len() as u64);
+    }
+
+    fn encoded_response(encoding: &str, body: &[u8]) -> Vec<u8> {
+        let mut input = format!(
+            "HTTP/1.1 200 OK\r\nContent-Encoding: {}\r\nContent-Length: {}\r\n\r\n",
+            encoding,
+            body.len()
+        )
+        .into_bytes();
+        input.extend_from_slice(body);
+        input
+    }
+
+    #[cfg(feature = "brotli")]
+    #[test]
+    fn stacked_encodings_decode_in_reverse_order() {
+        let text = b"stacked codings ".repeat(16);
+        // gzip applied first, then br
+        let encoded = crate::coding::brotli::tests::brotli(&gzip(&text));
+        let input = encoded_response("gzip, br", &encoded);
+        let mut request = RequestBuilder::new("GET", "/").unwrap();
+        request.set_auto_decompress(true);
+        let mut parser = ResponseParser::for_request(&request);
+        for piece in input.chunks(5) {
+            parser.feed(piece).unwrap();
+        }
+        let (_, body, complete) = collect(&mut parser);
+        assert_eq!(body, text);
+        assert!(complete);
+        assert_eq!(parser.encoded_body_bytes(), encoded.len() as u64);
+        assert_eq!(parser.undecoded_encoding(), None);
+    }
+
+    #[test]
+    fn unsupported_encoding_is_passed_through_and_flagged() {
+        let input = encoded_response("gzip, compress", b"LZW...");
+        let mut parser = ResponseParser::new();
+        parser.set_decompress(true);
+        parser.feed(&input).unwrap();
+        let (_, body, complete) = collect(&mut parser);
+        assert_eq!(body, b"LZW...");
+        assert!(complete);
+        assert_eq!(
+            parser.undecoded_encoding().as_deref(),
+            Some("gzip, compress")
+        );
+
+        let mut parser = ResponseParser::new();
+        parser.set_decompress(true);
+        parser.set_reject_unsupported_encoding(true);
+        assert_eq!(
+            parser.feed(&input),
+            Err(Error::UnsupportedEncoding("gzip, compress".into()))
+        );
+
+        // identity is a no-op coding
+        let mut parser = ResponseParser::new();
+        parser.set_decompress(true);
+        parser
+            .feed(&encoded_response("identity", b"plain"))
+            .unwrap();
+        assert_eq!(collect(&mut parser).1, b"plain");
+        assert_eq!(parser.undecoded_encoding(), None);
+    }
+
+    #[test]
+    fn for_request_takes_method() {
+        let request = RequestBuilder::new("HEAD", "/").unwrap();
+        let mut parser = ResponseParser::for_request(&request);
+        parser
+            .feed(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n")
+            .unwrap();
+        assert!(parser.is_complete());
+    }
+
+    #[test]
+    fn wasm_event_wrapper() {
+        let mut parser = ResponseParser::new();
+        parser
+            .feed(b"HTTP/1.1 404 Not Found\r\nX-A: 1\r\nContent-Length: 2\r\n\r\nno")
+            .unwrap();
+        let head = parser.next_event().unwrap();
+        assert_eq!(head.kind(), "head");
+        assert_eq!(head.status(), 404);
+        assert_eq!(head.header_names(), ["X-A", "Content-Length"]);
+        assert_eq!(head.header_values(), ["1", "2"]);
+        assert_eq!(head.header("x-a").as_deref(), Some("1"));
+        assert_eq!(head.header_all("CONTENT-LENGTH"), ["2"]);
+        assert_eq!(head.header("missing"), None);
+        let mut body = parser.next_event().unwrap();
+        assert_eq!(body.kind(), "body");
+        assert_eq!(body.take_data(), b"no");
+        assert_eq!(parser.next_event().unwrap().kind(), "complete");
+        assert!(parser.next_event().is_none());
+    }
+}
@@ -0,0 +1,2374 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::mem;
+
+use wasm_bindgen::prelude::*;
+
+use super::frame::{self, be_u32, Dependency, FrameHeader};
+use super::hpack::{Decoder, Encoder};
+use super::options::PseudoOrder;
+use super::Http2Options;
+use crate::error::Error;
+use crate::headers::{validate_trailer, Headers};
+use crate::http1::is_token;
+use crate::timing::Timings;
+
+/// Client connection preface, sent before the first SETTINGS frame.
+pub const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// Output of [`Http2Connection`], in the order frames arrived.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    /// 1xx interim head (e.g. 103 Early Hints), including `:status`; any number of
+    /// them may come before the final head
+    Informational { stream_id: u32, headers: Headers },
+    /// Final response head, including `:status`
+    Headers {
+        stream_id: u32,
+        headers: Headers,
+        end_stream: bool,
+    },
+    Data {
+        stream_id: u32,
+        data: Vec<u8>,
+        end_stream: bool,
+    },
+    /// Trailer fields; always ends the stream
+    Trailers { stream_id: u32, headers: Headers },
+    /// The stream was reset, by the peer or because its response was malformed
+    Reset { stream_id: u32, error_code: u32 },
+    /// The peer is shutting the connection down. Streams up to `last_stream_id` may
+    /// still complete; the others are reported as `Abandoned`
+    GoAway {
+        last_stream_id: u32,
+        error_code: u32,
+        debug_data: Vec<u8>,
+    },
+    /// The connection went away before the stream finished. With `retryable` the
+    /// server never processed the request, so it can be sent again on a new
+    /// connection; otherwise it may have been partly executed. `error_code` and
+    /// `debug_data` come from the server's GOAWAY, if one arrived
+    Abandoned {
+        stream_id: u32,
+        retryable: bool,
+        error_code: u32,
+        debug_data: Vec<u8>,
+    },
+}
+
+/// Settings the peer announced (defaults until its SETTINGS frame arrives).
+#[derive(Debug)]
+struct PeerSettings {
+    max_concurrent_streams: Option<u32>,
+    initial_window_size: u32,
+    max_frame_size: u32,
+}
+
+#[derive(Debug)]
+struct Stream {
+    /// Header fields (validated and ordered) of a request waiting for the peer's
+    /// concurrency limit; the stream is still idle on the wire
+    queued: Option<Vec<(String, String)>>,
+    /// We sent END_STREAM (or will with the queued HEADERS)
+    local_closed: bool,
+    /// Whether the final (non-1xx) response head has arrived
+    final_head: bool,
+    send_window: i64,
+    recv_window: i64,
+    /// Received DATA bytes not yet returned with WINDOW_UPDATE
+    recv_unacked: u32,
+    /// Body bytes waiting for send window
+    pending: VecDeque<u8>,
+    /// END_STREAM goes out once `pending` is empty
+    end_after_pending: bool,
+    /// Trailer fields that end the stream instead of an empty DATA frame
+    trailers: Option<Vec<(String, String)>>,
+}
+
+impl Stream {
+    /// RFC 9113 §5.1 state. Streams are no longer tracked once the peer has sent
+    /// END_STREAM: a request body still being sent is aborted then.
+    fn state(&self) -> &'static str {
+        match self.local_closed {
+            _ if self.queued.is_some() => "queued",
+            false => "open",
+            true => "half-closed-local",
+        }
+    }
+}
+
+/// Header block being reassembled from HEADERS and CONTINUATION frames.
+#[derive(Debug)]
+struct PartialBlock {
+    stream_id: u32,
+    block: Vec<u8>,
+    end_stream: bool,
+    /// Set for PUSH_PROMISE blocks, which are decoded and then refused
+    promised_stream_id: Option<u32>,
+}
+
+/// Sans-IO HTTP/2 client connection (RFC 9113).
+///
+/// Feed it what `take_plaintext` returned, send what `take_outgoing` returns with
+/// `write_plaintext`, and drain events. The connection preface is queued on creation.
+#[wasm_bindgen]
+#[derive(Debug)]
+pub struct Http2Connection {
+    encoder: Encoder,
+    decoder: Decoder,
+    peer: PeerSettings,
+    /// Our SETTINGS_INITIAL_WINDOW_SIZE
+    local_initial_window: u32,
+    /// The connection window we keep replenishing to
+    local_connection_window: u32,
+    /// Our SETTINGS_MAX_FRAME_SIZE
+    local_max_frame_size: u32,
+    /// Whether our SETTINGS leave server push enabled
+    push_enabled: bool,
+    /// Highest stream id the server promised (and we refused)
+    last_promised_id: u32,
+    headers_priority: Option<Dependency>,
+    pseudo_order: PseudoOrder,
+    streams: BTreeMap<u32, Stream>,
+    next_stream_id: u32,
+    send_window: i64,
+    recv_window: i64,
+    /// Received DATA bytes not yet returned with WINDOW_UPDATE
+    recv_unacked: u32,
+    /// Windows are only replenished through `release_capacity`
+    manual_flow_control: bool,
+    /// Per stream: DATA bytes delivered but not released yet (manual mode)
+    unreleased: BTreeMap<u32, u32>,
+    /// Whether the peer's first SETTINGS frame has arrived
+    peer_settings_seen: bool,
+    continuation: Option<PartialBlock>,
+    going_away: bool,
+    /// Error code and debug data of the server's last GOAWAY
+    peer_goaway: Option<(u32, Vec<u8>)>,
+    /// Whether we have sent GOAWAY ourselves
+    goaway_sent: bool,
+    /// Unparsed received bytes (at most one partial frame)
+    buf: Vec<u8>,
+    out: Vec<u8>,
+    events: VecDeque<ConnectionEvent>,
+    failed: Option<Error>,
+    /// Time last given to `set_now`
+    now: Option<f64>,
+    /// TLS handshake of the connection, from `set_tls_timings`
+    setup: Timings,
+    /// Per stream, kept after it finishes
+    timings: BTreeMap<u32, Timings>,
+    /// Whether any response byte has arrived; later streams did not wait for setup
+    response_seen: bool,
+}
+
+impl Default for Http2Connection {
+    fn default() -> Self {
+        Http2Connection::with_options(&Http2Options::default())
+    }
+}
+
+#[wasm_bindgen]
+impl Http2Connection {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Http2Connection {
+        Http2Connection::default()
+    }
+
+    /// Create a connection with additional settings (see `Http2Options`).
+    pub fn with_options(options: &Http2Options) -> Http2Connection {
+        let local_initial_window = options
+            .setting(frame::SETTINGS_INITIAL_WINDOW_SIZE)
+            .unwrap_or(frame::DEFAULT_WINDOW);
+        let local_connection_window = frame::DEFAULT_WINDOW + options.connection_window_increment;
+        let table_size = options
+            .setting(frame::SETTINGS_HEADER_TABLE_SIZE)
+            .unwrap_or(4096);
+        let list_size = options
+            .setting(frame::SETTINGS_MAX_HEADER_LIST_SIZE)
+            .unwrap_or(u32::MAX);
+
+        let mut out = PREFACE.to_vec();
+        frame::write_settings(&mut out, &options.settings);
+        if options.connection_window_increment > 0 {
+            frame::write_window_update(&mut out, 0, options.connection_window_increment);
+        }
+        for (stream_id, dependency) in &options.priority_frames {
+            frame::write_priority(&mut out, *stream_id, dependency);
+        }
+        // The priority tree's idle streams can't be opened later
+        let highest_idle = options.priority_frames.iter().map(|&(id, _)| id).max();
+        let next_stream_id = highest_idle.map_or(1, |id| (id + 1) | 1);
+
+        Http2Connection {
+            encoder: Encoder::new(options.hpack.clone()),
+            decoder: Decoder::new(table_size as usize, list_size as usize),
+            peer: PeerSettings {
+                max_concurrent_streams: None,
+                initial_window_size: frame::DEFAULT_WINDOW,
+                max_frame_size: frame::MIN_MAX_FRAME_SIZE,
+            },
+            local_initial_window,
+            local_connection_window,
+            local_max_frame_size: options
+                .setting(frame::SETTINGS_MAX_FRAME_SIZE)
+                .unwrap_or(frame::MIN_MAX_FRAME_SIZE),
+            push_enabled: options.setting(frame::SETTINGS_ENABLE_PUSH) != Some(0),
+            last_promised_id: 0,
+            headers_priority: options.headers_priority,
+            pseudo_order: options.pseudo_order,
+            streams: BTreeMap::new(),
+            next_stream_id,
+            send_window: frame::DEFAULT_WINDOW as i64,
+            recv_window: local_connection_window as i64,
+            recv_unacked: 0,
+            manual_flow_control: options.manual_flow_control,
+            unreleased: BTreeMap::new(),
+            peer_settings_seen: false,
+            continuation: None,
+            going_away: false,
+            peer_goaway: None,
+            goaway_sent: false,
+            buf: Vec::new(),
+            out,
+            events: VecDeque::new(),
+            failed: None,
+            now: None,
+            setup: Timings::default(),
+            timings: BTreeMap::new(),
+            response_seen: false,
+        }
+    }
+
+    /// Start a request. `names`/`values` are the header fields in order, pseudo-headers
+    /// (`:method`, `:scheme`, `:authority`, `:path`) first. Names are lowercased.
+    /// With `end_stream` the request has no body; otherwise send it with `send_body`.
+    /// Returns the new stream id. Any number of requests may be open at once; beyond
+    /// the server's SETTINGS_MAX_CONCURRENT_STREAMS they are queued and sent in order
+    /// as earlier streams finish. Events carry the stream id they belong to.
+    pub fn open_stream(
+        &mut self,
+        names: Vec<String>,
+        values: Vec<String>,
+        end_stream: bool,
+    ) -> Result<u32, Error> {
+        let headers = field_list(names, values)?;
+        self.start_stream(&headers, end_stream)
+    }
+
+    /// `open_stream` with this request's pseudo-headers emitted in `pseudo_order`
+    /// (see `Http2Options::set_pseudo_header_order`) instead of the connection's order.
+    pub fn open_stream_ordered(
+        &mut self,
+        names: Vec<String>,
+        values: Vec<String>,
+        end_stream: bool,
+        pseudo_order: &str,
+    ) -> Result<u32, Error> {
+        let headers = field_list(names, values)?;
+        self.start_stream_with_order(&headers, end_stream, pseudo_order)
+    }
+
+    /// Queue request body bytes for `stream_id`; `end_stream` marks the last piece.
+    /// Data beyond the current flow-control windows is held until the peer opens them.
+    pub fn send_body(
+        &mut self,
+        stream_id: u32,
+        data: &[u8],
+        end_stream: bool,
+    ) -> Result<(), Error> {
+        self.check_failed()?;
+        let stream = self
+            .streams
+            .get_mut(&stream_id)
+            .ok_or(Error::UnknownStream(stream_id))?;
+        if stream.local_closed || stream.end_after_pending {
+            return Err(Error::StreamClosed(stream_id));
+        }
+        stream.pending.extend(data);
+        stream.end_after_pending = end_stream;
+        self.flush_streams();
+        Ok(())
+    }
+
+    /// Push the next piece of a streamed request body, accepting only what the
+    /// flow-control windows can take right away (see `send_capacity`). Returns how
+    /// many bytes of `data` were accepted; offer the rest again once the server has
+    /// opened its windows. Once the response is complete the upload is aborted with
+    /// RST_STREAM (NO_ERROR), and this fails with `StreamClosed`.
+    pub fn write_body_chunk(&mut self, stream_id: u32, data: &[u8]) -> Result<usize, Error> {
+        let accepted = data.len().min(self.upload_capacity(stream_id)?);
+        self.send_body(stream_id, &data[..accepted], false)?;
+        Ok(accepted)
+    }
+
+    /// End a streamed request body (END_STREAM goes out after the queued data).
+    pub fn finish_body(&mut self, stream_id: u32) -> Result<(), Error> {
+        self.upload_capacity(stream_id)?;
+        self.send_body(stream_id, &[], true)
+    }
+
+    /// End a request body with trailers: a HEADERS frame with END_STREAM after the
+    /// queued data. Names are lowercased; fields such as content-length or host are
+    /// not allowed in trailers.
+    pub fn send_trailers(
+        &mut self,
+        stream_id: u32,
+        names: Vec<String>,
+        values: Vec<String>,
+    ) -> Result<(), Error> {
+        let trailers = field_list(names, values)?;
+        self.send_trailer_fields(stream_id, &trailers)
+    }
+
+    /// Bytes `write_body_chunk` accepts for `stream_id` right now: what both the
+    /// stream's and the connection's send windows allow, less the data already
+    /// waiting for them. 0 for finished streams.
+    pub fn send_capacity(&self, stream_id: u32) -> usize {
+        let Some(stream) = self.streams.get(&stream_id) else {
+            return 0;
+        };
+        let window = stream.send_window.min(self.send_window);
+        (window - stream.pending.len() as i64).max(0) as usize
+    }
+
+    /// Body bytes of `stream_id` still waiting for flow-control window.
+    pub fn pending_body_bytes(&self, stream_id: u32) -> usize {
+        self.streams
+            .get(&stream_id)
+            .map_or(0, |stream| stream.pending.len())
+    }
+
+    /// Return `bytes` of received body data on `stream_id` to the server once the
+    /// application has consumed it (manual flow control, see
+    /// `Http2Options::set_manual_flow_control`). WINDOW_UPDATE frames go out once half
+    /// a window is released. Resetting a stream releases whatever is left of it.
+    pub fn release_capacity(&mut self, stream_id: u32, bytes: u32) -> Result<(), Error> {
+        self.check_failed()?;
+        let unreleased = self.unreleased_bytes(stream_id);
+        if bytes > unreleased {
+            return Err(Error::ReleaseTooLarge {
+                stream_id,
+                unreleased,
+            });
+        }
+        if bytes == unreleased {
+            self.unreleased.remove(&stream_id);
+        } else {
+            self.unreleased.insert(stream_id, unreleased - bytes);
+        }
+        self.replenish(Some(stream_id), bytes);
+        Ok(())
+    }
+
+    /// Body bytes received on `stream_id` that have not been released yet.
+    pub fn unreleased_bytes(&self, stream_id: u32) -> u32 {
+        self.unreleased.get(&stream_id).copied().unwrap_or(0)
+    }
+
+    /// Abandon a stream: sends RST_STREAM with `error_code` (CANCEL is 8). A queued
+    /// request is dropped without anything being sent.
+    pub fn reset_stream(&mut self, stream_id: u32, error_code: u32) -> Result<(), Error> {
+        self.check_failed()?;
+        let stream = self
+            .streams
+            .remove(&stream_id)
+            .ok_or(Error::UnknownStream(stream_id))?;
+        if stream.queued.is_none() {
+            frame::write_rst_stream(&mut self.out, stream_id, error_code);
+            self.forget_unreleased(stream_id);
+            self.flush_streams();
+        }
+        Ok(())
+    }
+
+    /// Feed received plaintext. Events become available through `next_event`.
+    pub fn feed(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.check_failed()?;
+        let events_before = self.events.len();
+        self.buf.extend_from_slice(data);
+        let buf = mem::take(&mut self.buf);
+        let mut pos = 0;
+        let result = loop {
+            let Some(header) = FrameHeader::parse(&buf[pos..]) else {
+                break Ok(());
+            };
+            if header.len > self.local_max_frame_size as usize {
+                break Err(self.connection_error(frame::FRAME_SIZE_ERROR, "frame too large"));
+            }
+            let start = pos + frame::HEADER_LEN;
+            let Some(payload) = buf.get(start..start + header.len) else {
+                break Ok(());
+            };
+            pos = start + header.len;
+            if let Err(e) = self.handle_frame(header, payload) {
+                break Err(e);
+            }
+        };
+        self.buf = buf;
+        self.buf.drain(..pos);
+        self.time_responses(events_before);
+        match &result {
+            Ok(()) => {
+                self.flush_streams();
+                if self.is_finished() && !self.goaway_sent {
+                    // Wind down cleanly once the server's GOAWAY has been honored
+                    frame::write_goaway(&mut self.out, 0, frame::NO_ERROR);
+                    self.goaway_sent = true;
+                }
+            }
+            Err(e) => self.failed = Some(e.clone()),
+        }
+        result
+    }
+
+    /// Bytes to send with `write_plaintext`.
+    pub fn take_outgoing(&mut self) -> Vec<u8> {
+        mem::take(&mut self.out)
+    }
+
+    /// Whether `take_outgoing` has bytes to send.
+    pub fn wants_write(&self) -> bool {
+        !self.out.is_empty()
+    }
+
+    /// Next event, or nothing if more input is needed.
+    pub fn next_event(&mut self) -> Option<Http2Event> {
+        self.poll_event().map(Http2Event)
+    }
+
+    /// Number of streams that have not finished in both directions, queued
+    /// requests included.
+    pub fn open_streams(&self) -> usize {
+        self.streams.len()
+    }
+
+    /// Number of requests waiting for the server's SETTINGS_MAX_CONCURRENT_STREAMS.
+    /// They are sent, oldest first, as soon as other streams finish.
+    pub fn queued_streams(&self) -> usize {
+        self.streams.values().filter(|s| s.queued.is_some()).count()
+    }
+
+    /// State of a stream: "idle" (not opened yet), "queued", "open",
+    /// "half-closed-local" (request sent, response pending) or "closed".
+    pub fn stream_state(&self, stream_id: u32) -> String {
+        let state = match self.streams.get(&stream_id) {
+            Some(stream) => stream.state(),
+            None if stream_id >= self.next_stream_id => "idle",
+            None => "closed",
+        };
+        state.to_string()
+    }
+
+    /// Whether the peer sent GOAWAY; open a new connection for further requests.
+    pub fn is_going_away(&self) -> bool {
+        self.going_away
+    }
+
+    /// Whether the connection is going away and every stream on it has completed or
+    /// been abandoned. Send what `take_outgoing` returns (our own GOAWAY), then close
+    /// the transport with `TlsConnection::send_close_notify`.
+    pub fn is_finished(&self) -> bool {
+        self.going_away && self.streams.is_empty()
+    }
+
+    /// Tell the connection that the transport has closed. Streams still open are
+    /// reported as `Abandoned`: queued requests as retryable, sent ones as not.
+    pub fn transport_closed(&mut self) {
+        let (error_code, debug_data) = self.peer_goaway.clone().unwrap_or_default();
+        for (stream_id, stream) in mem::take(&mut self.streams) {
+            self.events.push_back(ConnectionEvent::Abandoned {
+                stream_id,
+                retryable: stream.queued.is_some(),
+                error_code,
+                debug_data: debug_data.clone(),
+            });
+        }
+        self.unreleased.clear();
+        self.going_away = true;
+        self.failed.get_or_insert(Error::GoingAway);
+    }
+
+    /// Current time in milliseconds on the caller's clock, stamped on the phases
+    /// the calls that follow reach (see `stream_timings`). Without it they stay null.
+    pub fn set_now(&mut self, now_ms: f64) {
+        self.now = Some(now_ms);
+    }
+
+    /// TLS handshake of this connection, e.g. `TlsConnection::timings()`,
+    /// reported for the streams opened before the first response byte arrived:
+    /// the ones that waited for the connection to be set up.
+    pub fn set_tls_timings(&mut self, timings: &Timings) {
+        self.setup = Timings::handshake(timings.tls_start, timings.tls_end);
+        if !self.response_seen {
+            for t in self.timings.values_mut() {
+                t.tls_start = timings.tls_start;
+                t.tls_end = timings.tls_end;
+            }
+        }
+    }
+
+    /// Phases of a stream, finished or not: queued at `open_stream`,
+    /// request_sent once END_STREAM went out, first_byte with the first frame of
+    /// its response and last_byte with the one ending it. Nothing for a stream
+    /// id never opened.
+    pub fn stream_timings(&self, stream_id: u32) -> Option<Timings> {
+        self.timings.get(&stream_id).copied()
+    }
+}
+
+impl Http2Connection {
+    /// Rust-side `next_event`.
+    pub fn poll_event(&mut self) -> Option<ConnectionEvent> {
+        self.events.pop_front()
+    }
+
+    /// Rust-side `send_trailers`.
+    pub fn send_trailer_fields(&mut self, stream_id: u32, trailers: &Headers) -> Result<(), Error> {
+        let mut fields = Vec::with_capacity(trailers.len());
+        for (name, value) in trailers.iter() {
+            let name = name.to_ascii_lowercase();
+            validate_trailer(&name, value)?;
+            fields.push((name, value.to_string()));
+        }
+        self.upload_capacity(stream_id)?;
+        if let Some(stream) = self.streams.get_mut(&stream_id) {
+            stream.trailers = Some(fields);
+            stream.end_after_pending = true;
+        }
+        self.flush_streams();
+        Ok(())
+    }
+
+    /// Rust-side `open_stream`.
+    pub fn start_stream(&mut self, headers: &Headers, end_stream: bool) -> Result<u32, Error> {
+        self.open(headers, end_stream, self.pseudo_order)
+    }
+
+    /// Rust-side `open_stream_ordered`.
+    pub fn start_stream_with_order(
+        &mut self,
+        headers: &Headers,
+        end_stream: bool,
+        pseudo_order: &str,
+    ) -> Result<u32, Error> {
+        let order = PseudoOrder::parse(pseudo_order)?;
+        self.open(headers, end_stream, order)
+    }
+
+    fn open(
+        &mut self,
+        headers: &Headers,
+        end_stream: bool,
+        order: PseudoOrder,
+    ) -> Result<u32, Error> {
+        self.check_failed()?;
+        if self.going_away || self.next_stream_id > frame::MAX_WINDOW as u32 {
+            return Err(Error::GoingAway);
+        }
+        let fields = request_fields(headers, order)?
+            .into_iter()
+            .map(|(n, v)| (n, v.to_string()))
+            .collect();
+        let stream_id = self.next_stream_id;
+        self.next_stream_id += 2;
+        self.streams.insert(
+            stream_id,
+            Stream {
+                queued: Some(fields),
+                local_closed: end_stream,
+                final_head: false,
+                send_window: self.peer.initial_window_size as i64,
+                recv_window: self.local_initial_window as i64,
+                recv_unacked: 0,
+                pending: VecDeque::new(),
+                end_after_pending: false,
+                trailers: None,
+            },
+        );
+        let setup = if self.response_seen {
+            Timings::default()
+        } else {
+            self.setup
+        };
+        self.timings.insert(
+            stream_id,
+            Timings {
+                queued: self.now,
+                ..setup
+            },
+        );
+        self.flush_streams();
+        Ok(stream_id)
+    }
+
+    /// Send the HEADERS of queued requests while the peer's concurrency limit allows,
+    /// oldest first. Returns whether any were sent.
+    fn start_queued(&mut self) -> bool {
+        let mut started = false;
+        while self.has_room() {
+            let Some((&stream_id, stream)) =
+                self.streams.iter_mut().find(|(_, s)| s.queued.is_some())
+            else {
+                break;
+            };
+            let fields = stream.queued.take().unwrap_or_default();
+            let end_stream = stream.local_closed;
+            let mut block = Vec::new();
+            self.encoder.encode(
+                fields.iter().map(|(n, v)| (n.as_str(), v.as_str())),
+                &mut block,
+            );
+            self.write_header_block(stream_id, &block, end_stream, true);
+            started = true;
+        }
+        started
+    }
+
+    /// Whether a queued request is waiting and the peer allows another open stream.
+    fn has_room(&self) -> bool {
+        let active = self.streams.values().filter(|s| s.queued.is_none()).count();
+        let queued = self.streams.len() - active;
+        queued > 0
+            && self
+                .peer
+                .max_concurrent_streams
+                .is_none_or(|limit| active < limit as usize)
+    }
+
+    /// `send_capacity` of a stream that can still take body data: a stream opened
+    /// earlier that is gone now (finished, reset or aborted) is `StreamClosed`.
+    fn upload_capacity(&self, stream_id: u32) -> Result<usize, Error> {
+        self.check_failed()?;
+        match self.streams.get(&stream_id) {
+            Some(stream) if !stream.local_closed && !stream.end_after_pending => {
+                Ok(self.send_capacity(stream_id))
+            }
+            Some(_) => Err(Error::StreamClosed(stream_id)),
+            None if stream_id % 2 == 1 && stream_id < self.next_stream_id => {
+                Err(Error::StreamClosed(stream_id))
+            }
+            None => Err(Error::UnknownStream(stream_id)),
+        }
+    }
+
+    fn check_failed(&self) -> Result<(), Error> {
+        match &self.failed {
+            Some(e) => Err(e.clone()),
+            None => Ok(()),
+        }
+    }
+
+    /// HEADERS plus as many CONTINUATION frames as the peer's frame size requires.
+    /// Only a request's head (`first`) carries the priority, trailers never do.
+    fn write_header_block(&mut self, stream_id: u32, block: &[u8], end_stream: bool, first: bool) {
+        let max = self.peer.max_frame_size as usize;
+        let mut kind = frame::HEADERS;
+        let mut flags = if end_stream {
+            frame::FLAG_END_STREAM
+        } else {
+            0
+        };
+        let mut payload = Vec::new();
+        if let Some(dependency) = self.headers_priority.as_ref().filter(|_| first) {
+            flags |= frame::FLAG_PRIORITY;
+            payload.extend_from_slice(&dependency.encode());
+        }
+        payload.extend_from_slice(block);
+        let mut chunks = payload.chunks(max).peekable();
+        // An empty block still needs its HEADERS frame
+        let first: &[u8] = chunks.next().unwrap_or_default();
+        let mut chunk = first;
+        loop {
+            if chunks.peek().is_none() {
+                flags |= frame::FLAG_END_HEADERS;
+            }
+            frame::write_frame(&mut self.out, kind, flags, stream_id, chunk);
+            match chunks.next() {
+                Some(next) => chunk = next,
+                None => break,
+            }
+            kind = frame::CONTINUATION;
+            flags = 0;
+        }
+    }
+
+    /// Start queued requests, then send whatever pending body data the windows allow,
+    /// one frame per stream in turn so that concurrent uploads share the connection.
+    fn flush_streams(&mut self) {
+        loop {
+            self.start_queued();
+            self.send_data();
+            // Streams that just finished may have made room for queued ones
+            if !self.has_room() {
+                break;
+            }
+        }
+        for (stream_id, stream) in &self.streams {
+            if stream.local_closed && stream.queued.is_none() {
+                if let Some(t) = self.timings.get_mut(stream_id) {
+                    t.request_sent = t.request_sent.or(self.now);
+                }
+            }
+        }
+    }
+
+    /// Stamp first_byte / last_byte on the streams the events queued since
+    /// `events_before` belong to.
+    fn time_responses(&mut self, events_before: usize) {
+        for event in self.events.iter().skip(events_before) {
+            let (stream_id, ends) = match event {
+                ConnectionEvent::Informational { stream_id, .. } => (*stream_id, false),
+                ConnectionEvent::Headers {
+                    stream_id,
+                    end_stream,
+                    ..
+                }
+                | ConnectionEvent::Data {
+                    stream_id,
+                    end_stream,
+                    ..
+                } => (*stream_id, *end_stream),
+                ConnectionEvent::Trailers { stream_id, .. } => (*stream_id, true),
+                ConnectionEvent::Reset { .. }
+                | ConnectionEvent::GoAway { .. }
+                | ConnectionEvent::Abandoned { .. } => continue,
+            };
+            self.response_seen = true;
+            if let Some(t) = self.timings.get_mut(&stream_id) {
+                t.first_byte = t.first_byte.or(self.now);
+                if ends {
+                    t.last_byte = t.last_byte.or(self.now);
+                }
+            }
+        }
+    }
+
+    fn send_data(&mut self) {
+        let max_frame = self.peer.max_frame_size as i64;
+        let mut trailers = Vec::new();
+        loop {
+            let mut progress = false;
+            for (&stream_id, stream) in self.streams.iter_mut() {
+                if stream.local_closed || stream.queued.is_some() {
+                    continue;
+                }
+                if stream.pending.is_empty() {
+                    if stream.end_after_pending {
+                        match stream.trailers.take() {
+                            Some(fields) => trailers.push((stream_id, fields)),
+                            None => frame::write_frame(
+                                &mut self.out,
+                                frame::DATA,
+                                frame::FLAG_END_STREAM,
+                                stream_id,
+                                &[],
+                            ),
+                        }
+                        stream.local_closed = true;
+                    }
+                    continue;
+                }
+                let n = (stream.pending.len() as i64)
+                    .min(stream.send_window)
+                    .min(self.send_window)
+                    .min(max_frame);
+                if n <= 0 {
+                    continue;
+                }
+                let chunk: Vec<u8> = stream.pending.drain(..n as usize).collect();
+                stream.send_window -= n;
+                self.send_window -= n;
+                let end = stream.pending.is_empty()
+                    && stream.end_after_pending
+                    && stream.trailers.is_none();
+                let flags = if end { frame::FLAG_END_STREAM } else { 0 };
+                frame::write_frame(&mut self.out, frame::DATA, flags, stream_id, &chunk);
+                stream.local_closed = end;
+                progress = true;
+            }
+            if !progress {
+                break;
+            }
+        }
+        // After each stream's last DATA frame, which went out above
+        for (stream_id, fields) in trailers {
+            let mut block = Vec::new();
+            self.encoder.encode(
+                fields.iter().map(|(n, v)| (n.as_str(), v.as_str())),
+                &mut block,
+            );
+            self.write_header_block(stream_id, &block, true, false);
+        }
+    }
+
+    fn handle_frame(&mut self, header: FrameHeader, payload: &[u8]) -> Result<(), Error> {
+        if let Some(partial) = &self.continuation {
+            if header.kind != frame::CONTINUATION || header.stream_id != partial.stream_id {
+                return Err(self.connection_error(frame::PROTOCOL_ERROR, "expected CONTINUATION"));
+            }
+        }
+        if !self.peer_settings_seen && header.kind != frame::SETTINGS {
+            return Err(self.connection_error(
+                frame::PROTOCOL_ERROR,
+                "server preface must start with SETTINGS",
+            ));
+        }
+        match header.kind {
+            frame::DATA => self.on_data(header, payload),
+            frame::HEADERS => self.on_headers(header, payload),
+            frame::CONTINUATION => self.on_continuation(header, payload),
+            frame::PRIORITY => {
+                if header.stream_id == 0 {
+                    return Err(
+                        self.connection_error(frame::PROTOCOL_ERROR, "PRIORITY on stream 0")
+                    );
+                }
+                Ok(())
+            }
+            frame::RST_STREAM => self.on_rst_stream(header, payload),
+            frame::SETTINGS => self.on_settings(header, payload),
+            frame::PUSH_PROMISE => self.on_push_promise(header, payload),
+            frame::PING => self.on_ping(header, payload),
+            frame::GOAWAY => self.on_goaway(header, payload),
+            frame::WINDOW_UPDATE => self.on_window_update(header, payload),
+            // Unknown frame types are ignored (RFC 9113 §4.1)
+            _ => Ok(()),
+        }
+    }
+
+    fn on_data(&mut self, header: FrameHeader, payload: &[u8]) -> Result<(), Error> {
+        let id = header.stream_id;
+        if id == 0 {
+            return Err(self.connection_error(frame::PROTOCOL_ERROR, "DATA on stream 0"));
+        }
+        // The whole frame, padding included, counts against the windows
+        let len = payload.len() as u32;
+        if len as i64 > self.recv_window {
+            return Err(
+                self.connection_error(frame::FLOW_CONTROL_ERROR, "connection window exceeded")
+            );
+        }
+        self.recv_window -= len as i64;
+
+        let data = self.strip_padding(header, payload)?;
+        let end_stream = header.flags & frame::FLAG_END_STREAM != 0;
+        let manual = self.manual_flow_control;
+        let tracked = self.check_stream(id)?.is_some();
+        let Some(stream) = self.streams.get_mut(&id).filter(|_| tracked) else {
+            // Nobody will consume it, so it is returned at once
+            self.replenish(None, len);
+            return Ok(());
+        };
+        let error = if !stream.final_head {
+            Some(frame::PROTOCOL_ERROR)
+        } else if len as i64 > stream.recv_window {
+            Some(frame::FLOW_CONTROL_ERROR)
+        } else {
+            None
+        };
+        if let Some(code) = error {
+            self.replenish(None, len);
+            return self.reset(id, code);
+        }
+        stream.recv_window -= len as i64;
+        // In manual mode the data waits for `release_capacity`; padding never does
+        let held = if manual { data.len() as u32 } else { 0 };
+        if held > 0 {
+            *self.unreleased.entry(id).or_default() += held;
+        }
+        self.events.push_back(ConnectionEvent::Data {
+            stream_id: id,
+            data: data.to_vec(),
+            end_stream,
+        });
+        if end_stream {
+            self.close_remote(id);
+        }
+        self.replenish(Some(id), len - held);
+        Ok(())
+    }
+
+    /// Give `n` received bytes back to the peer's send windows (the stream's only while
+    /// it can still receive), with WINDOW_UPDATE once half a window has accumulated.
+    fn replenish(&mut self, stream_id: Option<u32>, n: u32) {
+        if n == 0 {
+            return;
+        }
+        self.recv_unacked += n;
+        if self.recv_unacked >= self.local_connection_window / 2 {
+            frame::write_window_update(&mut self.out, 0, self.recv_unacked);
+            self.recv_window += self.recv_unacked as i64;
+            self.recv_unacked = 0;
+        }
+        let threshold = self.local_initial_window / 2;
+        let Some(id) = stream_id else {
+            return;
+        };
+        let Some(stream) = self.streams.get_mut(&id) else {
+            return;
+        };
+        stream.recv_unacked += n;
+        if stream.recv_unacked >= threshold {
+            frame::write_window_update(&mut self.out, id, stream.recv_unacked);
+            stream.recv_window += stream.recv_unacked as i64;
+            stream.recv_unacked = 0;
+        }
+    }
+
+    /// A stream will not be read any further: return its unreleased bytes to the
+    /// connection window.
+    fn forget_unreleased(&mut self, stream_id: u32) {
+        if let Some(n) = self.unreleased.remove(&stream_id) {
+            self.replenish(None, n);
+        }
+    }
+
+    fn on_headers(&mut self, header: FrameHeader, payload: &[u8]) -> Result<(), Error> {
+        if header.stream_id == 0 {
+            return Err(self.connection_error(frame::PROTOCOL_ERROR, "HEADERS on stream 0"));
+        }
+        let mut fragment = self.strip_padding(header, payload)?;
+        if header.flags & frame::FLAG_PRIORITY != 0 {
+            fragment = fragment
+                .get(5..)
+                .ok_or_else(|| self.connection_error(frame::FRAME_SIZE_ERROR, "short HEADERS"))?;
+        }
+        let partial = PartialBlock {
+            stream_id: header.stream_id,
+            block: fragment.to_vec(),
+            end_stream: header.flags & frame::FLAG_END_STREAM != 0,
+            promised_stream_id: None,
+        };
+        if header.flags & frame::FLAG_END_HEADERS != 0 {
+            self.on_header_block(partial)
+        } else {
+            self.continuation = Some(partial);
+            Ok(())
+        }
+    }
+
+    fn on_push_promise(&mut self, header: FrameHeader, payload: &[u8]) -> Result<(), Error> {
+        if !self.push_enabled {
+            return Err(self.connection_error(frame::PROTOCOL_ERROR, "unexpected PUSH_PROMISE"));
+        }
+        if header.stream_id == 0 {
+            return Err(self.connection_error(frame::PROTOCOL_ERROR, "PUSH_PROMISE on stream 0"));
+        }
+        let fragment = self.strip_padding(header, payload)?;
+        let Some((promised, fragment)) = fragment.split_first_chunk::<4>() else {
+            return Err(self.connection_error(frame::FRAME_SIZE_ERROR, "short PUSH_PROMISE"));
+        };
+        let promised = u32::from_be_bytes(*promised) & 0x7fff_ffff;
+        if !promised.is_multiple_of(2) || promised <= self.last_promised_id {
+            return Err(self.connection_error(frame::PROTOCOL_ERROR, "bad promised stream id"));
+        }
+        self.check_stream(header.stream_id)?;
+        self.last_promised_id = promised;
+        let partial = PartialBlock {
+            stream_id: header.stream_id,
+            block: fragment.to_vec(),
+            end_stream: false,
+            promised_stream_id: Some(promised),
+        };
+        if header.flags & frame::FLAG_END_HEADERS != 0 {
+            self.on_header_block(partial)
+        } else {
+            self.continuation = Some(partial);
+            Ok(())
+        }
+    }
+
+    fn on_continuation(&mut self, header: FrameHeader, payload: &[u8]) -> Result<(), Error> {
+        let Some(mut partial) = self.continuation.take() else {
+            return Err(self.connection_error(frame::PROTOCOL_ERROR, "unexpected CONTINUATION"));
+        };
+        partial.block.extend_from_slice(payload);
+        if partial.block.len() > frame::MAX_MAX_FRAME_SIZE as usize {
+            return Err(self.connection_error(frame::PROTOCOL_ERROR, "header block too large"));
+        }
+        if header.flags & frame::FLAG_END_HEADERS != 0 {
+            self.on_header_block(partial)
+        } else {
+            self.continuation = Some(partial);
+            Ok(())
+        }
+    }
+
+    fn on_header_block(&mut self, partial: PartialBlock) -> Result<(), Error> {
+        // Decode even for streams we no longer track, to keep the HPACK state in sync
+        let headers = match self.decoder.decode(&partial.block) {
+            Ok(headers) => headers,
+            Err(e) => return Err(self.fatal(frame::COMPRESSION_ERROR, e)),
+        };
+        if let Some(promised) = partial.promised_stream_id {
+            // Pushed responses are never used
+            frame::write_rst_stream(&mut self.out, promised, frame::CANCEL);
+            return Ok(());
+        }
+        let id = partial.stream_id;
+        let end_stream = partial.end_stream;
+        let Some(stream) = self.check_stream(id)? else {
+            return Ok(());
+        };
+        if stream.final_head {
+            if !end_stream || !valid_response_fields(&headers, false) {
+                return self.reset(id, frame::PROTOCOL_ERROR);
+            }
+            self.events.push_back(ConnectionEvent::Trailers {
+                stream_id: id,
+                headers,
+            });
+        } else {
+            if !valid_response_fields(&headers, true) {
+                return self.reset(id, frame::PROTOCOL_ERROR);
+            }
+            let interim = headers
+                .get(":status")
+                .is_some_and(|s| s.starts_with('1') && s != "101");
+            if interim && end_stream {
+                return self.reset(id, frame::PROTOCOL_ERROR);
+            }
+            stream.final_head = !interim;
+            self.events.push_back(if interim {
+                ConnectionEvent::Informational {
+                    stream_id: id,
+                    headers,
+                }
+            } else {
+                ConnectionEvent::Headers {
+                    stream_id: id,
+                    headers,
+                    end_stream,
+                }
+            });
+        }
+        if end_stream {
+            self.close_remote(id);
+        }
+        Ok(())
+    }
+
+    fn on_rst_stream(&mut self, header: FrameHeader, payload: &[u8]) -> Result<(), Error> {
+        if header.stream_id == 0 {
+            return Err(self.connection_error(frame::PROTOCOL_ERROR, "RST_STREAM on stream 0"));
+        }
+        if payload.len() != 4 {
+            return Err(self.connection_error(frame::FRAME_SIZE_ERROR, "bad RST_STREAM length"));
+        }
+        if self.check_stream(header.stream_id)?.is_some() {
+            self.streams.remove(&header.stream_id);
+            self.forget_unreleased(header.stream_id);
+            self.events.push_back(ConnectionEvent::Reset {
+                stream_id: header.stream_id,
+                error_code: be_u32(payload),
+            });
+        }
+        Ok(())
+    }
+
+    fn on_settings(&mut self, header: FrameHeader, payload: &[u8]) -> Result<(), Error> {
+        if header.stream_id != 0 {
+            return Err(self.connection_error(frame::PROTOCOL_ERROR, "SETTINGS on a stream"));
+        }
+        if header.flags & frame::FLAG_ACK != 0 {
+            if !payload.is_empty() || !self.peer_settings_seen {
+                return Err(self.connection_error(frame::FRAME_SIZE_ERROR, "bad SETTINGS ACK"));
+            }
+            return Ok(());
+        }
+        if !payload.len().is_multiple_of(6) {
+            return Err(self.connection_error(frame::FRAME_SIZE_ERROR, "bad SETTINGS length"));
+        }
+        for setting in payload.chunks(6) {
+            let id = u16::from_be_bytes([setting[0], setting[1]]);
+            let value = be_u32(&setting[2..]);
+            match id {
+                frame::SETTINGS_HEADER_TABLE_SIZE => {
+                    self.encoder.set_peer_max_table_size(value as usize);
+                }
+                frame::SETTINGS_ENABLE_PUSH if value != 0 => {
+                    return Err(self.connection_error(frame::PROTOCOL_ERROR, "server enabled push"));
+                }
+                frame::SETTINGS_MAX_CONCURRENT_STREAMS => {
+                    self.peer.max_concurrent_streams = Some(value);
+                }
+                frame::SETTINGS_INITIAL_WINDOW_SIZE => {
+                    if value as i64 > frame::MAX_WINDOW {
+                        return Err(self.connection_error(
+                            frame::FLOW_CONTROL_ERROR,
+                            "initial window too large",
+                        ));
+                    }
+                    let delta = value as i64 - self.peer.initial_window_size as i64;
+                    self.peer.initial_window_size = value;
+                    for stream in self.streams.values_mut() {
+                        stream.send_window += delta;
+                        if stream.send_window > frame::MAX_WINDOW {
+                            return Err(self.connection_error(
+                                frame::FLOW_CONTROL_ERROR,
+                                "stream window overflow",
+                            ));
+                        }
+                    }
+                }
+                frame::SETTINGS_MAX_FRAME_SIZE => {
+                    if !(frame::MIN_MAX_FRAME_SIZE..=frame::MAX_MAX_FRAME_SIZE).contains(&value) {
+                        return Err(
+                            self.connection_error(frame::PROTOCOL_ERROR, "bad max frame size")
+                        );
+                    }
+                    self.peer.max_frame_size = value;
+                }
+                // Unknown settings are ignored (RFC 9113 §6.5.2)
+                _ => {}
+            }
+        }
+        self.peer_settings_seen = true;
+        frame::write_frame(&mut self.out, frame::SETTINGS, frame::FLAG_ACK, 0, &[]);
+        self.flush_streams();
+        Ok(())
+    }
+
+    fn on_ping(&mut self, header: FrameHeader, payload: &[u8]) -> Result<(), Error> {
+        if header.stream_id != 0 {
+            return Err(self.connection_error(frame::PROTOCOL_ERROR, "PING on a stream"));
+        }
+        if payload.len() != 8 {
+            return Err(self.connection_error(frame::FRAME_SIZE_ERROR, "bad PING length"));
+        }
+        if header.flags & frame::FLAG_ACK == 0 {
+            frame::write_frame(&mut self.out, frame::PING, frame::FLAG_ACK, 0, payload);
+        }
+        Ok(())
+    }
+
+    fn on_goaway(&mut self, header: FrameHeader, payload: &[u8]) -> Result<(), Error> {
+        if header.stream_id != 0 {
+            return Err(self.connection_error(frame::PROTOCOL_ERROR, "GOAWAY on a stream"));
+        }
+        if payload.len() < 8 {
+            return Err(self.connection_error(frame::FRAME_SIZE_ERROR, "short GOAWAY"));
+        }
+        let last_stream_id = be_u32(payload) & 0x7fff_ffff;
+        let error_code = be_u32(&payload[4..]);
+        let debug_data = payload[8..].to_vec();
+        self.going_away = true;
+        self.peer_goaway = Some((error_code, debug_data.clone()));
+        self.events.push_back(ConnectionEvent::GoAway {
+            last_stream_id,
+            error_code,
+            debug_data: debug_data.clone(),
+        });
+        let mut refused: Vec<u32> = self
+            .streams
+            .split_off(&(last_stream_id + 1))
+            .into_keys()
+            .collect();
+        // Requests still queued are never sent
+        let queued: Vec<u32> = self
+            .streams
+            .iter()
+            .filter(|(_, s)| s.queued.is_some())
+            .map(|(&id, _)| id)
+            .collect();
+        for id in &queued {
+            self.streams.remove(id);
+        }
+        refused.extend(queued);
+        refused.sort_unstable();
+        for stream_id in refused {
+            self.forget_unreleased(stream_id);
+            self.events.push_back(ConnectionEvent::Abandoned {
+                stream_id,
+                retryable: true,
+                error_code,
+                debug_data: debug_data.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    fn on_window_update(&mut self, header: FrameHeader, payload: &[u8]) -> Result<(), Error> {
+        if payload.len() != 4 {
+            return Err(self.connection_error(frame::FRAME_SIZE_ERROR, "bad WINDOW_UPDATE length"));
+        }
+        let increment = (be_u32(payload) & 0x7fff_ffff) as i64;
+        let id = header.stream_id;
+        if id == 0 {
+            if increment == 0 {
+                return Err(self.connection_error(frame::PROTOCOL_ERROR, "zero window increment"));
+            }
+            self.send_window += increment;
+            if self.send_window > frame::MAX_WINDOW {
+                return Err(self.connection_error(frame::FLOW_CONTROL_ERROR, "window overflow"));
+            }
+        } else {
+            let Some(stream) = self.check_stream(id)? else {
+                return Ok(());
+            };
+            if increment == 0 {
+                return self.reset(id, frame::PROTOCOL_ERROR);
+            }
+            stream.send_window += increment;
+            if stream.send_window > frame::MAX_WINDOW {
+                return self.reset(id, frame::FLOW_CONTROL_ERROR);
+            }
+        }
+        self.flush_streams();
+        Ok(())
+    }
+
+    /// The stream a frame refers to, or nothing if it has already finished (frames may
+    /// still be in flight). A stream we never opened is a connection error.
+    fn check_stream(&mut self, id: u32) -> Result<Option<&mut Stream>, Error> {
+        if id.is_multiple_of(2) && id <= self.last_promised_id {
+            // A refused push
+            return Ok(None);
+        }
+        let queued = self.streams.get(&id).is_some_and(|s| s.queued.is_some());
+        if id >= self.next_stream_id || id.is_multiple_of(2) || queued {
+            return Err(self.connection_error(frame::PROTOCOL_ERROR, "frame on an idle stream"));
+        }
+        Ok(self.streams.get_mut(&id))
+    }
+
+    /// Payload of a DATA or HEADERS frame without its padding.
+    fn strip_padding<'a>(
+        &mut self,
+        header: FrameHeader,
+        payload: &'a [u8],
+    ) -> Result<&'a [u8], Error> {
+        if header.flags & frame::FLAG_PADDED == 0 {
+            return Ok(payload);
+        }
+        match payload.split_first() {
+            Some((&pad, rest)) if (pad as usize) <= rest.len() => {
+                Ok(&rest[..rest.len() - pad as usize])
+            }
+            _ => Err(self.connection_error(frame::PROTOCOL_ERROR, "bad padding")),
+        }
+    }
+
+    fn close_remote(&mut self, id: u32) {
+        if let Some(stream) = self.streams.remove(&id) {
+            if !stream.local_closed {
+                // The response is complete, so the rest of the request body is
+                // not needed (RFC 9113 §8.1)
+                frame::write_rst_stream(&mut self.out, id, frame::NO_ERROR);
+            }
+        }
+    }
+
+    /// Stream error: reset the stream and report it.
+    fn reset(&mut self, stream_id: u32, error_code: u32) -> Result<(), Error> {
+        self.streams.remove(&stream_id);
+        self.forget_unreleased(stream_id);
+        frame::write_rst_stream(&mut self.out, stream_id, error_code);
+        self.events.push_back(ConnectionEvent::Reset {
+            stream_id,
+            error_code,
+        });
+        Ok(())
+    }
+
+    fn connection_error(&mut self, code: u32, reason: &'static str) -> Error {
+        self.fatal(code, Error::Http2 { code, reason })
+    }
+
+    /// Queue GOAWAY with `code`; the connection accepts nothing afterwards.
+    fn fatal(&mut self, code: u32, error: Error) -> Error {
+        // The server never opens streams we process, so the last stream id is 0
+        frame::write_goaway(&mut self.out, 0, code);
+        self.goaway_sent = true;
+        self.going_away = true;
+        error
+    }
+}
+
+fn field_list(names: Vec<String>, values: Vec<String>) -> Result<Headers, Error> {
+    if names.len() != values.len() {
+        return Err(Error::HeaderCountMismatch);
+    }
+    let mut headers = Headers::new();
+    for (name, value) in names.into_iter().zip(values) {
+        headers.push(name, value);
+    }
+    Ok(headers)
+}
+
+/// Validate request fields, lowercase their names and put the pseudo-headers in `order`.
+fn request_fields(headers: &Headers, order: PseudoOrder) -> Result<Vec<(String, &str)>, Error> {
+    let mut fields = Vec::with_capacity(headers.len());
+    let mut seen_regular = false;
+    let mut pseudo: Vec<String> = Vec::new();
+    for (name, value) in headers.iter() {
+        let name = name.to_ascii_lowercase();
+        if value.bytes().any(|b| b == b'\r' || b == b'\n' || b == 0) {
+            return Err(Error::InvalidHeaderValue(name));
+        }
+        if let Some(pseudo_name) = name.strip_prefix(':') {
+            let known = matches!(
+                pseudo_name,
+                "method" | "scheme" | "authority" | "path" | "protocol"
+            );
+            if !known || seen_regular || pseudo.contains(&name) {
+                return Err(Error::InvalidPseudoHeader(name));
+            }
+            pseudo.push(name.clone());
+        } else {
+            if !is_token(&name) {
+                return Err(Error::InvalidHeaderName(name));
+            }
+            let connection_specific = matches!(
+                name.as_str(),
+                "connection" | "keep-alive" | "proxy-connection" | "transfer-encoding" | "upgrade"
+            );
+            if connection_specific || (name == "te" && !value.eq_ignore_ascii_case("trailers")) {
+                return Err(Error::ConnectionHeader(name));
+            }
+            seen_regular = true;
+        }
+        fields.push((name, value));
+    }
+    let has = |n: &str| pseudo.iter().any(|p| p == n);
+    let connect = headers.get(":method") == Some("CONNECT");
+    // CONNECT requests carry only :method and :authority
+    let required: &[&str] = if connect {
+        &[":method", ":authority"]
+    } else {
+        &[":method", ":scheme", ":path"]
+    };
+    if let Some(missing) = required.iter().find(|r| !has(r)) {
+        return Err(Error::InvalidPseudoHeader(missing.to_string()));
+    }
+    // Pseudo-headers come first, so the stable sort leaves regular fields untouched
+    fields[..pseudo.len()].sort_by_key(|(name, _)| order.position(name));
+    Ok(fields)
+}
+
+/// Response heads carry exactly one leading `:status`; trailers carry none.
+/// Names must be lowercase.
+fn valid_response_fields(headers: &Headers, head: bool) -> bool {
+    let mut names = headers.iter().map(|(n, _)| n);
+    if head {
+        let status_ok = headers
+            .get(":status")
+            .is_some_and(|s| s.len() == 3 && s.bytes().all(|b| b.is_ascii_digit()));
+        if names.next() != Some(":status") || !status_ok {
+            return false;
+        }
+    }
+    names.all(|n| !n.starts_with(':') && !n.bytes().any(|b| b.is_ascii_uppercase()))
+}
+
+/// One connection event, as exposed to JS.
+#[wasm_bindgen]
+#[derive(Debug)]
+pub struct Http2Event(ConnectionEvent);
+
+#[wasm_bindgen]
+impl Http2Event {
+    /// "informational", "headers", "data", "trailers", "reset", "goaway" or "abandoned".
+    pub fn kind(&self) -> String {
+        match self.0 {
+            ConnectionEvent::Informational { .. } => "informational",
+            ConnectionEvent::Headers { .. } => "headers",
+            ConnectionEvent::Data { .. } => "data",
+            ConnectionEvent::Trailers { .. } => "trailers",
+            ConnectionEvent::Reset { .. } => "reset",
+            ConnectionEvent::GoAway { .. } => "goaway",
+            ConnectionEvent::Abandoned { .. } => "abandoned",
+        }
+        .to_string()
+    }
+
+    /// Stream the event belongs to (the last processed stream id for "goaway").
+    pub fn stream_id(&self) -> u32 {
+        match self.0 {
+            ConnectionEvent::Informational { stream_id, .. }
+            | ConnectionEvent::Headers { stream_id, .. }
+            | ConnectionEvent::Data { stream_id, .. }
+            | ConnectionEvent::Trailers { stream_id, .. }
+            | ConnectionEvent::Reset { stream_id, .. }
+            | ConnectionEvent::Abandoned { stream_id, .. } => stream_id,
+            ConnectionEvent::GoAway { last_stream_id, .. } => last_stream_id,
+        }
+    }
+
+    /// Whether this is the last event of its stream.
+    pub fn end_stream(&self) -> bool {
+        match self.0 {
+            ConnectionEvent::Headers { end_stream, .. }
+            | ConnectionEvent::Data { end_stream, .. } => end_stream,
+            ConnectionEvent::Trailers { .. }
+            | ConnectionEvent::Reset { .. }
+            | ConnectionEvent::Abandoned { .. } => true,
+            ConnectionEvent::Informational { .. } | ConnectionEvent::GoAway { .. } => false,
+        }
+    }
+
+    /// Status code of a headers event (0 otherwise).
+    pub fn status(&self) -> u16 {
+        self.fields()
+            .and_then(|h| h.get(":status"))
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0)
+    }
+
+    /// Field names of a headers or trailers event, pseudo-headers included.
+    pub fn header_names(&self) -> Vec<String> {
+        self.fields().map(Headers::names).unwrap_or_default()
+    }
+
+    pub fn header_values(&self) -> Vec<String> {
+        self.fields().map(Headers::values).unwrap_or_default()
+    }
+
+    /// Error code of a reset, goaway or abandoned event (0 otherwise).
+    pub fn error_code(&self) -> u32 {
+        match self.0 {
+            ConnectionEvent::Reset { error_code, .. }
+            | ConnectionEvent::GoAway { error_code, .. }
+            | ConnectionEvent::Abandoned { error_code, .. } => error_code,
+            _ => 0,
+        }
+    }
+
+    /// Whether the request of a reset or abandoned stream was never processed by the
+    /// server and can safely be sent again (on a new connection if this one is going
+    /// away). A server RST_STREAM with REFUSED_STREAM counts too (RFC 9113 §8.7).
+    pub fn retryable(&self) -> bool {
+        match self.0 {
+            ConnectionEvent::Reset { error_code, .. } => error_code == frame::REFUSED_STREAM,
+            ConnectionEvent::Abandoned { retryable, .. } => retryable,
+            _ => false,
+        }
+    }
+
+    /// Take the payload of a data event, or the debug data of a goaway or abandoned event.
+    pub fn take_data(&mut self) -> Vec<u8> {
+        match &mut self.0 {
+            ConnectionEvent::Data { data, .. } => mem::take(data),
+            ConnectionEvent::GoAway { debug_data, .. }
+            | ConnectionEvent::Abandoned { debug_data, .. } => mem::take(debug_data),
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl Http2Event {
+    pub fn into_inner(self) -> ConnectionEvent {
+        self.0
+    }
+
+    fn fields(&self) -> Option<&Headers> {
+        match &self.0 {
+            ConnectionEvent::Informational { headers, .. }
+            | ConnectionEvent::Headers { headers, .. }
+            | ConnectionEvent::Trailers { headers, .. } => Some(headers),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http2::hpack::{EncoderConfig, DEFAULT_TABLE_SIZE};
+    use frame::{CANCEL, NO_ERROR, PROTOCOL_ERROR};
+
+    fn frame(kind: u8, flags: u8, stream_id: u32, payload: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        frame::write_frame(&mut out, kind, flags, stream_id, payload);
+        out
+    }
+
+    fn settings(values: &[(u16, u32)]) -> Vec<u8> {
+        let mut out = Vec::new();
+        frame::write_settings(&mut out, values);
+        out
+    }
+
+    fn frames(mut bytes: &[u8]) -> Vec<(FrameHeader, Vec<u8>)> {
+        let mut frames = Vec::new();
+        while let Some(header) = FrameHeader::parse(bytes) {
+            let end = frame::HEADER_LEN + header.len;
+            frames.push((header, bytes[frame::HEADER_LEN..end].to_vec()));
+            bytes = &bytes[end..];
+        }
+        assert!(bytes.is_empty());
+        frames
+    }
+
+    fn request(path: &str) -> Headers {
+        let mut headers = Headers::new();
+        for (n, v) in [
+            (":method", "GET"),
+            (":authority", "example.com"),
+            (":scheme", "https"),
+            (":path", path),
+            ("User-Agent", "test"),
+        ] {
+            headers.push(n, v);
+        }
+        headers
+    }
+
+    /// The server side of a conversation, encoding with its own HPACK state.
+    struct Server {
+        encoder: Encoder,
+        decoder: Decoder,
+    }
+
+    impl Server {
+        fn new() -> Self {
+            Server {
+                encoder: Encoder::new(EncoderConfig::default()),
+                decoder: Decoder::new(DEFAULT_TABLE_SIZE, usize::MAX),
+            }
+        }
+
+        fn headers(&mut self, stream_id: u32, fields: &[(&str, &str)], flags: u8) -> Vec<u8> {
+            let mut block = Vec::new();
+            self.encoder.encode(fields.iter().copied(), &mut block);
+            frame(
+                frame::HEADERS,
+                flags | frame::FLAG_END_HEADERS,
+                stream_id,
+                &block,
+            )
+        }
+    }
+
+    /// A connection past the SETTINGS exchange, with its preface bytes discarded.
+    fn connected(peer_settings: &[(u16, u32)]) -> Http2Connection {
+        let mut conn = Http2Connection::new();
+        conn.take_outgoing();
+        conn.feed(&settings(peer_settings)).unwrap();
+        conn.take_outgoing();
+        conn
+    }
+
+    fn drain(conn: &mut Http2Connection) -> Vec<ConnectionEvent> {
+        std::iter::from_fn(|| conn.poll_event()).collect()
+    }
+
+    #[test]
+    fn preface_and_settings_exchange() {
+        let mut conn = Http2Connection::new();
+        let out = conn.take_outgoing();
+        assert_eq!(&out[..PREFACE.len()], PREFACE);
+        let sent = frames(&out[PREFACE.len()..]);
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0].0.kind, frame::SETTINGS);
+        assert_eq!(
+            sent[0].1,
+            [0, 1, 0, 1, 0, 0, 0, 2, 0, 0, 0, 0, 0, 4, 0, 0x60, 0, 0, 0, 6, 0, 4, 0, 0]
+        );
+        assert_eq!(sent[1].0.kind, frame::WINDOW_UPDATE);
+        assert_eq!(be_u32(&sent[1].1), 15_663_105);
+
+        // Anything but SETTINGS first is a protocol error
+        let mut early = Http2Connection::new();
+        let ping = frame(frame::PING, 0, 0, &[0; 8]);
+        assert!(matches!(
+            early.feed(&ping),
+            Err(Error::Http2 { code: 1, .. })
+        ));
+
+        conn.feed(&settings(&[(frame::SETTINGS_MAX_CONCURRENT_STREAMS, 100)]))
+            .unwrap();
+        let ack = frames(&conn.take_outgoing());
+        assert_eq!(
+            ack[0].0,
+            FrameHeader::parse(&frame(frame::SETTINGS, 1, 0, &[])).unwrap()
+        );
+    }
+
+    #[test]
+    fn firefox_preface_and_refused_push() {
+        let mut conn = Http2Connection::with_options(&Http2Options::profile("firefox").unwrap());
+        let out = conn.take_outgoing();
+        let sent = frames(&out[PREFACE.len()..]);
+        let kinds: Vec<u8> = sent.iter().map(|(h, _)| h.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                frame::SETTINGS,
+                frame::WINDOW_UPDATE,
+                frame::PRIORITY,
+                frame::PRIORITY,
+                frame::PRIORITY,
+                frame::PRIORITY,
+                frame::PRIORITY,
+                frame::PRIORITY
+            ]
+        );
+        assert_eq!(be_u32(&sent[1].1), 12_517_377);
+        // Stream 9 depends on 7, wire weight 0
+        assert_eq!(sent[5].0.stream_id, 9);
+        assert_eq!(sent[5].1, [0, 0, 0, 7, 0]);
+
+        conn.feed(&settings(&[])).unwrap();
+        conn.take_outgoing();
+        let id = conn.start_stream(&request("/"), true).unwrap();
+        assert_eq!(id, 15);
+        let sent = frames(&conn.take_outgoing());
+        assert_ne!(sent[0].0.flags & frame::FLAG_PRIORITY, 0);
+        assert_eq!(sent[0].1[..5], [0, 0, 0, 13, 41]);
+
+        // Our SETTINGS leave push enabled; promises are decoded, then cancelled
+        let mut server = Server::new();
+        let mut block = Vec::new();
+        server
+            .encoder
+            .encode([(":method", "GET"), (":path", "/app.js")], &mut block);
+        let promise = [&2u32.to_be_bytes()[..], &block].concat();
+        conn.feed(&frame(
+            frame::PUSH_PROMISE,
+            frame::FLAG_END_HEADERS,
+            id,
+            &promise,
+        ))
+        .unwrap();
+        let sent = frames(&conn.take_outgoing());
+        assert_eq!(sent[0].0.kind, frame::RST_STREAM);
+        assert_eq!(sent[0].0.stream_id, 2);
+        assert_eq!(be_u32(&sent[0].1), CANCEL);
+        // Frames on the refused stream are ignored
+        conn.feed(&server.headers(2, &[(":status", "200")], 0))
+            .unwrap();
+        conn.feed(&server.headers(id, &[(":status", "204")], frame::FLAG_END_STREAM))
+            .unwrap();
+        assert!(matches!(
+            drain(&mut conn)[..],
+            [ConnectionEvent::Headers { stream_id: 15, .. }]
+        ));
+        // Promised ids must increase
+        assert!(conn
+            .feed(&frame(
+                frame::PUSH_PROMISE,
+                frame::FLAG_END_HEADERS,
+                15,
+                &promise
+            ))
+            .is_err());
+    }
+
+    #[test]
+    fn pseudo_header_order_follows_profile() {
+        let mut request = Headers::new();
+        for (n, v) in [
+            (":path", "/"),
+            (":scheme", "https"),
+            (":method", "GET"),
+            (":authority", "example.com"),
+            ("user-agent", "test"),
+            ("accept", "*/*"),
+        ] {
+            request.push(n, v);
+        }
+        let names = |conn: &mut Http2Connection, server: &mut Server| {
+            let sent = frames(&conn.take_outgoing());
+            let fields = server.decoder.decode(&sent[0].1[5..]).unwrap();
+            fields
+                .iter()
+                .map(|(n, _)| n.to_string())
+                .collect::<Vec<_>>()
+        };
+        for (profile, expected) in [
+            ("chrome", [":method", ":authority", ":scheme", ":path"]),
+            ("chrome-old", [":method", ":authority", ":scheme", ":path"]),
+            ("firefox", [":method", ":path", ":authority", ":scheme"]),
+        ] {
+            let mut conn = Http2Connection::with_options(&Http2Options::profile(profile).unwrap());
+            conn.feed(&settings(&[])).unwrap();
+            conn.take_outgoing();
+            let mut server = Server::new();
+            conn.start_stream(&request, true).unwrap();
+            let mut all = expected.to_vec();
+            all.extend(["user-agent", "accept"]);
+            assert_eq!(names(&mut conn, &mut server), all, "{}", profile);
+
+            conn.start_stream_with_order(&request, true, "s,p,a,m")
+                .unwrap();
+            assert_eq!(
+                names(&mut conn, &mut server),
+                [
+                    ":scheme",
+                    ":path",
+                    ":authority",
+                    ":method",
+                    "user-agent",
+                    "accept"
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn get_request_and_response() {
+        let mut conn = connected(&[]);
+        let mut server = Server::new();
+        let id = conn.start_stream(&request("/"), true).unwrap();
+        assert_eq!(id, 1);
+
+        let sent = frames(&conn.take_outgoing());
+        assert_eq!(sent.len(), 1);
+        let (header, block) = &sent[0];
+        assert_eq!(header.kind, frame::HEADERS);
+        assert_eq!(
+            header.flags,
+            frame::FLAG_END_STREAM | frame::FLAG_END_HEADERS | frame::FLAG_PRIORITY
+        );
+        // Exclusive dependency on stream 0, weight 256
+        assert_eq!(block[..5], [0x80, 0, 0, 0, 255]);
+        let fields = server.decoder.decode(&block[5..]).unwrap();
+        let names: Vec<_> = fields.iter().map(|(n, _)| n).collect();
+        assert_eq!(
+            names,
+            [":method", ":authority", ":scheme", ":path", "user-agent"]
+        );
+
+        let mut response =
+            server.headers(1, &[(":status", "200"), ("content-type", "text/plain")], 0);
+        response.extend(frame(frame::DATA, 0, 1, b"hello "));
+        // Padded DATA: pad length 3
+        response.extend(frame(
+            frame::DATA,
+            frame::FLAG_PADDED | frame::FLAG_END_STREAM,
+            1,
+            b"\x03world\0\0\0",
+        ));
+        for step in [1, 7, response.len()] {
+            let mut conn = connected(&[]);
+            conn.start_stream(&request("/"), true).unwrap();
+            for piece in response.chunks(step) {
+                conn.feed(piece).unwrap();
+            }
+            let events = drain(&mut conn);
+            let ConnectionEvent::Headers {
+                headers,
+                end_stream: false,
+                ..
+            } = &events[0]
+            else {
+                panic!("{:?}", events);
+            };
+            assert_eq!(headers.get(":status"), Some("200"));
+            let body: Vec<u8> = events[1..]
+                .iter()
+                .flat_map(|e| match e {
+                    ConnectionEvent::Data { data, .. } => data.clone(),
+                    _ => panic!("{:?}", e),
+                })
+                .collect();
+            assert_eq!(body, b"hello world");
+            assert!(matches!(
+                events.last(),
+                Some(ConnectionEvent::Data {
+                    end_stream: true,
+                    ..
+                })
+            ));
+            assert_eq!(conn.open_streams(), 0);
+        }
+    }
+
+    #[test]
+    fn interim_continuation_and_trailers() {
+        let mut conn = connected(&[]);
+        let mut server = Server::new();
+        conn.start_stream(&request("/grpc"), true).unwrap();
+        conn.take_outgoing();
+
+        let mut input = server.headers(
+            1,
+            &[(":status", "103"), ("link", "</a.css>; rel=preload")],
+            0,
+        );
+        input.extend(server.headers(
+            1,
+            &[(":status", "103"), ("link", "</b.js>; rel=preload")],
+            0,
+        ));
+        // Final head split across HEADERS and CONTINUATION
+        let mut block = Vec::new();
+        server.encoder.encode(
+            [(":status", "200"), ("content-type", "application/grpc")],
+            &mut block,
+        );
+        input.extend(frame(frame::HEADERS, 0, 1, &block[..3]));
+        input.extend(frame(
+            frame::CONTINUATION,
+            frame::FLAG_END_HEADERS,
+            1,
+            &block[3..],
+        ));
+        input.extend(frame(frame::DATA, 0, 1, b"msg"));
+        input.extend(server.headers(1, &[("grpc-status", "0")], frame::FLAG_END_STREAM));
+        conn.feed(&input).unwrap();
+
+        let statuses: Vec<_> = drain(&mut conn)
+            .into_iter()
+            .map(|e| match e {
+                ConnectionEvent::Informational { headers, .. } => {
+                    format!(
+                        "{} {}",
+                        headers.get(":status").unwrap(),
+                        headers.get("link").unwrap()
+                    )
+                }
+                ConnectionEvent::Headers { headers, .. } => {
+                    headers.get(":status").unwrap().to_string()
+                }
+                ConnectionEvent::Data { .. } => "data".to_string(),
+                ConnectionEvent::Trailers { headers, .. } => {
+                    headers.get("grpc-status").unwrap().to_string()
+                }
+                e => panic!("{:?}", e),
+            })
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                "103 </a.css>; rel=preload",
+                "103 </b.js>; rel=preload",
+                "200",
+                "data",
+                "0"
+            ]
+        );
+        assert_eq!(conn.open_streams(), 0);
+    }
+
+    #[test]
+    fn request_body_is_flow_controlled() {
+        let mut conn = connected(&[(frame::SETTINGS_INITIAL_WINDOW_SIZE, 10)]);
+        let id = conn.start_stream(&request("/upload"), false).unwrap();
+        conn.send_body(id, &[b'x'; 25], true).unwrap();
+        let sent = frames(&conn.take_outgoing());
+        assert_eq!(sent.last().unwrap().1.len(), 10);
+        assert_eq!(conn.pending_body_bytes(id), 15);
+        assert_eq!(
+            conn.send_body(id, b"more", false),
+            Err(Error::StreamClosed(id))
+        );
+
+        conn.feed(&frame(frame::WINDOW_UPDATE, 0, id, &10u32.to_be_bytes()))
+            .unwrap();
+        let sent = frames(&conn.take_outgoing());
+        assert_eq!((sent.len(), sent[0].1.len(), sent[0].0.flags), (1, 10, 0));
+
+        // Shrinking the initial window can push the stream window below zero
+        conn.feed(&settings(&[(frame::SETTINGS_INITIAL_WINDOW_SIZE, 0)]))
+            .unwrap();
+        conn.feed(&frame(frame::WINDOW_UPDATE, 0, id, &9u32.to_be_bytes()))
+            .unwrap();
+        let sent = frames(&conn.take_outgoing());
+        assert!(sent.iter().all(|(h, _)| h.kind != frame::DATA));
+        conn.feed(&frame(frame::WINDOW_UPDATE, 0, id, &6u32.to_be_bytes()))
+            .unwrap();
+        let sent = frames(&conn.take_outgoing());
+        let (header, payload) = sent.last().unwrap();
+        assert_eq!(
+            (header.kind, header.flags, payload.len()),
+            (frame::DATA, frame::FLAG_END_STREAM, 5)
+        );
+        assert_eq!(conn.pending_body_bytes(id), 0);
+    }
+
+    #[test]
+    fn streamed_upload_follows_the_windows() {
+        let mut conn = connected(&[]);
+        let id = conn.start_stream(&request("/upload"), false).unwrap();
+        conn.take_outgoing();
+        let piece = vec![7u8; 16 * 1024];
+        let total = 50 << 20;
+        let (mut offered, mut sent, mut unacked) = (0, 0, 0);
+        while offered < total {
+            let accepted = conn
+                .write_body_chunk(id, &piece[offered % piece.len()..])
+                .unwrap();
+            offered += accepted;
+            // Never more is buffered than the windows let out
+            assert_eq!(conn.pending_body_bytes(id), 0);
+            for (header, payload) in frames(&conn.take_outgoing()) {
+                assert_eq!((header.kind, header.flags), (frame::DATA, 0));
+                sent += payload.len();
+                unacked += payload.len() as u32;
+            }
+            if accepted == 0 {
+                assert_eq!(conn.send_capacity(id), 0);
+                let increment = unacked.to_be_bytes();
+                conn.feed(&frame(frame::WINDOW_UPDATE, 0, 0, &increment))
+                    .unwrap();
+                conn.feed(&frame(frame::WINDOW_UPDATE, 0, id, &increment))
+                    .unwrap();
+                assert_eq!(conn.send_capacity(id), 65_535);
+                unacked = 0;
+            }
+        }
+        assert_eq!(sent, total);
+        conn.finish_body(id).unwrap();
+        let last = frames(&conn.take_outgoing());
+        assert_eq!(last[0].0.flags, frame::FLAG_END_STREAM);
+        assert_eq!(conn.finish_body(id), Err(Error::StreamClosed(id)));
+    }
+
+    #[test]
+    fn request_trailers_follow_the_body() {
+        let mut conn = connected(&[(frame::SETTINGS_INITIAL_WINDOW_SIZE, 2)]);
+        let mut server = Server::new();
+        let id = conn.start_stream(&request("/rpc"), false).unwrap();
+        conn.send_body(id, b"abcd", false).unwrap();
+        let mut trailers = Headers::new();
+        trailers.push("Content-Length", "4");
+        assert_eq!(
+            conn.send_trailer_fields(id, &trailers),
+            Err(Error::ForbiddenTrailer("content-length".to_string()))
+        );
+        let mut trailers = Headers::new();
+        trailers.push("Grpc-Status", "0");
+        conn.send_trailer_fields(id, &trailers).unwrap();
+        assert_eq!(
+            conn.write_body_chunk(id, b"late"),
+            Err(Error::StreamClosed(id))
+        );
+        let sent = frames(&conn.take_outgoing());
+        server.decoder.decode(&sent[0].1[5..]).unwrap();
+        // The trailers wait for the data the window holds back
+        assert_eq!(sent[1].0.kind, frame::DATA);
+        assert_eq!((sent.len(), sent[1].0.flags), (2, 0));
+
+        conn.feed(&frame(frame::WINDOW_UPDATE, 0, id, &2u32.to_be_bytes()))
+            .unwrap();
+        let sent = frames(&conn.take_outgoing());
+        assert_eq!((sent[0].0.kind, sent[0].0.flags), (frame::DATA, 0));
+        let (header, block) = &sent[1];
+        assert_eq!(header.kind, frame::HEADERS);
+        assert_eq!(
+            header.flags,
+            frame::FLAG_END_STREAM | frame::FLAG_END_HEADERS
+        );
+        let decoded = server.decoder.decode(block).unwrap();
+        assert_eq!(decoded.names(), ["grpc-status"]);
+        assert_eq!(conn.stream_state(id), "half-closed-local");
+    }
+
+    #[test]
+    fn complete_response_aborts_the_upload() {
+        let mut conn = connected(&[]);
+        let mut server = Server::new();
+        let id = conn.start_stream(&request("/upload"), false).unwrap();
+        assert_eq!(conn.write_body_chunk(id, b"part").unwrap(), 4);
+        conn.take_outgoing();
+        conn.feed(&server.headers(id, &[(":status", "413")], frame::FLAG_END_STREAM))
+            .unwrap();
+        let sent = frames(&conn.take_outgoing());
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].0.kind, frame::RST_STREAM);
+        assert_eq!(be_u32(&sent[0].1), NO_ERROR);
+        assert!(matches!(
+            &drain(&mut conn)[..],
+            [ConnectionEvent::Headers {
+                end_stream: true,
+                ..
+            }]
+        ));
+        assert_eq!(
+            conn.write_body_chunk(id, b"more"),
+            Err(Error::StreamClosed(id))
+        );
+        assert_eq!(conn.finish_body(id), Err(Error::StreamClosed(id)));
+        assert_eq!(conn.send_capacity(id), 0);
+        assert_eq!(conn.open_streams(), 0);
+        assert_eq!(
+            conn.write_body_chunk(99, b"x"),
+            Err(Error::UnknownStream(99))
+        );
+    }
+
+    #[test]
+    fn concurrent_uploads_are_interleaved() {
+        let mut conn = connected(&[(frame::SETTINGS_INITIAL_WINDOW_SIZE, 0)]);
+        let first = conn.start_stream(&request("/a"), false).unwrap();
+        let second = conn.start_stream(&request("/b"), false).unwrap();
+        conn.send_body(first, &[b'a'; 40_000], true).unwrap();
+        conn.send_body(second, &[b'b'; 40_000], true).unwrap();
+        conn.take_outgoing();
+
+        // Both streams get window at once and share the 65535-byte connection window
+        conn.feed(&settings(&[(frame::SETTINGS_INITIAL_WINDOW_SIZE, 65_535)]))
+            .unwrap();
+        let data = |conn: &mut Http2Connection| -> Vec<(u32, usize)> {
+            frames(&conn.take_outgoing())
+                .iter()
+                .filter(|(h, _)| h.kind == frame::DATA)
+                .map(|(h, payload)| (h.stream_id, payload.len()))
+                .collect()
+        };
+        assert_eq!(
+            data(&mut conn),
+            [
+                (first, 16_384),
+                (second, 16_384),
+                (first, 16_384),
+                (second, 16_383)
+            ]
+        );
+
+        conn.feed(&frame(
+            frame::WINDOW_UPDATE,
+            0,
+            0,
+            &100_000u32.to_be_bytes(),
+        ))
+        .unwrap();
+        assert_eq!(data(&mut conn), [(first, 7_232), (second, 7_233)]);
+    }
+
+    #[test]
+    fn excess_requests_are_queued() {
+        let mut conn = connected(&[(frame::SETTINGS_MAX_CONCURRENT_STREAMS, 2)]);
+        let mut server = Server::new();
+        let ids: Vec<u32> = ["/1", "/2", "/3", "/4"]
+            .iter()
+            .map(|path| conn.start_stream(&request(path), true).unwrap())
+            .collect();
+        let sent = frames(&conn.take_outgoing());
+        let opened: Vec<u32> = sent.iter().map(|(h, _)| h.stream_id).collect();
+        assert_eq!(opened, [ids[0], ids[1]]);
+        for (_, block) in &sent {
+            server.decoder.decode(&block[5..]).unwrap();
+        }
+        assert_eq!((conn.open_streams(), conn.queued_streams()), (4, 2));
+        assert_eq!(conn.stream_state(ids[0]), "half-closed-local");
+        assert_eq!(conn.stream_state(ids[2]), "queued");
+        assert_eq!(conn.stream_state(99), "idle");
+
+        // A queued request is dropped without touching the wire
+        conn.reset_stream(ids[2], CANCEL).unwrap();
+        assert!(conn.take_outgoing().is_empty());
+        assert_eq!(conn.stream_state(ids[2]), "closed");
+
+        // Finishing a stream sends the next queued request
+        conn.feed(&server.headers(ids[0], &[(":status", "204")], frame::FLAG_END_STREAM))
+            .unwrap();
+        let sent = frames(&conn.take_outgoing());
+        assert_eq!(sent.len(), 1);
+        assert_eq!(
+            (sent[0].0.kind, sent[0].0.stream_id),
+            (frame::HEADERS, ids[3])
+        );
+        let fields = server.decoder.decode(&sent[0].1[5..]).unwrap();
+        assert_eq!(fields.get(":path"), Some("/4"));
+        assert_eq!(conn.queued_streams(), 0);
+
+        // Server frames for a stream that is still queued are a protocol error
+        let mut conn = connected(&[(frame::SETTINGS_MAX_CONCURRENT_STREAMS, 0)]);
+        let id = conn.start_stream(&request("/"), true).unwrap();
+        assert!(conn.take_outgoing().is_empty());
+        let mut server = Server::new();
+        assert!(conn
+            .feed(&server.headers(id, &[(":status", "200")], 0))
+            .is_err());
+
+        // GOAWAY refuses queued requests even when it covers their ids
+        let mut conn = connected(&[(frame::SETTINGS_MAX_CONCURRENT_STREAMS, 1)]);
+        let sent_id = conn.start_stream(&request("/"), true).unwrap();
+        let queued_id = conn.start_stream(&request("/"), true).unwrap();
+        let mut goaway = frame::MAX_WINDOW.to_be_bytes()[4..].to_vec();
+        goaway.extend_from_slice(&NO_ERROR.to_be_bytes());
+        conn.feed(&frame(frame::GOAWAY, 0, 0, &goaway)).unwrap();
+        assert!(matches!(
+            drain(&mut conn)[..],
+            [
+                ConnectionEvent::GoAway { .. },
+                ConnectionEvent::Abandoned { stream_id, retryable: true, .. }
+            ] if stream_id == queued_id
+        ));
+        assert_eq!(conn.stream_state(sent_id), "half-closed-local");
+    }
+
+    #[test]
+    fn received_data_is_replenished() {
+        let mut conn = connected(&[]);
+        let mut server = Server::new();
+        conn.start_stream(&request("/big"), true).unwrap();
+        conn.take_outgoing();
+        conn.feed(&server.headers(1, &[(":status", "200")], 0))
+            .unwrap();
+        let chunk = frame(frame::DATA, 0, 1, &[0; 16_384]);
+        let mut updates = Vec::new();
+        for _ in 0..200 {
+            conn.feed(&chunk).unwrap();
+            updates.extend(
+                frames(&conn.take_outgoing())
+                    .into_iter()
+                    .filter(|(h, _)| h.kind == frame::WINDOW_UPDATE)
+                    .map(|(h, p)| (h.stream_id, be_u32(&p))),
+            );
+        }
+        // The stream window (6 MiB) is topped up at half; the connection (15 MiB) is not yet
+        assert_eq!(updates, [(1, 3 * 1024 * 1024)]);
+    }
+
+    #[test]
+    fn manual_flow_control_waits_for_release() {
+        let mut options = Http2Options::new();
+        options.set_manual_flow_control(true);
+        options.set_initial_stream_window(40_000).unwrap();
+        options.set_connection_window(65_535).unwrap();
+        let mut conn = Http2Connection::with_options(&options);
+        let sent = frames(&conn.take_outgoing()[PREFACE.len()..]);
+        assert!(sent.iter().all(|(h, _)| h.kind != frame::WINDOW_UPDATE));
+        conn.feed(&settings(&[])).unwrap();
+        let mut server = Server::new();
+        let id = conn.start_stream(&request("/big"), true).unwrap();
+        conn.take_outgoing();
+        conn.feed(&server.headers(id, &[(":status", "200")], 0))
+            .unwrap();
+
+        let updates = |conn: &mut Http2Connection| -> Vec<(u32, u32)> {
+            frames(&conn.take_outgoing())
+                .into_iter()
+                .filter(|(h, _)| h.kind == frame::WINDOW_UPDATE)
+                .map(|(h, p)| (h.stream_id, be_u32(&p)))
+                .collect()
+        };
+        // The server sends a whole stream window, then has to stop
+        for len in [16_384, 16_384, 7_232] {
+            conn.feed(&frame(frame::DATA, 0, id, &vec![0; len]))
+                .unwrap();
+        }
+        assert!(updates(&mut conn).is_empty());
+        assert_eq!(conn.unreleased_bytes(id), 40_000);
+
+        conn.release_capacity(id, 10_000).unwrap();
+        assert!(updates(&mut conn).is_empty());
+        conn.release_capacity(id, 30_000).unwrap();
+        assert_eq!(updates(&mut conn), [(0, 40_000), (id, 40_000)]);
+        assert_eq!(
+            conn.release_capacity(id, 1),
+            Err(Error::ReleaseTooLarge {
+                stream_id: id,
+                unreleased: 0
+            })
+        );
+
+        // ...and resumes with the released window
+        conn.feed(&frame(frame::DATA, 0, id, &[0; 16_384])).unwrap();
+        assert_eq!(conn.unreleased_bytes(id), 16_384);
+        conn.reset_stream(id, CANCEL).unwrap();
+        assert_eq!(conn.unreleased_bytes(id), 0);
+    }
+
+    #[test]
+    fn resets_pings_and_goaway() {
+        let mut conn = connected(&[]);
+        let mut server = Server::new();
+        let first = conn.start_stream(&request("/1"), true).unwrap();
+        let second = conn.start_stream(&request("/2"), true).unwrap();
+        let third = conn.start_stream(&request("/3"), true).unwrap();
+        conn.take_outgoing();
+
+        conn.feed(&frame(frame::PING, 0, 0, b"pingpong")).unwrap();
+        let pong = frames(&conn.take_outgoing());
+        assert_eq!(
+            (pong[0].0.flags, pong[0].1.as_slice()),
+            (frame::FLAG_ACK, &b"pingpong"[..])
+        );
+
+        conn.feed(&frame(frame::RST_STREAM, 0, first, &CANCEL.to_be_bytes()))
+            .unwrap();
+        // DATA before any response head is malformed
+        conn.feed(&frame(frame::DATA, 0, second, b"x")).unwrap();
+        let mut goaway = second.to_be_bytes().to_vec();
+        goaway.extend(NO_ERROR.to_be_bytes());
+        goaway.extend(b"recycle");
+        conn.feed(&frame(frame::GOAWAY, 0, 0, &goaway)).unwrap();
+        assert_eq!(
+            drain(&mut conn),
+            [
+                ConnectionEvent::Reset {
+                    stream_id: first,
+                    error_code: CANCEL
+                },
+                ConnectionEvent::Reset {
+                    stream_id: second,
+                    error_code: PROTOCOL_ERROR
+                },
+                ConnectionEvent::GoAway {
+                    last_stream_id: second,
+                    error_code: NO_ERROR,
+                    debug_data: b"recycle".to_vec()
+                },
+                ConnectionEvent::Abandoned {
+                    stream_id: third,
+                    retryable: true,
+                    error_code: NO_ERROR,
+                    debug_data: b"recycle".to_vec()
+                },
+            ]
+        );
+        let rst = frames(&conn.take_outgoing());
+        assert_eq!(
+            (rst[0].0.kind, rst[0].0.stream_id),
+            (frame::RST_STREAM, second)
+        );
+        assert_eq!(
+            conn.start_stream(&request("/4"), true),
+            Err(Error::GoingAway)
+        );
+
+        // Late frames for finished streams are ignored, but still decoded
+        conn.feed(&server.headers(first, &[(":status", "200")], frame::FLAG_END_STREAM))
+            .unwrap();
+        assert!(drain(&mut conn).is_empty());
+    }
+
+    #[test]
+    fn goaway_reports_retryable_streams() {
+        let mut conn = connected(&[]);
+        let mut server = Server::new();
+        let ids: Vec<u32> = ["/1", "/2", "/3"]
+            .iter()
+            .map(|path| conn.start_stream(&request(path), true).unwrap())
+            .collect();
+        conn.take_outgoing();
+        let mut goaway = ids[0].to_be_bytes().to_vec();
+        goaway.extend_from_slice(&NO_ERROR.to_be_bytes());
+        goaway.extend(b"bye");
+        conn.feed(&frame(frame::GOAWAY, 0, 0, &goaway)).unwrap();
+        let events: Vec<Http2Event> = std::iter::from_fn(|| conn.next_event()).collect();
+        let summary: Vec<(String, u32, bool)> = events
+            .iter()
+            .map(|e| (e.kind(), e.stream_id(), e.retryable()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("goaway".to_string(), ids[0], false),
+                ("abandoned".to_string(), ids[1], true),
+                ("abandoned".to_string(), ids[2], true),
+            ]
+        );
+        let mut abandoned = events.into_iter().nth(1).unwrap();
+        assert_eq!(abandoned.take_data(), b"bye");
+        assert!(!conn.is_finished());
+        assert!(conn.take_outgoing().is_empty());
+
+        // The stream below the threshold completes, then we send our own GOAWAY
+        conn.feed(&server.headers(ids[0], &[(":status", "204")], frame::FLAG_END_STREAM))
+            .unwrap();
+        assert!(conn.is_finished());
+        let sent = frames(&conn.take_outgoing());
+        assert_eq!(sent[0].0.kind, frame::GOAWAY);
+        assert_eq!(be_u32(&sent[0].1[4..]), NO_ERROR);
+
+        // A transport that closes early leaves processed streams non-retryable
+        let mut conn = connected(&[(frame::SETTINGS_MAX_CONCURRENT_STREAMS, 1)]);
+        let sent_id = conn.start_stream(&request("/"), true).unwrap();
+        let queued_id = conn.start_stream(&request("/"), true).unwrap();
+        conn.transport_closed();
+        let abandoned: Vec<(u32, bool)> = drain(&mut conn)
+            .into_iter()
+            .map(|e| match e {
+                ConnectionEvent::Abandoned {
+                    stream_id,
+                    retryable,
+                    ..
+                } => (stream_id, retryable),
+                other => panic!("unexpected {:?}", other),
+            })
+            .collect();
+        assert_eq!(abandoned, [(sent_id, false), (queued_id, true)]);
+        assert!(conn.is_finished());
+        assert_eq!(
+            conn.start_stream(&request("/"), true),
+            Err(Error::GoingAway)
+        );
+    }
+
+    #[test]
+    fn connection_errors_send_goaway() {
+        for input in [
+            frame(frame::DATA, 0, 7, b"idle stream"),
+            frame(
+                frame::PUSH_PROMISE,
+                frame::FLAG_END_HEADERS,
+                1,
+                &[0, 0, 0, 2],
+            ),
+            frame(frame::WINDOW_UPDATE, 0, 0, &0u32.to_be_bytes()),
+            frame(frame::HEADERS, frame::FLAG_END_HEADERS, 1, &[0xbe]),
+            frame(frame::SETTINGS, 0, 0, &[0, 5, 0, 0, 0x10]),
+        ] {
+            let mut conn = connected(&[]);
+            conn.start_stream(&request("/"), true).unwrap();
+            conn.take_outgoing();
+            let err = conn.feed(&input).unwrap_err();
+            let sent = frames(&conn.take_outgoing());
+            assert_eq!(sent.last().unwrap().0.kind, frame::GOAWAY, "{}", err);
+            assert_eq!(conn.feed(&[]), Err(err));
+        }
+    }
+
+    #[test]
+    fn invalid_request_headers() {
+        let mut conn = connected(&[]);
+        let mut late_pseudo = request("/");
+        late_pseudo.push(":protocol", "websocket");
+        let mut connection = request("/");
+        connection.push("Connection", "keep-alive");
+        let mut missing = Headers::new();
+        missing.push(":method", "GET");
+        for (headers, err) in [
+            (late_pseudo, Error::InvalidPseudoHeader(":protocol".into())),
+            (connection, Error::ConnectionHeader("connection".into())),
+            (missing, Error::InvalidPseudoHeader(":scheme".into())),
+        ] {
+            assert_eq!(conn.start_stream(&headers, true), Err(err));
+        }
+        assert!(conn.take_outgoing().is_empty());
+        assert_eq!(
+            conn.open_stream(vec![":method".into()], vec![], true),
+            Err(Error::HeaderCountMismatch)
+        );
+    }
+
+    #[test]
+    fn streams_are_timed_on_the_caller_clock() {
+        let mut conn = connected(&[]);
+        conn.set_tls_timings(&Timings::handshake(Some(1.0), Some(4.0)));
+        conn.set_now(10.0);
+        let a = conn.start_stream(&request("/a"), true).unwrap();
+        conn.set_now(20.0);
+        let b = conn.start_stream(&request("/b"), false).unwrap();
+        let timed = conn.stream_timings(b).unwrap();
+        assert_eq!((timed.queued, timed.request_sent), (Some(20.0), None));
+        conn.set_now(30.0);
+        conn.send_body(b, b"x", true).unwrap();
+
+        let mut server = Server::new();
+        conn.set_now(40.0);
+        conn.feed(&server.headers(a, &[(":status", "200")], 0))
+            .unwrap();
+        conn.set_now(50.0);
+        conn.feed(&frame(frame::DATA, frame::FLAG_END_STREAM, a, b"ok"))
+            .unwrap();
+        let c = conn.start_stream(&request("/c"), true).unwrap();
+
+        let timed = conn.stream_timings(a).unwrap();
+        assert_eq!(
+            timed.to_json(),
+            r#"{"queued":10,"tlsStart":1,"tlsEnd":4,"requestSent":10,"firstByte":40,"lastByte":50}"#
+        );
+        assert_eq!(conn.stream_timings(b).unwrap().request_sent, Some(30.0));
+        assert_eq!(conn.stream_timings(b).unwrap().tls_end, Some(4.0));
+        // Opened once the connection had already answered: it did not wait for TLS
+        assert_eq!(conn.stream_timings(c).unwrap().tls_start, None);
+        assert_eq!(conn.stream_timings(99), None);
+    }
+}
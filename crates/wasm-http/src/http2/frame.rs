@@ -0,0 +1,152 @@
+//! Frame layout, types, flags, setting ids and error codes (RFC 9113 §4, §6, §7).
+
+pub(crate) const HEADER_LEN: usize = 9;
+
+pub(crate) const DATA: u8 = 0x0;
+pub(crate) const HEADERS: u8 = 0x1;
+pub(crate) const PRIORITY: u8 = 0x2;
+pub(crate) const RST_STREAM: u8 = 0x3;
+pub(crate) const SETTINGS: u8 = 0x4;
+pub(crate) const PUSH_PROMISE: u8 = 0x5;
+pub(crate) const PING: u8 = 0x6;
+pub(crate) const GOAWAY: u8 = 0x7;
+pub(crate) const WINDOW_UPDATE: u8 = 0x8;
+pub(crate) const CONTINUATION: u8 = 0x9;
+
+pub(crate) const FLAG_END_STREAM: u8 = 0x1;
+pub(crate) const FLAG_ACK: u8 = 0x1;
+pub(crate) const FLAG_END_HEADERS: u8 = 0x4;
+pub(crate) const FLAG_PADDED: u8 = 0x8;
+pub(crate) const FLAG_PRIORITY: u8 = 0x20;
+
+pub const SETTINGS_HEADER_TABLE_SIZE: u16 = 0x1;
+pub const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+pub const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+pub const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+pub const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+pub const SETTINGS_MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+pub const NO_ERROR: u32 = 0x0;
+pub const PROTOCOL_ERROR: u32 = 0x1;
+pub const INTERNAL_ERROR: u32 = 0x2;
+pub const FLOW_CONTROL_ERROR: u32 = 0x3;
+pub const STREAM_CLOSED: u32 = 0x5;
+pub const FRAME_SIZE_ERROR: u32 = 0x6;
+pub const REFUSED_STREAM: u32 = 0x7;
+pub const CANCEL: u32 = 0x8;
+pub const COMPRESSION_ERROR: u32 = 0x9;
+
+/// Smallest (and default) SETTINGS_MAX_FRAME_SIZE.
+pub(crate) const MIN_MAX_FRAME_SIZE: u32 = 16_384;
+/// Largest SETTINGS_MAX_FRAME_SIZE a peer may announce.
+pub(crate) const MAX_MAX_FRAME_SIZE: u32 = (1 << 24) - 1;
+/// Largest flow-control window.
+pub(crate) const MAX_WINDOW: i64 = (1 << 31) - 1;
+/// Stream and connection windows before any SETTINGS or WINDOW_UPDATE.
+pub(crate) const DEFAULT_WINDOW: u32 = 65_535;
+
+/// Name of an error code, as used in RFC 9113 §7.
+pub fn error_code_name(code: u32) -> &'static str {
+    match code {
+        0x0 => "NO_ERROR",
+        0x1 => "PROTOCOL_ERROR",
+        0x2 => "INTERNAL_ERROR",
+        0x3 => "FLOW_CONTROL_ERROR",
+        0x4 => "SETTINGS_TIMEOUT",
+        0x5 => "STREAM_CLOSED",
+        0x6 => "FRAME_SIZE_ERROR",
+        0x7 => "REFUSED_STREAM",
+        0x8 => "CANCEL",
+        0x9 => "COMPRESSION_ERROR",
+        0xa => "CONNECT_ERROR",
+        0xb => "ENHANCE_YOUR_CALM",
+        0xc => "INADEQUATE_SECURITY",
+        0xd => "HTTP_1_1_REQUIRED",
+        _ => "UNKNOWN_ERROR",
+    }
+}
+
+/// Stream dependency and weight (RFC 9113 §5.3.1), as carried by PRIORITY frames and
+/// HEADERS frames with the PRIORITY flag.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Dependency {
+    pub(crate) exclusive: bool,
+    pub(crate) depends_on: u32,
+    /// 1-256; the wire value is one less
+    pub(crate) weight: u16,
+}
+
+impl Dependency {
+    /// The 5-byte wire form.
+    pub(crate) fn encode(&self) -> [u8; 5] {
+        let mut b = [0; 5];
+        let exclusive = if self.exclusive { 0x8000_0000 } else { 0 };
+        b[..4].copy_from_slice(&(self.depends_on | exclusive).to_be_bytes());
+        b[4] = (self.weight - 1) as u8;
+        b
+    }
+}
+
+/// The fixed 9-byte frame header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct FrameHeader {
+    pub(crate) len: usize,
+    pub(crate) kind: u8,
+    pub(crate) flags: u8,
+    pub(crate) stream_id: u32,
+}
+
+impl FrameHeader {
+    /// Parse a frame header from the start of `buf`, if enough bytes are there.
+    pub(crate) fn parse(buf: &[u8]) -> Option<FrameHeader> {
+        let h = buf.get(..HEADER_LEN)?;
+        Some(FrameHeader {
+            len: (h[0] as usize) << 16 | (h[1] as usize) << 8 | h[2] as usize,
+            kind: h[3],
+            flags: h[4],
+            // The reserved high bit is ignored on receipt
+            stream_id: be_u32(&h[5..9]) & 0x7fff_ffff,
+        })
+    }
+}
+
+/// Append a frame with `payload` (at most 2^24 - 1 bytes) to `out`.
+pub(crate) fn write_frame(out: &mut Vec<u8>, kind: u8, flags: u8, stream_id: u32, payload: &[u8]) {
+    let len = payload.len() as u32;
+    out.extend_from_slice(&len.to_be_bytes()[1..]);
+    out.push(kind);
+    out.push(flags);
+    out.extend_from_slice(&stream_id.to_be_bytes());
+    out.extend_from_slice(payload);
+}
+
+pub(crate) fn write_settings(out: &mut Vec<u8>, settings: &[(u16, u32)]) {
+    let payload: Vec<u8> = settings
+        .iter()
+        .flat_map(|&(id, value)| id.to_be_bytes().into_iter().chain(value.to_be_bytes()))
+        .collect();
+    write_frame(out, SETTINGS, 0, 0, &payload);
+}
+
+pub(crate) fn write_window_update(out: &mut Vec<u8>, stream_id: u32, increment: u32) {
+    write_frame(out, WINDOW_UPDATE, 0, stream_id, &increment.to_be_bytes());
+}
+
+pub(crate) fn write_rst_stream(out: &mut Vec<u8>, stream_id: u32, code: u32) {
+    write_frame(out, RST_STREAM, 0, stream_id, &code.to_be_bytes());
+}
+
+pub(crate) fn write_priority(out: &mut Vec<u8>, stream_id: u32, dependency: &Dependency) {
+    write_frame(out, PRIORITY, 0, stream_id, &dependency.encode());
+}
+
+pub(crate) fn write_goaway(out: &mut Vec<u8>, last_stream_id: u32, code: u32) {
+    let mut payload = last_stream_id.to_be_bytes().to_vec();
+    payload.extend_from_slice(&code.to_be_bytes());
+    write_frame(out, GOAWAY, 0, 0, &payload);
+}
+
+/// Big-endian u32 from the first four bytes of `b` (callers check the length).
+pub(crate) fn be_u32(b: &[u8]) -> u32 {
+    u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+}
@@ -0,0 +1,684 @@
+//! HPACK header compression (RFC 7541).
+
+use std::collections::VecDeque;
+
+use super::huffman;
+use crate::error::Error;
+use crate::headers::{latin1, Headers};
+
+/// Table size both sides start from (the SETTINGS_HEADER_TABLE_SIZE default).
+pub(crate) const DEFAULT_TABLE_SIZE: usize = 4096;
+
+/// Per-entry overhead counted towards the table size (RFC 7541 §4.1).
+const ENTRY_OVERHEAD: usize = 32;
+
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+#[derive(Clone, Debug)]
+struct Entry {
+    name: String,
+    value: String,
+    /// Octets plus overhead; kept because decoded names are Latin-1, not UTF-8
+    size: usize,
+}
+
+#[derive(Debug)]
+struct DynamicTable {
+    /// Newest first, matching HPACK index order
+    entries: VecDeque<Entry>,
+    size: usize,
+    max_size: usize,
+}
+
+impl DynamicTable {
+    fn new(max_size: usize) -> Self {
+        DynamicTable {
+            entries: VecDeque::new(),
+            size: 0,
+            max_size,
+        }
+    }
+
+    fn insert(&mut self, name: String, value: String, octets: usize) {
+        let size = octets + ENTRY_OVERHEAD;
+        if size > self.max_size {
+            // An entry larger than the table empties it (RFC 7541 §4.4)
+            self.entries.clear();
+            self.size = 0;
+            return;
+        }
+        self.size += size;
+        self.entries.push_front(Entry { name, value, size });
+        self.evict();
+    }
+
+    fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = max_size;
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        while self.size > self.max_size {
+            match self.entries.pop_back() {
+                Some(e) => self.size -= e.size,
+                None => break,
+            }
+        }
+    }
+}
+
+/// Decodes header blocks received from the peer.
+#[derive(Debug)]
+pub(crate) struct Decoder {
+    table: DynamicTable,
+    /// Largest size a table size update may ask for (our SETTINGS_HEADER_TABLE_SIZE)
+    max_table_size: usize,
+    /// Largest decoded header list accepted (our SETTINGS_MAX_HEADER_LIST_SIZE)
+    max_list_size: usize,
+}
+
+impl Decoder {
+    pub(crate) fn new(max_table_size: usize, max_list_size: usize) -> Self {
+        Decoder {
+            table: DynamicTable::new(DEFAULT_TABLE_SIZE.min(max_table_size)),
+            max_table_size,
+            max_list_size,
+        }
+    }
+
+    /// Decode one complete header block (HEADERS plus any CONTINUATION payloads).
+    pub(crate) fn decode(&mut self, block: &[u8]) -> Result<Headers, Error> {
+        let mut r = Reader { buf: block, pos: 0 };
+        let mut headers = Headers::new();
+        let mut list_size = 0;
+        while let Some(first) = r.peek() {
+            let (name, value, octets) = if first & 0x80 != 0 {
+                let index = r.integer(7)?;
+                let (name, value) = self.lookup(index)?;
+                let octets = latin1_len(name) + latin1_len(value);
+                (name.to_string(), value.to_string(), octets)
+            } else if first & 0x20 != 0 && first & 0x40 == 0 {
+                // Dynamic table size update, only allowed before the first field
+                if !headers.is_empty() {
+                    return Err(Error::Compression("table size update after a field"));
+                }
+                let size = r.integer(5)?;
+                if size > self.max_table_size {
+                    return Err(Error::Compression("table size update above the limit"));
+                }
+                self.table.set_max_size(size);
+                continue;
+            } else {
+                // Literal with incremental indexing (01), without indexing (0000)
+                // or never indexed (0001)
+                let indexed = first & 0x40 != 0;
+                let index = r.integer(if indexed { 6 } else { 4 })?;
+                let name = match index {
+                    0 => latin1(&r.string()?),
+                    _ => self.lookup(index)?.0.to_string(),
+                };
+                let value = latin1(&r.string()?);
+                let octets = latin1_len(&name) + latin1_len(&value);
+                if indexed {
+                    self.table.insert(name.clone(), value.clone(), octets);
+                }
+                (name, value, octets)
+            };
+            list_size += octets + ENTRY_OVERHEAD;
+            if list_size > self.max_list_size {
+                return Err(Error::HeadersTooLarge {
+                    limit: self.max_list_size,
+                });
+            }
+            headers.push(name, value);
+        }
+        Ok(headers)
+    }
+
+    fn lookup(&self, index: usize) -> Result<(&str, &str), Error> {
+        match index {
+            0 => Err(Error::Compression("index 0")),
+            1..=61 => Ok(STATIC_TABLE[index - 1]),
+            _ => self
+                .table
+                .entries
+                .get(index - 62)
+                .map(|e| (e.name.as_str(), e.value.as_str()))
+                .ok_or(Error::Compression("index outside the table")),
+        }
+    }
+}
+
+/// How the encoder codes string literals.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HuffmanMode {
+    /// Huffman-code a literal only when that makes it shorter (what Chrome does)
+    Shorter,
+    Always,
+    Never,
+}
+
+/// Encoder behavior that shows up on the wire. The defaults reproduce Chrome.
+#[derive(Clone, Debug)]
+pub(crate) struct EncoderConfig {
+    /// Dynamic table size to use, capped by the peer's limit; None uses the whole limit
+    pub(crate) table_size: Option<usize>,
+    pub(crate) huffman: HuffmanMode,
+    /// Lowercase names always sent as never-indexed literals
+    pub(crate) never_indexed: Vec<String>,
+    /// Send each `cookie` pair as its own field (RFC 9113 §8.2.3)
+    pub(crate) split_cookies: bool,
+}
+
+impl Default for EncoderConfig {
+    fn default() -> Self {
+        EncoderConfig {
+            table_size: None,
+            huffman: HuffmanMode::Shorter,
+            never_indexed: Vec::new(),
+            split_cookies: true,
+        }
+    }
+}
+
+/// Encodes header blocks sent to the peer.
+#[derive(Debug)]
+pub(crate) struct Encoder {
+    config: EncoderConfig,
+    table: DynamicTable,
+    /// Smallest size the table was set to since the last block (RFC 7541 §4.2)
+    pending_min_size: Option<usize>,
+}
+
+impl Encoder {
+    pub(crate) fn new(config: EncoderConfig) -> Self {
+        let mut encoder = Encoder {
+            config,
+            table: DynamicTable::new(DEFAULT_TABLE_SIZE),
+            pending_min_size: None,
+        };
+        // A configured size below the default is announced in the first block
+        encoder.set_peer_max_table_size(DEFAULT_TABLE_SIZE);
+        encoder
+    }
+
+    /// Apply the peer's SETTINGS_HEADER_TABLE_SIZE. A resulting size change is
+    /// announced at the start of the next block, as Chrome does even when the
+    /// table grows.
+    pub(crate) fn set_peer_max_table_size(&mut self, peer_max: usize) {
+        let size = self.config.table_size.map_or(peer_max, |s| s.min(peer_max));
+        if size != self.table.max_size {
+            self.pending_min_size = Some(self.pending_min_size.map_or(size, |m| m.min(size)));
+            self.table.set_max_size(size);
+        }
+    }
+
+    /// Append the encoding of `fields` to `out`.
+    ///
+    /// Like Chrome, every field goes into the dynamic table except pseudo-headers
+    /// other than `:authority` and the configured never-indexed names.
+    pub(crate) fn encode<'a>(
+        &mut self,
+        fields: impl IntoIterator<Item = (&'a str, &'a str)>,
+        out: &mut Vec<u8>,
+    ) {
+        if let Some(min) = self.pending_min_size.take() {
+            if min < self.table.max_size {
+                write_integer(min, 5, 0x20, out);
+            }
+            write_integer(self.table.max_size, 5, 0x20, out);
+        }
+        for (name, value) in fields {
+            if name == "cookie" && self.config.split_cookies {
+                for crumb in value.split(';') {
+                    self.encode_field(name, crumb.strip_prefix(' ').unwrap_or(crumb), out);
+                }
+            } else {
+                self.encode_field(name, value, out);
+            }
+        }
+    }
+
+    fn encode_field(&mut self, name: &str, value: &str, out: &mut Vec<u8>) {
+        let huffman = self.config.huffman;
+        if self.config.never_indexed.iter().any(|n| n == name) {
+            let name_index = self.find(name, value).map_or(0, |(i, _)| i);
+            write_integer(name_index, 4, 0x10, out);
+            if name_index == 0 {
+                write_string(name.as_bytes(), huffman, out);
+            }
+            write_string(value.as_bytes(), huffman, out);
+            return;
+        }
+        let index = !name.starts_with(':') || name == ":authority";
+        match self.find(name, value) {
+            Some((i, true)) => write_integer(i, 7, 0x80, out),
+            found => {
+                let name_index = found.map_or(0, |(i, _)| i);
+                if index {
+                    write_integer(name_index, 6, 0x40, out);
+                } else {
+                    write_integer(name_index, 4, 0x00, out);
+                }
+                if name_index == 0 {
+                    write_string(name.as_bytes(), huffman, out);
+                }
+                write_string(value.as_bytes(), huffman, out);
+                if index {
+                    let octets = name.len() + value.len();
+                    self.table
+                        .insert(name.to_string(), value.to_string(), octets);
+                }
+            }
+        }
+    }
+
+    /// Best table index for a field: (index, whether the value matches too).
+    fn find(&self, name: &str, value: &str) -> Option<(usize, bool)> {
+        let dynamic = self
+            .table
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(i, e)| (i + 62, e.name.as_str(), e.value.as_str()));
+        let all = STATIC_TABLE
+            .iter()
+            .enumerate()
+            .map(|(i, &(n, v))| (i + 1, n, v))
+            .chain(dynamic);
+        let mut name_match = None;
+        for (i, n, v) in all {
+            if n == name {
+                if v == value {
+                    return Some((i, true));
+                }
+                name_match = name_match.or(Some((i, false)));
+            }
+        }
+        name_match
+    }
+}
+
+/// Octets behind a decoded (Latin-1) string.
+fn latin1_len(s: &str) -> usize {
+    s.chars().count()
+}
+
+fn write_integer(value: usize, prefix: u8, first: u8, out: &mut Vec<u8>) {
+    let max = (1usize << prefix) - 1;
+    if value < max {
+        out.push(first | value as u8);
+        return;
+    }
+    out.push(first | max as u8);
+    let mut rest = value - max;
+    while rest >= 0x80 {
+        out.push(rest as u8 | 0x80);
+        rest >>= 7;
+    }
+    out.push(rest as u8);
+}
+
+fn write_string(data: &[u8], mode: HuffmanMode, out: &mut Vec<u8>) {
+    let huffman_len = huffman::encoded_len(data);
+    let use_huffman = match mode {
+        HuffmanMode::Shorter => huffman_len < data.len(),
+        HuffmanMode::Always => true,
+        HuffmanMode::Never => false,
+    };
+    if use_huffman {
+        write_integer(huffman_len, 7, 0x80, out);
+        huffman::encode(data, out);
+    } else {
+        write_integer(data.len(), 7, 0x00, out);
+        out.extend_from_slice(data);
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.buf.get(self.pos).copied()
+    }
+
+    fn byte(&mut self) -> Result<u8, Error> {
+        let b = self
+            .peek()
+            .ok_or(Error::Compression("truncated header block"))?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn integer(&mut self, prefix: u8) -> Result<usize, Error> {
+        let max = (1usize << prefix) - 1;
+        let mut value = self.byte()? as usize & max;
+        if value < max {
+            return Ok(value);
+        }
+        // Four continuation bytes are plenty for any size or index we accept
+        for shift in [0, 7, 14, 21] {
+            let b = self.byte()?;
+            value += ((b & 0x7f) as usize) << shift;
+            if b & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(Error::Compression("integer too large"))
+    }
+
+    fn string(&mut self) -> Result<Vec<u8>, Error> {
+        let huffman = self.peek().is_some_and(|b| b & 0x80 != 0);
+        let len = self.integer(7)?;
+        let data = self
+            .buf
+            .get(self.pos..)
+            .and_then(|rest| rest.get(..len))
+            .ok_or(Error::Compression("truncated header block"))?;
+        self.pos += len;
+        if huffman {
+            huffman::decode(data)
+        } else {
+            Ok(data.to_vec())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        let s: String = s.split_whitespace().collect();
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn fields(headers: &Headers) -> Vec<(&str, &str)> {
+        headers.iter().collect()
+    }
+
+    /// RFC 7541 C.4: three requests sharing one dynamic table, Huffman-coded
+    const C4: [(&[(&str, &str)], &str); 3] = [
+        (
+            &[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+            ],
+            "8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff",
+        ),
+        (
+            &[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+                ("cache-control", "no-cache"),
+            ],
+            "8286 84be 5886 a8eb 1064 9cbf",
+        ),
+        (
+            &[
+                (":method", "GET"),
+                (":scheme", "https"),
+                (":path", "/index.html"),
+                (":authority", "www.example.com"),
+                ("custom-key", "custom-value"),
+            ],
+            "8287 85bf 4088 25a8 49e9 5ba9 7d7f 8925 a849 e95b b8e8 b4bf",
+        ),
+    ];
+
+    #[test]
+    fn rfc7541_requests() {
+        let mut encoder = Encoder::new(EncoderConfig::default());
+        let mut decoder = Decoder::new(DEFAULT_TABLE_SIZE, usize::MAX);
+        for (request, encoded) in C4 {
+            let mut out = Vec::new();
+            encoder.encode(request.iter().copied(), &mut out);
+            assert_eq!(out, hex(encoded));
+            assert_eq!(fields(&decoder.decode(&out).unwrap()), request);
+        }
+        assert_eq!(encoder.table.size, 164);
+        assert_eq!(decoder.table.size, 164);
+    }
+
+    #[test]
+    fn rfc7541_responses_with_eviction() {
+        // C.6.1 and C.6.2, with a 256-byte table
+        let mut decoder = Decoder::new(256, usize::MAX);
+        let first = decoder
+            .decode(&hex(
+                "4882 6402 5885 aec3 771a 4b61 96d0 7abe 9410 54d4 44a8 2005 9504 0b81 66e0 \
+                 82a6 2d1b ff6e 919d 29ad 1718 63c7 8f0b 97c8 e9ae 82ae 43d3",
+            ))
+            .unwrap();
+        assert_eq!(
+            fields(&first),
+            [
+                (":status", "302"),
+                ("cache-control", "private"),
+                ("date", "Mon, 21 Oct 2013 20:13:21 GMT"),
+                ("location", "https://www.example.com"),
+            ]
+        );
+        let second = decoder.decode(&hex("4883 640e ffc1 c0bf")).unwrap();
+        assert_eq!(second.get(":status"), Some("307"));
+        assert_eq!(second.get("location"), Some("https://www.example.com"));
+        assert_eq!(decoder.table.size, 222);
+        assert_eq!(decoder.table.entries.len(), 4);
+    }
+
+    #[test]
+    fn table_size_updates() {
+        let mut encoder = Encoder::new(EncoderConfig::default());
+        encoder.set_peer_max_table_size(0);
+        encoder.set_peer_max_table_size(1024);
+        let mut out = Vec::new();
+        encoder.encode([("x-a", "1")], &mut out);
+        // Smallest size first, then the final one (RFC 7541 §4.2)
+        assert_eq!(&out[..4], &[0x20, 0x3f, 0xe1, 0x07]);
+
+        let mut decoder = Decoder::new(DEFAULT_TABLE_SIZE, usize::MAX);
+        assert_eq!(fields(&decoder.decode(&out).unwrap()), [("x-a", "1")]);
+        assert_eq!(decoder.table.max_size, 1024);
+
+        let mut strict = Decoder::new(512, usize::MAX);
+        assert!(strict.decode(&out).is_err());
+        // A size update may not follow a field
+        assert!(decoder.decode(&[0x82, 0x20]).is_err());
+    }
+
+    #[test]
+    fn malformed_blocks() {
+        let mut decoder = Decoder::new(DEFAULT_TABLE_SIZE, 100);
+        for block in [
+            &[0x80][..],                           // index 0
+            &[0xbe],                               // empty dynamic table
+            &[0x41, 0x85, b'a'],                   // string longer than the block
+            &[0xff, 0xff, 0xff, 0xff, 0xff, 0x7f], // oversized integer
+        ] {
+            assert!(decoder.decode(block).is_err(), "{:02x?}", block);
+        }
+        let mut big = Vec::new();
+        Encoder::new(EncoderConfig::default())
+            .encode([("x-long", "v".repeat(80).as_str())], &mut big);
+        assert_eq!(
+            decoder.decode(&big),
+            Err(Error::HeadersTooLarge { limit: 100 })
+        );
+    }
+
+    #[test]
+    fn pseudo_headers_other_than_authority_are_not_indexed() {
+        let mut encoder = Encoder::new(EncoderConfig::default());
+        encoder.encode(
+            [(":path", "/a/long/path"), (":authority", "example.com")],
+            &mut Vec::new(),
+        );
+        let names: Vec<_> = encoder.table.entries.iter().map(|e| &e.name).collect();
+        assert_eq!(names, [":authority"]);
+    }
+
+    /// Encoded independently following Chrome's encoder rules (announced 64 KiB table,
+    /// indexing policy, Huffman only when shorter, cookie crumbs).
+    const CHROME_NAVIGATION: &[u8] =
+        include_bytes!("../../tests/fixtures/chrome-120-navigation.hpack");
+
+    #[test]
+    fn defaults_match_chrome() {
+        let request = [
+            (":method", "GET"),
+            (":authority", "www.example.com"),
+            (":scheme", "https"),
+            (":path", "/"),
+            (
+                "sec-ch-ua",
+                "\"Not_A Brand\";v=\"8\", \"Chromium\";v=\"120\", \"Google Chrome\";v=\"120\"",
+            ),
+            ("sec-ch-ua-mobile", "?0"),
+            ("sec-ch-ua-platform", "\"Windows\""),
+            ("upgrade-insecure-requests", "1"),
+            (
+                "user-agent",
+                "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 \
+                 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36",
+            ),
+            (
+                "accept",
+                "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,\
+                 image/webp,image/apng,*/*;q=0.8,application/signed-exchange;v=b3;q=0.7",
+            ),
+            ("sec-fetch-site", "none"),
+            ("sec-fetch-mode", "navigate"),
+            ("sec-fetch-user", "?1"),
+            ("sec-fetch-dest", "document"),
+            ("accept-encoding", "gzip, deflate, br"),
+            ("accept-language", "en-US,en;q=0.9"),
+            ("cookie", "session=abc123; theme=dark"),
+        ];
+        let mut encoder = Encoder::new(EncoderConfig::default());
+        encoder.set_peer_max_table_size(65_536);
+        let mut out = Vec::new();
+        encoder.encode(request, &mut out);
+        assert_eq!(out, CHROME_NAVIGATION);
+    }
+
+    #[test]
+    fn huffman_modes_and_never_indexed() {
+        let encode = |config: EncoderConfig| {
+            let mut out = Vec::new();
+            Encoder::new(config).encode([("authorization", "secret"), ("x-y", "0")], &mut out);
+            out
+        };
+        let never = encode(EncoderConfig {
+            huffman: HuffmanMode::Never,
+            never_indexed: vec!["authorization".into()],
+            ..EncoderConfig::default()
+        });
+        // Never-indexed literal with static name index 23, raw value
+        assert_eq!(&never[..9], b"\x1f\x08\x06secret");
+        // "0" is one byte raw but would take 5 bits padded to a byte either way
+        let always = encode(EncoderConfig {
+            huffman: HuffmanMode::Always,
+            ..EncoderConfig::default()
+        });
+        let shorter = encode(EncoderConfig::default());
+        assert!(always.len() >= shorter.len());
+        for block in [never, always, shorter] {
+            let headers = Decoder::new(DEFAULT_TABLE_SIZE, usize::MAX)
+                .decode(&block)
+                .unwrap();
+            assert_eq!(
+                fields(&headers),
+                [("authorization", "secret"), ("x-y", "0")]
+            );
+        }
+    }
+
+    #[test]
+    fn configured_table_size_is_announced() {
+        let mut encoder = Encoder::new(EncoderConfig {
+            table_size: Some(1024),
+            ..EncoderConfig::default()
+        });
+        let mut out = Vec::new();
+        encoder.encode([("x-a", "1")], &mut out);
+        assert_eq!(&out[..3], &[0x3f, 0xe1, 0x07]);
+        // A larger server limit does not raise it; a smaller one lowers it
+        encoder.set_peer_max_table_size(65_536);
+        encoder.set_peer_max_table_size(512);
+        let mut next = Vec::new();
+        encoder.encode([("x-a", "1")], &mut next);
+        assert_eq!(&next[..3], &[0x3f, 0xe1, 0x03]);
+    }
+}
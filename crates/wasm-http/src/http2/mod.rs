@@ -0,0 +1,20 @@
+//! Sans-IO HTTP/2 client (RFC 9113) with HPACK header compression (RFC 7541).
+//!
+//! Use it once `negotiated_alpn()` returns "h2": the connection consumes the
+//! plaintext from `take_plaintext` and produces the bytes for `write_plaintext`.
+
+mod connection;
+mod frame;
+mod hpack;
+mod huffman;
+mod options;
+
+pub use connection::{ConnectionEvent, Http2Connection, Http2Event, PREFACE};
+pub use frame::{
+    error_code_name, CANCEL, COMPRESSION_ERROR, FLOW_CONTROL_ERROR, FRAME_SIZE_ERROR,
+    INTERNAL_ERROR, NO_ERROR, PROTOCOL_ERROR, REFUSED_STREAM, SETTINGS_ENABLE_PUSH,
+    SETTINGS_HEADER_TABLE_SIZE, SETTINGS_INITIAL_WINDOW_SIZE, SETTINGS_MAX_CONCURRENT_STREAMS,
+    SETTINGS_MAX_FRAME_SIZE, SETTINGS_MAX_HEADER_LIST_SIZE, STREAM_CLOSED,
+};
+pub use hpack::HuffmanMode;
+pub use options::Http2Options;
@@ -0,0 +1,467 @@
+use std::fmt;
+
+use wasm_bindgen::prelude::*;
+
+use super::frame::{self, Dependency};
+use super::hpack::{EncoderConfig, HuffmanMode};
+use crate::error::Error;
+
+/// Optional settings for `Http2Connection::with_options`.
+/// Every setting defaults to what Chrome does.
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct Http2Options {
+    pub(crate) hpack: EncoderConfig,
+    /// SETTINGS sent with the preface, in order
+    pub(crate) settings: Vec<(u16, u32)>,
+    /// Connection WINDOW_UPDATE sent after SETTINGS; 0 sends none
+    pub(crate) connection_window_increment: u32,
+    /// PRIORITY frames sent after the WINDOW_UPDATE, building a tree of idle streams
+    pub(crate) priority_frames: Vec<(u32, Dependency)>,
+    /// Priority carried by every request's HEADERS frame
+    pub(crate) headers_priority: Option<Dependency>,
+    pub(crate) pseudo_order: PseudoOrder,
+    pub(crate) manual_flow_control: bool,
+}
+
+impl Default for Http2Options {
+    fn default() -> Self {
+        Http2Options::chrome()
+    }
+}
+
+#[wasm_bindgen]
+impl Http2Options {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Http2Options {
+        Http2Options::default()
+    }
+
+    /// Connection preface of a browser: "chrome" (the default), "chrome-old"
+    /// (Chrome 99-105, which still sent MAX_CONCURRENT_STREAMS) or "firefox"
+    /// (which builds a priority tree with PRIORITY frames before its first request).
+    /// The setters below override single parts of a profile.
+    pub fn profile(name: &str) -> Result<Http2Options, Error> {
+        match name {
+            "chrome" => Ok(Http2Options::chrome()),
+            "chrome-old" => Ok(Http2Options {
+                settings: vec![
+                    (frame::SETTINGS_HEADER_TABLE_SIZE, 65_536),
+                    (frame::SETTINGS_MAX_CONCURRENT_STREAMS, 1000),
+                    (frame::SETTINGS_INITIAL_WINDOW_SIZE, 6_291_456),
+                    (frame::SETTINGS_MAX_HEADER_LIST_SIZE, 262_144),
+                ],
+                ..Http2Options::chrome()
+            }),
+            "firefox" => {
+                let dependency = |depends_on, weight| Dependency {
+                    exclusive: false,
+                    depends_on,
+                    weight,
+                };
+                Ok(Http2Options {
+                    hpack: EncoderConfig::default(),
+                    settings: vec![
+                        (frame::SETTINGS_HEADER_TABLE_SIZE, 65_536),
+                        (frame::SETTINGS_INITIAL_WINDOW_SIZE, 131_072),
+                        (frame::SETTINGS_MAX_FRAME_SIZE, 16_384),
+                    ],
+                    connection_window_increment: 12_517_377,
+                    priority_frames: vec![
+                        (3, dependency(0, 201)),
+                        (5, dependency(0, 101)),
+                        (7, dependency(0, 1)),
+                        (9, dependency(7, 1)),
+                        (11, dependency(3, 1)),
+                        (13, dependency(0, 241)),
+                    ],
+                    headers_priority: Some(dependency(13, 42)),
+                    pseudo_order: PseudoOrder::parse("m,p,a,s")?,
+                    manual_flow_control: false,
+                })
+            }
+            _ => Err(Error::InvalidOption {
+                option: "HTTP/2 profile",
+                value: name.to_string(),
+            }),
+        }
+    }
+
+    /// Replace the SETTINGS list; `ids[i]` is sent with `values[i]`, in this order.
+    pub fn set_settings(&mut self, ids: Vec<u16>, values: Vec<u32>) -> Result<(), Error> {
+        if ids.len() != values.len() {
+            return Err(Error::InvalidOption {
+                option: "settings",
+                value: format!("{} ids, {} values", ids.len(), values.len()),
+            });
+        }
+        let settings: Vec<(u16, u32)> = ids.into_iter().zip(values).collect();
+        for &(id, value) in &settings {
+            check_setting(id, value)?;
+        }
+        self.settings = settings;
+        Ok(())
+    }
+
+    /// Set one SETTINGS value, keeping its position if it is already sent.
+    pub fn set_setting(&mut self, id: u16, value: u32) -> Result<(), Error> {
+        check_setting(id, value)?;
+        match self.settings.iter_mut().find(|(i, _)| *i == id) {
+            Some(setting) => setting.1 = value,
+            None => self.settings.push((id, value)),
+        }
+        Ok(())
+    }
+
+    /// Stop sending a setting; the peer then assumes its default value.
+    pub fn remove_setting(&mut self, id: u16) {
+        self.settings.retain(|&(i, _)| i != id);
+    }
+
+    /// Receive window of each stream (sent as SETTINGS_INITIAL_WINDOW_SIZE).
+    pub fn set_initial_stream_window(&mut self, size: u32) -> Result<(), Error> {
+        self.set_setting(frame::SETTINGS_INITIAL_WINDOW_SIZE, size)
+    }
+
+    /// Receive window of the whole connection, at least 65535 (the protocol default).
+    /// Sets the increment of the WINDOW_UPDATE sent with the preface.
+    pub fn set_connection_window(&mut self, size: u32) -> Result<(), Error> {
+        let increment = size
+            .checked_sub(frame::DEFAULT_WINDOW)
+            .ok_or(Error::InvalidOption {
+                option: "connection window",
+                value: size.to_string(),
+            })?;
+        self.set_connection_window_increment(increment)
+    }
+
+    /// Stop replenishing receive windows automatically: the server may then send at
+    /// most one window of data ahead of what the application has passed to
+    /// `Http2Connection::release_capacity`. Off by default, where windows are topped
+    /// up as soon as half of them is used.
+    pub fn set_manual_flow_control(&mut self, enabled: bool) {
+        self.manual_flow_control = enabled;
+    }
+
+    /// Increment of the connection WINDOW_UPDATE sent with the preface (0 for none).
+    pub fn set_connection_window_increment(&mut self, increment: u32) -> Result<(), Error> {
+        if frame::DEFAULT_WINDOW as i64 + increment as i64 > frame::MAX_WINDOW {
+            return Err(Error::InvalidOption {
+                option: "connection window increment",
+                value: increment.to_string(),
+            });
+        }
+        self.connection_window_increment = increment;
+        Ok(())
+    }
+
+    /// PRIORITY frames to send with the preface, in the fingerprint notation
+    /// `stream:exclusive:depends_on:weight` joined by commas, e.g.
+    /// "3:0:0:201,5:0:0:101". An empty string or "0" sends none. Requests then start
+    /// at the first odd stream id above the ones used here.
+    pub fn set_priority_frames(&mut self, spec: &str) -> Result<(), Error> {
+        let invalid = || Error::InvalidOption {
+            option: "priority frames",
+            value: spec.to_string(),
+        };
+        if spec.is_empty() || spec == "0" {
+            self.priority_frames.clear();
+            return Ok(());
+        }
+        let mut frames = Vec::new();
+        for entry in spec.split(',') {
+            let parts: Vec<u32> = entry
+                .split(':')
+                .map(|p| p.trim().parse())
+                .collect::<Result<_, _>>()
+                .map_err(|_| invalid())?;
+            let &[stream_id, exclusive, depends_on, weight] = parts.as_slice() else {
+                return Err(invalid());
+            };
+            let dependency = dependency(exclusive == 1, depends_on, weight).ok_or_else(invalid)?;
+            let valid_id = stream_id != 0 && stream_id <= frame::MAX_WINDOW as u32;
+            if !valid_id || exclusive > 1 || depends_on == stream_id {
+                return Err(invalid());
+            }
+            frames.push((stream_id, dependency));
+        }
+        self.priority_frames = frames;
+        Ok(())
+    }
+
+    /// Send every request's HEADERS frame with the PRIORITY flag and this dependency
+    /// (`weight` 1-256). Chrome uses exclusive, stream 0, weight 256.
+    pub fn set_headers_priority(
+        &mut self,
+        exclusive: bool,
+        depends_on: u32,
+        weight: u16,
+    ) -> Result<(), Error> {
+        self.headers_priority = Some(dependency(exclusive, depends_on, weight as u32).ok_or(
+            Error::InvalidOption {
+                option: "headers priority",
+                value: format!("{}:{}:{}", exclusive as u8, depends_on, weight),
+            },
+        )?);
+        Ok(())
+    }
+
+    /// Order of the request pseudo-headers in HEADERS frames, as the letters `m`
+    /// (`:method`), `a` (`:authority`), `s` (`:scheme`) and `p` (`:path`) joined by
+    /// commas: "m,a,s,p" for Chrome, "m,p,a,s" for Firefox. Regular fields keep the
+    /// caller's order after them. `Http2Connection::open_stream_ordered` overrides
+    /// this for a single request.
+    pub fn set_pseudo_header_order(&mut self, order: &str) -> Result<(), Error> {
+        self.pseudo_order = PseudoOrder::parse(order)?;
+        Ok(())
+    }
+
+    /// Send HEADERS frames without priority information.
+    pub fn clear_headers_priority(&mut self) {
+        self.headers_priority = None;
+    }
+
+    /// The Akamai HTTP/2 fingerprint these options produce:
+    /// `settings|window_update|priority_frames|pseudo_header_order`, e.g.
+    /// "1:65536;2:0;4:6291456;6:262144|15663105|0|m,a,s,p" for Chrome.
+    pub fn akamai_fingerprint(&self) -> String {
+        let settings: Vec<String> = self
+            .settings
+            .iter()
+            .map(|(id, value)| format!("{}:{}", id, value))
+            .collect();
+        let priorities: Vec<String> = self
+            .priority_frames
+            .iter()
+            .map(|(id, d)| format!("{}:{}:{}:{}", id, d.exclusive as u8, d.depends_on, d.weight))
+            .collect();
+        format!(
+            "{}|{}|{}|{}",
+            settings.join(";"),
+            self.connection_window_increment,
+            if priorities.is_empty() {
+                "0".to_string()
+            } else {
+                priorities.join(",")
+            },
+            self.pseudo_order
+        )
+    }
+
+    /// HPACK dynamic table size our encoder uses, capped by the server's
+    /// SETTINGS_HEADER_TABLE_SIZE. By default the encoder takes the server's whole
+    /// limit; either way a size other than 4096 is announced with a table size update.
+    pub fn set_encoder_table_size(&mut self, size: u32) {
+        self.hpack.table_size = Some(size as usize);
+    }
+
+    /// Huffman coding of header literals: "shorter" (default, only when it saves
+    /// bytes), "always" or "never".
+    pub fn set_huffman(&mut self, mode: &str) -> Result<(), Error> {
+        self.hpack.huffman = match mode {
+            "shorter" => HuffmanMode::Shorter,
+            "always" => HuffmanMode::Always,
+            "never" => HuffmanMode::Never,
+            _ => {
+                return Err(Error::InvalidOption {
+                    option: "Huffman mode",
+                    value: mode.to_string(),
+                })
+            }
+        };
+        Ok(())
+    }
+
+    /// Header names (e.g. "cookie", "authorization") always sent as never-indexed
+    /// literals, so intermediaries never add them to a table. Chrome uses none.
+    pub fn set_never_indexed(&mut self, names: Vec<String>) {
+        self.hpack.never_indexed = names.iter().map(|n| n.to_ascii_lowercase()).collect();
+    }
+
+    /// Send each pair of a `cookie` header as a separate field, as Chrome does
+    /// (on by default). Servers join them back together.
+    pub fn set_split_cookies(&mut self, enabled: bool) {
+        self.hpack.split_cookies = enabled;
+    }
+}
+
+impl Http2Options {
+    fn chrome() -> Http2Options {
+        Http2Options {
+            hpack: EncoderConfig::default(),
+            settings: vec![
+                (frame::SETTINGS_HEADER_TABLE_SIZE, 65_536),
+                (frame::SETTINGS_ENABLE_PUSH, 0),
+                (frame::SETTINGS_INITIAL_WINDOW_SIZE, 6_291_456),
+                (frame::SETTINGS_MAX_HEADER_LIST_SIZE, 262_144),
+            ],
+            // Raises the connection window to 15 MiB
+            connection_window_increment: 15_663_105,
+            priority_frames: Vec::new(),
+            headers_priority: Some(Dependency {
+                exclusive: true,
+                depends_on: 0,
+                weight: 256,
+            }),
+            pseudo_order: PseudoOrder::CHROME,
+            manual_flow_control: false,
+        }
+    }
+
+    /// Value of a setting we send, if any (the last one counts).
+    pub(crate) fn setting(&self, id: u16) -> Option<u32> {
+        self.settings
+            .iter()
+            .rev()
+            .find(|&&(i, _)| i == id)
+            .map(|&(_, v)| v)
+    }
+}
+
+/// Emission order of the four request pseudo-headers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct PseudoOrder(pub(crate) [&'static str; 4]);
+
+impl PseudoOrder {
+    const CHROME: PseudoOrder = PseudoOrder([":method", ":authority", ":scheme", ":path"]);
+
+    /// Parse the fingerprint notation, e.g. "m,p,a,s".
+    pub(crate) fn parse(order: &str) -> Result<PseudoOrder, Error> {
+        let invalid = || Error::InvalidOption {
+            option: "pseudo-header order",
+            value: order.to_string(),
+        };
+        let mut names = Vec::with_capacity(4);
+        for letter in order.split(',') {
+            let name = match letter.trim() {
+                "m" => ":method",
+                "a" => ":authority",
+                "s" => ":scheme",
+                "p" => ":path",
+                _ => return Err(invalid()),
+            };
+            if names.contains(&name) {
+                return Err(invalid());
+            }
+            names.push(name);
+        }
+        names.try_into().map(PseudoOrder).map_err(|_| invalid())
+    }
+
+    /// Sort position of a pseudo-header; others (`:protocol`) go last.
+    pub(crate) fn position(&self, name: &str) -> usize {
+        self.0
+            .iter()
+            .position(|&n| n == name)
+            .unwrap_or(self.0.len())
+    }
+}
+
+impl fmt::Display for PseudoOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let letters: Vec<&str> = self.0.iter().map(|n| &n[1..2]).collect();
+        f.write_str(&letters.join(","))
+    }
+}
+
+fn dependency(exclusive: bool, depends_on: u32, weight: u32) -> Option<Dependency> {
+    let valid = depends_on <= frame::MAX_WINDOW as u32 && (1..=256).contains(&weight);
+    valid.then_some(Dependency {
+        exclusive,
+        depends_on,
+        weight: weight as u16,
+    })
+}
+
+/// Reject setting values a peer would treat as a connection error (RFC 9113 §6.5.2).
+fn check_setting(id: u16, value: u32) -> Result<(), Error> {
+    let valid = match id {
+        frame::SETTINGS_ENABLE_PUSH => value <= 1,
+        frame::SETTINGS_INITIAL_WINDOW_SIZE => value as i64 <= frame::MAX_WINDOW,
+        frame::SETTINGS_MAX_FRAME_SIZE => {
+            (frame::MIN_MAX_FRAME_SIZE..=frame::MAX_MAX_FRAME_SIZE).contains(&value)
+        }
+        _ => true,
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::InvalidOption {
+            option: "setting",
+            value: format!("{}:{}", id, value),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profile_fingerprints() {
+        assert_eq!(
+            Http2Options::new().akamai_fingerprint(),
+            "1:65536;2:0;4:6291456;6:262144|15663105|0|m,a,s,p"
+        );
+        assert_eq!(
+            Http2Options::profile("chrome-old")
+                .unwrap()
+                .akamai_fingerprint(),
+            "1:65536;3:1000;4:6291456;6:262144|15663105|0|m,a,s,p"
+        );
+        assert_eq!(
+            Http2Options::profile("firefox").unwrap().akamai_fingerprint(),
+            "1:65536;4:131072;5:16384|12517377|3:0:0:201,5:0:0:101,7:0:0:1,9:0:7:1,11:0:3:1,13:0:0:241|m,p,a,s"
+        );
+        assert!(matches!(
+            Http2Options::profile("netscape"),
+            Err(Error::InvalidOption { .. })
+        ));
+    }
+
+    #[test]
+    fn overrides() {
+        let mut options = Http2Options::new();
+        options
+            .set_setting(frame::SETTINGS_INITIAL_WINDOW_SIZE, 65_535)
+            .unwrap();
+        options
+            .set_setting(frame::SETTINGS_MAX_CONCURRENT_STREAMS, 100)
+            .unwrap();
+        options.remove_setting(frame::SETTINGS_ENABLE_PUSH);
+        options.set_connection_window_increment(0).unwrap();
+        options.set_priority_frames("3:1:0:256, 5:0:3:1").unwrap();
+        assert_eq!(
+            options.akamai_fingerprint(),
+            "1:65536;4:65535;6:262144;3:100|0|3:1:0:256,5:0:3:1|m,a,s,p"
+        );
+
+        options.set_settings(vec![2, 4], vec![0, 1 << 20]).unwrap();
+        options.set_priority_frames("0").unwrap();
+        options.set_pseudo_header_order("s, p, a, m").unwrap();
+        assert_eq!(options.akamai_fingerprint(), "2:0;4:1048576|0|0|s,p,a,m");
+
+        for spec in [
+            "3:0:0",
+            "3:0:0:0",
+            "3:0:0:257",
+            "0:0:0:1",
+            "3:2:0:1",
+            "3:0:3:1",
+            "x",
+        ] {
+            assert!(options.set_priority_frames(spec).is_err(), "{}", spec);
+        }
+        assert!(options.set_settings(vec![2], vec![]).is_err());
+        assert!(options.set_setting(frame::SETTINGS_ENABLE_PUSH, 2).is_err());
+        assert!(options
+            .set_setting(frame::SETTINGS_MAX_FRAME_SIZE, 100)
+            .is_err());
+        assert!(options.set_connection_window_increment(u32::MAX).is_err());
+        assert!(options.set_headers_priority(true, 0, 0).is_err());
+        for order in ["m,a,s", "m,a,s,p,m", "m,a,s,s", "m,a,s,x"] {
+            assert!(options.set_pseudo_header_order(order).is_err(), "{}", order);
+        }
+        assert_eq!(options.akamai_fingerprint(), "2:0;4:1048576|0|0|s,p,a,m");
+    }
+}
@@ -0,0 +1,284 @@
+//! Response body integrity: subresource-integrity style expectations, and the
+//! Content-Digest / Repr-Digest fields (RFC 9530), hashed as the body streams.
+
+use sha2::{Digest, Sha256, Sha384, Sha512};
+
+use crate::base64;
+use crate::error::Error;
+use crate::headers::Headers;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Algorithm {
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl Algorithm {
+    /// Name in integrity metadata ("sha256-...").
+    fn sri_name(self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => "sha256",
+            Algorithm::Sha384 => "sha384",
+            Algorithm::Sha512 => "sha512",
+        }
+    }
+
+    /// Key in a digest field ("sha-256=:...:"); RFC 9530 registers no SHA-384.
+    fn field_name(self) -> Option<&'static str> {
+        match self {
+            Algorithm::Sha256 => Some("sha-256"),
+            Algorithm::Sha384 => None,
+            Algorithm::Sha512 => Some("sha-512"),
+        }
+    }
+
+    fn hasher(self) -> Hasher {
+        match self {
+            Algorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            Algorithm::Sha384 => Hasher::Sha384(Sha384::new()),
+            Algorithm::Sha512 => Hasher::Sha512(Sha512::new()),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Hasher {
+    Sha256(Sha256),
+    Sha384(Sha384),
+    Sha512(Sha512),
+}
+
+impl Hasher {
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha256(h) => h.update(data),
+            Hasher::Sha384(h) => h.update(data),
+            Hasher::Sha512(h) => h.update(data),
+        }
+    }
+
+    fn finish(self) -> Vec<u8> {
+        match self {
+            Hasher::Sha256(h) => h.finalize().to_vec(),
+            Hasher::Sha384(h) => h.finalize().to_vec(),
+            Hasher::Sha512(h) => h.finalize().to_vec(),
+        }
+    }
+}
+
+/// Content-Digest value for `body`: its SHA-256 as `sha-256=:<base64>:`.
+pub(crate) fn content_digest(body: &[u8]) -> String {
+    format!("sha-256=:{}:", base64::encode(&Sha256::digest(body)))
+}
+
+/// Digests the body is expected to match, from integrity metadata. As in SRI,
+/// only the strongest algorithm listed counts, and any of its digests may match.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Expected {
+    algorithm: Algorithm,
+    digests: Vec<Vec<u8>>,
+}
+
+impl Expected {
+    /// Parse space-separated `sha256-<base64>` (or sha384 / sha512) tokens;
+    /// `?options` after a digest are ignored.
+    pub(crate) fn parse(metadata: &str) -> Result<Expected, Error> {
+        let mut hashes = Vec::new();
+        for token in metadata.split_ascii_whitespace() {
+            let (name, value) = token.split_once('-').ok_or(Error::InvalidIntegrity)?;
+            let algorithm = match name.to_ascii_lowercase().as_str() {
+                "sha256" => Algorithm::Sha256,
+                "sha384" => Algorithm::Sha384,
+                "sha512" => Algorithm::Sha512,
+                _ => continue,
+            };
+            let value = value.split('?').next().unwrap_or_default();
+            let digest = base64::decode(value).ok_or(Error::InvalidIntegrity)?;
+            hashes.push((algorithm, digest));
+        }
+        let algorithm = hashes
+            .iter()
+            .map(|(a, _)| *a)
+            .max()
+            .ok_or(Error::InvalidIntegrity)?;
+        let digests = hashes
+            .into_iter()
+            .filter(|(a, _)| *a == algorithm)
+            .map(|(_, d)| d)
+            .collect();
+        Ok(Expected { algorithm, digests })
+    }
+}
+
+/// A digest field of the response, checked against the received content.
+#[derive(Clone, Debug)]
+struct FieldCheck {
+    field: &'static str,
+    algorithm: Algorithm,
+    expected: Vec<u8>,
+    hasher: Hasher,
+}
+
+/// Hashes of one response body in progress.
+#[derive(Clone, Debug)]
+pub(crate) struct BodyIntegrity {
+    expected: Option<Expected>,
+    /// Over the body as delivered (after content decoding, if any)
+    decoded: Option<(Algorithm, Hasher)>,
+    /// Over the body as received (content-coded, transfer coding removed)
+    fields: Vec<FieldCheck>,
+}
+
+impl BodyIntegrity {
+    /// Hashing for a response with this status and these headers, or None if
+    /// nothing is expected or to be computed. Repr-Digest covers the whole
+    /// representation, so it is only checked on a 200.
+    pub(crate) fn new(
+        expected: Option<&Expected>,
+        compute: bool,
+        verify_fields: bool,
+        status: u16,
+        headers: &Headers,
+    ) -> Option<BodyIntegrity> {
+        let mut fields = Vec::new();
+        if verify_fields {
+            for field in ["Content-Digest", "Repr-Digest"] {
+                if field == "Repr-Digest" && status != 200 {
+                    continue;
+                }
+                let value = headers.get_all(field).collect::<Vec<_>>().join(",");
+                if let Some((algorithm, expected)) = strongest_field_digest(&value) {
+                    fields.push(FieldCheck {
+                        field,
+                        algorithm,
+                        expected,
+                        hasher: algorithm.hasher(),
+                    });
+                }
+            }
+        }
+        let decoded = match expected {
+            Some(e) => Some(e.algorithm),
+            None => compute.then_some(Algorithm::Sha256),
+        };
+        if decoded.is_none() && fields.is_empty() {
+            return None;
+        }
+        Some(BodyIntegrity {
+            expected: expected.cloned(),
+            decoded: decoded.map(|a| (a, a.hasher())),
+            fields,
+        })
+    }
+
+    pub(crate) fn update_encoded(&mut self, data: &[u8]) {
+        for check in &mut self.fields {
+            check.hasher.update(data);
+        }
+    }
+
+    pub(crate) fn update_decoded(&mut self, data: &[u8]) {
+        if let Some((_, hasher)) = &mut self.decoded {
+            hasher.update(data);
+        }
+    }
+
+    /// The body's digest as integrity metadata (if one was computed), and the
+    /// verdict: `Integrity` for the first check that failed.
+    pub(crate) fn finish(self) -> (Option<String>, Result<(), Error>) {
+        let mut result = Ok(());
+        let digest = self.decoded.map(|(algorithm, hasher)| {
+            let digest = hasher.finish();
+            let sri = |d: &[u8]| format!("{}-{}", algorithm.sri_name(), base64::encode(d));
+            if let Some(expected) = &self.expected {
+                if !expected.digests.contains(&digest) {
+                    let listed: Vec<String> = expected.digests.iter().map(|d| sri(d)).collect();
+                    result = Err(Error::Integrity {
+                        check: "integrity",
+                        expected: listed.join(" "),
+                        computed: sri(&digest),
+                    });
+                }
+            }
+            sri(&digest)
+        });
+        for check in self.fields {
+            let computed = check.hasher.finish();
+            if result.is_ok() && computed != check.expected {
+                let field = |d: &[u8]| {
+                    let name = check.algorithm.field_name().unwrap_or_default();
+                    format!("{}=:{}:", name, base64::encode(d))
+                };
+                result = Err(Error::Integrity {
+                    check: check.field,
+                    expected: field(&check.expected),
+                    computed: field(&computed),
+                });
+            }
+        }
+        (digest, result)
+    }
+}
+
+/// Strongest supported digest in a Content-Digest / Repr-Digest dictionary
+/// (`sha-256=:<base64>:, sha-512=:<base64>:`). Members that do not parse are
+/// skipped, as RFC 9530 lets a recipient ignore them.
+fn strongest_field_digest(value: &str) -> Option<(Algorithm, Vec<u8>)> {
+    value
+        .split(',')
+        .filter_map(|member| {
+            let (key, value) = member.split_once('=')?;
+            let algorithm = match key.trim() {
+                "sha-256" => Algorithm::Sha256,
+                "sha-512" => Algorithm::Sha512,
+                _ => return None,
+            };
+            let value = value.split(';').next()?.trim();
+            let encoded = value.strip_prefix(':')?.strip_suffix(':')?;
+            Some((algorithm, base64::decode(encoded)?))
+        })
+        .max_by_key(|(algorithm, _)| *algorithm)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ABC_SHA256: &str = "sha256-ungWv48Bz+pBQUDeXa4iI7ADYaOWF3qctBD/YfIAFa0=";
+
+    #[test]
+    fn metadata_uses_the_strongest_algorithm() {
+        let expected =
+            Expected::parse(&format!("sha1-abc {}?ct=x sha256-AAAA", ABC_SHA256)).unwrap();
+        assert_eq!(
+            (expected.algorithm, expected.digests.len()),
+            (Algorithm::Sha256, 2)
+        );
+        let expected = Expected::parse(&format!("{} sha512-AAAA", ABC_SHA256)).unwrap();
+        assert_eq!(expected.algorithm, Algorithm::Sha512);
+        for metadata in ["", "md5-abc", "sha256-!!"] {
+            assert_eq!(Expected::parse(metadata), Err(Error::InvalidIntegrity));
+        }
+        assert_eq!(
+            strongest_field_digest("unixsum=:AA==:, sha-256=:AAAA:;x=1, sha-512=bad"),
+            Some((Algorithm::Sha256, vec![0; 3]))
+        );
+    }
+
+    #[test]
+    fn computes_and_checks_across_chunks() {
+        let expected = Expected::parse(ABC_SHA256).unwrap();
+        let mut headers = Headers::new();
+        headers.push("Repr-Digest", "sha-256=:AAAA:");
+        let mut integrity =
+            BodyIntegrity::new(Some(&expected), false, true, 206, &headers).unwrap();
+        for chunk in [&b"a"[..], b"bc"] {
+            integrity.update_encoded(chunk);
+            integrity.update_decoded(chunk);
+        }
+        // Repr-Digest does not describe a partial response
+        assert_eq!(integrity.finish(), (Some(ABC_SHA256.to_string()), Ok(())));
+        assert!(BodyIntegrity::new(None, false, true, 206, &headers).is_none());
+    }
+}
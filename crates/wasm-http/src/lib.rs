@@ -0,0 +1,54 @@
+//! Sans-IO HTTP layer for stealth-fetch.
+//!
+//! Nothing in this crate touches a socket: builders produce the bytes to hand to
+//! `TlsConnection::write_plaintext`, and parsers consume what `take_plaintext` returns.
+//! The JS side stays in charge of all IO.
+
+mod alt_svc;
+mod auth;
+pub mod base64;
+mod cache;
+mod charset;
+pub mod coding;
+mod cookie;
+pub mod dns;
+mod error;
+mod har;
+mod headers;
+mod hsts;
+pub mod http1;
+pub mod http2;
+mod integrity;
+pub mod json;
+pub mod md5;
+mod multipart;
+mod profile;
+mod psl;
+mod range;
+mod redirect;
+mod signature;
+mod socks;
+mod sse;
+mod timing;
+mod url;
+pub mod websocket;
+
+pub use alt_svc::{AltService, AltSvcStore};
+pub use auth::{AuthChallenge, AuthStore};
+pub use cache::{CacheControl, CachedResponse};
+pub use charset::{CharsetDecoder, META_PRESCAN_LENGTH};
+pub use cookie::CookieJar;
+pub use error::Error;
+pub use har::HarRecorder;
+pub use headers::Headers;
+pub use hsts::HstsStore;
+pub use multipart::MultipartBuilder;
+pub use profile::{available_profiles, profile_browser, HeaderProfile};
+pub use psl::PublicSuffixList;
+pub use range::ResumableDownload;
+pub use redirect::{RedirectController, RedirectPolicy, RedirectStep};
+pub use signature::SignatureParams;
+pub use socks::{Socks4Client, Socks5Client, SocksClient};
+pub use sse::{SseEvent, SseParser, DEFAULT_MAX_EVENT_SIZE};
+pub use timing::Timings;
+pub use url::{domain_to_ascii, parse_url, resolve_url, HttpUrl};
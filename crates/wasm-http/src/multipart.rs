@@ -0,0 +1,438 @@
+//! `multipart/form-data` request bodies (RFC 7578), encoded the way browsers do and
+//! pulled in chunks so large files never have to sit in wasm memory whole.
+
+use std::collections::VecDeque;
+use std::mem;
+
+use wasm_bindgen::prelude::*;
+
+use crate::error::Error;
+use crate::http1::{write_chunk, RequestBuilder, LAST_CHUNK};
+
+/// Characters of a generated boundary after Chrome's `----WebKitFormBoundary` prefix.
+const BOUNDARY_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+
+#[derive(Debug)]
+enum Body {
+    Bytes(Vec<u8>),
+    /// Supplied through `write_data`; the size is checked against the data if known
+    Stream(Option<u64>),
+}
+
+#[derive(Debug)]
+struct Part {
+    /// Delimiter line and part headers, up to and including the blank line
+    head: Vec<u8>,
+    body: Body,
+}
+
+/// Builds a `multipart/form-data` body from text fields and files.
+///
+/// Add every part, call `apply_headers` on the request, write the request head, and
+/// then write what `next_chunk` returns until `is_done`. Parts added with
+/// `add_file_stream` take their data from `write_data` / `end_data` whenever
+/// `needs_data` says so. The body is sent with a Content-Length if every part's size
+/// is known, and chunked otherwise (`next_chunk` then also adds the chunk framing).
+#[wasm_bindgen]
+#[derive(Debug)]
+pub struct MultipartBuilder {
+    boundary: String,
+    parts: VecDeque<Part>,
+    /// The part list is fixed: headers were applied or output has started
+    sealed: bool,
+    chunked: bool,
+    /// Encoded bytes not yet returned by `next_chunk`
+    pending: Vec<u8>,
+    /// Declared size and bytes written so far of the streamed part in progress
+    streaming: Option<(Option<u64>, u64)>,
+    /// The closing delimiter is in `pending`
+    closed: bool,
+    done: bool,
+}
+
+#[wasm_bindgen]
+impl MultipartBuilder {
+    /// Start a body with a random boundary in Chrome's format.
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Result<MultipartBuilder, Error> {
+        let mut random = [0u8; 16];
+        getrandom::fill(&mut random).map_err(|_| Error::Multipart("no random source"))?;
+        let suffix: String = random
+            .iter()
+            .map(|b| BOUNDARY_ALPHABET[*b as usize % BOUNDARY_ALPHABET.len()] as char)
+            .collect();
+        Ok(MultipartBuilder {
+            boundary: format!("----WebKitFormBoundary{}", suffix),
+            parts: VecDeque::new(),
+            sealed: false,
+            chunked: false,
+            pending: Vec::new(),
+            streaming: None,
+            closed: false,
+            done: false,
+        })
+    }
+
+    /// Use `boundary` instead of the random one, e.g. to match another browser.
+    /// Letters, digits and `'+_-.` only, at most 70 characters. Only possible before
+    /// any part is added.
+    pub fn set_boundary(&mut self, boundary: &str) -> Result<(), Error> {
+        let valid = (1..=70).contains(&boundary.len())
+            && boundary
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"'+_-.".contains(&b));
+        if !valid {
+            return Err(Error::InvalidOption {
+                option: "multipart boundary",
+                value: boundary.to_string(),
+            });
+        }
+        if !self.parts.is_empty() || self.sealed {
+            return Err(Error::Multipart("boundary set after parts were added"));
+        }
+        self.boundary = boundary.to_string();
+        Ok(())
+    }
+
+    pub fn boundary(&self) -> String {
+        self.boundary.clone()
+    }
+
+    /// Value of the request's Content-Type header.
+    pub fn content_type(&self) -> String {
+        format!("multipart/form-data; boundary={}", self.boundary)
+    }
+
+    /// Append a text field.
+    pub fn add_text(&mut self, name: &str, value: &str) -> Result<(), Error> {
+        let head = self.part_head(name, None, "")?;
+        self.push(head, Body::Bytes(value.as_bytes().to_vec()))
+    }
+
+    /// Append a file held in memory. An empty `content_type` means
+    /// `application/octet-stream`.
+    pub fn add_file(
+        &mut self,
+        name: &str,
+        filename: &str,
+        content_type: &str,
+        data: Vec<u8>,
+    ) -> Result<(), Error> {
+        let head = self.part_head(name, Some(filename), content_type)?;
+        self.push(head, Body::Bytes(data))
+    }
+
+    /// Append a file whose data is supplied later through `write_data`. Without a
+    /// `size` the body as a whole is sent chunked.
+    pub fn add_file_stream(
+        &mut self,
+        name: &str,
+        filename: &str,
+        content_type: &str,
+        size: Option<u64>,
+    ) -> Result<(), Error> {
+        let head = self.part_head(name, Some(filename), content_type)?;
+        self.push(head, Body::Stream(size))
+    }
+
+    /// Total body length, if every part's size is known.
+    pub fn content_length(&self) -> Option<u64> {
+        let mut total = self.closing_delimiter().len() as u64;
+        for part in &self.parts {
+            let body = match part.body {
+                Body::Bytes(ref data) => data.len() as u64,
+                Body::Stream(size) => size?,
+            };
+            total += part.head.len() as u64 + body + 2;
+        }
+        Some(total)
+    }
+
+    /// Add Content-Type and either Content-Length or `Transfer-Encoding: chunked` to
+    /// `request`, whose body then comes from `next_chunk` instead of `set_body`.
+    /// No parts can be added afterwards.
+    pub fn apply_headers(&mut self, request: &mut RequestBuilder) -> Result<(), Error> {
+        self.seal()?;
+        request.header("Content-Type", &self.content_type())?;
+        match self.content_length() {
+            Some(len) => request.header("Content-Length", &len.to_string())?,
+            None => request.header("Transfer-Encoding", "chunked")?,
+        }
+        request.set_streamed_body();
+        Ok(())
+    }
+
+    /// Next piece of the body, with at most `max` bytes of body data (chunk framing
+    /// comes on top). Empty once the body is complete, or while `needs_data`.
+    pub fn next_chunk(&mut self, max: usize) -> Result<Vec<u8>, Error> {
+        if !self.sealed {
+            self.seal()?;
+        }
+        while self.pending.len() < max && self.streaming.is_none() {
+            match self.parts.pop_front() {
+                Some(part) => {
+                    self.pending.extend_from_slice(&part.head);
+                    match part.body {
+                        Body::Bytes(data) => {
+                            self.pending.extend_from_slice(&data);
+                            self.pending.extend_from_slice(b"\r\n");
+                        }
+                        Body::Stream(size) => self.streaming = Some((size, 0)),
+                    }
+                }
+                None if !self.closed => {
+                    let closing = self.closing_delimiter();
+                    self.pending.extend_from_slice(closing.as_bytes());
+                    self.closed = true;
+                }
+                None => break,
+            }
+        }
+
+        let take = self.pending.len().min(max);
+        let data = if take == self.pending.len() {
+            mem::take(&mut self.pending)
+        } else {
+            let rest = self.pending.split_off(take);
+            mem::replace(&mut self.pending, rest)
+        };
+        let finished = self.closed && self.pending.is_empty();
+        let mut out = if !self.chunked {
+            data
+        } else {
+            let mut out = Vec::new();
+            if !data.is_empty() {
+                write_chunk(&mut out, &data);
+            }
+            out
+        };
+        if finished && max > 0 && !self.done {
+            if self.chunked {
+                out.extend_from_slice(LAST_CHUNK);
+            }
+            self.done = true;
+        }
+        Ok(out)
+    }
+
+    /// Whether the streamed part in progress is waiting for `write_data` or
+    /// `end_data`.
+    pub fn needs_data(&self) -> bool {
+        self.streaming.is_some() && self.pending.is_empty()
+    }
+
+    /// Supply data for the streamed part in progress.
+    pub fn write_data(&mut self, data: &[u8]) -> Result<(), Error> {
+        let Some((size, written)) = &mut self.streaming else {
+            return Err(Error::Multipart("no streamed part is waiting for data"));
+        };
+        let total = *written + data.len() as u64;
+        if let Some(size) = *size {
+            if total > size {
+                return Err(Error::MultipartSizeMismatch {
+                    declared: size,
+                    actual: total,
+                });
+            }
+        }
+        *written = total;
+        self.pending.extend_from_slice(data);
+        Ok(())
+    }
+
+    /// End the streamed part in progress; it must have the declared size.
+    pub fn end_data(&mut self) -> Result<(), Error> {
+        let Some((size, written)) = self.streaming else {
+            return Err(Error::Multipart("no streamed part is waiting for data"));
+        };
+        if let Some(size) = size {
+            if written != size {
+                return Err(Error::MultipartSizeMismatch {
+                    declared: size,
+                    actual: written,
+                });
+            }
+        }
+        self.streaming = None;
+        self.pending.extend_from_slice(b"\r\n");
+        Ok(())
+    }
+
+    /// Whether the whole body, terminating chunk included, has been returned.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+}
+
+impl MultipartBuilder {
+    fn push(&mut self, head: Vec<u8>, body: Body) -> Result<(), Error> {
+        if self.sealed {
+            return Err(Error::Multipart("part added after the body was started"));
+        }
+        self.parts.push_back(Part { head, body });
+        Ok(())
+    }
+
+    fn seal(&mut self) -> Result<(), Error> {
+        if self.sealed {
+            return Err(Error::Multipart(
+                "headers applied after the body was started",
+            ));
+        }
+        self.sealed = true;
+        self.chunked = self.content_length().is_none();
+        Ok(())
+    }
+
+    fn part_head(
+        &self,
+        name: &str,
+        filename: Option<&str>,
+        content_type: &str,
+    ) -> Result<Vec<u8>, Error> {
+        if content_type.bytes().any(|b| matches!(b, b'\r' | b'\n' | 0)) {
+            return Err(Error::InvalidHeaderValue("Content-Type".to_string()));
+        }
+        let mut head = format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"{}\"",
+            self.boundary,
+            escape(name)
+        );
+        if let Some(filename) = filename {
+            head.push_str(&format!("; filename=\"{}\"", escape(filename)));
+            let content_type = match content_type {
+                "" => "application/octet-stream",
+                other => other,
+            };
+            head.push_str(&format!("\r\nContent-Type: {}", content_type));
+        }
+        head.push_str("\r\n\r\n");
+        Ok(head.into_bytes())
+    }
+
+    fn closing_delimiter(&self) -> String {
+        format!("--{}--\r\n", self.boundary)
+    }
+}
+
+/// Field and file names as the HTML standard encodes them: LF, CR and `"` are
+/// percent-encoded, everything else is sent as is.
+fn escape(name: &str) -> String {
+    name.replace('\n', "%0A")
+        .replace('\r', "%0D")
+        .replace('"', "%22")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn builder() -> MultipartBuilder {
+        let mut form = MultipartBuilder::new().unwrap();
+        form.set_boundary("X").unwrap();
+        form
+    }
+
+    /// Pull the whole body, feeding `stream` to the streamed parts in pieces.
+    fn drain(form: &mut MultipartBuilder, max: usize, stream: &[u8]) -> Vec<u8> {
+        let mut body = Vec::new();
+        let mut rest = stream;
+        while !form.is_done() {
+            if form.needs_data() {
+                if rest.is_empty() {
+                    form.end_data().unwrap();
+                } else {
+                    let (piece, tail) = rest.split_at(rest.len().min(3));
+                    form.write_data(piece).unwrap();
+                    rest = tail;
+                }
+            }
+            body.extend(form.next_chunk(max).unwrap());
+        }
+        body
+    }
+
+    #[test]
+    fn known_sizes_give_content_length() {
+        let mut form = builder();
+        form.add_text("a \"b\"\r\n", "1").unwrap();
+        form.add_file("f", "x.txt", "text/plain", b"hi".to_vec())
+            .unwrap();
+        form.add_file_stream("g", "y.bin", "", Some(7)).unwrap();
+        let expected = "--X\r\nContent-Disposition: form-data; name=\"a %22b%22%0D%0A\"\r\n\r\n\
+                        1\r\n\
+                        --X\r\nContent-Disposition: form-data; name=\"f\"; filename=\"x.txt\"\r\n\
+                        Content-Type: text/plain\r\n\r\nhi\r\n\
+                        --X\r\nContent-Disposition: form-data; name=\"g\"; filename=\"y.bin\"\r\n\
+                        Content-Type: application/octet-stream\r\n\r\nstreams\r\n\
+                        --X--\r\n";
+        assert_eq!(form.content_length(), Some(expected.len() as u64));
+
+        let mut req = RequestBuilder::new("POST", "/upload").unwrap();
+        req.set_host("example.com").unwrap();
+        form.apply_headers(&mut req).unwrap();
+        assert_eq!(
+            String::from_utf8(req.to_bytes().unwrap()).unwrap(),
+            format!(
+                "POST /upload HTTP/1.1\r\nHost: example.com\r\n\
+                 Content-Type: multipart/form-data; boundary=X\r\n\
+                 Content-Length: {}\r\n\r\n",
+                expected.len()
+            )
+        );
+        assert!(form.add_text("late", "").is_err());
+        assert_eq!(drain(&mut form, 5, b"streams"), expected.as_bytes());
+        assert!(form.next_chunk(5).unwrap().is_empty());
+    }
+
+    #[test]
+    fn unknown_size_is_chunked() {
+        let mut form = builder();
+        form.add_file_stream("g", "y", "image/png", None).unwrap();
+        assert_eq!(form.content_length(), None);
+        let mut req = RequestBuilder::new("POST", "/").unwrap();
+        req.set_host("h").unwrap();
+        form.apply_headers(&mut req).unwrap();
+        assert!(String::from_utf8(req.to_bytes().unwrap())
+            .unwrap()
+            .ends_with("Transfer-Encoding: chunked\r\n\r\n"));
+
+        let body = drain(&mut form, 1 << 20, b"abcdefg");
+        let head = "--X\r\nContent-Disposition: form-data; name=\"g\"; filename=\"y\"\r\n\
+                    Content-Type: image/png\r\n\r\n";
+        assert_eq!(
+            String::from_utf8(body).unwrap(),
+            format!(
+                "{:x}\r\n{}\r\n3\r\nabc\r\n3\r\ndef\r\n1\r\ng\r\n9\r\n\r\n--X--\r\n\r\n0\r\n\r\n",
+                head.len(),
+                head
+            )
+        );
+    }
+
+    #[test]
+    fn streamed_sizes_and_misuse_are_checked() {
+        let mut form = builder();
+        assert!(form.write_data(b"x").is_err());
+        assert!(form.set_boundary("has space").is_err());
+        form.add_file_stream("g", "y", "", Some(2)).unwrap();
+        assert!(form.set_boundary("Y").is_err());
+        assert!(form.add_file("f", "x", "a\r\nb", Vec::new()).is_err());
+        form.next_chunk(64).unwrap();
+        assert!(!form.needs_data());
+        form.next_chunk(64).unwrap();
+        assert!(form.needs_data());
+        assert_eq!(
+            form.end_data(),
+            Err(Error::MultipartSizeMismatch {
+                declared: 2,
+                actual: 0
+            })
+        );
+        assert!(form.write_data(b"abc").is_err());
+
+        let boundary = MultipartBuilder::new().unwrap().boundary();
+        assert!(boundary.starts_with("----WebKitFormBoundary") && boundary.len() == 38);
+        assert_ne!(boundary, MultipartBuilder::new().unwrap().boundary());
+    }
+}
@@ -0,0 +1,497 @@
+//! Browser request header sets, named like the `Http2Options::profile` presets so a
+//! connection's fingerprint and its headers describe the same browser.
+
+use wasm_bindgen::prelude::*;
+
+use crate::coding::accept_encoding;
+use crate::error::Error;
+use crate::http1::is_token;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Browser {
+    /// Chrome 131
+    Chrome,
+    /// Chrome 104, paired with the "chrome-old" HTTP/2 preface
+    ChromeOld,
+    /// Firefox 133
+    Firefox,
+}
+
+impl Browser {
+    const ALL: [Browser; 3] = [Browser::Chrome, Browser::ChromeOld, Browser::Firefox];
+
+    fn from_name(name: &str) -> Option<Browser> {
+        Browser::ALL.into_iter().find(|b| b.name() == name)
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Browser::Chrome => "chrome",
+            Browser::ChromeOld => "chrome-old",
+            Browser::Firefox => "firefox",
+        }
+    }
+
+    fn major(self) -> u32 {
+        match self {
+            Browser::Chrome => 131,
+            Browser::ChromeOld => 104,
+            Browser::Firefox => 133,
+        }
+    }
+
+    fn is_chrome(self) -> bool {
+        self != Browser::Firefox
+    }
+
+    fn product(self) -> &'static str {
+        if self.is_chrome() {
+            "Chrome"
+        } else {
+            "Firefox"
+        }
+    }
+
+    /// Token the user-agent has to carry, e.g. "Chrome/131."
+    fn product_token(self) -> String {
+        format!("{}/{}.", self.product(), self.major())
+    }
+
+    fn label(self) -> String {
+        format!("{} {}", self.product(), self.major())
+    }
+}
+
+/// What the browser is loading, which decides the Accept and Sec-Fetch-* values.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RequestType {
+    /// Top-level document typed into the address bar
+    Navigation,
+    /// Same-origin `fetch()` or XMLHttpRequest; browsers send the same fields for both
+    Fetch,
+}
+
+const CHROME_NAVIGATE_ACCEPT: &str = "text/html,application/xhtml+xml,application/xml;q=0.9,\
+     image/avif,image/webp,image/apng,*/*;q=0.8,application/signed-exchange;v=b3;q=0.7";
+
+/// Header fields in the browser's send order, lowercase as on HTTP/2.
+fn default_fields(browser: Browser, request: RequestType) -> Vec<(&'static str, &'static str)> {
+    let (sec_ch_ua, user_agent, accept_encoding) = match browser {
+        Browser::Chrome => (
+            r#""Google Chrome";v="131", "Chromium";v="131", "Not_A Brand";v="24""#,
+            "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 \
+             (KHTML, like Gecko) Chrome/131.0.0.0 Safari/537.36",
+            "gzip, deflate, br, zstd",
+        ),
+        Browser::ChromeOld => (
+            r#""Chromium";v="104", " Not A;Brand";v="99", "Google Chrome";v="104""#,
+            "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 \
+             (KHTML, like Gecko) Chrome/104.0.0.0 Safari/537.36",
+            "gzip, deflate, br",
+        ),
+        Browser::Firefox => (
+            "",
+            "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:133.0) Gecko/20100101 Firefox/133.0",
+            "gzip, deflate, br, zstd",
+        ),
+    };
+    let mut fields = match (browser.is_chrome(), request) {
+        (true, RequestType::Navigation) => vec![
+            ("sec-ch-ua", sec_ch_ua),
+            ("sec-ch-ua-mobile", "?0"),
+            ("sec-ch-ua-platform", r#""Windows""#),
+            ("upgrade-insecure-requests", "1"),
+            ("user-agent", user_agent),
+            ("accept", CHROME_NAVIGATE_ACCEPT),
+            ("sec-fetch-site", "none"),
+            ("sec-fetch-mode", "navigate"),
+            ("sec-fetch-user", "?1"),
+            ("sec-fetch-dest", "document"),
+            ("accept-encoding", accept_encoding),
+            ("accept-language", "en-US,en;q=0.9"),
+        ],
+        (true, RequestType::Fetch) => vec![
+            ("sec-ch-ua", sec_ch_ua),
+            ("sec-ch-ua-mobile", "?0"),
+            ("user-agent", user_agent),
+            ("sec-ch-ua-platform", r#""Windows""#),
+            ("accept", "*/*"),
+            ("sec-fetch-site", "same-origin"),
+            ("sec-fetch-mode", "cors"),
+            ("sec-fetch-dest", "empty"),
+            ("accept-encoding", accept_encoding),
+            ("accept-language", "en-US,en;q=0.9"),
+        ],
+        (false, RequestType::Navigation) => vec![
+            ("user-agent", user_agent),
+            (
+                "accept",
+                "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8",
+            ),
+            ("accept-language", "en-US,en;q=0.5"),
+            ("accept-encoding", accept_encoding),
+            ("upgrade-insecure-requests", "1"),
+            ("sec-fetch-dest", "document"),
+            ("sec-fetch-mode", "navigate"),
+            ("sec-fetch-site", "none"),
+            ("sec-fetch-user", "?1"),
+            ("priority", "u=0, i"),
+            ("te", "trailers"),
+        ],
+        (false, RequestType::Fetch) => vec![
+            ("user-agent", user_agent),
+            ("accept", "*/*"),
+            ("accept-language", "en-US,en;q=0.5"),
+            ("accept-encoding", accept_encoding),
+            ("sec-fetch-dest", "empty"),
+            ("sec-fetch-mode", "cors"),
+            ("sec-fetch-site", "same-origin"),
+            ("priority", "u=4"),
+            ("te", "trailers"),
+        ],
+    };
+    // The Priority header arrived in Chrome 124
+    if browser == Browser::Chrome {
+        let priority = match request {
+            RequestType::Navigation => "u=0, i",
+            RequestType::Fetch => "u=1, i",
+        };
+        fields.push(("priority", priority));
+    }
+    fields
+}
+
+/// Request headers of a browser, in its order, with individual values overridable.
+/// Pseudo-headers and Host are not included: pass `names`/`values` after them to
+/// `Http2Connection::open_stream`, or add `http1_names`/`values` to a `RequestBuilder`.
+///
+/// The Accept-Encoding value is the browser's, so with the `brotli` or `zstd` feature
+/// disabled it can list codings that cannot be decoded (`contradictions` says so).
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct HeaderProfile {
+    browser: Browser,
+    /// Lowercase name and value, in send order
+    fields: Vec<(String, String)>,
+}
+
+#[wasm_bindgen]
+impl HeaderProfile {
+    /// Headers of `profile` ("chrome", "chrome-old" or "firefox", as for
+    /// `Http2Options::profile`) for a `request_type` of "navigation" (typing a URL),
+    /// "fetch" or "xhr" (same-origin script requests).
+    #[wasm_bindgen(constructor)]
+    pub fn new(profile: &str, request_type: &str) -> Result<HeaderProfile, Error> {
+        let browser = Browser::from_name(profile).ok_or_else(|| Error::InvalidOption {
+            option: "header profile",
+            value: profile.to_string(),
+        })?;
+        let request = match request_type {
+            "navigation" => RequestType::Navigation,
+            "fetch" | "xhr" => RequestType::Fetch,
+            _ => {
+                return Err(Error::InvalidOption {
+                    option: "request type",
+                    value: request_type.to_string(),
+                })
+            }
+        };
+        let fields = default_fields(browser, request)
+            .into_iter()
+            .map(|(n, v)| (n.to_string(), v.to_string()))
+            .collect();
+        Ok(HeaderProfile { browser, fields })
+    }
+
+    /// Name of the profile, which is also its `Http2Options::profile` name.
+    pub fn profile(&self) -> String {
+        self.browser.name().to_string()
+    }
+
+    /// Set a header's value. A header the profile sends keeps its position; any
+    /// other is appended after the profile's headers.
+    pub fn set_header(&mut self, name: &str, value: &str) -> Result<(), Error> {
+        if !is_token(name) {
+            return Err(Error::InvalidHeaderName(name.to_string()));
+        }
+        let name = name.to_ascii_lowercase();
+        if value.bytes().any(|b| b == b'\r' || b == b'\n' || b == 0) {
+            return Err(Error::InvalidHeaderValue(name));
+        }
+        match self.fields.iter_mut().find(|(n, _)| *n == name) {
+            Some(field) => field.1 = value.to_string(),
+            None => self.fields.push((name, value.to_string())),
+        }
+        Ok(())
+    }
+
+    /// Stop sending a header.
+    pub fn remove_header(&mut self, name: &str) {
+        self.fields.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+    }
+
+    /// Current value of a header.
+    pub fn header(&self, name: &str) -> Option<String> {
+        self.get(name).map(str::to_string)
+    }
+
+    /// Header names in send order, lowercase as on HTTP/2.
+    pub fn names(&self) -> Vec<String> {
+        self.fields.iter().map(|(n, _)| n.clone()).collect()
+    }
+
+    /// Header names in send order with the casing the browser uses on HTTP/1.1:
+    /// Title-Case, except that Chrome keeps its client hints (sec-ch-ua*) lowercase.
+    pub fn http1_names(&self) -> Vec<String> {
+        self.fields
+            .iter()
+            .map(|(n, _)| {
+                if self.browser.is_chrome() && n.starts_with("sec-ch-ua") {
+                    n.clone()
+                } else if n == "te" {
+                    "TE".to_string()
+                } else {
+                    title_case(n)
+                }
+            })
+            .collect()
+    }
+
+    /// Header values, matching `names`.
+    pub fn values(&self) -> Vec<String> {
+        self.fields.iter().map(|(_, v)| v.clone()).collect()
+    }
+
+    /// Overrides that give the profile away: a user-agent or client hint naming
+    /// another browser or version, client hints a browser never sends or a missing
+    /// one it always sends, a platform or mobile hint that disagrees with the
+    /// user-agent, and Accept-Encoding codings that are not compiled in. Each entry
+    /// names the header and the problem, without its value. Empty when consistent.
+    pub fn contradictions(&self) -> Vec<String> {
+        let browser = self.browser;
+        let mut found = Vec::new();
+        let user_agent = self.get("user-agent");
+        if let Some(ua) = user_agent {
+            if !ua.contains(&browser.product_token()) {
+                found.push(format!("user-agent does not name {}", browser.label()));
+            }
+        }
+
+        let hints: Vec<&str> = self
+            .fields
+            .iter()
+            .map(|(n, _)| n.as_str())
+            .filter(|n| n.starts_with("sec-ch-ua"))
+            .collect();
+        if !browser.is_chrome() {
+            found.extend(
+                hints
+                    .iter()
+                    .map(|n| format!("{} is never sent by Firefox", n)),
+            );
+        } else if let Some(brands) = self.get("sec-ch-ua") {
+            let brand = format!(r#""Google Chrome";v="{}""#, browser.major());
+            if !brands.contains(&brand) {
+                found.push(format!("sec-ch-ua does not name {}", browser.label()));
+            }
+        } else {
+            found.push("sec-ch-ua is missing, which Chrome always sends".to_string());
+        }
+
+        if let (Some(ua), Some(platform)) = (user_agent, self.get("sec-ch-ua-platform")) {
+            if ua_platform(ua).is_some_and(|p| platform.trim_matches('"') != p) {
+                found.push("sec-ch-ua-platform does not match the user-agent".to_string());
+            }
+        }
+        if let (Some(ua), Some(mobile)) = (user_agent, self.get("sec-ch-ua-mobile")) {
+            let expected = if ua.contains("Mobile") { "?1" } else { "?0" };
+            if mobile != expected {
+                found.push("sec-ch-ua-mobile does not match the user-agent".to_string());
+            }
+        }
+
+        if let Some(codings) = self.get("accept-encoding") {
+            let supported = accept_encoding();
+            let supported: Vec<&str> = supported.split(", ").collect();
+            let missing: Vec<&str> = codings
+                .split(',')
+                .map(|c| c.split(';').next().unwrap_or("").trim())
+                .filter(|c| !c.is_empty() && *c != "*" && *c != "identity")
+                .filter(|c| !supported.iter().any(|s| s.eq_ignore_ascii_case(c)))
+                .collect();
+            if !missing.is_empty() {
+                found.push(format!(
+                    "accept-encoding lists {}, which is not compiled in",
+                    missing.join(", ")
+                ));
+            }
+        }
+        found
+    }
+}
+
+impl HeaderProfile {
+    fn get(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Names of the browser profiles, each valid for `HeaderProfile::new` and
+/// `Http2Options::profile`.
+#[wasm_bindgen]
+pub fn available_profiles() -> Vec<String> {
+    Browser::ALL.iter().map(|b| b.name().to_string()).collect()
+}
+
+/// Browser a profile imitates and its major version, e.g. ("Chrome", 131).
+pub fn profile_browser(name: &str) -> Option<(&'static str, u32)> {
+    Browser::from_name(name).map(|b| (b.product(), b.major()))
+}
+
+/// The sec-ch-ua-platform value a browser reports alongside this user-agent.
+fn ua_platform(ua: &str) -> Option<&'static str> {
+    if ua.contains("Android") {
+        Some("Android")
+    } else if ua.contains("Windows") {
+        Some("Windows")
+    } else if ua.contains("CrOS") {
+        Some("Chrome OS")
+    } else if ua.contains("Macintosh") {
+        Some("macOS")
+    } else if ua.contains("Linux") {
+        Some("Linux")
+    } else {
+        None
+    }
+}
+
+fn title_case(name: &str) -> String {
+    name.split('-')
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+                None => String::new(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn browser_order_per_request_type() {
+        let chrome = HeaderProfile::new("chrome", "navigation").unwrap();
+        assert_eq!(
+            chrome.names(),
+            [
+                "sec-ch-ua",
+                "sec-ch-ua-mobile",
+                "sec-ch-ua-platform",
+                "upgrade-insecure-requests",
+                "user-agent",
+                "accept",
+                "sec-fetch-site",
+                "sec-fetch-mode",
+                "sec-fetch-user",
+                "sec-fetch-dest",
+                "accept-encoding",
+                "accept-language",
+                "priority",
+            ]
+        );
+        assert_eq!(
+            &chrome.http1_names()[2..5],
+            [
+                "sec-ch-ua-platform",
+                "Upgrade-Insecure-Requests",
+                "User-Agent"
+            ]
+        );
+        let xhr = HeaderProfile::new("chrome", "xhr").unwrap();
+        assert_eq!(xhr.header("sec-fetch-mode").as_deref(), Some("cors"));
+        assert_eq!(xhr.header("accept").as_deref(), Some("*/*"));
+        assert!(xhr.header("upgrade-insecure-requests").is_none());
+
+        let old = HeaderProfile::new("chrome-old", "navigation").unwrap();
+        assert!(old.header("priority").is_none());
+        assert!(old.header("user-agent").unwrap().contains("Chrome/104."));
+
+        let firefox = HeaderProfile::new("firefox", "fetch").unwrap();
+        assert_eq!(firefox.names()[0], "user-agent");
+        assert!(!firefox.names().iter().any(|n| n.starts_with("sec-ch")));
+        assert_eq!(firefox.http1_names().last().unwrap(), "TE");
+        assert_eq!(firefox.profile(), "firefox");
+        #[cfg(all(feature = "brotli", feature = "zstd"))]
+        for profile in [chrome, xhr, old, firefox] {
+            assert!(profile.contradictions().is_empty(), "{:?}", profile);
+        }
+
+        assert!(HeaderProfile::new("safari", "navigation").is_err());
+        assert!(HeaderProfile::new("chrome", "image").is_err());
+    }
+
+    #[test]
+    fn overrides_keep_order_and_are_checked() {
+        let mut profile = HeaderProfile::new("chrome", "navigation").unwrap();
+        profile
+            .set_header("Accept-Language", "de-DE,de;q=0.9")
+            .unwrap();
+        profile.set_header("Cookie", "a=1").unwrap();
+        let names = profile.names();
+        assert_eq!(names[11], "accept-language");
+        assert_eq!(names.last().unwrap(), "cookie");
+        assert_eq!(profile.values()[11], "de-DE,de;q=0.9");
+        assert!(profile.set_header("bad name", "x").is_err());
+        assert!(profile.set_header("x-test", "a\r\nb").is_err());
+
+        profile
+            .set_header(
+                "user-agent",
+                "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 \
+                 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36",
+            )
+            .unwrap();
+        profile.set_header("accept-encoding", "gzip").unwrap();
+        assert_eq!(
+            profile.contradictions(),
+            [
+                "user-agent does not name Chrome 131",
+                "sec-ch-ua-platform does not match the user-agent",
+            ]
+        );
+        profile.remove_header("sec-ch-ua");
+        assert!(profile
+            .contradictions()
+            .contains(&"sec-ch-ua is missing, which Chrome always sends".to_string()));
+
+        let mut firefox = HeaderProfile::new("firefox", "navigation").unwrap();
+        firefox.set_header("sec-ch-ua-mobile", "?0").unwrap();
+        firefox
+            .set_header("accept-encoding", "gzip, snappy")
+            .unwrap();
+        assert_eq!(
+            firefox.contradictions(),
+            [
+                "sec-ch-ua-mobile is never sent by Firefox",
+                "accept-encoding lists snappy, which is not compiled in",
+            ]
+        );
+    }
+
+    #[test]
+    fn every_profile_is_a_header_and_http2_preset() {
+        for name in available_profiles() {
+            assert_eq!(HeaderProfile::new(&name, "fetch").unwrap().profile(), name);
+            assert!(crate::http2::Http2Options::profile(&name).is_ok());
+        }
+        assert_eq!(profile_browser("chrome-old"), Some(("Chrome", 104)));
+        assert_eq!(profile_browser("safari"), None);
+    }
+}
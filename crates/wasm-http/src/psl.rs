@@ -0,0 +1,222 @@
+//! Public suffix list lookups (https://publicsuffix.org/list/), for cookie domains and
+//! anything else that needs to know where a registrable domain starts.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+#[cfg(feature = "psl")]
+use std::sync::OnceLock;
+
+use wasm_bindgen::prelude::*;
+
+use crate::domain_to_ascii;
+
+#[cfg(feature = "psl")]
+static BUNDLED: OnceLock<PublicSuffixList> = OnceLock::new();
+
+#[derive(Debug, Default)]
+struct Rules {
+    /// Plain rules, e.g. "co.uk"
+    suffixes: HashSet<String>,
+    /// Wildcard rules without their "*." prefix: "ck" for "*.ck"
+    wildcards: HashSet<String>,
+    /// Exception rules without their '!': "www.ck" for "!www.ck"
+    exceptions: HashSet<String>,
+}
+
+/// A parsed public suffix list, ICANN and private sections alike (so "github.io"
+/// counts as a suffix, as in browsers). Cheap to clone.
+///
+/// Unicode rules and hosts are compared in their punycode ("xn--") forms, the
+/// form answers come back in.
+#[wasm_bindgen]
+#[derive(Clone, Default)]
+pub struct PublicSuffixList {
+    rules: Arc<Rules>,
+}
+
+#[wasm_bindgen]
+impl PublicSuffixList {
+    /// Parse a list in the publicsuffix.org format: one rule per line, `//` comments,
+    /// `*.` wildcards and `!` exceptions.
+    #[wasm_bindgen(constructor)]
+    pub fn new(list: &str) -> PublicSuffixList {
+        let mut rules = Rules::default();
+        for line in list.lines() {
+            let Some(rule) = line.split_whitespace().next() else {
+                continue;
+            };
+            if rule.starts_with("//") {
+                continue;
+            }
+            let (set, name) = if let Some(exception) = rule.strip_prefix('!') {
+                (&mut rules.exceptions, exception)
+            } else if let Some(parent) = rule.strip_prefix("*.") {
+                (&mut rules.wildcards, parent)
+            } else {
+                (&mut rules.suffixes, rule)
+            };
+            if let Some(name) = domain_to_ascii(name) {
+                set.insert(name);
+            }
+        }
+        PublicSuffixList {
+            rules: Arc::new(rules),
+        }
+    }
+
+    /// The list compiled into the module (needs the `psl` feature, about 115 KiB).
+    #[cfg(feature = "psl")]
+    pub fn bundled() -> PublicSuffixList {
+        BUNDLED
+            .get_or_init(|| PublicSuffixList::new(include_str!("../data/public_suffix_list.dat")))
+            .clone()
+    }
+
+    /// Public suffix of `host`, e.g. "co.uk" for "www.example.co.uk". A TLD that is
+    /// not on the list is its own suffix. Null for IP addresses and malformed names.
+    pub fn public_suffix(&self, host: &str) -> Option<String> {
+        let host = normalize(host)?;
+        let labels: Vec<&str> = host.split('.').collect();
+        let start = self.suffix_start(&labels);
+        Some(labels[start..].join("."))
+    }
+
+    /// Whether `domain` is itself a public suffix, like "com" or "github.io".
+    pub fn is_public_suffix(&self, domain: &str) -> bool {
+        let Some(host) = normalize(domain) else {
+            return false;
+        };
+        let labels: Vec<&str> = host.split('.').collect();
+        self.suffix_start(&labels) == 0
+    }
+
+    /// Registrable domain of `host`: its public suffix plus one more label, e.g.
+    /// "example.co.uk" for "www.example.co.uk". Null when `host` is a public suffix,
+    /// an IP address or malformed.
+    pub fn effective_tld_plus_one(&self, host: &str) -> Option<String> {
+        let host = normalize(host)?;
+        let labels: Vec<&str> = host.split('.').collect();
+        let start = self.suffix_start(&labels).checked_sub(1)?;
+        Some(labels[start..].join("."))
+    }
+}
+
+impl PublicSuffixList {
+    /// Index of the first label of the public suffix of `labels`.
+    fn suffix_start(&self, labels: &[&str]) -> usize {
+        let rules = &self.rules;
+        let suffix = |i: usize| labels[i..].join(".");
+        // An exception rule always wins; its suffix is the rule minus its first label
+        if let Some(i) = (0..labels.len()).find(|&i| rules.exceptions.contains(&suffix(i))) {
+            return i + 1;
+        }
+        // Otherwise the matching rule with the most labels, longest first
+        for i in 0..labels.len() {
+            let wildcard = i + 1 < labels.len() && rules.wildcards.contains(&suffix(i + 1));
+            if wildcard || rules.suffixes.contains(&suffix(i)) {
+                return i;
+            }
+        }
+        // The implicit "*" rule
+        labels.len() - 1
+    }
+}
+
+impl fmt::Debug for PublicSuffixList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PublicSuffixList")
+            .field("suffixes", &self.rules.suffixes.len())
+            .field("wildcards", &self.rules.wildcards.len())
+            .field("exceptions", &self.rules.exceptions.len())
+            .finish()
+    }
+}
+
+/// Lowercased ASCII host without a trailing dot; None for IPs and empty labels.
+fn normalize(host: &str) -> Option<String> {
+    let host = host.strip_suffix('.').unwrap_or(host);
+    let host = match host.is_ascii() {
+        true => host.to_ascii_lowercase(),
+        false => domain_to_ascii(host)?,
+    };
+    let is_ip = host.starts_with('[') || host.parse::<Ipv4Addr>().is_ok();
+    (!is_ip && !host.is_empty() && !host.split('.').any(str::is_empty)).then_some(host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIST: &str = "// comment\n\ncom\nuk\nco.uk\n*.ck\n!www.ck\njp\n*.kobe.jp\n\
+                        !city.kobe.jp\ngithub.io  trailing text\n";
+
+    #[test]
+    fn plain_wildcard_and_exception_rules() {
+        let list = PublicSuffixList::new(LIST);
+        let cases = [
+            ("www.example.co.uk", "co.uk", Some("example.co.uk")),
+            ("Example.COM.", "com", Some("example.com")),
+            ("evil.github.io", "github.io", Some("evil.github.io")),
+            ("a.b.ck", "b.ck", Some("a.b.ck")),
+            ("b.ck", "b.ck", None),
+            ("www.ck", "ck", Some("www.ck")),
+            ("a.www.ck", "ck", Some("www.ck")),
+            ("x.city.kobe.jp", "kobe.jp", Some("city.kobe.jp")),
+            ("x.other.kobe.jp", "other.kobe.jp", Some("x.other.kobe.jp")),
+            ("host.unlisted", "unlisted", Some("host.unlisted")),
+            ("localhost", "localhost", None),
+        ];
+        for (host, suffix, etld1) in cases {
+            assert_eq!(
+                list.public_suffix(host).as_deref(),
+                Some(suffix),
+                "{}",
+                host
+            );
+            assert_eq!(
+                list.effective_tld_plus_one(host).as_deref(),
+                etld1,
+                "{}",
+                host
+            );
+        }
+        assert!(list.is_public_suffix("github.io"));
+        assert!(list.is_public_suffix("anything.ck"));
+        assert!(!list.is_public_suffix("www.ck"));
+        for bad in ["1.2.3.4", "[::1]", "", "a..b"] {
+            assert_eq!(list.public_suffix(bad), None, "{:?}", bad);
+            assert_eq!(list.effective_tld_plus_one(bad), None, "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn unicode_rules_match_punycode_hosts() {
+        let list = PublicSuffixList::new("cn\n公司.cn\n*.個人.hk\n");
+        assert!(list.is_public_suffix("xn--55qx5d.cn"));
+        assert_eq!(
+            list.effective_tld_plus_one("a.xn--55qx5d.cn").as_deref(),
+            Some("a.xn--55qx5d.cn")
+        );
+        assert_eq!(
+            list.effective_tld_plus_one("b.a.公司.cn").as_deref(),
+            Some("a.xn--55qx5d.cn")
+        );
+        assert!(list.is_public_suffix("x.xn--gmqw5a.hk"));
+    }
+
+    #[cfg(feature = "psl")]
+    #[test]
+    fn bundled_list() {
+        let list = PublicSuffixList::bundled();
+        assert_eq!(
+            list.effective_tld_plus_one("a.b.example.co.uk").as_deref(),
+            Some("example.co.uk")
+        );
+        assert!(list.is_public_suffix("github.io"));
+        assert!(list.is_public_suffix("s3.amazonaws.com"));
+        assert_eq!(list.public_suffix("www.ck").as_deref(), Some("ck"));
+        assert!(list.is_public_suffix("xn--55qx5d.cn"));
+    }
+}
@@ -0,0 +1,336 @@
+//! Resuming an interrupted download with a single `Range: bytes=N-` request.
+
+use wasm_bindgen::prelude::*;
+
+use crate::error::Error;
+use crate::headers::Headers;
+use crate::http1::RequestBuilder;
+
+/// One download across attempts: how much of the representation has been
+/// received, and the validator that ties a resumed range to the same version of it.
+///
+/// Feed every response head to `on_response` and every body byte count to
+/// `advance`. After a broken connection, `apply` (or `header_names` /
+/// `header_values` for HTTP/2) turns the next request into a resume: `Range`
+/// from the current offset and `If-Range` with the validator, so a server whose
+/// copy changed sends the whole new representation instead of a mismatched tail.
+#[wasm_bindgen]
+#[derive(Clone, Debug, Default)]
+pub struct ResumableDownload {
+    /// Bytes of the representation received so far
+    offset: u64,
+    /// Strong ETag, or Last-Modified date, of the representation
+    validator: Option<String>,
+    /// Complete length, once a response stated it
+    total: Option<u64>,
+}
+
+#[wasm_bindgen]
+impl ResumableDownload {
+    /// A download starting from the first byte.
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> ResumableDownload {
+        ResumableDownload::default()
+    }
+
+    /// Resume from byte `offset` of the representation identified by `validator`:
+    /// a strong ETag (`"abc"`) or a Last-Modified date, or "" to resume without
+    /// If-Range. A weak ETag cannot validate a range and is refused.
+    pub fn resume(offset: u64, validator: &str) -> Result<ResumableDownload, Error> {
+        let validator = match validator.trim() {
+            "" => None,
+            v if v.starts_with("W/") => {
+                return Err(Error::Range("a weak ETag cannot validate a range"));
+            }
+            v => Some(v.to_string()),
+        };
+        Ok(ResumableDownload {
+            offset,
+            validator,
+            total: None,
+        })
+    }
+
+    /// Count `len` more body bytes written out from a "full" or "partial" response.
+    pub fn advance(&mut self, len: u64) {
+        self.offset += len;
+    }
+
+    /// Bytes received so far: where the next request resumes.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Validator sent as If-Range, or null if the server offered none.
+    pub fn validator(&self) -> Option<String> {
+        self.validator.clone()
+    }
+
+    /// Complete length of the representation, once a response stated it.
+    pub fn total_length(&self) -> Option<u64> {
+        self.total
+    }
+
+    /// Whether every byte of a representation of known length has been received.
+    pub fn is_complete(&self) -> bool {
+        self.total == Some(self.offset)
+    }
+
+    /// Add the resume headers to `request`: `Range: bytes=N-` (replacing one added
+    /// earlier) and `If-Range`. Does nothing at offset 0.
+    pub fn apply(&self, request: &mut RequestBuilder) -> Result<(), Error> {
+        for (name, value) in self.headers() {
+            if !request.replace_header(&name.to_ascii_lowercase(), &value) {
+                request.header(name, &value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Names of the resume headers, for `Http2Connection::open_stream`.
+    pub fn header_names(&self) -> Vec<String> {
+        self.headers().map(|(n, _)| n.to_string()).collect()
+    }
+
+    /// Values matching `header_names`.
+    pub fn header_values(&self) -> Vec<String> {
+        self.headers().map(|(_, v)| v).collect()
+    }
+
+    /// Check the status and headers of a response to the download, and say what
+    /// to do with its body:
+    /// - "full": the whole representation from byte 0 (the first request)
+    /// - "range-ignored": a 200 to a resume, so the server ignored the range or the
+    ///   representation changed; the offset is reset and this body restarts the file
+    /// - "partial": a 206 continuing exactly at the offset; append its body
+    /// - "complete": a 416 confirming every byte was already received
+    /// - "other": any other status, left to the caller
+    ///
+    /// A 206 that starts elsewhere, disagrees with its Content-Length or the known
+    /// length, or carries a different validator fails with a `Range` error.
+    pub fn on_response(
+        &mut self,
+        status: u16,
+        names: Vec<String>,
+        values: Vec<String>,
+    ) -> Result<String, Error> {
+        if names.len() != values.len() {
+            return Err(Error::HeaderCountMismatch);
+        }
+        let mut headers = Headers::new();
+        for (name, value) in names.into_iter().zip(values) {
+            headers.push(name, value);
+        }
+        self.check(status, &headers).map(str::to_string)
+    }
+}
+
+impl ResumableDownload {
+    /// `on_response` with the response headers already collected.
+    pub fn check(&mut self, status: u16, headers: &Headers) -> Result<&'static str, Error> {
+        match status {
+            200 => {
+                let outcome = if self.offset == 0 {
+                    "full"
+                } else {
+                    "range-ignored"
+                };
+                self.offset = 0;
+                self.validator = response_validator(headers);
+                self.total = headers
+                    .get("content-length")
+                    .and_then(|v| v.trim().parse().ok());
+                Ok(outcome)
+            }
+            206 => self.check_partial(headers).map(|()| "partial"),
+            416 => match headers.get("content-range").and_then(parse_content_range) {
+                Some(ContentRange::Unsatisfied(total)) if total == self.offset => {
+                    self.total = Some(total);
+                    Ok("complete")
+                }
+                _ => Err(Error::Range("range not satisfiable")),
+            },
+            _ => Ok("other"),
+        }
+    }
+
+    fn check_partial(&mut self, headers: &Headers) -> Result<(), Error> {
+        let content_type = headers.get("content-type").unwrap_or_default();
+        if content_type
+            .trim_start()
+            .to_ascii_lowercase()
+            .starts_with("multipart/byteranges")
+        {
+            return Err(Error::Range("multipart/byteranges is not supported"));
+        }
+        let Some(ContentRange::Bytes { first, last, total }) =
+            headers.get("content-range").and_then(parse_content_range)
+        else {
+            return Err(Error::Range("missing or invalid Content-Range"));
+        };
+        if first != self.offset {
+            return Err(Error::Range("Content-Range does not start at the offset"));
+        }
+        if let Some(length) = headers.get("content-length") {
+            if length.trim().parse::<u64>().ok() != Some(last - first + 1) {
+                return Err(Error::Range("Content-Length does not match Content-Range"));
+            }
+        }
+        if let (Some(known), Some(total)) = (self.total, total) {
+            if known != total {
+                return Err(Error::Range("complete length changed"));
+            }
+        }
+        if let Some(validator) = &self.validator {
+            let field = if validator.starts_with('"') {
+                "etag"
+            } else {
+                "last-modified"
+            };
+            if headers.get(field).is_some_and(|v| v.trim() != validator) {
+                return Err(Error::Range("representation changed"));
+            }
+        }
+        self.total = total.or(self.total);
+        Ok(())
+    }
+
+    fn headers(&self) -> impl Iterator<Item = (&'static str, String)> + '_ {
+        let resume = self.offset > 0;
+        let range = resume.then(|| ("Range", format!("bytes={}-", self.offset)));
+        let if_range = self
+            .validator
+            .as_ref()
+            .filter(|_| resume)
+            .map(|v| ("If-Range", v.clone()));
+        range.into_iter().chain(if_range)
+    }
+}
+
+/// Strong ETag if there is one, else Last-Modified: what If-Range may carry.
+fn response_validator(headers: &Headers) -> Option<String> {
+    match headers.get("etag").map(str::trim) {
+        Some(etag) if etag.starts_with('"') => Some(etag.to_string()),
+        _ => headers.get("last-modified").map(|v| v.trim().to_string()),
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum ContentRange {
+    /// `bytes first-last/total`, with `*` as an unknown total
+    Bytes {
+        first: u64,
+        last: u64,
+        total: Option<u64>,
+    },
+    /// `bytes */total`, sent with 416
+    Unsatisfied(u64),
+}
+
+/// RFC 9110 §14.4 Content-Range, bytes unit only.
+fn parse_content_range(value: &str) -> Option<ContentRange> {
+    let rest = value.trim().strip_prefix("bytes ")?;
+    let (range, total) = rest.split_once('/')?;
+    if range == "*" {
+        return total.parse().ok().map(ContentRange::Unsatisfied);
+    }
+    let (first, last) = range.split_once('-')?;
+    let (first, last): (u64, u64) = (first.parse().ok()?, last.parse().ok()?);
+    let total = match total {
+        "*" => None,
+        total => Some(total.parse().ok()?),
+    };
+    if last < first || total.is_some_and(|t| last >= t) {
+        return None;
+    }
+    Some(ContentRange::Bytes { first, last, total })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(fields: &[(&str, &str)]) -> Headers {
+        let mut headers = Headers::new();
+        for (name, value) in fields {
+            headers.push(*name, *value);
+        }
+        headers
+    }
+
+    #[test]
+    fn download_resumes_with_if_range() {
+        let mut download = ResumableDownload::new();
+        let first = headers(&[
+            ("ETag", "\"v1\""),
+            ("Last-Modified", "Tue, 13 Oct 2026 10:00:00 GMT"),
+            ("Content-Length", "1000"),
+        ]);
+        assert_eq!(download.check(200, &first), Ok("full"));
+        assert!(download.header_names().is_empty());
+        download.advance(400);
+        assert_eq!(download.validator().as_deref(), Some("\"v1\""));
+
+        let mut request = RequestBuilder::new("GET", "/file").unwrap();
+        request.set_host("example.com").unwrap();
+        request.header("Range", "bytes=0-").unwrap();
+        download.apply(&mut request).unwrap();
+        let bytes = String::from_utf8(request.to_bytes().unwrap()).unwrap();
+        assert!(bytes.contains("\r\nRange: bytes=400-\r\nIf-Range: \"v1\"\r\n"));
+
+        let partial = headers(&[
+            ("Content-Range", "bytes 400-999/1000"),
+            ("Content-Length", "600"),
+            ("ETag", "\"v1\""),
+        ]);
+        assert_eq!(download.check(206, &partial), Ok("partial"));
+        download.advance(600);
+        assert!(download.is_complete());
+        let done = headers(&[("Content-Range", "bytes */1000")]);
+        assert_eq!(download.check(416, &done), Ok("complete"));
+    }
+
+    #[test]
+    fn ignored_ranges_and_bad_partials() {
+        let mut download = ResumableDownload::resume(500, "\"v1\"").unwrap();
+        assert_eq!(download.header_values(), ["bytes=500-", "\"v1\""]);
+        // The representation changed: the server sends all of the new one
+        let changed = headers(&[("ETag", "\"v2\""), ("Content-Length", "800")]);
+        assert_eq!(download.check(200, &changed), Ok("range-ignored"));
+        assert_eq!((download.offset(), download.total_length()), (0, Some(800)));
+
+        let mut download = ResumableDownload::resume(500, "\"v1\"").unwrap();
+        for (fields, reason) in [
+            (
+                &[("Content-Range", "bytes 0-999/1000")][..],
+                "Content-Range does not start at the offset",
+            ),
+            (
+                &[
+                    ("Content-Range", "bytes 500-999/1000"),
+                    ("Content-Length", "10"),
+                ],
+                "Content-Length does not match Content-Range",
+            ),
+            (
+                &[("Content-Range", "bytes 500-999/1000"), ("ETag", "\"v2\"")],
+                "representation changed",
+            ),
+            (
+                &[("Content-Range", "bytes 500-1000/1000")],
+                "missing or invalid Content-Range",
+            ),
+        ] {
+            assert_eq!(
+                download.check(206, &headers(fields)),
+                Err(Error::Range(reason))
+            );
+        }
+        assert!(ResumableDownload::resume(1, "W/\"v1\"").is_err());
+        // Weak ETags fall back to Last-Modified
+        let weak = headers(&[("ETag", "W/\"v1\""), ("Last-Modified", "yesterday")]);
+        let mut download = ResumableDownload::new();
+        download.check(200, &weak).unwrap();
+        assert_eq!(download.validator().as_deref(), Some("yesterday"));
+    }
+}
@@ -0,0 +1,662 @@
+//! Redirect following, sans-IO, after Fetch's "HTTP-redirect fetch" (§4.4).
+//!
+//! `RedirectController` remembers the request in flight. Each response goes through
+//! `on_response`, which either says the exchange is done or describes the next
+//! request: method, URL, headers to send and whether the body is resent. The caller
+//! builds and sends it, opening a connection to the new origin if the step says so.
+
+use wasm_bindgen::prelude::*;
+
+use crate::error::Error;
+use crate::headers::Headers;
+use crate::hsts::HstsStore;
+use crate::url::HttpUrl;
+
+/// Fetch's redirect limit, shared by every browser.
+const DEFAULT_MAX_REDIRECTS: usize = 20;
+
+/// Headers describing a request body, dropped along with it (Fetch "request-body-header name").
+const BODY_HEADERS: [&str; 5] = [
+    "content-encoding",
+    "content-language",
+    "content-location",
+    "content-type",
+    "content-length",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ReferrerPolicy {
+    NoReferrer,
+    NoReferrerWhenDowngrade,
+    Origin,
+    OriginWhenCrossOrigin,
+    SameOrigin,
+    StrictOrigin,
+    StrictOriginWhenCrossOrigin,
+    UnsafeUrl,
+}
+
+impl ReferrerPolicy {
+    fn parse(token: &str) -> Option<ReferrerPolicy> {
+        Some(match token.trim().to_ascii_lowercase().as_str() {
+            "no-referrer" => ReferrerPolicy::NoReferrer,
+            "no-referrer-when-downgrade" => ReferrerPolicy::NoReferrerWhenDowngrade,
+            "origin" => ReferrerPolicy::Origin,
+            "origin-when-cross-origin" => ReferrerPolicy::OriginWhenCrossOrigin,
+            "same-origin" => ReferrerPolicy::SameOrigin,
+            "strict-origin" => ReferrerPolicy::StrictOrigin,
+            "strict-origin-when-cross-origin" => ReferrerPolicy::StrictOriginWhenCrossOrigin,
+            "unsafe-url" => ReferrerPolicy::UnsafeUrl,
+            _ => return None,
+        })
+    }
+
+    /// Referer value for a request from `referrer` to `target` (Referrer Policy §8.3).
+    fn referrer(self, referrer: &HttpUrl, target: &HttpUrl) -> Option<String> {
+        let full = referrer.without_fragment();
+        let origin = format!("{}/", referrer.origin());
+        let full = if full.len() > 4096 {
+            origin.clone()
+        } else {
+            full
+        };
+        let same_origin = referrer.same_origin(target);
+        let downgrade = referrer.secure && !target.secure;
+        match self {
+            ReferrerPolicy::NoReferrer => None,
+            ReferrerPolicy::NoReferrerWhenDowngrade => (!downgrade).then_some(full),
+            ReferrerPolicy::Origin => Some(origin),
+            ReferrerPolicy::OriginWhenCrossOrigin if same_origin => Some(full),
+            ReferrerPolicy::OriginWhenCrossOrigin => Some(origin),
+            ReferrerPolicy::SameOrigin => same_origin.then_some(full),
+            ReferrerPolicy::StrictOrigin => (!downgrade).then_some(origin),
+            ReferrerPolicy::StrictOriginWhenCrossOrigin if same_origin => Some(full),
+            ReferrerPolicy::StrictOriginWhenCrossOrigin => (!downgrade).then_some(origin),
+            ReferrerPolicy::UnsafeUrl => Some(full),
+        }
+    }
+}
+
+/// How redirects are followed. The defaults are a browser's.
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct RedirectPolicy {
+    max_redirects: usize,
+    referrer_policy: ReferrerPolicy,
+    allow_https_downgrade: bool,
+    detect_loops: bool,
+    /// Lowercased names removed when a hop changes origin
+    cross_origin_strip: Vec<String>,
+}
+
+impl Default for RedirectPolicy {
+    fn default() -> Self {
+        RedirectPolicy {
+            max_redirects: DEFAULT_MAX_REDIRECTS,
+            referrer_policy: ReferrerPolicy::StrictOriginWhenCrossOrigin,
+            allow_https_downgrade: true,
+            detect_loops: true,
+            cross_origin_strip: vec!["authorization".to_string(), "cookie".to_string()],
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl RedirectPolicy {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> RedirectPolicy {
+        RedirectPolicy::default()
+    }
+
+    /// Redirects followed before giving up (default 20; 0 follows none).
+    pub fn set_max_redirects(&mut self, max: usize) {
+        self.max_redirects = max;
+    }
+
+    /// Referrer policy applied to the Referer header on every hop, until a response
+    /// sets its own with Referrer-Policy (default "strict-origin-when-cross-origin").
+    pub fn set_referrer_policy(&mut self, policy: &str) -> Result<(), Error> {
+        self.referrer_policy =
+            ReferrerPolicy::parse(policy).ok_or_else(|| Error::InvalidOption {
+                option: "referrer policy",
+                value: policy.to_string(),
+            })?;
+        Ok(())
+    }
+
+    /// Follow redirects from https to http (on by default, as in browsers).
+    pub fn set_allow_https_downgrade(&mut self, allow: bool) {
+        self.allow_https_downgrade = allow;
+    }
+
+    /// Fail as soon as a request repeats, rather than at the hop limit (on by default).
+    /// Turn off for sites that redirect back to the same URL after setting a cookie.
+    pub fn set_detect_loops(&mut self, enabled: bool) {
+        self.detect_loops = enabled;
+    }
+
+    /// Also remove header `name` when a redirect leads to another origin, on top of
+    /// Authorization and Cookie. Re-add cookies for the new URL from a `CookieJar`.
+    pub fn strip_on_cross_origin(&mut self, name: &str) {
+        self.cross_origin_strip.push(name.to_ascii_lowercase());
+    }
+}
+
+/// The request to send after a redirect.
+#[wasm_bindgen]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RedirectStep {
+    status: u16,
+    method: String,
+    url: HttpUrl,
+    new_connection: bool,
+    keeps_body: bool,
+    headers: Vec<(String, String)>,
+}
+
+#[wasm_bindgen]
+impl RedirectStep {
+    /// Redirect status that led here.
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    pub fn method(&self) -> String {
+        self.method.clone()
+    }
+
+    /// Absolute URL of the next request, fragment included.
+    pub fn url(&self) -> String {
+        self.url.to_string()
+    }
+
+    /// Host to connect to; IPv6 literals keep their brackets.
+    pub fn host(&self) -> String {
+        self.url.host.clone()
+    }
+
+    pub fn port(&self) -> u16 {
+        self.url.port
+    }
+
+    /// Whether the next request goes over TLS.
+    pub fn secure(&self) -> bool {
+        self.url.secure
+    }
+
+    /// Request target (path and query) for `RequestBuilder::new` or `:path`.
+    pub fn target(&self) -> String {
+        self.url.target()
+    }
+
+    /// Host header / `:authority` value.
+    pub fn authority(&self) -> String {
+        self.url.authority()
+    }
+
+    /// The origin changed, so the current connection cannot carry the request.
+    pub fn new_connection(&self) -> bool {
+        self.new_connection
+    }
+
+    /// Send the original body again (307/308); false when it was dropped.
+    pub fn keeps_body(&self) -> bool {
+        self.keeps_body
+    }
+
+    /// Request headers for the next hop, in the original order and casing.
+    pub fn header_names(&self) -> Vec<String> {
+        self.headers.iter().map(|(n, _)| n.clone()).collect()
+    }
+
+    pub fn header_values(&self) -> Vec<String> {
+        self.headers.iter().map(|(_, v)| v.clone()).collect()
+    }
+}
+
+/// Follows the redirects of one request.
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct RedirectController {
+    policy: RedirectPolicy,
+    referrer_policy: ReferrerPolicy,
+    method: String,
+    url: HttpUrl,
+    headers: Vec<(String, String)>,
+    has_body: bool,
+    /// The request's Referer, which stays the referrer on every hop
+    referrer: Option<HttpUrl>,
+    /// Method and URL of every request sent, the first included
+    chain: Vec<(String, HttpUrl)>,
+    /// Known HSTS hosts and the time (unix seconds) to judge expiry by
+    hsts: Option<(HstsStore, i64)>,
+}
+
+#[wasm_bindgen]
+impl RedirectController {
+    /// Start from the first request: its method, absolute URL, headers and whether
+    /// it has a body.
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        policy: &RedirectPolicy,
+        method: &str,
+        url: &str,
+        names: Vec<String>,
+        values: Vec<String>,
+        has_body: bool,
+    ) -> Result<RedirectController, Error> {
+        if names.len() != values.len() {
+            return Err(Error::HeaderCountMismatch);
+        }
+        let url = HttpUrl::parse(url)?;
+        let headers: Vec<(String, String)> = names.into_iter().zip(values).collect();
+        let referrer = headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case("referer"))
+            .and_then(|(_, v)| HttpUrl::parse(v).ok());
+        Ok(RedirectController {
+            policy: policy.clone(),
+            referrer_policy: policy.referrer_policy,
+            method: method.to_string(),
+            chain: vec![(method.to_string(), url.clone())],
+            url,
+            headers,
+            has_body,
+            referrer,
+            hsts: None,
+        })
+    }
+
+    /// Upgrade hops to known HSTS hosts to https, on port 443 rather than 80.
+    /// Strict-Transport-Security headers of the responses fed in are recorded in
+    /// the controller's copy of `store`, read back with `hsts_store`. An upgraded
+    /// hop is not a downgrade, so `set_allow_https_downgrade(false)` lets it through.
+    pub fn set_hsts(&mut self, store: &HstsStore, now: f64) {
+        self.hsts = Some((store.clone(), now as i64));
+    }
+
+    /// The store given to `set_hsts`, with the policies seen since.
+    pub fn hsts_store(&self) -> Option<HstsStore> {
+        self.hsts.as_ref().map(|(store, _)| store.clone())
+    }
+
+    /// Feed the status and headers of the latest response. Returns null when it is
+    /// the final response (not 301/302/303/307/308, or no Location), otherwise the
+    /// next request, which the controller then treats as sent.
+    pub fn on_response(
+        &mut self,
+        status: u16,
+        names: Vec<String>,
+        values: Vec<String>,
+    ) -> Result<Option<RedirectStep>, Error> {
+        if names.len() != values.len() {
+            return Err(Error::HeaderCountMismatch);
+        }
+        let mut headers = Headers::new();
+        for (name, value) in names.into_iter().zip(values) {
+            headers.push(name, value);
+        }
+        self.follow(status, &headers)
+    }
+
+    /// Redirects followed so far.
+    pub fn redirects(&self) -> usize {
+        self.chain.len() - 1
+    }
+
+    /// URL of the latest request.
+    pub fn url(&self) -> String {
+        self.url.to_string()
+    }
+
+    /// URL of every request so far, the first one included.
+    pub fn url_chain(&self) -> Vec<String> {
+        self.chain.iter().map(|(_, url)| url.to_string()).collect()
+    }
+}
+
+impl RedirectController {
+    /// `on_response` with the response headers already collected.
+    pub fn follow(
+        &mut self,
+        status: u16,
+        headers: &Headers,
+    ) -> Result<Option<RedirectStep>, Error> {
+        if let Some((store, now)) = &mut self.hsts {
+            store.store_for(&self.url, headers, *now);
+        }
+        if !matches!(status, 301 | 302 | 303 | 307 | 308) {
+            return Ok(None);
+        }
+        let Some(location) = headers.get("location") else {
+            return Ok(None);
+        };
+        let mut next = self.url.join(location)?;
+        if let Some((store, now)) = &self.hsts {
+            store.upgrade_in_place(&mut next, *now);
+        }
+        if self.redirects() >= self.policy.max_redirects {
+            return Err(Error::TooManyRedirects {
+                limit: self.policy.max_redirects,
+            });
+        }
+        if self.url.secure && !next.secure && !self.policy.allow_https_downgrade {
+            return Err(Error::InsecureRedirect);
+        }
+        // The last token a browser recognizes wins
+        let policy = headers
+            .get_all("referrer-policy")
+            .flat_map(|v| v.split(','))
+            .filter_map(ReferrerPolicy::parse)
+            .last();
+        if let Some(policy) = policy {
+            self.referrer_policy = policy;
+        }
+
+        let rewrite = match status {
+            301 | 302 => self.method == "POST",
+            303 => self.method != "GET" && self.method != "HEAD",
+            _ => false,
+        };
+        let mut method = self.method.clone();
+        let mut keeps_body = self.has_body;
+        let mut request = self.headers.clone();
+        if rewrite {
+            method = "GET".to_string();
+            keeps_body = false;
+            request.retain(|(n, _)| !BODY_HEADERS.iter().any(|b| n.eq_ignore_ascii_case(b)));
+        }
+        if self.policy.detect_loops
+            && self
+                .chain
+                .iter()
+                .any(|(m, u)| *m == method && same_resource(u, &next))
+        {
+            return Err(Error::RedirectLoop);
+        }
+
+        let cross_origin = !self.url.same_origin(&next);
+        if cross_origin {
+            let strip = &self.policy.cross_origin_strip;
+            request.retain(|(n, _)| !strip.iter().any(|s| n.eq_ignore_ascii_case(s)));
+        }
+        let referer = self
+            .referrer
+            .as_ref()
+            .map(|r| self.referrer_policy.referrer(r, &next));
+        request.retain_mut(|(name, value)| {
+            if name.eq_ignore_ascii_case("host") {
+                *value = next.authority();
+            } else if name.eq_ignore_ascii_case("origin") && cross_origin {
+                // A cross-origin hop taints the request's origin (Fetch §4.4 step 11)
+                *value = "null".to_string();
+            } else if name.eq_ignore_ascii_case("referer") {
+                match &referer {
+                    Some(Some(referer)) => *value = referer.clone(),
+                    Some(None) => return false,
+                    None => {}
+                }
+            }
+            true
+        });
+
+        self.method = method.clone();
+        self.url = next.clone();
+        self.headers = request.clone();
+        self.has_body = keeps_body;
+        self.chain.push((method.clone(), next.clone()));
+        Ok(Some(RedirectStep {
+            status,
+            method,
+            url: next,
+            new_connection: cross_origin,
+            keeps_body,
+            headers: request,
+        }))
+    }
+}
+
+/// Same URL, fragments aside: those never reach the server.
+fn same_resource(a: &HttpUrl, b: &HttpUrl) -> bool {
+    a.without_fragment() == b.without_fragment()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn controller(
+        method: &str,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: bool,
+    ) -> RedirectController {
+        controller_with(&RedirectPolicy::new(), method, url, headers, body)
+    }
+
+    fn controller_with(
+        policy: &RedirectPolicy,
+        method: &str,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: bool,
+    ) -> RedirectController {
+        let names = headers.iter().map(|(n, _)| n.to_string()).collect();
+        let values = headers.iter().map(|(_, v)| v.to_string()).collect();
+        RedirectController::new(policy, method, url, names, values, body).unwrap()
+    }
+
+    fn redirect(location: &str) -> Headers {
+        let mut headers = Headers::new();
+        headers.push("Location", location);
+        headers
+    }
+
+    fn names(step: &RedirectStep) -> Vec<String> {
+        step.header_names()
+    }
+
+    const POST: &[(&str, &str)] = &[
+        ("Host", "example.com"),
+        ("Content-Type", "application/json"),
+        ("Accept", "*/*"),
+        ("Content-Length", "2"),
+    ];
+
+    #[test]
+    fn moved_and_found_turn_post_into_get() {
+        for status in [301, 302] {
+            let mut c = controller("POST", "https://example.com/form", POST, true);
+            let step = c.follow(status, &redirect("/done")).unwrap().unwrap();
+            assert_eq!(step.method(), "GET");
+            assert!(!step.keeps_body());
+            assert_eq!(names(&step), ["Host", "Accept"]);
+            assert_eq!(step.url(), "https://example.com/done");
+            assert!(!step.new_connection());
+
+            // Only POST is rewritten
+            let mut c = controller("PUT", "https://example.com/form", POST, true);
+            let step = c.follow(status, &redirect("/done")).unwrap().unwrap();
+            assert_eq!((step.method().as_str(), step.keeps_body()), ("PUT", true));
+            assert_eq!(step.header_names().len(), 4);
+        }
+    }
+
+    #[test]
+    fn see_other_turns_everything_but_head_into_get() {
+        let mut c = controller("DELETE", "https://example.com/item", POST, true);
+        let step = c.follow(303, &redirect("/items")).unwrap().unwrap();
+        assert_eq!((step.method().as_str(), step.keeps_body()), ("GET", false));
+        assert_eq!(names(&step), ["Host", "Accept"]);
+
+        let mut c = controller("HEAD", "https://example.com/item", &[], false);
+        let step = c.follow(303, &redirect("/items")).unwrap().unwrap();
+        assert_eq!(step.method(), "HEAD");
+    }
+
+    #[test]
+    fn temporary_and_permanent_keep_method_and_body() {
+        for status in [307, 308] {
+            let mut c = controller("POST", "https://example.com/upload", POST, true);
+            let step = c
+                .follow(status, &redirect("https://example.com:8443/v2/upload"))
+                .unwrap()
+                .unwrap();
+            assert_eq!(step.status(), status);
+            assert_eq!((step.method().as_str(), step.keeps_body()), ("POST", true));
+            assert_eq!(
+                step.header_values(),
+                ["example.com:8443", "application/json", "*/*", "2"]
+            );
+            assert!(step.new_connection());
+            assert_eq!((step.host().as_str(), step.port()), ("example.com", 8443));
+            assert_eq!(step.target(), "/v2/upload");
+        }
+    }
+
+    #[test]
+    fn final_responses() {
+        let mut c = controller("GET", "https://example.com/", &[], false);
+        for status in [200, 204, 300, 304, 404] {
+            assert_eq!(c.follow(status, &redirect("/x")), Ok(None));
+        }
+        assert_eq!(c.follow(302, &Headers::new()), Ok(None));
+        assert_eq!(
+            c.on_response(302, vec!["Location".into()], vec![]),
+            Err(Error::HeaderCountMismatch)
+        );
+        assert_eq!(
+            c.follow(302, &redirect("javascript:alert(1)")),
+            Err(Error::InvalidUrl)
+        );
+        assert_eq!(c.redirects(), 0);
+    }
+
+    #[test]
+    fn cross_origin_hops_strip_credentials_and_trim_the_referer() {
+        let headers = [
+            ("Host", "www.example.com"),
+            ("Authorization", "Bearer t"),
+            ("Cookie", "a=1"),
+            ("Referer", "https://www.example.com/page?secret=1"),
+            ("X-Trace", "1"),
+        ];
+        let mut policy = RedirectPolicy::new();
+        policy.strip_on_cross_origin("X-Trace");
+        let url = "https://www.example.com/start";
+        let mut c = controller_with(&policy, "GET", url, &headers, false);
+
+        let step = c.follow(302, &redirect("/next")).unwrap().unwrap();
+        assert_eq!(step.header_names().len(), 5);
+        assert_eq!(
+            step.header_values()[3],
+            "https://www.example.com/page?secret=1"
+        );
+
+        let step = c
+            .follow(302, &redirect("https://cdn.example.net/x"))
+            .unwrap()
+            .unwrap();
+        assert!(step.new_connection());
+        assert_eq!(names(&step), ["Host", "Referer"]);
+        assert_eq!(
+            step.header_values(),
+            ["cdn.example.net", "https://www.example.com/"]
+        );
+
+        // Downgrades lose the Referer under the default policy
+        let step = c
+            .follow(301, &redirect("http://cdn.example.net/y"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(names(&step), ["Host"]);
+        assert_eq!(c.url_chain().len(), 4);
+    }
+
+    #[test]
+    fn referrer_policy_from_the_response_and_the_options() {
+        let headers = [
+            ("Referer", "https://a.example/p"),
+            ("Origin", "https://a.example"),
+        ];
+        let mut c = controller("POST", "https://a.example/p", &headers, true);
+        let mut response = redirect("/q");
+        response.push("Referrer-Policy", "bogus, no-referrer");
+        let step = c.follow(307, &response).unwrap().unwrap();
+        assert_eq!(names(&step), ["Origin"]);
+
+        let mut policy = RedirectPolicy::new();
+        policy.set_referrer_policy("unsafe-url").unwrap();
+        let mut c = controller_with(&policy, "POST", "https://a.example/p", &headers, true);
+        let step = c
+            .follow(307, &redirect("http://b.example/"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(step.header_values(), ["https://a.example/p", "null"]);
+        assert!(matches!(
+            policy.set_referrer_policy("sometimes"),
+            Err(Error::InvalidOption { .. })
+        ));
+    }
+
+    #[test]
+    fn hop_limit_loops_and_downgrades() {
+        let mut policy = RedirectPolicy::new();
+        policy.set_max_redirects(2);
+        let mut c = controller_with(&policy, "GET", "https://example.com/0", &[], false);
+        assert!(c.follow(302, &redirect("/1")).unwrap().is_some());
+        assert!(c.follow(302, &redirect("/2")).unwrap().is_some());
+        assert_eq!(
+            c.follow(302, &redirect("/3")),
+            Err(Error::TooManyRedirects { limit: 2 })
+        );
+
+        let mut c = controller("GET", "https://example.com/a", &[], false);
+        c.follow(302, &redirect("/b")).unwrap();
+        assert_eq!(c.follow(302, &redirect("/a#x")), Err(Error::RedirectLoop));
+        // A different method is a different request
+        let mut c = controller("POST", "https://example.com/a", &[], true);
+        assert!(c.follow(303, &redirect("/a")).unwrap().is_some());
+
+        let mut policy = RedirectPolicy::new();
+        policy.set_detect_loops(false);
+        let mut c = controller_with(&policy, "GET", "https://example.com/a", &[], false);
+        assert!(c.follow(302, &redirect("/a")).unwrap().is_some());
+        policy.set_allow_https_downgrade(false);
+        let mut c = controller_with(&policy, "GET", "https://example.com/a", &[], false);
+        assert_eq!(
+            c.follow(302, &redirect("http://example.com/a")),
+            Err(Error::InsecureRedirect)
+        );
+    }
+
+    #[test]
+    fn hsts_hosts_are_followed_over_https() {
+        let mut policy = RedirectPolicy::new();
+        policy.set_allow_https_downgrade(false);
+        let mut c = controller_with(&policy, "GET", "https://example.com/a", &[], false);
+        c.set_hsts(&HstsStore::new(), 1_700_000_000.0);
+        let mut response = redirect("http://example.com/b");
+        response.push("Strict-Transport-Security", "max-age=60; includeSubDomains");
+        let step = c.follow(301, &response).unwrap().unwrap();
+        assert_eq!(step.url(), "https://example.com/b");
+        assert!(!step.new_connection());
+
+        let step = c
+            .follow(302, &redirect("http://www.example.com:80/c"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (step.url().as_str(), step.port()),
+            ("https://www.example.com/c", 443)
+        );
+        assert_eq!(
+            c.follow(302, &redirect("http://other.test/")),
+            Err(Error::InsecureRedirect)
+        );
+        assert!(c
+            .hsts_store()
+            .unwrap()
+            .should_upgrade("example.com", 1_700_000_000.0));
+    }
+}
@@ -0,0 +1,599 @@
+//! HTTP Message Signatures (RFC 9421) for requests: the covered components, the
+//! signature base they canonicalize to, and the Signature-Input / Signature fields.
+//!
+//! No cryptography happens here. The signature over the base comes from the
+//! caller: `HttpSigningKey` in wasm-tls, or Web Crypto over `signature_base`.
+
+use wasm_bindgen::prelude::*;
+
+use crate::base64;
+use crate::error::Error;
+use crate::headers::Headers;
+use crate::http1::{is_token, RequestBuilder};
+use crate::integrity::content_digest;
+use crate::url::{percent_decode, HttpUrl};
+
+/// One covered component: a field name or a derived component (§2.2).
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Component {
+    /// Lowercased field name, or `@method` and the like
+    name: String,
+    /// The `name` parameter of `@query-param`, as given (percent-encoded)
+    query_param: Option<String>,
+}
+
+const DERIVED: [&str; 8] = [
+    "@method",
+    "@target-uri",
+    "@authority",
+    "@scheme",
+    "@request-target",
+    "@path",
+    "@query",
+    "@query-param",
+];
+
+impl Component {
+    /// `content-type`, `@method`, or `@query-param;name="id"`, quoted or not.
+    fn parse(identifier: &str) -> Result<Component, Error> {
+        let invalid = || Error::InvalidComponent(identifier.to_string());
+        let mut parts = identifier.trim().split(';');
+        let name = parts.next().unwrap_or_default().trim();
+        let name = name
+            .strip_prefix('"')
+            .and_then(|n| n.strip_suffix('"'))
+            .unwrap_or(name)
+            .to_ascii_lowercase();
+        let mut query_param = None;
+        for parameter in parts {
+            match parameter.trim().split_once('=') {
+                Some(("name", value)) if name == "@query-param" && query_param.is_none() => {
+                    let value = value
+                        .strip_prefix('"')
+                        .and_then(|v| v.strip_suffix('"'))
+                        .filter(|v| is_sf_string(v))
+                        .ok_or_else(invalid)?;
+                    query_param = Some(value.to_string());
+                }
+                // key, sf, bs, req and tr are not supported
+                _ => return Err(invalid()),
+            }
+        }
+        let valid = if name.starts_with('@') {
+            DERIVED.contains(&name.as_str()) && (name == "@query-param") == query_param.is_some()
+        } else {
+            is_token(&name)
+        };
+        if !valid {
+            return Err(invalid());
+        }
+        Ok(Component { name, query_param })
+    }
+
+    /// The identifier as it appears in the signature base and Signature-Input.
+    fn serialize(&self, out: &mut String) {
+        out.push('"');
+        out.push_str(&self.name);
+        out.push('"');
+        if let Some(param) = &self.query_param {
+            out.push_str(";name=\"");
+            out.push_str(param);
+            out.push('"');
+        }
+    }
+
+    /// Canonical value in `method url headers` (§2.1, §2.2).
+    fn value(&self, method: &str, url: &HttpUrl, headers: &Headers) -> Result<String, Error> {
+        let value = match self.name.as_str() {
+            "@method" => method.to_string(),
+            "@target-uri" => url.without_fragment(),
+            "@authority" => url.authority(),
+            "@scheme" => (if url.secure { "https" } else { "http" }).to_string(),
+            "@request-target" => url.target(),
+            "@path" => url.path.clone(),
+            "@query" => format!("?{}", url.query.as_deref().unwrap_or_default()),
+            "@query-param" => {
+                let wanted = form_decode(self.query_param.as_deref().unwrap_or_default());
+                let mut values = url
+                    .query
+                    .as_deref()
+                    .unwrap_or_default()
+                    .split('&')
+                    .filter(|pair| !pair.is_empty())
+                    .map(|pair| pair.split_once('=').unwrap_or((pair, "")))
+                    .filter(|(name, _)| form_decode(name) == wanted);
+                match (values.next(), values.next()) {
+                    (Some((_, value)), None) => form_encode(&form_decode(value)),
+                    // Absent, or repeated: the query as a whole has to be covered
+                    _ => return Err(Error::MissingComponent(self.label())),
+                }
+            }
+            field => {
+                let lines: Vec<&str> = headers.get_all(field).map(str::trim).collect();
+                if lines.is_empty() {
+                    return Err(Error::MissingComponent(self.label()));
+                }
+                lines.join(", ")
+            }
+        };
+        Ok(value)
+    }
+
+    fn label(&self) -> String {
+        let mut out = String::new();
+        self.serialize(&mut out);
+        out
+    }
+}
+
+/// One signature to add to a request: its label, covered components and
+/// parameters (§2.3). Parameters are written in the order created, expires,
+/// keyid, alg, nonce, tag, whichever are set.
+#[wasm_bindgen]
+#[derive(Clone, Debug, PartialEq)]
+pub struct SignatureParams {
+    label: String,
+    components: Vec<Component>,
+    created: Option<i64>,
+    expires: Option<i64>,
+    keyid: Option<String>,
+    alg: Option<String>,
+    nonce: Option<String>,
+    tag: Option<String>,
+}
+
+#[wasm_bindgen]
+impl SignatureParams {
+    /// `label` names the signature in both fields (e.g. "sig1"). Each of
+    /// `components` is a lowercase field name or a derived component:
+    /// `@method`, `@target-uri`, `@authority`, `@scheme`, `@request-target`,
+    /// `@path`, `@query`, or `@query-param;name="..."` with the name as it is
+    /// percent-encoded in the URL. Field parameters (`;sf`, `;key`, ...) are not
+    /// supported.
+    #[wasm_bindgen(constructor)]
+    pub fn new(label: &str, components: Vec<String>) -> Result<SignatureParams, Error> {
+        if !is_sf_key(label) {
+            return Err(Error::InvalidSignatureParam("label"));
+        }
+        let mut parsed: Vec<Component> = Vec::with_capacity(components.len());
+        for identifier in &components {
+            let component = Component::parse(identifier)?;
+            // §2.5: each component at most once
+            if parsed.contains(&component) {
+                return Err(Error::InvalidComponent(identifier.clone()));
+            }
+            parsed.push(component);
+        }
+        Ok(SignatureParams {
+            label: label.to_string(),
+            components: parsed,
+            created: None,
+            expires: None,
+            keyid: None,
+            alg: None,
+            nonce: None,
+            tag: None,
+        })
+    }
+
+    /// `created`, in unix seconds.
+    pub fn set_created(&mut self, created: f64) {
+        self.created = Some(created as i64);
+    }
+
+    /// `expires`, in unix seconds.
+    pub fn set_expires(&mut self, expires: f64) {
+        self.expires = Some(expires as i64);
+    }
+
+    pub fn set_keyid(&mut self, keyid: &str) -> Result<(), Error> {
+        self.keyid = Some(sf_string(keyid, "keyid")?);
+        Ok(())
+    }
+
+    /// `alg`, e.g. "ed25519"; a signing key refuses parameters naming another.
+    pub fn set_alg(&mut self, alg: &str) -> Result<(), Error> {
+        self.alg = Some(sf_string(alg, "alg")?);
+        Ok(())
+    }
+
+    pub fn set_nonce(&mut self, nonce: &str) -> Result<(), Error> {
+        self.nonce = Some(sf_string(nonce, "nonce")?);
+        Ok(())
+    }
+
+    pub fn set_tag(&mut self, tag: &str) -> Result<(), Error> {
+        self.tag = Some(sf_string(tag, "tag")?);
+        Ok(())
+    }
+
+    /// The covered components and parameters as an inner list, e.g.
+    /// `("@method" "@path");created=1618884473;keyid="k"`.
+    pub fn signature_params(&self) -> String {
+        let mut out = String::from("(");
+        for (i, component) in self.components.iter().enumerate() {
+            if i > 0 {
+                out.push(' ');
+            }
+            component.serialize(&mut out);
+        }
+        out.push(')');
+        for (name, value) in [("created", self.created), ("expires", self.expires)] {
+            if let Some(value) = value {
+                out.push_str(&format!(";{}={}", name, value));
+            }
+        }
+        for (name, value) in [
+            ("keyid", &self.keyid),
+            ("alg", &self.alg),
+            ("nonce", &self.nonce),
+            ("tag", &self.tag),
+        ] {
+            if let Some(value) = value {
+                out.push_str(&format!(";{}=\"{}\"", name, value));
+            }
+        }
+        out
+    }
+
+    /// The signature base (§2.5) of a request to `url` with the given header
+    /// fields, for signing it elsewhere (e.g. Web Crypto) and passing the result
+    /// to `signature_header`.
+    pub fn signature_base(
+        &self,
+        method: &str,
+        url: &str,
+        names: Vec<String>,
+        values: Vec<String>,
+    ) -> Result<String, Error> {
+        if names.len() != values.len() {
+            return Err(Error::HeaderCountMismatch);
+        }
+        let mut headers = Headers::new();
+        for (name, value) in names.into_iter().zip(values) {
+            headers.push(name, value);
+        }
+        self.base(method, &HttpUrl::parse(url)?, &headers)
+    }
+
+    /// Signature-Input value: `label=` and `signature_params`.
+    pub fn signature_input_header(&self) -> String {
+        format!("{}={}", self.label, self.signature_params())
+    }
+
+    /// Signature value carrying `signature` under the label.
+    pub fn signature_header(&self, signature: &[u8]) -> String {
+        format!("{}=:{}:", self.label, base64::encode(signature))
+    }
+}
+
+impl SignatureParams {
+    pub fn alg(&self) -> Option<&str> {
+        self.alg.as_deref()
+    }
+
+    /// `signature_base` with the URL parsed and the headers collected.
+    pub(crate) fn base(
+        &self,
+        method: &str,
+        url: &HttpUrl,
+        headers: &Headers,
+    ) -> Result<String, Error> {
+        let mut out = String::new();
+        for component in &self.components {
+            component.serialize(&mut out);
+            out.push_str(": ");
+            out.push_str(&component.value(method, url, headers)?);
+            out.push('\n');
+        }
+        out.push_str("\"@signature-params\": ");
+        out.push_str(&self.signature_params());
+        Ok(out)
+    }
+
+    /// Sign `request` to `url` with `sign` and append the Signature-Input and
+    /// Signature headers. When `content-digest` is covered and the request has
+    /// none, a SHA-256 Content-Digest of the body is added first.
+    pub fn sign_request<E: From<Error>>(
+        &self,
+        request: &mut RequestBuilder,
+        url: &str,
+        sign: impl FnOnce(&[u8]) -> Result<Vec<u8>, E>,
+    ) -> Result<(), E> {
+        let url = HttpUrl::parse(url)?;
+        let wants_digest = self.components.iter().any(|c| c.name == "content-digest");
+        if wants_digest && request.header_all("content-digest").is_empty() {
+            let digest = content_digest(request.body());
+            request.header("Content-Digest", &digest)?;
+        }
+        let base = self.base(request.method(), &url, &request.fields())?;
+        let signature = sign(base.as_bytes())?;
+        request.header("Signature-Input", &self.signature_input_header())?;
+        request.header("Signature", &self.signature_header(&signature))?;
+        Ok(())
+    }
+}
+
+/// The value as an sf-string's content, with `\` and `"` escaped; printable
+/// ASCII only.
+fn sf_string(value: &str, param: &'static str) -> Result<String, Error> {
+    if !value.bytes().all(|b| (0x20..0x7f).contains(&b)) {
+        return Err(Error::InvalidSignatureParam(param));
+    }
+    Ok(value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Content of an sf-string as written: printable ASCII with `\` and `"` only escaped.
+fn is_sf_string(value: &str) -> bool {
+    let mut escaped = false;
+    value.bytes().all(|b| match b {
+        _ if escaped => {
+            escaped = false;
+            b == b'\\' || b == b'"'
+        }
+        b'\\' => {
+            escaped = true;
+            true
+        }
+        b'"' => false,
+        b => (0x20..0x7f).contains(&b),
+    }) && !escaped
+}
+
+/// sf-key (RFC 8941 §3.1.2): a lowercase letter or `*`, then `a-z0-9_-.*`.
+fn is_sf_key(key: &str) -> bool {
+    key.bytes()
+        .next()
+        .is_some_and(|b| b.is_ascii_lowercase() || b == b'*')
+        && key
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b"_-.*".contains(&b))
+}
+
+/// application/x-www-form-urlencoded parsing of one name or value.
+fn form_decode(value: &str) -> Vec<u8> {
+    percent_decode(&value.replace('+', " "))
+}
+
+/// §2.2.8: percent-encode everything outside the form-urlencoded safe set,
+/// spaces included.
+fn form_encode(value: &[u8]) -> String {
+    let mut out = String::with_capacity(value.len());
+    for &b in value {
+        if b.is_ascii_alphanumeric() || b"*-._".contains(&b) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(components: &[&str]) -> SignatureParams {
+        let components = components.iter().map(|c| c.to_string()).collect();
+        SignatureParams::new("sig1", components).unwrap()
+    }
+
+    fn base(params: &SignatureParams, method: &str, url: &str, fields: &[(&str, &str)]) -> String {
+        let mut headers = Headers::new();
+        for (name, value) in fields {
+            headers.push(*name, *value);
+        }
+        let url = HttpUrl::parse(url).unwrap();
+        params.base(method, &url, &headers).unwrap()
+    }
+
+    /// §2.1: field values are trimmed, and repeated lines joined with ", ".
+    #[test]
+    fn field_values_are_combined() {
+        let fields = [
+            ("Host", "www.example.com"),
+            ("X-OWS-Header", "   Leading and trailing whitespace.   "),
+            ("Cache-Control", "max-age=60"),
+            ("Cache-Control", "   must-revalidate"),
+            ("Example-Dict", " a=1,    b=2;x=1;y=2,   c=(a   b   c)"),
+        ];
+        let p = params(&["host", "x-ows-header", "cache-control", "example-dict"]);
+        assert_eq!(
+            base(&p, "GET", "https://www.example.com/", &fields),
+            "\"host\": www.example.com\n\
+             \"x-ows-header\": Leading and trailing whitespace.\n\
+             \"cache-control\": max-age=60, must-revalidate\n\
+             \"example-dict\": a=1,    b=2;x=1;y=2,   c=(a   b   c)\n\
+             \"@signature-params\": (\"host\" \"x-ows-header\" \"cache-control\" \"example-dict\")"
+        );
+        let missing = params(&["x-missing"]);
+        let url = HttpUrl::parse("https://example.com/").unwrap();
+        assert_eq!(
+            missing.base("GET", &url, &Headers::new()),
+            Err(Error::MissingComponent("\"x-missing\"".into()))
+        );
+    }
+
+    /// §2.2 examples for the derived components.
+    #[test]
+    fn derived_components() {
+        let p = params(&[
+            "@method",
+            "@target-uri",
+            "@authority",
+            "@scheme",
+            "@request-target",
+            "@path",
+            "@query",
+        ]);
+        let lines: Vec<String> = base(&p, "POST", "https://WWW.example.com/path?param=value", &[])
+            .lines()
+            .map(String::from)
+            .collect();
+        assert_eq!(
+            lines[..7],
+            [
+                "\"@method\": POST",
+                "\"@target-uri\": https://www.example.com/path?param=value",
+                "\"@authority\": www.example.com",
+                "\"@scheme\": https",
+                "\"@request-target\": /path?param=value",
+                "\"@path\": /path",
+                "\"@query\": ?param=value",
+            ]
+        );
+        let query = params(&["@query"]);
+        assert!(base(&query, "GET", "http://example.com:8080/", &[]).starts_with("\"@query\": ?\n"));
+    }
+
+    /// §2.2.8: names and values are compared decoded and written re-encoded.
+    #[test]
+    fn query_parameters() {
+        let p = params(&[
+            "@query-param;name=\"baz\"",
+            "\"@query-param\";name=\"qux\"",
+            "@query-param;name=\"param\"",
+        ]);
+        assert_eq!(
+            base(
+                &p,
+                "GET",
+                "https://example.com/path?param=value&foo=bar&baz=batman&qux=",
+                &[]
+            ),
+            "\"@query-param\";name=\"baz\": batman\n\
+             \"@query-param\";name=\"qux\": \n\
+             \"@query-param\";name=\"param\": value\n\
+             \"@signature-params\": (\"@query-param\";name=\"baz\" \
+             \"@query-param\";name=\"qux\" \"@query-param\";name=\"param\")"
+        );
+        let p = params(&[
+            "@query-param;name=\"var\"",
+            "@query-param;name=\"bar\"",
+            "@query-param;name=\"fa%C3%A7ade%22%3A%20\"",
+        ]);
+        let url = "https://example.com/parameters?var=this%20is%20a%20big%0Avalue\
+                   &bar=with+plus+whitespace&fa%C3%A7ade%22%3A%20=something";
+        let lines: Vec<String> = base(&p, "GET", url, &[])
+            .lines()
+            .map(String::from)
+            .collect();
+        assert_eq!(
+            lines[..3],
+            [
+                "\"@query-param\";name=\"var\": this%20is%20a%20big%0Avalue",
+                "\"@query-param\";name=\"bar\": with%20plus%20whitespace",
+                "\"@query-param\";name=\"fa%C3%A7ade%22%3A%20\": something",
+            ]
+        );
+        let repeated = params(&["@query-param;name=\"a\""]);
+        let url = HttpUrl::parse("https://example.com/?a=1&a=2").unwrap();
+        assert!(matches!(
+            repeated.base("GET", &url, &Headers::new()),
+            Err(Error::MissingComponent(_))
+        ));
+    }
+
+    /// The Ed25519 example of §B.2.6, up to the signature itself.
+    #[test]
+    fn signs_a_request_builder() {
+        let fields = [
+            "date",
+            "@method",
+            "@path",
+            "@authority",
+            "content-type",
+            "content-length",
+        ];
+        let mut p = params(&fields);
+        p.label = "sig-b26".into();
+        p.set_created(1618884473.0);
+        p.set_keyid("test-key-ed25519").unwrap();
+        let mut request = RequestBuilder::new("POST", "/foo?param=Value&Pet=dog").unwrap();
+        request.set_host("example.com").unwrap();
+        request
+            .header("Date", "Tue, 20 Apr 2021 02:07:55 GMT")
+            .unwrap();
+        request.header("Content-Type", "application/json").unwrap();
+        request.set_body(b"{\"hello\": \"world\"}".to_vec());
+
+        let mut signed = None;
+        p.sign_request(
+            &mut request,
+            "https://example.com/foo?param=Value&Pet=dog",
+            |base| {
+                signed = Some(String::from_utf8(base.to_vec()).unwrap());
+                Ok::<_, Error>(vec![1, 2, 3])
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            signed.unwrap(),
+            "\"date\": Tue, 20 Apr 2021 02:07:55 GMT\n\
+             \"@method\": POST\n\
+             \"@path\": /foo\n\
+             \"@authority\": example.com\n\
+             \"content-type\": application/json\n\
+             \"content-length\": 18\n\
+             \"@signature-params\": (\"date\" \"@method\" \"@path\" \"@authority\" \
+             \"content-type\" \"content-length\");created=1618884473;keyid=\"test-key-ed25519\""
+        );
+        let bytes = String::from_utf8(request.to_bytes().unwrap()).unwrap();
+        assert!(bytes.contains(
+            "\r\nSignature-Input: sig-b26=(\"date\" \"@method\" \"@path\" \"@authority\" \
+             \"content-type\" \"content-length\");created=1618884473;keyid=\"test-key-ed25519\"\r\n\
+             Signature: sig-b26=:AQID:\r\n"
+        ));
+    }
+
+    #[test]
+    fn content_digest_is_added_when_covered() {
+        let p = params(&["content-digest"]);
+        let mut request = RequestBuilder::new("POST", "/").unwrap();
+        request.set_host("example.com").unwrap();
+        request.set_body(b"{\"hello\": \"world\"}".to_vec());
+        p.sign_request(&mut request, "https://example.com/", |_| {
+            Ok::<_, Error>(vec![])
+        })
+        .unwrap();
+        let bytes = String::from_utf8(request.to_bytes().unwrap()).unwrap();
+        assert!(bytes.contains(
+            "\r\nContent-Digest: sha-256=:X48E9qOokqqrvdts8nOJRJN3OWDUoyWxBf7kbu9DBPE=:\r\n"
+        ));
+    }
+
+    #[test]
+    fn invalid_identifiers_and_params() {
+        for bad in [
+            "@status",
+            "@signature-params",
+            "@query-param",
+            "@method;name=\"x\"",
+            "example-dict;sf",
+            "bad header",
+        ] {
+            assert_eq!(
+                SignatureParams::new("sig1", vec![bad.to_string()]),
+                Err(Error::InvalidComponent(bad.to_string())),
+                "{}",
+                bad
+            );
+        }
+        assert_eq!(
+            SignatureParams::new("sig1", vec!["date".into(), "Date".into()]),
+            Err(Error::InvalidComponent("Date".into()))
+        );
+        assert_eq!(
+            SignatureParams::new("Sig", vec![]),
+            Err(Error::InvalidSignatureParam("label"))
+        );
+        let mut p = params(&[]);
+        assert_eq!(
+            p.set_nonce("a\nb"),
+            Err(Error::InvalidSignatureParam("nonce"))
+        );
+        p.set_tag("say \"hi\"").unwrap();
+        p.set_expires(10.0);
+        assert_eq!(p.signature_params(), "();expires=10;tag=\"say \\\"hi\\\"\"");
+    }
+}
@@ -0,0 +1,206 @@
+//! Sans-IO SOCKS client handshakes: SOCKS5, SOCKS4/4a for legacy proxies, and
+//! `SocksClient`, which tries the first and falls back to the second.
+
+mod socks4;
+mod socks5;
+
+use wasm_bindgen::prelude::*;
+
+use crate::error::Error;
+
+pub use socks4::Socks4Client;
+pub use socks5::Socks5Client;
+
+/// The handshake in progress.
+#[derive(Debug)]
+enum Active {
+    Socks5(Socks5Client),
+    Socks4(Socks4Client),
+}
+
+/// A SOCKS client for a proxy whose version is unknown: starts with SOCKS5 and,
+/// if the proxy answers the greeting with anything but version 5, as SOCKS4-only
+/// appliances do, falls back to SOCKS4a (plain SOCKS4 for an IPv4 destination).
+/// `protocol` says which one is in use.
+///
+/// Driven like `Socks5Client`, with one more state: on fallback `feed` returns true
+/// and `state` is "reconnect". A proxy that got a version it does not speak
+/// closes the connection, so close it, open a new one and send `take_outgoing`
+/// there; bytes still arriving on the old connection are ignored until then.
+///
+/// SOCKS4 has no passwords: after a fallback the username goes out as the user id
+/// and the password is dropped. An IPv6 destination cannot fall back, and the
+/// fallback fails with the SOCKS4 error.
+#[wasm_bindgen]
+#[derive(Debug)]
+pub struct SocksClient {
+    host: String,
+    port: u16,
+    user_id: String,
+    active: Active,
+    /// Fallen back, with the SOCKS4 request not yet taken
+    reconnect: bool,
+}
+
+#[wasm_bindgen]
+impl SocksClient {
+    /// Connect to `host:port` (see `Socks5Client::new`) without authentication.
+    #[wasm_bindgen(constructor)]
+    pub fn new(host: &str, port: u16) -> Result<SocksClient, Error> {
+        Ok(SocksClient::start(
+            host,
+            port,
+            "",
+            Socks5Client::new(host, port)?,
+        ))
+    }
+
+    /// Connect to `host:port` with a username and password for SOCKS5
+    /// (see `Socks5Client::with_credentials`); SOCKS4 gets the username alone.
+    pub fn with_credentials(
+        host: &str,
+        port: u16,
+        username: &str,
+        password: &str,
+    ) -> Result<SocksClient, Error> {
+        let client = Socks5Client::with_credentials(host, port, username, password)?;
+        Ok(SocksClient::start(host, port, username, client))
+    }
+
+    /// Bytes to send to the proxy (moved out; empty afterwards). In the
+    /// "reconnect" state, the SOCKS4 request for the new connection.
+    pub fn take_outgoing(&mut self) -> Vec<u8> {
+        self.reconnect = false;
+        match &mut self.active {
+            Active::Socks5(client) => client.take_outgoing(),
+            Active::Socks4(client) => client.take_outgoing(),
+        }
+    }
+
+    /// Feed bytes received from the proxy. Returns true if there is something to
+    /// send (`take_outgoing`), which after a fallback goes on a new connection.
+    pub fn feed(&mut self, data: &[u8]) -> Result<bool, Error> {
+        if self.reconnect {
+            return Ok(true);
+        }
+        let client = match &mut self.active {
+            Active::Socks5(client) => client,
+            Active::Socks4(client) => return client.feed(data),
+        };
+        match client.feed(data) {
+            Err(_) if client.wrong_version() => {
+                let client = Socks4Client::new(&self.host, self.port, &self.user_id)?;
+                self.active = Active::Socks4(client);
+                self.reconnect = true;
+                Ok(true)
+            }
+            result => result,
+        }
+    }
+
+    /// Tell the client the proxy closed the connection: an error unless the tunnel
+    /// was established, the handshake had already failed, or the connection is
+    /// being replaced after a fallback.
+    pub fn transport_closed(&mut self) -> Result<(), Error> {
+        if self.reconnect {
+            return Ok(());
+        }
+        match &mut self.active {
+            Active::Socks5(client) => client.transport_closed(),
+            Active::Socks4(client) => client.transport_closed(),
+        }
+    }
+
+    /// "reconnect" after a fallback, until `take_outgoing`; otherwise the state of
+    /// the handshake in use (see `Socks5Client::state` and `Socks4Client::state`).
+    pub fn state(&self) -> String {
+        match &self.active {
+            _ if self.reconnect => "reconnect".to_string(),
+            Active::Socks5(client) => client.state(),
+            Active::Socks4(client) => client.state(),
+        }
+    }
+
+    /// "socks5", or "socks4a" / "socks4" after a fallback.
+    pub fn protocol(&self) -> String {
+        match &self.active {
+            Active::Socks5(_) => "socks5".to_string(),
+            Active::Socks4(client) => client.protocol(),
+        }
+    }
+
+    /// Reply code of the handshake in use, once its reply has arrived.
+    pub fn reply_code(&self) -> Option<u8> {
+        match &self.active {
+            Active::Socks5(client) => client.reply_code(),
+            Active::Socks4(client) => client.reply_code(),
+        }
+    }
+
+    /// Take the bytes that arrived after the proxy's reply: the start of the
+    /// origin's side of the tunnel, for the TLS connection.
+    pub fn take_tunnel_data(&mut self) -> Vec<u8> {
+        match &mut self.active {
+            Active::Socks5(client) => client.take_tunnel_data(),
+            Active::Socks4(client) => client.take_tunnel_data(),
+        }
+    }
+}
+
+impl SocksClient {
+    fn start(host: &str, port: u16, user_id: &str, client: Socks5Client) -> SocksClient {
+        SocksClient {
+            host: host.to_string(),
+            port,
+            user_id: user_id.to_string(),
+            active: Active::Socks5(client),
+            reconnect: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn falls_back_to_socks4a() {
+        let mut client = SocksClient::with_credentials("example.com", 443, "fred", "pw").unwrap();
+        assert_eq!(client.protocol(), "socks5");
+        assert_eq!(client.take_outgoing(), [5, 2, 0, 2]);
+        // A SOCKS4 proxy reads the greeting as a request and rejects it
+        assert!(client.feed(&[0, 91]).unwrap());
+        assert_eq!(client.state(), "reconnect");
+        assert_eq!(client.protocol(), "socks4a");
+        assert!(client.feed(&[0, 0, 0, 0, 0, 0]).unwrap());
+        assert_eq!(client.transport_closed(), Ok(()));
+        assert_eq!(
+            client.take_outgoing(),
+            b"\x04\x01\x01\xbb\x00\x00\x00\x01fred\x00example.com\x00"
+        );
+        assert_eq!(client.state(), "connecting");
+        assert!(!client.feed(&[0, 90, 0, 0, 0, 0, 0, 0, 0x16]).unwrap());
+        assert_eq!(client.state(), "established");
+        assert_eq!(client.reply_code(), Some(90));
+        assert_eq!(client.take_tunnel_data(), [0x16]);
+
+        // SOCKS5 proxies keep SOCKS5, refusals included
+        let mut client = SocksClient::new("192.0.2.1", 443).unwrap();
+        client.take_outgoing();
+        client.feed(&[5, 0]).unwrap();
+        client.take_outgoing();
+        assert!(client.feed(&[5, 2, 0, 1, 0, 0, 0, 0, 0, 0]).is_err());
+        assert_eq!(client.state(), "failed");
+        assert_eq!(client.protocol(), "socks5");
+
+        let mut client = SocksClient::new("2001:db8::1", 443).unwrap();
+        client.take_outgoing();
+        assert_eq!(
+            client.feed(b"HTTP/1.1 400 Bad Request\r\n"),
+            Err(Error::Socks4(
+                "IPv6 destinations are not supported by SOCKS4"
+            ))
+        );
+        assert_eq!(client.state(), "failed");
+    }
+}
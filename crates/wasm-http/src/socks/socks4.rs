@@ -0,0 +1,275 @@
+//! SOCKS4, and its 4a extension that lets the proxy resolve the name.
+
+use std::net::IpAddr;
+use std::{fmt, mem};
+
+use wasm_bindgen::prelude::*;
+
+use crate::error::Error;
+use crate::url::dns_name;
+
+const VERSION: u8 = 4;
+const CMD_CONNECT: u8 = 1;
+const GRANTED: u8 = 90;
+/// VN, CD, DSTPORT and DSTIP of the reply
+const REPLY_LEN: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Waiting for the reply to CONNECT
+    Connecting,
+    Established,
+    Failed,
+}
+
+/// Sans-IO SOCKS4 client, for legacy proxies that speak nothing newer: one
+/// CONNECT request and one 8-byte reply. A domain name is sent with the 4a
+/// extension (DSTIP 0.0.0.1, then the name), so the proxy resolves it; an IPv4
+/// address is sent as plain SOCKS4. IPv6 cannot be expressed at all.
+///
+/// Send `take_outgoing` on a fresh TCP connection to the proxy and `feed` it what
+/// comes back until `state` is "established" or "failed"; the socket is then a
+/// raw tunnel to the origin, as with `Socks5Client`.
+#[wasm_bindgen]
+pub struct Socks4Client {
+    state: State,
+    /// "socks4" or "socks4a"
+    protocol: &'static str,
+    outgoing: Vec<u8>,
+    received: Vec<u8>,
+    /// CD of the proxy's reply
+    reply: Option<u8>,
+    tunnel_data: Vec<u8>,
+    failed: Option<Error>,
+}
+
+#[wasm_bindgen]
+impl Socks4Client {
+    /// Connect to `host:port`, identifying as `user_id` (often empty; proxies that
+    /// check it compare it with identd). `host` is an IPv4 address or a domain
+    /// name, normalized as for `Socks5Client`.
+    #[wasm_bindgen(constructor)]
+    pub fn new(host: &str, port: u16, user_id: &str) -> Result<Socks4Client, Error> {
+        if user_id.len() > 255 || user_id.contains('\0') {
+            return Err(Error::Socks4(
+                "user id must be at most 255 bytes, without NUL",
+            ));
+        }
+        let mut request = vec![VERSION, CMD_CONNECT];
+        request.extend_from_slice(&port.to_be_bytes());
+        let protocol = match host.trim_matches(['[', ']']).parse::<IpAddr>() {
+            Ok(IpAddr::V4(ip)) => {
+                request.extend_from_slice(&ip.octets());
+                request.extend_from_slice(user_id.as_bytes());
+                request.push(0);
+                "socks4"
+            }
+            Ok(IpAddr::V6(_)) => {
+                return Err(Error::Socks4(
+                    "IPv6 destinations are not supported by SOCKS4",
+                ))
+            }
+            Err(_) => {
+                let name = dns_name(host).ok_or_else(|| Error::InvalidOption {
+                    option: "host",
+                    value: host.to_string(),
+                })?;
+                request.extend_from_slice(&[0, 0, 0, 1]);
+                request.extend_from_slice(user_id.as_bytes());
+                request.push(0);
+                request.extend_from_slice(name.as_bytes());
+                request.push(0);
+                "socks4a"
+            }
+        };
+        Ok(Socks4Client {
+            state: State::Connecting,
+            protocol,
+            outgoing: request,
+            received: Vec::new(),
+            reply: None,
+            tunnel_data: Vec::new(),
+            failed: None,
+        })
+    }
+
+    /// Bytes to send to the proxy (moved out; empty afterwards).
+    pub fn take_outgoing(&mut self) -> Vec<u8> {
+        mem::take(&mut self.outgoing)
+    }
+
+    /// Feed bytes received from the proxy; the reply may arrive in pieces. Returns
+    /// false, as nothing follows the request (the same signature as
+    /// `Socks5Client::feed`). Fails on a refusal or a malformed reply, and once
+    /// established.
+    pub fn feed(&mut self, data: &[u8]) -> Result<bool, Error> {
+        if let Some(e) = &self.failed {
+            return Err(e.clone());
+        }
+        if self.state == State::Established {
+            return Err(Error::Socks4(
+                "already established; feed the TLS connection",
+            ));
+        }
+        self.received.extend_from_slice(data);
+        if self.received.len() < REPLY_LEN {
+            return Ok(false);
+        }
+        let result = self.read_reply();
+        if let Err(e) = &result {
+            self.state = State::Failed;
+            self.failed = Some(e.clone());
+        }
+        result.map(|()| false)
+    }
+
+    /// Tell the client the proxy closed the connection: an error unless the tunnel
+    /// was established (or had already failed).
+    pub fn transport_closed(&mut self) -> Result<(), Error> {
+        if self.state != State::Connecting {
+            return Ok(());
+        }
+        let error = Error::Socks4("connection closed before the reply to CONNECT");
+        self.state = State::Failed;
+        self.failed = Some(error.clone());
+        Err(error)
+    }
+
+    /// "connecting", "established" or "failed".
+    pub fn state(&self) -> String {
+        match self.state {
+            State::Connecting => "connecting",
+            State::Established => "established",
+            State::Failed => "failed",
+        }
+        .to_string()
+    }
+
+    /// "socks4" for an IPv4 destination, "socks4a" for a domain name.
+    pub fn protocol(&self) -> String {
+        self.protocol.to_string()
+    }
+
+    /// CD of the proxy's reply, once it has arrived: 90 on success.
+    pub fn reply_code(&self) -> Option<u8> {
+        self.reply
+    }
+
+    /// Take the bytes that arrived after the proxy's reply: the start of the
+    /// origin's side of the tunnel, for the TLS connection.
+    pub fn take_tunnel_data(&mut self) -> Vec<u8> {
+        mem::take(&mut self.tunnel_data)
+    }
+}
+
+impl Socks4Client {
+    fn read_reply(&mut self) -> Result<(), Error> {
+        let reply: Vec<u8> = self.received.drain(..REPLY_LEN).collect();
+        // VN is 0; some proxies echo 4
+        if reply[0] != 0 && reply[0] != VERSION {
+            return Err(Error::Socks4("malformed reply to CONNECT"));
+        }
+        self.reply = Some(reply[1]);
+        if reply[1] != GRANTED {
+            return Err(Error::Socks4(reply_error(reply[1])));
+        }
+        self.tunnel_data = mem::take(&mut self.received);
+        self.state = State::Established;
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Socks4Client {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Socks4Client")
+            .field("state", &self.state)
+            .field("protocol", &self.protocol)
+            .field("reply", &self.reply)
+            .field("failed", &self.failed)
+            .finish()
+    }
+}
+
+/// What a failed reply's CD means.
+fn reply_error(code: u8) -> &'static str {
+    match code {
+        91 => "request rejected or failed",
+        92 => "request rejected: the proxy cannot reach identd on this host",
+        93 => "request rejected: identd reports a different user id",
+        _ => "unassigned reply code",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn socks4_and_4a_requests() {
+        let mut client = Socks4Client::new("192.0.2.1", 443, "fred").unwrap();
+        assert_eq!(client.protocol(), "socks4");
+        assert_eq!(
+            client.take_outgoing(),
+            b"\x04\x01\x01\xbb\xc0\x00\x02\x01fred\x00"
+        );
+
+        let mut client = Socks4Client::new("Bücher.example", 80, "").unwrap();
+        assert_eq!(client.protocol(), "socks4a");
+        assert_eq!(
+            client.take_outgoing(),
+            b"\x04\x01\x00\x50\x00\x00\x00\x01\x00xn--bcher-kva.example\x00"
+        );
+
+        for host in ["2001:db8::1", "[::1]"] {
+            assert_eq!(
+                Socks4Client::new(host, 443, "").unwrap_err().to_string(),
+                "SOCKS4 proxy: IPv6 destinations are not supported by SOCKS4"
+            );
+        }
+        assert!(Socks4Client::new("example.com", 443, "a\0b").is_err());
+        assert!(Socks4Client::new("a b", 443, "").is_err());
+    }
+
+    #[test]
+    fn fragmented_reply() {
+        let mut client = Socks4Client::new("example.com", 443, "").unwrap();
+        client.take_outgoing();
+        for b in [0, 90, 0, 0, 0, 0, 0] {
+            client.feed(&[b]).unwrap();
+            assert_eq!(client.state(), "connecting");
+        }
+        client.feed(b"\x00\x16\x03").unwrap();
+        assert_eq!(client.state(), "established");
+        assert_eq!(client.reply_code(), Some(90));
+        assert_eq!(client.take_tunnel_data(), b"\x16\x03");
+        assert!(client.feed(b"x").is_err());
+        assert_eq!(client.transport_closed(), Ok(()));
+    }
+
+    #[test]
+    fn refusals() {
+        let refuse = |reply: &[u8]| {
+            let mut client = Socks4Client::new("192.0.2.1", 443, "").unwrap();
+            let err = client.feed(reply).unwrap_err();
+            assert_eq!(client.state(), "failed");
+            err.to_string()
+        };
+        assert_eq!(
+            refuse(&[0, 91, 0, 0, 0, 0, 0, 0]),
+            "SOCKS4 proxy: request rejected or failed"
+        );
+        assert_eq!(
+            refuse(&[0, 93, 0, 0, 0, 0, 0, 0]),
+            "SOCKS4 proxy: request rejected: identd reports a different user id"
+        );
+        assert_eq!(
+            refuse(&[5, 0, 0, 0, 0, 0, 0, 0]),
+            "SOCKS4 proxy: malformed reply to CONNECT"
+        );
+
+        let mut client = Socks4Client::new("192.0.2.1", 443, "").unwrap();
+        client.feed(&[0, 90]).unwrap();
+        assert!(client.transport_closed().is_err());
+        assert_eq!(client.state(), "failed");
+    }
+}
@@ -0,0 +1,609 @@
+//! SOCKS5 (RFC 1928), with username/password authentication (RFC 1929).
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::{fmt, mem};
+
+use wasm_bindgen::prelude::*;
+
+use crate::error::Error;
+use crate::url::dns_name;
+
+const VERSION: u8 = 5;
+const NO_AUTH: u8 = 0;
+const USERNAME_PASSWORD: u8 = 2;
+const NO_ACCEPTABLE_METHOD: u8 = 0xff;
+const CMD_CONNECT: u8 = 1;
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+
+/// The error for a proxy whose first reply does not start with version 5.
+const NOT_SOCKS5: Error = Error::Socks5("not a SOCKS5 proxy");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Waiting for the proxy's method selection
+    Greeting,
+    /// Waiting for the username/password status
+    Authenticating,
+    /// Waiting for the reply to CONNECT
+    Connecting,
+    Established,
+    Failed,
+}
+
+/// Where the destination's name is resolved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Resolve {
+    /// By the caller, who passes an IP address
+    Local,
+    /// By the proxy, from the name in the CONNECT request
+    Remote,
+}
+
+/// Sans-IO SOCKS5 client: negotiates an authentication method (none, or
+/// username/password), then asks the proxy to CONNECT to the origin.
+///
+/// Send `take_outgoing` on a fresh TCP connection to the proxy and `feed` it
+/// whatever the proxy sends back, sending `take_outgoing` again whenever `feed`
+/// returns true, until `state` is "established" or "failed". Once established the
+/// socket is a raw tunnel to the origin, as with `ProxyTunnel`: the TLS handshake
+/// goes through it, starting with `take_tunnel_data`.
+///
+/// A domain name is sent to the proxy to resolve (`set_resolve("remote")`, the
+/// default), so no DNS lookup for it happens on this side.
+///
+/// Credentials only ever appear in the bytes of `take_outgoing`: errors never
+/// carry them and `Debug` leaves them out.
+#[wasm_bindgen]
+pub struct Socks5Client {
+    state: State,
+    /// ATYP, address and port of the CONNECT request
+    destination: Vec<u8>,
+    resolve: Resolve,
+    credentials: Option<(String, String)>,
+    outgoing: Vec<u8>,
+    /// Received bytes of the message being read
+    received: Vec<u8>,
+    /// REP of the proxy's reply
+    reply: Option<u8>,
+    /// BND.ADDR and BND.PORT of a successful reply
+    bound: Option<(String, u16)>,
+    /// Bytes the origin sent through the tunnel along with the reply
+    tunnel_data: Vec<u8>,
+    failed: Option<Error>,
+}
+
+#[wasm_bindgen]
+impl Socks5Client {
+    /// Connect to `host:port` through a proxy that needs no authentication.
+    /// `host` may be an IP address, sent as one, or a domain name, which the proxy
+    /// resolves. A name is sent in the ASCII form the TLS layer uses for SNI (an
+    /// IDN as Punycode), and must fit DNS: labels of 1 to 63 bytes, 253 in all.
+    #[wasm_bindgen(constructor)]
+    pub fn new(host: &str, port: u16) -> Result<Socks5Client, Error> {
+        let mut destination = match host.trim_matches(['[', ']']).parse::<IpAddr>() {
+            Ok(IpAddr::V4(ip)) => [&[ATYP_IPV4][..], &ip.octets()].concat(),
+            Ok(IpAddr::V6(ip)) => [&[ATYP_IPV6][..], &ip.octets()].concat(),
+            Err(_) => {
+                let name = dns_name(host).ok_or_else(|| Error::InvalidOption {
+                    option: "host",
+                    value: host.to_string(),
+                })?;
+                [&[ATYP_DOMAIN, name.len() as u8][..], name.as_bytes()].concat()
+            }
+        };
+        destination.extend_from_slice(&port.to_be_bytes());
+        let mut client = Socks5Client {
+            state: State::Greeting,
+            destination,
+            resolve: Resolve::Remote,
+            credentials: None,
+            outgoing: Vec::new(),
+            received: Vec::new(),
+            reply: None,
+            bound: None,
+            tunnel_data: Vec::new(),
+            failed: None,
+        };
+        client.outgoing = client.greeting();
+        Ok(client)
+    }
+
+    /// Connect to `host:port` through a proxy that may ask for a username and
+    /// password (RFC 1929); each must be 1 to 255 bytes. No authentication is
+    /// offered as well, for proxies that need none.
+    pub fn with_credentials(
+        host: &str,
+        port: u16,
+        username: &str,
+        password: &str,
+    ) -> Result<Socks5Client, Error> {
+        for value in [username, password] {
+            if value.is_empty() || value.len() > 255 {
+                return Err(Error::Socks5(
+                    "username and password must be 1 to 255 bytes",
+                ));
+            }
+        }
+        let mut client = Socks5Client::new(host, port)?;
+        client.credentials = Some((username.to_string(), password.to_string()));
+        client.outgoing = client.greeting();
+        Ok(client)
+    }
+
+    /// Where the destination's name is resolved: "remote" (the default) sends it to
+    /// the proxy; "local" states that the caller resolved it, and so only accepts a
+    /// client made with an IP address. Must be chosen before the CONNECT request
+    /// goes out.
+    pub fn set_resolve(&mut self, resolve: &str) -> Result<(), Error> {
+        let resolve = match resolve {
+            "local" => Resolve::Local,
+            "remote" => Resolve::Remote,
+            _ => {
+                return Err(Error::InvalidOption {
+                    option: "resolve",
+                    value: resolve.to_string(),
+                })
+            }
+        };
+        if !matches!(self.state, State::Greeting | State::Authenticating) {
+            return Err(Error::Socks5(
+                "resolve must be set before the CONNECT request",
+            ));
+        }
+        if resolve == Resolve::Local && self.destination[0] == ATYP_DOMAIN {
+            return Err(Error::Socks5(
+                "resolve \"local\" needs an IP address; resolve the host and pass that",
+            ));
+        }
+        self.resolve = resolve;
+        Ok(())
+    }
+
+    /// "local" or "remote" (see `set_resolve`).
+    pub fn resolve(&self) -> String {
+        match self.resolve {
+            Resolve::Local => "local",
+            Resolve::Remote => "remote",
+        }
+        .to_string()
+    }
+
+    /// Bytes to send to the proxy (moved out; empty afterwards).
+    pub fn take_outgoing(&mut self) -> Vec<u8> {
+        mem::take(&mut self.outgoing)
+    }
+
+    /// Feed bytes received from the proxy; replies may arrive in any number of
+    /// pieces. Returns true if there is something to send (`take_outgoing`).
+    /// Fails on a refusal or a malformed reply, and once established, since
+    /// everything from then on belongs to the TLS connection.
+    pub fn feed(&mut self, data: &[u8]) -> Result<bool, Error> {
+        if let Some(e) = &self.failed {
+            return Err(e.clone());
+        }
+        if self.state == State::Established {
+            return Err(Error::Socks5(
+                "already established; feed the TLS connection",
+            ));
+        }
+        self.received.extend_from_slice(data);
+        let result = self.advance();
+        if let Err(e) = &result {
+            self.state = State::Failed;
+            self.failed = Some(e.clone());
+        }
+        result.map(|()| !self.outgoing.is_empty())
+    }
+
+    /// Tell the client the proxy closed the connection: an error unless the tunnel
+    /// was established (or had already failed). RFC 1929 proxies close right after
+    /// rejecting credentials, some without sending the status first.
+    pub fn transport_closed(&mut self) -> Result<(), Error> {
+        let error = match self.state {
+            State::Established | State::Failed => return Ok(()),
+            State::Greeting => Error::Socks5("connection closed before the method selection"),
+            State::Authenticating => Error::Socks5(
+                "connection closed during authentication; the credentials were likely rejected",
+            ),
+            State::Connecting => Error::Socks5("connection closed before the reply to CONNECT"),
+        };
+        self.state = State::Failed;
+        self.failed = Some(error.clone());
+        Err(error)
+    }
+
+    /// "greeting", "authenticating", "connecting", "established" or "failed".
+    pub fn state(&self) -> String {
+        match self.state {
+            State::Greeting => "greeting",
+            State::Authenticating => "authenticating",
+            State::Connecting => "connecting",
+            State::Established => "established",
+            State::Failed => "failed",
+        }
+        .to_string()
+    }
+
+    /// REP of the proxy's reply to CONNECT, once it has arrived: 0 on success.
+    pub fn reply_code(&self) -> Option<u8> {
+        self.reply
+    }
+
+    /// The address the proxy connected from, as it reported it ("host:port",
+    /// with an IPv6 address in brackets), once established; for diagnostics.
+    pub fn bound_address(&self) -> Option<String> {
+        let (host, port) = self.bound.as_ref()?;
+        match host.contains(':') {
+            true => Some(format!("[{}]:{}", host, port)),
+            false => Some(format!("{}:{}", host, port)),
+        }
+    }
+
+    /// Host part of `bound_address`: an IP address, or a name, without brackets.
+    pub fn bound_host(&self) -> Option<String> {
+        self.bound.as_ref().map(|(host, _)| host.clone())
+    }
+
+    /// Port part of `bound_address`.
+    pub fn bound_port(&self) -> Option<u16> {
+        self.bound.as_ref().map(|&(_, port)| port)
+    }
+
+    /// Take the bytes that arrived after the proxy's reply: the start of the
+    /// origin's side of the tunnel, for the TLS connection.
+    pub fn take_tunnel_data(&mut self) -> Vec<u8> {
+        mem::take(&mut self.tunnel_data)
+    }
+}
+
+impl Socks5Client {
+    /// Whether the proxy answered the greeting with another version than 5, as a
+    /// SOCKS4-only proxy does.
+    pub(crate) fn wrong_version(&self) -> bool {
+        self.failed == Some(NOT_SOCKS5)
+    }
+
+    fn greeting(&self) -> Vec<u8> {
+        match self.credentials {
+            Some(_) => vec![VERSION, 2, NO_AUTH, USERNAME_PASSWORD],
+            None => vec![VERSION, 1, NO_AUTH],
+        }
+    }
+
+    fn connect_request(&mut self) {
+        self.outgoing.extend_from_slice(&[VERSION, CMD_CONNECT, 0]);
+        self.outgoing.extend_from_slice(&self.destination);
+        self.state = State::Connecting;
+    }
+
+    /// Handle every complete message in `received`.
+    fn advance(&mut self) -> Result<(), Error> {
+        loop {
+            match self.state {
+                State::Greeting => {
+                    let Some([version, method]) = self.take() else {
+                        return Ok(());
+                    };
+                    if version != VERSION {
+                        return Err(NOT_SOCKS5);
+                    }
+                    match (method, &self.credentials) {
+                        (NO_AUTH, _) => self.connect_request(),
+                        (USERNAME_PASSWORD, Some((username, password))) => {
+                            self.outgoing.push(1);
+                            for value in [username, password] {
+                                self.outgoing.push(value.len() as u8);
+                                self.outgoing.extend_from_slice(value.as_bytes());
+                            }
+                            self.state = State::Authenticating;
+                        }
+                        (NO_ACCEPTABLE_METHOD, None) => {
+                            return Err(Error::Socks5("the proxy requires authentication"))
+                        }
+                        (NO_ACCEPTABLE_METHOD, Some(_)) => {
+                            return Err(Error::Socks5(
+                                "the proxy accepts none of the offered authentication methods",
+                            ))
+                        }
+                        _ => {
+                            return Err(Error::Socks5(
+                                "the proxy chose an authentication method that was not offered",
+                            ))
+                        }
+                    }
+                }
+                State::Authenticating => {
+                    // The version of this sub-negotiation is 1, though some proxies send 5
+                    let Some([_, status]) = self.take() else {
+                        return Ok(());
+                    };
+                    if status != 0 {
+                        return Err(Error::Socks5("username or password rejected"));
+                    }
+                    self.connect_request();
+                }
+                State::Connecting => {
+                    let Some(len) = self.reply_len()? else {
+                        return Ok(());
+                    };
+                    let reply = self.received.drain(..len).collect::<Vec<u8>>();
+                    self.reply = Some(reply[1]);
+                    if reply[1] != 0 {
+                        return Err(Error::Socks5(reply_error(reply[1])));
+                    }
+                    self.bound = Some(bound_address(&reply[3..]));
+                    self.tunnel_data = mem::take(&mut self.received);
+                    self.state = State::Established;
+                    return Ok(());
+                }
+                State::Established | State::Failed => return Ok(()),
+            }
+        }
+    }
+
+    /// Take the next `N` received bytes, if they have all arrived.
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let message = self.received.get(..N)?.try_into().ok()?;
+        self.received.drain(..N);
+        Some(message)
+    }
+
+    /// Length of the reply (VER REP RSV ATYP BND.ADDR BND.PORT), once enough of it
+    /// has arrived to tell.
+    fn reply_len(&self) -> Result<Option<usize>, Error> {
+        let Some(&[version, _, _, atyp]) = self.received.get(..4) else {
+            return Ok(None);
+        };
+        if version != VERSION {
+            return Err(Error::Socks5("malformed reply to CONNECT"));
+        }
+        let address = match atyp {
+            ATYP_IPV4 => 4,
+            ATYP_IPV6 => 16,
+            ATYP_DOMAIN => match self.received.get(4) {
+                Some(&len) => 1 + len as usize,
+                None => return Ok(None),
+            },
+            _ => return Err(Error::Socks5("unknown address type in the reply")),
+        };
+        let len = 4 + address + 2;
+        Ok((self.received.len() >= len).then_some(len))
+    }
+}
+
+impl fmt::Debug for Socks5Client {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Socks5Client")
+            .field("state", &self.state)
+            .field("resolve", &self.resolve)
+            .field("credentials", &self.credentials.is_some())
+            .field("reply", &self.reply)
+            .field("bound", &self.bound)
+            .field("failed", &self.failed)
+            .finish()
+    }
+}
+
+/// ATYP BND.ADDR BND.PORT as host and port.
+fn bound_address(address: &[u8]) -> (String, u16) {
+    let (address, port) = address.split_at(address.len() - 2);
+    let port = u16::from_be_bytes([port[0], port[1]]);
+    let host = match address[0] {
+        ATYP_IPV4 => {
+            let octets: [u8; 4] = address[1..].try_into().unwrap_or_default();
+            Ipv4Addr::from(octets).to_string()
+        }
+        ATYP_IPV6 => {
+            let octets: [u8; 16] = address[1..].try_into().unwrap_or_default();
+            Ipv6Addr::from(octets).to_string()
+        }
+        _ => String::from_utf8_lossy(&address[2..]).into_owned(),
+    };
+    (host, port)
+}
+
+/// What a failed reply's REP means: RFC 1928 codes, and the ones Tor adds for
+/// onion services.
+fn reply_error(code: u8) -> &'static str {
+    match code {
+        1 => "general SOCKS server failure",
+        2 => "connection not allowed by ruleset",
+        3 => "network unreachable",
+        4 => "host unreachable",
+        5 => "connection refused",
+        6 => "TTL expired",
+        7 => "command not supported",
+        8 => "address type not supported",
+        0xf0 => "onion service descriptor not found",
+        0xf1 => "onion service descriptor is invalid",
+        0xf2 => "onion service introduction failed",
+        0xf3 => "onion service rendezvous failed",
+        0xf4 => "onion service requires client authorization",
+        0xf5 => "onion service client authorization was rejected",
+        0xf6 => "invalid onion address",
+        0xf7 => "onion service introduction timed out",
+        _ => "unassigned reply code",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed `input` one byte at a time; returns whether any feed asked to send.
+    fn feed_bytes(client: &mut Socks5Client, input: &[u8]) -> Result<bool, Error> {
+        let mut wants_write = false;
+        for b in input {
+            wants_write |= client.feed(&[*b])?;
+        }
+        Ok(wants_write)
+    }
+
+    #[test]
+    fn destination_address_types() {
+        let connect = |host: &str| {
+            let mut client = Socks5Client::new(host, 443).unwrap();
+            assert_eq!(client.take_outgoing(), [5, 1, 0]);
+            client.feed(&[5, 0]).unwrap();
+            client.take_outgoing()
+        };
+        assert_eq!(connect("192.0.2.1"), [5, 1, 0, 1, 192, 0, 2, 1, 1, 187]);
+        let mut ipv6 = vec![5, 1, 0, 4, 0x20, 0x01, 0x0d, 0xb8];
+        ipv6.extend([0; 11]);
+        ipv6.extend([1, 1, 187]);
+        assert_eq!(connect("[2001:db8::1]"), ipv6);
+        assert_eq!(connect("2001:db8::1"), ipv6);
+        assert_eq!(
+            connect("Bücher.example"),
+            [&[5, 1, 0, 3, 21][..], b"xn--bcher-kva.example", &[1, 187]].concat()
+        );
+        assert!(Socks5Client::new("a b", 443).is_err());
+
+        // DNS limits: 63-byte labels, 253 bytes in all
+        let longest = [&"a".repeat(63)[..]; 4].join(".")[..253].to_string();
+        let connect_longest = connect(&longest);
+        assert_eq!(connect_longest[4], 253);
+        assert!(Socks5Client::new(&format!("{longest}a"), 443).is_err());
+        assert!(Socks5Client::new(&format!("{}.example", "a".repeat(64)), 443).is_err());
+        assert!(Socks5Client::new("a..example", 443).is_err());
+    }
+
+    #[test]
+    fn resolve_modes() {
+        let mut client = Socks5Client::new("example.com", 443).unwrap();
+        assert_eq!(client.resolve(), "remote");
+        assert!(client.set_resolve("local").is_err());
+        assert!(client.set_resolve("proxy").is_err());
+        assert_eq!(client.resolve(), "remote");
+
+        let mut client = Socks5Client::new("192.0.2.1", 443).unwrap();
+        client.set_resolve("local").unwrap();
+        assert_eq!(client.resolve(), "local");
+        assert_eq!(client.take_outgoing(), [5, 1, 0]);
+        client.feed(&[5, 0]).unwrap();
+        assert_eq!(client.take_outgoing(), [5, 1, 0, 1, 192, 0, 2, 1, 1, 187]);
+        assert_eq!(
+            client.set_resolve("remote"),
+            Err(Error::Socks5(
+                "resolve must be set before the CONNECT request"
+            ))
+        );
+    }
+
+    #[test]
+    fn username_password_and_fragmented_reply() {
+        let mut client =
+            Socks5Client::with_credentials("example.com", 443, "user", "secret").unwrap();
+        assert_eq!(client.take_outgoing(), [5, 2, 0, 2]);
+        assert!(feed_bytes(&mut client, &[5, 2]).unwrap());
+        assert_eq!(client.state(), "authenticating");
+        assert_eq!(client.take_outgoing(), b"\x01\x04user\x06secret");
+        assert!(!client.feed(&[1]).unwrap());
+        assert!(client.feed(&[0]).unwrap());
+        assert_eq!(client.state(), "connecting");
+        assert_eq!(
+            client.take_outgoing(),
+            [&[5, 1, 0, 3, 11][..], b"example.com", &[1, 187]].concat()
+        );
+        // A domain-typed reply split everywhere, the start of the tunnel right after it
+        let reply = [&[5, 0, 0, 3, 9][..], b"proxy.lan", &[0x04]].concat();
+        assert!(!feed_bytes(&mut client, &reply).unwrap());
+        assert_eq!(client.state(), "connecting");
+        assert!(!client.feed(b"\x38\x16\x03").unwrap());
+        assert_eq!(client.state(), "established");
+        assert_eq!(client.reply_code(), Some(0));
+        assert_eq!(client.bound_address().as_deref(), Some("proxy.lan:1080"));
+        assert_eq!(client.bound_host().as_deref(), Some("proxy.lan"));
+        assert_eq!(client.bound_port(), Some(1080));
+        assert_eq!(client.take_tunnel_data(), b"\x16\x03");
+        assert_eq!(
+            client.feed(b"x"),
+            Err(Error::Socks5(
+                "already established; feed the TLS connection"
+            ))
+        );
+        let debug = format!("{:?}", client);
+        assert!(
+            !debug.contains("secret") && !debug.contains("user"),
+            "{debug}"
+        );
+    }
+
+    #[test]
+    fn rejected_credentials() {
+        let mut client =
+            Socks5Client::with_credentials("example.com", 443, "user", "wrong").unwrap();
+        client.feed(&[5, 2]).unwrap();
+        let err = client.feed(&[1, 1]).unwrap_err();
+        assert_eq!(err, Error::Socks5("username or password rejected"));
+        assert!(!err.to_string().contains("wrong"));
+        assert_eq!(client.state(), "failed");
+        // The proxy closes right after; nothing more to report
+        assert_eq!(client.transport_closed(), Ok(()));
+
+        // Or closes without sending the status
+        let mut client =
+            Socks5Client::with_credentials("example.com", 443, "user", "wrong").unwrap();
+        client.feed(&[5, 2]).unwrap();
+        client.take_outgoing();
+        let err = client.transport_closed().unwrap_err();
+        assert!(err.to_string().contains("credentials were likely rejected"));
+        assert_eq!(client.feed(&[1, 0]), Err(err));
+
+        assert!(Socks5Client::with_credentials("example.com", 443, "", "x").is_err());
+        assert!(Socks5Client::with_credentials("example.com", 443, "u", &"x".repeat(256)).is_err());
+    }
+
+    #[test]
+    fn method_selection_failures() {
+        let mut client = Socks5Client::new("example.com", 443).unwrap();
+        assert_eq!(
+            client.feed(&[5, 0xff]),
+            Err(Error::Socks5("the proxy requires authentication"))
+        );
+        let mut client = Socks5Client::new("example.com", 443).unwrap();
+        assert!(client.feed(&[5, 2]).is_err());
+        let mut client = Socks5Client::new("example.com", 443).unwrap();
+        assert_eq!(
+            client.feed(&[4, 0]),
+            Err(Error::Socks5("not a SOCKS5 proxy"))
+        );
+        let mut client = Socks5Client::new("example.com", 443).unwrap();
+        assert_eq!(
+            client.transport_closed(),
+            Err(Error::Socks5(
+                "connection closed before the method selection"
+            ))
+        );
+    }
+
+    #[test]
+    fn reply_codes() {
+        let refuse = |code: u8| {
+            let mut client = Socks5Client::new("192.0.2.1", 443).unwrap();
+            client.feed(&[5, 0]).unwrap();
+            let result = client.feed(&[5, code, 0, 1, 0, 0, 0, 0, 0, 0]);
+            assert_eq!(client.reply_code(), Some(code));
+            assert_eq!(client.bound_address(), None);
+            result.unwrap_err().to_string()
+        };
+        assert_eq!(refuse(5), "SOCKS5 proxy: connection refused");
+        assert_eq!(refuse(2), "SOCKS5 proxy: connection not allowed by ruleset");
+        assert_eq!(refuse(0xf6), "SOCKS5 proxy: invalid onion address");
+        assert_eq!(refuse(0x42), "SOCKS5 proxy: unassigned reply code");
+
+        let mut client = Socks5Client::new("192.0.2.1", 443).unwrap();
+        client.feed(&[5, 0]).unwrap();
+        let mut reply = vec![5, 0, 0, 4];
+        reply.extend([0; 15]);
+        reply.extend([1, 0x1f, 0x90]);
+        client.feed(&reply).unwrap();
+        assert_eq!(client.bound_address().as_deref(), Some("[::1]:8080"));
+        assert_eq!(client.bound_host().as_deref(), Some("::1"));
+        let mut client = Socks5Client::new("192.0.2.1", 443).unwrap();
+        client.feed(&[5, 0, 5, 0, 0, 9]).unwrap_err();
+        assert_eq!(client.state(), "failed");
+    }
+}
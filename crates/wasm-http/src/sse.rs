@@ -0,0 +1,337 @@
+//! Server-sent events: incremental `text/event-stream` parsing as the HTML
+//! standard specifies it (§9.2.6).
+
+use std::collections::VecDeque;
+use std::mem;
+
+use wasm_bindgen::prelude::*;
+
+use crate::error::Error;
+
+/// Default cap on the bytes of one event block (its lines, comments included).
+pub const DEFAULT_MAX_EVENT_SIZE: usize = 1024 * 1024;
+
+const BOM: &[u8] = b"\xef\xbb\xbf";
+
+/// One dispatched event.
+#[wasm_bindgen]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SseEvent {
+    event: String,
+    data: String,
+    id: String,
+    retry: Option<u32>,
+}
+
+#[wasm_bindgen]
+impl SseEvent {
+    /// Event type; "message" unless an event field named another.
+    pub fn event(&self) -> String {
+        self.event.clone()
+    }
+
+    /// Data fields of the event, joined with "\n".
+    pub fn data(&self) -> String {
+        self.data.clone()
+    }
+
+    /// Last event ID when the event was dispatched (empty if none was set).
+    pub fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    /// Reconnection time in milliseconds last set by the stream, if any.
+    pub fn retry(&self) -> Option<u32> {
+        self.retry
+    }
+}
+
+/// Incremental `text/event-stream` parser. Feed it the decoded body in chunks
+/// split anywhere (e.g. HTTP/1 body events or HTTP/2 data) and drain events.
+#[wasm_bindgen]
+#[derive(Debug)]
+pub struct SseParser {
+    /// Bytes of the line in progress
+    line: Vec<u8>,
+    /// A leading BOM has been looked for
+    bom_checked: bool,
+    /// The last line ended with CR, so an LF right after it belongs to it
+    skip_lf: bool,
+    event: String,
+    data: String,
+    /// Last event ID buffer: set by id fields, copied to `last_event_id` on dispatch
+    id: String,
+    last_event_id: String,
+    retry: Option<u32>,
+    /// Bytes of the event block in progress
+    event_size: usize,
+    max_event_size: usize,
+    events: VecDeque<SseEvent>,
+    failed: Option<Error>,
+}
+
+impl Default for SseParser {
+    fn default() -> Self {
+        SseParser {
+            line: Vec::new(),
+            bom_checked: false,
+            skip_lf: false,
+            event: String::new(),
+            data: String::new(),
+            id: String::new(),
+            last_event_id: String::new(),
+            retry: None,
+            event_size: 0,
+            max_event_size: DEFAULT_MAX_EVENT_SIZE,
+            events: VecDeque::new(),
+            failed: None,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl SseParser {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> SseParser {
+        SseParser::default()
+    }
+
+    /// Resume after a reconnect: events keep reporting `last_event_id` until the
+    /// stream sets a new one.
+    pub fn set_last_event_id(&mut self, id: &str) {
+        self.id = id.to_string();
+        self.last_event_id = id.to_string();
+    }
+
+    /// Fail once an event block exceeds `limit` bytes (1 MiB by default), so a
+    /// stream without blank lines cannot grow the buffers without bound.
+    pub fn set_max_event_size(&mut self, limit: usize) {
+        self.max_event_size = limit;
+    }
+
+    /// Feed decoded body bytes. Events become available through `next_event`.
+    pub fn feed(&mut self, data: &[u8]) -> Result<(), Error> {
+        if let Some(e) = &self.failed {
+            return Err(e.clone());
+        }
+        let result = if self.bom_checked {
+            self.process(data)
+        } else {
+            // Hold back what could still be the start of a BOM
+            self.line.extend_from_slice(data);
+            if self.line.len() < BOM.len() && BOM.starts_with(&self.line) {
+                return Ok(());
+            }
+            self.bom_checked = true;
+            let mut pending = mem::take(&mut self.line);
+            if pending.starts_with(BOM) {
+                pending.drain(..BOM.len());
+            }
+            self.process(&pending)
+        };
+        if let Err(e) = &result {
+            self.failed = Some(e.clone());
+        }
+        result
+    }
+
+    /// Next event, or nothing if more input is needed.
+    pub fn next_event(&mut self) -> Option<SseEvent> {
+        self.events.pop_front()
+    }
+
+    /// Signal the end of the stream. An event without its terminating blank line
+    /// is discarded, as the standard requires.
+    pub fn finish(&mut self) {
+        self.line.clear();
+        self.event.clear();
+        self.data.clear();
+        self.event_size = 0;
+    }
+
+    /// ID of the last dispatched event, to send as Last-Event-ID when reconnecting
+    /// (see `RequestBuilder::add_last_event_id`).
+    pub fn last_event_id(&self) -> String {
+        self.last_event_id.clone()
+    }
+
+    /// Reconnection time in milliseconds last set by a retry field.
+    pub fn retry(&self) -> Option<u32> {
+        self.retry
+    }
+}
+
+impl SseParser {
+    /// Rust-side `next_event`.
+    pub fn poll_event(&mut self) -> Option<SseEvent> {
+        self.events.pop_front()
+    }
+
+    fn process(&mut self, input: &[u8]) -> Result<(), Error> {
+        let mut start = 0;
+        for (i, &b) in input.iter().enumerate() {
+            if mem::take(&mut self.skip_lf) && b == b'\n' {
+                start = i + 1;
+                continue;
+            }
+            if b == b'\r' || b == b'\n' {
+                let mut line = mem::take(&mut self.line);
+                line.extend_from_slice(&input[start..i]);
+                self.process_line(&line)?;
+                line.clear();
+                self.line = line;
+                self.skip_lf = b == b'\r';
+                start = i + 1;
+            }
+        }
+        self.line.extend_from_slice(&input[start..]);
+        self.check_size(self.line.len())
+    }
+
+    fn process_line(&mut self, line: &[u8]) -> Result<(), Error> {
+        if line.is_empty() {
+            self.dispatch();
+            return Ok(());
+        }
+        self.check_size(line.len())?;
+        self.event_size += line.len();
+        // Comment
+        if line[0] == b':' {
+            return Ok(());
+        }
+        let line = String::from_utf8_lossy(line);
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (&line[..], ""),
+        };
+        match field {
+            "event" => self.event = value.to_string(),
+            "data" => {
+                self.data.push_str(value);
+                self.data.push('\n');
+            }
+            "id" if !value.contains('\0') => self.id = value.to_string(),
+            "retry" if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) => {
+                // Out of range values are as invalid as any other
+                if let Ok(ms) = value.parse() {
+                    self.retry = Some(ms);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn dispatch(&mut self) {
+        self.last_event_id = self.id.clone();
+        self.event_size = 0;
+        let event = mem::take(&mut self.event);
+        if self.data.is_empty() {
+            return;
+        }
+        let mut data = mem::take(&mut self.data);
+        data.pop();
+        self.events.push_back(SseEvent {
+            event: if event.is_empty() {
+                "message".to_string()
+            } else {
+                event
+            },
+            data,
+            id: self.last_event_id.clone(),
+            retry: self.retry,
+        });
+    }
+
+    fn check_size(&self, pending: usize) -> Result<(), Error> {
+        if self.event_size + pending > self.max_event_size {
+            return Err(Error::SseEventTooLarge {
+                limit: self.max_event_size,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(parser: &mut SseParser) -> Vec<(String, String, String)> {
+        std::iter::from_fn(|| parser.poll_event())
+            .map(|e| (e.event(), e.data(), e.id()))
+            .collect()
+    }
+
+    fn event(event: &str, data: &str, id: &str) -> (String, String, String) {
+        (event.to_string(), data.to_string(), id.to_string())
+    }
+
+    #[test]
+    fn parses_fields_and_line_endings() {
+        let stream = "\u{feff}: comment\r\n\
+                      data: first\r\n\
+                      data:second\r\n\
+                      data\r\n\
+                      id: 1\r\r\
+                      event: update\ndata:  two spaces\nretry: 1500\n\n\
+                      id\nretry: soon\ndata: x\n\n\
+                      id: bad\0id\ndata\n\n\
+                      event: ignored\nid: 2\n\n\
+                      data: last\n\n\
+                      data: unterminated";
+        // Byte by byte: BOM, multibyte characters and CRLF split across chunks
+        let mut parser = SseParser::new();
+        for b in stream.as_bytes().chunks(1) {
+            parser.feed(b).unwrap();
+        }
+        let expected = vec![
+            event("message", "first\nsecond\n", "1"),
+            event("update", " two spaces", "1"),
+            event("message", "x", ""),
+            event("message", "", ""),
+            event("message", "last", "2"),
+        ];
+        assert_eq!(events(&mut parser), expected);
+        assert_eq!(parser.retry(), Some(1500));
+        assert_eq!(parser.last_event_id(), "2");
+        parser.finish();
+        parser.feed(b"\n").unwrap();
+        assert_eq!(parser.poll_event(), None);
+
+        let mut parser = SseParser::new();
+        parser.feed(stream.as_bytes()).unwrap();
+        assert_eq!(events(&mut parser), expected);
+    }
+
+    #[test]
+    fn bom_only_at_start_and_size_limit() {
+        let mut parser = SseParser::new();
+        parser.feed(b"\xef\xbb").unwrap();
+        parser
+            .feed(b"\xbfdata: a\n\n\xef\xbb\xbfdata: b\n\n")
+            .unwrap();
+        let mut parser2 = SseParser::new();
+        parser2.feed(b"\xefdata: c\n\n").unwrap();
+        assert_eq!(parser.poll_event().unwrap().data(), "a");
+        // Later BOMs are part of the field name
+        assert_eq!(parser.poll_event(), None);
+        // Neither is a broken one
+        assert_eq!(parser2.poll_event(), None);
+
+        let mut parser = SseParser::new();
+        parser.set_last_event_id("41");
+        parser.set_max_event_size(16);
+        parser.feed(b"data: 0123456789\n\n").unwrap();
+        let first = parser.poll_event().unwrap();
+        assert_eq!((first.id(), first.retry()), ("41".to_string(), None));
+        parser.feed(b"data: 01234\ndata: 56789\n").unwrap_err();
+        assert_eq!(
+            parser.feed(b"\n"),
+            Err(Error::SseEventTooLarge { limit: 16 })
+        );
+        let mut parser = SseParser::new();
+        parser.set_max_event_size(16);
+        assert!(parser.feed(&[b'x'; 17]).is_err());
+    }
+}
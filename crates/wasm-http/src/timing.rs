@@ -0,0 +1,121 @@
+//! Phase timestamps of a request, after the browser's resource timing.
+//!
+//! The library reads no clock: connections record the time last given to their
+//! `set_now`, in whatever milliseconds the caller counts (`performance.now()`,
+//! `Date.now()`). Phases that did not happen, or happened with no time set, are
+//! null rather than zero.
+
+use wasm_bindgen::prelude::*;
+
+use crate::json;
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize),
+    serde(rename_all = "camelCase")
+)]
+pub struct Timings {
+    pub(crate) queued: Option<f64>,
+    pub(crate) tls_start: Option<f64>,
+    pub(crate) tls_end: Option<f64>,
+    pub(crate) request_sent: Option<f64>,
+    pub(crate) first_byte: Option<f64>,
+    pub(crate) last_byte: Option<f64>,
+}
+
+#[wasm_bindgen]
+impl Timings {
+    /// When the request was handed to the connection.
+    pub fn queued(&self) -> Option<f64> {
+        self.queued
+    }
+
+    /// TLS handshake of the connection the request waited for; null on a
+    /// connection that was already set up.
+    pub fn tls_start(&self) -> Option<f64> {
+        self.tls_start
+    }
+
+    pub fn tls_end(&self) -> Option<f64> {
+        self.tls_end
+    }
+
+    /// When the last byte of the request, body included, was produced.
+    pub fn request_sent(&self) -> Option<f64> {
+        self.request_sent
+    }
+
+    /// When the first byte of the response arrived.
+    pub fn first_byte(&self) -> Option<f64> {
+        self.first_byte
+    }
+
+    /// When the response was complete.
+    pub fn last_byte(&self) -> Option<f64> {
+        self.last_byte
+    }
+
+    /// Time to first byte: from `request_sent` to `first_byte`.
+    pub fn ttfb(&self) -> Option<f64> {
+        Some(self.first_byte? - self.request_sent?)
+    }
+
+    /// The phases as a JSON object with camelCase keys, e.g. for logging.
+    pub fn to_json(&self) -> String {
+        let fields = [
+            ("queued", self.queued),
+            ("tlsStart", self.tls_start),
+            ("tlsEnd", self.tls_end),
+            ("requestSent", self.request_sent),
+            ("firstByte", self.first_byte),
+            ("lastByte", self.last_byte),
+        ];
+        let mut out = String::from("{");
+        for (i, (key, value)) in fields.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            json::quote(key, &mut out);
+            match value {
+                Some(ms) if ms.is_finite() => out.push_str(&format!(":{}", ms)),
+                _ => out.push_str(":null"),
+            }
+        }
+        out.push('}');
+        out
+    }
+}
+
+impl Timings {
+    /// Timings of a connection's TLS handshake alone.
+    pub fn handshake(start: Option<f64>, end: Option<f64>) -> Timings {
+        Timings {
+            tls_start: start,
+            tls_end: end,
+            ..Timings::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_has_nulls_for_missing_phases() {
+        let timings = Timings {
+            queued: Some(1.0),
+            request_sent: Some(2.5),
+            first_byte: Some(40.0),
+            ..Timings::default()
+        };
+        assert_eq!(
+            timings.to_json(),
+            r#"{"queued":1,"tlsStart":null,"tlsEnd":null,"requestSent":2.5,"firstByte":40,"lastByte":null}"#
+        );
+        assert_eq!(timings.ttfb(), Some(37.5));
+        assert_eq!(Timings::handshake(Some(1.0), None).ttfb(), None);
+    }
+}
@@ -0,0 +1,535 @@
+//! Absolute http(s) URLs and reference resolution (RFC 3986 §5.2), parsed the way
+//! the WHATWG URL standard parses special schemes: hosts are percent-decoded,
+//! mapped and Punycode-encoded by UTS #46, IPv4 numbers are canonicalized, dot
+//! segments (`%2e` included) are removed and default ports are dropped.
+//!
+//! This is not a WHATWG URL parser: only http and https are accepted, and any
+//! other scheme is an `InvalidUrl`, where the JS URL class would parse it as an
+//! opaque non-special URL.
+
+use std::fmt;
+use std::net::Ipv4Addr;
+
+use idna::AsciiDenyList;
+use wasm_bindgen::prelude::*;
+
+use crate::error::Error;
+
+/// A parsed http(s) URL. From JS, see `parse_url`.
+#[wasm_bindgen]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HttpUrl {
+    pub(crate) secure: bool,
+    /// Lowercased; IPv6 literals keep their brackets
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    /// Starts with '/'
+    pub(crate) path: String,
+    pub(crate) query: Option<String>,
+    pub(crate) fragment: Option<String>,
+}
+
+#[wasm_bindgen]
+impl HttpUrl {
+    /// "https" or "http".
+    pub fn scheme(&self) -> String {
+        if self.secure { "https" } else { "http" }.to_string()
+    }
+
+    /// ASCII host: a domain with any IDN labels Punycode-encoded, a dotted
+    /// IPv4 address, or a bracketed IPv6 literal.
+    pub fn host(&self) -> String {
+        self.host.clone()
+    }
+
+    /// The port, the scheme's default when the URL names none.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn path(&self) -> String {
+        self.path.clone()
+    }
+
+    /// Query without the '?'; null when there is no '?' at all.
+    pub fn query(&self) -> Option<String> {
+        self.query.clone()
+    }
+
+    /// Fragment without the '#'.
+    pub fn fragment(&self) -> Option<String> {
+        self.fragment.clone()
+    }
+
+    /// The normalized URL, fragment included.
+    pub fn href(&self) -> String {
+        self.to_string()
+    }
+
+    /// Host plus the port when it is not the scheme's default: the Host header value.
+    pub fn authority(&self) -> String {
+        if self.port == self.default_port() {
+            self.host.clone()
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+
+    /// Serialized origin, e.g. "https://example.com:8443". URLs with the same
+    /// origin can share a connection.
+    pub fn origin(&self) -> String {
+        format!("{}://{}", self.scheme(), self.authority())
+    }
+
+    /// Origin-form request target: path and query.
+    pub fn target(&self) -> String {
+        match &self.query {
+            Some(query) => format!("{}?{}", self.path, query),
+            None => self.path.clone(),
+        }
+    }
+
+    /// Resolve `reference` against this URL, as `resolve_url` does.
+    pub fn resolve(&self, reference: &str) -> Result<HttpUrl, Error> {
+        self.join(reference)
+    }
+}
+
+impl HttpUrl {
+    /// Parse an absolute http or https URL. Userinfo is dropped.
+    pub(crate) fn parse(url: &str) -> Result<HttpUrl, Error> {
+        let url = clean(url);
+        let (scheme, rest) = url.split_once("://").ok_or(Error::InvalidUrl)?;
+        let secure = match scheme.to_ascii_lowercase().as_str() {
+            "https" => true,
+            "http" => false,
+            _ => return Err(Error::InvalidUrl),
+        };
+        let authority_end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+        let (authority, rest) = rest.split_at(authority_end);
+        let host_port = authority.rsplit_once('@').map_or(authority, |(_, h)| h);
+        let (host, port) = match host_port.rfind(':') {
+            Some(i) if !host_port[i..].contains(']') => (&host_port[..i], &host_port[i + 1..]),
+            _ => (host_port, ""),
+        };
+        let host = parse_host(host)?;
+        let port = match port {
+            "" if secure => 443,
+            "" => 80,
+            port => port.parse().map_err(|_| Error::InvalidUrl)?,
+        };
+        let (path, query, fragment) = split_reference(rest);
+        Ok(HttpUrl {
+            secure,
+            host,
+            port,
+            path: match path {
+                "" => "/".to_string(),
+                path => remove_dot_segments(path),
+            },
+            query: query.map(String::from),
+            fragment: fragment.map(String::from),
+        })
+    }
+
+    /// Resolve `reference` (e.g. a Location value) against this URL. Without a
+    /// fragment of its own, the result keeps this URL's, as Fetch specifies.
+    pub(crate) fn join(&self, reference: &str) -> Result<HttpUrl, Error> {
+        let reference = clean(reference);
+        let mut url = if let Some((scheme, rest)) = split_scheme(&reference) {
+            match scheme.to_ascii_lowercase().as_str() {
+                // "http:g" is relative to an http URL, as in WHATWG
+                same if same == self.scheme() && !rest.starts_with("//") => self.join(rest)?,
+                // and slashes after a special scheme are optional
+                "http" | "https" => {
+                    HttpUrl::parse(&format!("{}://{}", scheme, rest.trim_start_matches('/')))?
+                }
+                _ => return Err(Error::InvalidUrl),
+            }
+        } else if reference.starts_with("//") {
+            let scheme = if self.secure { "https:" } else { "http:" };
+            HttpUrl::parse(&format!("{}{}", scheme, reference))?
+        } else {
+            let (path, query, fragment) = split_reference(&reference);
+            let mut url = self.clone();
+            url.fragment = fragment.map(String::from);
+            if !path.is_empty() {
+                url.path = if path.starts_with('/') {
+                    remove_dot_segments(path)
+                } else {
+                    let dir = &self.path[..=self.path.rfind('/').unwrap_or(0)];
+                    remove_dot_segments(&format!("{}{}", dir, path))
+                };
+                url.query = query.map(String::from);
+            } else if query.is_some() {
+                url.query = query.map(String::from);
+            }
+            url
+        };
+        if url.fragment.is_none() {
+            url.fragment = self.fragment.clone();
+        }
+        Ok(url)
+    }
+
+    fn default_port(&self) -> u16 {
+        if self.secure {
+            443
+        } else {
+            80
+        }
+    }
+
+    pub(crate) fn same_origin(&self, other: &HttpUrl) -> bool {
+        self.secure == other.secure && self.host == other.host && self.port == other.port
+    }
+
+    /// The URL without its fragment, as sent in a Referer header.
+    pub(crate) fn without_fragment(&self) -> String {
+        format!("{}{}", self.origin(), self.target())
+    }
+}
+
+impl fmt::Display for HttpUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.without_fragment())?;
+        match &self.fragment {
+            Some(fragment) => write!(f, "#{}", fragment),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Parse an absolute http(s) URL, e.g. to key connections by `origin()` or to
+/// read the `host()` and `port()` to connect to. Unlike `new URL`, any other
+/// scheme fails, and the "//" after the scheme is required.
+#[wasm_bindgen]
+pub fn parse_url(url: &str) -> Result<HttpUrl, Error> {
+    HttpUrl::parse(url)
+}
+
+/// Resolve `reference`, e.g. a Location header value, against the absolute URL
+/// `base` and return the normalized result. Without a fragment of its own, the
+/// result keeps `base`'s, as Fetch specifies for redirects. Unlike `new URL`,
+/// a reference with a scheme other than http or https fails.
+#[wasm_bindgen]
+pub fn resolve_url(base: &str, reference: &str) -> Result<String, Error> {
+    Ok(HttpUrl::parse(base)?.join(reference)?.to_string())
+}
+
+/// A hostname in the ASCII form DNS, SNI and certificates use, by WHATWG's
+/// domain to ASCII: the UTS #46 mapping (case folding, NFC, fullwidth and other
+/// compatibility forms) with each non-ASCII label Punycode-encoded as "xn--…".
+/// Null for an empty name, one UTS #46 rejects, or one with a character no host
+/// may hold.
+#[wasm_bindgen]
+pub fn domain_to_ascii(host: &str) -> Option<String> {
+    let ascii = idna::domain_to_ascii_cow(host.as_bytes(), AsciiDenyList::URL).ok()?;
+    let valid = !ascii.is_empty()
+        && ascii
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-._~".contains(&b));
+    valid.then(|| ascii.into_owned())
+}
+
+/// `host` in the ASCII form sent for SNI, if it fits DNS: labels of 1 to 63
+/// bytes, 253 bytes in all.
+pub(crate) fn dns_name(host: &str) -> Option<String> {
+    let name = domain_to_ascii(host)?;
+    let labels_fit = name.split('.').all(|label| (1..=63).contains(&label.len()));
+    (labels_fit && name.len() <= 253).then_some(name)
+}
+
+/// The host as it is sent (see `HttpUrl::host`).
+fn parse_host(host: &str) -> Result<String, Error> {
+    if let Some(literal) = host.strip_prefix('[') {
+        let valid = literal.strip_suffix(']').is_some_and(|address| {
+            address.contains(':')
+                && address
+                    .bytes()
+                    .all(|b| b.is_ascii_hexdigit() || b".:".contains(&b))
+        });
+        if !valid {
+            return Err(Error::InvalidUrl);
+        }
+        return Ok(host.to_ascii_lowercase());
+    }
+    let decoded = String::from_utf8(percent_decode(host)).map_err(|_| Error::InvalidUrl)?;
+    let host = domain_to_ascii(&decoded).ok_or(Error::InvalidUrl)?;
+    if ends_in_number(&host) {
+        ipv4(&host).ok_or(Error::InvalidUrl)
+    } else {
+        Ok(host)
+    }
+}
+
+/// Decode `%XX` escapes; a '%' not followed by two hex digits stays as it is.
+pub(crate) fn percent_decode(value: &str) -> Vec<u8> {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = bytes
+            .get(i + 1..i + 3)
+            .filter(|h| h.iter().all(u8::is_ascii_hexdigit));
+        match (bytes[i], escape) {
+            (b'%', Some(hex)) => {
+                let hex = std::str::from_utf8(hex).unwrap_or_default();
+                out.push(u8::from_str_radix(hex, 16).unwrap_or_default());
+                i += 3;
+            }
+            (b, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+/// WHATWG's "ends in a number": such a host can only be an IPv4 address.
+fn ends_in_number(host: &str) -> bool {
+    let host = host.strip_suffix('.').unwrap_or(host);
+    let last = host.rsplit('.').next().unwrap_or_default();
+    !last.is_empty() && (last.bytes().all(|b| b.is_ascii_digit()) || ipv4_number(last).is_some())
+}
+
+/// WHATWG's IPv4 parser: up to four parts, each decimal, octal (leading 0) or
+/// hex (0x), the last filling the remaining bytes, so "0x7f.1" is 127.0.0.1.
+fn ipv4(host: &str) -> Option<String> {
+    let host = host.strip_suffix('.').unwrap_or(host);
+    let numbers = host
+        .split('.')
+        .map(ipv4_number)
+        .collect::<Option<Vec<u64>>>()?;
+    let (last, leading) = numbers.split_last()?;
+    if numbers.len() > 4
+        || leading.iter().any(|&n| n > 255)
+        || *last >> (8 * (4 - leading.len())) != 0
+    {
+        return None;
+    }
+    let address = leading
+        .iter()
+        .enumerate()
+        .fold(*last, |address, (i, n)| address | n << (24 - 8 * i));
+    Some(Ipv4Addr::from(address as u32).to_string())
+}
+
+fn ipv4_number(part: &str) -> Option<u64> {
+    let (digits, radix) = match part.strip_prefix("0x") {
+        Some(hex) => (hex, 16),
+        None if part.len() > 1 && part.starts_with('0') => (&part[1..], 8),
+        None => (part, 10),
+    };
+    if digits.is_empty() {
+        return (radix == 16).then_some(0);
+    }
+    if !digits.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    u64::from_str_radix(digits, radix).ok()
+}
+
+/// Trim, drop tabs and newlines and turn backslashes into slashes, as browsers do
+/// for http(s) URLs, then percent-encode bytes a request target cannot carry.
+fn clean(url: &str) -> String {
+    let mut out = String::with_capacity(url.len());
+    for c in url.trim_matches(|c: char| c <= ' ').chars() {
+        match c {
+            '\t' | '\n' | '\r' => {}
+            '\\' => out.push('/'),
+            c if c <= ' ' || c == '\x7f' || !c.is_ascii() || "\"<>`".contains(c) => {
+                let mut buf = [0; 4];
+                for b in c.encode_utf8(&mut buf).bytes() {
+                    out.push_str(&format!("%{:02X}", b));
+                }
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Scheme of `reference` and what follows its ':', if it has one.
+fn split_scheme(reference: &str) -> Option<(&str, &str)> {
+    let (scheme, rest) = reference.split_once(':')?;
+    let valid = scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"+-.".contains(&b));
+    valid.then_some((scheme, rest))
+}
+
+/// Split a relative reference into path, query and fragment.
+fn split_reference(reference: &str) -> (&str, Option<&str>, Option<&str>) {
+    let (rest, fragment) = match reference.split_once('#') {
+        Some((rest, fragment)) => (rest, Some(fragment)),
+        None => (reference, None),
+    };
+    match rest.split_once('?') {
+        Some((path, query)) => (path, Some(query), fragment),
+        None => (rest, None, fragment),
+    }
+}
+
+/// RFC 3986 §5.2.4, for a path starting with '/'.
+fn remove_dot_segments(path: &str) -> String {
+    let segments: Vec<&str> = path.split('/').skip(1).collect();
+    let mut out: Vec<&str> = Vec::with_capacity(segments.len());
+    for (i, segment) in segments.iter().enumerate() {
+        match segment.to_ascii_lowercase().replace("%2e", ".").as_str() {
+            dots @ ("." | "..") => {
+                if dots == ".." {
+                    out.pop();
+                }
+                // A trailing dot segment still names a directory
+                if i + 1 == segments.len() {
+                    out.push("");
+                }
+            }
+            _ => out.push(segment),
+        }
+    }
+    format!("/{}", out.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_absolute_urls() {
+        let url = HttpUrl::parse(" HTTPS://user:pw@Example.COM:8443/a/./b/../c?q=1#top ").unwrap();
+        assert_eq!(url.host, "example.com");
+        assert_eq!(url.port, 8443);
+        assert_eq!(url.target(), "/a/c?q=1");
+        assert_eq!(url.origin(), "https://example.com:8443");
+        assert_eq!(url.to_string(), "https://example.com:8443/a/c?q=1#top");
+
+        let url = HttpUrl::parse("http://[::1]:8080").unwrap();
+        assert_eq!((url.host.as_str(), url.port), ("[::1]", 8080));
+        assert_eq!(url.target(), "/");
+        assert_eq!(HttpUrl::parse("http://h:80/").unwrap().authority(), "h");
+
+        for bad in [
+            "ftp://h/",
+            "https:///x",
+            "https://h:99999/",
+            "/relative",
+            "https://h st/",
+        ] {
+            assert_eq!(HttpUrl::parse(bad), Err(Error::InvalidUrl), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn resolves_references() {
+        let base = HttpUrl::parse("http://a/b/c/d;p?q#f").unwrap();
+        // RFC 3986 §5.4.1, fragments aside (they are inherited here)
+        let cases = [
+            ("g", "http://a/b/c/g#f"),
+            ("./g", "http://a/b/c/g#f"),
+            ("g/", "http://a/b/c/g/#f"),
+            ("/g", "http://a/g#f"),
+            ("//g", "http://g/#f"),
+            ("?y", "http://a/b/c/d;p?y#f"),
+            ("g?y", "http://a/b/c/g?y#f"),
+            ("#s", "http://a/b/c/d;p?q#s"),
+            ("", "http://a/b/c/d;p?q#f"),
+            (".", "http://a/b/c/#f"),
+            ("..", "http://a/b/#f"),
+            ("../../../g", "http://a/g#f"),
+            ("g/../h", "http://a/b/c/h#f"),
+            ("https://b/x y", "https://b/x%20y#f"),
+            ("\\\\other\\p", "http://other/p#f"),
+            // A special scheme: the base's is relative, slashes are optional
+            ("http:g", "http://a/b/c/g#f"),
+            ("HTTP:?y", "http://a/b/c/d;p?y#f"),
+            ("https:g", "https://g/#f"),
+            ("https:/g/h", "https://g/h#f"),
+        ];
+        for (reference, expected) in cases {
+            assert_eq!(
+                base.join(reference).unwrap().to_string(),
+                expected,
+                "{:?}",
+                reference
+            );
+        }
+        assert_eq!(base.join("mailto:x@y"), Err(Error::InvalidUrl));
+        assert_eq!(base.join("javascript:alert(1)"), Err(Error::InvalidUrl));
+    }
+
+    #[test]
+    fn hosts_are_normalized() {
+        let host = |url: &str| parse_url(url).map(|u| u.host());
+        // RFC 3492 §7.1 and WHATWG's IDNA examples
+        assert_eq!(
+            host("https://Bücher.example/"),
+            Ok("xn--bcher-kva.example".into())
+        );
+        assert_eq!(
+            host("http://例え。テスト/"),
+            Ok("xn--r8jz45g.xn--zckzah".into())
+        );
+        assert_eq!(host("http://☃.net"), Ok("xn--n3h.net".into()));
+        assert_eq!(host("http://ex%61mple.com"), Ok("example.com".into()));
+        assert_eq!(host("http://0x7f.1/"), Ok("127.0.0.1".into()));
+        assert_eq!(host("http://3232235777"), Ok("192.168.1.1".into()));
+        assert_eq!(host("http://0300.0250.0.01."), Ok("192.168.0.1".into()));
+        assert_eq!(
+            host("http://[::FFFF:1.2.3.4]/"),
+            Ok("[::ffff:1.2.3.4]".into())
+        );
+        for bad in [
+            "http://1.2.3.256/",
+            "http://08.1.2.3/",
+            "http://a.b.0x/",
+            "http://ex%2fample/",
+            "http://[example]/",
+        ] {
+            assert!(parse_url(bad).is_err(), "{:?}", bad);
+        }
+        assert_eq!(
+            domain_to_ascii("MÜNCHEN.de").as_deref(),
+            Some("xn--mnchen-3ya.de")
+        );
+        // UTS #46 maps fullwidth and compatibility forms, and composes (NFC)
+        assert_eq!(
+            host("https://ｅｘａｍｐｌｅ.com/"),
+            Ok("example.com".into())
+        );
+        assert_eq!(
+            domain_to_ascii("mu\u{308}nchen.de").as_deref(),
+            Some("xn--mnchen-3ya.de")
+        );
+        assert_eq!(domain_to_ascii("ﬁ.com").as_deref(), Some("fi.com"));
+        assert_eq!(domain_to_ascii(""), None);
+        assert_eq!(domain_to_ascii("xn--a.com"), None);
+    }
+
+    #[test]
+    fn parts_and_resolution() {
+        let url = parse_url("https://example.com:443/a/%2E%2e/b/%2e/?x#y").unwrap();
+        assert_eq!(url.scheme(), "https");
+        assert_eq!(
+            (url.port(), url.authority()),
+            (443, "example.com".to_string())
+        );
+        assert_eq!(url.path(), "/b/");
+        assert_eq!(
+            (url.query(), url.fragment()),
+            (Some("x".into()), Some("y".into()))
+        );
+        assert_eq!(url.href(), "https://example.com/b/?x#y");
+        assert_eq!(
+            resolve_url("https://example.com/a/b", "../c?d"),
+            Ok("https://example.com/c?d".to_string())
+        );
+        assert_eq!(
+            url.resolve("//münchen.de:8080/").unwrap().origin(),
+            "https://xn--mnchen-3ya.de:8080"
+        );
+    }
+}
@@ -0,0 +1,1265 @@
+use std::collections::VecDeque;
+use std::mem;
+
+use wasm_bindgen::prelude::*;
+
+use super::accept::accept_for;
+use super::deflate::{self, Deflate, DeflateParams};
+use super::frame::{self, FrameHeader};
+use crate::base64;
+use crate::error::Error;
+use crate::headers::Headers;
+use crate::http1::{RequestBuilder, ResponseEvent, ResponseParser, Version};
+
+/// Default cap on the size of one received message (after reassembly).
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Output of [`WebSocketClient`], in the order frames arrived.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Message {
+    /// The server accepted the upgrade; `protocol` is the subprotocol it selected
+    Open {
+        protocol: Option<String>,
+        headers: Headers,
+    },
+    Text(String),
+    Binary(Vec<u8>),
+    /// Already answered with a pong
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    /// The server closed the connection (1005 if its close frame had no code), or
+    /// the transport closed without a close frame (1006)
+    Close {
+        code: u16,
+        reason: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// `handshake` not called yet
+    Connecting,
+    /// Upgrade request sent, waiting for the 101
+    Handshaking,
+    Open,
+    /// Our close frame was sent; waiting for the server's
+    Closing,
+    Closed,
+}
+
+/// Sans-IO WebSocket client (RFC 6455) over an HTTP/1.1 connection.
+///
+/// `handshake` turns a `RequestBuilder` into the upgrade request to send with
+/// `write_plaintext`; from then on everything `take_plaintext` returns goes to
+/// `feed`, and whatever `take_outgoing` returns is written back. Messages come out
+/// of `next_event`, starting with an "open" event once the upgrade succeeded.
+/// Pings are answered and the server's close frame echoed automatically.
+#[wasm_bindgen]
+#[derive(Debug)]
+pub struct WebSocketClient {
+    state: State,
+    parser: ResponseParser,
+    /// Sec-WebSocket-Key of the upgrade request
+    key: String,
+    /// Subprotocols offered in the upgrade request
+    offered: Vec<String>,
+    protocol: Option<String>,
+    /// Received bytes not yet parsed into frames
+    buf: Vec<u8>,
+    /// Offer permessage-deflate unless the request carries its own extensions
+    deflate_enabled: bool,
+    deflate_offer: DeflateParams,
+    /// permessage-deflate offers of the upgrade request
+    deflate_offers: Vec<DeflateParams>,
+    /// Compression state, if the server accepted permessage-deflate
+    deflate: Option<Deflate>,
+    /// Opcode, compression and payload of a fragmented message being reassembled
+    fragments: Option<(u8, bool, Vec<u8>)>,
+    out: Vec<u8>,
+    events: VecDeque<Message>,
+    max_message_size: usize,
+    max_frame_size: usize,
+    failed: Option<Error>,
+}
+
+impl Default for WebSocketClient {
+    fn default() -> Self {
+        WebSocketClient {
+            state: State::Connecting,
+            parser: ResponseParser::new(),
+            key: String::new(),
+            offered: Vec::new(),
+            protocol: None,
+            buf: Vec::new(),
+            deflate_enabled: true,
+            deflate_offer: DeflateParams::default(),
+            deflate_offers: Vec::new(),
+            deflate: None,
+            fragments: None,
+            out: Vec::new(),
+            events: VecDeque::new(),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            max_frame_size: 0,
+            failed: None,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl WebSocketClient {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> WebSocketClient {
+        WebSocketClient::default()
+    }
+
+    /// Largest message accepted from the server (16 MiB by default); bigger ones
+    /// fail the connection with 1009.
+    pub fn set_max_message_size(&mut self, limit: usize) {
+        self.max_message_size = limit;
+    }
+
+    /// Split outgoing messages into frames of at most `limit` payload bytes
+    /// (0, the default, sends every message as a single frame).
+    pub fn set_max_frame_size(&mut self, limit: usize) {
+        self.max_frame_size = limit;
+    }
+
+    /// Offer permessage-deflate (on by default, as browsers do). Only applies when
+    /// the request has no Sec-WebSocket-Extensions header of its own; one added by
+    /// the caller is sent as is, and its permessage-deflate offers are honored.
+    pub fn set_permessage_deflate(&mut self, enabled: bool) {
+        self.deflate_enabled = enabled;
+    }
+
+    /// Largest window (log2, 8-15) we offer to compress with. 0, the default, offers
+    /// client_max_window_bits without a value, letting the server pick (like Chrome).
+    pub fn set_client_max_window_bits(&mut self, bits: u8) -> Result<(), Error> {
+        self.deflate_offer.client_max_window_bits =
+            Some(window_bits("client_max_window_bits", bits)?);
+        Ok(())
+    }
+
+    /// Ask the server to compress with a window of at most 2^`bits` bytes (8-15);
+    /// 0, the default, leaves it to the server.
+    pub fn set_server_max_window_bits(&mut self, bits: u8) -> Result<(), Error> {
+        let bits = window_bits("server_max_window_bits", bits)?;
+        self.deflate_offer.server_max_window_bits = (bits != 0).then_some(bits);
+        Ok(())
+    }
+
+    /// Offer to compress every message on its own (off by default). Costs ratio,
+    /// saves keeping the compression window between messages.
+    pub fn set_client_no_context_takeover(&mut self, enabled: bool) {
+        self.deflate_offer.client_no_context_takeover = enabled;
+    }
+
+    /// Ask the server to compress every message on its own (off by default).
+    pub fn set_server_no_context_takeover(&mut self, enabled: bool) {
+        self.deflate_offer.server_no_context_takeover = enabled;
+    }
+
+    /// Bytes of the upgrade request for `request`, which must be a GET over HTTP/1.1
+    /// with its Host set. Headers the caller added keep their position and casing,
+    /// so the request can match a browser; missing Upgrade, Connection,
+    /// Sec-WebSocket-Key and Sec-WebSocket-Version headers are appended. The key is
+    /// always generated here, replacing the value of a Sec-WebSocket-Key the caller
+    /// added as a placeholder. Subprotocols are offered through Sec-WebSocket-Protocol.
+    /// permessage-deflate is offered last, after the headers above, unless disabled
+    /// or already present; it is the only extension implemented, so a server
+    /// accepting any other fails the handshake.
+    pub fn handshake(&mut self, request: &RequestBuilder) -> Result<Vec<u8>, Error> {
+        if self.state != State::Connecting {
+            return Err(Error::WebSocketHandshake("handshake already sent"));
+        }
+        if !request.method().eq_ignore_ascii_case("GET") || request.version() != Version::Http11 {
+            return Err(Error::WebSocketHandshake(
+                "upgrade must be a GET over HTTP/1.1",
+            ));
+        }
+        let mut nonce = [0; 16];
+        random(&mut nonce)?;
+        let key = base64::encode(&nonce);
+
+        let mut request = request.clone();
+        request.replace_header("sec-websocket-key", &key);
+        for (name, value) in [
+            ("Upgrade", "websocket"),
+            ("Connection", "Upgrade"),
+            ("Sec-WebSocket-Key", key.as_str()),
+            ("Sec-WebSocket-Version", "13"),
+        ] {
+            if request.header_all(&name.to_ascii_lowercase()).is_empty() {
+                request.header(name, value)?;
+            }
+        }
+        let extensions = request.header_all("sec-websocket-extensions");
+        if extensions.is_empty() && self.deflate_enabled {
+            request.header("Sec-WebSocket-Extensions", &self.deflate_offer.to_string())?;
+        }
+        let offers =
+            deflate::parse_extensions(request.header_all("sec-websocket-extensions").into_iter())
+                .map_err(Error::WebSocketHandshake)?
+                .into_iter()
+                .filter(|(name, _)| name == deflate::EXTENSION)
+                .map(|(_, params)| DeflateParams::parse(&params, true))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(Error::WebSocketHandshake)?;
+        let bytes = request.to_bytes()?;
+
+        self.offered = request
+            .header_all("sec-websocket-protocol")
+            .into_iter()
+            .flat_map(|v| v.split(','))
+            .map(|p| p.trim().to_string())
+            .filter(|p| !p.is_empty())
+            .collect();
+        self.key = key;
+        self.deflate_offers = offers;
+        self.parser = ResponseParser::new();
+        self.parser.set_request_method("GET");
+        self.state = State::Handshaking;
+        Ok(bytes)
+    }
+
+    /// Feed received plaintext. Events become available through `next_event`; on a
+    /// protocol error a close frame with the matching code is queued for
+    /// `take_outgoing` before the error is returned.
+    pub fn feed(&mut self, data: &[u8]) -> Result<(), Error> {
+        if let Some(e) = &self.failed {
+            return Err(e.clone());
+        }
+        let result = match self.state {
+            State::Connecting => Err(Error::WebSocketHandshake("handshake not sent")),
+            State::Handshaking => self.feed_handshake(data),
+            State::Open | State::Closing => {
+                self.buf.extend_from_slice(data);
+                self.read_frames()
+            }
+            // Anything after the server's close frame is ignored
+            State::Closed => Ok(()),
+        };
+        if let Err(e) = &result {
+            self.failed = Some(e.clone());
+            self.state = State::Closed;
+        }
+        result
+    }
+
+    /// Send a text message.
+    pub fn send_text(&mut self, text: &str) -> Result<(), Error> {
+        self.send_message(frame::TEXT, text.as_bytes())
+    }
+
+    /// Send a binary message.
+    pub fn send_binary(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.send_message(frame::BINARY, data)
+    }
+
+    /// Send a ping with up to 125 bytes of payload; the pong shows up as an event.
+    pub fn ping(&mut self, payload: &[u8]) -> Result<(), Error> {
+        if payload.len() > frame::MAX_CONTROL_PAYLOAD {
+            return Err(Error::InvalidOption {
+                option: "ping payload length",
+                value: payload.len().to_string(),
+            });
+        }
+        self.check_open()?;
+        self.write_frame(true, frame::PING, payload)
+    }
+
+    /// Start the closing handshake with `code` (1000, or 3000-4999 for applications)
+    /// and a reason of up to 123 bytes. "close" arrives once the server answered.
+    pub fn close(&mut self, code: u16, reason: &str) -> Result<(), Error> {
+        if !(code == frame::NORMAL_CLOSURE || (3000..=4999).contains(&code)) {
+            return Err(Error::InvalidOption {
+                option: "close code",
+                value: code.to_string(),
+            });
+        }
+        if reason.len() > frame::MAX_CONTROL_PAYLOAD - 2 {
+            return Err(Error::InvalidOption {
+                option: "close reason length",
+                value: reason.len().to_string(),
+            });
+        }
+        self.check_open()?;
+        let mut payload = code.to_be_bytes().to_vec();
+        payload.extend_from_slice(reason.as_bytes());
+        self.write_frame(true, frame::CLOSE, &payload)?;
+        self.state = State::Closing;
+        Ok(())
+    }
+
+    /// Bytes to send with `write_plaintext`.
+    pub fn take_outgoing(&mut self) -> Vec<u8> {
+        mem::take(&mut self.out)
+    }
+
+    /// Whether `take_outgoing` has bytes to send.
+    pub fn wants_write(&self) -> bool {
+        !self.out.is_empty()
+    }
+
+    /// Next event, or nothing if more input is needed.
+    pub fn next_event(&mut self) -> Option<WebSocketEvent> {
+        self.poll_event().map(WebSocketEvent)
+    }
+
+    /// "connecting", "open", "closing" (our close frame sent) or "closed". Once
+    /// closed, flush `take_outgoing` and close the transport.
+    pub fn state(&self) -> String {
+        match self.state {
+            State::Connecting | State::Handshaking => "connecting",
+            State::Open => "open",
+            State::Closing => "closing",
+            State::Closed => "closed",
+        }
+        .to_string()
+    }
+
+    /// Whether the server accepted permessage-deflate, once open.
+    pub fn permessage_deflate(&self) -> bool {
+        self.deflate.is_some()
+    }
+
+    /// Subprotocol the server selected, once open.
+    pub fn protocol(&self) -> Option<String> {
+        self.protocol.clone()
+    }
+
+    /// Tell the client that the transport has closed. Without a closing handshake
+    /// this is reported as a "close" event with code 1006.
+    pub fn transport_closed(&mut self) {
+        if self.state != State::Closed {
+            if self.state != State::Connecting && self.failed.is_none() {
+                self.events.push_back(Message::Close {
+                    code: frame::ABNORMAL_CLOSURE,
+                    reason: String::new(),
+                });
+            }
+            self.state = State::Closed;
+        }
+        self.failed.get_or_insert(Error::WebSocketClosed);
+    }
+}
+
+impl WebSocketClient {
+    /// Rust-side `next_event`.
+    pub fn poll_event(&mut self) -> Option<Message> {
+        self.events.pop_front()
+    }
+
+    fn feed_handshake(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.parser.feed(data)?;
+        while let Some(event) = self.parser.poll_event() {
+            match event {
+                ResponseEvent::Head(head) => {
+                    if head.status != 101 {
+                        return Err(Error::WebSocketRejected {
+                            status: head.status,
+                        });
+                    }
+                    let (protocol, deflate) = self.check_upgrade(&head.headers)?;
+                    self.protocol = protocol;
+                    self.deflate = deflate;
+                    self.events.push_back(Message::Open {
+                        protocol: self.protocol.clone(),
+                        headers: head.headers,
+                    });
+                }
+                ResponseEvent::Complete => {
+                    self.buf = self.parser.take_leftover();
+                    self.state = State::Open;
+                    return self.read_frames();
+                }
+                ResponseEvent::Informational(_)
+                | ResponseEvent::Body(_)
+                | ResponseEvent::Trailers(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// RFC 6455 §4.1: validate the 101 response; returns the selected subprotocol and
+    /// the compression state if permessage-deflate was accepted.
+    fn check_upgrade(&self, headers: &Headers) -> Result<(Option<String>, Option<Deflate>), Error> {
+        let has_token = |name: &str, token: &str| {
+            headers
+                .get_all(name)
+                .flat_map(|v| v.split(','))
+                .any(|t| t.trim().eq_ignore_ascii_case(token))
+        };
+        if !has_token("upgrade", "websocket") {
+            return Err(Error::WebSocketHandshake("missing Upgrade: websocket"));
+        }
+        if !has_token("connection", "upgrade") {
+            return Err(Error::WebSocketHandshake("missing Connection: upgrade"));
+        }
+        if headers.get("sec-websocket-accept").map(str::trim) != Some(&accept_for(&self.key)) {
+            return Err(Error::WebSocketHandshake("wrong Sec-WebSocket-Accept"));
+        }
+        let extensions = deflate::parse_extensions(headers.get_all("sec-websocket-extensions"))
+            .map_err(Error::WebSocketHandshake)?;
+        let deflate = match extensions.as_slice() {
+            [] => None,
+            [(name, params)] if name == deflate::EXTENSION && !self.deflate_offers.is_empty() => {
+                let accepted =
+                    DeflateParams::parse(params, false).map_err(Error::WebSocketHandshake)?;
+                // With several offers, the server accepted one it can satisfy
+                let mut negotiated = self.deflate_offers.iter().map(|o| o.negotiate(&accepted));
+                let first = negotiated.next().unwrap();
+                let params = negotiated
+                    .find_map(Result::ok)
+                    .map_or(first, Ok)
+                    .map_err(Error::WebSocketHandshake)?;
+                Some(Deflate::new(&params))
+            }
+            _ => return Err(Error::WebSocketHandshake("unsupported extension")),
+        };
+        let mut protocols = headers.get_all("sec-websocket-protocol");
+        let protocol = match (protocols.next(), protocols.next()) {
+            (None, _) => None,
+            (Some(p), None) if self.offered.iter().any(|o| o == p.trim()) => {
+                Some(p.trim().to_string())
+            }
+            _ => return Err(Error::WebSocketHandshake("subprotocol not offered")),
+        };
+        Ok((protocol, deflate))
+    }
+
+    fn read_frames(&mut self) -> Result<(), Error> {
+        let mut pos = 0;
+        let result = loop {
+            if self.state == State::Closed {
+                break Ok(());
+            }
+            let header = match FrameHeader::parse(&self.buf[pos..]) {
+                Ok(Some(header)) => header,
+                Ok(None) => break Ok(()),
+                Err(reason) => break Err(self.fail(frame::PROTOCOL_ERROR, reason)),
+            };
+            if let Err(e) = self.check_header(&header) {
+                break Err(e);
+            }
+            let start = pos + header.header_len;
+            let Some(payload) = self.buf.get(start..start + header.len as usize) else {
+                break Ok(());
+            };
+            let payload = payload.to_vec();
+            pos = start + header.len as usize;
+            if let Err(e) = self.handle_frame(header, payload) {
+                break Err(e);
+            }
+        };
+        self.buf.drain(..pos);
+        result
+    }
+
+    /// Checks that need only the header, so oversized payloads are never buffered.
+    fn check_header(&mut self, header: &FrameHeader) -> Result<(), Error> {
+        if header.mask.is_some() {
+            return Err(self.fail(frame::PROTOCOL_ERROR, "masked server frame"));
+        }
+        // RSV1 marks the first frame of a compressed message
+        let compressed_start = self.deflate.is_some()
+            && matches!(header.opcode, frame::TEXT | frame::BINARY)
+            && header.rsv == frame::RSV1 >> 4;
+        if header.rsv != 0 && !compressed_start {
+            return Err(self.fail(frame::PROTOCOL_ERROR, "reserved bits set"));
+        }
+        match header.opcode {
+            frame::CLOSE | frame::PING | frame::PONG => {
+                if !header.fin {
+                    return Err(self.fail(frame::PROTOCOL_ERROR, "fragmented control frame"));
+                }
+                if header.len > frame::MAX_CONTROL_PAYLOAD as u64 {
+                    return Err(self.fail(frame::PROTOCOL_ERROR, "control frame too large"));
+                }
+            }
+            frame::TEXT | frame::BINARY | frame::CONTINUATION => {
+                let continuation = header.opcode == frame::CONTINUATION;
+                if continuation != self.fragments.is_some() {
+                    return Err(self.fail(
+                        frame::PROTOCOL_ERROR,
+                        if continuation {
+                            "continuation without a message"
+                        } else {
+                            "new message inside a fragmented one"
+                        },
+                    ));
+                }
+                let buffered = self.fragments.as_ref().map_or(0, |(_, _, data)| data.len());
+                if header.len > (self.max_message_size - buffered) as u64 {
+                    return Err(self.fail(frame::MESSAGE_TOO_BIG, "message too large"));
+                }
+            }
+            _ => return Err(self.fail(frame::PROTOCOL_ERROR, "unknown opcode")),
+        }
+        Ok(())
+    }
+
+    fn handle_frame(&mut self, header: FrameHeader, payload: Vec<u8>) -> Result<(), Error> {
+        match header.opcode {
+            frame::PING => {
+                if self.state == State::Open {
+                    self.write_frame(true, frame::PONG, &payload)?;
+                }
+                self.events.push_back(Message::Ping(payload));
+            }
+            frame::PONG => self.events.push_back(Message::Pong(payload)),
+            frame::CLOSE => self.handle_close(&payload)?,
+            opcode => {
+                let (opcode, compressed, data) = match self.fragments.take() {
+                    Some((opcode, compressed, mut data)) => {
+                        data.extend(payload);
+                        (opcode, compressed, data)
+                    }
+                    None => (opcode, header.rsv != 0, payload),
+                };
+                if !header.fin {
+                    self.fragments = Some((opcode, compressed, data));
+                    return Ok(());
+                }
+                let data = match (&mut self.deflate, compressed) {
+                    (Some(deflate), true) => {
+                        match deflate.decompress(&data, self.max_message_size) {
+                            Ok(data) => data,
+                            Err((code, reason)) => return Err(self.fail(code, reason)),
+                        }
+                    }
+                    _ => data,
+                };
+                let message = if opcode == frame::TEXT {
+                    match String::from_utf8(data) {
+                        Ok(text) => Message::Text(text),
+                        Err(_) => {
+                            return Err(self.fail(frame::INVALID_PAYLOAD, "text is not UTF-8"))
+                        }
+                    }
+                } else {
+                    Message::Binary(data)
+                };
+                // Messages after our close frame are still delivered (RFC 6455 §1.4)
+                self.events.push_back(message);
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_close(&mut self, payload: &[u8]) -> Result<(), Error> {
+        let (code, reason) = match payload {
+            [] => (frame::NO_STATUS_RECEIVED, String::new()),
+            [_] => return Err(self.fail(frame::PROTOCOL_ERROR, "close frame of one byte")),
+            [hi, lo, reason @ ..] => {
+                let code = u16::from_be_bytes([*hi, *lo]);
+                if !frame::is_valid_close_code(code) {
+                    return Err(self.fail(frame::PROTOCOL_ERROR, "invalid close code"));
+                }
+                match std::str::from_utf8(reason) {
+                    Ok(reason) => (code, reason.to_string()),
+                    Err(_) => {
+                        return Err(self.fail(frame::INVALID_PAYLOAD, "close reason is not UTF-8"))
+                    }
+                }
+            }
+        };
+        if self.state == State::Open {
+            // Echo the code, as RFC 6455 §5.5.1 suggests
+            let echo = if payload.is_empty() {
+                &[][..]
+            } else {
+                &payload[..2]
+            };
+            self.write_frame(true, frame::CLOSE, echo)?;
+        }
+        self.state = State::Closed;
+        self.events.push_back(Message::Close { code, reason });
+        Ok(())
+    }
+
+    fn check_open(&self) -> Result<(), Error> {
+        match self.state {
+            State::Open => Ok(()),
+            _ => Err(Error::WebSocketClosed),
+        }
+    }
+
+    /// Compress the whole message if permessage-deflate is in use, then fragment it.
+    fn send_message(&mut self, opcode: u8, data: &[u8]) -> Result<(), Error> {
+        self.check_open()?;
+        let compressed;
+        let (opcode, data) = match &mut self.deflate {
+            Some(deflate) => {
+                compressed = deflate.compress(data);
+                (opcode | frame::RSV1, &compressed[..])
+            }
+            None => (opcode, data),
+        };
+        if self.max_frame_size == 0 || data.len() <= self.max_frame_size {
+            return self.write_frame(true, opcode, data);
+        }
+        let mut chunks = data.chunks(self.max_frame_size).peekable();
+        let mut opcode = opcode;
+        while let Some(chunk) = chunks.next() {
+            self.write_frame(chunks.peek().is_none(), opcode, chunk)?;
+            opcode = frame::CONTINUATION;
+        }
+        Ok(())
+    }
+
+    /// Queue a frame masked with a fresh key, as every client frame must be.
+    fn write_frame(&mut self, fin: bool, opcode: u8, payload: &[u8]) -> Result<(), Error> {
+        let mut mask = [0; 4];
+        random(&mut mask)?;
+        frame::write_frame(&mut self.out, fin, opcode, Some(mask), payload);
+        Ok(())
+    }
+
+    /// Fail the connection (RFC 6455 §7.1.7): queue a close frame with `code`.
+    fn fail(&mut self, code: u16, reason: &'static str) -> Error {
+        if self.state == State::Open {
+            // Ignore a randomness failure: the connection is failing anyway
+            let _ = self.write_frame(true, frame::CLOSE, &code.to_be_bytes());
+        }
+        self.state = State::Closed;
+        Error::WebSocket { code, reason }
+    }
+}
+
+fn window_bits(option: &'static str, bits: u8) -> Result<u8, Error> {
+    match bits {
+        0 | 8..=15 => Ok(bits),
+        _ => Err(Error::InvalidOption {
+            option,
+            value: bits.to_string(),
+        }),
+    }
+}
+
+/// Fill `buf` from the platform CSPRNG (Web Crypto in the browser).
+fn random(buf: &mut [u8]) -> Result<(), Error> {
+    getrandom::fill(buf).map_err(|_| Error::WebSocketHandshake("no random source"))
+}
+
+/// One WebSocket event, as exposed to JS.
+#[wasm_bindgen]
+#[derive(Debug)]
+pub struct WebSocketEvent(Message);
+
+#[wasm_bindgen]
+impl WebSocketEvent {
+    /// "open", "text", "binary", "ping", "pong" or "close".
+    pub fn kind(&self) -> String {
+        match self.0 {
+            Message::Open { .. } => "open",
+            Message::Text(_) => "text",
+            Message::Binary(_) => "binary",
+            Message::Ping(_) => "ping",
+            Message::Pong(_) => "pong",
+            Message::Close { .. } => "close",
+        }
+        .to_string()
+    }
+
+    /// Message of a text event, or the reason of a close event.
+    pub fn text(&self) -> Option<String> {
+        match &self.0 {
+            Message::Text(text) => Some(text.clone()),
+            Message::Close { reason, .. } => Some(reason.clone()),
+            _ => None,
+        }
+    }
+
+    /// Status code of a close event (0 otherwise).
+    pub fn code(&self) -> u16 {
+        match self.0 {
+            Message::Close { code, .. } => code,
+            _ => 0,
+        }
+    }
+
+    /// Subprotocol of an open event.
+    pub fn protocol(&self) -> Option<String> {
+        match &self.0 {
+            Message::Open { protocol, .. } => protocol.clone(),
+            _ => None,
+        }
+    }
+
+    /// Response header names of an open event, in received order and casing.
+    pub fn header_names(&self) -> Vec<String> {
+        self.headers().map(Headers::names).unwrap_or_default()
+    }
+
+    /// Response header values of an open event, index-aligned with `header_names`.
+    pub fn header_values(&self) -> Vec<String> {
+        self.headers().map(Headers::values).unwrap_or_default()
+    }
+
+    /// Take the payload of a binary, ping or pong event (empty for other events).
+    pub fn take_data(&mut self) -> Vec<u8> {
+        match &mut self.0 {
+            Message::Binary(data) | Message::Ping(data) | Message::Pong(data) => mem::take(data),
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl WebSocketEvent {
+    pub fn into_inner(self) -> Message {
+        self.0
+    }
+
+    fn headers(&self) -> Option<&Headers> {
+        match &self.0 {
+            Message::Open { headers, .. } => Some(headers),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::websocket::frame::apply_mask;
+
+    fn upgrade_request() -> RequestBuilder {
+        let mut request = RequestBuilder::new("GET", "/chat").unwrap();
+        request.set_host("example.com").unwrap();
+        request
+    }
+
+    fn server_frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        frame::write_frame(&mut out, fin, opcode, None, payload);
+        out
+    }
+
+    /// Unmask the frames the client queued; the opcodes keep their RSV bits.
+    fn client_frames(out: &[u8]) -> Vec<(bool, u8, Vec<u8>)> {
+        let mut frames = Vec::new();
+        let mut pos = 0;
+        while pos < out.len() {
+            let header = FrameHeader::parse(&out[pos..]).unwrap().unwrap();
+            let start = pos + header.header_len;
+            pos = start + header.len as usize;
+            let mut payload = out[start..pos].to_vec();
+            apply_mask(&mut payload, header.mask.expect("client frames are masked"));
+            frames.push((header.fin, header.rsv << 4 | header.opcode, payload));
+        }
+        frames
+    }
+
+    fn header_value<'a>(request: &'a str, name: &str) -> &'a str {
+        request
+            .lines()
+            .find_map(|l| l.strip_prefix(name)?.strip_prefix(": "))
+            .unwrap()
+    }
+
+    fn switching(request: &[u8], extra: &str) -> Vec<u8> {
+        let key = header_value(std::str::from_utf8(request).unwrap(), "Sec-WebSocket-Key");
+        format!(
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Accept: {}\r\n{}\r\n",
+            accept_for(key),
+            extra
+        )
+        .into_bytes()
+    }
+
+    fn open_client() -> WebSocketClient {
+        let mut ws = WebSocketClient::new();
+        let request = ws.handshake(&upgrade_request()).unwrap();
+        ws.feed(&switching(&request, "")).unwrap();
+        assert!(matches!(ws.poll_event(), Some(Message::Open { .. })));
+        ws
+    }
+
+    #[test]
+    fn handshake_keeps_caller_headers() {
+        let mut request = upgrade_request();
+        request.header("User-Agent", "Mozilla/5.0").unwrap();
+        request.header("Sec-WebSocket-Key", "placeholder").unwrap();
+        request
+            .header("Sec-WebSocket-Protocol", "chat, superchat")
+            .unwrap();
+        let mut ws = WebSocketClient::new();
+        let bytes = ws.handshake(&request).unwrap();
+        let text = String::from_utf8(bytes.clone()).unwrap();
+        let key = header_value(&text, "Sec-WebSocket-Key");
+        assert_eq!(key.len(), 24);
+        assert_ne!(key, "placeholder");
+        assert!(text.starts_with(
+            "GET /chat HTTP/1.1\r\nHost: example.com\r\nUser-Agent: Mozilla/5.0\r\n\
+             Sec-WebSocket-Key: "
+        ));
+        assert!(text.ends_with(
+            "Upgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Version: 13\r\n\
+             Sec-WebSocket-Extensions: permessage-deflate; client_max_window_bits\r\n\r\n"
+        ));
+        assert_eq!(
+            ws.handshake(&request),
+            Err(Error::WebSocketHandshake("handshake already sent"))
+        );
+        assert_eq!(ws.state(), "connecting");
+
+        // The 101 and the first frame in one read
+        let mut response = switching(&bytes, "Sec-WebSocket-Protocol: superchat\r\n");
+        response.extend(server_frame(true, frame::TEXT, b"hi"));
+        ws.feed(&response).unwrap();
+        let Some(Message::Open { protocol, headers }) = ws.poll_event() else {
+            panic!("expected open");
+        };
+        assert_eq!(protocol.as_deref(), Some("superchat"));
+        assert!(headers.contains("sec-websocket-accept"));
+        assert_eq!(ws.poll_event(), Some(Message::Text("hi".to_string())));
+        assert_eq!(ws.protocol().as_deref(), Some("superchat"));
+        assert_eq!(ws.state(), "open");
+    }
+
+    #[test]
+    fn handshake_failures() {
+        let respond = |response: &dyn Fn(&[u8]) -> Vec<u8>| {
+            let mut ws = WebSocketClient::new();
+            let request = ws.handshake(&upgrade_request()).unwrap();
+            ws.feed(&response(&request)).unwrap_err()
+        };
+        assert_eq!(
+            respond(&|_| b"HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\n\r\n".to_vec()),
+            Error::WebSocketRejected { status: 403 }
+        );
+        assert_eq!(
+            respond(&|_| {
+                b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                  Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\n"
+                    .to_vec()
+            }),
+            Error::WebSocketHandshake("wrong Sec-WebSocket-Accept")
+        );
+        assert_eq!(
+            respond(&|r| switching(r, "Sec-WebSocket-Extensions: x-webkit-deflate-frame\r\n")),
+            Error::WebSocketHandshake("unsupported extension")
+        );
+        assert_eq!(
+            respond(&|r| {
+                switching(
+                    r,
+                    "Sec-WebSocket-Extensions: permessage-deflate, permessage-deflate\r\n",
+                )
+            }),
+            Error::WebSocketHandshake("unsupported extension")
+        );
+        assert_eq!(
+            respond(&|r| {
+                switching(
+                    r,
+                    "Sec-WebSocket-Extensions: permessage-deflate; server_max_window_bits=7\r\n",
+                )
+            }),
+            Error::WebSocketHandshake("invalid permessage-deflate window bits")
+        );
+        assert_eq!(
+            respond(&|r| switching(r, "Sec-WebSocket-Protocol: chat\r\n")),
+            Error::WebSocketHandshake("subprotocol not offered")
+        );
+        assert_eq!(
+            respond(&|r| {
+                let response = String::from_utf8(switching(r, "")).unwrap();
+                response
+                    .replace("Upgrade: websocket", "Upgrade: h2c")
+                    .into_bytes()
+            }),
+            Error::WebSocketHandshake("missing Upgrade: websocket")
+        );
+
+        let mut post = RequestBuilder::new("POST", "/").unwrap();
+        post.set_host("example.com").unwrap();
+        assert_eq!(
+            WebSocketClient::new().handshake(&post),
+            Err(Error::WebSocketHandshake(
+                "upgrade must be a GET over HTTP/1.1"
+            ))
+        );
+        assert_eq!(
+            WebSocketClient::new().send_text("early"),
+            Err(Error::WebSocketClosed)
+        );
+    }
+
+    #[test]
+    fn messages_are_masked_and_fragmented() {
+        let mut ws = open_client();
+        ws.send_text("hello").unwrap();
+        ws.set_max_frame_size(4);
+        ws.send_binary(&[1, 2, 3, 4, 5, 6, 7, 8, 9]).unwrap();
+        ws.ping(b"?").unwrap();
+        let out = ws.take_outgoing();
+        assert!(!ws.wants_write());
+        assert_eq!(
+            client_frames(&out),
+            vec![
+                (true, frame::TEXT, b"hello".to_vec()),
+                (false, frame::BINARY, vec![1, 2, 3, 4]),
+                (false, frame::CONTINUATION, vec![5, 6, 7, 8]),
+                (true, frame::CONTINUATION, vec![9]),
+                (true, frame::PING, b"?".to_vec()),
+            ]
+        );
+        // Payloads are not sent in the clear
+        assert!(!out.windows(5).any(|w| w == b"hello"));
+        assert!(ws.ping(&[0; 126]).is_err());
+    }
+
+    #[test]
+    fn reassembles_fragments_around_control_frames() {
+        let mut ws = open_client();
+        let mut input = server_frame(false, frame::TEXT, "h\u{e9}".as_bytes());
+        input.extend(server_frame(true, frame::PING, b"p"));
+        input.extend(server_frame(false, frame::CONTINUATION, b"llo"));
+        input.extend(server_frame(true, frame::CONTINUATION, b" world"));
+        input.extend(server_frame(true, frame::PONG, b""));
+        // Split in the middle of a header and of a payload
+        for chunk in input.chunks(3) {
+            ws.feed(chunk).unwrap();
+        }
+        assert_eq!(ws.poll_event(), Some(Message::Ping(b"p".to_vec())));
+        assert_eq!(
+            ws.poll_event(),
+            Some(Message::Text("h\u{e9}llo world".to_string()))
+        );
+        assert_eq!(ws.poll_event(), Some(Message::Pong(Vec::new())));
+        assert_eq!(ws.poll_event(), None);
+        assert_eq!(
+            client_frames(&ws.take_outgoing()),
+            vec![(true, frame::PONG, b"p".to_vec())]
+        );
+    }
+
+    #[test]
+    fn protocol_errors_fail_with_close_codes() {
+        let fail = |input: Vec<u8>, max_message_size: usize| {
+            let mut ws = open_client();
+            ws.set_max_message_size(max_message_size);
+            let error = ws.feed(&input).unwrap_err();
+            assert_eq!(ws.feed(b"more"), Err(error.clone()));
+            assert_eq!(ws.state(), "closed");
+            let Error::WebSocket { code, .. } = error else {
+                panic!("expected a WebSocket error");
+            };
+            // The failure was announced to the server with the same code
+            assert_eq!(
+                client_frames(&ws.take_outgoing()),
+                vec![(true, frame::CLOSE, code.to_be_bytes().to_vec())]
+            );
+            error
+        };
+        let error = |code, reason| Error::WebSocket { code, reason };
+        let limit = DEFAULT_MAX_MESSAGE_SIZE;
+
+        let mut masked = Vec::new();
+        frame::write_frame(&mut masked, true, frame::TEXT, Some([9; 4]), b"x");
+        assert_eq!(
+            fail(masked, limit),
+            error(frame::PROTOCOL_ERROR, "masked server frame")
+        );
+        assert_eq!(
+            fail(vec![0xc1, 0], limit),
+            error(frame::PROTOCOL_ERROR, "reserved bits set")
+        );
+        assert_eq!(
+            fail(server_frame(true, 0x3, b""), limit),
+            error(frame::PROTOCOL_ERROR, "unknown opcode")
+        );
+        assert_eq!(
+            fail(server_frame(false, frame::PING, b""), limit),
+            error(frame::PROTOCOL_ERROR, "fragmented control frame")
+        );
+        assert_eq!(
+            fail(server_frame(true, frame::PING, &[0; 126]), limit),
+            error(frame::PROTOCOL_ERROR, "control frame too large")
+        );
+        assert_eq!(
+            fail(server_frame(true, frame::CONTINUATION, b""), limit),
+            error(frame::PROTOCOL_ERROR, "continuation without a message")
+        );
+        let mut interleaved = server_frame(false, frame::TEXT, b"a");
+        interleaved.extend(server_frame(true, frame::TEXT, b"b"));
+        assert_eq!(
+            fail(interleaved, limit),
+            error(frame::PROTOCOL_ERROR, "new message inside a fragmented one")
+        );
+        assert_eq!(
+            fail(vec![0x82, 126, 0, 1], limit),
+            error(frame::PROTOCOL_ERROR, "non-minimal frame length")
+        );
+        // Rejected on the header, before the payload arrives
+        assert_eq!(
+            fail(vec![0x82, 126, 0x10, 0], 4095),
+            error(frame::MESSAGE_TOO_BIG, "message too large")
+        );
+        let mut fragmented = server_frame(false, frame::BINARY, &[0; 3]);
+        fragmented.extend(server_frame(true, frame::CONTINUATION, &[0; 2]));
+        assert_eq!(
+            fail(fragmented, 4),
+            error(frame::MESSAGE_TOO_BIG, "message too large")
+        );
+        assert_eq!(
+            fail(server_frame(true, frame::TEXT, &[0xff]), limit),
+            error(frame::INVALID_PAYLOAD, "text is not UTF-8")
+        );
+        assert_eq!(
+            fail(server_frame(true, frame::CLOSE, &[0x03, 0xed]), limit),
+            error(frame::PROTOCOL_ERROR, "invalid close code")
+        );
+        assert_eq!(
+            fail(server_frame(true, frame::CLOSE, &[0x03, 0xe8, 0xc3]), limit),
+            error(frame::INVALID_PAYLOAD, "close reason is not UTF-8")
+        );
+    }
+
+    fn deflate_client(request: RequestBuilder, accepted: &str) -> WebSocketClient {
+        let mut ws = WebSocketClient::new();
+        let bytes = ws.handshake(&request).unwrap();
+        let extensions = format!("Sec-WebSocket-Extensions: {}\r\n", accepted);
+        ws.feed(&switching(&bytes, &extensions)).unwrap();
+        assert!(matches!(ws.poll_event(), Some(Message::Open { .. })));
+        assert!(ws.permessage_deflate());
+        ws
+    }
+
+    #[test]
+    fn permessage_deflate_offers() {
+        let mut ws = WebSocketClient::new();
+        ws.set_client_max_window_bits(10).unwrap();
+        ws.set_server_max_window_bits(12).unwrap();
+        ws.set_server_no_context_takeover(true);
+        assert!(ws.set_client_max_window_bits(16).is_err());
+        let text = String::from_utf8(ws.handshake(&upgrade_request()).unwrap()).unwrap();
+        assert_eq!(
+            header_value(&text, "Sec-WebSocket-Extensions"),
+            "permessage-deflate; client_max_window_bits=10; server_max_window_bits=12; \
+             server_no_context_takeover"
+        );
+
+        let mut ws = WebSocketClient::new();
+        ws.set_permessage_deflate(false);
+        let text = String::from_utf8(ws.handshake(&upgrade_request()).unwrap()).unwrap();
+        assert!(!text.contains("Sec-WebSocket-Extensions"));
+        let response = switching(
+            text.as_bytes(),
+            "Sec-WebSocket-Extensions: permessage-deflate\r\n",
+        );
+        assert_eq!(
+            ws.feed(&response),
+            Err(Error::WebSocketHandshake("unsupported extension"))
+        );
+
+        // The caller's own offers are sent verbatim; the server takes the second
+        let mut request = upgrade_request();
+        request
+            .header(
+                "Sec-WebSocket-Extensions",
+                "permessage-deflate; server_max_window_bits=10, permessage-deflate",
+            )
+            .unwrap();
+        let mut ws = WebSocketClient::new();
+        ws.set_server_max_window_bits(9).unwrap();
+        let bytes = ws.handshake(&request).unwrap();
+        assert_eq!(
+            header_value(
+                std::str::from_utf8(&bytes).unwrap(),
+                "Sec-WebSocket-Extensions"
+            ),
+            "permessage-deflate; server_max_window_bits=10, permessage-deflate"
+        );
+        ws.feed(&switching(
+            &bytes,
+            "Sec-WebSocket-Extensions: permessage-deflate\r\n",
+        ))
+        .unwrap();
+        assert!(ws.permessage_deflate());
+        let mut ws = WebSocketClient::new();
+        let bytes = ws.handshake(&request).unwrap();
+        assert_eq!(
+            ws.feed(&switching(
+                &bytes,
+                "Sec-WebSocket-Extensions: permessage-deflate; client_max_window_bits=9\r\n"
+            )),
+            Err(Error::WebSocketHandshake(
+                "client_max_window_bits not offered"
+            ))
+        );
+    }
+
+    #[test]
+    fn compresses_whole_messages() {
+        let mut ws = deflate_client(upgrade_request(), "permessage-deflate");
+        ws.set_max_frame_size(8);
+        let message = "compressible ".repeat(20);
+        ws.send_text(&message).unwrap();
+        ws.send_text(&message).unwrap();
+        let mut frames = client_frames(&ws.take_outgoing()).into_iter();
+        let mut receiver = Deflate::new(&DeflateParams::default());
+        for _ in 0..2 {
+            // RSV1 only on the first fragment of the compressed payload
+            let (fin, opcode, mut payload) = frames.next().unwrap();
+            assert!(!fin && opcode == frame::TEXT | frame::RSV1);
+            for (fin, opcode, data) in frames.by_ref() {
+                assert_eq!(opcode, frame::CONTINUATION);
+                payload.extend(data);
+                if fin {
+                    break;
+                }
+            }
+            assert!(payload.len() < message.len() / 4);
+            assert_eq!(
+                receiver.decompress(&payload, 1000).unwrap(),
+                message.as_bytes()
+            );
+        }
+        assert!(frames.next().is_none());
+
+        let mut ws = deflate_client(upgrade_request(), "permessage-deflate");
+        ws.send_binary(b"x").unwrap();
+        let frames = client_frames(&ws.take_outgoing());
+        assert_eq!(frames[0].1, frame::BINARY | frame::RSV1);
+        // Control frames are never compressed
+        ws.ping(b"x").unwrap();
+        assert_eq!(
+            client_frames(&ws.take_outgoing()),
+            vec![(true, frame::PING, b"x".to_vec())]
+        );
+    }
+
+    #[test]
+    fn decompresses_fragmented_messages() {
+        let mut ws = deflate_client(upgrade_request(), "permessage-deflate");
+        // RFC 7692 §7.2.3.1, split over two frames, then §7.2.3.2 reusing the window
+        let mut input = vec![0x41, 0x03, 0xf2, 0x48, 0xcd];
+        input.extend(server_frame(true, frame::PING, b""));
+        input.extend([0x80, 0x04, 0xc9, 0xc9, 0x07, 0x00]);
+        input.extend([0xc1, 0x05, 0xf2, 0x00, 0x11, 0x00, 0x00]);
+        input.extend(server_frame(true, frame::BINARY, b"plain"));
+        ws.feed(&input).unwrap();
+        assert_eq!(ws.poll_event(), Some(Message::Ping(Vec::new())));
+        assert_eq!(ws.poll_event(), Some(Message::Text("Hello".to_string())));
+        assert_eq!(ws.poll_event(), Some(Message::Text("Hello".to_string())));
+        assert_eq!(ws.poll_event(), Some(Message::Binary(b"plain".to_vec())));
+
+        let fail = |accepted: &str, max_message_size: usize, input: &[u8]| {
+            let mut ws = deflate_client(upgrade_request(), accepted);
+            ws.set_max_message_size(max_message_size);
+            ws.feed(input).unwrap_err()
+        };
+        let error = |code, reason| Error::WebSocket { code, reason };
+        let limit = DEFAULT_MAX_MESSAGE_SIZE;
+        assert_eq!(
+            fail("permessage-deflate", limit, &[0x41, 0x01, 0xf2, 0xc0, 0x00]),
+            error(frame::PROTOCOL_ERROR, "reserved bits set")
+        );
+        assert_eq!(
+            fail("permessage-deflate", limit, &[0xc9, 0x00]),
+            error(frame::PROTOCOL_ERROR, "reserved bits set")
+        );
+        assert_eq!(
+            fail("permessage-deflate", limit, &[0xc1, 0x02, 0xff, 0xff]),
+            error(frame::INVALID_PAYLOAD, "invalid compressed data")
+        );
+        // Small on the wire, too large once inflated
+        let mut bomb = Vec::new();
+        let payload = Deflate::new(&DeflateParams::default()).compress(&[b'a'; 100_000]);
+        assert!(payload.len() < 1000);
+        frame::write_frame(&mut bomb, true, frame::BINARY | frame::RSV1, None, &payload);
+        assert_eq!(
+            fail("permessage-deflate", 99_999, &bomb),
+            error(frame::MESSAGE_TOO_BIG, "message too large")
+        );
+        // No context takeover by the server: every message decodes on its own
+        let mut ws = deflate_client(
+            upgrade_request(),
+            "permessage-deflate; server_no_context_takeover",
+        );
+        ws.feed(&[0xc1, 0x07, 0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00])
+            .unwrap();
+        assert_eq!(ws.poll_event(), Some(Message::Text("Hello".to_string())));
+        ws.feed(&[0xc1, 0x07, 0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00])
+            .unwrap();
+        assert_eq!(ws.poll_event(), Some(Message::Text("Hello".to_string())));
+    }
+
+    #[test]
+    fn closing_handshake() {
+        // Initiated by the client
+        let mut ws = open_client();
+        assert!(ws.close(1001, "").is_err());
+        ws.close(1000, "bye").unwrap();
+        assert_eq!(ws.state(), "closing");
+        assert_eq!(ws.send_text("late"), Err(Error::WebSocketClosed));
+        let mut close = b"\x03\xe8bye".to_vec();
+        assert_eq!(
+            client_frames(&ws.take_outgoing()),
+            vec![(true, frame::CLOSE, close.clone())]
+        );
+        // Data still in flight from the server is delivered, and not answered
+        let mut input = server_frame(true, frame::BINARY, b"last");
+        input.extend(server_frame(true, frame::PING, b""));
+        input.extend(server_frame(true, frame::CLOSE, &close));
+        ws.feed(&input).unwrap();
+        assert_eq!(ws.poll_event(), Some(Message::Binary(b"last".to_vec())));
+        assert_eq!(ws.poll_event(), Some(Message::Ping(Vec::new())));
+        assert_eq!(
+            ws.poll_event(),
+            Some(Message::Close {
+                code: 1000,
+                reason: "bye".to_string()
+            })
+        );
+        assert!(!ws.wants_write());
+        assert_eq!(ws.state(), "closed");
+
+        // Initiated by the server: the code is echoed
+        let mut ws = open_client();
+        close.truncate(2);
+        close.extend_from_slice(b"restart");
+        close[1] = 0xe9;
+        ws.feed(&server_frame(true, frame::CLOSE, &close)).unwrap();
+        assert_eq!(
+            ws.poll_event(),
+            Some(Message::Close {
+                code: 1001,
+                reason: "restart".to_string()
+            })
+        );
+        assert_eq!(
+            client_frames(&ws.take_outgoing()),
+            vec![(true, frame::CLOSE, vec![0x03, 0xe9])]
+        );
+        ws.feed(b"ignored").unwrap();
+
+        // Without a code, and without a close frame at all
+        let mut ws = open_client();
+        ws.feed(&server_frame(true, frame::CLOSE, b"")).unwrap();
+        assert_eq!(
+            ws.poll_event(),
+            Some(Message::Close {
+                code: frame::NO_STATUS_RECEIVED,
+                reason: String::new()
+            })
+        );
+        assert_eq!(
+            client_frames(&ws.take_outgoing()),
+            vec![(true, frame::CLOSE, Vec::new())]
+        );
+        let mut ws = open_client();
+        ws.transport_closed();
+        assert_eq!(
+            ws.poll_event(),
+            Some(Message::Close {
+                code: frame::ABNORMAL_CLOSURE,
+                reason: String::new()
+            })
+        );
+        assert_eq!(ws.feed(b""), Err(Error::WebSocketClosed));
+    }
+}
@@ -0,0 +1,460 @@
+//! permessage-deflate (RFC 7692): negotiation and per-message compression.
+//!
+//! Each message is one raw deflate stream segment ended by a sync flush, whose
+//! trailing 00 00 ff ff is left out on the wire. Unless no_context_takeover was
+//! negotiated for a direction, its LZ77 window carries over between messages.
+
+use std::fmt;
+
+use miniz_oxide::deflate::core::{CompressionStrategy, CompressorOxide};
+use miniz_oxide::deflate::stream::deflate;
+use miniz_oxide::inflate::stream::{inflate, InflateState};
+use miniz_oxide::{DataFormat, MZError, MZFlush, MZStatus};
+
+use super::frame;
+use crate::http1::is_token;
+
+pub(crate) const EXTENSION: &str = "permessage-deflate";
+
+/// Appended to every message before inflating it.
+const TAIL: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+/// Compression output is staged through a buffer of this size.
+const OUT_CHUNK: usize = 16 * 1024;
+
+/// Same as zlib's default level.
+const LEVEL: u8 = 6;
+
+/// Name and parameters of one Sec-WebSocket-Extensions element.
+pub(crate) type Extension = (String, Vec<(String, Option<String>)>);
+
+/// Parse every element of the given Sec-WebSocket-Extensions values.
+pub(crate) fn parse_extensions<'a>(
+    values: impl Iterator<Item = &'a str>,
+) -> Result<Vec<Extension>, &'static str> {
+    let mut extensions = Vec::new();
+    for element in values.flat_map(|v| v.split(',')) {
+        let mut parts = element.split(';').map(str::trim);
+        let name = parts.next().unwrap_or_default();
+        if name.is_empty() {
+            // Empty list elements are allowed (RFC 9110 §5.6.1)
+            continue;
+        }
+        if !is_token(name) {
+            return Err("malformed Sec-WebSocket-Extensions");
+        }
+        let mut params = Vec::new();
+        for param in parts {
+            let (key, value) = match param.split_once('=') {
+                Some((key, value)) => {
+                    let value = value.trim();
+                    let value = value
+                        .strip_prefix('"')
+                        .and_then(|v| v.strip_suffix('"'))
+                        .unwrap_or(value);
+                    (key.trim(), Some(value.to_string()))
+                }
+                None => (param, None),
+            };
+            if !is_token(key) || value.as_deref().is_some_and(|v| !is_token(v)) {
+                return Err("malformed Sec-WebSocket-Extensions");
+            }
+            params.push((key.to_ascii_lowercase(), value));
+        }
+        extensions.push((name.to_ascii_lowercase(), params));
+    }
+    Ok(extensions)
+}
+
+/// permessage-deflate parameters, as offered or as accepted by the server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct DeflateParams {
+    pub(crate) server_no_context_takeover: bool,
+    pub(crate) client_no_context_takeover: bool,
+    /// Largest window the server may compress with (log2, 8-15)
+    pub(crate) server_max_window_bits: Option<u8>,
+    /// Largest window we may compress with; 0 in an offer means the parameter
+    /// without a value, i.e. the server may pick one
+    pub(crate) client_max_window_bits: Option<u8>,
+}
+
+impl Default for DeflateParams {
+    /// What Chrome offers: `permessage-deflate; client_max_window_bits`.
+    fn default() -> Self {
+        DeflateParams {
+            server_no_context_takeover: false,
+            client_no_context_takeover: false,
+            server_max_window_bits: None,
+            client_max_window_bits: Some(0),
+        }
+    }
+}
+
+impl DeflateParams {
+    /// Parameters of a permessage-deflate element (RFC 7692 §7.1). Only an offer may
+    /// carry client_max_window_bits without a value.
+    pub(crate) fn parse(
+        params: &[(String, Option<String>)],
+        offer: bool,
+    ) -> Result<DeflateParams, &'static str> {
+        let mut parsed = DeflateParams {
+            client_max_window_bits: None,
+            ..DeflateParams::default()
+        };
+        let mut seen = Vec::new();
+        for (key, value) in params {
+            if seen.contains(&key.as_str()) {
+                return Err("repeated permessage-deflate parameter");
+            }
+            seen.push(key);
+            let bits = || match value.as_deref().map(str::parse::<u8>) {
+                Some(Ok(bits @ 8..=15)) => Ok(bits),
+                _ => Err("invalid permessage-deflate window bits"),
+            };
+            match (key.as_str(), value) {
+                ("server_no_context_takeover", None) => parsed.server_no_context_takeover = true,
+                ("client_no_context_takeover", None) => parsed.client_no_context_takeover = true,
+                ("server_max_window_bits", _) => parsed.server_max_window_bits = Some(bits()?),
+                ("client_max_window_bits", None) if offer => {
+                    parsed.client_max_window_bits = Some(0)
+                }
+                ("client_max_window_bits", _) => parsed.client_max_window_bits = Some(bits()?),
+                _ => return Err("unknown permessage-deflate parameter"),
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Check the server's accepted parameters against this offer (RFC 7692 §7.1)
+    /// and return what applies to the connection.
+    pub(crate) fn negotiate(
+        &self,
+        accepted: &DeflateParams,
+    ) -> Result<DeflateParams, &'static str> {
+        if accepted.client_max_window_bits.is_some() && self.client_max_window_bits.is_none() {
+            return Err("client_max_window_bits not offered");
+        }
+        if let Some(offered) = self.client_max_window_bits.filter(|&b| b != 0) {
+            if accepted.client_max_window_bits.is_some_and(|b| b > offered) {
+                return Err("client_max_window_bits larger than offered");
+            }
+        }
+        if self.server_no_context_takeover && !accepted.server_no_context_takeover {
+            return Err("server_no_context_takeover not accepted");
+        }
+        if let Some(offered) = self.server_max_window_bits {
+            if accepted.server_max_window_bits.is_none_or(|b| b > offered) {
+                return Err("server_max_window_bits not accepted");
+            }
+        }
+        Ok(DeflateParams {
+            server_no_context_takeover: accepted.server_no_context_takeover,
+            client_no_context_takeover: self.client_no_context_takeover
+                || accepted.client_no_context_takeover,
+            server_max_window_bits: accepted.server_max_window_bits,
+            client_max_window_bits: accepted
+                .client_max_window_bits
+                .or(self.client_max_window_bits.filter(|&b| b != 0)),
+        })
+    }
+}
+
+impl fmt::Display for DeflateParams {
+    /// The offer as a Sec-WebSocket-Extensions element.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(EXTENSION)?;
+        match self.client_max_window_bits {
+            Some(0) => f.write_str("; client_max_window_bits")?,
+            Some(bits) => write!(f, "; client_max_window_bits={}", bits)?,
+            None => {}
+        }
+        if let Some(bits) = self.server_max_window_bits {
+            write!(f, "; server_max_window_bits={}", bits)?;
+        }
+        if self.client_no_context_takeover {
+            f.write_str("; client_no_context_takeover")?;
+        }
+        if self.server_no_context_takeover {
+            f.write_str("; server_no_context_takeover")?;
+        }
+        Ok(())
+    }
+}
+
+/// Compression state of a connection that negotiated permessage-deflate.
+pub(crate) struct Deflate {
+    compressor: Box<CompressorOxide>,
+    inflate: Box<InflateState>,
+    client_no_context_takeover: bool,
+    server_no_context_takeover: bool,
+}
+
+impl Deflate {
+    pub(crate) fn new(params: &DeflateParams) -> Deflate {
+        // Smaller windows make miniz_oxide fall back to a 4 KiB window or RLE, both
+        // within the limit
+        let window_bits = params.client_max_window_bits.unwrap_or(15);
+        Deflate {
+            compressor: Box::new(CompressorOxide::with_params(
+                DataFormat::Raw,
+                LEVEL,
+                CompressionStrategy::Default,
+                window_bits,
+            )),
+            inflate: InflateState::new_boxed(DataFormat::Raw),
+            client_no_context_takeover: params.client_no_context_takeover,
+            server_no_context_takeover: params.server_no_context_takeover,
+        }
+    }
+
+    /// Compress a whole message into the payload to send (before fragmentation).
+    pub(crate) fn compress(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(data.len() / 2 + 16);
+        let mut chunk = [0u8; OUT_CHUNK];
+        let mut consumed = 0;
+        loop {
+            let res = deflate(
+                &mut self.compressor,
+                &data[consumed..],
+                &mut chunk,
+                MZFlush::Sync,
+            );
+            consumed += res.bytes_consumed;
+            out.extend_from_slice(&chunk[..res.bytes_written]);
+            // The flush is complete once it no longer fills the output
+            if res.status.is_err() || consumed == data.len() && res.bytes_written < chunk.len() {
+                break;
+            }
+        }
+        debug_assert!(out.ends_with(&TAIL));
+        out.truncate(out.len().saturating_sub(TAIL.len()));
+        if self.client_no_context_takeover {
+            self.compressor.reset();
+        }
+        out
+    }
+
+    /// Inflate a received message, failing with 1009 once it exceeds `limit` bytes
+    /// (so a small payload cannot expand without bound) and 1007 if it is corrupt.
+    pub(crate) fn decompress(
+        &mut self,
+        payload: &[u8],
+        limit: usize,
+    ) -> Result<Vec<u8>, (u16, &'static str)> {
+        let mut out = Vec::new();
+        let mut chunk = [0u8; OUT_CHUNK];
+        'input: for input in [payload, &TAIL] {
+            let mut consumed = 0;
+            loop {
+                let res = inflate(
+                    &mut self.inflate,
+                    &input[consumed..],
+                    &mut chunk,
+                    MZFlush::Sync,
+                );
+                consumed += res.bytes_consumed;
+                if res.bytes_written > limit - out.len() {
+                    return Err((frame::MESSAGE_TOO_BIG, "message too large"));
+                }
+                out.extend_from_slice(&chunk[..res.bytes_written]);
+                match res.status {
+                    // A final block ends the stream; the next message starts afresh
+                    Ok(MZStatus::StreamEnd) => {
+                        self.inflate.reset(DataFormat::Raw);
+                        break 'input;
+                    }
+                    Ok(_) if consumed == input.len() && res.bytes_written < chunk.len() => break,
+                    Ok(_) => {}
+                    Err(MZError::Buf) => break,
+                    Err(_) => return Err((frame::INVALID_PAYLOAD, "invalid compressed data")),
+                }
+            }
+        }
+        if self.server_no_context_takeover {
+            self.inflate.reset(DataFormat::Raw);
+        }
+        Ok(out)
+    }
+}
+
+impl fmt::Debug for Deflate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Deflate")
+            .field(
+                "client_no_context_takeover",
+                &self.client_no_context_takeover,
+            )
+            .field(
+                "server_no_context_takeover",
+                &self.server_no_context_takeover,
+            )
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(header: &str, offer: bool) -> Result<DeflateParams, &'static str> {
+        let extensions = parse_extensions([header].into_iter())?;
+        DeflateParams::parse(&extensions[0].1, offer)
+    }
+
+    #[test]
+    fn parses_and_negotiates_parameters() {
+        assert_eq!(
+            parse_extensions(
+                ["permessage-deflate; client_max_window_bits=\"10\", , x-foo"].into_iter()
+            ),
+            Ok(vec![
+                (
+                    "permessage-deflate".to_string(),
+                    vec![("client_max_window_bits".to_string(), Some("10".to_string()))]
+                ),
+                ("x-foo".to_string(), Vec::new()),
+            ])
+        );
+        assert!(parse_extensions(["permessage-deflate; a=b c"].into_iter()).is_err());
+
+        let chrome = DeflateParams::default();
+        assert_eq!(
+            chrome.to_string(),
+            "permessage-deflate; client_max_window_bits"
+        );
+        assert_eq!(params(&chrome.to_string(), true), Ok(chrome.clone()));
+        assert_eq!(
+            params("permessage-deflate; client_max_window_bits", false),
+            Err("invalid permessage-deflate window bits")
+        );
+        assert_eq!(
+            params("permessage-deflate; server_max_window_bits=16", false),
+            Err("invalid permessage-deflate window bits")
+        );
+        assert_eq!(
+            params(
+                "permessage-deflate; server_no_context_takeover; server_no_context_takeover",
+                false
+            ),
+            Err("repeated permessage-deflate parameter")
+        );
+        assert_eq!(
+            params("permessage-deflate; server_no_context_takeover=1", false),
+            Err("unknown permessage-deflate parameter")
+        );
+
+        let accepted = params(
+            "permessage-deflate; client_max_window_bits=9; server_no_context_takeover",
+            false,
+        )
+        .unwrap();
+        let negotiated = chrome.negotiate(&accepted).unwrap();
+        assert_eq!(negotiated.client_max_window_bits, Some(9));
+        assert!(negotiated.server_no_context_takeover && !negotiated.client_no_context_takeover);
+
+        let plain = params("permessage-deflate", true).unwrap();
+        assert_eq!(
+            plain.negotiate(&accepted),
+            Err("client_max_window_bits not offered")
+        );
+        let strict = params(
+            "permessage-deflate; server_max_window_bits=10; server_no_context_takeover",
+            true,
+        )
+        .unwrap();
+        assert_eq!(
+            strict.negotiate(
+                &params("permessage-deflate; server_max_window_bits=10", false).unwrap()
+            ),
+            Err("server_no_context_takeover not accepted")
+        );
+        assert_eq!(
+            strict.negotiate(
+                &params("permessage-deflate; server_no_context_takeover", false).unwrap()
+            ),
+            Err("server_max_window_bits not accepted")
+        );
+        let limited = params(
+            "permessage-deflate; server_max_window_bits=9; server_no_context_takeover",
+            false,
+        )
+        .unwrap();
+        assert_eq!(strict.negotiate(&limited), Ok(limited));
+    }
+
+    #[test]
+    fn rfc7692_examples() {
+        // §7.2.3.1 and §7.2.3.2: the second "Hello" refers back into the first
+        let mut deflate = Deflate::new(&DeflateParams::default());
+        let first = [0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00];
+        let second = [0xf2, 0x00, 0x11, 0x00, 0x00];
+        assert_eq!(deflate.decompress(&first, 100).unwrap(), b"Hello");
+        assert_eq!(deflate.decompress(&second, 100).unwrap(), b"Hello");
+
+        // Without context takeover the back-reference has nothing to point to
+        let mut deflate = Deflate::new(&DeflateParams {
+            server_no_context_takeover: true,
+            ..DeflateParams::default()
+        });
+        assert_eq!(deflate.decompress(&first, 100).unwrap(), b"Hello");
+        assert_ne!(deflate.decompress(&second, 100), Ok(b"Hello".to_vec()));
+
+        // §7.2.3.3: a stored block
+        let mut deflate = Deflate::new(&DeflateParams::default());
+        let stored = [
+            0x00, 0x05, 0x00, 0xfa, 0xff, 0x48, 0x65, 0x6c, 0x6c, 0x6f, 0x00,
+        ];
+        assert_eq!(deflate.decompress(&stored, 100).unwrap(), b"Hello");
+        assert_eq!(
+            deflate.decompress(&[0xff, 0xff], 100),
+            Err((frame::INVALID_PAYLOAD, "invalid compressed data"))
+        );
+    }
+
+    #[test]
+    fn context_takeover_and_bomb_limit() {
+        let message = br#"{"type":"ticker","product_id":"BTC-USD","price":"64000.00"}"#;
+        let compressed_twice = |client_no_context_takeover| {
+            let params = DeflateParams {
+                client_no_context_takeover,
+                ..DeflateParams::default()
+            };
+            let mut sender = Deflate::new(&params);
+            let mut receiver = Deflate::new(&DeflateParams {
+                server_no_context_takeover: client_no_context_takeover,
+                ..DeflateParams::default()
+            });
+            let (a, b) = (sender.compress(message), sender.compress(message));
+            assert_eq!(receiver.decompress(&a, 1000).unwrap(), message);
+            assert_eq!(receiver.decompress(&b, 1000).unwrap(), message);
+            (a.len(), b.len())
+        };
+        let (first, second) = compressed_twice(false);
+        assert!(second < first / 2);
+        let (first, second) = compressed_twice(true);
+        assert_eq!(first, second);
+
+        // Small windows still round-trip
+        let mut sender = Deflate::new(&DeflateParams {
+            client_max_window_bits: Some(8),
+            ..DeflateParams::default()
+        });
+        let mut receiver = Deflate::new(&DeflateParams::default());
+        let long = message.repeat(50);
+        assert_eq!(
+            receiver
+                .decompress(&sender.compress(&long), 1 << 20)
+                .unwrap(),
+            long
+        );
+        assert_eq!(receiver.decompress(&sender.compress(b""), 0).unwrap(), b"");
+
+        let zeros = Deflate::new(&DeflateParams::default()).compress(&[0; 1 << 20]);
+        assert!(zeros.len() < 8 * 1024);
+        let mut receiver = Deflate::new(&DeflateParams::default());
+        assert_eq!(
+            receiver.decompress(&zeros, (1 << 20) - 1),
+            Err((frame::MESSAGE_TOO_BIG, "message too large"))
+        );
+    }
+}
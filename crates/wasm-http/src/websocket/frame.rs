@@ -0,0 +1,181 @@
+//! Frame layout, opcodes and close codes (RFC 6455 §5.2, §7.4).
+
+pub(crate) const CONTINUATION: u8 = 0x0;
+pub(crate) const TEXT: u8 = 0x1;
+pub(crate) const BINARY: u8 = 0x2;
+pub(crate) const CLOSE: u8 = 0x8;
+pub(crate) const PING: u8 = 0x9;
+pub(crate) const PONG: u8 = 0xa;
+
+/// Set on the first frame of a permessage-deflate compressed message; written
+/// into the opcode byte.
+pub(crate) const RSV1: u8 = 0x40;
+
+/// Largest control frame payload.
+pub(crate) const MAX_CONTROL_PAYLOAD: usize = 125;
+
+pub const NORMAL_CLOSURE: u16 = 1000;
+pub const GOING_AWAY: u16 = 1001;
+pub const PROTOCOL_ERROR: u16 = 1002;
+pub const UNSUPPORTED_DATA: u16 = 1003;
+/// Reported for a close frame without a status code; never sent.
+pub const NO_STATUS_RECEIVED: u16 = 1005;
+/// Reported when the transport closed without a close frame; never sent.
+pub const ABNORMAL_CLOSURE: u16 = 1006;
+pub const INVALID_PAYLOAD: u16 = 1007;
+pub const POLICY_VIOLATION: u16 = 1008;
+pub const MESSAGE_TOO_BIG: u16 = 1009;
+pub const INTERNAL_ERROR: u16 = 1011;
+
+/// Whether `code` may appear in a close frame (RFC 6455 §7.4, IANA registry).
+pub(crate) fn is_valid_close_code(code: u16) -> bool {
+    matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999)
+}
+
+/// A parsed frame header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct FrameHeader {
+    pub(crate) fin: bool,
+    /// RSV1-3 bits; only permessage-deflate gives one (RSV1) a meaning
+    pub(crate) rsv: u8,
+    pub(crate) opcode: u8,
+    pub(crate) mask: Option<[u8; 4]>,
+    pub(crate) len: u64,
+    /// Bytes taken by the header itself
+    pub(crate) header_len: usize,
+}
+
+impl FrameHeader {
+    /// Parse a header from the start of `buf`: nothing if it is incomplete, an error
+    /// if the length is not in its minimal form or has the top bit set.
+    pub(crate) fn parse(buf: &[u8]) -> Result<Option<FrameHeader>, &'static str> {
+        let [b0, b1, ..] = *buf else {
+            return Ok(None);
+        };
+        let (len, mut header_len) = match b1 & 0x7f {
+            126 => {
+                let Some(bytes) = buf.get(2..4) else {
+                    return Ok(None);
+                };
+                let len = u16::from_be_bytes([bytes[0], bytes[1]]) as u64;
+                if len < 126 {
+                    return Err("non-minimal frame length");
+                }
+                (len, 4)
+            }
+            127 => {
+                let Some(bytes) = buf.get(2..10) else {
+                    return Ok(None);
+                };
+                let len = u64::from_be_bytes(bytes.try_into().unwrap());
+                if len >> 63 != 0 {
+                    return Err("frame length has the top bit set");
+                }
+                if len <= u16::MAX as u64 {
+                    return Err("non-minimal frame length");
+                }
+                (len, 10)
+            }
+            len => (len as u64, 2),
+        };
+        let mask = if b1 & 0x80 != 0 {
+            let Some(key) = buf.get(header_len..header_len + 4) else {
+                return Ok(None);
+            };
+            header_len += 4;
+            Some(key.try_into().unwrap())
+        } else {
+            None
+        };
+        Ok(Some(FrameHeader {
+            fin: b0 & 0x80 != 0,
+            rsv: b0 >> 4 & 0x7,
+            opcode: b0 & 0xf,
+            mask,
+            len,
+            header_len,
+        }))
+    }
+}
+
+/// Append a frame; the payload is masked with `mask` if one is given. `opcode` may
+/// have `RSV1` set.
+pub(crate) fn write_frame(
+    out: &mut Vec<u8>,
+    fin: bool,
+    opcode: u8,
+    mask: Option<[u8; 4]>,
+    payload: &[u8],
+) {
+    out.push(if fin { 0x80 } else { 0 } | opcode);
+    let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+    match payload.len() {
+        len @ 0..=125 => out.push(mask_bit | len as u8),
+        len @ 126..=0xffff => {
+            out.push(mask_bit | 126);
+            out.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            out.push(mask_bit | 127);
+            out.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    match mask {
+        Some(key) => {
+            out.extend_from_slice(&key);
+            let start = out.len();
+            out.extend_from_slice(payload);
+            apply_mask(&mut out[start..], key);
+        }
+        None => out.extend_from_slice(payload),
+    }
+}
+
+/// XOR `data` with the masking key (masking and unmasking are the same operation).
+pub(crate) fn apply_mask(data: &mut [u8], key: [u8; 4]) {
+    for (i, b) in data.iter_mut().enumerate() {
+        *b ^= key[i % 4];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn length_forms_round_trip() {
+        let key = [1, 2, 3, 4];
+        for len in [0, 125, 126, 0xffff, 0x10000] {
+            let payload = vec![0x5a; len];
+            let mut out = Vec::new();
+            write_frame(&mut out, true, BINARY, Some(key), &payload);
+            let header = FrameHeader::parse(&out).unwrap().unwrap();
+            assert_eq!(header.len, len as u64);
+            assert_eq!(header.mask, Some(key));
+            assert_eq!(header.header_len + len, out.len());
+            assert!(header.fin && header.opcode == BINARY && header.rsv == 0);
+            let mut data = out[header.header_len..].to_vec();
+            apply_mask(&mut data, key);
+            assert_eq!(data, payload);
+            // Every prefix shorter than the header is incomplete
+            for end in 0..header.header_len {
+                assert_eq!(FrameHeader::parse(&out[..end]), Ok(None));
+            }
+        }
+
+        assert_eq!(
+            FrameHeader::parse(&[0x82, 126, 0, 125]),
+            Err("non-minimal frame length")
+        );
+        assert_eq!(
+            FrameHeader::parse(&[0x82, 127, 0, 0, 0, 0, 0, 0, 0xff, 0xff]),
+            Err("non-minimal frame length")
+        );
+        assert_eq!(
+            FrameHeader::parse(&[0x82, 127, 0x80, 0, 0, 0, 0, 0, 0, 0]),
+            Err("frame length has the top bit set")
+        );
+        let header = FrameHeader::parse(&[0x71, 0]).unwrap().unwrap();
+        assert!(!header.fin && header.rsv == 7 && header.opcode == TEXT);
+    }
+}
@@ -0,0 +1,16 @@
+//! Sans-IO WebSocket client (RFC 6455).
+//!
+//! The upgrade is an ordinary HTTP/1.1 request, so its headers can match a browser's;
+//! after the 101 the client consumes the plaintext from `take_plaintext` and
+//! produces the bytes for `write_plaintext`. permessage-deflate (RFC 7692) is the only extension.
+
+mod accept;
+mod client;
+mod deflate;
+mod frame;
+
+pub use client::{Message, WebSocketClient, WebSocketEvent, DEFAULT_MAX_MESSAGE_SIZE};
+pub use frame::{
+    ABNORMAL_CLOSURE, GOING_AWAY, INTERNAL_ERROR, INVALID_PAYLOAD, MESSAGE_TOO_BIG, NORMAL_CLOSURE,
+    NO_STATUS_RECEIVED, POLICY_VIOLATION, PROTOCOL_ERROR, UNSUPPORTED_DATA,
+};
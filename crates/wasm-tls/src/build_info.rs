@@ -0,0 +1,100 @@
+//! What the loaded module was built with, for the JS side to log or check.
+
+use wasm_bindgen::prelude::*;
+
+/// rustls version this crate is pinned to in Cargo.toml.
+const RUSTLS_VERSION: &str = "0.23.45";
+
+const CRYPTO_PROVIDER: &str = "rustls-rustcrypto";
+
+/// Cargo features of this crate, and whether each is compiled in.
+const FEATURES: [(&str, bool); 5] = [
+    ("brotli", cfg!(feature = "brotli")),
+    ("zstd", cfg!(feature = "zstd")),
+    ("psl", cfg!(feature = "psl")),
+    ("serde", cfg!(feature = "serde")),
+    (
+        "dangerous-js-verifier",
+        cfg!(feature = "dangerous-js-verifier"),
+    ),
+];
+
+#[derive(Debug, PartialEq, Eq)]
+struct BuildInfo {
+    version: &'static str,
+    rustls_version: &'static str,
+    crypto_provider: &'static str,
+    bundled_roots: usize,
+    features: Vec<&'static str>,
+    tls_versions: Vec<&'static str>,
+}
+
+impl BuildInfo {
+    fn current() -> BuildInfo {
+        let tls_versions = rustls::ALL_VERSIONS
+            .iter()
+            .filter_map(|v| match v.version {
+                rustls::ProtocolVersion::TLSv1_3 => Some("1.3"),
+                rustls::ProtocolVersion::TLSv1_2 => Some("1.2"),
+                _ => None,
+            })
+            .collect();
+        BuildInfo {
+            version: env!("CARGO_PKG_VERSION"),
+            rustls_version: RUSTLS_VERSION,
+            crypto_provider: CRYPTO_PROVIDER,
+            bundled_roots: webpki_roots::TLS_SERVER_ROOTS.len(),
+            features: FEATURES
+                .iter()
+                .filter(|(_, enabled)| *enabled)
+                .map(|(name, _)| *name)
+                .collect(),
+            tls_versions,
+        }
+    }
+
+    fn to_js(&self) -> Result<JsValue, JsValue> {
+        let strings = |items: &[&str]| {
+            items
+                .iter()
+                .map(|s| JsValue::from_str(s))
+                .collect::<js_sys::Array>()
+        };
+        let object = js_sys::Object::new();
+        let set = |key: &str, value: JsValue| {
+            js_sys::Reflect::set(&object, &JsValue::from_str(key), &value).map(drop)
+        };
+        set("version", self.version.into())?;
+        set("rustlsVersion", self.rustls_version.into())?;
+        set("cryptoProvider", self.crypto_provider.into())?;
+        set("bundledRoots", (self.bundled_roots > 0).into())?;
+        set("bundledRootCount", (self.bundled_roots as f64).into())?;
+        set("features", strings(&self.features).into())?;
+        set("tlsVersions", strings(&self.tls_versions).into())?;
+        Ok(object.into())
+    }
+}
+
+/// Build details of this wasm module as an object: `version`, `rustlsVersion`,
+/// `cryptoProvider`, `bundledRoots` (and `bundledRootCount`), `features` (the
+/// Cargo features compiled in, e.g. "brotli") and `tlsVersions` ("1.3", "1.2").
+#[wasm_bindgen]
+pub fn build_info() -> Result<JsValue, JsError> {
+    BuildInfo::current()
+        .to_js()
+        .map_err(|e| JsError::new(&crate::js_error_message(&e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_the_compiled_capabilities() {
+        let info = BuildInfo::current();
+        assert_eq!(info.version, "0.1.0");
+        assert_eq!(info.tls_versions, ["1.3", "1.2"]);
+        assert!(info.bundled_roots > 100);
+        assert_eq!(info.features.contains(&"brotli"), cfg!(feature = "brotli"));
+    }
+}
@@ -0,0 +1,60 @@
+//! The wall clock rustls reads: session tickets are stamped with it, the ticket
+//! age of a resumption offer is computed from it and certificates are checked
+//! against it. The system clock (`Date.now()` on wasm) until the caller sets it.
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use rustls::pki_types::UnixTime;
+use rustls::time_provider::{DefaultTimeProvider, TimeProvider};
+
+#[derive(Debug, Default)]
+pub(crate) struct Clock {
+    /// Unix milliseconds the caller set last
+    unix_ms: Mutex<Option<u64>>,
+}
+
+impl Clock {
+    pub(crate) fn new(unix_ms: Option<u64>) -> Self {
+        Clock {
+            unix_ms: Mutex::new(unix_ms),
+        }
+    }
+
+    pub(crate) fn set(&self, unix_ms: u64) {
+        if let Ok(mut now) = self.unix_ms.lock() {
+            *now = Some(unix_ms);
+        }
+    }
+}
+
+impl TimeProvider for Clock {
+    fn current_time(&self) -> Option<UnixTime> {
+        match *self.unix_ms.lock().ok()? {
+            Some(ms) => Some(UnixTime::since_unix_epoch(Duration::from_millis(ms))),
+            None => DefaultTimeProvider.current_time(),
+        }
+    }
+}
+
+/// A JS time in milliseconds, clamped to what a `Clock` holds.
+pub(crate) fn millis(ms: f64) -> u64 {
+    // `as` saturates, and maps NaN to 0
+    ms.max(0.0) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_time_overrides_the_system_clock() {
+        let clock = Clock::default();
+        let system = clock.current_time().unwrap().as_secs();
+        assert!(system > 1_700_000_000);
+        clock.set(1_000_999);
+        assert_eq!(clock.current_time().unwrap().as_secs(), 1_000);
+        assert_eq!(millis(-5.0), 0);
+        assert_eq!(millis(f64::NAN), 0);
+    }
+}
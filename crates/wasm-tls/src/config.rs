@@ -0,0 +1,385 @@
+//! The JSON config object of `TlsConnection::from_json`.
+
+use rustls::SupportedProtocolVersion;
+use wasm_http::base64;
+use wasm_http::json::{self, Value};
+
+use crate::options::TlsOptions;
+
+const FIELDS: &[&str] = &[
+    "hostname",
+    "alpn",
+    "versions",
+    "minVersion",
+    "rawPublicKeys",
+    "roots",
+    "clientCert",
+    "suppressExtensions",
+    "suppressKeyShares",
+    "fingerprintTarget",
+    "cipherSuites",
+    "groups",
+    "reorderChain",
+    "enforceMustStaple",
+    "earlyData",
+    "maxHandshakeSize",
+    "compactionThreshold",
+    "keyUpdateAfterBytes",
+    "maxChunkSize",
+    "maxPlaintextFragment",
+    "currentTime",
+    "ticketAge",
+    "echConfigList",
+];
+
+/// A parsed config object: the constructor arguments plus the options.
+#[derive(Debug)]
+pub(crate) struct Config {
+    pub(crate) hostname: String,
+    /// Comma-separated, as the constructors take it
+    pub(crate) alpn: String,
+    pub(crate) versions: Vec<&'static SupportedProtocolVersion>,
+    /// DER SubjectPublicKeyInfo pins; authenticates by raw public key when set
+    pub(crate) raw_public_keys: Option<Vec<Vec<u8>>>,
+    pub(crate) options: TlsOptions,
+}
+
+/// Parse a config object. Errors name the offending field, e.g. `roots[1].der`.
+pub(crate) fn parse(text: &str) -> Result<Config, String> {
+    let root = json::parse(text).ok_or("Config is not valid JSON")?;
+    let fields = object(&root, "config", FIELDS)?;
+    let mut config = Config {
+        hostname: String::new(),
+        alpn: String::new(),
+        versions: rustls::DEFAULT_VERSIONS.to_vec(),
+        raw_public_keys: None,
+        options: TlsOptions::new(),
+    };
+    let mut versions_given = false;
+    for (key, value) in fields {
+        let key = key.as_str();
+        let options = &mut config.options;
+        match key {
+            "hostname" => config.hostname = string(value, key)?,
+            "alpn" => {
+                let protocols = strings(value, key)?;
+                if protocols.iter().any(|p| p.is_empty() || p.contains(',')) {
+                    return Err(invalid(key, "non-empty protocol names without commas"));
+                }
+                config.alpn = protocols.join(",");
+            }
+            "versions" => {
+                versions_given = true;
+                config.versions = Vec::new();
+                for (i, version) in strings(value, key)?.iter().enumerate() {
+                    config.versions.push(match version.as_str() {
+                        "1.2" => &rustls::version::TLS12,
+                        "1.3" => &rustls::version::TLS13,
+                        _ => return Err(invalid(&format!("{}[{}]", key, i), "\"1.2\" or \"1.3\"")),
+                    });
+                }
+                if config.versions.is_empty() {
+                    return Err(invalid(key, "at least one version"));
+                }
+            }
+            "minVersion" => options
+                .set_min_version(&string(value, key)?)
+                .map_err(|_| invalid(key, "\"1.2\" or \"1.3\""))?,
+            "rawPublicKeys" => {
+                let keys = array(value, key)?
+                    .iter()
+                    .enumerate()
+                    .map(|(i, v)| bytes(v, &format!("{}[{}]", key, i)))
+                    .collect::<Result<_, _>>()?;
+                config.raw_public_keys = Some(keys);
+            }
+            "roots" => {
+                for (i, root) in array(value, key)?.iter().enumerate() {
+                    let path = format!("{}[{}]", key, i);
+                    object(root, &path, &["hostname", "der"])?;
+                    let hostname = string(member(root, &path, "hostname")?, &path)?;
+                    let der = bytes(member(root, &path, "der")?, &format!("{}.der", path))?;
+                    options
+                        .add_root(&hostname, der)
+                        .map_err(|e| format!("Config field {:?}: {}", path, e))?;
+                }
+            }
+            "clientCert" => {
+                object(value, key, &["chain", "key"])?;
+                let chain = array(member(value, key, "chain")?, "clientCert.chain")?
+                    .iter()
+                    .enumerate()
+                    .map(|(i, v)| bytes(v, &format!("clientCert.chain[{}]", i)))
+                    .collect::<Result<_, _>>()?;
+                let der = bytes(member(value, key, "key")?, "clientCert.key")?;
+                options
+                    .set_client_cert(chain, der)
+                    .map_err(|e| format!("Config field {:?}: {}", key, e))?;
+            }
+            "suppressExtensions" => {
+                for extension in numbers(value, key)? {
+                    options
+                        .insert_suppressed_extension(extension)
+                        .map_err(|e| format!("Config field {:?}: {}", key, e))?;
+                }
+            }
+            "suppressKeyShares" => {
+                for group in numbers(value, key)? {
+                    options
+                        .insert_suppressed_group(group)
+                        .map_err(|e| format!("Config field {:?}: {}", key, e))?;
+                }
+            }
+            "cipherSuites" => options
+                .order_cipher_suites(&numbers(value, key)?)
+                .map_err(|e| format!("Config field {:?}: {}", key, e))?,
+            "groups" => options
+                .order_groups(&numbers(value, key)?)
+                .map_err(|e| format!("Config field {:?}: {}", key, e))?,
+            "fingerprintTarget" => {
+                const LISTS: &[&str] = &["cipherSuites", "groups", "extensions"];
+                object(value, key, LISTS)?;
+                let mut lists = Vec::new();
+                for list in LISTS {
+                    let path = format!("{}.{}", key, list);
+                    lists.push(match value.get(list) {
+                        Some(v) => numbers(v, &path)?,
+                        None => Vec::new(),
+                    });
+                }
+                let [suites, groups, extensions] = <[Vec<u16>; 3]>::try_from(lists).unwrap();
+                options.set_fingerprint_target(suites, groups, extensions);
+            }
+            "reorderChain" => options.set_reorder_chain(flag(value, key)?),
+            "enforceMustStaple" => options.set_enforce_must_staple(flag(value, key)?),
+            "earlyData" => options.set_early_data(flag(value, key)?),
+            "maxHandshakeSize" => options.set_max_handshake_size(size(value, key)?),
+            "compactionThreshold" => options.set_compaction_threshold(size(value, key)?),
+            "keyUpdateAfterBytes" => options.set_key_update_after_bytes(size(value, key)?),
+            "maxChunkSize" => options.set_max_chunk_size(size(value, key)?),
+            "maxPlaintextFragment" => options.set_max_plaintext_fragment(size(value, key)?),
+            "currentTime" => options.set_current_time(millis(value, key)? as f64),
+            "ticketAge" => options.set_ticket_age(millis(value, key)? as f64),
+            "echConfigList" => options
+                .set_ech_configs(&bytes(value, key)?)
+                .map_err(|e| format!("Config field {:?}: {}", key, e))?,
+            _ => unreachable!("checked against FIELDS"),
+        }
+    }
+    if config.hostname.is_empty() {
+        return Err("Config field \"hostname\" is missing".to_string());
+    }
+    if config.raw_public_keys.is_some() {
+        if versions_given && config.versions != [&rustls::version::TLS13] {
+            return Err(invalid("versions", "[\"1.3\"] with rawPublicKeys"));
+        }
+        config.versions = vec![&rustls::version::TLS13];
+    }
+    if config.options.ech_config.is_some() {
+        if versions_given && config.versions != [&rustls::version::TLS13] {
+            return Err(invalid("versions", "[\"1.3\"] with echConfigList"));
+        }
+        config.versions = vec![&rustls::version::TLS13];
+    }
+    Ok(config)
+}
+
+fn invalid(path: &str, expected: &str) -> String {
+    format!("Config field {:?}: expected {}", path, expected)
+}
+
+/// Members of an object whose keys all appear in `allowed`.
+fn object<'a>(
+    value: &'a Value,
+    path: &str,
+    allowed: &[&str],
+) -> Result<&'a [(String, Value)], String> {
+    let Value::Object(members) = value else {
+        return Err(invalid(path, "an object"));
+    };
+    if let Some((key, _)) = members.iter().find(|(k, _)| !allowed.contains(&k.as_str())) {
+        return Err(format!("Config field {:?} in {:?} is unknown", key, path));
+    }
+    Ok(members)
+}
+
+fn member<'a>(value: &'a Value, path: &str, key: &str) -> Result<&'a Value, String> {
+    value
+        .get(key)
+        .ok_or_else(|| format!("Config field {:?} is missing in {:?}", key, path))
+}
+
+fn array<'a>(value: &'a Value, path: &str) -> Result<&'a [Value], String> {
+    match value {
+        Value::Array(items) => Ok(items),
+        _ => Err(invalid(path, "an array")),
+    }
+}
+
+fn string(value: &Value, path: &str) -> Result<String, String> {
+    value
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| invalid(path, "a string"))
+}
+
+fn strings(value: &Value, path: &str) -> Result<Vec<String>, String> {
+    array(value, path)?
+        .iter()
+        .enumerate()
+        .map(|(i, v)| string(v, &format!("{}[{}]", path, i)))
+        .collect()
+}
+
+fn flag(value: &Value, path: &str) -> Result<bool, String> {
+    value
+        .as_bool()
+        .ok_or_else(|| invalid(path, "true or false"))
+}
+
+fn integer(value: &Value, path: &str, max: u64) -> Result<u64, String> {
+    match value {
+        Value::Number(n) if n.fract() == 0.0 && *n >= 0.0 && *n <= max as f64 => Ok(*n as u64),
+        _ => Err(invalid(path, &format!("an integer from 0 to {}", max))),
+    }
+}
+
+fn size(value: &Value, path: &str) -> Result<usize, String> {
+    // Exact in an f64, and within a wasm32 usize
+    integer(value, path, u32::MAX as u64).map(|n| n as usize)
+}
+
+/// Milliseconds, exact in an f64.
+fn millis(value: &Value, path: &str) -> Result<u64, String> {
+    integer(value, path, (1 << 53) - 1)
+}
+
+/// IANA code points.
+fn numbers(value: &Value, path: &str) -> Result<Vec<u16>, String> {
+    array(value, path)?
+        .iter()
+        .enumerate()
+        .map(|(i, v)| integer(v, &format!("{}[{}]", path, i), u16::MAX as u64).map(|n| n as u16))
+        .collect()
+}
+
+/// Standard base64, padding optional.
+fn bytes(value: &Value, path: &str) -> Result<Vec<u8>, String> {
+    let text = value
+        .as_str()
+        .ok_or_else(|| invalid(path, "a base64 string"))?;
+    base64::decode(text).ok_or_else(|| invalid(path, "a base64 string"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRIVATE_CA: &[u8] = include_bytes!("../tests/fixtures/private-ca.der");
+
+    #[test]
+    fn full_config() {
+        let json = format!(
+            r#"{{
+                "hostname": "internal.example",
+                "alpn": ["h2", "http/1.1"],
+                "versions": ["1.3"],
+                "minVersion": "1.3",
+                "roots": [{{"hostname": "internal.example", "der": "{}"}}],
+                "suppressExtensions": [35],
+                "cipherSuites": [4867, 4865],
+                "groups": [23],
+                "fingerprintTarget": {{"cipherSuites": [4865], "groups": [29]}},
+                "earlyData": true,
+                "maxChunkSize": 16384,
+                "maxPlaintextFragment": 1200,
+                "currentTime": 1760400000000
+            }}"#,
+            base64::encode(PRIVATE_CA)
+        );
+        let config = parse(&json).unwrap();
+        assert_eq!(config.alpn, "h2,http/1.1");
+        assert_eq!(config.versions, [&rustls::version::TLS13]);
+        let options = &config.options;
+        assert_eq!(
+            options.roots_for("internal.example").len(),
+            crate::get_root_store().len() + 1
+        );
+        assert!(options.suppresses(35) && options.early_data);
+        assert_eq!(options.cipher_suite_order, [0x1303, 0x1301]);
+        assert_eq!(options.group_order, [23]);
+        assert_eq!(options.max_chunk_size, 16384);
+        assert_eq!(options.max_plaintext_fragment, 1200);
+        assert_eq!(options.min_version, Some(0x0304));
+        assert_eq!(options.current_time, Some(1_760_400_000_000));
+        let target = options.fingerprint_target.as_ref().unwrap();
+        assert_eq!(
+            (&target.groups[..], target.extensions.len()),
+            (&[29][..], 0)
+        );
+    }
+
+    #[test]
+    fn ech_config_list_implies_tls13() {
+        let list = base64::encode(&crate::tests::ech_config_list("public.example", &[9; 32]));
+        let json = format!(
+            r#"{{"hostname": "a.example", "echConfigList": "{}"}}"#,
+            list
+        );
+        let config = parse(&json).unwrap();
+        assert!(config.options.ech_config.is_some());
+        assert_eq!(config.versions, [&rustls::version::TLS13]);
+
+        let json = format!(
+            r#"{{"hostname": "a.example", "versions": ["1.2"], "echConfigList": "{}"}}"#,
+            list
+        );
+        assert_eq!(
+            parse(&json).unwrap_err(),
+            "Config field \"versions\": expected [\"1.3\"] with echConfigList"
+        );
+    }
+
+    #[test]
+    fn errors_name_the_field() {
+        for (json, error) in [
+            ("[]", "Config field \"config\": expected an object"),
+            ("{\"alpn\": []}", "Config field \"hostname\" is missing"),
+            (
+                r#"{"hostname": "a", "grease": true}"#,
+                "Config field \"grease\" in \"config\" is unknown",
+            ),
+            (
+                r#"{"hostname": "a", "signatureSchemes": [2052]}"#,
+                "Config field \"signatureSchemes\" in \"config\" is unknown",
+            ),
+            (
+                r#"{"hostname": "a", "groups": [29, 29]}"#,
+                "Config field \"groups\": Group 0x001d is listed twice",
+            ),
+            (
+                r#"{"hostname": "a", "versions": ["1.3", "1.1"]}"#,
+                "Config field \"versions[1]\": expected \"1.2\" or \"1.3\"",
+            ),
+            (
+                r#"{"hostname": "a", "roots": [{"hostname": "a", "der": "!"}]}"#,
+                "Config field \"roots[0].der\": expected a base64 string",
+            ),
+            (
+                r#"{"hostname": "a", "maxChunkSize": -1}"#,
+                "Config field \"maxChunkSize\": expected an integer from 0 to 4294967295",
+            ),
+            (
+                r#"{"hostname": "a", "suppressExtensions": [51]}"#,
+                "Config field \"suppressExtensions\": Extension 51 cannot be suppressed: \
+                 rustls requires it for TLS 1.3",
+            ),
+            (
+                r#"{"hostname": "a", "rawPublicKeys": [], "versions": ["1.2"]}"#,
+                "Config field \"versions\": expected [\"1.3\"] with rawPublicKeys",
+            ),
+        ] {
+            assert_eq!(parse(json).unwrap_err(), error, "{}", json);
+        }
+    }
+}
@@ -0,0 +1,288 @@
+//! Minimal DER walking for the few certificate fields rustls/webpki don't expose.
+//! Only definite-length encodings are accepted (DER forbids anything else).
+
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_CONTEXT_0: u8 = 0xa0;
+const TAG_CONTEXT_3: u8 = 0xa3;
+const TAG_OID: u8 = 0x06;
+const TAG_INTEGER: u8 = 0x02;
+
+/// id-pe-tlsfeature (RFC 7633), 1.3.6.1.5.5.7.1.24
+const OID_TLS_FEATURE: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x01, 0x18];
+/// status_request, the TLS extension whose presence in the TLS feature list means must-staple
+const STATUS_REQUEST: &[u8] = &[0x05];
+
+/// Split one TLV off the front of `input`: returns (tag, value, rest).
+fn read_tlv(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = input.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = if first < 0x80 {
+        (first as usize, rest)
+    } else {
+        let n = (first & 0x7f) as usize;
+        if n == 0 || n > 4 || rest.len() < n {
+            return None;
+        }
+        let len = rest[..n]
+            .iter()
+            .fold(0usize, |acc, &b| (acc << 8) | b as usize);
+        (len, &rest[n..])
+    };
+    if rest.len() < len {
+        return None;
+    }
+    Some((tag, &rest[..len], &rest[len..]))
+}
+
+fn expect(input: &[u8], tag: u8) -> Option<(&[u8], &[u8])> {
+    match read_tlv(input)? {
+        (t, value, rest) if t == tag => Some((value, rest)),
+        _ => None,
+    }
+}
+
+/// Issuer and subject Names of a certificate, as the value bytes of each Name
+/// SEQUENCE (the same form webpki uses for `TrustAnchor::subject`).
+pub(crate) struct CertNames<'a> {
+    pub issuer: &'a [u8],
+    pub subject: &'a [u8],
+}
+
+pub(crate) fn cert_names(cert: &[u8]) -> Option<CertNames<'_>> {
+    tbs_names(cert).map(|(names, _)| names)
+}
+
+/// Names of a certificate, and the TBSCertificate fields after the subject.
+fn tbs_names(cert: &[u8]) -> Option<(CertNames<'_>, &[u8])> {
+    let (certificate, _) = expect(cert, TAG_SEQUENCE)?;
+    let (tbs, _) = expect(certificate, TAG_SEQUENCE)?;
+    // version [0] EXPLICIT is optional
+    let rest = match read_tlv(tbs)? {
+        (TAG_CONTEXT_0, _, rest) => rest,
+        _ => tbs,
+    };
+    let (_serial, _, rest) = read_tlv(rest)?;
+    let (_signature, rest) = expect(rest, TAG_SEQUENCE)?;
+    let (issuer, rest) = expect(rest, TAG_SEQUENCE)?;
+    let (_validity, rest) = expect(rest, TAG_SEQUENCE)?;
+    let (subject, rest) = expect(rest, TAG_SEQUENCE)?;
+    Some((CertNames { issuer, subject }, rest))
+}
+
+/// Whether `spki` is a DER SubjectPublicKeyInfo: an algorithm identifier and a
+/// BIT STRING key, nothing after it.
+pub(crate) fn is_spki(spki: &[u8]) -> bool {
+    let Some((fields, [])) = expect(spki, TAG_SEQUENCE) else {
+        return false;
+    };
+    let Some((_algorithm, rest)) = expect(fields, TAG_SEQUENCE) else {
+        return false;
+    };
+    matches!(expect(rest, 0x03), Some((_, [])))
+}
+
+/// Whether the certificate's TLS feature extension lists status_request, i.e. the
+/// server must staple an OCSP response (RFC 7633 "must-staple").
+pub(crate) fn has_must_staple(cert: &[u8]) -> Option<bool> {
+    let (_, rest) = tbs_names(cert)?;
+    let (_spki, mut rest) = expect(rest, TAG_SEQUENCE)?;
+    // issuerUniqueID [1] and subjectUniqueID [2] may precede extensions [3]
+    let mut extensions = loop {
+        if rest.is_empty() {
+            return Some(false);
+        }
+        let (tag, value, next) = read_tlv(rest)?;
+        if tag == TAG_CONTEXT_3 {
+            break expect(value, TAG_SEQUENCE)?.0;
+        }
+        rest = next;
+    };
+    while !extensions.is_empty() {
+        let (extension, next) = expect(extensions, TAG_SEQUENCE)?;
+        extensions = next;
+        let (oid, fields) = expect(extension, TAG_OID)?;
+        if oid != OID_TLS_FEATURE {
+            continue;
+        }
+        // critical BOOLEAN DEFAULT FALSE, then the extnValue OCTET STRING
+        let (tag, value, fields) = read_tlv(fields)?;
+        let value = if tag == 0x01 {
+            expect(fields, 0x04)?.0
+        } else {
+            value
+        };
+        let (mut features, _) = expect(value, TAG_SEQUENCE)?;
+        while !features.is_empty() {
+            let (feature, next) = expect(features, TAG_INTEGER)?;
+            if feature == STATUS_REQUEST {
+                return Some(true);
+            }
+            features = next;
+        }
+        return Some(false);
+    }
+    Some(false)
+}
+
+/// An ECDSA-Sig-Value (SEQUENCE of INTEGER r and s) as the fixed-size `r || s`
+/// with `size` bytes each, the form JOSE and HTTP message signatures use.
+pub(crate) fn ecdsa_raw(signature: &[u8], size: usize) -> Option<Vec<u8>> {
+    let (fields, []) = expect(signature, TAG_SEQUENCE)? else {
+        return None;
+    };
+    let (r, rest) = expect(fields, TAG_INTEGER)?;
+    let (s, []) = expect(rest, TAG_INTEGER)? else {
+        return None;
+    };
+    let mut out = Vec::with_capacity(2 * size);
+    for integer in [r, s] {
+        // DER adds a zero byte before a high bit and drops leading zeros
+        let digits = match integer {
+            [0, rest @ ..] => rest,
+            digits => digits,
+        };
+        if digits.len() > size {
+            return None;
+        }
+        out.resize(out.len() + size - digits.len(), 0);
+        out.extend_from_slice(digits);
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Wrap `value` in a TLV with the given tag.
+    pub(crate) fn tlv(tag: u8, value: &[u8]) -> Vec<u8> {
+        let mut out = vec![tag];
+        if value.len() < 0x80 {
+            out.push(value.len() as u8);
+        } else {
+            out.push(0x82);
+            out.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        }
+        out.extend_from_slice(value);
+        out
+    }
+
+    /// A structurally valid (unsigned) certificate with the given names.
+    pub(crate) fn fake_cert(issuer: &[u8], subject: &[u8]) -> Vec<u8> {
+        let mut tbs = tlv(TAG_CONTEXT_0, &tlv(0x02, &[2]));
+        tbs.extend(tlv(0x02, &[1]));
+        tbs.extend(tlv(TAG_SEQUENCE, &[]));
+        tbs.extend(tlv(TAG_SEQUENCE, issuer));
+        tbs.extend(tlv(TAG_SEQUENCE, &[]));
+        tbs.extend(tlv(TAG_SEQUENCE, subject));
+        tbs.extend(tlv(TAG_SEQUENCE, &[]));
+        let mut cert = tlv(TAG_SEQUENCE, &tbs);
+        cert.extend(tlv(TAG_SEQUENCE, &[]));
+        cert.extend(tlv(0x03, &[0]));
+        tlv(TAG_SEQUENCE, &cert)
+    }
+
+    #[test]
+    fn extracts_issuer_and_subject() {
+        let cert = fake_cert(b"ca-name", b"leaf-name");
+        let names = cert_names(&cert).unwrap();
+        assert_eq!(names.issuer, b"ca-name");
+        assert_eq!(names.subject, b"leaf-name");
+    }
+
+    #[test]
+    fn handles_long_form_lengths_and_missing_version() {
+        let long_subject = vec![b'x'; 300];
+        let mut tbs = tlv(0x02, &[1]);
+        tbs.extend(tlv(TAG_SEQUENCE, &[]));
+        tbs.extend(tlv(TAG_SEQUENCE, b"issuer"));
+        tbs.extend(tlv(TAG_SEQUENCE, &[]));
+        tbs.extend(tlv(TAG_SEQUENCE, &long_subject));
+        let cert = tlv(TAG_SEQUENCE, &tlv(TAG_SEQUENCE, &tbs));
+        let names = cert_names(&cert).unwrap();
+        assert_eq!(names.issuer, b"issuer");
+        assert_eq!(names.subject, &long_subject[..]);
+    }
+
+    /// `fake_cert` with a subjectPublicKeyInfo and the given extensions.
+    pub(crate) fn fake_cert_with_extensions(extensions: &[Vec<u8>]) -> Vec<u8> {
+        let mut tbs = tlv(TAG_CONTEXT_0, &tlv(0x02, &[2]));
+        tbs.extend(tlv(0x02, &[1]));
+        tbs.extend(tlv(TAG_SEQUENCE, &[]));
+        tbs.extend(tlv(TAG_SEQUENCE, b"issuer"));
+        tbs.extend(tlv(TAG_SEQUENCE, &[]));
+        tbs.extend(tlv(TAG_SEQUENCE, b"subject"));
+        tbs.extend(tlv(TAG_SEQUENCE, &[]));
+        tbs.extend(tlv(0x81, &[0]));
+        if !extensions.is_empty() {
+            tbs.extend(tlv(TAG_CONTEXT_3, &tlv(TAG_SEQUENCE, &extensions.concat())));
+        }
+        tlv(TAG_SEQUENCE, &tlv(TAG_SEQUENCE, &tbs))
+    }
+
+    /// A TLS feature extension listing `features`, optionally marked critical.
+    pub(crate) fn tls_feature(features: &[u8], critical: bool) -> Vec<u8> {
+        let list: Vec<u8> = features
+            .iter()
+            .flat_map(|&f| tlv(TAG_INTEGER, &[f]))
+            .collect();
+        let mut extension = tlv(TAG_OID, OID_TLS_FEATURE);
+        if critical {
+            extension.extend(tlv(0x01, &[0xff]));
+        }
+        extension.extend(tlv(0x04, &tlv(TAG_SEQUENCE, &list)));
+        tlv(TAG_SEQUENCE, &extension)
+    }
+
+    #[test]
+    fn finds_must_staple() {
+        // basicConstraints, to be skipped
+        let other = tlv(
+            TAG_SEQUENCE,
+            &[tlv(TAG_OID, &[0x55, 0x1d, 0x13]), tlv(0x04, &[0x30, 0])].concat(),
+        );
+        let cert = |exts: &[Vec<u8>]| has_must_staple(&fake_cert_with_extensions(exts));
+        assert_eq!(cert(&[other.clone(), tls_feature(&[5], false)]), Some(true));
+        assert_eq!(cert(&[tls_feature(&[17, 5], true)]), Some(true));
+        assert_eq!(cert(&[tls_feature(&[17], false)]), Some(false));
+        assert_eq!(cert(&[other]), Some(false));
+        assert_eq!(cert(&[]), Some(false));
+        assert_eq!(has_must_staple(&fake_cert(b"a", b"b")), Some(false));
+        assert_eq!(has_must_staple(&[0x30, 0x00]), None);
+    }
+
+    #[test]
+    fn recognizes_spki() {
+        let spki = tlv(
+            TAG_SEQUENCE,
+            &[tlv(TAG_SEQUENCE, &[]), tlv(0x03, &[0, 4])].concat(),
+        );
+        assert!(is_spki(&spki));
+        assert!(!is_spki(&spki[..spki.len() - 1]));
+        assert!(!is_spki(&[spki.clone(), vec![0]].concat()));
+        assert!(!is_spki(&fake_cert(b"a", b"b")));
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        let cert = fake_cert(b"a", b"b");
+        assert!(cert_names(&cert[..cert.len() - 20]).is_none());
+        assert!(cert_names(&[]).is_none());
+    }
+
+    #[test]
+    fn ecdsa_signature_to_fixed_size() {
+        let mut high = vec![0u8];
+        high.extend([0x80; 4]);
+        let der = tlv(
+            TAG_SEQUENCE,
+            &[tlv(TAG_INTEGER, &high), tlv(TAG_INTEGER, &[0x01, 0x02])].concat(),
+        );
+        assert_eq!(
+            ecdsa_raw(&der, 4).unwrap(),
+            [0x80, 0x80, 0x80, 0x80, 0, 0, 0x01, 0x02]
+        );
+        assert_eq!(ecdsa_raw(&der, 3), None);
+        assert_eq!(ecdsa_raw(&der[..der.len() - 1], 4), None);
+    }
+}
@@ -0,0 +1,378 @@
+use rustls::internal::msgs::codec::Codec;
+use rustls::{
+    AlertDescription, CertificateError, Error, PeerIncompatible, PeerMisbehaved, ProtocolVersion,
+};
+use wasm_bindgen::prelude::*;
+
+/// Details of a certificate that is not valid for the requested server name.
+#[wasm_bindgen]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HostnameMismatch {
+    expected: String,
+    presented: Vec<String>,
+}
+
+#[wasm_bindgen]
+impl HostnameMismatch {
+    /// Server name we asked for (the SNI hostname).
+    pub fn expected(&self) -> String {
+        self.expected.clone()
+    }
+
+    /// Names from the leaf certificate's subjectAltName extension
+    /// (DNS names, possibly wildcards, and IP addresses). Empty if unknown.
+    pub fn presented(&self) -> Vec<String> {
+        self.presented.clone()
+    }
+}
+
+/// Details of a handshake aborted because the server sent more than
+/// `TlsOptions::set_max_handshake_size` bytes before completing it.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HandshakeTooLarge {
+    limit: usize,
+    received: usize,
+}
+
+#[wasm_bindgen]
+impl HandshakeTooLarge {
+    /// Configured limit in bytes.
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Handshake bytes received, including the input that crossed the limit.
+    pub fn received(&self) -> usize {
+        self.received
+    }
+}
+
+impl HandshakeTooLarge {
+    pub(crate) fn new(limit: usize, received: usize) -> Self {
+        HandshakeTooLarge { limit, received }
+    }
+}
+
+/// Details of a handshake refused because the server negotiated a protocol
+/// version below `TlsOptions::require_min_version`.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VersionTooLow {
+    required: u16,
+    negotiated: u16,
+}
+
+#[wasm_bindgen]
+impl VersionTooLow {
+    /// The configured floor, as a code point (0x0303 TLS 1.2, 0x0304 TLS 1.3).
+    pub fn required(&self) -> u16 {
+        self.required
+    }
+
+    /// The version the server chose, as a code point.
+    pub fn negotiated(&self) -> u16 {
+        self.negotiated
+    }
+}
+
+impl VersionTooLow {
+    /// The violation, if `negotiated` is known and below `required`.
+    pub(crate) fn check(required: u16, negotiated: Option<ProtocolVersion>) -> Option<Self> {
+        let negotiated = u16::from(negotiated?);
+        (negotiated < required).then_some(VersionTooLow {
+            required,
+            negotiated,
+        })
+    }
+
+    /// Error thrown to JS, e.g. "TLS version too low: negotiated TLS 1.2, required TLS 1.3".
+    pub(crate) fn message(&self) -> String {
+        format!(
+            "TLS version too low: negotiated {}, required {}",
+            version_name(self.negotiated),
+            version_name(self.required)
+        )
+    }
+}
+
+fn version_name(version: u16) -> String {
+    match version {
+        0x0301..=0x0304 => format!("TLS 1.{}", version - 0x0301),
+        _ => format!("version 0x{:04x}", version),
+    }
+}
+
+/// Details of a handshake that failed because this connection and the server
+/// share no cipher suite, key exchange group or protocol version, typically after
+/// `TlsOptions::suppress_key_share` or a version list narrowed what is offered.
+#[wasm_bindgen]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NegotiationFailure {
+    kind: Negotiated,
+    alert: Option<u8>,
+    offered: Vec<u16>,
+}
+
+/// The parameter a `NegotiationFailure` found no overlap for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Negotiated {
+    CipherSuite,
+    KeyExchange,
+    Version,
+}
+
+impl Negotiated {
+    fn as_str(self) -> &'static str {
+        match self {
+            Negotiated::CipherSuite => "cipher-suite",
+            Negotiated::KeyExchange => "key-exchange",
+            Negotiated::Version => "version",
+        }
+    }
+
+    /// Prefix of the error thrown to JS.
+    pub(crate) fn message(self) -> &'static str {
+        match self {
+            Negotiated::CipherSuite => "No common cipher suite",
+            Negotiated::KeyExchange => "No common key exchange group",
+            Negotiated::Version => "No common TLS version",
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl NegotiationFailure {
+    /// What found no overlap: "cipher-suite", "key-exchange" or "version".
+    pub fn kind(&self) -> String {
+        self.kind.as_str().to_string()
+    }
+
+    /// Alert the server refused the ClientHello with (40 handshake_failure,
+    /// 70 protocol_version, 71 insufficient_security), if it was the server that
+    /// gave up rather than rustls rejecting the server's choice.
+    pub fn alert(&self) -> Option<u8> {
+        self.alert
+    }
+
+    /// IANA code points this connection offered of that kind: cipher suites,
+    /// named groups, or protocol versions (0x0303, 0x0304).
+    pub fn offered(&self) -> Vec<u16> {
+        self.offered.clone()
+    }
+}
+
+impl NegotiationFailure {
+    pub(crate) fn new(kind: Negotiated, alert: Option<u8>, offered: Vec<u16>) -> Self {
+        NegotiationFailure {
+            kind,
+            alert,
+            offered,
+        }
+    }
+
+    pub(crate) fn message(&self) -> &'static str {
+        self.kind.message()
+    }
+
+    /// What `error` says found no overlap, and the alert it came with.
+    ///
+    /// The server's choice names the parameter when rustls rejects it. A
+    /// handshake_failure or insufficient_security alert in answer to the
+    /// ClientHello (`hello_answered` false) does not: it is put down to the key
+    /// exchange when `groups_narrowed`, since a server sharing one of the listed
+    /// groups would have asked for it with a HelloRetryRequest, and to the cipher
+    /// suites otherwise.
+    pub(crate) fn classify(
+        error: &Error,
+        hello_answered: bool,
+        groups_narrowed: bool,
+    ) -> Option<(Negotiated, Option<u8>)> {
+        let kind = match error {
+            Error::PeerMisbehaved(
+                PeerMisbehaved::SelectedUnofferedCipherSuite
+                | PeerMisbehaved::SelectedUnusableCipherSuiteForVersion,
+            ) => Negotiated::CipherSuite,
+            Error::PeerMisbehaved(
+                PeerMisbehaved::SelectedUnofferedKxGroup
+                | PeerMisbehaved::IllegalHelloRetryRequestWithUnofferedNamedGroup,
+            ) => Negotiated::KeyExchange,
+            Error::PeerMisbehaved(
+                PeerMisbehaved::IllegalHelloRetryRequestWithUnsupportedVersion,
+            )
+            | Error::PeerIncompatible(
+                PeerIncompatible::ServerDoesNotSupportTls12Or13
+                | PeerIncompatible::ServerTlsVersionIsDisabledByOurConfig,
+            ) => Negotiated::Version,
+            Error::AlertReceived(alert) if !hello_answered => {
+                let kind = match alert {
+                    AlertDescription::ProtocolVersion => Negotiated::Version,
+                    AlertDescription::HandshakeFailure | AlertDescription::InsufficientSecurity
+                        if groups_narrowed =>
+                    {
+                        Negotiated::KeyExchange
+                    }
+                    AlertDescription::HandshakeFailure | AlertDescription::InsufficientSecurity => {
+                        Negotiated::CipherSuite
+                    }
+                    _ => return None,
+                };
+                return Some((kind, Some(u8::from(*alert))));
+            }
+            _ => return None,
+        };
+        Some((kind, None))
+    }
+}
+
+impl HostnameMismatch {
+    /// Extract mismatch details from a rustls error, if that is what it reports.
+    pub(crate) fn from_tls_error(error: &Error, hostname: &str) -> Option<HostnameMismatch> {
+        match error {
+            Error::InvalidCertificate(CertificateError::NotValidForNameContext {
+                expected,
+                presented,
+            }) => Some(HostnameMismatch {
+                expected: expected.to_str().into_owned(),
+                presented: presented.clone(),
+            }),
+            Error::InvalidCertificate(CertificateError::NotValidForName) => {
+                Some(HostnameMismatch {
+                    expected: hostname.to_string(),
+                    presented: Vec::new(),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Alert rustls sends when the handshake fails with `error` because our verifier
+/// rejected the server's certificate (RFC 8446 §6.2 codes).
+pub(crate) fn alert_for_rejection(error: &Error) -> Option<u8> {
+    match error {
+        Error::InvalidCertificate(e) => Some(AlertDescription::from(e.clone()).into()),
+        _ => None,
+    }
+}
+
+/// The ECHConfigList a server that rejected ECH offered to retry with (the
+/// `retry_configs` of its encrypted_client_hello extension), encoded the way
+/// `TlsOptions::set_ech_config_list` takes it. None if `error` is not an ECH
+/// rejection or the server sent no configs.
+pub(crate) fn ech_retry_configs(error: &Error) -> Option<Vec<u8>> {
+    match error {
+        Error::PeerIncompatible(PeerIncompatible::ServerRejectedEncryptedClientHello(Some(
+            configs,
+        ))) => {
+            let mut list = Vec::new();
+            configs.encode(&mut list);
+            Some(list)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::pki_types::ServerName;
+
+    #[test]
+    fn maps_name_errors_only() {
+        let error = Error::InvalidCertificate(CertificateError::NotValidForNameContext {
+            expected: ServerName::try_from("api.example.com").unwrap(),
+            presented: vec!["*.example.net".into(), "example.net".into()],
+        });
+        let mismatch = HostnameMismatch::from_tls_error(&error, "ignored").unwrap();
+        assert_eq!(mismatch.expected(), "api.example.com");
+        assert_eq!(mismatch.presented(), ["*.example.net", "example.net"]);
+
+        let bare = Error::InvalidCertificate(CertificateError::NotValidForName);
+        let mismatch = HostnameMismatch::from_tls_error(&bare, "host.test").unwrap();
+        assert_eq!(mismatch.expected(), "host.test");
+        assert!(mismatch.presented().is_empty());
+
+        let other = Error::InvalidCertificate(CertificateError::UnknownIssuer);
+        assert_eq!(HostnameMismatch::from_tls_error(&other, "host.test"), None);
+    }
+
+    #[test]
+    fn negotiation_failures() {
+        let classify = |e, answered, narrowed| NegotiationFailure::classify(&e, answered, narrowed);
+        let alert = |a| Error::AlertReceived(a);
+        assert_eq!(
+            classify(
+                Error::PeerMisbehaved(
+                    PeerMisbehaved::IllegalHelloRetryRequestWithUnofferedNamedGroup
+                ),
+                true,
+                false
+            ),
+            Some((Negotiated::KeyExchange, None))
+        );
+        assert_eq!(
+            classify(alert(AlertDescription::HandshakeFailure), false, false),
+            Some((Negotiated::CipherSuite, Some(40)))
+        );
+        assert_eq!(
+            classify(alert(AlertDescription::InsufficientSecurity), false, true),
+            Some((Negotiated::KeyExchange, Some(71)))
+        );
+        assert_eq!(
+            classify(alert(AlertDescription::ProtocolVersion), false, true),
+            Some((Negotiated::Version, Some(70)))
+        );
+        // After the ServerHello, a handshake_failure is about something else
+        assert_eq!(
+            classify(alert(AlertDescription::HandshakeFailure), true, false),
+            None
+        );
+        assert_eq!(classify(Error::HandshakeNotComplete, false, false), None);
+    }
+
+    #[test]
+    fn version_floor() {
+        let check = |negotiated| VersionTooLow::check(0x0304, negotiated);
+        let low = check(Some(ProtocolVersion::TLSv1_2)).unwrap();
+        assert_eq!((low.required(), low.negotiated()), (0x0304, 0x0303));
+        assert_eq!(
+            low.message(),
+            "TLS version too low: negotiated TLS 1.2, required TLS 1.3"
+        );
+        assert_eq!(check(Some(ProtocolVersion::TLSv1_3)), None);
+        // Not negotiated yet
+        assert_eq!(check(None), None);
+    }
+
+    #[test]
+    fn rejection_alerts() {
+        let alert = |e| alert_for_rejection(&Error::InvalidCertificate(e));
+        // bad_certificate, unknown_ca, certificate_expired, access_denied
+        assert_eq!(alert(CertificateError::NotValidForName), Some(42));
+        assert_eq!(alert(CertificateError::UnknownIssuer), Some(48));
+        assert_eq!(alert(CertificateError::Expired), Some(45));
+        assert_eq!(
+            alert(CertificateError::ApplicationVerificationFailure),
+            Some(49)
+        );
+        assert_eq!(alert_for_rejection(&Error::HandshakeNotComplete), None);
+    }
+
+    #[test]
+    fn retry_configs_are_reencoded() {
+        use rustls::internal::msgs::codec::Reader;
+        use rustls::internal::msgs::handshake::EchConfigPayload;
+
+        let list = crate::tests::ech_config_list("public.example", &[7; 32]);
+        let configs = Vec::<EchConfigPayload>::read(&mut Reader::init(&list)).unwrap();
+        let rejected = |configs| {
+            Error::PeerIncompatible(PeerIncompatible::ServerRejectedEncryptedClientHello(
+                configs,
+            ))
+        };
+        assert_eq!(ech_retry_configs(&rejected(Some(configs))), Some(list));
+        assert_eq!(ech_retry_configs(&rejected(None)), None);
+        assert_eq!(ech_retry_configs(&Error::HandshakeNotComplete), None);
+    }
+}
@@ -0,0 +1,1871 @@
+//! `StealthFetch`: whole requests over pooled TLS connections, HTTP/1.1 or HTTP/2
+//! as ALPN decides, with one browser profile throughout. Sans-IO like the rest of
+//! the module: the JS host owns the sockets, is told through its transport
+//! callbacks what to open, send and close, and reports back what happened.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+use std::mem;
+use std::net::IpAddr;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+use wasm_http::coding::{BodyDecoder, DEFAULT_MAX_DECODED_SIZE};
+use wasm_http::dns::{rcode_name, DohQuery, A, MAX_MESSAGE_SIZE};
+use wasm_http::http1::{Http1Connection, RequestBuilder, ResponseEvent};
+use wasm_http::http2::{ConnectionEvent, Http2Connection, Http2Options};
+use wasm_http::{parse_url, HeaderProfile, Headers, HttpUrl, Timings};
+
+use crate::{js_error_message, TlsConnection, TlsOptions};
+
+/// Connections opened to one HTTP/1.1 origin at most, as browsers do.
+const MAX_CONNECTIONS_PER_ORIGIN: usize = 6;
+/// Finished requests whose timings and connection info are kept.
+const MAX_FINISHED: usize = 256;
+/// RST_STREAM code for a stream we stop reading.
+const CANCEL: u32 = 0x8;
+/// Hosts whose DoH answer is kept.
+const MAX_DNS_CACHE: usize = 256;
+
+/// Options of one `StealthFetch::fetch` call.
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct FetchOptions {
+    method: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    request_type: String,
+}
+
+impl Default for FetchOptions {
+    fn default() -> Self {
+        FetchOptions {
+            method: "GET".to_string(),
+            headers: Vec::new(),
+            body: Vec::new(),
+            request_type: "navigation".to_string(),
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl FetchOptions {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> FetchOptions {
+        FetchOptions::default()
+    }
+
+    /// Request method (default "GET").
+    pub fn set_method(&mut self, method: &str) {
+        self.method = method.to_string();
+    }
+
+    /// Set a header: one the profile sends keeps its position, any other is sent
+    /// after the profile's (see `HeaderProfile::set_header`).
+    pub fn set_header(&mut self, name: &str, value: &str) {
+        self.headers.push((name.to_string(), value.to_string()));
+    }
+
+    pub fn set_body(&mut self, body: Vec<u8>) {
+        self.body = body;
+    }
+
+    /// "navigation" (the default), "fetch" or "xhr": which of the profile's
+    /// header sets to send.
+    pub fn set_request_type(&mut self, request_type: &str) {
+        self.request_type = request_type.to_string();
+    }
+}
+
+/// What the host has to do with its sockets.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Action {
+    /// Open a TCP connection, then call `connected` or `connect_failed`
+    Connect {
+        connection: u32,
+        host: String,
+        port: u16,
+    },
+    Send {
+        connection: u32,
+        data: Vec<u8>,
+    },
+    Close {
+        connection: u32,
+    },
+}
+
+/// Progress of a request: `Head`, any number of `Data` and `Complete`, or
+/// `Failed` at any point.
+#[derive(Debug)]
+pub(crate) enum FetchEvent {
+    Head {
+        request: u32,
+        status: u16,
+        headers: Headers,
+    },
+    Data {
+        request: u32,
+        data: Vec<u8>,
+    },
+    Complete {
+        request: u32,
+    },
+    Failed {
+        request: u32,
+        error: JsValue,
+    },
+}
+
+/// The connection a request went out on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct ConnectionInfo {
+    pub(crate) connection: u32,
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    /// "h2" or "http/1.1"
+    pub(crate) protocol: &'static str,
+    /// Address the host was asked to connect to, when DoH resolved the name
+    pub(crate) address: Option<String>,
+    /// The connection had carried other requests before
+    pub(crate) reused: bool,
+    /// The handshake resumed a cached session
+    pub(crate) resumed: bool,
+    pub(crate) tls_version: Option<u16>,
+    pub(crate) cipher_suite: Option<u16>,
+}
+
+impl ConnectionInfo {
+    fn to_js(&self) -> Result<JsValue, JsValue> {
+        let object = js_sys::Object::new();
+        let set = |key: &str, value: JsValue| {
+            js_sys::Reflect::set(&object, &JsValue::from_str(key), &value).map(drop)
+        };
+        set("connectionId", self.connection.into())?;
+        set("host", self.host.as_str().into())?;
+        set("port", self.port.into())?;
+        set(
+            "address",
+            self.address.as_deref().map_or(JsValue::NULL, JsValue::from),
+        )?;
+        set("protocol", self.protocol.into())?;
+        set("reused", self.reused.into())?;
+        set("resumed", self.resumed.into())?;
+        set(
+            "tlsVersion",
+            self.tls_version.map_or(JsValue::NULL, JsValue::from),
+        )?;
+        set(
+            "cipherSuite",
+            self.cipher_suite.map_or(JsValue::NULL, JsValue::from),
+        )?;
+        Ok(object.into())
+    }
+}
+
+/// A request from `fetch` until its response is complete.
+#[derive(Debug)]
+struct Exchange {
+    url: HttpUrl,
+    /// Pool key, e.g. "https://example.com"
+    origin: String,
+    method: String,
+    headers: HeaderProfile,
+    body: Vec<u8>,
+    /// Set once the request was sent
+    connection: Option<ConnectionInfo>,
+    /// HTTP/2 stream of the request
+    stream: Option<u32>,
+    /// Content decoding of an HTTP/2 response body (HTTP/1.1 decodes in the parser)
+    decoder: Option<BodyDecoder>,
+}
+
+enum Protocol {
+    /// TCP connect or TLS handshake in progress
+    Handshaking,
+    Http1(Box<Http1Connection>),
+    Http2(Box<Http2Connection>),
+}
+
+struct Connection {
+    id: u32,
+    origin: String,
+    host: String,
+    port: u16,
+    tls: TlsConnection,
+    /// Waiting for a DoH lookup of `host` before the host is asked to connect
+    resolving: bool,
+    /// Address the host connects to in place of `host`, found by DoH
+    address: Option<String>,
+    /// The host reported the TCP connection as established
+    open: bool,
+    /// We are done with the connection: send what is left, then close it
+    closing: bool,
+    /// The transport is gone; nothing more can be sent
+    closed: bool,
+    protocol: Protocol,
+    /// Requests in flight by HTTP/2 stream id; HTTP/1.1 uses 0
+    in_flight: BTreeMap<u32, u32>,
+    /// Requests sent on the connection so far
+    served: u32,
+    /// Ciphertext for the next `Send`
+    outgoing: Vec<u8>,
+}
+
+impl Connection {
+    /// Whether a request can go out on the connection right away.
+    fn accepts_request(&self) -> bool {
+        match &self.protocol {
+            Protocol::Handshaking => false,
+            Protocol::Http1(h1) => h1.is_idle() && h1.is_reusable() && self.in_flight.is_empty(),
+            Protocol::Http2(h2) => !h2.is_going_away(),
+        }
+    }
+
+    /// Whether the connection can still take requests, now or once set up.
+    fn usable(&self) -> bool {
+        match &self.protocol {
+            Protocol::Handshaking => true,
+            Protocol::Http1(h1) => h1.is_reusable(),
+            Protocol::Http2(h2) => !h2.is_going_away(),
+        }
+    }
+
+    fn set_now(&mut self, now: f64) {
+        self.tls.set_now(now);
+        match &mut self.protocol {
+            Protocol::Handshaking => {}
+            Protocol::Http1(h1) => h1.set_now(now),
+            Protocol::Http2(h2) => h2.set_now(now),
+        }
+    }
+
+    fn write(&mut self, plaintext: &[u8]) -> Result<(), JsValue> {
+        if !plaintext.is_empty() {
+            self.tls.write_plaintext(plaintext)?;
+            self.outgoing.extend(self.tls.flush_outgoing_tls()?);
+        }
+        Ok(())
+    }
+
+    fn info(&self) -> ConnectionInfo {
+        ConnectionInfo {
+            connection: self.id,
+            host: self.host.clone(),
+            port: self.port,
+            address: self.address.clone(),
+            protocol: match self.protocol {
+                Protocol::Http2(_) => "h2",
+                _ => "http/1.1",
+            },
+            reused: self.served > 0,
+            resumed: self.tls.was_resumed(),
+            tls_version: self.tls.conn.protocol_version().map(u16::from),
+            cipher_suite: self
+                .tls
+                .conn
+                .negotiated_cipher_suite()
+                .map(|suite| u16::from(suite.suite())),
+        }
+    }
+
+    /// Serialize `exchange` onto the connection; returns its HTTP/2 stream id.
+    fn send(&mut self, exchange: &Exchange) -> Result<Option<u32>, JsValue> {
+        let url = &exchange.url;
+        let headers = &exchange.headers;
+        let (bytes, stream) = match &mut self.protocol {
+            Protocol::Handshaking => return Err(JsError::new("Connection is not set up").into()),
+            Protocol::Http1(h1) => {
+                let mut request = RequestBuilder::new(&exchange.method, &url.target())?;
+                request.set_host(&url.authority())?;
+                for (name, value) in headers.http1_names().iter().zip(headers.values()) {
+                    request.header(name, &value)?;
+                }
+                request.set_auto_decompress(true);
+                request.set_body(exchange.body.clone());
+                (h1.send(&request)?, None)
+            }
+            Protocol::Http2(h2) => {
+                let mut names = [":method", ":authority", ":scheme", ":path"].map(String::from);
+                let mut fields = Headers::new();
+                for (name, value) in names.iter_mut().zip([
+                    exchange.method.clone(),
+                    url.authority(),
+                    url.scheme(),
+                    url.target(),
+                ]) {
+                    fields.push(mem::take(name), value);
+                }
+                for (name, value) in headers.names().into_iter().zip(headers.values()) {
+                    fields.push(name, value);
+                }
+                let has_body = !exchange.body.is_empty();
+                if has_body && headers.header("content-length").is_none() {
+                    fields.push("content-length", exchange.body.len().to_string());
+                }
+                let stream = h2.start_stream(&fields, !has_body)?;
+                if has_body {
+                    h2.send_body(stream, &exchange.body, true)?;
+                }
+                (h2.take_outgoing(), Some(stream))
+            }
+        };
+        self.write(&bytes)?;
+        Ok(stream)
+    }
+}
+
+/// Promise or body stream of a request made with `fetch`.
+enum Response {
+    Waiting {
+        url: String,
+        resolve: js_sys::Function,
+        reject: js_sys::Function,
+    },
+    Streaming {
+        controller: JsValue,
+    },
+}
+
+/// A DoH lookup for a host, carried by a request whose events stay internal.
+struct Lookup {
+    host: String,
+    query: DohQuery,
+    status: u16,
+    content_type: Option<String>,
+    body: Vec<u8>,
+}
+
+struct Transport {
+    connect: js_sys::Function,
+    send: js_sys::Function,
+    close: js_sys::Function,
+}
+
+/// Requests over a pool of TLS connections with a browser's headers and HTTP/2
+/// preface, driven sans-IO by a JS host that owns the sockets.
+///
+/// The host registers its transport with `set_transport`, then calls `fetch`. It
+/// is asked to open connections and send bytes, and reports back with
+/// `connected`, `receive`, `closed` and `connect_failed`. Connections are kept
+/// alive and reused: an origin that negotiated h2 gets one connection carrying
+/// every request, an HTTP/1.1 origin up to six with one request at a time each.
+/// Redirects are not followed; see `RedirectController`. Hostnames are left to
+/// the host to resolve unless `set_doh_resolver` is called.
+#[wasm_bindgen]
+pub struct StealthFetch {
+    profile: String,
+    http2: Http2Options,
+    tls_options: TlsOptions,
+    connections: BTreeMap<u32, Connection>,
+    exchanges: BTreeMap<u32, Exchange>,
+    /// Requests waiting for a connection, oldest first
+    pending: VecDeque<u32>,
+    /// Protocol each origin negotiated last
+    protocols: BTreeMap<String, &'static str>,
+    finished: BTreeMap<u32, (Timings, ConnectionInfo)>,
+    resolver: Option<HttpUrl>,
+    /// Address and expiry (on the `set_now` clock) by host
+    dns_cache: BTreeMap<String, (String, f64)>,
+    /// DoH lookups by the request carrying them
+    lookups: BTreeMap<u32, Lookup>,
+    /// `open` queued a lookup that `dispatch` has yet to send
+    lookup_queued: bool,
+    next_connection: u32,
+    next_request: u32,
+    now: Option<f64>,
+    actions: VecDeque<Action>,
+    events: VecDeque<FetchEvent>,
+    transport: Option<Transport>,
+    responses: BTreeMap<u32, Response>,
+    /// Requests whose body stream was cancelled, for `run_transport` to cancel
+    cancelled: Rc<RefCell<Vec<u32>>>,
+}
+
+#[wasm_bindgen]
+impl StealthFetch {
+    /// A client with the headers and HTTP/2 settings of browser `profile`:
+    /// "chrome", "chrome-old" or "firefox" (see `HeaderProfile`).
+    #[wasm_bindgen(constructor)]
+    pub fn new(profile: &str) -> Result<StealthFetch, JsError> {
+        HeaderProfile::new(profile, "navigation")?;
+        Ok(StealthFetch {
+            profile: profile.to_string(),
+            http2: Http2Options::profile(profile)?,
+            tls_options: TlsOptions::default(),
+            connections: BTreeMap::new(),
+            exchanges: BTreeMap::new(),
+            pending: VecDeque::new(),
+            protocols: BTreeMap::new(),
+            finished: BTreeMap::new(),
+            resolver: None,
+            dns_cache: BTreeMap::new(),
+            lookups: BTreeMap::new(),
+            lookup_queued: false,
+            next_connection: 1,
+            next_request: 1,
+            now: None,
+            actions: VecDeque::new(),
+            events: VecDeque::new(),
+            transport: None,
+            responses: BTreeMap::new(),
+            cancelled: Rc::default(),
+        })
+    }
+
+    /// TLS settings of the connections opened from now on.
+    pub fn set_tls_options(&mut self, options: &TlsOptions) {
+        self.tls_options = options.clone();
+    }
+
+    /// Resolve hostnames with the DoH resolver at `url` (RFC 8484, e.g.
+    /// "https://1.1.1.1/dns-query") rather than leaving them to the host: a new
+    /// connection first looks up its host's A records, or AAAA ones when there
+    /// are none, in a request to the resolver, and `connect` is then given the
+    /// address. Answers are kept for their TTL once `set_now` has been called.
+    /// The resolver's own host is still the host's to resolve, so a URL with an
+    /// IP address keeps every lookup away from the platform's resolver.
+    pub fn set_doh_resolver(&mut self, url: &str) -> Result<(), JsError> {
+        let url = parse_url(url)?;
+        if url.scheme() != "https" {
+            return Err(JsError::new("The DoH resolver must have an https URL"));
+        }
+        self.resolver = Some(url);
+        self.dns_cache.clear();
+        Ok(())
+    }
+
+    /// The host's sockets: `connect(connectionId, host, port)` opens a TCP
+    /// connection, `send(connectionId, bytes)` writes to it and
+    /// `close(connectionId)` closes it. They are called from inside the methods
+    /// of this object and must not call back into it synchronously.
+    pub fn set_transport(
+        &mut self,
+        connect: js_sys::Function,
+        send: js_sys::Function,
+        close: js_sys::Function,
+    ) {
+        self.transport = Some(Transport {
+            connect,
+            send,
+            close,
+        });
+    }
+
+    /// Request `url` (https only). Resolves once the response head has arrived,
+    /// to `{ requestId, url, status, headers, body }`: `headers` is a list of
+    /// `[name, value]` pairs and `body` a ReadableStream of the decoded body.
+    /// Rejects, or errors the body stream, when the request fails. Cancelling
+    /// the body stream cancels the request the next time the host calls in.
+    pub fn fetch(&mut self, url: &str, options: &FetchOptions) -> Result<js_sys::Promise, JsError> {
+        let request = self.start(url, options)?;
+        let mut callbacks = None;
+        let promise = js_sys::Promise::new(&mut |resolve, reject| {
+            callbacks = Some((resolve, reject));
+        });
+        if let Some((resolve, reject)) = callbacks {
+            let url = self.exchanges[&request].url.href();
+            let waiting = Response::Waiting {
+                url,
+                resolve,
+                reject,
+            };
+            self.responses.insert(request, waiting);
+        }
+        self.run_transport()?;
+        Ok(promise)
+    }
+
+    /// The host's TCP connection `connection` is established.
+    pub fn connected(&mut self, connection: u32) -> Result<(), JsError> {
+        self.with_connection(connection, |this, conn| {
+            conn.open = true;
+            match conn.tls.flush_outgoing_tls() {
+                Ok(hello) => conn.outgoing = hello,
+                Err(e) => this.fail_connection(conn, e.into()),
+            }
+        });
+        self.take_lookup_events();
+        self.run_transport()
+    }
+
+    /// Bytes the host read from `connection`.
+    pub fn receive(&mut self, connection: u32, data: &[u8]) -> Result<(), JsError> {
+        self.with_connection(connection, |this, conn| {
+            if let Err(error) = this.receive_on(conn, data) {
+                this.fail_connection(conn, error);
+            }
+        });
+        self.dispatch();
+        self.run_transport()
+    }
+
+    /// The peer closed `connection`, or the host's socket failed. Requests still
+    /// waiting for a response on it fail, except HTTP/2 ones the server never
+    /// processed, which are sent again.
+    pub fn closed(&mut self, connection: u32) -> Result<(), JsError> {
+        self.with_connection(connection, |this, conn| {
+            conn.closed = true;
+            this.end_connection(conn);
+        });
+        self.dispatch();
+        self.run_transport()
+    }
+
+    /// `connection` could not be opened. The requests waiting for it fail with
+    /// `message` unless another connection to the origin can take them.
+    pub fn connect_failed(&mut self, connection: u32, message: &str) -> Result<(), JsError> {
+        self.with_connection(connection, |this, conn| {
+            conn.closed = true;
+            if this.has_requests(conn) {
+                this.fail_connection(conn, js_sys::Error::new(message).into());
+            }
+        });
+        self.dispatch();
+        self.run_transport()
+    }
+
+    /// Current time in milliseconds on the caller's clock, stamped on the phases
+    /// the calls that follow reach (see `timings`). Without it they stay null.
+    pub fn set_now(&mut self, now_ms: f64) {
+        self.now = Some(now_ms);
+        for conn in self.connections.values_mut() {
+            conn.set_now(now_ms);
+        }
+    }
+
+    /// Phases of request `request_id`, TLS handshake included when the request
+    /// waited for it. Kept for the last 256 finished requests.
+    pub fn timings(&self, request_id: u32) -> Option<Timings> {
+        if let Some((timings, _)) = self.finished.get(&request_id) {
+            return Some(*timings);
+        }
+        let exchange = self.exchanges.get(&request_id)?;
+        let conn = self
+            .connections
+            .get(&exchange.connection.as_ref()?.connection)?;
+        match &conn.protocol {
+            Protocol::Handshaking => None,
+            Protocol::Http1(h1) => Some(h1.timings()),
+            Protocol::Http2(h2) => h2.stream_timings(exchange.stream?),
+        }
+    }
+
+    /// The connection request `request_id` went out on: `{ connectionId, host,
+    /// port, address, protocol, reused, resumed, tlsVersion, cipherSuite }`, or
+    /// null while it waits for one. `address` is what DoH resolved `host` to, or
+    /// null when the host resolved it.
+    pub fn connection_info(&self, request_id: u32) -> Result<JsValue, JsError> {
+        match self.request_connection(request_id) {
+            Some(info) => info
+                .to_js()
+                .map_err(|e| JsError::new(&js_error_message(&e))),
+            None => Ok(JsValue::NULL),
+        }
+    }
+
+    /// Connections open or being opened.
+    pub fn connections(&self) -> usize {
+        self.connections.len()
+    }
+
+    /// Requests waiting for a connection.
+    pub fn pending_requests(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+impl StealthFetch {
+    /// Rust-side `fetch`: queues the request and returns its id, which events,
+    /// `timings` and `connection_info` refer to.
+    pub fn start(&mut self, url: &str, options: &FetchOptions) -> Result<u32, JsError> {
+        let request = self.queue(url, options)?;
+        self.dispatch();
+        Ok(request)
+    }
+
+    /// Stop request `request`: it gets no more events, its HTTP/2 stream is
+    /// reset and its HTTP/1.1 connection closed.
+    pub fn cancel(&mut self, request: u32) {
+        if self.lookups.contains_key(&request) {
+            return;
+        }
+        self.responses.remove(&request);
+        self.events.retain(|event| {
+            !matches!(event,
+                FetchEvent::Head { request: r, .. }
+                | FetchEvent::Data { request: r, .. }
+                | FetchEvent::Complete { request: r }
+                | FetchEvent::Failed { request: r, .. } if *r == request)
+        });
+        self.pending.retain(|&r| r != request);
+        let Some(exchange) = self.exchanges.remove(&request) else {
+            return;
+        };
+        let Some(info) = exchange.connection else {
+            return;
+        };
+        self.with_connection(info.connection, |_, conn| {
+            conn.in_flight.retain(|_, &mut r| r != request);
+            match &mut conn.protocol {
+                Protocol::Http2(h2) => {
+                    if let Some(stream) = exchange.stream {
+                        let _ = h2.reset_stream(stream, CANCEL);
+                    }
+                    let outgoing = h2.take_outgoing();
+                    if conn.write(&outgoing).is_err() {
+                        conn.closing = true;
+                    }
+                }
+                // The rest of the response would have to be read to reuse it
+                _ => conn.closing = true,
+            }
+        });
+    }
+
+    /// `start` without `dispatch`: the request waits in `pending`.
+    fn queue(&mut self, url: &str, options: &FetchOptions) -> Result<u32, JsError> {
+        let url = parse_url(url)?;
+        if url.scheme() != "https" {
+            return Err(JsError::new("StealthFetch only fetches https URLs"));
+        }
+        RequestBuilder::new(&options.method, &url.target())?;
+        let mut headers = HeaderProfile::new(&self.profile, &options.request_type)?;
+        for (name, value) in &options.headers {
+            headers.set_header(name, value)?;
+        }
+        let request = self.next_request;
+        self.next_request += 1;
+        let exchange = Exchange {
+            origin: url.origin(),
+            url,
+            method: options.method.clone(),
+            headers,
+            body: options.body.clone(),
+            connection: None,
+            stream: None,
+            decoder: None,
+        };
+        self.exchanges.insert(request, exchange);
+        self.pending.push_back(request);
+        Ok(request)
+    }
+
+    /// Next thing the host has to do, when no transport is registered.
+    #[cfg(test)]
+    pub(crate) fn poll_action(&mut self) -> Option<Action> {
+        self.actions.pop_front()
+    }
+
+    /// Next request event, when no transport is registered.
+    #[cfg(test)]
+    pub(crate) fn poll_event(&mut self) -> Option<FetchEvent> {
+        self.events.pop_front()
+    }
+
+    pub(crate) fn request_connection(&self, request: u32) -> Option<&ConnectionInfo> {
+        match self.finished.get(&request) {
+            Some((_, info)) => Some(info),
+            None => self.exchanges.get(&request)?.connection.as_ref(),
+        }
+    }
+
+    /// Send pending requests on connections that can take them, and open
+    /// connections for the rest.
+    fn dispatch(&mut self) {
+        self.take_lookup_events();
+        for request in mem::take(&mut self.pending) {
+            let Some(exchange) = self.exchanges.get(&request) else {
+                continue;
+            };
+            let ready = self
+                .connections
+                .iter()
+                .find(|(_, c)| c.origin == exchange.origin && c.accepts_request())
+                .map(|(&id, _)| id);
+            match ready {
+                Some(id) => self.with_connection(id, |this, conn| this.send_request(conn, request)),
+                None => self.pending.push_back(request),
+            }
+        }
+
+        let mut waiting: Vec<(String, usize, HttpUrl)> = Vec::new();
+        for exchange in self.pending.iter().filter_map(|r| self.exchanges.get(r)) {
+            match waiting.iter_mut().find(|(o, _, _)| *o == exchange.origin) {
+                Some((_, count, _)) => *count += 1,
+                None => waiting.push((exchange.origin.clone(), 1, exchange.url.clone())),
+            }
+        }
+        for (origin, count, url) in waiting {
+            let usable: Vec<&Connection> = self
+                .connections
+                .values()
+                .filter(|c| c.origin == origin && c.usable())
+                .collect();
+            let connecting = usable
+                .iter()
+                .filter(|c| matches!(c.protocol, Protocol::Handshaking))
+                .count();
+            // Until an origin is known to speak HTTP/1.1, one connection may be all it needs
+            let limit = match self.protocols.get(&origin) {
+                Some(&"http/1.1") => MAX_CONNECTIONS_PER_ORIGIN,
+                _ => 1,
+            };
+            let wanted = count
+                .saturating_sub(connecting)
+                .min(limit.saturating_sub(usable.len()));
+            for _ in 0..wanted {
+                self.open(&origin, &url);
+            }
+        }
+        // Lookups go out like any request, on connections to the resolver
+        if mem::take(&mut self.lookup_queued) {
+            self.dispatch();
+        }
+    }
+
+    fn open(&mut self, origin: &str, url: &HttpUrl) {
+        let host = url.host();
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let mut tls = match TlsConnection::with_options(host, "h2,http/1.1", &self.tls_options) {
+            Ok(tls) => tls,
+            Err(e) => {
+                let error = JsValue::from(e);
+                self.fail_pending(origin, &error);
+                return;
+            }
+        };
+        if let Some(now) = self.now {
+            tls.set_now(now);
+        }
+        let resolve = host.parse::<IpAddr>().is_err()
+            && self
+                .resolver
+                .as_ref()
+                .is_some_and(|resolver| resolver.origin() != origin);
+        let address = self.cached_address(host).filter(|_| resolve);
+        let resolving = resolve && address.is_none();
+        if resolving && !self.lookups.values().any(|lookup| lookup.host == host) {
+            if let Err(error) = self.lookup(host, "A") {
+                self.fail_pending(origin, &error);
+                return;
+            }
+        }
+        let id = self.next_connection;
+        self.next_connection += 1;
+        let conn = Connection {
+            id,
+            origin: origin.to_string(),
+            host: host.to_string(),
+            port: url.port(),
+            tls,
+            resolving,
+            address: address.clone(),
+            open: false,
+            closing: false,
+            closed: false,
+            protocol: Protocol::Handshaking,
+            in_flight: BTreeMap::new(),
+            served: 0,
+            outgoing: Vec::new(),
+        };
+        self.connections.insert(id, conn);
+        if !resolving {
+            self.actions.push_back(Action::Connect {
+                connection: id,
+                host: address.unwrap_or_else(|| host.to_string()),
+                port: url.port(),
+            });
+        }
+    }
+
+    /// Fresh DoH answer for `host`.
+    fn cached_address(&self, host: &str) -> Option<String> {
+        let (address, expires) = self.dns_cache.get(host)?;
+        (self.now? < *expires).then(|| address.clone())
+    }
+
+    /// Queue a DoH query of `record_type` for `host` to the resolver.
+    fn lookup(&mut self, host: &str, record_type: &str) -> Result<(), JsValue> {
+        let Some(resolver) = &self.resolver else {
+            return Ok(());
+        };
+        let query = DohQuery::new(&resolver.href(), host, record_type)?;
+        let mut options = FetchOptions::new();
+        options.set_method(&query.method());
+        for (name, value) in query.header_names().iter().zip(query.header_values()) {
+            options.set_header(name, &value);
+        }
+        options.set_body(query.body());
+        options.set_request_type("fetch");
+        let request = self.queue(&query.url(), &options)?;
+        let lookup = Lookup {
+            host: host.to_string(),
+            query,
+            status: 0,
+            content_type: None,
+            body: Vec::new(),
+        };
+        self.lookups.insert(request, lookup);
+        self.lookup_queued = true;
+        Ok(())
+    }
+
+    /// Take the events of lookup requests out of the queue, which is the host's,
+    /// and act on the lookups they finish.
+    fn take_lookup_events(&mut self) {
+        if self.lookups.is_empty() {
+            return;
+        }
+        for event in mem::take(&mut self.events) {
+            let request = match &event {
+                FetchEvent::Head { request, .. }
+                | FetchEvent::Data { request, .. }
+                | FetchEvent::Complete { request }
+                | FetchEvent::Failed { request, .. } => *request,
+            };
+            if !self.lookups.contains_key(&request) {
+                self.events.push_back(event);
+                continue;
+            }
+            match event {
+                FetchEvent::Head {
+                    status, headers, ..
+                } => {
+                    if let Some(lookup) = self.lookups.get_mut(&request) {
+                        lookup.status = status;
+                        lookup.content_type = headers.get("content-type").map(String::from);
+                    }
+                }
+                FetchEvent::Data { data, .. } => {
+                    // Beyond the largest DNS message the response fails anyway
+                    if let Some(lookup) = self.lookups.get_mut(&request) {
+                        if lookup.body.len() <= MAX_MESSAGE_SIZE {
+                            lookup.body.extend(data);
+                        }
+                    }
+                }
+                FetchEvent::Complete { .. } => {
+                    if let Some(lookup) = self.lookups.remove(&request) {
+                        self.lookup_done(lookup);
+                    }
+                }
+                FetchEvent::Failed { error, .. } => {
+                    if let Some(lookup) = self.lookups.remove(&request) {
+                        self.lookup_failed(&lookup.host, &js_error_message(&error));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Connect what waits for the looked-up host, or look up its AAAA records
+    /// when it has no A records.
+    fn lookup_done(&mut self, lookup: Lookup) {
+        let host = &lookup.host;
+        let response =
+            lookup
+                .query
+                .read_response(lookup.status, lookup.content_type.as_deref(), &lookup.body);
+        let message = match response {
+            Ok(message) if message.rcode() == 0 => message,
+            Ok(message) => return self.lookup_failed(host, rcode_name(message.rcode())),
+            Err(e) => return self.lookup_failed(host, &e.to_string()),
+        };
+        let queried = lookup.query.message().questions.first();
+        match message.addresses().first() {
+            Some(&(address, ttl)) => self.resolved(host, address, ttl),
+            None if queried.is_some_and(|q| q.record_type == A) => {
+                if let Err(error) = self.lookup(host, "AAAA") {
+                    self.lookup_failed(host, &js_error_message(&error));
+                }
+            }
+            None => self.lookup_failed(host, "no A or AAAA records"),
+        }
+    }
+
+    fn resolved(&mut self, host: &str, address: IpAddr, ttl: u32) {
+        let address = address.to_string();
+        if let Some(now) = self.now {
+            if self.dns_cache.len() >= MAX_DNS_CACHE && !self.dns_cache.contains_key(host) {
+                self.dns_cache.pop_first();
+            }
+            let expires = now + ttl as f64 * 1000.0;
+            self.dns_cache
+                .insert(host.to_string(), (address.clone(), expires));
+        }
+        for conn in self.connections.values_mut() {
+            if conn.resolving && conn.host == host {
+                conn.resolving = false;
+                conn.address = Some(address.clone());
+                self.actions.push_back(Action::Connect {
+                    connection: conn.id,
+                    host: address.clone(),
+                    port: conn.port,
+                });
+            }
+        }
+    }
+
+    /// Fail the connections waiting for `host`, as if they could not be opened.
+    fn lookup_failed(&mut self, host: &str, reason: &str) {
+        let message = format!("DNS lookup of {} failed: {}", host, reason);
+        let waiting: Vec<u32> = self
+            .connections
+            .values()
+            .filter(|conn| conn.resolving && conn.host == host)
+            .map(|conn| conn.id)
+            .collect();
+        for id in waiting {
+            self.with_connection(id, |this, conn| {
+                conn.closed = true;
+                if this.has_requests(conn) {
+                    this.fail_connection(conn, js_sys::Error::new(&message).into());
+                }
+            });
+        }
+    }
+
+    /// Run `f` on connection `id` outside the pool, then queue what it has to
+    /// send and retire it if it is done.
+    fn with_connection(&mut self, id: u32, f: impl FnOnce(&mut Self, &mut Connection)) {
+        let Some(mut conn) = self.connections.remove(&id) else {
+            return;
+        };
+        if let Some(now) = self.now {
+            conn.set_now(now);
+        }
+        f(self, &mut conn);
+        if conn.closing && !conn.closed {
+            conn.tls.send_close_notify();
+            if let Ok(alert) = conn.tls.flush_outgoing_tls() {
+                conn.outgoing.extend(alert);
+            }
+        }
+        if conn.open && !conn.closed && !conn.outgoing.is_empty() {
+            let data = mem::take(&mut conn.outgoing);
+            self.actions.push_back(Action::Send {
+                connection: id,
+                data,
+            });
+        }
+        if conn.closing || conn.closed {
+            if !conn.closed {
+                self.actions.push_back(Action::Close { connection: id });
+            }
+        } else {
+            self.connections.insert(id, conn);
+        }
+    }
+
+    fn send_request(&mut self, conn: &mut Connection, request: u32) {
+        let Some(exchange) = self.exchanges.get_mut(&request) else {
+            return;
+        };
+        let info = conn.info();
+        match conn.send(exchange) {
+            Ok(stream) => {
+                exchange.connection = Some(info);
+                exchange.stream = stream;
+                conn.in_flight.insert(stream.unwrap_or(0), request);
+                conn.served += 1;
+            }
+            Err(error) => self.fail(request, error),
+        }
+    }
+
+    fn receive_on(&mut self, conn: &mut Connection, data: &[u8]) -> Result<(), JsValue> {
+        let mut result = conn.tls.pump(data)?;
+        conn.outgoing.extend(result.take_outgoing());
+        if matches!(conn.protocol, Protocol::Handshaking) && !conn.tls.is_handshaking() {
+            self.start_protocol(conn)?;
+        }
+        let plaintext = result.take_plaintext();
+        match &mut conn.protocol {
+            Protocol::Handshaking => {}
+            Protocol::Http1(h1) => h1.feed(&plaintext)?,
+            Protocol::Http2(h2) => h2.feed(&plaintext)?,
+        }
+        self.drain(conn)?;
+        if conn.tls.peer_has_closed() {
+            self.end_connection(conn);
+        }
+        Ok(())
+    }
+
+    /// The handshake is done: set up the protocol ALPN picked.
+    fn start_protocol(&mut self, conn: &mut Connection) -> Result<(), JsValue> {
+        let timings = conn.tls.timings();
+        let h2 = conn.tls.negotiated_alpn().as_deref() == Some("h2");
+        let preface = if h2 {
+            let mut h2 = Http2Connection::with_options(&self.http2);
+            h2.set_tls_timings(&timings);
+            let preface = h2.take_outgoing();
+            conn.protocol = Protocol::Http2(Box::new(h2));
+            preface
+        } else {
+            let mut h1 = Http1Connection::new();
+            h1.set_tls_timings(&timings);
+            conn.protocol = Protocol::Http1(Box::new(h1));
+            Vec::new()
+        };
+        if let Some(now) = self.now {
+            conn.set_now(now);
+        }
+        let protocol = if h2 { "h2" } else { "http/1.1" };
+        self.protocols.insert(conn.origin.clone(), protocol);
+        conn.write(&preface)
+    }
+
+    /// Turn what the HTTP layer parsed into request events.
+    fn drain(&mut self, conn: &mut Connection) -> Result<(), JsValue> {
+        match &mut conn.protocol {
+            Protocol::Handshaking => {}
+            Protocol::Http1(h1) => {
+                while let Some(event) = h1.poll_event() {
+                    let Some(&request) = conn.in_flight.get(&0) else {
+                        break;
+                    };
+                    match event {
+                        ResponseEvent::Informational(_) | ResponseEvent::Trailers(_) => {}
+                        ResponseEvent::Head(head) => self.events.push_back(FetchEvent::Head {
+                            request,
+                            status: head.status,
+                            headers: head.headers,
+                        }),
+                        ResponseEvent::Body(data) => {
+                            self.events.push_back(FetchEvent::Data { request, data })
+                        }
+                        ResponseEvent::Complete => {
+                            conn.in_flight.remove(&0);
+                            self.complete(request, h1.timings());
+                            if !h1.is_reusable() {
+                                conn.closing = true;
+                            }
+                        }
+                    }
+                }
+            }
+            Protocol::Http2(h2) => {
+                while let Some(event) = h2.poll_event() {
+                    self.stream_event(h2, &mut conn.in_flight, event);
+                }
+                if h2.is_finished() {
+                    conn.closing = true;
+                }
+                let outgoing = h2.take_outgoing();
+                conn.write(&outgoing)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn stream_event(
+        &mut self,
+        h2: &mut Http2Connection,
+        in_flight: &mut BTreeMap<u32, u32>,
+        event: ConnectionEvent,
+    ) {
+        let (stream, data, end_stream) = match event {
+            ConnectionEvent::Informational { .. } | ConnectionEvent::GoAway { .. } => return,
+            ConnectionEvent::Headers {
+                stream_id,
+                headers,
+                end_stream,
+            } => {
+                let Some(&request) = in_flight.get(&stream_id) else {
+                    return;
+                };
+                let status = headers
+                    .get(":status")
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or_default();
+                let mut fields = Headers::new();
+                for (name, value) in headers.iter().filter(|(n, _)| !n.starts_with(':')) {
+                    fields.push(name, value);
+                }
+                if let Some(exchange) = self.exchanges.get_mut(&request) {
+                    exchange.decoder =
+                        Some(BodyDecoder::for_headers(&fields, DEFAULT_MAX_DECODED_SIZE));
+                }
+                self.events.push_back(FetchEvent::Head {
+                    request,
+                    status,
+                    headers: fields,
+                });
+                (stream_id, Vec::new(), end_stream)
+            }
+            ConnectionEvent::Data {
+                stream_id,
+                data,
+                end_stream,
+            } => (stream_id, data, end_stream),
+            ConnectionEvent::Trailers { stream_id, .. } => (stream_id, Vec::new(), true),
+            ConnectionEvent::Reset {
+                stream_id,
+                error_code,
+            } => {
+                if let Some(request) = in_flight.remove(&stream_id) {
+                    let message = format!("HTTP/2 stream reset with error code {}", error_code);
+                    self.fail(request, JsError::new(&message).into());
+                }
+                return;
+            }
+            ConnectionEvent::Abandoned {
+                stream_id,
+                retryable,
+                ..
+            } => {
+                let Some(request) = in_flight.remove(&stream_id) else {
+                    return;
+                };
+                match self.exchanges.get_mut(&request) {
+                    Some(exchange) if retryable => {
+                        exchange.connection = None;
+                        exchange.stream = None;
+                        self.pending.push_front(request);
+                    }
+                    _ => self.fail(request, closed_error()),
+                }
+                return;
+            }
+        };
+        let Some(&request) = in_flight.get(&stream) else {
+            return;
+        };
+        let Some(decoder) = self
+            .exchanges
+            .get_mut(&request)
+            .and_then(|e| e.decoder.as_mut())
+        else {
+            return;
+        };
+        let decoded = decoder.decode(&data).and_then(|decoded| {
+            if end_stream {
+                decoder.finish()?;
+            }
+            Ok(decoded)
+        });
+        match decoded {
+            Ok(decoded) => {
+                if !decoded.is_empty() {
+                    self.events.push_back(FetchEvent::Data {
+                        request,
+                        data: decoded,
+                    });
+                }
+                if end_stream {
+                    in_flight.remove(&stream);
+                    self.complete(request, h2.stream_timings(stream).unwrap_or_default());
+                }
+            }
+            Err(error) => {
+                in_flight.remove(&stream);
+                if !end_stream {
+                    // Nothing more of it can be read
+                    let _ = h2.reset_stream(stream, CANCEL);
+                }
+                self.fail(request, error.into());
+            }
+        }
+    }
+
+    /// The transport is gone or the peer sent close_notify: let the HTTP layer
+    /// finish what it can, and fail or requeue what is left.
+    fn end_connection(&mut self, conn: &mut Connection) {
+        let result = match &mut conn.protocol {
+            Protocol::Handshaking => Ok(()),
+            Protocol::Http1(h1) => h1.finish().map_err(JsValue::from),
+            Protocol::Http2(h2) => {
+                h2.transport_closed();
+                Ok(())
+            }
+        };
+        let result = result.and_then(|()| self.drain(conn));
+        if self.has_requests(conn) {
+            let error = result.err().unwrap_or_else(closed_error);
+            self.fail_connection(conn, error);
+        }
+        conn.closing = true;
+    }
+
+    /// Whether failing the connection would fail a request: one in flight on
+    /// it, or one waiting for a connection being set up to its origin.
+    fn has_requests(&self, conn: &Connection) -> bool {
+        !conn.in_flight.is_empty()
+            || (matches!(conn.protocol, Protocol::Handshaking)
+                && self.pending.iter().any(|r| {
+                    self.exchanges
+                        .get(r)
+                        .is_some_and(|e| e.origin == conn.origin)
+                }))
+    }
+
+    /// Fail the requests in flight on `conn`. Failing to set one up fails the
+    /// requests waiting for it too, unless another connection to the origin
+    /// is left to take them.
+    fn fail_connection(&mut self, conn: &mut Connection, error: JsValue) {
+        for request in mem::take(&mut conn.in_flight).into_values() {
+            self.fail(request, error.clone());
+        }
+        let others = self
+            .connections
+            .values()
+            .any(|c| c.origin == conn.origin && c.usable());
+        if matches!(conn.protocol, Protocol::Handshaking) && !others {
+            self.fail_pending(&conn.origin, &error);
+        }
+        conn.closing = true;
+    }
+
+    fn fail_pending(&mut self, origin: &str, error: &JsValue) {
+        let (failed, kept) = mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|r| self.exchanges.get(r).is_some_and(|e| e.origin == origin));
+        self.pending = kept;
+        for request in failed {
+            self.fail(request, error.clone());
+        }
+    }
+
+    fn complete(&mut self, request: u32, timings: Timings) {
+        self.events.push_back(FetchEvent::Complete { request });
+        let Some(exchange) = self.exchanges.remove(&request) else {
+            return;
+        };
+        if let Some(info) = exchange.connection {
+            self.finished.insert(request, (timings, info));
+            while self.finished.len() > MAX_FINISHED {
+                self.finished.pop_first();
+            }
+        }
+    }
+
+    fn fail(&mut self, request: u32, error: JsValue) {
+        self.exchanges.remove(&request);
+        self.events.push_back(FetchEvent::Failed { request, error });
+    }
+
+    /// Hand queued actions to the transport and events to the promises and
+    /// body streams of `fetch`. Without a transport both stay queued.
+    fn run_transport(&mut self) -> Result<(), JsError> {
+        let cancelled = mem::take(&mut *self.cancelled.borrow_mut());
+        for request in cancelled {
+            self.cancel(request);
+        }
+        let Some(transport) = &self.transport else {
+            return Ok(());
+        };
+        let failed = |e: JsValue| {
+            JsError::new(&format!(
+                "Transport callback failed: {}",
+                js_error_message(&e)
+            ))
+        };
+        while let Some(action) = self.actions.pop_front() {
+            match action {
+                Action::Connect {
+                    connection,
+                    host,
+                    port,
+                } => transport.connect.call3(
+                    &JsValue::NULL,
+                    &connection.into(),
+                    &host.into(),
+                    &port.into(),
+                ),
+                Action::Send { connection, data } => transport.send.call2(
+                    &JsValue::NULL,
+                    &connection.into(),
+                    &js_sys::Uint8Array::from(&data[..]),
+                ),
+                Action::Close { connection } => {
+                    transport.close.call1(&JsValue::NULL, &connection.into())
+                }
+            }
+            .map_err(failed)?;
+        }
+        while let Some(event) = self.events.pop_front() {
+            self.deliver(event)
+                .map_err(|e| JsError::new(&js_error_message(&e)))?;
+        }
+        Ok(())
+    }
+
+    fn deliver(&mut self, event: FetchEvent) -> Result<(), JsValue> {
+        match event {
+            FetchEvent::Head {
+                request,
+                status,
+                headers,
+            } => {
+                let Some(Response::Waiting { url, resolve, .. }) = self.responses.remove(&request)
+                else {
+                    return Ok(());
+                };
+                let (body, controller) = body_stream(request, &self.cancelled)?;
+                let list = js_sys::Array::new();
+                for (name, value) in headers.iter() {
+                    list.push(&js_sys::Array::of2(&name.into(), &value.into()));
+                }
+                let response = js_sys::Object::new();
+                let set = |key: &str, value: &JsValue| {
+                    js_sys::Reflect::set(&response, &JsValue::from_str(key), value).map(drop)
+                };
+                set("requestId", &request.into())?;
+                set("url", &url.into())?;
+                set("status", &status.into())?;
+                set("headers", &list)?;
+                set("body", &body)?;
+                self.responses
+                    .insert(request, Response::Streaming { controller });
+                resolve.call1(&JsValue::NULL, &response)?;
+            }
+            FetchEvent::Data { request, data } => {
+                if let Some(Response::Streaming { controller }) = self.responses.get(&request) {
+                    let chunk = js_sys::Uint8Array::from(&data[..]);
+                    call_method(controller, "enqueue", &chunk)?;
+                }
+            }
+            FetchEvent::Complete { request } => {
+                if let Some(Response::Streaming { controller }) = self.responses.remove(&request) {
+                    call_method(&controller, "close", &JsValue::UNDEFINED)?;
+                }
+            }
+            FetchEvent::Failed { request, error } => match self.responses.remove(&request) {
+                Some(Response::Waiting { reject, .. }) => {
+                    reject.call1(&JsValue::NULL, &error)?;
+                }
+                Some(Response::Streaming { controller }) => {
+                    call_method(&controller, "error", &error)?;
+                }
+                None => {}
+            },
+        }
+        Ok(())
+    }
+}
+
+/// Error of a request whose connection closed before its response was complete.
+fn closed_error() -> JsValue {
+    JsError::new("Connection closed before the response was complete").into()
+}
+
+/// A ReadableStream and the controller to fill it with. Cancelling the stream
+/// adds `request` to `cancelled`.
+fn body_stream(
+    request: u32,
+    cancelled: &Rc<RefCell<Vec<u32>>>,
+) -> Result<(JsValue, JsValue), JsValue> {
+    let controller = Rc::new(RefCell::new(JsValue::UNDEFINED));
+    let slot = controller.clone();
+    // `start` runs inside the constructor
+    let start = Closure::once_into_js(move |c: JsValue| {
+        *slot.borrow_mut() = c;
+    });
+    let cancelled = cancelled.clone();
+    let cancel = Closure::once_into_js(move |_reason: JsValue| {
+        cancelled.borrow_mut().push(request);
+    });
+    let source = js_sys::Object::new();
+    js_sys::Reflect::set(&source, &JsValue::from_str("start"), &start)?;
+    js_sys::Reflect::set(&source, &JsValue::from_str("cancel"), &cancel)?;
+    let constructor: js_sys::Function =
+        js_sys::Reflect::get(&js_sys::global(), &JsValue::from_str("ReadableStream"))?
+            .dyn_into()?;
+    let stream = js_sys::Reflect::construct(&constructor, &js_sys::Array::of1(&source))?;
+    let controller = controller.take();
+    Ok((stream, controller))
+}
+
+fn call_method(target: &JsValue, name: &str, arg: &JsValue) -> Result<JsValue, JsValue> {
+    let method: js_sys::Function =
+        js_sys::Reflect::get(target, &JsValue::from_str(name))?.dyn_into()?;
+    method.call1(target, arg)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::sync::Arc;
+
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+    use rustls::{ServerConfig, ServerConnection};
+    use wasm_http::dns::{DnsMessage, DotClient, Edns, Record, RecordData, AAAA, DOT_ALPN};
+    use wasm_http::http2::PREFACE;
+
+    use super::*;
+    use crate::get_provider;
+
+    const SERVER_CA: &[u8] = include_bytes!("../tests/fixtures/server-ca.der");
+    const SERVER_CERT: &[u8] = include_bytes!("../tests/fixtures/server-cert.der");
+    const SERVER_KEY: &[u8] = include_bytes!("../tests/fixtures/server-key.der");
+
+    /// In-memory server for fetch.test, the far end of one of the host's sockets.
+    struct Server {
+        tls: ServerConnection,
+        /// Plaintext not answered yet
+        received: Vec<u8>,
+    }
+
+    impl Server {
+        fn new(alpn: &str) -> Server {
+            let key = PrivateKeyDer::try_from(SERVER_KEY.to_vec()).unwrap();
+            let mut config = ServerConfig::builder_with_provider(get_provider())
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_no_client_auth()
+                .with_single_cert(vec![CertificateDer::from(SERVER_CERT.to_vec())], key)
+                .unwrap();
+            config.alpn_protocols = vec![alpn.as_bytes().to_vec()];
+            Server {
+                tls: ServerConnection::new(Arc::new(config)).unwrap(),
+                received: Vec::new(),
+            }
+        }
+
+        fn receive(&mut self, mut data: &[u8]) {
+            while !data.is_empty() {
+                self.tls.read_tls(&mut data).unwrap();
+                self.tls.process_new_packets().unwrap();
+            }
+            let _ = self.tls.reader().read_to_end(&mut self.received);
+        }
+
+        fn take_outgoing(&mut self) -> Vec<u8> {
+            let mut out = Vec::new();
+            while self.tls.wants_write() {
+                self.tls.write_tls(&mut out).unwrap();
+            }
+            out
+        }
+    }
+
+    /// The JS host's part: carry out `fetch`'s actions on in-memory servers that
+    /// answer what they received with `respond`, until nothing moves any more.
+    fn run(
+        fetch: &mut StealthFetch,
+        servers: &mut BTreeMap<u32, Server>,
+        alpn: &str,
+        respond: &mut dyn FnMut(&[u8]) -> Option<Vec<u8>>,
+    ) {
+        loop {
+            let mut progressed = false;
+            while let Some(action) = fetch.poll_action() {
+                progressed = true;
+                match action {
+                    Action::Connect {
+                        connection,
+                        host,
+                        port,
+                    } => {
+                        // The origin by name or by the address DoH found, or the resolver
+                        assert!(
+                            matches!(
+                                (host.as_str(), port),
+                                ("fetch.test" | "2001:db8::7", 443) | ("fetch.test", 8443)
+                            ),
+                            "connect to {}:{}",
+                            host,
+                            port
+                        );
+                        servers.insert(connection, Server::new(alpn));
+                        fetch.connected(connection).unwrap();
+                    }
+                    Action::Send { connection, data } => {
+                        let server = servers.get_mut(&connection).unwrap();
+                        server.receive(&data);
+                        if let Some(reply) = respond(&server.received) {
+                            server.received.clear();
+                            server.tls.writer().write_all(&reply).unwrap();
+                        }
+                    }
+                    Action::Close { connection } => {
+                        servers.remove(&connection);
+                    }
+                }
+            }
+            for (&connection, server) in servers.iter_mut() {
+                let out = server.take_outgoing();
+                if !out.is_empty() {
+                    progressed = true;
+                    fetch.receive(connection, &out).unwrap();
+                }
+            }
+            if !progressed {
+                break;
+            }
+        }
+    }
+
+    fn client() -> StealthFetch {
+        let mut options = TlsOptions::default();
+        options.add_root("fetch.test", SERVER_CA.to_vec()).unwrap();
+        let mut fetch = StealthFetch::new("chrome").unwrap();
+        fetch.set_tls_options(&options);
+        fetch
+    }
+
+    /// Status and body of `request`, from the events so far.
+    fn response(events: &[FetchEvent], request: u32) -> (u16, Vec<u8>, bool) {
+        let mut response = (0, Vec::new(), false);
+        for event in events {
+            match event {
+                FetchEvent::Head {
+                    request: r, status, ..
+                } if *r == request => response.0 = *status,
+                FetchEvent::Data { request: r, data } if *r == request => response.1.extend(data),
+                FetchEvent::Complete { request: r } if *r == request => response.2 = true,
+                FetchEvent::Failed { request: r, .. } if *r == request => {
+                    panic!("request {} failed", request)
+                }
+                _ => {}
+            }
+        }
+        response
+    }
+
+    fn drain_events(fetch: &mut StealthFetch) -> Vec<FetchEvent> {
+        std::iter::from_fn(|| fetch.poll_event()).collect()
+    }
+
+    #[test]
+    fn http1_requests_reuse_the_connection() {
+        let mut fetch = client();
+        fetch.set_now(100.0);
+        let mut servers = BTreeMap::new();
+        let mut requests = Vec::new();
+        let mut respond = |received: &[u8]| {
+            let text = String::from_utf8(received.to_vec()).unwrap();
+            let (head, body) = text.split_once("\r\n\r\n")?;
+            let length = head
+                .lines()
+                .find_map(|l| l.strip_prefix("Content-Length: "))
+                .map_or(0, |n| n.parse().unwrap());
+            if body.len() < length {
+                return None;
+            }
+            requests.push(text.clone());
+            Some(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello".to_vec())
+        };
+
+        let first = fetch
+            .start("https://Fetch.test/hello?x=1", &FetchOptions::new())
+            .unwrap();
+        assert_eq!(fetch.connections(), 1);
+        assert_eq!(fetch.pending_requests(), 1);
+        run(&mut fetch, &mut servers, "http/1.1", &mut respond);
+        let events = drain_events(&mut fetch);
+        assert_eq!(response(&events, first), (200, b"hello".to_vec(), true));
+
+        let mut options = FetchOptions::new();
+        options.set_method("POST");
+        options.set_header("Content-Type", "text/plain");
+        options.set_body(b"data".to_vec());
+        options.set_request_type("fetch");
+        let second = fetch.start("https://fetch.test/submit", &options).unwrap();
+        run(&mut fetch, &mut servers, "http/1.1", &mut respond);
+        let events = drain_events(&mut fetch);
+        assert_eq!(response(&events, second), (200, b"hello".to_vec(), true));
+        assert_eq!(fetch.connections(), 1);
+
+        // The profile's headers, Host first and with Chrome's casing
+        assert!(requests[0].starts_with("GET /hello?x=1 HTTP/1.1\r\nHost: fetch.test\r\n"));
+        assert!(requests[0].contains("\r\nsec-ch-ua: "));
+        assert!(requests[0].contains("\r\nUser-Agent: Mozilla/5.0 "));
+        assert!(requests[1].starts_with("POST /submit HTTP/1.1\r\n"));
+        assert!(requests[1].contains("\r\nContent-Type: text/plain\r\n"));
+        assert!(requests[1].ends_with("\r\n\r\ndata"));
+
+        let first_info = fetch.request_connection(first).unwrap().clone();
+        let second_info = fetch.request_connection(second).unwrap();
+        assert_eq!(first_info.protocol, "http/1.1");
+        assert_eq!(first_info.tls_version, Some(0x0304));
+        assert!(!first_info.reused);
+        assert_eq!(second_info.connection, first_info.connection);
+        assert!(second_info.reused);
+        // Only the first request waited for the handshake
+        assert_eq!(fetch.timings(first).unwrap().tls_start(), Some(100.0));
+        assert_eq!(fetch.timings(second).unwrap().tls_start(), None);
+    }
+
+    #[test]
+    fn http2_requests_share_one_connection() {
+        let mut fetch = client();
+        let mut servers = BTreeMap::new();
+        let frame = |kind: u8, flags: u8, stream: u32, payload: &[u8]| {
+            let mut frame = (payload.len() as u32).to_be_bytes()[1..].to_vec();
+            frame.extend([kind, flags]);
+            frame.extend(stream.to_be_bytes());
+            frame.extend(payload);
+            frame
+        };
+        let mut respond = |received: &[u8]| {
+            let mut reply = Vec::new();
+            let mut rest = received;
+            if let Some(frames) = rest.strip_prefix(PREFACE) {
+                reply.extend(frame(0x4, 0, 0, &[]));
+                reply.extend(frame(0x4, 0x1, 0, &[]));
+                rest = frames;
+            }
+            while rest.len() >= 9 {
+                let length = u32::from_be_bytes([0, rest[0], rest[1], rest[2]]) as usize;
+                let stream = u32::from_be_bytes(rest[5..9].try_into().unwrap()) & 0x7fff_ffff;
+                if rest[3] == 0x1 {
+                    // :status 200 from the static table, then the body
+                    reply.extend(frame(0x1, 0x4, stream, &[0x88]));
+                    reply.extend(frame(0x0, 0x1, stream, b"h2 body"));
+                }
+                rest = &rest[9 + length..];
+            }
+            (!reply.is_empty()).then_some(reply)
+        };
+
+        let first = fetch
+            .start("https://fetch.test/a", &FetchOptions::new())
+            .unwrap();
+        let second = fetch
+            .start("https://fetch.test/b", &FetchOptions::new())
+            .unwrap();
+        // Whether the origin speaks h2 is not known yet: one connection first
+        assert_eq!(fetch.connections(), 1);
+        run(&mut fetch, &mut servers, "h2", &mut respond);
+        let events = drain_events(&mut fetch);
+        for request in [first, second] {
+            assert_eq!(response(&events, request), (200, b"h2 body".to_vec(), true));
+        }
+        let first_info = fetch.request_connection(first).unwrap();
+        let second_info = fetch.request_connection(second).unwrap();
+        assert_eq!(first_info.protocol, "h2");
+        assert_eq!(second_info.connection, first_info.connection);
+        assert!(second_info.reused);
+
+        let third = fetch
+            .start("https://fetch.test/c", &FetchOptions::new())
+            .unwrap();
+        assert_eq!(fetch.pending_requests(), 0);
+        run(&mut fetch, &mut servers, "h2", &mut respond);
+        let events = drain_events(&mut fetch);
+        assert_eq!(response(&events, third), (200, b"h2 body".to_vec(), true));
+        assert_eq!(fetch.connections(), 1);
+    }
+
+    #[test]
+    fn cancelled_requests_stop_mid_body() {
+        let mut fetch = client();
+        let mut servers = BTreeMap::new();
+        let frame = |kind: u8, flags: u8, stream: u32, payload: &[u8]| {
+            let mut frame = (payload.len() as u32).to_be_bytes()[1..].to_vec();
+            frame.extend([kind, flags]);
+            frame.extend(stream.to_be_bytes());
+            frame.extend(payload);
+            frame
+        };
+        let mut resets = Vec::new();
+        let mut respond = |received: &[u8]| {
+            let mut reply = Vec::new();
+            let mut rest = received;
+            if let Some(frames) = rest.strip_prefix(PREFACE) {
+                reply.extend(frame(0x4, 0, 0, &[]));
+                reply.extend(frame(0x4, 0x1, 0, &[]));
+                rest = frames;
+            }
+            while rest.len() >= 9 {
+                let length = u32::from_be_bytes([0, rest[0], rest[1], rest[2]]) as usize;
+                let stream = u32::from_be_bytes(rest[5..9].try_into().unwrap()) & 0x7fff_ffff;
+                match rest[3] {
+                    // Half the body, the rest once the stream is reset
+                    0x1 => {
+                        reply.extend(frame(0x1, 0x4, stream, &[0x88]));
+                        reply.extend(frame(0x0, 0, stream, b"first half"));
+                    }
+                    0x3 => {
+                        resets.push((stream, rest[9..9 + length].to_vec()));
+                        reply.extend(frame(0x0, 0x1, stream, b"second half"));
+                    }
+                    _ => {}
+                }
+                rest = &rest[9 + length..];
+            }
+            (!reply.is_empty()).then_some(reply)
+        };
+
+        let request = fetch
+            .start("https://fetch.test/a", &FetchOptions::new())
+            .unwrap();
+        run(&mut fetch, &mut servers, "h2", &mut respond);
+        let events = drain_events(&mut fetch);
+        assert_eq!(
+            response(&events, request),
+            (200, b"first half".to_vec(), false)
+        );
+        fetch.cancel(request);
+        run(&mut fetch, &mut servers, "h2", &mut respond);
+        assert!(drain_events(&mut fetch).is_empty());
+        assert_eq!(resets, [(1, CANCEL.to_be_bytes().to_vec())]);
+        // The connection outlives the stream
+        assert_eq!(fetch.connections(), 1);
+
+        // An HTTP/1.1 connection cannot: it closes, from the body stream's cancel
+        let mut fetch = client();
+        let mut servers = BTreeMap::new();
+        let mut respond = |received: &[u8]| {
+            received
+                .ends_with(b"\r\n\r\n")
+                .then(|| b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nhello".to_vec())
+        };
+        let request = fetch
+            .start("https://fetch.test/a", &FetchOptions::new())
+            .unwrap();
+        run(&mut fetch, &mut servers, "http/1.1", &mut respond);
+        let events = drain_events(&mut fetch);
+        assert_eq!(response(&events, request), (200, b"hello".to_vec(), false));
+        fetch.cancelled.borrow_mut().push(request);
+        fetch.run_transport().unwrap();
+        run(&mut fetch, &mut servers, "http/1.1", &mut respond);
+        assert!(drain_events(&mut fetch).is_empty());
+        assert!(servers.is_empty());
+        assert_eq!(fetch.connections(), 0);
+    }
+
+    #[test]
+    fn hosts_resolve_over_doh() {
+        let mut fetch = client();
+        fetch
+            .set_doh_resolver("https://fetch.test:8443/dns-query")
+            .unwrap();
+        fetch.set_now(0.0);
+        let mut servers = BTreeMap::new();
+        let mut queries = Vec::new();
+        let mut respond = |received: &[u8]| {
+            let end = received.windows(4).position(|w| w == b"\r\n\r\n")? + 4;
+            let head = std::str::from_utf8(&received[..end]).unwrap();
+            if !head.starts_with("POST /dns-query ") {
+                return Some(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello".to_vec());
+            }
+            assert!(head.contains("\r\nContent-Type: application/dns-message\r\n"));
+            let length: usize = head
+                .lines()
+                .find_map(|l| l.strip_prefix("Content-Length: "))
+                .map_or(0, |n| n.parse().unwrap());
+            let query = DnsMessage::decode(received.get(end..end + length)?).unwrap();
+            let question = &query.questions[0];
+            queries.push((question.name.clone(), question.record_type));
+            // No A records, one AAAA record
+            let mut answer = query.clone();
+            answer.flags |= 0x8000;
+            if question.record_type == AAAA {
+                let address = RecordData::Aaaa("2001:db8::7".parse().unwrap());
+                answer.answers.push(Record::new("fetch.test", 300, address));
+            }
+            let body = answer.encode().unwrap();
+            let mut reply = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/dns-message\r\nContent-Length: {}\r\n\r\n",
+                body.len()
+            )
+            .into_bytes();
+            reply.extend(body);
+            Some(reply)
+        };
+
+        let first = fetch
+            .start("https://fetch.test/", &FetchOptions::new())
+            .unwrap();
+        run(&mut fetch, &mut servers, "http/1.1", &mut respond);
+        let events = drain_events(&mut fetch);
+        assert_eq!(response(&events, first), (200, b"hello".to_vec(), true));
+        // The lookups' own requests stay internal
+        assert!(events.iter().all(|event| match event {
+            FetchEvent::Head { request, .. }
+            | FetchEvent::Data { request, .. }
+            | FetchEvent::Complete { request }
+            | FetchEvent::Failed { request, .. } => *request == first,
+        }));
+        let info = fetch.request_connection(first).unwrap().clone();
+        assert_eq!(info.host, "fetch.test");
+        assert_eq!(info.address.as_deref(), Some("2001:db8::7"));
+        // The origin's connection and the resolver's
+        assert_eq!(fetch.connections(), 2);
+
+        // The answer stays fresh for its TTL: a new connection needs no lookup
+        fetch.closed(info.connection).unwrap();
+        servers.remove(&info.connection);
+        fetch.set_now(299_000.0);
+        let second = fetch
+            .start("https://fetch.test/", &FetchOptions::new())
+            .unwrap();
+        run(&mut fetch, &mut servers, "http/1.1", &mut respond);
+        let events = drain_events(&mut fetch);
+        assert_eq!(response(&events, second), (200, b"hello".to_vec(), true));
+        let second_info = fetch.request_connection(second).unwrap();
+        assert_ne!(second_info.connection, info.connection);
+        assert_eq!(second_info.address.as_deref(), Some("2001:db8::7"));
+        assert_eq!(
+            queries,
+            [
+                ("fetch.test".to_string(), A),
+                ("fetch.test".to_string(), AAAA)
+            ]
+        );
+    }
+
+    #[test]
+    fn dns_over_tls_pipelines_on_one_connection() {
+        let mut options = TlsOptions::default();
+        options.add_root("fetch.test", SERVER_CA.to_vec()).unwrap();
+        let mut tls = TlsConnection::with_options("fetch.test", DOT_ALPN, &options).unwrap();
+        let mut server = Server::new(DOT_ALPN);
+        let mut dot = DotClient::new();
+        let first = dot.query("fetch.test", "A").unwrap();
+        let second = dot.query("fetch.test", "AAAA").unwrap();
+        // Queued until the handshake is done, then sent with our Finished
+        tls.write_plaintext(&dot.take_outgoing()).unwrap();
+        loop {
+            let out = tls.flush_outgoing_tls().unwrap();
+            server.receive(&out);
+            let reply = server.take_outgoing();
+            if out.is_empty() && reply.is_empty() {
+                break;
+            }
+            if !reply.is_empty() {
+                tls.feed_ciphertext(&reply).unwrap();
+            }
+        }
+        assert_eq!(tls.negotiated_alpn().as_deref(), Some(DOT_ALPN));
+
+        let mut queries = Vec::new();
+        let mut rest = &server.received[..];
+        while let [high, low, tail @ ..] = rest {
+            let len = u16::from_be_bytes([*high, *low]) as usize;
+            queries.push(DnsMessage::decode(&tail[..len]).unwrap());
+            rest = &tail[len..];
+        }
+        assert_eq!(queries.len(), 2);
+        // Answered last query first, the response split across two records
+        for query in queries.iter().rev() {
+            let mut response = query.clone();
+            response.flags |= 0x8000;
+            let mut edns = Edns::default();
+            edns.options.push((11, 50u16.to_be_bytes().to_vec()));
+            response.edns = Some(edns);
+            let data = if query.questions[0].record_type == AAAA {
+                RecordData::Aaaa("2001:db8::7".parse().unwrap())
+            } else {
+                RecordData::A([192, 0, 2, 7].into())
+            };
+            response.answers.push(Record::new("fetch.test", 300, data));
+            let encoded = response.encode().unwrap();
+            let mut framed = (encoded.len() as u16).to_be_bytes().to_vec();
+            framed.extend(encoded);
+            let (head, tail) = framed.split_at(5);
+            server.tls.writer().write_all(head).unwrap();
+            server.tls.writer().write_all(tail).unwrap();
+        }
+        tls.feed_ciphertext(&server.take_outgoing()).unwrap();
+        assert_eq!(dot.feed(&tls.take_plaintext()).unwrap(), [second, first]);
+        let mut addresses = |id| dot.take_response(id).unwrap().unwrap().addresses();
+        assert_eq!(addresses(first)[0].0.to_string(), "192.0.2.7");
+        assert_eq!(addresses(second)[0].0.to_string(), "2001:db8::7");
+        assert_eq!(dot.idle_timeout(), 5000);
+    }
+}
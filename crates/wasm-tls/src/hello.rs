@@ -0,0 +1,538 @@
+//! Parsing of plaintext handshake records (ClientHello / ServerHello) for fingerprint inspection.
+
+const CONTENT_TYPE_CHANGE_CIPHER_SPEC: u8 = 20;
+const CONTENT_TYPE_HANDSHAKE: u8 = 22;
+pub(crate) const HANDSHAKE_CLIENT_HELLO: u8 = 1;
+const HANDSHAKE_SERVER_HELLO: u8 = 2;
+
+/// The random that makes a ServerHello a HelloRetryRequest (RFC 8446 §4.1.3).
+const HELLO_RETRY_REQUEST_RANDOM: [u8; 32] = [
+    0xcf, 0x21, 0xad, 0x74, 0xe5, 0x9a, 0x61, 0x11, 0xbe, 0x1d, 0x8c, 0x02, 0x1e, 0x65, 0xb8, 0x91,
+    0xc2, 0xa2, 0x11, 0x16, 0x7a, 0xbb, 0x8c, 0x5e, 0x07, 0x9e, 0x09, 0xe2, 0xc8, 0xa8, 0x33, 0x9c,
+];
+
+/// Big-endian cursor over a handshake message body.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.0.len() < n {
+            return None;
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    /// A vector with a `len_bytes`-byte length prefix.
+    fn vec(&mut self, len_bytes: usize) -> Option<&'a [u8]> {
+        let len = self
+            .take(len_bytes)?
+            .iter()
+            .fold(0usize, |acc, &b| (acc << 8) | b as usize);
+        self.take(len)
+    }
+}
+
+/// Reassemble the payload of consecutive handshake records at the start of `records`
+/// and return the first complete handshake message (header included) of type `msg_type`.
+pub(crate) fn find_handshake_message(records: &[u8], msg_type: u8) -> Option<Vec<u8>> {
+    handshake_messages(records, msg_type).into_iter().next()
+}
+
+/// The server's ServerHello in the records it sent first, past a HelloRetryRequest
+/// and the ChangeCipherSpec records TLS 1.3 servers send for middlebox
+/// compatibility. None until the whole message has arrived.
+pub(crate) fn find_server_hello(records: &[u8]) -> Option<Vec<u8>> {
+    handshake_messages(records, HANDSHAKE_SERVER_HELLO)
+        .into_iter()
+        .find(|msg| msg.get(6..38) != Some(&HELLO_RETRY_REQUEST_RANDOM[..]))
+}
+
+/// Every complete handshake message (header included) of type `msg_type` in the
+/// plaintext handshake records at the start of `records`.
+fn handshake_messages(records: &[u8], msg_type: u8) -> Vec<Vec<u8>> {
+    let mut payload = Vec::new();
+    let mut rest = Reader(records);
+    while let Some(content_type) = rest.u8() {
+        let (Some(_version), Some(fragment)) = (rest.take(2), rest.vec(2)) else {
+            break;
+        };
+        match content_type {
+            CONTENT_TYPE_HANDSHAKE => payload.extend_from_slice(fragment),
+            CONTENT_TYPE_CHANGE_CIPHER_SPEC => {}
+            _ => break,
+        }
+    }
+
+    let mut found = Vec::new();
+    let mut messages = Reader(&payload);
+    while let Some(typ) = messages.u8() {
+        let Some(body) = messages.vec(3) else {
+            break;
+        };
+        if typ == msg_type {
+            let mut msg = Vec::with_capacity(4 + body.len());
+            msg.push(typ);
+            msg.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+            msg.extend_from_slice(body);
+            found.push(msg);
+        }
+    }
+    found
+}
+
+const EXTENSION_SERVER_NAME: u16 = 0;
+const EXTENSION_SUPPORTED_GROUPS: u16 = 10;
+const EXTENSION_EC_POINT_FORMATS: u16 = 11;
+const EXTENSION_SIGNATURE_ALGORITHMS: u16 = 13;
+const EXTENSION_ALPN: u16 = 16;
+const EXTENSION_SUPPORTED_VERSIONS: u16 = 43;
+
+/// The fingerprinted lists of a ClientHello, each in wire order.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct HelloFingerprint {
+    pub(crate) cipher_suites: Vec<u16>,
+    /// From the supported_groups extension
+    pub(crate) groups: Vec<u16>,
+    pub(crate) extensions: Vec<u16>,
+}
+
+/// The rest of what JA3 and JA4 take from a ClientHello.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct HelloDetails {
+    pub(crate) fingerprint: HelloFingerprint,
+    pub(crate) legacy_version: u16,
+    pub(crate) point_formats: Vec<u8>,
+    /// In wire order
+    pub(crate) signature_algorithms: Vec<u16>,
+    pub(crate) first_alpn: Option<Vec<u8>>,
+    pub(crate) supported_versions: Vec<u16>,
+}
+
+/// Fingerprinted lists of a ClientHello handshake message.
+pub(crate) fn client_hello_fingerprint(msg: &[u8]) -> Option<HelloFingerprint> {
+    client_hello_details(msg).map(|d| d.fingerprint)
+}
+
+pub(crate) fn client_hello_details(msg: &[u8]) -> Option<HelloDetails> {
+    let mut r = Reader(msg);
+    if r.u8()? != HANDSHAKE_CLIENT_HELLO {
+        return None;
+    }
+    let mut body = Reader(r.vec(3)?);
+    let mut details = HelloDetails {
+        legacy_version: body.u16()?,
+        ..HelloDetails::default()
+    };
+    body.take(32)?; // random
+    body.vec(1)?; // legacy_session_id
+    details.fingerprint.cipher_suites = u16_list(body.vec(2)?)?;
+    body.vec(1)?; // legacy_compression_methods
+    let mut extensions = Reader(body.vec(2).unwrap_or_default());
+    while let Some(typ) = extensions.u16() {
+        let data = extensions.vec(2)?;
+        match typ {
+            EXTENSION_SUPPORTED_GROUPS => {
+                details.fingerprint.groups = u16_list(Reader(data).vec(2)?)?;
+            }
+            // Malformed contents only leave these lists empty
+            EXTENSION_EC_POINT_FORMATS => {
+                details.point_formats = Reader(data).vec(1).unwrap_or_default().to_vec();
+            }
+            EXTENSION_SIGNATURE_ALGORITHMS => {
+                let list = Reader(data).vec(2).and_then(u16_list);
+                details.signature_algorithms = list.unwrap_or_default();
+            }
+            EXTENSION_ALPN => {
+                let mut names = Reader(Reader(data).vec(2).unwrap_or_default());
+                details.first_alpn = names.vec(1).map(<[u8]>::to_vec);
+            }
+            EXTENSION_SUPPORTED_VERSIONS => {
+                let list = Reader(data).vec(1).and_then(u16_list);
+                details.supported_versions = list.unwrap_or_default();
+            }
+            _ => {}
+        }
+        details.fingerprint.extensions.push(typ);
+    }
+    Some(details)
+}
+
+/// JA3 string of a ClientHello: version, cipher suites, extensions, groups and
+/// point formats in wire order, in decimal, GREASE left out. The JA3 hash is its
+/// MD5. It changes with extension order, which rustls shuffles per hello.
+pub(crate) fn ja3(details: &HelloDetails) -> String {
+    let decimal = |list: &[u16]| {
+        list.iter()
+            .filter(|&&v| !is_grease(v))
+            .map(u16::to_string)
+            .collect::<Vec<_>>()
+            .join("-")
+    };
+    let f = &details.fingerprint;
+    let point_formats: Vec<String> = details.point_formats.iter().map(u8::to_string).collect();
+    format!(
+        "{},{},{},{},{}",
+        details.legacy_version,
+        decimal(&f.cipher_suites),
+        decimal(&f.extensions),
+        decimal(&f.groups),
+        point_formats.join("-")
+    )
+}
+
+/// JA3S string of a ServerHello: version, chosen cipher suite and extensions in
+/// wire order, in decimal. The JA3S hash is its MD5. The version is the legacy
+/// one, 771 (TLS 1.2) for TLS 1.3 as well.
+pub(crate) fn ja3s(msg: &[u8]) -> Option<String> {
+    let mut r = Reader(msg);
+    if r.u8()? != HANDSHAKE_SERVER_HELLO {
+        return None;
+    }
+    let mut body = Reader(r.vec(3)?);
+    let version = body.u16()?;
+    body.take(32)?; // random
+    body.vec(1)?; // legacy_session_id_echo
+    let cipher_suite = body.u16()?;
+    body.u8()?; // legacy_compression_method
+    let mut extensions = Reader(body.vec(2).unwrap_or_default());
+    let mut types = Vec::new();
+    while let Some(typ) = extensions.u16() {
+        extensions.vec(2)?;
+        types.push(typ.to_string());
+    }
+    Some(format!("{},{},{}", version, cipher_suite, types.join("-")))
+}
+
+/// JA4 fingerprint (FoxIO) of a ClientHello sent over TCP, e.g.
+/// "t13d1516h2_8daaf6152771_e5627efa2ab1". Suites and extensions are sorted, so
+/// unlike JA3 it does not depend on extension order.
+pub(crate) fn ja4(details: &HelloDetails) -> String {
+    use sha2::{Digest, Sha256};
+
+    let no_grease =
+        |list: &[u16]| -> Vec<u16> { list.iter().copied().filter(|&v| !is_grease(v)).collect() };
+    let f = &details.fingerprint;
+    let suites = no_grease(&f.cipher_suites);
+    let extensions = no_grease(&f.extensions);
+    let version = no_grease(&details.supported_versions)
+        .into_iter()
+        .max()
+        .unwrap_or(details.legacy_version);
+    let version = match version {
+        0x0304 => "13",
+        0x0303 => "12",
+        0x0302 => "11",
+        0x0301 => "10",
+        0x0300 => "s3",
+        _ => "00",
+    };
+    let sni = if extensions.contains(&EXTENSION_SERVER_NAME) {
+        'd'
+    } else {
+        'i'
+    };
+    let alpn = match details.first_alpn.as_deref() {
+        Some([first, .., last])
+            if first.is_ascii_alphanumeric() && last.is_ascii_alphanumeric() =>
+        {
+            format!("{}{}", *first as char, *last as char)
+        }
+        Some([only]) if only.is_ascii_alphanumeric() => format!("{0}{0}", *only as char),
+        Some(value) if !value.is_empty() => {
+            let hex: String = value.iter().map(|b| format!("{:02x}", b)).collect();
+            format!("{}{}", &hex[..1], &hex[hex.len() - 1..])
+        }
+        _ => "00".to_string(),
+    };
+    let hex_list = |list: &[u16]| {
+        list.iter()
+            .map(|v| format!("{:04x}", v))
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+    let truncated_hash = |text: String| {
+        if text.is_empty() {
+            return "000000000000".to_string();
+        }
+        let digest = Sha256::digest(text.as_bytes());
+        digest[..6]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+    };
+    let mut sorted_suites = suites.clone();
+    sorted_suites.sort_unstable();
+    let mut sorted_extensions: Vec<u16> = extensions
+        .iter()
+        .copied()
+        .filter(|&e| e != EXTENSION_SERVER_NAME && e != EXTENSION_ALPN)
+        .collect();
+    sorted_extensions.sort_unstable();
+    let mut extension_text = hex_list(&sorted_extensions);
+    if !details.signature_algorithms.is_empty() && !extension_text.is_empty() {
+        extension_text.push('_');
+        extension_text.push_str(&hex_list(&details.signature_algorithms));
+    }
+    format!(
+        "t{}{}{:02}{:02}{}_{}_{}",
+        version,
+        sni,
+        suites.len().min(99),
+        extensions.len().min(99),
+        alpn,
+        truncated_hash(hex_list(&sorted_suites)),
+        truncated_hash(extension_text)
+    )
+}
+
+/// Extension type codes of a ClientHello handshake message, in wire order.
+pub(crate) fn client_hello_extensions(msg: &[u8]) -> Option<Vec<u16>> {
+    client_hello_fingerprint(msg).map(|f| f.extensions)
+}
+
+fn u16_list(bytes: &[u8]) -> Option<Vec<u16>> {
+    let mut r = Reader(bytes);
+    let mut list = Vec::with_capacity(bytes.len() / 2);
+    while !r.0.is_empty() {
+        list.push(r.u16()?);
+    }
+    Some(list)
+}
+
+/// GREASE values (RFC 8701): 0x0a0a, 0x1a1a, ... 0xfafa.
+fn is_grease(value: u16) -> bool {
+    value & 0x0f0f == 0x0a0a && value >> 8 == value & 0xff
+}
+
+/// Every way `actual` diverges from `target`, as readable sentences. Suites and
+/// groups the provider does not implement (not in `supported_suites` /
+/// `supported_groups`) are said to be missing for that reason. Extension order is
+/// not compared: rustls randomizes it per hello, as Chrome does.
+pub(crate) fn fidelity_report(
+    target: &HelloFingerprint,
+    actual: &HelloFingerprint,
+    supported_suites: &[u16],
+    supported_groups: &[u16],
+) -> Vec<String> {
+    let mut report = Vec::new();
+    compare(
+        &mut report,
+        "cipher suite",
+        &target.cipher_suites,
+        &actual.cipher_suites,
+        Some(supported_suites),
+    );
+    compare(
+        &mut report,
+        "group",
+        &target.groups,
+        &actual.groups,
+        Some(supported_groups),
+    );
+    compare(
+        &mut report,
+        "extension",
+        &target.extensions,
+        &actual.extensions,
+        None,
+    );
+    report
+}
+
+/// `supported` is given for ordered lists; extensions pass `None`.
+fn compare(
+    report: &mut Vec<String>,
+    kind: &str,
+    target: &[u16],
+    actual: &[u16],
+    supported: Option<&[u16]>,
+) {
+    if target.iter().any(|&v| is_grease(v)) && !actual.iter().any(|&v| is_grease(v)) {
+        report.push(format!("{}: GREASE not emitted", kind));
+    }
+    let target: Vec<u16> = target.iter().copied().filter(|&v| !is_grease(v)).collect();
+    let actual: Vec<u16> = actual.iter().copied().filter(|&v| !is_grease(v)).collect();
+    for value in target.iter().filter(|v| !actual.contains(v)) {
+        match supported {
+            Some(supported) if !supported.contains(value) => report.push(format!(
+                "{} 0x{:04x} not emitted: the crypto provider lacks it",
+                kind, value
+            )),
+            _ => report.push(format!("{} 0x{:04x} not emitted", kind, value)),
+        }
+    }
+    for value in actual.iter().filter(|v| !target.contains(v)) {
+        report.push(format!(
+            "{} 0x{:04x} emitted but not in the target",
+            kind, value
+        ));
+    }
+    if supported.is_some() {
+        let common = |list: &[u16], other: &[u16]| -> Vec<u16> {
+            list.iter().copied().filter(|v| other.contains(v)).collect()
+        };
+        if common(&target, &actual) != common(&actual, &target) {
+            report.push(format!("{} order differs", kind));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(payload: &[u8]) -> Vec<u8> {
+        let mut out = vec![CONTENT_TYPE_HANDSHAKE, 3, 1];
+        out.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        out.extend_from_slice(payload);
+        out
+    }
+
+    fn client_hello(extensions: &[(u16, &[u8])]) -> Vec<u8> {
+        let mut body = vec![3, 3];
+        body.extend_from_slice(&[0; 32]);
+        body.push(0);
+        body.extend_from_slice(&[0, 2, 0x13, 0x01]);
+        body.extend_from_slice(&[1, 0]);
+        let mut exts = Vec::new();
+        for (typ, data) in extensions {
+            exts.extend_from_slice(&typ.to_be_bytes());
+            exts.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            exts.extend_from_slice(data);
+        }
+        body.extend_from_slice(&(exts.len() as u16).to_be_bytes());
+        body.extend(exts);
+        let mut msg = vec![HANDSHAKE_CLIENT_HELLO];
+        msg.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+        msg.extend(body);
+        msg
+    }
+
+    #[test]
+    fn extension_order_is_preserved() {
+        let msg = client_hello(&[(0, b"sni"), (0x0a0a, b""), (43, &[2, 3, 4]), (16, b"h2")]);
+        let found = find_handshake_message(&record(&msg), HANDSHAKE_CLIENT_HELLO).unwrap();
+        assert_eq!(found, msg);
+        assert_eq!(
+            client_hello_extensions(&found).unwrap(),
+            [0, 0x0a0a, 43, 16]
+        );
+    }
+
+    #[test]
+    fn reassembles_fragmented_records() {
+        let msg = client_hello(&[(10, &[0; 300]), (13, b"x")]);
+        let (a, b) = msg.split_at(100);
+        let records = [record(a), record(b)].concat();
+        let found = find_handshake_message(&records, HANDSHAKE_CLIENT_HELLO).unwrap();
+        assert_eq!(client_hello_extensions(&found).unwrap(), [10, 13]);
+    }
+
+    #[test]
+    fn fingerprint_and_fidelity() {
+        let msg = client_hello(&[
+            (0, b"sni"),
+            (10, &[0, 6, 0x0a, 0x0a, 0, 0x1d, 0, 0x17]),
+            (43, b""),
+        ]);
+        let actual = client_hello_fingerprint(&msg).unwrap();
+        assert_eq!(actual.cipher_suites, [0x1301]);
+        assert_eq!(actual.groups, [0x0a0a, 0x1d, 0x17]);
+        assert_eq!(actual.extensions, [0, 10, 43]);
+        assert!(fidelity_report(&actual, &actual, &[], &[]).is_empty());
+
+        let target = HelloFingerprint {
+            cipher_suites: vec![0x2a2a, 0x1302, 0x1301],
+            groups: vec![0x17, 0x1d],
+            extensions: vec![43, 0, 28],
+        };
+        assert_eq!(
+            fidelity_report(&target, &actual, &[0x1301], &[0x17, 0x1d]),
+            [
+                "cipher suite: GREASE not emitted",
+                "cipher suite 0x1302 not emitted: the crypto provider lacks it",
+                "group order differs",
+                "extension 0x001c not emitted",
+                "extension 0x000a emitted but not in the target",
+            ]
+        );
+    }
+
+    #[test]
+    fn truncated_input_yields_nothing() {
+        let msg = client_hello(&[(0, b"sni")]);
+        let rec = record(&msg);
+        assert!(find_handshake_message(&rec[..rec.len() - 1], HANDSHAKE_CLIENT_HELLO).is_none());
+        assert!(client_hello_extensions(&msg[..msg.len() - 2]).is_none());
+    }
+
+    #[test]
+    fn server_hello_after_retry_request() {
+        let server_hello = |random: [u8; 32]| {
+            let mut body = vec![3, 3];
+            body.extend_from_slice(&random);
+            body.extend_from_slice(&[0, 0x13, 0x01, 0]);
+            // supported_versions (TLS 1.3), then key_share
+            body.extend_from_slice(&[0, 14, 0, 43, 0, 2, 3, 4, 0, 51, 0, 4, 0, 29, 0, 0]);
+            let mut msg = vec![HANDSHAKE_SERVER_HELLO];
+            msg.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+            msg.extend(body);
+            msg
+        };
+        let retry = server_hello(HELLO_RETRY_REQUEST_RANDOM);
+        let hello = server_hello([7; 32]);
+        let change_cipher_spec = [CONTENT_TYPE_CHANGE_CIPHER_SPEC, 3, 3, 0, 1, 1];
+        let records = [record(&retry), change_cipher_spec.to_vec(), record(&hello)].concat();
+        assert_eq!(find_server_hello(&records[..records.len() - 1]), None);
+        assert_eq!(find_server_hello(&records).unwrap(), hello);
+        assert_eq!(ja3s(&hello).unwrap(), "771,4865,43-51");
+        assert_eq!(ja3s(&client_hello(&[])), None);
+    }
+
+    #[test]
+    fn ja3_and_ja4() {
+        let msg = client_hello(&[
+            (0x1a1a, b""),
+            (0, b"sni"),
+            (10, &[0, 4, 0, 29, 0, 23]),
+            (11, &[1, 0]),
+            (16, &[0, 3, 2, b'h', b'2']),
+        ]);
+        let details = client_hello_details(&msg).unwrap();
+        assert_eq!(ja3(&details), "771,4865,0-10-11-16,29-23,0");
+        assert_eq!(&ja4(&details)[..11], "t12d0104h2_");
+
+        // FoxIO's Chrome example
+        let chrome = HelloDetails {
+            fingerprint: HelloFingerprint {
+                cipher_suites: vec![
+                    0x0a0a, 0x1301, 0x1302, 0x1303, 0xc02b, 0xc02f, 0xc02c, 0xc030, 0xcca9, 0xcca8,
+                    0xc013, 0xc014, 0x009c, 0x009d, 0x002f, 0x0035,
+                ],
+                groups: vec![],
+                extensions: vec![
+                    0x0a0a, 0x001b, 0x0000, 0x0033, 0x0010, 0x4469, 0x0017, 0x002d, 0x000d, 0x0005,
+                    0x0023, 0x0012, 0x002b, 0xff01, 0x000b, 0x000a, 0x0015,
+                ],
+            },
+            legacy_version: 0x0303,
+            point_formats: vec![0],
+            signature_algorithms: vec![
+                0x0403, 0x0804, 0x0401, 0x0503, 0x0805, 0x0501, 0x0806, 0x0601,
+            ],
+            first_alpn: Some(b"h2".to_vec()),
+            supported_versions: vec![0x2a2a, 0x0304, 0x0303],
+        };
+        assert_eq!(ja4(&chrome), "t13d1516h2_8daaf6152771_e5627efa2ab1");
+    }
+}
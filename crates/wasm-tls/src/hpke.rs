@@ -0,0 +1,366 @@
+//! HPKE (RFC 9180) base mode, for Encrypted ClientHello: DHKEM(X25519,
+//! HKDF-SHA256) and HKDF-SHA256 with AES-128-GCM, AES-256-GCM or
+//! ChaCha20Poly1305, the suites ECH configs are published with. The crypto
+//! provider brings no HPKE of its own.
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes128Gcm, Aes256Gcm};
+use chacha20poly1305::ChaCha20Poly1305;
+use hkdf::Hkdf;
+use rustls::crypto::hpke::{
+    EncapsulatedSecret, Hpke, HpkeOpener, HpkePrivateKey, HpkePublicKey, HpkeSealer, HpkeSuite,
+};
+// `HpkeSuite`'s fields are typed with these, which rustls only exports under
+// `internal`, outside its semver guarantees; Cargo.toml pins rustls to one
+// release for them, to be checked again on every bump
+use rustls::internal::msgs::enums::{HpkeAead, HpkeKdf, HpkeKem};
+use rustls::internal::msgs::handshake::HpkeSymmetricCipherSuite;
+use rustls::Error;
+use sha2::Sha256;
+use x25519_dalek::{x25519, X25519_BASEPOINT_BYTES};
+use zeroize::{ZeroizeOnDrop, Zeroizing};
+
+/// Every suite, for `rustls::client::EchConfig::new` to pick from.
+pub(crate) static SUITES: &[&'static dyn Hpke] = &[
+    &X25519Hpke {
+        aead: HpkeAead::AES_128_GCM,
+    },
+    &X25519Hpke {
+        aead: HpkeAead::AES_256_GCM,
+    },
+    &X25519Hpke {
+        aead: HpkeAead::CHACHA20_POLY_1305,
+    },
+];
+
+const KEM_ID: u16 = 0x0020;
+const KDF_ID: u16 = 0x0001;
+/// Nonce length of all three AEADs
+const NONCE_LEN: usize = 12;
+
+#[derive(Debug)]
+pub(crate) struct X25519Hpke {
+    aead: HpkeAead,
+}
+
+impl X25519Hpke {
+    /// "HPKE" || kem_id || kdf_id || aead_id
+    fn suite_id(&self) -> Vec<u8> {
+        let mut id = b"HPKE".to_vec();
+        id.extend_from_slice(&KEM_ID.to_be_bytes());
+        id.extend_from_slice(&KDF_ID.to_be_bytes());
+        id.extend_from_slice(&u16::from(self.aead).to_be_bytes());
+        id
+    }
+
+    fn key_len(&self) -> usize {
+        match self.aead {
+            HpkeAead::AES_128_GCM => 16,
+            _ => 32,
+        }
+    }
+
+    /// `SetupBaseS` with the ephemeral key `sk_e`.
+    fn setup_sealer_with(
+        &self,
+        info: &[u8],
+        pk_r: &[u8],
+        sk_e: Zeroizing<[u8; 32]>,
+    ) -> Result<(EncapsulatedSecret, Context), Error> {
+        let pk_r = key(pk_r)?;
+        let enc = x25519(*sk_e, X25519_BASEPOINT_BYTES);
+        let shared_secret = shared_secret(&*dh(&sk_e, pk_r)?, &enc, &pk_r);
+        let context = self.key_schedule(&*shared_secret, info);
+        Ok((EncapsulatedSecret(enc.to_vec()), context))
+    }
+
+    /// `SetupBaseR`.
+    fn setup_opener_with(&self, enc: &[u8], info: &[u8], sk_r: &[u8]) -> Result<Context, Error> {
+        let sk_r = Zeroizing::new(key(sk_r)?);
+        let pk_r = x25519(*sk_r, X25519_BASEPOINT_BYTES);
+        let shared_secret = shared_secret(&*dh(&sk_r, key(enc)?)?, enc, &pk_r);
+        Ok(self.key_schedule(&*shared_secret, info))
+    }
+
+    /// `KeySchedule` in base mode: no PSK, so only the key and nonce are derived
+    /// (the exporter secret is never used).
+    fn key_schedule(&self, shared_secret: &[u8], info: &[u8]) -> Context {
+        let suite = self.suite_id();
+        let mut context = vec![0]; // mode_base
+        context.extend_from_slice(&*labeled_extract(&suite, b"", b"psk_id_hash", b""));
+        context.extend_from_slice(&*labeled_extract(&suite, b"", b"info_hash", info));
+        let secret = labeled_extract(&suite, shared_secret, b"secret", b"");
+        let mut key = vec![0; self.key_len()];
+        labeled_expand(&suite, &*secret, b"key", &context, &mut key);
+        let mut base_nonce = [0; NONCE_LEN];
+        labeled_expand(&suite, &*secret, b"base_nonce", &context, &mut base_nonce);
+        Context {
+            aead: self.aead,
+            key,
+            base_nonce,
+            seq: 0,
+        }
+    }
+}
+
+impl Hpke for X25519Hpke {
+    fn seal(
+        &self,
+        info: &[u8],
+        aad: &[u8],
+        plaintext: &[u8],
+        pub_key: &HpkePublicKey,
+    ) -> Result<(EncapsulatedSecret, Vec<u8>), Error> {
+        let (enc, mut sealer) = self.setup_sealer(info, pub_key)?;
+        Ok((enc, sealer.seal(aad, plaintext)?))
+    }
+
+    fn setup_sealer(
+        &self,
+        info: &[u8],
+        pub_key: &HpkePublicKey,
+    ) -> Result<(EncapsulatedSecret, Box<dyn HpkeSealer + 'static>), Error> {
+        let (enc, context) = self.setup_sealer_with(info, &pub_key.0, random_key()?)?;
+        Ok((enc, Box::new(context)))
+    }
+
+    fn open(
+        &self,
+        enc: &EncapsulatedSecret,
+        info: &[u8],
+        aad: &[u8],
+        ciphertext: &[u8],
+        secret_key: &HpkePrivateKey,
+    ) -> Result<Vec<u8>, Error> {
+        self.setup_opener(enc, info, secret_key)?
+            .open(aad, ciphertext)
+    }
+
+    fn setup_opener(
+        &self,
+        enc: &EncapsulatedSecret,
+        info: &[u8],
+        secret_key: &HpkePrivateKey,
+    ) -> Result<Box<dyn HpkeOpener + 'static>, Error> {
+        let context = self.setup_opener_with(&enc.0, info, secret_key.secret_bytes())?;
+        Ok(Box::new(context))
+    }
+
+    fn generate_key_pair(&self) -> Result<(HpkePublicKey, HpkePrivateKey), Error> {
+        let secret = random_key()?;
+        let public = x25519(*secret, X25519_BASEPOINT_BYTES);
+        Ok((
+            HpkePublicKey(public.to_vec()),
+            HpkePrivateKey::from(secret.to_vec()),
+        ))
+    }
+
+    fn suite(&self) -> HpkeSuite {
+        HpkeSuite {
+            kem: HpkeKem::DHKEM_X25519_HKDF_SHA256,
+            sym: HpkeSymmetricCipherSuite {
+                kdf_id: HpkeKdf::HKDF_SHA256,
+                aead_id: self.aead,
+            },
+        }
+    }
+}
+
+/// The encryption context of one side, sealing or opening messages in order.
+/// Its key and nonce are cleared on drop.
+#[derive(Debug, ZeroizeOnDrop)]
+struct Context {
+    #[zeroize(skip)]
+    aead: HpkeAead,
+    key: Vec<u8>,
+    base_nonce: [u8; NONCE_LEN],
+    #[zeroize(skip)]
+    seq: u64,
+}
+
+impl Context {
+    /// base_nonce XOR the sequence number, which then moves on.
+    fn next_nonce(&mut self) -> Result<[u8; NONCE_LEN], Error> {
+        if self.seq == u64::MAX {
+            return Err(Error::General("HPKE sequence number overflow".into()));
+        }
+        let mut nonce = self.base_nonce;
+        for (n, s) in nonce[NONCE_LEN - 8..]
+            .iter_mut()
+            .zip(self.seq.to_be_bytes())
+        {
+            *n ^= s;
+        }
+        self.seq += 1;
+        Ok(nonce)
+    }
+
+    fn crypt(&mut self, aad: &[u8], msg: &[u8], seal: bool) -> Result<Vec<u8>, Error> {
+        let nonce = self.next_nonce()?;
+        let nonce = (&nonce).into();
+        let payload = Payload { msg, aad };
+        let result = match (self.aead, seal) {
+            (HpkeAead::AES_128_GCM, true) => aes128(&self.key)?.encrypt(nonce, payload),
+            (HpkeAead::AES_128_GCM, false) => aes128(&self.key)?.decrypt(nonce, payload),
+            (HpkeAead::AES_256_GCM, true) => aes256(&self.key)?.encrypt(nonce, payload),
+            (HpkeAead::AES_256_GCM, false) => aes256(&self.key)?.decrypt(nonce, payload),
+            (HpkeAead::CHACHA20_POLY_1305, true) => chacha(&self.key)?.encrypt(nonce, payload),
+            (HpkeAead::CHACHA20_POLY_1305, false) => chacha(&self.key)?.decrypt(nonce, payload),
+            _ => return Err(Error::General("unsupported HPKE AEAD".into())),
+        };
+        result.map_err(|_| Error::General("HPKE AEAD failure".into()))
+    }
+}
+
+impl HpkeSealer for Context {
+    fn seal(&mut self, aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, Error> {
+        self.crypt(aad, plaintext, true)
+    }
+}
+
+impl HpkeOpener for Context {
+    fn open(&mut self, aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, Error> {
+        self.crypt(aad, ciphertext, false)
+    }
+}
+
+fn aes128(key: &[u8]) -> Result<Aes128Gcm, Error> {
+    Aes128Gcm::new_from_slice(key).map_err(|_| bad_key())
+}
+
+fn aes256(key: &[u8]) -> Result<Aes256Gcm, Error> {
+    Aes256Gcm::new_from_slice(key).map_err(|_| bad_key())
+}
+
+fn chacha(key: &[u8]) -> Result<ChaCha20Poly1305, Error> {
+    ChaCha20Poly1305::new_from_slice(key).map_err(|_| bad_key())
+}
+
+fn bad_key() -> Error {
+    Error::General("invalid HPKE key length".into())
+}
+
+fn key(bytes: &[u8]) -> Result<[u8; 32], Error> {
+    bytes.try_into().map_err(|_| bad_key())
+}
+
+fn random_key() -> Result<Zeroizing<[u8; 32]>, Error> {
+    let mut key = Zeroizing::new([0; 32]);
+    crate::get_provider()
+        .secure_random
+        .fill(&mut *key)
+        .map_err(|_| Error::FailedToGetRandomBytes)?;
+    Ok(key)
+}
+
+/// X25519, refusing the all-zero output of a small-order public key.
+fn dh(secret: &[u8; 32], public: [u8; 32]) -> Result<Zeroizing<[u8; 32]>, Error> {
+    let shared = Zeroizing::new(x25519(*secret, public));
+    if *shared == [0; 32] {
+        return Err(Error::General("HPKE public key of small order".into()));
+    }
+    Ok(shared)
+}
+
+/// DHKEM `ExtractAndExpand` over `kem_context` = enc || pkR, under the KEM's own suite ID.
+fn shared_secret(dh: &[u8], enc: &[u8], pk_r: &[u8]) -> Zeroizing<[u8; 32]> {
+    let mut suite = b"KEM".to_vec();
+    suite.extend_from_slice(&KEM_ID.to_be_bytes());
+    let prk = labeled_extract(&suite, b"", b"eae_prk", dh);
+    let kem_context = [enc, pk_r].concat();
+    let mut secret = Zeroizing::new([0; 32]);
+    labeled_expand(&suite, &*prk, b"shared_secret", &kem_context, &mut *secret);
+    secret
+}
+
+fn labeled_extract(suite_id: &[u8], salt: &[u8], label: &[u8], ikm: &[u8]) -> Zeroizing<[u8; 32]> {
+    let ikm = Zeroizing::new([b"HPKE-v1", suite_id, label, ikm].concat());
+    let (prk, _) = Hkdf::<Sha256>::extract(Some(salt), &ikm);
+    Zeroizing::new(prk.into())
+}
+
+fn labeled_expand(suite_id: &[u8], prk: &[u8], label: &[u8], info: &[u8], out: &mut [u8]) {
+    let length = (out.len() as u16).to_be_bytes();
+    let info = [&length[..], b"HPKE-v1", suite_id, label, info].concat();
+    Hkdf::<Sha256>::from_prk(prk)
+        .expect("PRK is a SHA-256 output")
+        .expand(&info, out)
+        .expect("HPKE outputs are far below the HKDF limit");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unhex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    /// RFC 9180 A.1.1: DHKEM(X25519, HKDF-SHA256), HKDF-SHA256, AES-128-GCM.
+    #[test]
+    fn rfc9180_base_mode_vector() {
+        let hpke = X25519Hpke {
+            aead: HpkeAead::AES_128_GCM,
+        };
+        let info = unhex("4f6465206f6e2061204772656369616e2055726e");
+        let sk_e = Zeroizing::new(
+            key(&unhex(
+                "52c4a758a802cd8b936eceea314432798d5baf2d7e9235dc084ab1b9cfa2f736",
+            ))
+            .unwrap(),
+        );
+        let sk_r = unhex("4612c550263fc8ad58375df3f557aac531d26850903e55a9f23f21d8534e8ac8");
+        let pk_r = x25519(key(&sk_r).unwrap(), X25519_BASEPOINT_BYTES);
+
+        let (enc, mut sealer) = hpke.setup_sealer_with(&info, &pk_r, sk_e).unwrap();
+        assert_eq!(
+            enc.0,
+            unhex("37fda3567bdbd628e88668c3c8d7e97d1d1253b6d4ea6d44c150f741f1bf4431")
+        );
+        assert_eq!(sealer.key, unhex("4531685d41d65f03dc48f6b8302c05b0"));
+        assert_eq!(
+            sealer.base_nonce.to_vec(),
+            unhex("56d890e5accaaf011cff4b7d")
+        );
+
+        let plaintext = unhex("4265617574792069732074727574682c20747275746820626561757479");
+        let ciphertext = sealer.seal(b"Count-0", &plaintext).unwrap();
+        assert_eq!(
+            ciphertext,
+            unhex(
+                "f938558b5d72f1a23810b4be2ab4f84331acc02fc97babc53a52ae8218a355a9\
+                 6d8770ac83d07bea87e13c512a"
+            )
+        );
+
+        let mut opener = hpke.setup_opener_with(&enc.0, &info, &sk_r).unwrap();
+        assert_eq!(opener.open(b"Count-0", &ciphertext).unwrap(), plaintext);
+    }
+
+    #[test]
+    fn every_suite_round_trips() {
+        for hpke in SUITES {
+            let (public, secret) = hpke.generate_key_pair().unwrap();
+            let (enc, mut sealer) = hpke.setup_sealer(b"info", &public).unwrap();
+            let first = sealer.seal(b"aad", b"first").unwrap();
+            let second = sealer.seal(b"aad", b"second").unwrap();
+
+            let mut opener = hpke.setup_opener(&enc, b"info", &secret).unwrap();
+            assert_eq!(opener.open(b"aad", &first).unwrap(), b"first");
+            assert_eq!(opener.open(b"aad", &second).unwrap(), b"second");
+            // Wrong info, or wrong aad: the context or the tag no longer match
+            assert!(hpke.open(&enc, b"other", b"aad", &first, &secret).is_err());
+            assert!(hpke.open(&enc, b"info", b"other", &first, &secret).is_err());
+        }
+    }
+
+    #[test]
+    fn small_order_keys_are_refused() {
+        let hpke = SUITES[0];
+        assert!(hpke.setup_sealer(b"", &HpkePublicKey(vec![0; 32])).is_err());
+        assert!(hpke.setup_sealer(b"", &HpkePublicKey(vec![0; 31])).is_err());
+    }
+}
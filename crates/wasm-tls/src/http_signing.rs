@@ -0,0 +1,227 @@
+//! Private keys for HTTP message signatures (RFC 9421), loaded and used through
+//! the crypto provider. What gets signed is built by `wasm_http::SignatureParams`.
+
+use std::sync::Arc;
+
+use rustls::pki_types::PrivateKeyDer;
+use rustls::sign::SigningKey;
+use rustls::SignatureScheme;
+use wasm_bindgen::prelude::*;
+use wasm_http::http1::RequestBuilder;
+use wasm_http::SignatureParams;
+
+use crate::{der, get_provider};
+
+/// RFC 9421 §3.3 algorithms the provider can sign with, by TLS scheme.
+const ALGORITHMS: [(SignatureScheme, &str); 2] = [
+    (SignatureScheme::ED25519, "ed25519"),
+    (SignatureScheme::ECDSA_NISTP256_SHA256, "ecdsa-p256-sha256"),
+];
+
+/// A key that signs requests: Ed25519 or ECDSA P-256.
+#[wasm_bindgen]
+#[derive(Debug)]
+pub struct HttpSigningKey {
+    key: Arc<dyn SigningKey>,
+    scheme: SignatureScheme,
+    alg: &'static str,
+}
+
+#[wasm_bindgen]
+impl HttpSigningKey {
+    /// A DER private key: Ed25519 in PKCS#8, or P-256 in PKCS#8 or SEC1 form.
+    #[wasm_bindgen(constructor)]
+    pub fn new(key: Vec<u8>) -> Result<HttpSigningKey, JsError> {
+        Self::from_der(key).map_err(|e| JsError::new(&e))
+    }
+
+    /// The key's `alg` name: "ed25519" or "ecdsa-p256-sha256".
+    pub fn alg(&self) -> String {
+        self.alg.to_string()
+    }
+
+    /// Signature over `data` in the form RFC 9421 puts in the Signature field:
+    /// 64 bytes for Ed25519, and `r || s` (not DER) for ECDSA.
+    pub fn sign(&self, data: &[u8]) -> Result<Vec<u8>, JsError> {
+        self.signature(data).map_err(|e| JsError::new(&e))
+    }
+
+    /// Sign `request` to `url` as `params` describes and append its
+    /// Signature-Input and Signature headers (and Content-Digest, when covered
+    /// but missing). Call it after the last covered header has been added.
+    pub fn sign_request(
+        &self,
+        request: &mut RequestBuilder,
+        url: &str,
+        params: &SignatureParams,
+    ) -> Result<(), JsError> {
+        self.check_alg(params).map_err(|e| JsError::new(&e))?;
+        params.sign_request(request, url, |base| {
+            self.signature(base).map_err(|e| JsError::new(&e))
+        })
+    }
+
+    /// For requests sent as a header list (`Http2Connection::open_stream`): the
+    /// Signature-Input and Signature values, in that order, for a request to
+    /// `url` with the fields `names`/`values`.
+    pub fn signature_headers(
+        &self,
+        method: &str,
+        url: &str,
+        names: Vec<String>,
+        values: Vec<String>,
+        params: &SignatureParams,
+    ) -> Result<Vec<String>, JsError> {
+        self.check_alg(params).map_err(|e| JsError::new(&e))?;
+        let base = params.signature_base(method, url, names, values)?;
+        let signature = self.sign(base.as_bytes())?;
+        Ok(vec![
+            params.signature_input_header(),
+            params.signature_header(&signature),
+        ])
+    }
+}
+
+impl HttpSigningKey {
+    /// Rust-side `new`.
+    pub fn from_der(key: Vec<u8>) -> Result<HttpSigningKey, String> {
+        let key = PrivateKeyDer::try_from(key)
+            .map_err(|_| "Signing key is not a DER PKCS#8 or SEC1 private key".to_string())?;
+        let key = get_provider()
+            .key_provider
+            .load_private_key(key)
+            .map_err(|e| format!("Unsupported signing key: {}", e))?;
+        let (scheme, alg) = ALGORITHMS
+            .into_iter()
+            .find(|(scheme, _)| key.choose_scheme(&[*scheme]).is_some())
+            .ok_or("Signing key is neither Ed25519 nor ECDSA P-256")?;
+        Ok(HttpSigningKey { key, scheme, alg })
+    }
+
+    fn signature(&self, data: &[u8]) -> Result<Vec<u8>, String> {
+        let signer = self
+            .key
+            .choose_scheme(&[self.scheme])
+            .ok_or("Signing key has no signer")?;
+        let signature = signer
+            .sign(data)
+            .map_err(|e| format!("Signing failed: {}", e))?;
+        match self.scheme {
+            SignatureScheme::ECDSA_NISTP256_SHA256 => der::ecdsa_raw(&signature, 32)
+                .ok_or_else(|| "Signer returned a malformed ECDSA signature".to_string()),
+            _ => Ok(signature),
+        }
+    }
+
+    /// §3.2: an `alg` parameter must name this key's algorithm.
+    fn check_alg(&self, params: &SignatureParams) -> Result<(), String> {
+        match params.alg() {
+            Some(alg) if alg != self.alg => Err(format!(
+                "Signature alg \"{}\" does not match the {} key",
+                alg, self.alg
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `test-key-ed25519` of RFC 9421 §B.1.4, as PKCS#8 DER.
+    const ED25519_KEY: &str = "302e020100300506032b6570042204209f8362f87a484a954e6e740c5b4\
+                               c0e84229139a20aa8ab56ff66586f6a7d29c5";
+    /// `test-key-ecc-p256` of §B.1.3, as SEC1 DER.
+    const P256_KEY: &str = "30770201010420529b85f3597e90ec5b8dfed3e26352bf4af9b4debb1a29bb\
+                            ae77fa105a5c603ba00a06082a8648ce3d030107a14403420004a885586552c2\
+                            acf6471878cfd7b0935b4ffe0fd2dfc341248ea17bc41e058af031ce2737d2d3\
+                            0ce0617e851e83c61ef5679d151867657649035d90a74cd9e85d";
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn params(alg: Option<&str>) -> SignatureParams {
+        let components = [
+            "date",
+            "@method",
+            "@path",
+            "@authority",
+            "content-type",
+            "content-length",
+        ];
+        let mut params =
+            SignatureParams::new("sig-b26", components.map(String::from).to_vec()).unwrap();
+        params.set_created(1618884473.0);
+        params.set_keyid("test-key-ed25519").unwrap();
+        if let Some(alg) = alg {
+            params.set_alg(alg).unwrap();
+        }
+        params
+    }
+
+    /// §B.2.6: Ed25519 is deterministic, so the signature matches the RFC's.
+    #[test]
+    fn ed25519_request_matches_the_rfc() {
+        let key = HttpSigningKey::from_der(hex(ED25519_KEY)).unwrap();
+        assert_eq!(key.alg, "ed25519");
+        let mut request = RequestBuilder::new("POST", "/foo?param=Value&Pet=dog").unwrap();
+        request.set_host("example.com").unwrap();
+        request
+            .header("Date", "Tue, 20 Apr 2021 02:07:55 GMT")
+            .unwrap();
+        request.header("Content-Type", "application/json").unwrap();
+        request.set_body(b"{\"hello\": \"world\"}".to_vec());
+        let url = "https://example.com/foo?param=Value&Pet=dog";
+        let b26 = params(None);
+        assert!(key.check_alg(&b26).is_ok());
+        b26.sign_request(&mut request, url, |base| {
+            Ok::<_, wasm_http::Error>(key.signature(base).unwrap())
+        })
+        .unwrap();
+        let bytes = String::from_utf8(request.to_bytes().unwrap()).unwrap();
+        assert!(bytes.contains(
+            "\r\nSignature: sig-b26=:wqcAqbmYJ2ji2glfAMaRy4gruYYnx2nEFN2HN6jrnDnQCK1u02Gb04v9\
+             EDgwUPiu4A0w6vuQv5lIp5WPpBKRCw==:\r\n"
+        ));
+        assert!(key.check_alg(&params(Some("ecdsa-p256-sha256"))).is_err());
+    }
+
+    #[test]
+    fn p256_signatures_are_raw_and_verify() {
+        let key = HttpSigningKey::from_der(hex(P256_KEY)).unwrap();
+        assert_eq!(key.alg, "ecdsa-p256-sha256");
+        let signature = key.signature(b"signature base").unwrap();
+        assert_eq!(signature.len(), 64);
+
+        // Back to DER for the provider's verifier
+        let integer = |digits: &[u8]| {
+            let digits = &digits[digits.iter().take_while(|&&b| b == 0).count()..];
+            let mut value = if digits[0] & 0x80 != 0 {
+                vec![0]
+            } else {
+                vec![]
+            };
+            value.extend_from_slice(digits);
+            [vec![0x02, value.len() as u8], value].concat()
+        };
+        let fields = [integer(&signature[..32]), integer(&signature[32..])].concat();
+        let der_signature = [vec![0x30, fields.len() as u8], fields].concat();
+        let public_key = &hex(P256_KEY)[hex(P256_KEY).len() - 65..];
+        let (_, verifiers) = get_provider()
+            .signature_verification_algorithms
+            .mapping
+            .iter()
+            .find(|(scheme, _)| *scheme == SignatureScheme::ECDSA_NISTP256_SHA256)
+            .unwrap();
+        assert!(verifiers.iter().any(|v| v
+            .verify_signature(public_key, b"signature base", &der_signature)
+            .is_ok()));
+
+        assert!(HttpSigningKey::from_der(vec![0x30, 0x00]).is_err());
+    }
+}
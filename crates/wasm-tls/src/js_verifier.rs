@@ -0,0 +1,95 @@
+use std::fmt;
+
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, WebPkiSupportedAlgorithms};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{CertificateError, DigitallySignedStruct, Error, SignatureScheme};
+use wasm_bindgen::JsValue;
+
+use crate::js_error_message;
+
+/// Certificate verifier that hands chain validation to a JS callback (see
+/// `TlsOptions::set_js_verifier`). Handshake signatures are still checked by the
+/// crypto provider, so the callback only decides whether the certificate is trusted.
+pub(crate) struct JsVerifier {
+    callback: SyncFunction,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+/// `js_sys::Function` is neither Send nor Sync, which rustls requires of verifiers.
+struct SyncFunction(js_sys::Function);
+
+// SAFETY: wasm32 without the atomics target feature has a single thread, so the
+// function is only ever touched from the thread that created it.
+unsafe impl Send for SyncFunction {}
+unsafe impl Sync for SyncFunction {}
+
+impl JsVerifier {
+    pub(crate) fn new(callback: js_sys::Function, algorithms: WebPkiSupportedAlgorithms) -> Self {
+        JsVerifier {
+            callback: SyncFunction(callback),
+            algorithms,
+        }
+    }
+}
+
+impl fmt::Debug for JsVerifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JsVerifier").finish_non_exhaustive()
+    }
+}
+
+impl ServerCertVerifier for JsVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let chain = js_sys::Array::new();
+        for cert in std::iter::once(end_entity).chain(intermediates) {
+            chain.push(&js_sys::Uint8Array::from(cert.as_ref()));
+        }
+        let verdict = self
+            .callback
+            .0
+            .call3(
+                &JsValue::NULL,
+                &chain,
+                &JsValue::from_str(&server_name.to_str()),
+                &JsValue::from_f64(now.as_secs() as f64),
+            )
+            .map_err(|e| Error::General(format!("JS verifier threw: {}", js_error_message(&e))))?;
+        if verdict.as_bool() == Some(true) {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
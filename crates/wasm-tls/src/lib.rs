@@ -1,17 +1,58 @@
 use std::io::{Read, Write};
 use std::mem;
-use std::sync::{Arc, OnceLock};
+use std::sync::{Arc, Mutex, OnceLock};
 use wasm_bindgen::prelude::*;
 
-use rustls::client::Resumption;
+use rustls::client::danger::ServerCertVerifier;
+use rustls::client::{
+    ClientSessionMemoryCache, ClientSessionStore, EchMode, EchStatus, Resumption, Tls12Resumption,
+};
 use rustls::pki_types::ServerName;
-use rustls::{ClientConfig, ClientConnection, RootCertStore};
+use rustls::{ClientConfig, ClientConnection, HandshakeKind, ProtocolVersion, RootCertStore};
+use rustls::{SupportedProtocolVersion, DEFAULT_VERSIONS};
+
+mod build_info;
+mod clock;
+mod config;
+mod der;
+mod error;
+mod fetch;
+mod hello;
+mod hpke;
+mod http_signing;
+#[cfg(feature = "dangerous-js-verifier")]
+mod js_verifier;
+mod options;
+mod pool;
+mod profiles;
+mod record;
+mod rpk;
+mod tickets;
+mod tunnel;
+mod verifier;
+
+pub use build_info::build_info;
+use error::Negotiated;
+pub use error::{HandshakeTooLarge, HostnameMismatch, NegotiationFailure, VersionTooLow};
+pub use fetch::{FetchOptions, StealthFetch};
+pub use http_signing::HttpSigningKey;
+pub use options::{TlsOptions, DEFAULT_COMPACTION_THRESHOLD, DEFAULT_MAX_HANDSHAKE_SIZE};
+pub use pool::TlsConnectionPool;
+pub use profiles::profile_info;
+pub use tunnel::TlsTunnel;
+use verifier::ServerVerifier;
+
+// Re-exported so the HTTP layer's wasm-bindgen exports are linked into this module.
+pub use wasm_http;
 
 static ROOT_STORE: OnceLock<Arc<RootCertStore>> = OnceLock::new();
 static PROVIDER: OnceLock<Arc<rustls::crypto::CryptoProvider>> = OnceLock::new();
+static SESSION_CACHE: OnceLock<Arc<ClientSessionMemoryCache>> = OnceLock::new();
 
 const IO_BUF_CAP: usize = 16 * 1024;
 const MAX_TLS_BUF_SIZE: usize = 128 * 1024;
+/// Most plaintext one TLS record may carry (RFC 8446 §5.1)
+const MAX_PLAINTEXT_FRAGMENT: usize = 16384;
 
 fn get_root_store() -> Arc<RootCertStore> {
     ROOT_STORE
@@ -29,143 +70,613 @@ fn get_provider() -> Arc<rustls::crypto::CryptoProvider> {
         .clone()
 }
 
+/// Session tickets are shared by every connection in the module, so a new
+/// connection to the same host can resume (and send early data).
+fn get_session_cache() -> Arc<ClientSessionMemoryCache> {
+    SESSION_CACHE
+        .get_or_init(|| Arc::new(ClientSessionMemoryCache::new(256)))
+        .clone()
+}
+
 /// TLS connection state, exposed to JS via wasm-bindgen.
 /// Uses rustls with buffer-based sync IO — JS layer drives socket IO asynchronously.
 #[wasm_bindgen]
 pub struct TlsConnection {
     conn: ClientConnection,
+    /// Kept to rebuild `conn` in `force_full_handshake`
+    config: Arc<ClientConfig>,
+    /// Server name the connection was created for
+    hostname: String,
+    /// The verifier checking the server certificate, unless a JS verifier does;
+    /// shared with the other connections of a `TlsConnectionPool`
+    webpki_verifier: Option<Arc<ServerVerifier>>,
+    /// What `webpki_verifier` found verifying this connection's certificate
+    trust_path: Option<verifier::TrustPath>,
+    must_staple: Option<bool>,
+    /// Set when the handshake failed because the certificate does not cover `hostname`
+    hostname_mismatch: Option<HostnameMismatch>,
+    /// Set when the handshake failed for want of a common suite, group or version
+    negotiation_failure: Option<NegotiationFailure>,
+    /// Protocol versions the config enables, for `NegotiationFailure::offered`
+    versions: Vec<u16>,
+    /// From `TlsOptions::require_min_version`
+    min_version: Option<u16>,
+    /// Set once the server negotiated a version below `min_version`
+    version_too_low: Option<VersionTooLow>,
+    /// Set when the handshake was aborted for exceeding `max_handshake_size`
+    handshake_too_large: Option<HandshakeTooLarge>,
+    max_handshake_size: usize,
+    /// Ciphertext received while handshaking
+    handshake_bytes: usize,
+    /// From `TlsOptions::set_key_update_after_bytes`; 0 is off
+    key_update_after_bytes: usize,
+    /// Plaintext written since the current traffic key was installed
+    bytes_under_key: usize,
+    /// `request_key_update` was called and the update has not been sent yet
+    key_update_requested: bool,
+    key_updates_sent: u32,
+    /// Alert code reported by `last_sent_alert`
+    last_sent_alert: Option<u8>,
     /// Ciphertext received from the network, pending rustls processing
     incoming_tls: Vec<u8>,
     /// Offset into incoming_tls for already-consumed bytes
     incoming_tls_offset: usize,
+    /// From `TlsOptions::set_compaction_threshold`
+    compaction_threshold: usize,
+    /// Times unprocessed ciphertext was moved to the front of incoming_tls
+    compactions: u32,
+    /// Record boundaries of everything fed so far
+    framing: record::RecordFraming,
     /// Ciphertext produced by rustls, pending network send
     outgoing_tls: Vec<u8>,
     /// Decrypted plaintext, pending upper-layer read
     plaintext_out: Vec<u8>,
+    /// Offset into plaintext_out for bytes already taken by `take_plaintext_chunk`
+    plaintext_offset: usize,
+    /// From `TlsOptions::set_max_chunk_size`
+    max_chunk_size: usize,
+    /// From `set_max_plaintext_fragment`; 0 is `MAX_PLAINTEXT_FRAGMENT`
+    max_plaintext_fragment: usize,
+    /// The last `feed_ciphertext` decrypted application data
+    last_feed_plaintext: bool,
+    /// `IoState` of the last `process_new_packets`: ciphertext rustls had queued
+    /// and plaintext it had decrypted
+    io_tls_bytes_to_write: usize,
+    io_plaintext_bytes_to_read: usize,
+    /// The server sent close_notify
+    peer_closed: bool,
+    /// Peak combined capacity of the internal buffers over the connection's lifetime
+    buffer_high_water: usize,
+    /// ClientHello handshake message as first emitted (empty if it could not be captured)
+    client_hello: Vec<u8>,
+    /// The server's ServerHello message, once it has arrived
+    server_hello: Option<Vec<u8>>,
+    /// Ciphertext received while handshaking, until it holds the ServerHello
+    server_records: Vec<u8>,
+    /// From `TlsOptions::set_fingerprint_target`
+    fingerprint_target: Option<hello::HelloFingerprint>,
+    /// `flush_outgoing_tls` has handed ciphertext to the caller
+    flushed: bool,
+    /// The wall clock rustls reads, shared with the config and ticket store
+    clock: Arc<clock::Clock>,
+    /// Time last given to `set_now`
+    now: Option<f64>,
+    /// When the ClientHello was first flushed
+    handshake_start: Option<f64>,
+    /// When the fed ciphertext completed the handshake
+    handshake_end: Option<f64>,
+    /// Filled by this connection's session store as tickets arrive
+    new_tickets: Arc<Mutex<Vec<tickets::NewTicket>>>,
+    tickets_received: u32,
+    /// From `set_ticket_callback`
+    ticket_callback: Option<js_sys::Function>,
+    /// ECHConfigList the server sent with its rejection of our ECH offer
+    ech_retry_configs: Option<Vec<u8>>,
+    /// `write_plaintext` was given data before the handshake completed
+    wrote_while_handshaking: bool,
+    /// Set when that data went out together with our Finished
+    false_start: bool,
+    /// Set on connections vended by a `TlsConnectionPool`
+    pooled: Option<pool::Pooled>,
+    /// Reading or writing failed; the connection cannot be used any more
+    failed: bool,
+}
+
+/// What connections to one host built from the same options and ALPN list have in
+/// common: the rustls config with its verifier, clock and ticket store.
+pub(crate) struct SharedConfig {
+    config: Arc<ClientConfig>,
+    webpki_verifier: Option<Arc<ServerVerifier>>,
+    versions: Vec<u16>,
+    clock: Arc<clock::Clock>,
+    new_tickets: Arc<Mutex<Vec<tickets::NewTicket>>>,
+}
+
+impl SharedConfig {
+    /// The config `TlsConnection::with_options` would build.
+    pub(crate) fn for_options(
+        hostname: &str,
+        alpn_protocols: &str,
+        options: &TlsOptions,
+    ) -> Result<SharedConfig, JsError> {
+        let verifiers = server_verifier(hostname, options)?;
+        let hostname = ascii_hostname(hostname).map_err(|e| JsError::new(&e))?;
+        SharedConfig::new(
+            &hostname,
+            alpn_protocols,
+            options,
+            verifiers,
+            DEFAULT_VERSIONS,
+        )
+    }
+
+    fn new(
+        hostname: &str,
+        alpn_protocols: &str,
+        options: &TlsOptions,
+        (verifier, webpki_verifier): Verifiers,
+        versions: &[&'static SupportedProtocolVersion],
+    ) -> Result<SharedConfig, JsError> {
+        let versions = match options.ech_config {
+            Some(_) => &[&rustls::version::TLS13],
+            None => versions,
+        };
+        let clock = Arc::new(clock::Clock::new(options.current_time));
+        if let Some(age) = options.ticket_age {
+            if let Some(stored) = tickets::stored_at(hostname) {
+                clock.set(stored * 1000 + age);
+            }
+        }
+        let mut config = client_config(alpn_protocols, options, verifier, versions, clock.clone())?;
+        let store = tickets::TicketObserver::new(get_session_cache(), clock.clone());
+        let new_tickets = store.received();
+        config.resumption = resumption(options, Resumption::store(Arc::new(store)));
+        Ok(SharedConfig {
+            config: Arc::new(config),
+            webpki_verifier,
+            versions: versions.iter().map(|v| u16::from(v.version)).collect(),
+            clock,
+            new_tickets,
+        })
+    }
+}
+
+/// Incoming ciphertext, outgoing ciphertext and plaintext buffers of a connection
+/// that is gone, for the next one to grow into.
+#[derive(Default)]
+pub(crate) struct Buffers([Vec<u8>; 3]);
+
+/// Everything one `pump` call produced.
+#[wasm_bindgen]
+#[derive(Debug, Default)]
+pub struct PumpResult {
+    plaintext: Vec<u8>,
+    outgoing: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl PumpResult {
+    /// Decrypted application data for the upper layer (moved out; empty afterwards).
+    pub fn take_plaintext(&mut self) -> Vec<u8> {
+        mem::take(&mut self.plaintext)
+    }
+
+    /// Ciphertext to send to the network (moved out; empty afterwards).
+    pub fn take_outgoing(&mut self) -> Vec<u8> {
+        mem::take(&mut self.outgoing)
+    }
 }
 
 #[wasm_bindgen]
 impl TlsConnection {
     /// Create a new TLS client connection.
-    /// `hostname`: server hostname for SNI
+    /// `hostname`: server hostname for SNI; an IDN may be given in Unicode
     /// `alpn_protocols`: comma-separated ALPN protocol list, e.g. "h2,http/1.1"
     #[wasm_bindgen(constructor)]
     pub fn new(hostname: &str, alpn_protocols: &str) -> Result<TlsConnection, JsError> {
-        let mut config = ClientConfig::builder_with_provider(get_provider())
-            .with_safe_default_protocol_versions()
-            .map_err(|e| JsError::new(&format!("Protocol version error: {}", e)))?
-            .with_root_certificates((*get_root_store()).clone())
-            .with_no_client_auth();
+        Self::with_options(hostname, alpn_protocols, &TlsOptions::default())
+    }
 
-        config.resumption = Resumption::in_memory_sessions(256);
+    /// Create a new TLS client connection with additional settings (see `TlsOptions`).
+    pub fn with_options(
+        hostname: &str,
+        alpn_protocols: &str,
+        options: &TlsOptions,
+    ) -> Result<TlsConnection, JsError> {
+        let verifiers = server_verifier(hostname, options)?;
+        Self::build(
+            hostname,
+            alpn_protocols,
+            options,
+            verifiers,
+            DEFAULT_VERSIONS,
+        )
+    }
 
-        // Set ALPN protocols
-        if !alpn_protocols.is_empty() {
-            config.alpn_protocols = alpn_protocols
-                .split(',')
-                .map(|p| p.trim().as_bytes().to_vec())
-                .collect();
-        }
+    /// Create a connection that offers Encrypted ClientHello with `ech_config_list`,
+    /// the server's ECHConfigList from its DNS HTTPS record: `hostname` is sent,
+    /// and the certificate checked against it, only inside the encrypted inner
+    /// ClientHello, while the outer one names the config's public name. The same
+    /// as `with_options` after `TlsOptions::set_ech_config_list`.
+    ///
+    /// A server that cannot decrypt the offer completes the handshake for the
+    /// public name instead, after which `feed_ciphertext` fails with "ECH
+    /// rejected", `ech_accepted` is false and `ech_retry_configs` holds the
+    /// configs it sent to retry with, if any.
+    pub fn with_ech(
+        hostname: &str,
+        alpn_protocols: &str,
+        ech_config_list: Vec<u8>,
+        options: &TlsOptions,
+    ) -> Result<TlsConnection, JsError> {
+        let mut options = options.clone();
+        options.set_ech_config_list(ech_config_list)?;
+        Self::with_options(hostname, alpn_protocols, &options)
+    }
 
-        let server_name: ServerName<'static> = ServerName::try_from(hostname.to_string())
-            .map_err(|e| JsError::new(&format!("Invalid hostname: {}", e)))?;
+    /// Create a TLS 1.3 connection that authenticates the server by a raw public key
+    /// (RFC 7250) instead of an X.509 certificate. The ClientHello asks for the
+    /// RawPublicKey server certificate type, and the handshake only succeeds if the
+    /// server's key is one of `allowed_spki` (DER SubjectPublicKeyInfo). No name is
+    /// checked: `hostname` is only sent as SNI.
+    pub fn with_raw_public_keys(
+        hostname: &str,
+        alpn_protocols: &str,
+        allowed_spki: Vec<js_sys::Uint8Array>,
+    ) -> Result<TlsConnection, JsError> {
+        let allowed = allowed_spki
+            .iter()
+            .map(js_sys::Uint8Array::to_vec)
+            .collect();
+        Self::with_raw_public_keys_der(hostname, alpn_protocols, allowed)
+    }
 
-        let conn = ClientConnection::new(Arc::new(config), server_name)
-            .map_err(|e| JsError::new(&format!("TLS connection error: {}", e)))?;
+    /// Create a connection from one JSON config object instead of a constructor
+    /// plus `TlsOptions` setters. Only `hostname` is required:
+    /// - `hostname`: string, sent as SNI and verified
+    /// - `alpn`: array of protocol names, e.g. `["h2", "http/1.1"]`
+    /// - `versions`: array of `"1.2"` / `"1.3"` (default both)
+    /// - `minVersion`: `"1.2"` or `"1.3"`, as `require_min_version`
+    /// - `rawPublicKeys`: array of base64 DER SubjectPublicKeyInfo; authenticates
+    ///   as `with_raw_public_keys` does, over TLS 1.3 only
+    /// - `roots`: array of `{"hostname", "der"}` (base64), as `add_root_for_host`
+    /// - `clientCert`: `{"chain": [base64 DER, ...], "key": base64 DER}`
+    /// - `suppressExtensions`, `suppressKeyShares`: arrays of code points
+    /// - `cipherSuites`, `groups`: arrays of code points to offer first, as
+    ///   `set_cipher_suite_order` and `set_group_order`
+    /// - `fingerprintTarget`: `{"cipherSuites", "groups", "extensions"}`, arrays of
+    ///   code points, as `set_fingerprint_target`
+    /// - `reorderChain`, `enforceMustStaple`, `earlyData`: booleans
+    /// - `maxHandshakeSize`, `compactionThreshold`, `keyUpdateAfterBytes`,
+    ///   `maxChunkSize`, `maxPlaintextFragment`: byte counts
+    /// - `echConfigList`: base64 ECHConfigList, as `set_ech_config_list`; the
+    ///   connection is then TLS 1.3 only
+    ///
+    /// Any other field, a value of the wrong type, or a setting the options reject
+    /// fails with an error naming the field, e.g. `Config field "roots[1].der":
+    /// expected a base64 string`.
+    pub fn from_json(config_json: &str) -> Result<TlsConnection, JsError> {
+        let config = config::parse(config_json).map_err(|e| JsError::new(&e))?;
+        let verifiers = match config.raw_public_keys {
+            Some(allowed) => {
+                let algorithms = get_provider().signature_verification_algorithms;
+                let verifier =
+                    rpk::RawKeyVerifier::new(allowed, algorithms).map_err(|e| JsError::new(&e))?;
+                (Arc::new(verifier) as Arc<dyn ServerCertVerifier>, None)
+            }
+            None => server_verifier(&config.hostname, &config.options)?,
+        };
+        Self::build(
+            &config.hostname,
+            &config.alpn,
+            &config.options,
+            verifiers,
+            &config.versions,
+        )
+    }
 
-        Ok(TlsConnection {
-            conn,
-            incoming_tls: Vec::with_capacity(IO_BUF_CAP),
-            incoming_tls_offset: 0,
-            outgoing_tls: Vec::with_capacity(IO_BUF_CAP),
-            plaintext_out: Vec::with_capacity(IO_BUF_CAP),
-        })
+    /// Drop every cached session for this connection's host and rebuild the
+    /// ClientHello without them, so it carries no pre_shared_key or early_data
+    /// extension and the handshake is a full one. Later connections to the host
+    /// resume again once this handshake has stored a new ticket.
+    /// Only possible before the first `flush_outgoing_tls` (or `pump`).
+    pub fn force_full_handshake(&mut self) -> Result<(), JsError> {
+        if self.flushed {
+            return Err(JsError::new(
+                "force_full_handshake must be called before the ClientHello is flushed",
+            ));
+        }
+        let server_name = ServerName::try_from(self.hostname.clone())
+            .map_err(|e| JsError::new(&format!("Invalid hostname: {}", e)))?;
+        let cache = get_session_cache();
+        cache.remove_tls12_session(&server_name);
+        while cache.take_tls13_ticket(&server_name).is_some() {}
+
+        self.conn = new_client_connection(&self.config, &self.hostname)?;
+        self.outgoing_tls.clear();
+        self.capture_client_hello()
     }
 
     /// Feed ciphertext received from the network into the TLS engine.
     /// Returns true if rustls has outgoing data to send (call `flush_outgoing_tls`).
     pub fn feed_ciphertext(&mut self, data: &[u8]) -> Result<bool, JsError> {
-        if self.incoming_tls.len() + data.len() > MAX_TLS_BUF_SIZE {
-            self.compact_incoming_tls();
-            if self.incoming_tls.len() + data.len() > MAX_TLS_BUF_SIZE {
-                return Err(JsError::new("Incoming TLS buffer exceeded maximum size"));
-            }
-        }
-        self.incoming_tls.extend_from_slice(data);
+        let result = self.feed_records(data);
+        self.failed |= result.is_err();
+        // Also the alert rustls queues when the records are rejected
+        self.drain_outgoing_tls()?;
+        result
+    }
 
-        // Let rustls read TLS records from our buffer (&[u8] implements Read)
-        let mut reader = &self.incoming_tls[self.incoming_tls_offset..];
-        let bytes_read = self
-            .conn
-            .read_tls(&mut reader)
-            .map_err(|e| JsError::new(&format!("read_tls error: {}", e)))?;
+    /// Feed ciphertext, process every complete record in it, and return all decrypted
+    /// plaintext together with all ciphertext that is now ready to send. Equivalent to
+    /// `feed_ciphertext` + `take_plaintext` + `flush_outgoing_tls` in one call.
+    pub fn pump(&mut self, incoming: &[u8]) -> Result<PumpResult, JsError> {
+        self.feed_all(incoming)?;
+        Ok(PumpResult {
+            plaintext: self.take_plaintext(),
+            outgoing: self.flush_outgoing_tls()?,
+        })
+    }
 
-        // Advance offset for processed bytes
-        self.incoming_tls_offset += bytes_read;
+    /// Write plaintext data (from the upper layer) into the TLS engine for encryption.
+    /// Returns true if rustls has outgoing data to send. A key update that is due
+    /// (see `TlsOptions::set_key_update_after_bytes` and `request_key_update`) is
+    /// sent ahead of the data.
+    ///
+    /// Data written during the handshake is queued and encrypted the moment the
+    /// traffic keys exist: the `feed_ciphertext` that completes the handshake then
+    /// returns true, and one flush sends it. On TLS 1.3, and on a resumed TLS 1.2
+    /// session, that is the flush carrying our Finished, so the request costs no
+    /// round trip of its own (see `false_start_used`). rustls has no TLS 1.2 False
+    /// Start (RFC 7918): after a full TLS 1.2 handshake the data waits for the
+    /// server's Finished.
+    ///
+    /// The data goes out in records of at most `max_plaintext_fragment` bytes.
+    pub fn write_plaintext(&mut self, data: &[u8]) -> Result<bool, JsError> {
+        let result = self.write_records(data);
+        self.failed |= result.is_err();
+        self.drain_outgoing_tls()?;
+        result
+    }
 
-        // Compact buffer occasionally to avoid unbounded growth
-        if self.incoming_tls_offset > 0 {
-            if self.incoming_tls_offset >= self.incoming_tls.len() {
-                self.incoming_tls.clear();
-                self.incoming_tls_offset = 0;
-            } else if self.incoming_tls_offset >= IO_BUF_CAP
-                && self.incoming_tls_offset >= self.incoming_tls.len() / 2
-            {
-                self.compact_incoming_tls();
-            }
+    /// Send a TLS 1.3 KeyUpdate before the next `write_plaintext`, e.g. from a timer
+    /// for a time-based rekeying policy. Ignored on TLS 1.2; waits for the handshake
+    /// to finish if called earlier.
+    pub fn request_key_update(&mut self) {
+        self.key_update_requested = true;
+    }
+
+    /// Cap the plaintext of each application-data record `write_plaintext`
+    /// produces at `bytes`, from the next write on: a large upload then goes out
+    /// as a run of records that size, for servers or fingerprints that expect a
+    /// particular record size pattern. 0 restores the default, and anything above
+    /// 16384 (the protocol maximum) counts as 16384. Handshake records and early
+    /// data are left as rustls frames them.
+    pub fn set_max_plaintext_fragment(&mut self, bytes: usize) {
+        self.max_plaintext_fragment = bytes;
+    }
+
+    /// Largest plaintext `write_plaintext` puts in one record: 16384 unless
+    /// lowered with `set_max_plaintext_fragment`.
+    pub fn max_plaintext_fragment(&self) -> usize {
+        match self.max_plaintext_fragment {
+            0 => MAX_PLAINTEXT_FRAGMENT,
+            bytes => bytes.min(MAX_PLAINTEXT_FRAGMENT),
         }
+    }
 
-        // Process the TLS records
+    /// Key updates sent so far by `write_plaintext`.
+    pub fn key_updates_sent(&self) -> u32 {
+        self.key_updates_sent
+    }
 
-        let io_state = self
-            .conn
-            .process_new_packets()
-            .map_err(|e| JsError::new(&format!("TLS error: {}", e)))?;
+    /// Same as `write_early_data_idempotent`.
+    pub fn write_early_data(&mut self, data: &[u8]) -> Result<usize, JsError> {
+        self.write_early_data_idempotent(data)
+    }
 
-        // Extract any decrypted plaintext (write directly into plaintext_out, no temp Vec)
-        let pt_bytes = io_state.plaintext_bytes_to_read();
-        if pt_bytes > 0 {
-            let start = self.plaintext_out.len();
-            self.plaintext_out.resize(start + pt_bytes, 0);
-            let n = self
-                .conn
-                .reader()
-                .read(&mut self.plaintext_out[start..])
-                .map_err(|e| JsError::new(&format!("plaintext read error: {}", e)))?;
-            self.plaintext_out.truncate(start + n);
+    /// Write plaintext as TLS 1.3 early data, before the handshake completes,
+    /// for a request that is safe to replay: an attacker can resend 0-RTT data,
+    /// so the server may see it twice. Fails for an HTTP/1.x request line with a
+    /// method other than GET, HEAD, OPTIONS or TRACE; other data (e.g. HTTP/2
+    /// frames) is taken to be replay-safe as the caller says.
+    ///
+    /// Returns how many bytes were accepted; 0 when early data is not possible
+    /// (not enabled in `TlsOptions`, no resumable session, or the limit is used up).
+    /// Check `early_data_was_accepted` after the handshake: rejected early data is
+    /// not retransmitted by rustls.
+    pub fn write_early_data_idempotent(&mut self, data: &[u8]) -> Result<usize, JsError> {
+        if let Some(method) = replay_unsafe_method(data) {
+            return Err(JsError::new(&format!(
+                "Refusing to send a {} request as early data, which can be replayed; \
+                 use write_early_data_unsafe to send it anyway",
+                method
+            )));
         }
-
-        Ok(self.conn.wants_write())
+        self.write_early_data_unsafe(data)
     }
 
-    /// Write plaintext data (from the upper layer) into the TLS engine for encryption.
-    /// Returns true if rustls has outgoing data to send.
-    pub fn write_plaintext(&mut self, data: &[u8]) -> Result<bool, JsError> {
-        self.conn
-            .writer()
-            .write_all(data)
-            .map_err(|e| JsError::new(&format!("write error: {}", e)))?;
-        Ok(self.conn.wants_write())
+    /// `write_early_data_idempotent` without the replay check, for payloads the
+    /// server is known to handle safely when replayed (e.g. with an idempotency key).
+    pub fn write_early_data_unsafe(&mut self, data: &[u8]) -> Result<usize, JsError> {
+        match self.conn.early_data() {
+            Some(mut early) => early
+                .write(data)
+                .map_err(|e| JsError::new(&format!("early data write error: {}", e))),
+            None => Ok(0),
+        }
     }
 
     /// Flush ciphertext produced by rustls (to be sent over the network).
     /// Returns the ciphertext bytes as a Vec<u8> (becomes Uint8Array in JS).
     pub fn flush_outgoing_tls(&mut self) -> Result<Vec<u8>, JsError> {
-        self.outgoing_tls.clear();
-        self.conn
-            .write_tls(&mut self.outgoing_tls)
-            .map_err(|e| JsError::new(&format!("write_tls error: {}", e)))?;
+        self.drain_outgoing_tls()?;
+        self.mark_flushed();
         Ok(mem::replace(
             &mut self.outgoing_tls,
             Vec::with_capacity(IO_BUF_CAP),
         ))
     }
 
-    /// Take decrypted plaintext data (for the upper layer to consume).
+    /// Like `flush_outgoing_tls`, but returns at most `max` bytes and keeps the rest
+    /// for the next call, so the driver can send in socket-sized pieces and wait for
+    /// backpressure in between. Call it until it returns nothing (or `wants_write`
+    /// is false); splitting points need not fall on record boundaries.
+    pub fn flush_outgoing_tls_chunk(&mut self, max: usize) -> Result<Vec<u8>, JsError> {
+        self.drain_outgoing_tls()?;
+        let n = max.min(self.outgoing_tls.len());
+        if n == self.outgoing_tls.len() {
+            return self.flush_outgoing_tls();
+        }
+        if n > 0 {
+            self.mark_flushed();
+        }
+        Ok(self.outgoing_tls.drain(..n).collect())
+    }
+
+    /// Number of ciphertext bytes `flush_outgoing_tls` would currently return.
+    /// Feeds, writes and `send_close_notify` move the records rustls queues into
+    /// the outgoing buffer as they go, so nothing is left in rustls to count.
+    pub fn outgoing_tls_pending(&self) -> usize {
+        debug_assert!(!self.conn.wants_write());
+        self.outgoing_tls.len()
+    }
+
+    /// Whether the last `feed_ciphertext` decrypted any application data, i.e.
+    /// whether `take_plaintext` is worth calling. Handshake records alone leave
+    /// it false.
+    pub fn last_feed_yielded_plaintext(&self) -> bool {
+        self.last_feed_plaintext
+    }
+
+    /// Ciphertext bytes rustls had queued to send when the last `feed_ciphertext`
+    /// processed its records (`IoState::tls_bytes_to_write`). Unless something
+    /// was written since, the next `flush_outgoing_tls` returns that much, plus
+    /// whatever an earlier chunked flush left over.
+    pub fn last_io_tls_bytes_to_write(&self) -> usize {
+        self.io_tls_bytes_to_write
+    }
+
+    /// Plaintext bytes the last `feed_ciphertext` decrypted
+    /// (`IoState::plaintext_bytes_to_read`); they have been added to what
+    /// `plaintext_pending` counts.
+    pub fn last_io_plaintext_bytes_to_read(&self) -> usize {
+        self.io_plaintext_bytes_to_read
+    }
+
+    /// Take decrypted plaintext data (for the upper layer to consume). Nothing
+    /// pending returns an empty Vec without allocating.
     pub fn take_plaintext(&mut self) -> Vec<u8> {
+        if self.plaintext_pending() == 0 {
+            return Vec::new();
+        }
+        self.plaintext_out
+            .drain(..mem::take(&mut self.plaintext_offset));
         mem::replace(&mut self.plaintext_out, Vec::with_capacity(IO_BUF_CAP))
     }
 
+    /// Like `take_plaintext`, but returns at most `TlsOptions::set_max_chunk_size`
+    /// bytes and keeps the rest for the next call. Call it until it returns nothing.
+    pub fn take_plaintext_chunk(&mut self) -> Vec<u8> {
+        let available = self.plaintext_pending();
+        let n = match self.max_chunk_size {
+            0 => available,
+            max => max.min(available),
+        };
+        if n == available {
+            return self.take_plaintext();
+        }
+        let start = self.plaintext_offset;
+        self.plaintext_offset += n;
+        self.plaintext_out[start..start + n].to_vec()
+    }
+
+    /// Copy up to `out.len()` bytes of pending plaintext into `out` and return how
+    /// many were copied; the rest stays pending. Unlike `take_plaintext`, nothing
+    /// is allocated.
+    pub fn read_plaintext_into(&mut self, out: &mut [u8]) -> usize {
+        let pending = &self.plaintext_out[self.plaintext_offset..];
+        let n = pending.len().min(out.len());
+        out[..n].copy_from_slice(&pending[..n]);
+        self.plaintext_offset += n;
+        if self.plaintext_offset == self.plaintext_out.len() {
+            self.plaintext_out.clear();
+            self.plaintext_offset = 0;
+        }
+        n
+    }
+
+    /// `read_plaintext_into` for `len` bytes of this module's memory at `ptr`, e.g.
+    /// part of a buffer from `alloc_buffer` that JS reads through a view on
+    /// `memory.buffer`. Nothing is marshalled: decrypted bytes are copied straight
+    /// into the region.
+    ///
+    /// # Safety
+    /// `ptr..ptr + len` must lie inside one live allocation (such as one made by
+    /// `alloc_buffer`) that nothing else uses during the call.
+    pub unsafe fn read_plaintext_into_ptr(&mut self, ptr: *mut u8, len: usize) -> usize {
+        if ptr.is_null() || len == 0 {
+            return 0;
+        }
+        // SAFETY: the caller guarantees the region is valid and unaliased
+        let out = unsafe { std::slice::from_raw_parts_mut(ptr, len) };
+        self.read_plaintext_into(out)
+    }
+
+    /// Number of plaintext bytes `take_plaintext` would currently return.
+    pub fn plaintext_pending(&self) -> usize {
+        self.plaintext_out.len() - self.plaintext_offset
+    }
+
+    /// Run the whole handshake synchronously through two JS callbacks:
+    /// `write_cb(bytes: Uint8Array)` sends ciphertext, and `read_cb()` returns the next
+    /// received ciphertext as a Uint8Array (empty, null or undefined means the peer closed).
+    ///
+    /// Both callbacks are invoked while this connection is borrowed by Rust, so they must
+    /// not call back into it (wasm-bindgen throws on the re-entrant borrow). Since
+    /// `read_cb` has to block until data arrives, this only suits transports that can
+    /// read synchronously; async sockets should keep driving `feed_ciphertext` themselves.
+    pub fn drive_handshake(
+        &mut self,
+        write_cb: &js_sys::Function,
+        read_cb: &js_sys::Function,
+    ) -> Result<(), JsError> {
+        loop {
+            let out = self.flush_outgoing_tls()?;
+            if !out.is_empty() {
+                write_cb
+                    .call1(&JsValue::NULL, &js_sys::Uint8Array::from(&out[..]))
+                    .map_err(|e| callback_error("write", e))?;
+            }
+            if !self.is_handshaking() {
+                return Ok(());
+            }
+            let data = read_cb
+                .call0(&JsValue::NULL)
+                .map_err(|e| callback_error("read", e))?;
+            if data.is_null() || data.is_undefined() {
+                return Err(JsError::new("Connection closed during handshake"));
+            }
+            let data = js_sys::Uint8Array::new(&data).to_vec();
+            if data.is_empty() {
+                return Err(JsError::new("Connection closed during handshake"));
+            }
+            self.feed_ciphertext(&data)?;
+        }
+    }
+
+    /// Whether the server sent close_notify. Stays true while decrypted data is still
+    /// waiting in `take_plaintext`; see `is_fully_drained`.
+    pub fn peer_has_closed(&self) -> bool {
+        self.peer_closed
+    }
+
+    /// Whether the server closed the connection and every byte it sent has been taken
+    /// with `take_plaintext` (or `pump`). Only then is the response complete.
+    pub fn is_fully_drained(&self) -> bool {
+        self.peer_closed && self.plaintext_pending() == 0
+    }
+
     /// Whether the TLS handshake is still in progress.
     pub fn is_handshaking(&self) -> bool {
         self.conn.is_handshaking()
@@ -179,35 +690,1640 @@ impl TlsConnection {
             .map(|p| String::from_utf8_lossy(p).to_string())
     }
 
+    /// Whether the server picked something other than our first ALPN choice, e.g.
+    /// http/1.1 when h2 was offered first, or no protocol at all. False while
+    /// handshaking and when no ALPN was offered. Servers that coerce http/1.1 show up
+    /// here; `negotiated_alpn` says which protocol stack to use.
+    pub fn alpn_downgraded(&self) -> bool {
+        match self.config.alpn_protocols.first() {
+            Some(preferred) if !self.conn.is_handshaking() => {
+                self.conn.alpn_protocol() != Some(preferred.as_slice())
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether the handshake resumed a cached session instead of a full handshake.
+    /// False until the handshake has completed.
+    pub fn was_resumed(&self) -> bool {
+        self.conn.handshake_kind() == Some(HandshakeKind::Resumed)
+    }
+
+    /// Whether data given to `write_plaintext` during the handshake was sent in
+    /// our final handshake flight, with our Finished, instead of a round trip
+    /// later: the latency False Start buys, though the server's Finished had
+    /// already been checked. Always false after a full TLS 1.2 handshake, which
+    /// rustls completes without False Start. False until the handshake has completed.
+    pub fn false_start_used(&self) -> bool {
+        self.false_start
+    }
+
+    /// What was negotiated: `{ hostname, tlsVersion, cipherSuite, alpn, resumed,
+    /// echAccepted }`, with null version, suite and ALPN while handshaking.
+    pub fn connection_info(&self) -> Result<JsValue, JsError> {
+        self.info_object()
+            .map_err(|e| JsError::new(&js_error_message(&e)))
+    }
+
+    /// The id a `TlsConnectionPool` gave this connection, to find its socket again
+    /// when `acquire` hands it back after a `release`. None unless it came from a pool.
+    pub fn pool_id(&self) -> Option<u32> {
+        self.pooled.as_ref().map(|pooled| pooled.id)
+    }
+
+    /// Whether the ClientHello offered ECH, hiding the server name in an encrypted
+    /// inner ClientHello behind the config's public name: true for connections
+    /// made `with_ech` (or with `TlsOptions::set_ech_config_list`). GREASE ECH
+    /// does not count.
+    pub fn ech_offered(&self) -> bool {
+        matches!(
+            self.conn.ech_status(),
+            EchStatus::Offered | EchStatus::Accepted | EchStatus::Rejected
+        )
+    }
+
+    /// Whether the server accepted the ECH offer, so the real server name never
+    /// left the encrypted inner ClientHello. A rejected offer silently completes
+    /// the handshake for the public name instead; check this before relying on
+    /// the name staying private. False while handshaking.
+    pub fn ech_accepted(&self) -> bool {
+        self.conn.ech_status() == EchStatus::Accepted
+    }
+
+    /// The ECHConfigList the server sent when it rejected the ECH offer, to build
+    /// a new connection `with_ech` and try again. Only trust it as far as the
+    /// handshake that delivered it: rustls reports the rejection once the server
+    /// has been authenticated for the public name. None until then, or if the
+    /// server sent no retry configs (ECH is then not available for the host).
+    pub fn ech_retry_configs(&self) -> Option<Vec<u8>> {
+        self.ech_retry_configs.clone()
+    }
+
+    /// Current time in milliseconds on the caller's clock (`performance.now()`,
+    /// `Date.now()`), stamped on the handshake phases the calls that follow reach.
+    /// The module reads no clock itself; without this `timings` stays null.
+    pub fn set_now(&mut self, now_ms: f64) {
+        self.now = Some(now_ms);
+    }
+
+    /// The handshake's `tlsStart` (first flush of the ClientHello) and `tlsEnd`
+    /// (the fed ciphertext that completed it), for `set_tls_timings` on the HTTP
+    /// connection. A resumed handshake still takes a round trip and is timed the
+    /// same way; `was_resumed` tells the two apart.
+    pub fn timings(&self) -> wasm_http::Timings {
+        wasm_http::Timings::handshake(self.handshake_start, self.handshake_end)
+    }
+
+    /// Call `callback(ticket)` whenever the server sends a TLS 1.3 session ticket
+    /// and it enters the module's session cache, so it can be noted without
+    /// polling. `ticket` is `{ serverName, cipherSuite, maxEarlyDataSize }`:
+    /// rustls keeps the ticket and its secret private, so there are no bytes to
+    /// persist. The callback runs inside `feed_ciphertext` (or `pump`) and must
+    /// not call back into this connection; what it throws is rethrown from there,
+    /// after the records were processed.
+    pub fn set_ticket_callback(&mut self, callback: js_sys::Function) {
+        self.ticket_callback = Some(callback);
+    }
+
+    /// Current time in Unix milliseconds, for rustls to read from now on instead
+    /// of the clock `TlsOptions::set_current_time` or `set_ticket_age` started
+    /// it at (or the system clock): the time received tickets are stamped with
+    /// and certificates are validated at.
+    pub fn set_current_time(&mut self, unix_ms: f64) {
+        self.clock.set(clock::millis(unix_ms));
+    }
+
+    /// TLS 1.3 session tickets stored from this connection so far.
+    pub fn tickets_received(&self) -> u32 {
+        self.tickets_received
+    }
+
+    /// Whether the server accepted the early data we sent. A resumed handshake can
+    /// still reject 0-RTT, in which case the request has to be sent again with
+    /// `write_plaintext`. False until the handshake has completed.
+    pub fn early_data_was_accepted(&self) -> bool {
+        self.conn.is_early_data_accepted()
+    }
+
+    /// Ciphertext bytes still missing from the TLS record currently being received,
+    /// e.g. to size the next socket read. When only part of the 5-byte record header
+    /// has arrived this is what completes the header, since the record length is not
+    /// known yet. 0 at a record boundary.
+    pub fn bytes_needed_for_next_record(&self) -> usize {
+        self.framing.bytes_needed()
+    }
+
+    /// Times the unprocessed part of the incoming ciphertext buffer was moved to its
+    /// front, to tune `TlsOptions::set_compaction_threshold` for a workload.
+    pub fn compactions(&self) -> u32 {
+        self.compactions
+    }
+
     /// Whether rustls needs more data from the network.
     pub fn wants_read(&self) -> bool {
         self.conn.wants_read()
     }
 
-    /// Whether rustls has data to write to the network.
+    /// Whether there is ciphertext to write to the network, queued in rustls or
+    /// left over from `flush_outgoing_tls_chunk`.
     pub fn wants_write(&self) -> bool {
-        self.conn.wants_write()
+        self.conn.wants_write() || !self.outgoing_tls.is_empty()
+    }
+
+    /// Total capacity (bytes) currently reserved by the internal buffers
+    /// (incoming ciphertext, outgoing ciphertext, decrypted plaintext).
+    pub fn buffer_memory_usage(&self) -> u32 {
+        self.buffer_capacity() as u32
+    }
+
+    /// Highest value `buffer_memory_usage` has reached since the connection was created.
+    /// Useful for sizing buffer caps and spotting buffers that never compact.
+    pub fn buffer_memory_high_water(&self) -> u32 {
+        self.buffer_high_water as u32
+    }
+
+    /// Extension type codes of the ClientHello we sent, in the order emitted.
+    /// Empty if no ClientHello was captured.
+    pub fn client_hello_extensions(&self) -> Vec<u16> {
+        hello::client_hello_extensions(&self.client_hello).unwrap_or_default()
+    }
+
+    /// The server's ServerHello handshake message (header included, record
+    /// framing removed), for fingerprint analysis. After a HelloRetryRequest it
+    /// is the ServerHello that followed. Null until it has been received.
+    pub fn last_server_hello(&self) -> Option<Vec<u8>> {
+        self.server_hello.clone()
+    }
+
+    /// JA3S hash of the ServerHello: the MD5 of its version, chosen cipher suite
+    /// and extension types in wire order, e.g. "771,4865,43-51". Compare with the
+    /// JA3 of `profile_info` to correlate client and server. Null until the
+    /// ServerHello has been received.
+    pub fn ja3s(&self) -> Option<String> {
+        let ja3s = hello::ja3s(self.server_hello.as_ref()?)?;
+        Some(wasm_http::md5::hex_digest(ja3s.as_bytes()))
+    }
+
+    /// How the emitted ClientHello diverges from the target set with
+    /// `TlsOptions::set_fingerprint_target`, one sentence per difference, e.g.
+    /// "extension 0x001c not emitted" or "cipher suite 0x1302 not emitted: the
+    /// crypto provider lacks it". Empty if it matches; nothing without a target.
+    /// Suite and group order is compared; extension order is not, since rustls
+    /// randomizes it per hello as Chrome does.
+    pub fn profile_fidelity(&self) -> Option<Vec<String>> {
+        let target = self.fingerprint_target.as_ref()?;
+        let actual = hello::client_hello_fingerprint(&self.client_hello).unwrap_or_default();
+        Some(hello::fidelity_report(
+            target,
+            &actual,
+            &supported_cipher_suites(),
+            &supported_kx_groups(),
+        ))
+    }
+
+    /// Why the certificate was rejected for this hostname, if the handshake failed
+    /// that way. The error thrown by `feed_ciphertext` then starts with "Hostname mismatch".
+    pub fn hostname_mismatch(&self) -> Option<HostnameMismatch> {
+        self.hostname_mismatch.clone()
+    }
+
+    /// Why the handshake failed, if this connection and the server had no cipher
+    /// suite, key exchange group or protocol version in common.
+    pub fn negotiation_failure(&self) -> Option<NegotiationFailure> {
+        self.negotiation_failure.clone()
+    }
+
+    /// Number of certificates in the validated path, from the server's certificate up
+    /// to and including the trust anchor. Null while handshaking, after a resumed
+    /// handshake (the certificate is not verified again), or with a JS verifier.
+    pub fn chain_depth(&self) -> Option<usize> {
+        self.trust_path().map(|path| path.depth)
+    }
+
+    /// Where the trust anchor that validated the server's certificate came from:
+    /// "webpki" for the bundled Mozilla roots, "custom" for a root added with
+    /// `TlsOptions::add_root_for_host`. Null in the same cases as `chain_depth`.
+    pub fn trust_anchor_source(&self) -> Option<String> {
+        self.trust_path()
+            .map(|path| if path.custom { "custom" } else { "webpki" }.to_string())
+    }
+
+    /// Whether the server's certificate carries the OCSP must-staple extension.
+    /// Set once the certificate has been seen, even if it was then rejected (with
+    /// `TlsOptions::set_enforce_must_staple`, the error names MustStapleViolation).
+    /// Null before that, after a resumed handshake, or with a JS verifier.
+    pub fn must_staple_required(&self) -> Option<bool> {
+        self.must_staple
+    }
+
+    /// AlertDescription code of the last alert we queued for the server: the fatal
+    /// alert sent when our verifier rejected its certificate (e.g. 42 bad_certificate,
+    /// 46 certificate_unknown, 48 unknown_ca), or 0 after `send_close_notify`.
+    /// Alerts for other handshake failures are not tracked.
+    pub fn last_sent_alert(&self) -> Option<u8> {
+        self.last_sent_alert
+    }
+
+    /// Set when `feed_ciphertext` failed because the server's handshake exceeded
+    /// `TlsOptions::set_max_handshake_size`; the error then starts with "Handshake too large".
+    pub fn handshake_too_large(&self) -> Option<HandshakeTooLarge> {
+        self.handshake_too_large
+    }
+
+    /// Set when `feed_ciphertext` failed because the server negotiated a version
+    /// below `TlsOptions::require_min_version`; the error then starts with
+    /// "TLS version too low". Every later feed and write fails the same way.
+    pub fn version_too_low(&self) -> Option<VersionTooLow> {
+        self.version_too_low
     }
 
     /// Send a TLS close_notify alert.
     pub fn send_close_notify(&mut self) {
         self.conn.send_close_notify();
+        // write_tls into a Vec does not fail
+        let _ = self.drain_outgoing_tls();
+        // Nothing is sent after a fatal alert
+        if self.last_sent_alert.is_none() {
+            self.last_sent_alert = Some(0);
+        }
+    }
+}
+
+/// The verifier handed to rustls, and the WebPKI verifier itself when that is the one
+/// used (it reports the validated path).
+type Verifiers = (Arc<dyn ServerCertVerifier>, Option<Arc<ServerVerifier>>);
+
+/// Certificate verifier for a connection to `hostname`.
+fn server_verifier(hostname: &str, options: &TlsOptions) -> Result<Verifiers, JsError> {
+    #[cfg(feature = "dangerous-js-verifier")]
+    if let Some(callback) = &options.js_verifier {
+        let verifier = js_verifier::JsVerifier::new(
+            callback.clone(),
+            get_provider().signature_verification_algorithms,
+        );
+        return Ok((Arc::new(verifier), None));
+    }
+    let hostname = ascii_hostname(hostname).map_err(|e| JsError::new(&e))?;
+    let roots = options.roots_for(&hostname);
+    let verifier = ServerVerifier::new(
+        roots,
+        get_provider(),
+        options.reorder_chain,
+        options.enforce_must_staple,
+    )
+    .map_err(|e| JsError::new(&format!("Verifier error: {}", e)))?;
+    let verifier = Arc::new(verifier);
+    Ok((verifier.clone(), Some(verifier)))
+}
+
+/// Client config for `options`, with the default (empty, private) resumption store.
+fn client_config(
+    alpn_protocols: &str,
+    options: &TlsOptions,
+    verifier: Arc<dyn ServerCertVerifier>,
+    versions: &[&'static SupportedProtocolVersion],
+    clock: Arc<clock::Clock>,
+) -> Result<ClientConfig, JsError> {
+    let provider = if options.suppressed_groups.is_empty()
+        && options.cipher_suite_order.is_empty()
+        && options.group_order.is_empty()
+    {
+        get_provider()
+    } else {
+        let mut provider = (*get_provider()).clone();
+        // rustls offers both in the provider's order; the sorts are stable
+        let rank =
+            |order: &[u16], code: u16| order.iter().position(|&c| c == code).unwrap_or(order.len());
+        provider
+            .cipher_suites
+            .sort_by_key(|s| rank(&options.cipher_suite_order, u16::from(s.suite())));
+        provider
+            .kx_groups
+            .sort_by_key(|g| rank(&options.group_order, u16::from(g.name())));
+        provider
+            .kx_groups
+            .retain(|g| !options.suppressed_groups.contains(&u16::from(g.name())));
+        Arc::new(provider)
+    };
+    let builder = ClientConfig::builder_with_details(provider, clock);
+    let config = match &options.ech_config {
+        Some(ech) => builder
+            .with_ech(EchMode::Enable(ech.clone()))
+            .map_err(|e| JsError::new(&format!("ECH error: {}", e)))?,
+        None => builder
+            .with_protocol_versions(versions)
+            .map_err(|e| JsError::new(&format!("Protocol version error: {}", e)))?,
+    };
+    let config = config
+        .dangerous()
+        .with_custom_certificate_verifier(verifier);
+    let mut config = match &options.client_cert {
+        Some(cert) => config.with_client_cert_resolver(Arc::new(
+            rustls::sign::SingleCertAndKey::from(cert.clone()),
+        )),
+        None => config.with_no_client_auth(),
+    };
+
+    config.enable_early_data = options.early_data && !options.suppresses(42);
+    config.enable_sni = !options.suppresses(0);
+    config.resumption = resumption(options, mem::take(&mut config.resumption));
+
+    // Set ALPN protocols
+    if !alpn_protocols.is_empty() && !options.suppresses(16) {
+        config.alpn_protocols = alpn_protocols
+            .split(',')
+            .map(|p| p.trim().as_bytes().to_vec())
+            .collect();
+    }
+    Ok(config)
+}
+
+/// `base` with the resumption extensions `TlsOptions::suppress_extension` dropped.
+fn resumption(options: &TlsOptions, base: Resumption) -> Resumption {
+    if options.suppresses(41) {
+        Resumption::disabled()
+    } else if options.suppresses(35) {
+        base.tls12_resumption(Tls12Resumption::SessionIdOnly)
+    } else {
+        base
+    }
+}
+
+/// `hostname` the way SNI and certificates carry it: lowercase, with IDN labels
+/// Punycode-encoded as URLs have them (`wasm_http::domain_to_ascii`). IP
+/// addresses are left as they are.
+pub(crate) fn ascii_hostname(hostname: &str) -> Result<String, String> {
+    if hostname.parse::<std::net::IpAddr>().is_ok() {
+        return Ok(hostname.to_string());
     }
+    wasm_http::domain_to_ascii(hostname).ok_or_else(|| format!("Invalid hostname: {}", hostname))
 }
 
-/// Get the library version string (for verification).
+fn new_client_connection(
+    config: &Arc<ClientConfig>,
+    hostname: &str,
+) -> Result<ClientConnection, JsError> {
+    let server_name: ServerName<'static> = ServerName::try_from(hostname.to_string())
+        .map_err(|e| JsError::new(&format!("Invalid hostname: {}", e)))?;
+    ClientConnection::new(config.clone(), server_name)
+        .map_err(|e| JsError::new(&format!("TLS connection error: {}", e)))
+}
+
+fn callback_error(which: &str, e: JsValue) -> JsError {
+    JsError::new(&format!(
+        "Handshake {} callback failed: {}",
+        which,
+        js_error_message(&e)
+    ))
+}
+
+/// Methods that are safe (RFC 9110 §9.2.1), and so never harmed by a replayed 0-RTT request.
+const REPLAY_SAFE_METHODS: [&str; 4] = ["GET", "HEAD", "OPTIONS", "TRACE"];
+
+/// Method of an HTTP/1.x request line at the start of `data` that is not safe
+/// to replay. The HTTP/2 connection preface (`PRI * HTTP/2.0`) is not a request.
+fn replay_unsafe_method(data: &[u8]) -> Option<&str> {
+    let end = data.iter().position(|&b| b == b' ')?;
+    let method = std::str::from_utf8(&data[..end]).ok()?;
+    let is_token = !method.is_empty()
+        && method
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b));
+    if !is_token || REPLAY_SAFE_METHODS.contains(&method) {
+        return None;
+    }
+    if method == "PRI" && data.starts_with(b"PRI * HTTP/2.0") {
+        return None;
+    }
+    Some(method)
+}
+
+/// Message of a value thrown by a JS callback.
+pub(crate) fn js_error_message(e: &JsValue) -> String {
+    e.as_string()
+        .or_else(|| e.dyn_ref::<js_sys::Error>().map(|e| e.message().into()))
+        .unwrap_or_else(|| "non-Error value thrown".to_string())
+}
+
+/// Allocate `len` zeroed bytes of module memory for `read_plaintext_into_ptr` and
+/// return their address; release them with `free_buffer`.
+#[wasm_bindgen]
+pub fn alloc_buffer(len: usize) -> *mut u8 {
+    Box::into_raw(vec![0u8; len].into_boxed_slice()).cast()
+}
+
+/// Release a buffer from `alloc_buffer`.
+///
+/// # Safety
+/// `ptr` and `len` must be exactly what `alloc_buffer` returned and was given,
+/// and the buffer must not be used afterwards.
+#[wasm_bindgen]
+pub unsafe fn free_buffer(ptr: *mut u8, len: usize) {
+    // SAFETY: the caller passes back an allocation from `alloc_buffer`
+    drop(unsafe { Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, len)) });
+}
+
+/// Get the library version string (for verification). `build_info` has the details.
 #[wasm_bindgen]
 pub fn wasm_tls_version() -> String {
     "wasm-tls v0.1.0 (rustls + rustls-rustcrypto)".to_string()
 }
 
+/// IANA code points of the cipher suites the crypto provider implements, TLS 1.3
+/// and TLS 1.2 alike, in its order of preference.
+#[wasm_bindgen]
+pub fn supported_cipher_suites() -> Vec<u16> {
+    get_provider()
+        .cipher_suites
+        .iter()
+        .map(|suite| u16::from(suite.suite()))
+        .collect()
+}
+
+/// IANA code points of the key exchange groups the crypto provider implements,
+/// in its order of preference.
+#[wasm_bindgen]
+pub fn supported_kx_groups() -> Vec<u16> {
+    get_provider()
+        .kx_groups
+        .iter()
+        .map(|group| u16::from(group.name()))
+        .collect()
+}
+
+/// IANA code points of the signature schemes server certificates and handshake
+/// signatures are verified with.
+#[wasm_bindgen]
+pub fn supported_signature_schemes() -> Vec<u16> {
+    get_provider()
+        .signature_verification_algorithms
+        .supported_schemes()
+        .into_iter()
+        .map(u16::from)
+        .collect()
+}
+
+/// Check a PEM bundle of root certificates before configuring it, e.g. to give a
+/// config UI immediate feedback: every section must be a certificate usable as a
+/// trust anchor. Returns how many there are; errors name the first bad entry
+/// ("PEM entry 3: ..."), counting sections from 1. No connection is opened.
+#[wasm_bindgen]
+pub fn validate_roots_pem(pem: &str) -> Result<usize, JsError> {
+    options::roots_from_pem(pem)
+        .map(|roots| roots.len())
+        .map_err(|e| JsError::new(&e))
+}
+
+/// The ClientHello records `TlsConnection::with_options` would send first, built
+/// without a connection, e.g. to check a profile's JA3/JA4 offline. The session
+/// cache is left alone, so the hello is that of a first visit: it never offers a
+/// cached session. The random, session ID and key shares differ per call.
+#[wasm_bindgen]
+pub fn preview_client_hello(
+    hostname: &str,
+    alpn_protocols: &str,
+    options: &TlsOptions,
+) -> Result<Vec<u8>, JsError> {
+    let hostname = ascii_hostname(hostname).map_err(|e| JsError::new(&e))?;
+    let (verifier, _) = server_verifier(&hostname, options)?;
+    let clock = Arc::new(clock::Clock::new(options.current_time));
+    let config = client_config(alpn_protocols, options, verifier, DEFAULT_VERSIONS, clock)?;
+    let mut conn = new_client_connection(&Arc::new(config), &hostname)?;
+    let mut records = Vec::new();
+    while conn.wants_write() {
+        conn.write_tls(&mut records)
+            .map_err(|e| JsError::new(&format!("write_tls error: {}", e)))?;
+    }
+    Ok(records)
+}
+
 impl TlsConnection {
+    /// Rust-side `with_raw_public_keys`, taking the keys as DER byte vectors.
+    pub fn with_raw_public_keys_der(
+        hostname: &str,
+        alpn_protocols: &str,
+        allowed_spki: Vec<Vec<u8>>,
+    ) -> Result<TlsConnection, JsError> {
+        let algorithms = get_provider().signature_verification_algorithms;
+        let verifier =
+            rpk::RawKeyVerifier::new(allowed_spki, algorithms).map_err(|e| JsError::new(&e))?;
+        Self::build(
+            hostname,
+            alpn_protocols,
+            &TlsOptions::default(),
+            (Arc::new(verifier), None),
+            &[&rustls::version::TLS13],
+        )
+    }
+
+    fn info_object(&self) -> Result<JsValue, JsValue> {
+        let object = js_sys::Object::new();
+        let set = |key: &str, value: JsValue| {
+            js_sys::Reflect::set(&object, &JsValue::from_str(key), &value).map(drop)
+        };
+        let version = self.conn.protocol_version().map(u16::from);
+        let suite = self
+            .conn
+            .negotiated_cipher_suite()
+            .map(|suite| u16::from(suite.suite()));
+        set("hostname", self.hostname.as_str().into())?;
+        set("tlsVersion", version.map_or(JsValue::NULL, JsValue::from))?;
+        set("cipherSuite", suite.map_or(JsValue::NULL, JsValue::from))?;
+        set(
+            "alpn",
+            self.negotiated_alpn().map_or(JsValue::NULL, JsValue::from),
+        )?;
+        set("resumed", self.was_resumed().into())?;
+        set("echAccepted", self.ech_accepted().into())?;
+        Ok(object.into())
+    }
+
+    /// Feed ciphertext and process every complete record in it, as `pump` does.
+    pub(crate) fn feed_all(&mut self, incoming: &[u8]) -> Result<(), JsError> {
+        self.feed_ciphertext(incoming)?;
+        // read_tls may stop short of the end of the buffer; keep going while it progresses
+        loop {
+            let remaining = self.incoming_tls.len() - self.incoming_tls_offset;
+            if remaining == 0 {
+                return Ok(());
+            }
+            self.feed_ciphertext(&[])?;
+            if self.incoming_tls.len() - self.incoming_tls_offset == remaining {
+                return Ok(());
+            }
+        }
+    }
+
+    fn build(
+        hostname: &str,
+        alpn_protocols: &str,
+        options: &TlsOptions,
+        verifiers: Verifiers,
+        versions: &[&'static SupportedProtocolVersion],
+    ) -> Result<TlsConnection, JsError> {
+        let hostname = &ascii_hostname(hostname).map_err(|e| JsError::new(&e))?;
+        let shared = SharedConfig::new(hostname, alpn_protocols, options, verifiers, versions)?;
+        Self::from_shared(hostname, options, &shared, Buffers::default())
+    }
+
+    /// A connection to `hostname` (already in ASCII) on a config built by
+    /// `SharedConfig::new` with the same `options`, in recycled `buffers`.
+    pub(crate) fn from_shared(
+        hostname: &str,
+        options: &TlsOptions,
+        shared: &SharedConfig,
+        buffers: Buffers,
+    ) -> Result<TlsConnection, JsError> {
+        let conn = new_client_connection(&shared.config, hostname)?;
+        let [incoming_tls, outgoing_tls, plaintext_out] = buffers.0.map(|mut buffer| {
+            buffer.clear();
+            buffer.reserve(IO_BUF_CAP);
+            buffer
+        });
+
+        let mut tls = TlsConnection {
+            conn,
+            config: shared.config.clone(),
+            hostname: hostname.to_string(),
+            webpki_verifier: shared.webpki_verifier.clone(),
+            trust_path: None,
+            must_staple: None,
+            hostname_mismatch: None,
+            negotiation_failure: None,
+            versions: shared.versions.clone(),
+            min_version: options.min_version,
+            version_too_low: None,
+            handshake_too_large: None,
+            max_handshake_size: options.max_handshake_size,
+            handshake_bytes: 0,
+            key_update_after_bytes: options.key_update_after_bytes,
+            bytes_under_key: 0,
+            key_update_requested: false,
+            key_updates_sent: 0,
+            last_sent_alert: None,
+            incoming_tls,
+            incoming_tls_offset: 0,
+            compaction_threshold: options.compaction_threshold,
+            compactions: 0,
+            framing: record::RecordFraming::default(),
+            outgoing_tls,
+            plaintext_out,
+            plaintext_offset: 0,
+            max_chunk_size: options.max_chunk_size,
+            max_plaintext_fragment: options.max_plaintext_fragment,
+            last_feed_plaintext: false,
+            io_tls_bytes_to_write: 0,
+            io_plaintext_bytes_to_read: 0,
+            peer_closed: false,
+            buffer_high_water: 0,
+            client_hello: Vec::new(),
+            server_hello: None,
+            server_records: Vec::new(),
+            fingerprint_target: options.fingerprint_target.clone(),
+            flushed: false,
+            clock: shared.clock.clone(),
+            now: None,
+            handshake_start: None,
+            handshake_end: None,
+            new_tickets: shared.new_tickets.clone(),
+            tickets_received: 0,
+            ticket_callback: None,
+            ech_retry_configs: None,
+            wrote_while_handshaking: false,
+            false_start: false,
+            pooled: None,
+            failed: false,
+        };
+        tls.capture_client_hello()?;
+        Ok(tls)
+    }
+
+    /// Whether the connection can carry another exchange as it is: handshake done,
+    /// nothing unread, unsent or half-received, and no close or error either way.
+    pub(crate) fn is_idle(&self) -> bool {
+        !self.conn.is_handshaking()
+            && !self.failed
+            && !self.peer_closed
+            && self.last_sent_alert.is_none()
+            && self.version_too_low.is_none()
+            && self.plaintext_pending() == 0
+            && self.incoming_tls_offset == self.incoming_tls.len()
+            && !self.wants_write()
+    }
+
+    /// Give up the connection, keeping its buffers.
+    pub(crate) fn into_buffers(self) -> Buffers {
+        Buffers([self.incoming_tls, self.outgoing_tls, self.plaintext_out])
+    }
+
+    /// rustls builds the ClientHello in `ClientConnection::new`; pull it into
+    /// `outgoing_tls` now so it can be inspected before the first flush.
+    fn capture_client_hello(&mut self) -> Result<(), JsError> {
+        self.drain_outgoing_tls()?;
+        self.client_hello =
+            hello::find_handshake_message(&self.outgoing_tls, hello::HANDSHAKE_CLIENT_HELLO)
+                .unwrap_or_default();
+        Ok(())
+    }
+
+    /// Whether our Finished was the last handshake message, so that application data
+    /// can follow it in the same flight: on TLS 1.3 and resumed TLS 1.2.
+    fn finished_last(&self) -> bool {
+        self.conn.protocol_version() == Some(ProtocolVersion::TLSv1_3)
+            || self.conn.handshake_kind() == Some(HandshakeKind::Resumed)
+    }
+
+    /// Whether a KeyUpdate can be sent now: only on TLS 1.3, after the handshake.
+    fn can_update_keys(&self) -> bool {
+        !self.conn.is_handshaking()
+            && self.conn.protocol_version() == Some(ProtocolVersion::TLSv1_3)
+    }
+
+    /// Fail once the negotiated version is known to be below the floor. The
+    /// ServerHello has been processed by then, but nothing of ours was encrypted.
+    fn check_min_version(&mut self) -> Result<(), JsError> {
+        if self.version_too_low.is_none() {
+            let Some(required) = self.min_version else {
+                return Ok(());
+            };
+            self.version_too_low = VersionTooLow::check(required, self.conn.protocol_version());
+        }
+        match &self.version_too_low {
+            Some(error) => Err(JsError::new(&error.message())),
+            None => Ok(()),
+        }
+    }
+
+    fn key_update_due(&self) -> bool {
+        self.key_update_requested
+            || (self.key_update_after_bytes > 0
+                && self.bytes_under_key >= self.key_update_after_bytes)
+    }
+
+    fn trust_path(&self) -> Option<verifier::TrustPath> {
+        if self.conn.is_handshaking() {
+            return None;
+        }
+        self.trust_path
+    }
+
+    /// Move what the verifier recorded while processing this connection's records
+    /// to the connection, before another connection on the verifier is processed.
+    fn take_verification(&mut self) {
+        let Some(verifier) = &self.webpki_verifier else {
+            return;
+        };
+        if let Some(path) = verifier.take_verified_path() {
+            self.trust_path = Some(path);
+        }
+        if let Some(must_staple) = verifier.take_must_staple() {
+            self.must_staple = Some(must_staple);
+        }
+    }
+
+    fn buffer_capacity(&self) -> usize {
+        self.incoming_tls.capacity() + self.outgoing_tls.capacity() + self.plaintext_out.capacity()
+    }
+
+    fn record_buffer_usage(&mut self) {
+        self.buffer_high_water = self.buffer_high_water.max(self.buffer_capacity());
+    }
+
+    /// Count the tickets the session store recorded, and pass them to the ticket
+    /// callback if there is one.
+    fn report_tickets(&mut self) -> Result<(), JsError> {
+        let new = match self.new_tickets.lock() {
+            Ok(mut received) => mem::take(&mut *received),
+            Err(_) => return Ok(()),
+        };
+        self.tickets_received += new.len() as u32;
+        let Some(callback) = &self.ticket_callback else {
+            return Ok(());
+        };
+        for ticket in new {
+            let value = ticket
+                .to_js()
+                .map_err(|e| JsError::new(&js_error_message(&e)))?;
+            callback.call1(&JsValue::NULL, &value).map_err(|e| {
+                JsError::new(&format!("Ticket callback failed: {}", js_error_message(&e)))
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Ciphertext is going to the caller; the first time, that is the ClientHello
+    /// and the handshake starts.
+    fn mark_flushed(&mut self) {
+        if !self.flushed {
+            self.handshake_start = self.now;
+        }
+        self.flushed = true;
+    }
+
+    /// `feed_ciphertext` up to the first error.
+    fn feed_records(&mut self, data: &[u8]) -> Result<bool, JsError> {
+        self.check_min_version()?;
+        let was_handshaking = self.conn.is_handshaking();
+        if was_handshaking {
+            self.handshake_bytes += data.len();
+            if self.handshake_bytes > self.max_handshake_size {
+                let error = HandshakeTooLarge::new(self.max_handshake_size, self.handshake_bytes);
+                self.handshake_too_large = Some(error);
+                self.record_buffer_usage();
+                return Err(JsError::new(&format!(
+                    "Handshake too large: {} bytes received, limit is {}",
+                    self.handshake_bytes, self.max_handshake_size
+                )));
+            }
+        }
+        if self.incoming_tls.len() + data.len() > MAX_TLS_BUF_SIZE {
+            self.compact_incoming_tls();
+            if self.incoming_tls.len() + data.len() > MAX_TLS_BUF_SIZE {
+                self.record_buffer_usage();
+                return Err(JsError::new("Incoming TLS buffer exceeded maximum size"));
+            }
+        }
+        self.incoming_tls.extend_from_slice(data);
+        self.framing.advance(data);
+        if was_handshaking && self.server_hello.is_none() {
+            self.server_records.extend_from_slice(data);
+            self.server_hello = hello::find_server_hello(&self.server_records);
+            if self.server_hello.is_some() {
+                self.server_records = Vec::new();
+            }
+        }
+
+        // Let rustls read TLS records from our buffer (&[u8] implements Read)
+        let mut reader = &self.incoming_tls[self.incoming_tls_offset..];
+        let bytes_read = self
+            .conn
+            .read_tls(&mut reader)
+            .map_err(|e| JsError::new(&format!("read_tls error: {}", e)))?;
+
+        // Advance offset for processed bytes
+        self.incoming_tls_offset += bytes_read;
+
+        // Compact buffer occasionally to avoid unbounded growth
+        if self.incoming_tls_offset > 0 {
+            if self.incoming_tls_offset >= self.incoming_tls.len() {
+                self.incoming_tls.clear();
+                self.incoming_tls_offset = 0;
+            } else if self.incoming_tls_offset >= self.compaction_threshold
+                && self.incoming_tls_offset >= self.incoming_tls.len() / 2
+            {
+                self.compact_incoming_tls();
+            }
+        }
+
+        // Process the TLS records
+
+        let io_state = self.conn.process_new_packets();
+        self.take_verification();
+        let io_state = match io_state {
+            Ok(state) => state,
+            Err(e) => return Err(self.tls_error(e)),
+        };
+        self.check_min_version()?;
+        if was_handshaking && !self.conn.is_handshaking() {
+            self.handshake_end = self.now;
+            self.server_records = Vec::new();
+            self.false_start = self.wrote_while_handshaking && self.finished_last();
+        }
+
+        self.io_tls_bytes_to_write = io_state.tls_bytes_to_write();
+        self.io_plaintext_bytes_to_read = io_state.plaintext_bytes_to_read();
+
+        // Extract any decrypted plaintext (write directly into plaintext_out, no temp Vec)
+        let pt_bytes = io_state.plaintext_bytes_to_read();
+        if pt_bytes > 0 {
+            let start = self.plaintext_out.len();
+            self.plaintext_out.resize(start + pt_bytes, 0);
+            let n = self
+                .conn
+                .reader()
+                .read(&mut self.plaintext_out[start..])
+                .map_err(|e| JsError::new(&format!("plaintext read error: {}", e)))?;
+            self.plaintext_out.truncate(start + n);
+            self.last_feed_plaintext = n > 0;
+        }
+        self.peer_closed |= io_state.peer_has_closed();
+
+        self.record_buffer_usage();
+        self.report_tickets()?;
+        Ok(self.wants_write())
+    }
+
+    /// `write_plaintext` up to the first error.
+    fn write_records(&mut self, data: &[u8]) -> Result<bool, JsError> {
+        self.check_min_version()?;
+        self.wrote_while_handshaking |= !data.is_empty() && self.conn.is_handshaking();
+        let mut rest = data;
+        loop {
+            let can_update = self.can_update_keys();
+            if can_update && self.key_update_due() {
+                self.conn
+                    .refresh_traffic_keys()
+                    .map_err(|e| JsError::new(&format!("key update error: {}", e)))?;
+                self.bytes_under_key = 0;
+                self.key_update_requested = false;
+                self.key_updates_sent += 1;
+            }
+            let n = if can_update && self.key_update_after_bytes > 0 {
+                rest.len()
+                    .min(self.key_update_after_bytes - self.bytes_under_key)
+            } else {
+                rest.len()
+            };
+            // rustls fragments each write on its own, so a write no larger than
+            // the limit is one record, also when queued during the handshake
+            let n = n.min(self.max_plaintext_fragment());
+            self.conn
+                .writer()
+                .write_all(&rest[..n])
+                .map_err(|e| JsError::new(&format!("write error: {}", e)))?;
+            self.bytes_under_key += n;
+            rest = &rest[n..];
+            if rest.is_empty() {
+                return Ok(self.wants_write());
+            }
+        }
+    }
+
+    /// Append everything rustls has queued for the network to `outgoing_tls`.
+    fn drain_outgoing_tls(&mut self) -> Result<(), JsError> {
+        while self.conn.wants_write() {
+            if let Err(e) = self.conn.write_tls(&mut self.outgoing_tls) {
+                self.failed = true;
+                return Err(JsError::new(&format!("write_tls error: {}", e)));
+            }
+        }
+        self.record_buffer_usage();
+        Ok(())
+    }
+
+    /// Convert a rustls error to the one thrown to JS, keeping structured details around.
+    fn tls_error(&mut self, e: rustls::Error) -> JsError {
+        self.failed = true;
+        self.hostname_mismatch = HostnameMismatch::from_tls_error(&e, &self.hostname);
+        if let Some(alert) = error::alert_for_rejection(&e) {
+            self.last_sent_alert = Some(alert);
+        }
+        self.negotiation_failure = self.classify_negotiation(&e);
+        if let rustls::Error::PeerIncompatible(
+            rustls::PeerIncompatible::ServerRejectedEncryptedClientHello(_),
+        ) = &e
+        {
+            self.ech_retry_configs = error::ech_retry_configs(&e);
+            JsError::new(match self.ech_retry_configs {
+                Some(_) => "ECH rejected: the server sent retry configs (see ech_retry_configs)",
+                None => "ECH rejected: the server sent no retry configs",
+            })
+        } else if self.hostname_mismatch.is_some() {
+            JsError::new(&format!("Hostname mismatch: {}", e))
+        } else if let Some(failure) = &self.negotiation_failure {
+            JsError::new(&format!("{}: {}", failure.message(), e))
+        } else {
+            JsError::new(&format!("TLS error: {}", e))
+        }
+    }
+
+    fn classify_negotiation(&self, e: &rustls::Error) -> Option<NegotiationFailure> {
+        let provider = self.config.crypto_provider();
+        let hello_answered = self.conn.negotiated_cipher_suite().is_some();
+        let groups_narrowed = provider.kx_groups.len() < get_provider().kx_groups.len();
+        let (kind, alert) = NegotiationFailure::classify(e, hello_answered, groups_narrowed)?;
+        let offered = match kind {
+            Negotiated::CipherSuite => provider
+                .cipher_suites
+                .iter()
+                .filter(|s| self.versions.contains(&u16::from(s.version().version)))
+                .map(|s| u16::from(s.suite()))
+                .collect(),
+            Negotiated::KeyExchange => provider
+                .kx_groups
+                .iter()
+                .map(|g| u16::from(g.name()))
+                .collect(),
+            Negotiated::Version => self.versions.clone(),
+        };
+        Some(NegotiationFailure::new(kind, alert, offered))
+    }
+
     fn compact_incoming_tls(&mut self) {
         if self.incoming_tls_offset > 0 {
             let remaining = self.incoming_tls.len() - self.incoming_tls_offset;
             self.incoming_tls.copy_within(self.incoming_tls_offset.., 0);
+            self.compactions += 1;
             self.incoming_tls.truncate(remaining);
             self.incoming_tls_offset = 0;
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn idn_hostnames_are_sent_as_punycode() {
+        let tls = TlsConnection::new("Bücher.Example", "").unwrap();
+        assert_eq!(tls.hostname, "xn--bcher-kva.example");
+        let sni = b"xn--bcher-kva.example";
+        assert!(tls.client_hello.windows(sni.len()).any(|w| w == sni));
+        assert_eq!(ascii_hostname("::1").unwrap(), "::1");
+        assert!(ascii_hostname("a b").is_err());
+    }
+
+    #[test]
+    fn outgoing_tls_pending_matches_flush() {
+        let mut tls = TlsConnection::new("example.com", "h2,http/1.1").unwrap();
+        let pending = tls.outgoing_tls_pending();
+        assert!(pending > 0, "ClientHello should be pending");
+        assert_eq!(tls.outgoing_tls_pending(), pending);
+        assert_eq!(tls.flush_outgoing_tls().unwrap().len(), pending);
+        assert_eq!(tls.outgoing_tls_pending(), 0);
+        assert!(!tls.wants_write());
+        assert!(!tls.peer_has_closed());
+        assert_eq!(tls.handshake_too_large(), None);
+        assert_eq!(tls.chain_depth(), None);
+        assert_eq!(tls.trust_anchor_source(), None);
+        assert_eq!(tls.must_staple_required(), None);
+        assert!(!tls.is_fully_drained());
+        assert_eq!(tls.bytes_needed_for_next_record(), 0);
+        assert_eq!(tls.tickets_received(), 0);
+    }
+
+    #[test]
+    fn outgoing_tls_pending_counts_feeds_writes_and_alerts() {
+        let mut tls = client();
+        let mut server = server(&rustls::version::TLS13);
+        let hello = tls.flush_outgoing_tls().unwrap();
+        server_receive(&mut server, &hello);
+        tls.feed_ciphertext(&server_send(&mut server)).unwrap();
+        let pending = tls.outgoing_tls_pending();
+        assert!(pending > 0, "client Finished should be pending");
+        assert_eq!(tls.flush_outgoing_tls().unwrap().len(), pending);
+        tls.write_plaintext(&[1; 3000]).unwrap();
+        let pending = tls.outgoing_tls_pending();
+        assert!(pending > 3000);
+        tls.send_close_notify();
+        let out = tls.flush_outgoing_tls().unwrap();
+        assert!(out.len() > pending);
+        assert_eq!(tls.outgoing_tls_pending(), 0);
+    }
+
+    #[test]
+    fn chunked_flush_drains_everything() {
+        let mut tls = TlsConnection::new("example.com", "h2,http/1.1").unwrap();
+        let pending = tls.outgoing_tls_pending();
+        assert!(tls.wants_write());
+        tls.set_now(5.0);
+        assert!(tls.flush_outgoing_tls_chunk(0).unwrap().is_empty());
+        tls.set_now(7.5);
+        let mut sent = Vec::new();
+        loop {
+            let chunk = tls.flush_outgoing_tls_chunk(100).unwrap();
+            if chunk.is_empty() {
+                break;
+            }
+            assert!(chunk.len() <= 100);
+            sent.extend(chunk);
+        }
+        assert_eq!(sent.len(), pending);
+        assert!(sent.ends_with(&tls.client_hello));
+        assert!(!tls.wants_write());
+        assert_eq!(tls.outgoing_tls_pending(), 0);
+        // The handshake started with the first piece of the ClientHello sent
+        assert_eq!(tls.timings().tls_start(), Some(7.5));
+        assert_eq!(tls.timings().tls_end(), None);
+    }
+
+    #[test]
+    fn plaintext_in_bounded_chunks() {
+        let mut options = TlsOptions::new();
+        options.set_max_chunk_size(1000);
+        let mut tls = TlsConnection::with_options("example.com", "", &options).unwrap();
+        assert!(tls.take_plaintext_chunk().is_empty());
+        let data: Vec<u8> = (0..2500u32).map(|i| i as u8).collect();
+        tls.plaintext_out.extend_from_slice(&data);
+        let mut taken = tls.take_plaintext_chunk();
+        assert_eq!((taken.len(), tls.plaintext_pending()), (1000, 1500));
+        // Decrypted data arriving in between is appended at the end
+        tls.plaintext_out.extend_from_slice(&data);
+        while tls.plaintext_pending() > 0 {
+            let chunk = tls.take_plaintext_chunk();
+            assert!(!chunk.is_empty() && chunk.len() <= 1000);
+            taken.extend(chunk);
+        }
+        assert_eq!(taken, [data.clone(), data.clone()].concat());
+
+        tls.plaintext_out.extend_from_slice(&data);
+        assert_eq!(tls.take_plaintext_chunk().len(), 1000);
+        assert_eq!(tls.take_plaintext(), &data[1000..]);
+        assert_eq!(tls.plaintext_pending(), 0);
+    }
+
+    #[test]
+    fn plaintext_into_caller_memory() {
+        let mut tls = TlsConnection::new("example.com", "").unwrap();
+        let data: Vec<u8> = (0..300u32).map(|i| i as u8).collect();
+        tls.plaintext_out.extend_from_slice(&data);
+        let mut out = [0u8; 200];
+        assert_eq!(tls.read_plaintext_into(&mut out), 200);
+        assert_eq!((&out[..], tls.plaintext_pending()), (&data[..200], 100));
+
+        let buffer = alloc_buffer(256);
+        // Into the second half of the buffer, as a JS view at an offset would
+        let n = unsafe { tls.read_plaintext_into_ptr(buffer.add(128), 128) };
+        let copied = unsafe { std::slice::from_raw_parts(buffer.add(128), n) };
+        assert_eq!(copied, &data[200..]);
+        assert_eq!(tls.plaintext_pending(), 0);
+        assert_eq!(unsafe { tls.read_plaintext_into_ptr(buffer, 256) }, 0);
+        unsafe { free_buffer(buffer, 256) };
+    }
+
+    #[test]
+    fn compaction_threshold_is_configurable() {
+        // One large, incomplete ServerHello: rustls takes it in pieces and waits
+        let mut record = vec![0x16, 0x03, 0x03, 0x40, 0x00, 0x02, 0x00, 0xff, 0xff];
+        record.resize(5 + 0x4000, 0);
+        let compactions = |threshold| {
+            let mut options = TlsOptions::new();
+            options.set_compaction_threshold(threshold);
+            let mut tls = TlsConnection::with_options("example.com", "", &options).unwrap();
+            tls.pump(&record).unwrap();
+            assert_eq!(tls.bytes_needed_for_next_record(), 0);
+            tls.compactions()
+        };
+        assert_eq!(compactions(usize::MAX), 0);
+        assert!(compactions(0) > compactions(DEFAULT_COMPACTION_THRESHOLD));
+    }
+
+    #[test]
+    fn lists_provider_capabilities() {
+        // TLS_AES_128_GCM_SHA256, TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256
+        let suites = supported_cipher_suites();
+        assert!(suites.contains(&0x1301) && suites.contains(&0xc02b));
+        // x25519, secp256r1
+        let groups = supported_kx_groups();
+        assert!(groups.contains(&0x001d) && groups.contains(&0x0017));
+        // ecdsa_secp256r1_sha256, rsa_pss_rsae_sha256
+        let schemes = supported_signature_schemes();
+        assert!(schemes.contains(&0x0403) && schemes.contains(&0x0804));
+    }
+
+    #[test]
+    fn raw_public_key_client_hello() {
+        const SPKI: &[u8] = include_bytes!("../tests/fixtures/client-spki.der");
+        let tls = TlsConnection::with_raw_public_keys_der("device.local", "", vec![SPKI.to_vec()])
+            .unwrap();
+        // server_certificate_type (RFC 7250)
+        let extensions = tls.client_hello_extensions();
+        assert!(extensions.contains(&20));
+        let plain = TlsConnection::new("device.local", "").unwrap();
+        assert!(!plain.client_hello_extensions().contains(&20));
+        assert_eq!(tls.chain_depth(), None);
+    }
+
+    #[test]
+    fn pump_returns_pending_outgoing() {
+        let mut tls = TlsConnection::new("example.com", "h2").unwrap();
+        let pending = tls.outgoing_tls_pending();
+        let mut result = tls.pump(&[]).unwrap();
+        assert_eq!(result.take_outgoing().len(), pending);
+        assert!(result.take_plaintext().is_empty());
+        assert!(result.take_outgoing().is_empty());
+        assert_eq!(tls.outgoing_tls_pending(), 0);
+    }
+
+    /// In-memory server for fetch.test, speaking only `version`.
+    pub(crate) fn server(version: &'static SupportedProtocolVersion) -> rustls::ServerConnection {
+        rustls::ServerConnection::new(server_config(version)).unwrap()
+    }
+
+    /// Config of `server`; connections sharing one can resume each other's sessions.
+    pub(crate) fn server_config(
+        version: &'static SupportedProtocolVersion,
+    ) -> Arc<rustls::ServerConfig> {
+        let cert = include_bytes!("../tests/fixtures/server-cert.der").to_vec();
+        let key = include_bytes!("../tests/fixtures/server-key.der").to_vec();
+        let config = rustls::ServerConfig::builder_with_provider(get_provider())
+            .with_protocol_versions(&[version])
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![cert.into()],
+                rustls::pki_types::PrivateKeyDer::try_from(key).unwrap(),
+            )
+            .unwrap();
+        Arc::new(config)
+    }
+
+    /// Client that trusts `server`.
+    pub(crate) fn client() -> TlsConnection {
+        let mut options = TlsOptions::new();
+        options
+            .add_root(
+                "fetch.test",
+                include_bytes!("../tests/fixtures/server-ca.der").to_vec(),
+            )
+            .unwrap();
+        TlsConnection::with_options("fetch.test", "", &options).unwrap()
+    }
+
+    /// Hand `data` to `server` and return the plaintext it decrypted.
+    pub(crate) fn server_receive(
+        server: &mut rustls::ServerConnection,
+        mut data: &[u8],
+    ) -> Vec<u8> {
+        while !data.is_empty() {
+            server.read_tls(&mut data).unwrap();
+            server.process_new_packets().unwrap();
+        }
+        let mut plaintext = Vec::new();
+        let _ = server.reader().read_to_end(&mut plaintext);
+        plaintext
+    }
+
+    pub(crate) fn server_send(server: &mut rustls::ServerConnection) -> Vec<u8> {
+        let mut out = Vec::new();
+        while server.wants_write() {
+            server.write_tls(&mut out).unwrap();
+        }
+        out
+    }
+
+    /// Handshake with a request written before the ClientHello went out. Returns
+    /// the plaintext the server had decrypted once our Finished arrived, and how
+    /// many non-empty flushes followed it.
+    fn handshake_with_queued_request(
+        version: &'static SupportedProtocolVersion,
+    ) -> (TlsConnection, Vec<u8>, usize) {
+        let mut tls = client();
+        let mut server = server(version);
+        let mut with_finished = Vec::new();
+        let mut flushes_after = 0;
+        tls.write_plaintext(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        loop {
+            let out = tls.flush_outgoing_tls().unwrap();
+            let finished_sent = !server.is_handshaking();
+            let received = server_receive(&mut server, &out);
+            if finished_sent {
+                flushes_after += usize::from(!out.is_empty());
+            } else if !server.is_handshaking() {
+                with_finished = received;
+            }
+            let reply = server_send(&mut server);
+            if reply.is_empty() && !tls.wants_write() {
+                break;
+            }
+            if !reply.is_empty() {
+                let wants_write = tls.feed_ciphertext(&reply).unwrap();
+                assert_eq!(wants_write, tls.wants_write());
+            }
+        }
+        assert!(!tls.is_handshaking());
+        (tls, with_finished, flushes_after)
+    }
+
+    #[test]
+    fn queued_data_rides_with_finished() {
+        let (tls, with_finished, flushes_after) =
+            handshake_with_queued_request(&rustls::version::TLS13);
+        assert_eq!(with_finished, b"GET / HTTP/1.1\r\n\r\n");
+        assert_eq!(flushes_after, 0);
+        assert!(tls.false_start_used());
+
+        // A full TLS 1.2 handshake holds it until the server's Finished, then
+        // sends it straight away
+        let (tls, with_finished, flushes_after) =
+            handshake_with_queued_request(&rustls::version::TLS12);
+        assert!(with_finished.is_empty());
+        assert_eq!(flushes_after, 1);
+        assert!(!tls.false_start_used());
+
+        let mut tls = TlsConnection::new("fetch.test", "").unwrap();
+        assert!(!tls.false_start_used());
+        tls.write_plaintext(b"").unwrap();
+        assert!(!tls.wrote_while_handshaking);
+    }
+
+    #[test]
+    fn io_state_sizes_after_each_feed() {
+        let mut tls = client();
+        let mut server = server(&rustls::version::TLS13);
+        assert_eq!(tls.last_io_tls_bytes_to_write(), 0);
+        let hello = tls.flush_outgoing_tls().unwrap();
+        server_receive(&mut server, &hello);
+        tls.feed_ciphertext(&server_send(&mut server)).unwrap();
+        // Our Finished
+        let finished = tls.last_io_tls_bytes_to_write();
+        assert!(finished > 0);
+        assert_eq!(tls.last_io_plaintext_bytes_to_read(), 0);
+        let out = tls.flush_outgoing_tls().unwrap();
+        assert_eq!(out.len(), finished);
+        server_receive(&mut server, &out);
+
+        server.writer().write_all(&[7; 1000]).unwrap();
+        tls.feed_ciphertext(&server_send(&mut server)).unwrap();
+        assert_eq!(tls.last_io_plaintext_bytes_to_read(), 1000);
+        assert_eq!(tls.last_io_tls_bytes_to_write(), 0);
+        assert_eq!(tls.plaintext_pending(), 1000);
+    }
+
+    #[test]
+    fn buffer_high_water_never_falls() {
+        let mut tls = client();
+        let mut server = server(&rustls::version::TLS13);
+        let hello = tls.flush_outgoing_tls().unwrap();
+        server_receive(&mut server, &hello);
+        tls.feed_ciphertext(&server_send(&mut server)).unwrap();
+        let finished = tls.flush_outgoing_tls().unwrap();
+        server_receive(&mut server, &finished);
+
+        let mut high_water = tls.buffer_memory_high_water();
+        let mut check = |tls: &TlsConnection| {
+            assert!(tls.buffer_memory_high_water() >= tls.buffer_memory_usage());
+            assert!(tls.buffer_memory_high_water() >= high_water);
+            high_water = tls.buffer_memory_high_water();
+        };
+        check(&tls);
+        // A full-size record each way
+        server.writer().write_all(&[7; 16384]).unwrap();
+        tls.feed_all(&server_send(&mut server)).unwrap();
+        check(&tls);
+        assert!(tls.buffer_memory_high_water() as usize >= 16384);
+        tls.write_plaintext(&[8; 16384]).unwrap();
+        check(&tls);
+        // Both hand their buffer out and swap in a new one
+        assert_eq!(tls.take_plaintext().len(), 16384);
+        check(&tls);
+        let out = tls.flush_outgoing_tls().unwrap();
+        assert_eq!(server_receive(&mut server, &out).len(), 16384);
+        check(&tls);
+    }
+
+    #[test]
+    fn drained_only_once_the_plaintext_is_taken() {
+        let mut tls = client();
+        let mut server = server(&rustls::version::TLS13);
+        let hello = tls.flush_outgoing_tls().unwrap();
+        server_receive(&mut server, &hello);
+        tls.feed_ciphertext(&server_send(&mut server)).unwrap();
+        let finished = tls.flush_outgoing_tls().unwrap();
+        server_receive(&mut server, &finished);
+
+        server.writer().write_all(b"last bytes").unwrap();
+        server.send_close_notify();
+        tls.feed_all(&server_send(&mut server)).unwrap();
+        assert!(tls.peer_has_closed() && !tls.is_fully_drained());
+        // Feeding nothing more changes nothing
+        tls.feed_ciphertext(&[]).unwrap();
+        assert!(tls.peer_has_closed() && !tls.is_fully_drained());
+        assert_eq!(tls.take_plaintext(), b"last bytes");
+        assert!(tls.is_fully_drained());
+    }
+
+    #[test]
+    fn oversized_handshake_is_aborted() {
+        let mut options = TlsOptions::new();
+        options
+            .add_root(
+                "fetch.test",
+                include_bytes!("../tests/fixtures/server-ca.der").to_vec(),
+            )
+            .unwrap();
+        options.set_max_handshake_size(500);
+        let mut tls = TlsConnection::with_options("fetch.test", "", &options).unwrap();
+        let mut server = server(&rustls::version::TLS13);
+        let hello = tls.flush_outgoing_tls().unwrap();
+        server_receive(&mut server, &hello);
+        // ServerHello through Finished, the certificate included
+        let flight = server_send(&mut server);
+        assert!(flight.len() > 500);
+        // Natively, building the JsError panics where wasm would return it
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            tls.feed_ciphertext(&flight).is_ok()
+        }));
+        assert!(!result.unwrap_or(false));
+        let error = tls.handshake_too_large().unwrap();
+        assert_eq!((error.limit(), error.received()), (500, flight.len()));
+        assert!(tls.is_handshaking());
+        assert!(tls.buffer_memory_high_water() >= tls.buffer_memory_usage());
+    }
+
+    #[test]
+    fn cipher_suite_and_group_order() {
+        let suites = supported_cipher_suites();
+        let groups = supported_kx_groups();
+        let last_suite = *suites.last().unwrap();
+        let last_group = *groups.last().unwrap();
+        let mut options = TlsOptions::new();
+        options
+            .order_cipher_suites(&[last_suite, suites[1]])
+            .unwrap();
+        options.order_groups(&[last_group]).unwrap();
+        let tls = TlsConnection::with_options("order.example", "", &options).unwrap();
+        let hello = hello::client_hello_fingerprint(&tls.client_hello).unwrap();
+        let mut expected = vec![last_suite, suites[1], suites[0]];
+        expected.extend(&suites[2..suites.len() - 1]);
+        // rustls adds the renegotiation SCSV at the end
+        expected.push(0x00ff);
+        assert_eq!(hello.cipher_suites, expected);
+        // The key share goes to the first group
+        assert_eq!(hello.groups[0], last_group);
+        assert_eq!(hello.groups.len(), groups.len());
+
+        assert!(options.order_groups(&[last_group, last_group]).is_err());
+        assert!(options.order_cipher_suites(&[0x00ff]).is_err());
+        assert!(options.order_cipher_suites(&[]).is_ok());
+    }
+
+    #[test]
+    fn handshake_records_yield_no_plaintext() {
+        let mut tls = TlsConnection::new("example.com", "h2").unwrap();
+        assert!(!tls.last_feed_yielded_plaintext());
+        // The start of a ServerHello record
+        tls.feed_ciphertext(&[0x16, 0x03, 0x03, 0x00, 0x7a, 0x02])
+            .unwrap();
+        assert!(!tls.last_feed_yielded_plaintext());
+        assert_eq!(tls.take_plaintext().capacity(), 0);
+    }
+
+    #[test]
+    fn key_updates_wait_for_the_handshake() {
+        let mut options = TlsOptions::new();
+        options.set_key_update_after_bytes(4);
+        let mut tls = TlsConnection::with_options("rekey.example", "", &options).unwrap();
+        tls.request_key_update();
+        // Buffered until the handshake completes, under the first traffic key
+        tls.write_plaintext(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(tls.key_updates_sent(), 0);
+        assert!(tls.key_update_due());
+    }
+
+    #[test]
+    fn uploads_split_into_fragments() {
+        let (mut tls, _, _) = handshake_with_queued_request(&rustls::version::TLS13);
+        assert_eq!(tls.max_plaintext_fragment(), 16384);
+        tls.set_max_plaintext_fragment(100);
+        tls.write_plaintext(&[b'x'; 250]).unwrap();
+        let out = tls.flush_outgoing_tls().unwrap();
+        // Content type byte and AEAD tag on top of each fragment
+        let mut lengths = Vec::new();
+        let mut rest = &out[..];
+        while let [0x17, 0x03, 0x03, high, low, tail @ ..] = rest {
+            let len = u16::from_be_bytes([*high, *low]) as usize;
+            lengths.push(len - 17);
+            rest = &tail[len..];
+        }
+        assert!(rest.is_empty());
+        assert_eq!(lengths, [100, 100, 50]);
+
+        tls.set_max_plaintext_fragment(100_000);
+        assert_eq!(tls.max_plaintext_fragment(), 16384);
+        let mut options = TlsOptions::new();
+        options.set_max_plaintext_fragment(512);
+        let tls = TlsConnection::with_options("fetch.test", "", &options).unwrap();
+        assert_eq!(tls.max_plaintext_fragment(), 512);
+    }
+
+    #[test]
+    fn fresh_connection_has_no_early_data() {
+        let mut options = TlsOptions::new();
+        options.set_early_data(true);
+        let mut tls = TlsConnection::with_options("fresh.example", "", &options).unwrap();
+        assert_eq!(tls.write_early_data(b"GET / HTTP/1.1\r\n\r\n").unwrap(), 0);
+        assert_eq!(
+            tls.write_early_data_unsafe(b"POST / HTTP/1.1\r\n").unwrap(),
+            0
+        );
+        assert!(!tls.was_resumed());
+        assert!(!tls.early_data_was_accepted());
+    }
+
+    #[test]
+    fn early_data_replay_check() {
+        for safe in [
+            &b"GET / HTTP/1.1\r\n"[..],
+            b"HEAD /x HTTP/1.1\r\n",
+            b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n",
+            b"\x00\x00\x12\x04\x00",
+            b"",
+        ] {
+            assert_eq!(replay_unsafe_method(safe), None);
+        }
+        assert_eq!(
+            replay_unsafe_method(b"POST /form HTTP/1.1\r\n"),
+            Some("POST")
+        );
+        assert_eq!(
+            replay_unsafe_method(b"DELETE /item HTTP/1.1"),
+            Some("DELETE")
+        );
+        assert_eq!(replay_unsafe_method(b"get / HTTP/1.1"), Some("get"));
+    }
+
+    #[test]
+    fn no_alpn_downgrade_before_the_handshake() {
+        let tls = TlsConnection::new("example.com", "h2,http/1.1").unwrap();
+        assert_eq!(tls.negotiated_alpn(), None);
+        assert!(!tls.alpn_downgraded());
+    }
+
+    #[test]
+    fn forced_full_handshake_rebuilds_client_hello() {
+        let mut tls = TlsConnection::new("forced.example", "h2").unwrap();
+        let mut exts = tls.client_hello_extensions();
+        tls.force_full_handshake().unwrap();
+        // No ticket was cached, so the rebuilt hello carries the same extensions
+        // (rustls shuffles their order per hello)
+        let mut rebuilt = tls.client_hello_extensions();
+        exts.sort_unstable();
+        rebuilt.sort_unstable();
+        assert_eq!(rebuilt, exts);
+        // pre_shared_key, early_data
+        assert!(!exts.contains(&41) && !exts.contains(&42));
+        let pending = tls.outgoing_tls_pending();
+        assert_eq!(tls.flush_outgoing_tls().unwrap().len(), pending);
+    }
+
+    #[test]
+    fn preview_matches_a_real_client_hello() {
+        let mut options = TlsOptions::new();
+        options.set_early_data(true);
+        let records = preview_client_hello("preview.example", "h2,http/1.1", &options).unwrap();
+        let msg = hello::find_handshake_message(&records, hello::HANDSHAKE_CLIENT_HELLO).unwrap();
+        let mut previewed = hello::client_hello_extensions(&msg).unwrap();
+        let tls = TlsConnection::with_options("preview.example", "h2,http/1.1", &options).unwrap();
+        let mut exts = tls.client_hello_extensions();
+        previewed.sort_unstable();
+        exts.sort_unstable();
+        assert_eq!(previewed, exts);
+        assert_eq!(records.len(), tls.outgoing_tls.len());
+    }
+
+    #[test]
+    fn suppressed_extensions_leave_the_client_hello() {
+        let full = TlsConnection::new("legacy.example", "h2").unwrap();
+        let full = hello::client_hello_fingerprint(&full.client_hello).unwrap();
+        assert!([0, 16, 35].iter().all(|e| full.extensions.contains(e)));
+
+        let mut options = TlsOptions::new();
+        for extension in [0, 16, 35] {
+            options.suppress_extension(extension).unwrap();
+        }
+        options.suppress_key_share(0x001d).unwrap();
+        let tls = TlsConnection::with_options("legacy.example", "h2", &options).unwrap();
+        let minimal = hello::client_hello_fingerprint(&tls.client_hello).unwrap();
+        assert!(![0, 16, 35].iter().any(|e| minimal.extensions.contains(e)));
+        assert!(minimal.extensions.contains(&51));
+        assert!(!minimal.groups.contains(&0x001d) && !minimal.groups.is_empty());
+    }
+
+    #[test]
+    fn negotiation_failures_list_what_was_offered() {
+        use rustls::AlertDescription::HandshakeFailure;
+        let refused = rustls::Error::AlertReceived(HandshakeFailure);
+        let tls = TlsConnection::new("strict.example", "").unwrap();
+        let failure = tls.classify_negotiation(&refused).unwrap();
+        assert_eq!(
+            (failure.kind(), failure.alert()),
+            ("cipher-suite".into(), Some(40))
+        );
+        assert_eq!(failure.offered(), supported_cipher_suites());
+
+        let mut options = TlsOptions::new();
+        options.suppress_key_share(0x001d).unwrap();
+        let tls = TlsConnection::with_options("strict.example", "", &options).unwrap();
+        let failure = tls.classify_negotiation(&refused).unwrap();
+        assert_eq!(failure.kind(), "key-exchange");
+        let mut groups = supported_kx_groups();
+        groups.retain(|&g| g != 0x001d);
+        assert_eq!(failure.offered(), groups);
+    }
+
+    #[test]
+    fn profile_fidelity_against_a_target() {
+        let plain = TlsConnection::new("fidelity.example", "h2").unwrap();
+        assert_eq!(plain.profile_fidelity(), None);
+
+        let msg = &plain.client_hello;
+        let emitted = hello::client_hello_fingerprint(msg).unwrap();
+        let mut options = TlsOptions::new();
+        let mut extensions = emitted.extensions.clone();
+        extensions.reverse();
+        options.set_fingerprint_target(
+            emitted.cipher_suites.clone(),
+            emitted.groups.clone(),
+            extensions,
+        );
+        let tls = TlsConnection::with_options("fidelity.example", "h2", &options).unwrap();
+        assert_eq!(tls.profile_fidelity(), Some(Vec::new()));
+
+        // record_size_limit, which rustls never sends
+        let mut extensions = emitted.extensions.clone();
+        extensions.push(28);
+        options.set_fingerprint_target(emitted.cipher_suites, emitted.groups, extensions);
+        let tls = TlsConnection::with_options("fidelity.example", "h2", &options).unwrap();
+        assert_eq!(
+            tls.profile_fidelity(),
+            Some(vec!["extension 0x001c not emitted".to_string()])
+        );
+    }
+
+    #[test]
+    fn client_hello_extensions_are_captured() {
+        let tls = TlsConnection::new("example.com", "h2,http/1.1").unwrap();
+        let exts = tls.client_hello_extensions();
+        // server_name, ALPN, supported_versions, key_share
+        for typ in [0, 16, 43, 51] {
+            assert!(exts.contains(&typ), "missing {} in {:?}", typ, exts);
+        }
+        // No encrypted_client_hello, not even GREASE
+        assert!(!exts.contains(&0xfe0d));
+        assert!(!tls.ech_offered() && !tls.ech_accepted());
+    }
+
+    /// ECHConfigList holding one draft-ietf-tls-esni config for an X25519 key,
+    /// offering HKDF-SHA256 with AES-128-GCM.
+    pub(crate) fn ech_config_list(public_name: &str, public_key: &[u8; 32]) -> Vec<u8> {
+        let mut contents = vec![1]; // config_id
+        contents.extend_from_slice(&[0x00, 0x20, 0x00, 0x20]); // DHKEM(X25519), key length
+        contents.extend_from_slice(public_key);
+        contents.extend_from_slice(&[0x00, 0x04, 0x00, 0x01, 0x00, 0x01]);
+        contents.push(0); // maximum_name_length
+        contents.push(public_name.len() as u8);
+        contents.extend_from_slice(public_name.as_bytes());
+        contents.extend_from_slice(&[0, 0]); // no extensions
+
+        let mut config = vec![0xfe, 0x0d];
+        config.extend_from_slice(&(contents.len() as u16).to_be_bytes());
+        config.extend_from_slice(&contents);
+        let mut list = (config.len() as u16).to_be_bytes().to_vec();
+        list.extend_from_slice(&config);
+        list
+    }
+
+    #[test]
+    fn ech_hides_the_hostname() {
+        let list = ech_config_list("public.example", &[9; 32]);
+        let tls =
+            TlsConnection::with_ech("secret.example", "h2", list, &TlsOptions::default()).unwrap();
+        assert!(tls.client_hello_extensions().contains(&0xfe0d));
+        assert!(tls.ech_offered() && !tls.ech_accepted());
+        assert_eq!(tls.ech_retry_configs(), None);
+        assert_eq!(tls.versions, [0x0304]);
+        // Only the public name is in the clear
+        let contains = |name: &[u8]| tls.client_hello.windows(name.len()).any(|w| w == name);
+        assert!(contains(b"public.example"));
+        assert!(!contains(b"secret.example"));
+
+        // A config for an unsupported KEM, and garbage
+        let mut p256 = ech_config_list("public.example", &[9; 32]);
+        p256[8] = 0x10;
+        let mut options = TlsOptions::default();
+        assert!(options.set_ech_configs(&p256).is_err());
+        assert!(options.set_ech_configs(b"\x00\x02ab").is_err());
+    }
+}
@@ -0,0 +1,326 @@
+//! `TlsConnectionPool`: connections vended per (hostname, ALPN list, profile), on
+//! configs built once, and taken back for reuse.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use wasm_bindgen::prelude::*;
+
+use crate::{ascii_hostname, Buffers, SharedConfig, TlsConnection, TlsOptions};
+
+/// Released connections kept open for `acquire`; beyond this the oldest is evicted.
+const MAX_IDLE: usize = 32;
+
+/// Buffer sets of dropped connections kept for new ones.
+const MAX_SPARE_BUFFERS: usize = 16;
+
+/// Configs kept; the first by key makes room for a new one.
+const MAX_CONFIGS: usize = 64;
+
+static NEXT_POOL: AtomicU32 = AtomicU32::new(1);
+
+/// What a connection was vended for, so `release` can put it back.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct PoolKey {
+    pool: u32,
+    hostname: String,
+    alpn: String,
+    profile: String,
+}
+
+/// Kept on a connection from a pool.
+#[derive(Clone, Debug)]
+pub(crate) struct Pooled {
+    key: PoolKey,
+    pub(crate) id: u32,
+}
+
+/// Vends `TlsConnection`s keyed by (hostname, ALPN list, profile), all made with
+/// the pool's `TlsOptions`. Connections with the same key share one rustls config,
+/// and with it the verifier, clock and ticket store; every connection shares the
+/// global session cache, so the second connection to a host can resume.
+///
+/// `release` takes a connection back. One still open and idle (handshake done,
+/// nothing unread or unsent, no close_notify either way) is handed out again by
+/// the next `acquire` for its key, already connected: `pool_id` says which socket
+/// it belongs to, and `is_handshaking` is false. Any other connection is dropped,
+/// keeping its buffers for the next new one.
+///
+/// Profiles shape the HTTP layer only, so they do not change the ClientHello; the
+/// pool still keeps connections of different profiles apart.
+#[wasm_bindgen]
+pub struct TlsConnectionPool {
+    id: u32,
+    profile: String,
+    options: TlsOptions,
+    configs: BTreeMap<PoolKey, SharedConfig>,
+    /// Released connections still open, oldest first
+    idle: VecDeque<TlsConnection>,
+    /// Pool ids of idle connections dropped since the last `take_evicted`
+    evicted: Vec<u32>,
+    spare: Vec<Buffers>,
+    next_connection: u32,
+}
+
+#[wasm_bindgen]
+impl TlsConnectionPool {
+    /// A pool for `profile` (e.g. "chrome"), whose connections are made with `options`.
+    #[wasm_bindgen(constructor)]
+    pub fn new(profile: &str, options: &TlsOptions) -> Result<TlsConnectionPool, JsError> {
+        check_profile(profile)?;
+        Ok(TlsConnectionPool {
+            id: NEXT_POOL.fetch_add(1, Ordering::Relaxed),
+            profile: profile.to_string(),
+            options: options.clone(),
+            configs: BTreeMap::new(),
+            idle: VecDeque::new(),
+            evicted: Vec::new(),
+            spare: Vec::new(),
+            next_connection: 1,
+        })
+    }
+
+    /// A connection to `hostname` offering `alpn_protocols` (comma-separated, as for
+    /// `TlsConnection::new`) under the pool's profile: an idle one released earlier
+    /// if there is one, otherwise a new one ready to send its ClientHello.
+    pub fn acquire(
+        &mut self,
+        hostname: &str,
+        alpn_protocols: &str,
+    ) -> Result<TlsConnection, JsError> {
+        let profile = self.profile.clone();
+        self.acquire_for_profile(hostname, alpn_protocols, &profile)
+    }
+
+    /// `acquire` under another profile than the pool's.
+    pub fn acquire_for_profile(
+        &mut self,
+        hostname: &str,
+        alpn_protocols: &str,
+        profile: &str,
+    ) -> Result<TlsConnection, JsError> {
+        check_profile(profile)?;
+        let hostname = ascii_hostname(hostname).map_err(|e| JsError::new(&e))?;
+        let key = PoolKey {
+            pool: self.id,
+            hostname,
+            alpn: alpn_protocols.to_string(),
+            profile: profile.to_string(),
+        };
+        let idle = self
+            .idle
+            .iter()
+            .rposition(|conn| conn.pooled.as_ref().is_some_and(|p| p.key == key));
+        if let Some(conn) = idle.and_then(|i| self.idle.remove(i)) {
+            return Ok(conn);
+        }
+
+        if !self.configs.contains_key(&key) {
+            if self.configs.len() >= MAX_CONFIGS {
+                self.configs.pop_first();
+            }
+            let shared = SharedConfig::for_options(&key.hostname, alpn_protocols, &self.options)?;
+            self.configs.insert(key.clone(), shared);
+        }
+        let shared = &self.configs[&key];
+        let buffers = self.spare.pop().unwrap_or_default();
+        let mut conn = TlsConnection::from_shared(&key.hostname, &self.options, shared, buffers)?;
+        conn.pooled = Some(Pooled {
+            key,
+            id: self.next_connection,
+        });
+        self.next_connection += 1;
+        Ok(conn)
+    }
+
+    /// Take back a connection from this pool: kept for `acquire` if it is idle,
+    /// otherwise dropped without a close_notify (send one first to close it
+    /// cleanly). Connections from elsewhere only give up their buffers.
+    pub fn release(&mut self, conn: TlsConnection) {
+        let ours = conn.pooled.as_ref().is_some_and(|p| p.key.pool == self.id);
+        if !ours || !conn.is_idle() {
+            self.recycle(conn);
+            return;
+        }
+        self.idle.push_back(conn);
+        if self.idle.len() > MAX_IDLE {
+            if let Some(oldest) = self.idle.pop_front() {
+                self.evicted.extend(oldest.pool_id());
+                self.recycle(oldest);
+            }
+        }
+    }
+
+    /// Pool ids of idle connections dropped to stay within the pool's limit since
+    /// the last call; their sockets can be closed.
+    pub fn take_evicted(&mut self) -> Vec<u32> {
+        std::mem::take(&mut self.evicted)
+    }
+
+    /// Released connections waiting for `acquire`.
+    pub fn idle_connections(&self) -> usize {
+        self.idle.len()
+    }
+
+    /// Drop every idle connection (see `take_evicted`), config and spare buffer.
+    pub fn clear(&mut self) {
+        self.evicted
+            .extend(self.idle.drain(..).filter_map(|conn| conn.pool_id()));
+        self.configs.clear();
+        self.spare.clear();
+    }
+}
+
+impl TlsConnectionPool {
+    fn recycle(&mut self, conn: TlsConnection) {
+        if self.spare.len() < MAX_SPARE_BUFFERS {
+            self.spare.push(conn.into_buffers());
+        }
+    }
+}
+
+fn check_profile(profile: &str) -> Result<(), JsError> {
+    match wasm_http::profile_browser(profile) {
+        Some(_) => Ok(()),
+        None => Err(JsError::new(&format!("Unknown profile: {}", profile))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rustls::version::TLS13;
+
+    use super::*;
+    use crate::tests::{server, server_config, server_receive, server_send};
+
+    fn pool() -> TlsConnectionPool {
+        let mut options = TlsOptions::new();
+        options
+            .add_root(
+                "fetch.test",
+                include_bytes!("../tests/fixtures/server-ca.der").to_vec(),
+            )
+            .unwrap();
+        TlsConnectionPool::new("chrome", &options).unwrap()
+    }
+
+    #[test]
+    fn connections_share_a_config_per_key() {
+        let mut pool = pool();
+        let a = pool.acquire("fetch.test", "h2,http/1.1").unwrap();
+        let b = pool.acquire("FETCH.test", "h2,http/1.1").unwrap();
+        assert!(Arc::ptr_eq(&a.config, &b.config));
+        assert_ne!(a.pool_id(), b.pool_id());
+        let c = pool.acquire("fetch.test", "http/1.1").unwrap();
+        let d = pool
+            .acquire_for_profile("fetch.test", "h2,http/1.1", "firefox")
+            .unwrap();
+        assert!(!Arc::ptr_eq(&a.config, &c.config));
+        assert!(!Arc::ptr_eq(&a.config, &d.config));
+        assert_eq!(pool.configs.len(), 3);
+        assert_eq!(
+            TlsConnection::new("fetch.test", "").unwrap().pool_id(),
+            None
+        );
+    }
+
+    #[test]
+    fn dropped_connections_leave_their_buffers() {
+        let mut pool = pool();
+        let mut conn = pool.acquire("fetch.test", "h2").unwrap();
+        conn.incoming_tls.reserve(100_000);
+        // Still handshaking, so not kept
+        pool.release(conn);
+        assert_eq!(pool.idle_connections(), 0);
+        assert_eq!(pool.spare.len(), 1);
+        let conn = pool.acquire("other.test", "h2").unwrap();
+        assert!(conn.incoming_tls.capacity() >= 100_000);
+        assert!(conn.incoming_tls.is_empty());
+        assert!(pool.spare.is_empty());
+        assert!(!conn.client_hello.is_empty());
+    }
+
+    fn handshake(conn: &mut TlsConnection, server: &mut rustls::ServerConnection) {
+        loop {
+            let out = conn.flush_outgoing_tls().unwrap();
+            server_receive(server, &out);
+            let reply = server_send(server);
+            if out.is_empty() && reply.is_empty() {
+                break;
+            }
+            conn.feed_ciphertext(&reply).unwrap();
+        }
+    }
+
+    #[test]
+    fn idle_connections_are_handed_out_again() {
+        let mut pool = pool();
+        let mut conn = pool.acquire("fetch.test", "").unwrap();
+        handshake(&mut conn, &mut server(&TLS13));
+        let id = conn.pool_id();
+        pool.release(conn);
+        assert_eq!(pool.idle_connections(), 1);
+        // Another key gets a new connection
+        assert!(pool.acquire("fetch.test", "h2").unwrap().is_handshaking());
+
+        let mut conn = pool.acquire("fetch.test", "").unwrap();
+        assert_eq!(conn.pool_id(), id);
+        assert!(!conn.is_handshaking());
+        assert_eq!(pool.idle_connections(), 0);
+
+        // Unread data keeps it out of the pool
+        conn.plaintext_out.extend_from_slice(b"unread");
+        pool.release(conn);
+        assert_eq!(pool.idle_connections(), 0);
+        assert!(pool.take_evicted().is_empty());
+    }
+
+    #[test]
+    fn failed_connections_are_not_handed_out() {
+        let mut pool = pool();
+        let mut conn = pool.acquire("fetch.test", "").unwrap();
+        handshake(&mut conn, &mut server(&TLS13));
+        let id = conn.pool_id();
+        // An application data record that does not decrypt
+        let mut record = vec![0x17, 0x03, 0x03, 0x00, 0x20];
+        record.extend([0xa5; 0x20]);
+        assert!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            conn.feed_ciphertext(&record)
+        }))
+        .is_err());
+        // The alert goes out, and nothing is left to show the failure but the flag
+        assert!(!conn.flush_outgoing_tls().unwrap().is_empty());
+        pool.release(conn);
+        assert_eq!(pool.idle_connections(), 0);
+        let conn = pool.acquire("fetch.test", "").unwrap();
+        assert_ne!(conn.pool_id(), id);
+        assert!(conn.is_handshaking());
+    }
+
+    #[test]
+    fn verification_stays_with_its_connection() {
+        let mut pool = pool();
+        let config = server_config(&TLS13);
+        let mut first = pool.acquire("fetch.test", "").unwrap();
+        handshake(
+            &mut first,
+            &mut rustls::ServerConnection::new(config.clone()).unwrap(),
+        );
+        assert!(!first.was_resumed());
+        assert_eq!(first.chain_depth(), Some(2));
+
+        // Resumed on the first one's ticket: no certificate was verified for it
+        let mut second = pool.acquire("fetch.test", "").unwrap();
+        handshake(
+            &mut second,
+            &mut rustls::ServerConnection::new(config).unwrap(),
+        );
+        assert!(second.was_resumed());
+        assert_eq!(second.chain_depth(), None);
+        assert_eq!(second.must_staple_required(), None);
+        assert_eq!(first.chain_depth(), Some(2));
+        assert_eq!(first.must_staple_required(), Some(false));
+    }
+}
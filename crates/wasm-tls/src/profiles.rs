@@ -0,0 +1,91 @@
+//! The named browser profiles, described for a JS-side selector.
+
+use wasm_bindgen::prelude::*;
+use wasm_http::md5::hex_digest;
+
+use crate::hello;
+use crate::options::TlsOptions;
+
+#[derive(Debug, PartialEq, Eq)]
+struct ProfileInfo {
+    name: String,
+    browser: &'static str,
+    version: u32,
+    ja3: String,
+    ja4: String,
+}
+
+impl ProfileInfo {
+    fn new(name: &str) -> Option<ProfileInfo> {
+        let (browser, version) = wasm_http::profile_browser(name)?;
+        // Profiles shape the HTTP layer only: every one sends this ClientHello
+        let records =
+            crate::preview_client_hello("example.com", "h2,http/1.1", &TlsOptions::default())
+                .ok()?;
+        let msg = hello::find_handshake_message(&records, hello::HANDSHAKE_CLIENT_HELLO)?;
+        let mut details = hello::client_hello_details(&msg)?;
+        // rustls shuffles the extensions per hello; sorted, JA3 stays the same
+        details.fingerprint.extensions.sort_unstable();
+        Some(ProfileInfo {
+            name: name.to_string(),
+            browser,
+            version,
+            ja3: hello::ja3(&details),
+            ja4: hello::ja4(&details),
+        })
+    }
+
+    fn to_js(&self) -> Result<JsValue, JsValue> {
+        let object = js_sys::Object::new();
+        let set = |key: &str, value: JsValue| {
+            js_sys::Reflect::set(&object, &JsValue::from_str(key), &value).map(drop)
+        };
+        set("name", self.name.as_str().into())?;
+        set("browser", self.browser.into())?;
+        set("version", self.version.into())?;
+        set("ja3", self.ja3.as_str().into())?;
+        set("ja3Hash", hex_digest(self.ja3.as_bytes()).into())?;
+        set("ja4", self.ja4.as_str().into())?;
+        Ok(object.into())
+    }
+}
+
+/// Describe a profile from `available_profiles()`: `name`, target `browser`
+/// ("Chrome", "Firefox") and major `version`, plus `ja3`, `ja3Hash` and `ja4`.
+/// The fingerprints are those of the ClientHello this module sends with default
+/// `TlsOptions`, which profiles do not change. rustls shuffles the extension
+/// order per hello, so `ja3` lists the extension types sorted (as normalized JA3
+/// does) and does not match the JA3 of one captured hello; JA4 sorts them anyway.
+#[wasm_bindgen]
+pub fn profile_info(name: &str) -> Result<JsValue, JsError> {
+    let info = ProfileInfo::new(name)
+        .ok_or_else(|| JsError::new(&format!("Unknown profile: {:?}", name)))?;
+    info.to_js()
+        .map_err(|e| JsError::new(&crate::js_error_message(&e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_profile_is_described() {
+        for name in wasm_http::available_profiles() {
+            let info = ProfileInfo::new(&name).unwrap();
+            assert_eq!(info.name, name);
+            assert!(info.ja3.starts_with("771,"), "{}", info.ja3);
+            assert!(info.ja4.starts_with("t13d"), "{}", info.ja4);
+        }
+        let firefox = ProfileInfo::new("firefox").unwrap();
+        assert_eq!((firefox.browser, firefox.version), ("Firefox", 133));
+        assert_eq!(ProfileInfo::new("safari"), None);
+        // Sorted lists keep both stable across hellos
+        let chrome = ProfileInfo::new("chrome").unwrap();
+        assert_eq!((&chrome.ja3, &chrome.ja4), (&firefox.ja3, &firefox.ja4));
+        let again = ProfileInfo::new("chrome").unwrap();
+        assert_eq!(
+            hex_digest(again.ja3.as_bytes()),
+            hex_digest(chrome.ja3.as_bytes())
+        );
+    }
+}
@@ -0,0 +1,92 @@
+//! Tracking of TLS record boundaries (RFC 8446 §5.1) in the received ciphertext.
+
+/// Content type, legacy version and length.
+const RECORD_HEADER_LEN: usize = 5;
+
+/// Where the received byte stream stands relative to its record framing.
+/// rustls takes partial records into its own buffer, so the boundaries are followed
+/// here as bytes are fed rather than read back from it.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct RecordFraming {
+    /// Bytes of the next record header seen so far
+    header: Vec<u8>,
+    /// Payload bytes still to come for the current record
+    payload_left: usize,
+}
+
+impl RecordFraming {
+    /// Account for `data`, the next bytes received from the network.
+    pub(crate) fn advance(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            if self.payload_left > 0 {
+                let n = self.payload_left.min(data.len());
+                self.payload_left -= n;
+                data = &data[n..];
+                continue;
+            }
+            let n = (RECORD_HEADER_LEN - self.header.len()).min(data.len());
+            self.header.extend_from_slice(&data[..n]);
+            data = &data[n..];
+            if self.header.len() == RECORD_HEADER_LEN {
+                self.payload_left = u16::from_be_bytes([self.header[3], self.header[4]]) as usize;
+                self.header.clear();
+            }
+        }
+    }
+
+    /// Bytes missing from the record being received: the rest of its payload, or the
+    /// rest of its header when that is incomplete (the payload length is not known yet).
+    /// 0 at a record boundary.
+    pub(crate) fn bytes_needed(&self) -> usize {
+        if self.payload_left > 0 {
+            self.payload_left
+        } else if self.header.is_empty() {
+            0
+        } else {
+            RECORD_HEADER_LEN - self.header.len()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(len: usize) -> Vec<u8> {
+        let mut r = vec![23, 3, 3];
+        r.extend_from_slice(&(len as u16).to_be_bytes());
+        r.resize(RECORD_HEADER_LEN + len, 0);
+        r
+    }
+
+    #[test]
+    fn follows_records_across_arbitrary_splits() {
+        let stream = [record(10), record(0), record(300)].concat();
+        for step in [1, 2, 4, 7, stream.len()] {
+            let mut framing = RecordFraming::default();
+            let mut fed = 0;
+            for piece in stream.chunks(step) {
+                framing.advance(piece);
+                fed += piece.len();
+                let expected = match fed {
+                    0 => 0,
+                    n @ 1..=4 => 5 - n,
+                    n @ 5..=14 => 15 - n,
+                    15 => 0,
+                    n @ 16..=19 => 20 - n,
+                    20 => 0,
+                    n @ 21..=24 => 25 - n,
+                    n => 325 - n,
+                };
+                assert_eq!(
+                    framing.bytes_needed(),
+                    expected,
+                    "step {} fed {}",
+                    step,
+                    fed
+                );
+            }
+            assert_eq!(framing.bytes_needed(), 0);
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use std::fmt;
+
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{verify_tls13_signature_with_raw_key, WebPkiSupportedAlgorithms};
+use rustls::pki_types::{CertificateDer, ServerName, SubjectPublicKeyInfoDer, UnixTime};
+use rustls::{CertificateError, DigitallySignedStruct, Error, SignatureScheme};
+
+use crate::der;
+
+/// Server verifier for RFC 7250 raw public keys: negotiates the RawPublicKey server
+/// certificate type and accepts only a key from a fixed set. There is no name or
+/// validity to check, so the hostname is only used for SNI.
+pub(crate) struct RawKeyVerifier {
+    /// DER SubjectPublicKeyInfo of every key accepted
+    allowed: Vec<Vec<u8>>,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl RawKeyVerifier {
+    pub(crate) fn new(
+        allowed: Vec<Vec<u8>>,
+        algorithms: WebPkiSupportedAlgorithms,
+    ) -> Result<Self, String> {
+        if allowed.is_empty() {
+            return Err("No raw public key allowed".to_string());
+        }
+        if let Some(i) = allowed.iter().position(|spki| !der::is_spki(spki)) {
+            return Err(format!(
+                "Raw public key {} is not a DER SubjectPublicKeyInfo",
+                i
+            ));
+        }
+        Ok(RawKeyVerifier {
+            allowed,
+            algorithms,
+        })
+    }
+}
+
+impl fmt::Debug for RawKeyVerifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RawKeyVerifier")
+            .field("allowed", &self.allowed.len())
+            .finish()
+    }
+}
+
+impl ServerCertVerifier for RawKeyVerifier {
+    /// `end_entity` holds the server's SubjectPublicKeyInfo rather than a certificate.
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        if self.allowed.iter().any(|spki| spki == end_entity.as_ref()) {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(Error::InvalidCertificate(CertificateError::UnknownIssuer))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        Err(Error::General(
+            "raw public keys are only supported with TLS 1.3".to_string(),
+        ))
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        let spki = SubjectPublicKeyInfoDer::from(cert.as_ref());
+        verify_tls13_signature_with_raw_key(message, &spki, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+
+    fn requires_raw_public_keys(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPKI: &[u8] = include_bytes!("../tests/fixtures/client-spki.der");
+    const CERT: &[u8] = include_bytes!("../tests/fixtures/client-cert.der");
+
+    fn verify(verifier: &RawKeyVerifier, key: &[u8]) -> Result<ServerCertVerified, Error> {
+        let name = ServerName::try_from("device.local").unwrap();
+        let now = UnixTime::since_unix_epoch(std::time::Duration::from_secs(0));
+        verifier.verify_server_cert(&CertificateDer::from(key), &[], &name, &[], now)
+    }
+
+    #[test]
+    fn accepts_only_allowed_keys() {
+        let algorithms = crate::get_provider().signature_verification_algorithms;
+        let verifier = RawKeyVerifier::new(vec![SPKI.to_vec()], algorithms).unwrap();
+        assert!(verifier.requires_raw_public_keys());
+        assert!(verify(&verifier, SPKI).is_ok());
+        assert!(matches!(
+            verify(&verifier, CERT),
+            Err(Error::InvalidCertificate(CertificateError::UnknownIssuer))
+        ));
+
+        assert_eq!(
+            RawKeyVerifier::new(Vec::new(), algorithms).unwrap_err(),
+            "No raw public key allowed"
+        );
+        assert_eq!(
+            RawKeyVerifier::new(vec![SPKI.to_vec(), CERT.to_vec()], algorithms).unwrap_err(),
+            "Raw public key 1 is not a DER SubjectPublicKeyInfo"
+        );
+    }
+}
@@ -0,0 +1,162 @@
+//! Noticing TLS 1.3 session tickets as rustls stores them, for
+//! `TlsConnection::set_ticket_callback`.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use rustls::client::{
+    ClientSessionMemoryCache, ClientSessionStore, Tls12ClientSessionValue, Tls13ClientSessionValue,
+};
+use rustls::pki_types::ServerName;
+use rustls::time_provider::TimeProvider;
+use rustls::NamedGroup;
+use wasm_bindgen::prelude::*;
+
+use crate::clock::Clock;
+
+/// Servers whose time of the last stored ticket is remembered; as many as the
+/// session cache holds.
+const MAX_STORED_AT: usize = 256;
+
+/// Unix seconds, on the storing connection's clock, at which the latest ticket
+/// of each server entered the session cache. rustls resumes with the latest.
+static STORED_AT: OnceLock<Mutex<BTreeMap<String, u64>>> = OnceLock::new();
+
+/// When the ticket a new connection to `server_name` would offer was stored.
+pub(crate) fn stored_at(server_name: &str) -> Option<u64> {
+    STORED_AT.get()?.lock().ok()?.get(server_name).copied()
+}
+
+fn record_stored_at(server_name: String, unix_secs: u64) {
+    let Ok(mut stored) = STORED_AT.get_or_init(Mutex::default).lock() else {
+        return;
+    };
+    stored.insert(server_name, unix_secs);
+    while stored.len() > MAX_STORED_AT {
+        let oldest = stored
+            .iter()
+            .min_by_key(|(_, &secs)| secs)
+            .map(|(name, _)| name.clone());
+        match oldest {
+            Some(name) => stored.remove(&name),
+            None => break,
+        };
+    }
+}
+
+/// What is known of a ticket once rustls has it. rustls 0.23 keeps the ticket
+/// and its secret private, so there are no bytes to hand out.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct NewTicket {
+    pub(crate) server_name: String,
+    pub(crate) cipher_suite: u16,
+    pub(crate) max_early_data_size: u32,
+}
+
+impl NewTicket {
+    pub(crate) fn to_js(&self) -> Result<JsValue, JsValue> {
+        let object = js_sys::Object::new();
+        let set = |key: &str, value: JsValue| {
+            js_sys::Reflect::set(&object, &JsValue::from_str(key), &value).map(drop)
+        };
+        set("serverName", self.server_name.as_str().into())?;
+        set("cipherSuite", self.cipher_suite.into())?;
+        set("maxEarlyDataSize", self.max_early_data_size.into())?;
+        Ok(object.into())
+    }
+}
+
+/// The module's session cache, recording each TLS 1.3 ticket one connection
+/// stores so the connection can report it after `process_new_packets`.
+#[derive(Debug)]
+pub(crate) struct TicketObserver {
+    inner: Arc<ClientSessionMemoryCache>,
+    received: Arc<Mutex<Vec<NewTicket>>>,
+    /// The connection's clock, which rustls stamps the ticket with
+    clock: Arc<Clock>,
+}
+
+impl TicketObserver {
+    pub(crate) fn new(inner: Arc<ClientSessionMemoryCache>, clock: Arc<Clock>) -> Self {
+        TicketObserver {
+            inner,
+            received: Arc::default(),
+            clock,
+        }
+    }
+
+    /// Handle on the tickets stored so far, for the connection to drain.
+    pub(crate) fn received(&self) -> Arc<Mutex<Vec<NewTicket>>> {
+        self.received.clone()
+    }
+}
+
+impl ClientSessionStore for TicketObserver {
+    fn set_kx_hint(&self, server_name: ServerName<'static>, group: NamedGroup) {
+        self.inner.set_kx_hint(server_name, group);
+    }
+
+    fn kx_hint(&self, server_name: &ServerName<'_>) -> Option<NamedGroup> {
+        self.inner.kx_hint(server_name)
+    }
+
+    fn set_tls12_session(&self, server_name: ServerName<'static>, value: Tls12ClientSessionValue) {
+        self.inner.set_tls12_session(server_name, value);
+    }
+
+    fn tls12_session(&self, server_name: &ServerName<'_>) -> Option<Tls12ClientSessionValue> {
+        self.inner.tls12_session(server_name)
+    }
+
+    fn remove_tls12_session(&self, server_name: &ServerName<'static>) {
+        self.inner.remove_tls12_session(server_name);
+    }
+
+    fn insert_tls13_ticket(
+        &self,
+        server_name: ServerName<'static>,
+        value: Tls13ClientSessionValue,
+    ) {
+        let ticket = NewTicket {
+            server_name: server_name.to_str().into_owned(),
+            cipher_suite: u16::from(value.suite().common.suite),
+            max_early_data_size: value.max_early_data_size(),
+        };
+        if let Some(now) = self.clock.current_time() {
+            record_stored_at(ticket.server_name.clone(), now.as_secs());
+        }
+        if let Ok(mut received) = self.received.lock() {
+            received.push(ticket);
+        }
+        self.inner.insert_tls13_ticket(server_name, value);
+    }
+
+    fn take_tls13_ticket(
+        &self,
+        server_name: &ServerName<'static>,
+    ) -> Option<Tls13ClientSessionValue> {
+        self.inner.take_tls13_ticket(server_name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{TlsConnection, TlsOptions};
+
+    #[test]
+    fn ticket_age_starts_the_clock_after_the_stored_ticket() {
+        record_stored_at("age.example".to_string(), 1_760_000_000);
+        let mut options = TlsOptions::default();
+        options.set_current_time(1_700_000_000_000.0);
+        options.set_ticket_age(90_500.0);
+        let mut tls = TlsConnection::with_options("age.example", "h2", &options).unwrap();
+        assert_eq!(tls.clock.current_time().unwrap().as_secs(), 1_760_000_090);
+        tls.set_current_time(1_760_000_100_000.0);
+        assert_eq!(tls.clock.current_time().unwrap().as_secs(), 1_760_000_100);
+
+        // Without a stored ticket only the current time applies
+        let tls = TlsConnection::with_options("fresh.example", "h2", &options).unwrap();
+        assert_eq!(tls.clock.current_time().unwrap().as_secs(), 1_700_000_000);
+    }
+}
@@ -0,0 +1,206 @@
+//! One TLS connection inside another, for HTTPS proxies and TLS-terminating
+//! relays.
+
+use wasm_bindgen::prelude::*;
+
+use crate::{PumpResult, TlsConnection};
+
+/// Two stacked connections driven as one: `outer` to the proxy, `inner` to the
+/// origin, with the inner connection's records carried as the outer one's
+/// application data. Feed, flush, read and write as on a single `TlsConnection`:
+/// ciphertext in and out is the outer connection's, plaintext the inner one's.
+///
+/// The outer connection may be handed over before or after its handshake. When the
+/// proxy wants an HTTP CONNECT first, send it over the outer connection alone and
+/// build the tunnel once the proxy has answered; bytes that came after the answer
+/// (`ProxyTunnel::take_tunnel_data`) go to `feed_tunnel_data`.
+#[wasm_bindgen]
+pub struct TlsTunnel {
+    outer: TlsConnection,
+    inner: TlsConnection,
+}
+
+#[wasm_bindgen]
+impl TlsTunnel {
+    /// Tunnel `inner` through `outer`. Both are taken over; the inner ClientHello
+    /// goes out with the first flush, or once the outer handshake is done.
+    #[wasm_bindgen(constructor)]
+    pub fn new(outer: TlsConnection, inner: TlsConnection) -> TlsTunnel {
+        TlsTunnel { outer, inner }
+    }
+
+    /// Feed ciphertext received from the proxy. Returns true if there is ciphertext
+    /// to send, from either connection (call `flush_outgoing_tls`).
+    pub fn feed_ciphertext(&mut self, data: &[u8]) -> Result<bool, JsError> {
+        self.outer.feed_all(data)?;
+        self.route()?;
+        Ok(self.wants_write())
+    }
+
+    /// Feed inner ciphertext that was received outside the outer connection, e.g.
+    /// what followed the proxy's CONNECT response in the same read.
+    pub fn feed_tunnel_data(&mut self, data: &[u8]) -> Result<bool, JsError> {
+        self.inner.feed_all(data)?;
+        self.route()?;
+        Ok(self.wants_write())
+    }
+
+    /// `feed_ciphertext` + `take_plaintext` + `flush_outgoing_tls` in one call.
+    pub fn pump(&mut self, incoming: &[u8]) -> Result<PumpResult, JsError> {
+        self.feed_ciphertext(incoming)?;
+        Ok(PumpResult {
+            plaintext: self.take_plaintext(),
+            outgoing: self.flush_outgoing_tls()?,
+        })
+    }
+
+    /// Write plaintext for the origin. Data written while either handshake is in
+    /// progress is queued until the inner connection's keys exist.
+    pub fn write_plaintext(&mut self, data: &[u8]) -> Result<bool, JsError> {
+        self.inner.write_plaintext(data)?;
+        self.route()?;
+        Ok(self.wants_write())
+    }
+
+    /// Ciphertext to send to the proxy: the inner connection's records, encrypted
+    /// again by the outer one, after the outer connection's own handshake messages.
+    pub fn flush_outgoing_tls(&mut self) -> Result<Vec<u8>, JsError> {
+        self.route()?;
+        self.outer.flush_outgoing_tls()
+    }
+
+    /// Whether either connection has ciphertext waiting to be flushed.
+    pub fn wants_write(&self) -> bool {
+        self.inner.wants_write() || self.outer.wants_write()
+    }
+
+    /// Plaintext the origin sent (moved out; empty afterwards).
+    pub fn take_plaintext(&mut self) -> Vec<u8> {
+        self.inner.take_plaintext()
+    }
+
+    /// Number of plaintext bytes `take_plaintext` would return.
+    pub fn plaintext_pending(&self) -> usize {
+        self.inner.plaintext_pending()
+    }
+
+    /// Whether either handshake, to the proxy or to the origin, is still in progress.
+    pub fn is_handshaking(&self) -> bool {
+        self.outer.is_handshaking() || self.inner.is_handshaking()
+    }
+
+    /// ALPN protocol negotiated with the origin.
+    pub fn negotiated_alpn(&self) -> Option<String> {
+        self.inner.negotiated_alpn()
+    }
+
+    /// Whether the origin sent close_notify, or the proxy did, after which nothing
+    /// more can arrive from the origin either.
+    pub fn peer_has_closed(&self) -> bool {
+        self.inner.peer_has_closed() || self.outer.peer_has_closed()
+    }
+
+    /// Whether the peer has closed and all its plaintext has been taken.
+    pub fn is_fully_drained(&self) -> bool {
+        self.peer_has_closed() && self.plaintext_pending() == 0
+    }
+
+    /// Close both connections: the origin's close_notify is queued inside the outer
+    /// connection first, so the proxy relays it before its own close_notify.
+    pub fn send_close_notify(&mut self) -> Result<(), JsError> {
+        self.inner.send_close_notify();
+        self.route()?;
+        self.outer.send_close_notify();
+        Ok(())
+    }
+
+    /// Current time on the caller's clock, for both connections' `timings`.
+    pub fn set_now(&mut self, now_ms: f64) {
+        self.outer.set_now(now_ms);
+        self.inner.set_now(now_ms);
+    }
+
+    /// `connection_info` of the connection to the proxy.
+    pub fn outer_connection_info(&self) -> Result<JsValue, JsError> {
+        self.outer.connection_info()
+    }
+
+    /// `connection_info` of the connection to the origin.
+    pub fn inner_connection_info(&self) -> Result<JsValue, JsError> {
+        self.inner.connection_info()
+    }
+}
+
+impl TlsTunnel {
+    /// The connection to the proxy.
+    pub fn outer(&self) -> &TlsConnection {
+        &self.outer
+    }
+
+    /// The connection to the origin.
+    pub fn inner(&self) -> &TlsConnection {
+        &self.inner
+    }
+
+    /// Hand the outer connection's plaintext to the inner one, then the inner
+    /// connection's ciphertext (including what it sent in reply) to the outer one.
+    fn route(&mut self) -> Result<(), JsError> {
+        let inbound = self.outer.take_plaintext();
+        if !inbound.is_empty() {
+            self.inner.feed_all(&inbound)?;
+        }
+        let outbound = self.inner.flush_outgoing_tls()?;
+        if !outbound.is_empty() {
+            self.outer.write_plaintext(&outbound)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use rustls::version::TLS13;
+
+    use super::*;
+    use crate::tests::{client, server, server_receive, server_send};
+
+    #[test]
+    fn origin_handshake_rides_inside_the_proxy_connection() {
+        let mut tunnel = TlsTunnel::new(client(), client());
+        let mut proxy = server(&TLS13);
+        let mut origin = server(&TLS13);
+        let request = b"GET / HTTP/1.1\r\n\r\n";
+        tunnel.write_plaintext(request).unwrap();
+        let mut received = Vec::new();
+        loop {
+            let out = tunnel.flush_outgoing_tls().unwrap();
+            let relayed = server_receive(&mut proxy, &out);
+            received.extend(server_receive(&mut origin, &relayed));
+            if received == request {
+                received.clear();
+                origin.writer().write_all(b"HTTP/1.1 204 \r\n\r\n").unwrap();
+            }
+            let answer = server_send(&mut origin);
+            proxy.writer().write_all(&answer).unwrap();
+            let reply = server_send(&mut proxy);
+            if out.is_empty() && reply.is_empty() {
+                break;
+            }
+            tunnel.feed_ciphertext(&reply).unwrap();
+        }
+        assert!(!tunnel.is_handshaking());
+        assert!(!tunnel.outer().is_handshaking() && !tunnel.inner().is_handshaking());
+        assert_eq!(tunnel.take_plaintext(), b"HTTP/1.1 204 \r\n\r\n");
+        assert!(!tunnel.peer_has_closed());
+
+        // The origin sees its close_notify before the proxy sees the outer one
+        tunnel.send_close_notify().unwrap();
+        let out = tunnel.flush_outgoing_tls().unwrap();
+        let relayed = server_receive(&mut proxy, &out);
+        assert!(proxy.process_new_packets().unwrap().peer_has_closed());
+        server_receive(&mut origin, &relayed);
+        assert!(origin.process_new_packets().unwrap().peer_has_closed());
+    }
+}
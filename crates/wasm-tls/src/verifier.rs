@@ -0,0 +1,399 @@
+use std::fmt;
+use std::iter;
+use std::sync::{Arc, Mutex};
+
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{crypto::CryptoProvider, SignatureScheme};
+use rustls::{CertificateError, DigitallySignedStruct, Error, OtherError, RootCertStore};
+
+use crate::{der, get_root_store};
+
+/// Server certificate verifier: WebPKI validation plus the opt-in leniency modes from `TlsOptions`.
+pub(crate) struct ServerVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    roots: Arc<RootCertStore>,
+    /// Anchors from this index on in `roots` were added with `add_root_for_host`
+    bundled: usize,
+    reorder_chain: bool,
+    enforce_must_staple: bool,
+    /// Path of the last certificate that passed verification
+    verified: Mutex<Option<TrustPath>>,
+    /// Whether the last certificate seen requires a stapled OCSP response
+    must_staple: Mutex<Option<bool>>,
+}
+
+/// How a verified certificate chained to its trust anchor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct TrustPath {
+    /// Certificates from the leaf to the trust anchor, both included
+    pub(crate) depth: usize,
+    /// The anchor came from `add_root_for_host` rather than the bundled roots
+    pub(crate) custom: bool,
+}
+
+impl ServerVerifier {
+    pub(crate) fn new(
+        roots: Arc<RootCertStore>,
+        provider: Arc<CryptoProvider>,
+        reorder_chain: bool,
+        enforce_must_staple: bool,
+    ) -> Result<Self, String> {
+        let inner = WebPkiServerVerifier::builder_with_provider(roots.clone(), provider)
+            .build()
+            .map_err(|e| e.to_string())?;
+        Ok(ServerVerifier {
+            inner,
+            roots,
+            bundled: get_root_store().len(),
+            reorder_chain,
+            enforce_must_staple,
+            verified: Mutex::new(None),
+            must_staple: Mutex::new(None),
+        })
+    }
+
+    /// Path of the certificate this verifier last accepted, if not taken yet.
+    /// Connections sharing the verifier take it after each verification.
+    pub(crate) fn take_verified_path(&self) -> Option<TrustPath> {
+        self.verified.lock().unwrap().take()
+    }
+
+    /// Whether the last certificate presented carries the must-staple extension,
+    /// if not taken yet; None too if it could not be parsed.
+    pub(crate) fn take_must_staple(&self) -> Option<bool> {
+        self.must_staple.lock().unwrap().take()
+    }
+
+    /// Fail a must-staple certificate that came without an OCSP response, if enforced.
+    /// The response itself is not checked, only its presence.
+    fn check_staple(&self, must_staple: Option<bool>, ocsp_response: &[u8]) -> Result<(), Error> {
+        if self.enforce_must_staple && must_staple == Some(true) && ocsp_response.is_empty() {
+            return Err(Error::InvalidCertificate(CertificateError::Other(
+                OtherError(Arc::new(MustStapleViolation)),
+            )));
+        }
+        Ok(())
+    }
+
+    fn is_trusted_subject(&self, name: &[u8]) -> bool {
+        self.roots
+            .roots
+            .iter()
+            .any(|ta| ta.subject.as_ref() == name)
+    }
+
+    /// Walk issuer links from the leaf until a certificate is issued by a trust anchor,
+    /// as WebPKI path building does, and report where that anchor came from.
+    fn trust_path(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+    ) -> Option<TrustPath> {
+        let path = reorder_chain(end_entity, intermediates).ok()?;
+        for (index, cert) in iter::once(end_entity).chain(&path.certs).enumerate() {
+            let issuer = der::cert_names(cert)?.issuer;
+            let anchor = self
+                .roots
+                .roots
+                .iter()
+                .position(|ta| ta.subject.as_ref() == issuer);
+            if let Some(anchor) = anchor {
+                return Some(TrustPath {
+                    depth: index + 2,
+                    custom: anchor >= self.bundled,
+                });
+            }
+        }
+        None
+    }
+
+    fn record(
+        &self,
+        result: Result<ServerCertVerified, Error>,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, Error> {
+        let must_staple = der::has_must_staple(end_entity);
+        *self.must_staple.lock().unwrap() = must_staple;
+        let result = result.and_then(|verified| {
+            self.check_staple(must_staple, ocsp_response)
+                .map(|()| verified)
+        });
+        let path = match result {
+            Ok(_) => self.trust_path(end_entity, intermediates),
+            Err(_) => None,
+        };
+        *self.verified.lock().unwrap() = path;
+        result
+    }
+}
+
+impl fmt::Debug for ServerVerifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServerVerifier")
+            .field("reorder_chain", &self.reorder_chain)
+            .finish()
+    }
+}
+
+impl ServerCertVerifier for ServerVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        if !self.reorder_chain {
+            let result = self.inner.verify_server_cert(
+                end_entity,
+                intermediates,
+                server_name,
+                ocsp_response,
+                now,
+            );
+            return self.record(result, end_entity, intermediates, ocsp_response);
+        }
+
+        let path = reorder_chain(end_entity, intermediates)?;
+        let result = match self.inner.verify_server_cert(
+            end_entity,
+            &path.certs,
+            server_name,
+            ocsp_response,
+            now,
+        ) {
+            Err(Error::InvalidCertificate(CertificateError::UnknownIssuer))
+                if !self.is_trusted_subject(path.last_issuer) =>
+            {
+                Err(Error::InvalidCertificate(CertificateError::Other(
+                    OtherError(Arc::new(IncompleteChain {
+                        depth: path.certs.len(),
+                        presented: intermediates.len(),
+                    })),
+                )))
+            }
+            result => result,
+        };
+        self.record(result, end_entity, intermediates, ocsp_response)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+/// Intermediates rearranged into issuer order, starting from the leaf.
+struct ReorderedChain<'c, 'a> {
+    certs: Vec<CertificateDer<'a>>,
+    /// Issuer Name of the last certificate in the path (leaf if `certs` is empty)
+    last_issuer: &'c [u8],
+}
+
+/// Rebuild the intermediate list by walking issuer -> subject links from the leaf.
+/// Certificates that are not on that path (duplicates, unrelated roots) are dropped,
+/// and so are extra certificates this parser cannot read: webpki judges what is left.
+fn reorder_chain<'c, 'a>(
+    end_entity: &'c CertificateDer<'a>,
+    intermediates: &'c [CertificateDer<'a>],
+) -> Result<ReorderedChain<'c, 'a>, Error> {
+    let leaf = der::cert_names(end_entity)
+        .ok_or(Error::InvalidCertificate(CertificateError::BadEncoding))?;
+    let mut remaining: Vec<_> = intermediates
+        .iter()
+        .filter_map(|c| der::cert_names(c).map(|n| (c, n)))
+        .collect();
+
+    let mut certs = Vec::with_capacity(remaining.len());
+    let mut issuer = leaf.issuer;
+    let mut subject = leaf.subject;
+    while issuer != subject {
+        let Some(pos) = remaining.iter().position(|(_, n)| n.subject == issuer) else {
+            break;
+        };
+        let (cert, names) = remaining.swap_remove(pos);
+        certs.push(cert.clone());
+        issuer = names.issuer;
+        subject = names.subject;
+    }
+
+    Ok(ReorderedChain {
+        certs,
+        last_issuer: issuer,
+    })
+}
+
+#[derive(Debug)]
+struct IncompleteChain {
+    depth: usize,
+    presented: usize,
+}
+
+impl fmt::Display for IncompleteChain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "certificate chain could not be reassembled: no issuer for the certificate at depth {} \
+             among {} presented intermediates or the trusted roots",
+            self.depth, self.presented
+        )
+    }
+}
+
+impl std::error::Error for IncompleteChain {}
+
+/// The certificate requires OCSP stapling (RFC 7633) but the server sent no response.
+#[derive(Debug)]
+struct MustStapleViolation;
+
+impl fmt::Display for MustStapleViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "MustStapleViolation: the certificate requires a stapled OCSP response \
+             and the server did not send one"
+        )
+    }
+}
+
+impl std::error::Error for MustStapleViolation {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::der::tests::fake_cert;
+    use rustls::pki_types::Der;
+
+    fn der(bytes: Vec<u8>) -> CertificateDer<'static> {
+        CertificateDer::from(bytes)
+    }
+
+    #[test]
+    fn reorders_shuffled_intermediates_and_drops_strays() {
+        let leaf = der(fake_cert(b"int1", b"leaf"));
+        let int1 = der(fake_cert(b"int2", b"int1"));
+        let int2 = der(fake_cert(b"root", b"int2"));
+        let stray = der(fake_cert(b"other", b"unrelated"));
+        let presented = [int2.clone(), stray, int1.clone()];
+
+        let path = reorder_chain(&leaf, &presented).unwrap();
+        assert_eq!(path.certs, vec![int1, int2]);
+        assert_eq!(path.last_issuer, b"root");
+    }
+
+    #[test]
+    fn stops_at_self_signed_certificate() {
+        let leaf = der(fake_cert(b"root", b"leaf"));
+        let root = der(fake_cert(b"root", b"root"));
+        let presented = [root.clone()];
+        let path = reorder_chain(&leaf, &presented).unwrap();
+        assert_eq!(path.certs, vec![root]);
+        assert_eq!(path.last_issuer, b"root");
+    }
+
+    #[test]
+    fn reports_where_the_path_breaks() {
+        let leaf = der(fake_cert(b"missing", b"leaf"));
+        let other = der(fake_cert(b"x", b"y"));
+        let presented = [other];
+        let path = reorder_chain(&leaf, &presented).unwrap();
+        assert!(path.certs.is_empty());
+        assert_eq!(path.last_issuer, b"missing");
+    }
+
+    #[test]
+    fn trust_path_names_the_anchor_source() {
+        let mut roots = (*get_root_store()).clone();
+        let bundled_subject = roots.roots[0].subject.clone();
+        let mut custom = roots.roots[0].clone();
+        custom.subject = Der::from_slice(b"private-root");
+        roots.roots.push(custom);
+        let verifier =
+            ServerVerifier::new(Arc::new(roots), crate::get_provider(), false, false).unwrap();
+
+        // Intermediates in any order; the leaf's issuer link decides the path
+        let leaf = der(fake_cert(b"int", b"leaf"));
+        let int = der(fake_cert(b"private-root", b"int"));
+        let stray = der(fake_cert(b"x", b"y"));
+        assert_eq!(
+            verifier.trust_path(&leaf, &[stray, int]),
+            Some(TrustPath {
+                depth: 3,
+                custom: true
+            })
+        );
+
+        let leaf = der(fake_cert(bundled_subject.as_ref(), b"leaf"));
+        assert_eq!(
+            verifier.trust_path(&leaf, &[]),
+            Some(TrustPath {
+                depth: 2,
+                custom: false
+            })
+        );
+        let orphan = der(fake_cert(b"unknown", b"leaf"));
+        assert_eq!(verifier.trust_path(&orphan, &[]), None);
+        assert_eq!(verifier.take_verified_path(), None);
+    }
+
+    #[test]
+    fn must_staple_only_enforced_when_asked() {
+        let verifier = |enforce| {
+            ServerVerifier::new(get_root_store(), crate::get_provider(), false, enforce).unwrap()
+        };
+        let strict = verifier(true);
+        match strict.check_staple(Some(true), &[]) {
+            Err(Error::InvalidCertificate(CertificateError::Other(e))) => {
+                assert!(e.to_string().starts_with("MustStapleViolation"))
+            }
+            other => panic!("{:?}", other),
+        }
+        assert!(strict.check_staple(Some(true), &[0x30]).is_ok());
+        assert!(strict.check_staple(Some(false), &[]).is_ok());
+        assert!(strict.check_staple(None, &[]).is_ok());
+        assert!(verifier(false).check_staple(Some(true), &[]).is_ok());
+        assert_eq!(strict.take_must_staple(), None);
+    }
+
+    #[test]
+    fn rejects_garbage_leaf() {
+        let junk = der(vec![1, 2, 3]);
+        assert!(matches!(
+            reorder_chain(&junk, &[]),
+            Err(Error::InvalidCertificate(CertificateError::BadEncoding))
+        ));
+    }
+
+    #[test]
+    fn skips_garbage_intermediates() {
+        let leaf = der(fake_cert(b"int", b"leaf"));
+        let int = der(fake_cert(b"root", b"int"));
+        let junk = der(vec![1, 2, 3]);
+        let presented = [junk, int.clone()];
+        let path = reorder_chain(&leaf, &presented).unwrap();
+        assert_eq!(path.certs, vec![int]);
+        assert_eq!(path.last_issuer, b"root");
+    }
+}
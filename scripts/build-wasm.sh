@@ -16,7 +16,8 @@ fi
 
 # Build with wasm-pack (--target web for CF Workers compatibility)
 # wasm-opt is disabled in Cargo.toml — we run it manually below with --enable-bulk-memory
-wasm-pack build "$CRATE_DIR" --target web --release --out-dir "$OUTPUT_DIR" --out-name wasm_tls
+# Extra arguments go to cargo, e.g. `build-wasm.sh --no-default-features` for a build without brotli/zstd
+wasm-pack build "$CRATE_DIR" --target web --release --out-dir "$OUTPUT_DIR" --out-name wasm_tls ${1+-- "$@"}
 
 # Clean up wasm-pack generated files we don't need
 rm -f "$OUTPUT_DIR/.gitignore"